@@ -0,0 +1,441 @@
+use axum::extract::{Json, Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::io::Write;
+use tracing::{error, info};
+
+use crate::encryption::random_hex;
+use crate::{cache, cookies, usage};
+use crate::jobs::{CancelError, JobFilter, JobKind, JobStatus};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct JobsQuery {
+    status: Option<JobStatus>,
+    kind: Option<JobKind>,
+    platform: Option<String>,
+    /// Unix seconds
+    since: Option<u64>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+/// Largest page GET /admin/jobs returns.
+const MAX_JOBS_PAGE: usize = 200;
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    /// Last day of the range, YYYY-MM-DD (default: today, UTC)
+    date: Option<String>,
+    /// Days ending with `date` (default 1)
+    days: Option<u32>,
+    /// Only this API key
+    key: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CookieUploadQuery {
+    /// Profile index to replace (default: the active profile)
+    profile: Option<usize>,
+}
+
+/// Check the admin token (`Authorization: Bearer <ADMIN_TOKEN>` or
+/// `X-Admin-Token`); returns the rejection response if it doesn't match.
+/// Admin routes are disabled while ADMIN_TOKEN is unset.
+pub fn reject_non_admin(headers: &HeaderMap, state: &AppState) -> Option<Response> {
+    let expected = state.settings.admin_token.as_str();
+    if expected.is_empty() {
+        return Some((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Route not found"})),
+        )
+            .into_response());
+    }
+
+    let supplied = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-admin-token").and_then(|v| v.to_str().ok()));
+
+    if supplied.is_some_and(|t| constant_time_eq(t.as_bytes(), expected.as_bytes())) {
+        None
+    } else {
+        Some(
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({"error": "Invalid admin token"})),
+            )
+                .into_response(),
+        )
+    }
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// GET /admin/cookies — Cookie profile pool health, per platform
+pub async fn cookies_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+
+    let pools: Vec<_> = state
+        .cookies
+        .iter()
+        .map(|pool| {
+            serde_json::json!({
+                "platform": pool.platform(),
+                "profiles": pool.status(),
+            })
+        })
+        .collect();
+    (StatusCode::OK, Json(serde_json::json!({ "pools": pools }))).into_response()
+}
+
+/// PUT /admin/cookies/{platform} — Replace a cookie profile with the uploaded
+/// Netscape cookie file
+pub async fn upload_cookies_handler(
+    State(state): State<AppState>,
+    Path(platform): Path<String>,
+    Query(query): Query<CookieUploadQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+
+    let Some(pool) = state.cookies.get(&platform).cloned() else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": format!("Unknown platform: {platform}")})),
+        )
+            .into_response();
+    };
+    let Some((idx, path)) = pool.profile(query.profile) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Unknown cookie profile"})),
+        )
+            .into_response();
+    };
+
+    let count = match cookies::parse_netscape(&body) {
+        Ok(c) => c.len(),
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": format!("Invalid Netscape cookie file: {e}")})),
+            )
+                .into_response();
+        }
+    };
+
+    // Write next to the target and rename, so yt-dlp never reads a partial file
+    let target = path.clone();
+    let written = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        if let Some(dir) = target.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = target.with_file_name(format!(".{name}.{}.tmp", random_hex(8)));
+        let result = write_private(&tmp, body.as_bytes()).and_then(|()| std::fs::rename(&tmp, &target));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        result
+    })
+    .await
+    .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+
+    if let Err(e) = written {
+        error!("Failed to write cookie file {}: {e}", path.display());
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to write cookie file"})),
+        )
+            .into_response();
+    }
+
+    pool.reset(idx);
+    let metrics = state.metrics.clone();
+    let warn_secs = state.settings.cookie_expiry_warn_days * 86_400;
+    let checked = pool.clone();
+    let _ = tokio::task::spawn_blocking(move || checked.run_checks(warn_secs, &metrics)).await;
+
+    let cleared = match state.redis {
+        Some(ref redis) => redis.clear_metadata().await,
+        None => 0,
+    };
+    info!(
+        "🍪 Cookie profile {} replaced ({count} cookies, {cleared} cached entries cleared)",
+        path.display()
+    );
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "success": true,
+            "platform": platform,
+            "profile": idx,
+            "path": path.to_string_lossy(),
+            "cookies": count,
+            "cache_entries_cleared": cleared,
+            "check": pool.status().into_iter().nth(idx).and_then(|p| p.check),
+        })),
+    )
+        .into_response()
+}
+
+/// Create `path` (which must not exist yet) readable by the owner only and
+/// write `data` to it: session cookies never sit world-readable on disk.
+fn write_private(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// GET /admin/blocklist — Loaded blocklist rules and last reload error
+pub async fn blocklist_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+    (StatusCode::OK, Json(state.blocklist.status())).into_response()
+}
+
+/// GET /admin/cache/stats — Redis cache hit ratio, failures and GET latency
+/// since startup
+pub async fn cache_stats_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "enabled": state.redis.is_some(),
+            "metadata_ttl_secs": cache::METADATA_TTL_SECS,
+            "metadata_ttl_jitter_pct": state.settings.cache_ttl_jitter_pct,
+            "key_prefix": state.settings.redis_key_prefix,
+            "caches": state.metrics.cache_stats(),
+        })),
+    )
+        .into_response()
+}
+
+/// POST /admin/blocklist/reload — Re-read BLOCKLIST_PATH now instead of
+/// waiting for the file watcher
+pub async fn reload_blocklist_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+
+    let blocklist = state.blocklist.clone();
+    match tokio::task::spawn_blocking(move || blocklist.reload()).await {
+        Ok(Ok(rules)) => {
+            info!("🚫 Blocklist reloaded via admin API: {rules} rules");
+            (StatusCode::OK, Json(state.blocklist.status())).into_response()
+        }
+        Ok(Err(e)) => {
+            error!("Blocklist reload failed: {e}");
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(serde_json::json!({"error": e})),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": format!("Reload task failed: {e}")})),
+        )
+            .into_response(),
+    }
+}
+
+/// GET /admin/jobs — Queued, running and recently finished jobs, newest
+/// first (?status=&kind=&platform=&since=&offset=&limit=)
+pub async fn jobs_handler(
+    State(state): State<AppState>,
+    Query(query): Query<JobsQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+
+    let filter = JobFilter {
+        status: query.status,
+        kind: query.kind,
+        platform: query.platform,
+        since: query.since,
+    };
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_JOBS_PAGE);
+    let (total, jobs) = state.jobs.list(&filter, query.offset, limit);
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "total": total,
+            "offset": query.offset,
+            "limit": limit,
+            "jobs": jobs,
+        })),
+    )
+        .into_response()
+}
+
+/// DELETE /admin/jobs/{id} — Cancel a queued extraction or slideshow
+pub async fn cancel_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+
+    match state.jobs.cancel(id) {
+        Ok(()) => {
+            info!("🛑 Job {id} cancelled via admin API");
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({"id": id, "status": "cancelled"})),
+            )
+                .into_response()
+        }
+        Err(CancelError::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": format!("Job {id} not found")})),
+        )
+            .into_response(),
+        Err(CancelError::NotQueued(status)) => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "error": "Only queued jobs can be cancelled",
+                "status": status,
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// GET /admin/streams — Responses currently proxied by /stream and /download
+pub async fn streams_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+
+    let streams = state.streams.list();
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "total": streams.len(),
+            "streams": streams,
+        })),
+    )
+        .into_response()
+}
+
+/// DELETE /admin/streams/{id} — Cut an active stream off
+pub async fn terminate_stream_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+
+    if state.streams.terminate(id) {
+        info!("🛑 Stream {id} terminated via admin API");
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({"id": id, "status": "terminated"})),
+        )
+            .into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": format!("Stream {id} not found")})),
+        )
+            .into_response()
+    }
+}
+
+/// GET /admin/usage — Bytes streamed per API key per day, with the key's
+/// tier and daily quota (?date=YYYY-MM-DD&days=&key=)
+pub async fn usage_handler(
+    State(state): State<AppState>,
+    Query(query): Query<UsageQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+
+    let last = match query.date.as_deref() {
+        Some(date) => match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(d) => d,
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": "date must be YYYY-MM-DD"})),
+                )
+                    .into_response()
+            }
+        },
+        None => chrono::Utc::now().date_naive(),
+    };
+    let Some(cache) = &state.redis else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"error": "Usage needs the cache (Redis is not connected)"})),
+        )
+            .into_response();
+    };
+
+    let settings = &state.settings;
+    let mut days = Vec::new();
+    let mut total_bytes = 0;
+    for day in usage::days_ending(last, query.days.unwrap_or(1)) {
+        let keys = match cache.usage_for_day(&day).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                error!("Usage read failed: {e}");
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({"error": format!("Usage read failed: {e}")})),
+                )
+                    .into_response();
+            }
+        };
+        let keys: Vec<_> = keys
+            .into_iter()
+            .filter(|(key, _)| query.key.as_ref().is_none_or(|k| k == key))
+            .map(|(key, bytes)| {
+                total_bytes += bytes;
+                serde_json::json!({
+                    "api_key": key,
+                    "tier": settings.tier_for(Some(&key)),
+                    "bytes": bytes,
+                    "quota_bytes": settings.daily_quota_for(&key),
+                })
+            })
+            .collect();
+        days.push(serde_json::json!({"date": day, "keys": keys}));
+    }
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "total_bytes": total_bytes,
+            "days": days,
+        })),
+    )
+        .into_response()
+}
@@ -0,0 +1,264 @@
+use axum::http::HeaderMap;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+use crate::config::Settings;
+use crate::stream::too_large;
+
+/// How often a running download's status is polled over RPC.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often progress is logged for long downloads.
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Downloads delegated to an aria2c daemon over its JSON-RPC interface
+/// (multi-connection, resume and retries handled by aria2c).
+pub struct Aria2 {
+    rpc: Rpc,
+    connections: u32,
+    /// Longest a download may take (DOWNLOAD_TIMEOUT, like native downloads)
+    timeout: Duration,
+    /// MAX_DOWNLOAD_BYTES, 0 for no limit
+    max_bytes: u64,
+    /// Locally spawned daemon; killed when the server exits
+    _daemon: Option<tokio::process::Child>,
+}
+
+impl Aria2 {
+    /// Connect to ARIA2_RPC_URL, or spawn a local `aria2c --enable-rpc`.
+    /// Returns `None` (native downloads) when DOWNLOAD_BACKEND isn't aria2c
+    /// or the daemon can't be started.
+    pub async fn from_settings(settings: &Settings) -> Option<Self> {
+        if settings.download_backend != "aria2c" {
+            return None;
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .ok()?;
+
+        let (rpc_url, secret, daemon) = if !settings.aria2_rpc_url.is_empty() {
+            (
+                settings.aria2_rpc_url.clone(),
+                settings.aria2_rpc_secret.clone(),
+                None,
+            )
+        } else {
+            let secret = if settings.aria2_rpc_secret.is_empty() {
+                let nanos = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_nanos();
+                format!("{nanos:x}")
+            } else {
+                settings.aria2_rpc_secret.clone()
+            };
+            let spawned = tokio::process::Command::new(&settings.aria2c_path)
+                .args([
+                    "--enable-rpc",
+                    "--rpc-listen-all=false",
+                    &format!("--rpc-listen-port={}", settings.aria2_rpc_port),
+                    &format!("--rpc-secret={secret}"),
+                    "--quiet=true",
+                    "--auto-file-renaming=false",
+                    "--allow-overwrite=true",
+                ])
+                .kill_on_drop(true)
+                .spawn();
+            match spawned {
+                Ok(child) => (
+                    format!("http://127.0.0.1:{}/jsonrpc", settings.aria2_rpc_port),
+                    secret,
+                    Some(child),
+                ),
+                Err(e) => {
+                    error!("❌ Failed to start aria2c ({}): {e}; using native downloads", settings.aria2c_path);
+                    return None;
+                }
+            }
+        };
+
+        let aria2 = Self {
+            rpc: Rpc { client, url: rpc_url, secret },
+            connections: settings.aria2_connections,
+            timeout: Duration::from_secs(settings.download_timeout),
+            max_bytes: settings.max_download_bytes,
+            _daemon: daemon,
+        };
+
+        // The daemon needs a moment to open its RPC port
+        for _ in 0..20 {
+            if let Ok(version) = aria2.rpc.call("aria2.getVersion", vec![]).await {
+                info!(
+                    "✅ aria2c {} ready at {}",
+                    version["version"].as_str().unwrap_or("?"),
+                    aria2.rpc.url
+                );
+                return Some(aria2);
+            }
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+        error!("❌ aria2c RPC at {} not reachable; using native downloads", aria2.rpc.url);
+        None
+    }
+
+
+    /// Download `url` to `dir/out` and wait (at most DOWNLOAD_TIMEOUT) for it
+    /// to finish. Returns the size. The download is removed from aria2c when
+    /// it fails, times out or this future is dropped (client went away).
+    pub async fn download(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+        dir: &Path,
+        out: &str,
+    ) -> Result<u64, String> {
+        let header_lines: Vec<String> = headers
+            .iter()
+            .filter_map(|(k, v)| v.to_str().ok().map(|v| format!("{k}: {v}")))
+            .collect();
+        let connections = self.connections.to_string();
+        let options = serde_json::json!({
+            "dir": dir.to_string_lossy(),
+            "out": out,
+            "header": header_lines,
+            "split": connections,
+            "max-connection-per-server": connections,
+            "continue": "true",
+            "max-tries": "5",
+        });
+
+        let gid = self
+            .rpc
+            .call("aria2.addUri", vec![serde_json::json!([url]), options])
+            .await?;
+        let gid = gid.as_str().ok_or("aria2.addUri returned no gid")?.to_string();
+        let mut guard = RemoveOnDrop { rpc: self.rpc.clone(), gid: Some(gid.clone()) };
+
+        let size = match tokio::time::timeout(self.timeout, self.wait(&gid, out)).await {
+            Ok(result) => result?,
+            Err(_) => {
+                warn!("aria2c download {out} exceeded {}s, removing it", self.timeout.as_secs());
+                return Err(format!("download exceeded {}s", self.timeout.as_secs()));
+            }
+        };
+        guard.gid = None;
+        let _ = self
+            .rpc
+            .call("aria2.removeDownloadResult", vec![serde_json::json!(gid)])
+            .await;
+        Ok(size)
+    }
+
+    async fn wait(&self, gid: &str, out: &str) -> Result<u64, String> {
+        let keys = serde_json::json!([
+            "status",
+            "totalLength",
+            "completedLength",
+            "downloadSpeed",
+            "connections",
+            "errorMessage"
+        ]);
+        let mut last_log = Instant::now();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let status = self
+                .rpc
+                .call("aria2.tellStatus", vec![serde_json::json!(gid), keys.clone()])
+                .await?;
+            let num = |key: &str| -> u64 {
+                status[key].as_str().and_then(|v| v.parse().ok()).unwrap_or(0)
+            };
+
+            let size = num("totalLength").max(num("completedLength"));
+            if self.max_bytes > 0 && size > self.max_bytes {
+                warn!("aria2c download {out} is {size} bytes, over MAX_DOWNLOAD_BYTES");
+                return Err(too_large(self.max_bytes));
+            }
+            match status["status"].as_str().unwrap_or("") {
+                "complete" => return Ok(num("completedLength")),
+                "error" | "removed" => {
+                    let msg = status["errorMessage"].as_str().unwrap_or("download failed");
+                    warn!("aria2c download {out} failed: {msg}");
+                    return Err(msg.to_string());
+                }
+                _ => {
+                    if last_log.elapsed() >= PROGRESS_LOG_INTERVAL {
+                        last_log = Instant::now();
+                        info!(
+                            "⬇️ aria2c {out}: {}/{} bytes, {} KiB/s, {} connections",
+                            num("completedLength"),
+                            num("totalLength"),
+                            num("downloadSpeed") / 1024,
+                            num("connections")
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// JSON-RPC endpoint of the aria2c daemon.
+#[derive(Clone)]
+struct Rpc {
+    client: reqwest::Client,
+    url: String,
+    secret: String,
+}
+
+impl Rpc {
+    async fn call(
+        &self,
+        method: &str,
+        mut params: Vec<serde_json::Value>,
+    ) -> Result<serde_json::Value, String> {
+        params.insert(0, serde_json::Value::String(format!("token:{}", self.secret)));
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "serverrs",
+            "method": method,
+            "params": params,
+        });
+        let response: serde_json::Value = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| format!("aria2 RPC {method} failed: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("aria2 RPC {method} returned invalid JSON: {e}"))?;
+        if let Some(err) = response.get("error") {
+            return Err(format!("aria2 RPC {method}: {}", err["message"].as_str().unwrap_or("error")));
+        }
+        Ok(response["result"].clone())
+    }
+}
+
+/// Force-removes an unfinished download (and its result) from aria2c when
+/// dropped, so it stops fetching once nobody waits for it.
+struct RemoveOnDrop {
+    rpc: Rpc,
+    gid: Option<String>,
+}
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        let Some(gid) = self.gid.take() else {
+            return;
+        };
+        let rpc = self.rpc.clone();
+        tokio::spawn(async move {
+            if let Err(e) = rpc.call("aria2.forceRemove", vec![serde_json::json!(gid)]).await {
+                // Already finished or failed on its own
+                info!("aria2c download {gid} not removed: {e}");
+            }
+            // forceRemove returns before the download is actually stopped
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let _ = rpc.call("aria2.removeDownloadResult", vec![serde_json::json!(gid)]).await;
+        });
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::checksum::sha256_bytes;
+use crate::tasks::{tick, TaskManager};
+
+/// Cache folder inside TEMP_DIR; hidden so the hourly work-dir cleanup skips it.
+pub const ASSET_CACHE_DIR: &str = ".slideshow-assets";
+
+/// Downloaded slideshow images and sounds, keyed by the hash of their CDN URL
+/// and kept for SLIDESHOW_ASSET_TTL_SECS, so a retried or re-parameterised
+/// slideshow request doesn't fetch every asset from the CDN again.
+pub struct AssetCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl AssetCache {
+    /// None when the TTL is 0 (caching disabled).
+    pub fn new(temp_dir: &Path, ttl_secs: u64) -> Option<Self> {
+        if ttl_secs == 0 {
+            return None;
+        }
+        let dir = temp_dir.join(ASSET_CACHE_DIR);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            warn!("Slideshow asset cache disabled, can't create {}: {e}", dir.display());
+            return None;
+        }
+        Some(Self {
+            dir,
+            ttl: Duration::from_secs(ttl_secs),
+        })
+    }
+
+    fn path_for(&self, url: &str) -> PathBuf {
+        self.dir.join(&sha256_bytes(url.as_bytes())[..32])
+    }
+
+    fn is_fresh(&self, path: &Path, now: SystemTime) -> bool {
+        std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() < self.ttl)
+    }
+
+    /// Place a fresh cached copy of `url` at `dest`; false on a miss.
+    /// Blocking — call from spawn_blocking.
+    pub fn fetch(&self, url: &str, dest: &Path) -> bool {
+        let cached = self.path_for(url);
+        if !self.is_fresh(&cached, SystemTime::now()) {
+            return false;
+        }
+        link_or_copy(&cached, dest).is_ok()
+    }
+
+    /// Keep a just-downloaded asset for later requests. Blocking.
+    pub fn store(&self, url: &str, src: &Path) {
+        let cached = self.path_for(url);
+        let part = cached.with_extension("part");
+        let stored = link_or_copy(src, &part).and_then(|()| std::fs::rename(&part, &cached));
+        if let Err(e) = stored {
+            let _ = std::fs::remove_file(&part);
+            warn!("Failed to cache slideshow asset {}: {e}", src.display());
+        }
+    }
+
+    /// Remove expired assets. Returns the number removed. Blocking.
+    pub fn sweep(&self) -> usize {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return 0;
+        };
+        let now = SystemTime::now();
+        entries
+            .flatten()
+            .filter(|e| !self.is_fresh(&e.path(), now))
+            .filter(|e| std::fs::remove_file(e.path()).is_ok())
+            .count()
+    }
+}
+
+/// Hard link (same filesystem, no copy), falling back to a copy.
+fn link_or_copy(src: &Path, dest: &Path) -> std::io::Result<()> {
+    let _ = std::fs::remove_file(dest);
+    std::fs::hard_link(src, dest).or_else(|_| std::fs::copy(src, dest).map(|_| ()))
+}
+
+/// Sweep expired assets once per TTL (at least every minute).
+pub fn spawn_sweep_task(tasks: &TaskManager, cache: Arc<AssetCache>) {
+    tasks.spawn("slideshow_asset_sweep", |cancel| async move {
+        let mut interval = tokio::time::interval(cache.ttl.max(Duration::from_secs(60)));
+        interval.tick().await;
+        while tick(&mut interval, &cancel).await {
+            let c = cache.clone();
+            let removed = tokio::task::spawn_blocking(move || c.sweep()).await.unwrap_or(0);
+            if removed > 0 {
+                info!("🧹 Removed {removed} expired slideshow asset(s)");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_fetch_and_expire() {
+        let dir = std::env::temp_dir().join(format!("asset_cache_test_{}", std::process::id()));
+        let cache = AssetCache::new(&dir, 60).unwrap();
+        let src = dir.join("image_0.jpg");
+        std::fs::write(&src, b"jpeg").unwrap();
+
+        let url = "https://cdn.example/img.jpg?sig=1";
+        let dest = dir.join("copy.jpg");
+        assert!(!cache.fetch(url, &dest));
+        cache.store(url, &src);
+        assert!(cache.fetch(url, &dest));
+        assert_eq!(std::fs::read(&dest).unwrap(), b"jpeg");
+        assert!(!cache.fetch("https://cdn.example/img.jpg?sig=2", &dest));
+
+        let old = SystemTime::now() - Duration::from_secs(120);
+        std::fs::File::options()
+            .write(true)
+            .open(cache.path_for(url))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert!(!cache.fetch(url, &dest));
+        assert_eq!(cache.sweep(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(AssetCache::new(&dir, 0).is_none());
+    }
+}
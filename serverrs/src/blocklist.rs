@@ -0,0 +1,324 @@
+use axum::extract::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::tasks::{tick, TaskManager};
+
+/// Status used when a rule doesn't set one (451 Unavailable For Legal Reasons).
+const DEFAULT_STATUS: u16 = 451;
+const DEFAULT_CODE: &str = "BLOCKED_BY_POLICY";
+
+/// BLOCKLIST_PATH file format:
+///
+/// ```json
+/// {"rules": [
+///   {"type": "video_id", "value": "7301234567890123456", "code": "DMCA_TAKEDOWN"},
+///   {"type": "creator", "value": "someuser", "status": 403},
+///   {"type": "url_pattern", "value": "tiktok\\.com/music/"}
+/// ]}
+/// ```
+#[derive(Deserialize)]
+struct BlocklistFile {
+    #[serde(default)]
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Deserialize)]
+struct RuleSpec {
+    #[serde(rename = "type")]
+    kind: RuleKind,
+    value: String,
+    status: Option<u16>,
+    code: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RuleKind {
+    VideoId,
+    Creator,
+    UrlPattern,
+}
+
+struct Rule {
+    kind: RuleKind,
+    value: String,
+    pattern: Option<Regex>,
+    status: StatusCode,
+    code: String,
+}
+
+/// What a request is checked against: the submitted/CDN URL and whatever
+/// ids are known at that point.
+#[derive(Default)]
+pub struct Subject<'a> {
+    pub url: Option<&'a str>,
+    pub video_id: Option<&'a str>,
+    pub creator: Option<&'a str>,
+}
+
+impl<'a> Subject<'a> {
+    /// Video id and `@creator` as they appear in a TikTok/Douyin page URL.
+    pub fn from_url(url: &'a str) -> Self {
+        let video_id = segment_after(url, "/video/").or_else(|| segment_after(url, "/photo/"));
+        let creator = segment_after(url, "/@");
+        Self {
+            url: Some(url),
+            video_id,
+            creator,
+        }
+    }
+}
+
+fn segment_after<'a>(url: &'a str, marker: &str) -> Option<&'a str> {
+    let rest = &url[url.find(marker)? + marker.len()..];
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    Some(&rest[..end]).filter(|s| !s.is_empty())
+}
+
+/// A blocklist hit, rendered as `{"error", "code"}` with the rule's status.
+#[derive(Debug)]
+pub struct BlockMatch {
+    pub status: StatusCode,
+    pub code: String,
+}
+
+impl IntoResponse for BlockMatch {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            Json(serde_json::json!({
+                "error": "Content unavailable due to policy",
+                "code": self.code,
+            })),
+        )
+            .into_response()
+    }
+}
+
+#[derive(Serialize)]
+pub struct BlocklistStatus {
+    pub path: String,
+    pub rules: usize,
+    pub loaded_at: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    rules: Vec<Rule>,
+    modified: Option<SystemTime>,
+    loaded_at: Option<u64>,
+    error: Option<String>,
+}
+
+/// Video id / creator / URL pattern blocklist loaded from BLOCKLIST_PATH and
+/// reloaded when the file changes (or via POST /admin/blocklist/reload).
+pub struct Blocklist {
+    path: Option<PathBuf>,
+    inner: RwLock<Loaded>,
+}
+
+impl Blocklist {
+    pub fn new(path: &str) -> Self {
+        let list = Self {
+            path: (!path.is_empty()).then(|| PathBuf::from(path)),
+            inner: RwLock::new(Loaded::default()),
+        };
+        if list.path.is_some() {
+            if let Err(e) = list.reload() {
+                warn!("⚠️ Blocklist not loaded: {e}");
+            }
+        }
+        list
+    }
+
+    /// Re-read the file. On error the previous rules stay in effect.
+    pub fn reload(&self) -> Result<usize, String> {
+        let Some(ref path) = self.path else {
+            return Ok(0);
+        };
+        let result = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(|e| format!("{}: {e}", path.display()))
+            .and_then(|modified| {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+                Ok((modified, parse_rules(&text)?))
+            });
+
+        let mut inner = self.inner.write().unwrap();
+        match result {
+            Ok((modified, rules)) => {
+                let count = rules.len();
+                *inner = Loaded {
+                    rules,
+                    modified: Some(modified),
+                    loaded_at: Some(unix_now()),
+                    error: None,
+                };
+                info!("🚫 Blocklist loaded: {count} rules");
+                Ok(count)
+            }
+            Err(e) => {
+                inner.error = Some(e.clone());
+                Err(e)
+            }
+        }
+    }
+
+    fn reload_if_changed(&self) {
+        let Some(ref path) = self.path else {
+            return;
+        };
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified != self.inner.read().unwrap().modified {
+            if let Err(e) = self.reload() {
+                warn!("⚠️ Blocklist reload failed: {e}");
+            }
+        }
+    }
+
+    pub fn check(&self, subject: &Subject) -> Option<BlockMatch> {
+        let inner = self.inner.read().unwrap();
+        let hit = inner.rules.iter().find(|rule| match rule.kind {
+            RuleKind::VideoId => subject.video_id == Some(rule.value.as_str()),
+            RuleKind::Creator => subject
+                .creator
+                .is_some_and(|c| c.trim_start_matches('@').eq_ignore_ascii_case(&rule.value)),
+            RuleKind::UrlPattern => match (&rule.pattern, subject.url) {
+                (Some(re), Some(url)) => re.is_match(url),
+                _ => false,
+            },
+        })?;
+        info!("🚫 Blocked by {} rule {}", rule_label(hit.kind), hit.value);
+        Some(BlockMatch {
+            status: hit.status,
+            code: hit.code.clone(),
+        })
+    }
+
+    pub fn status(&self) -> BlocklistStatus {
+        let inner = self.inner.read().unwrap();
+        BlocklistStatus {
+            path: self
+                .path
+                .as_ref()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            rules: inner.rules.len(),
+            loaded_at: inner.loaded_at,
+            error: inner.error.clone(),
+        }
+    }
+}
+
+fn rule_label(kind: RuleKind) -> &'static str {
+    match kind {
+        RuleKind::VideoId => "video_id",
+        RuleKind::Creator => "creator",
+        RuleKind::UrlPattern => "url_pattern",
+    }
+}
+
+fn parse_rules(text: &str) -> Result<Vec<Rule>, String> {
+    let file: BlocklistFile =
+        serde_json::from_str(text).map_err(|e| format!("invalid blocklist JSON: {e}"))?;
+    file.rules
+        .into_iter()
+        .map(|spec| {
+            let pattern = match spec.kind {
+                RuleKind::UrlPattern => Some(
+                    Regex::new(&spec.value)
+                        .map_err(|e| format!("invalid url_pattern {}: {e}", spec.value))?,
+                ),
+                _ => None,
+            };
+            let status = StatusCode::from_u16(spec.status.unwrap_or(DEFAULT_STATUS))
+                .map_err(|e| format!("invalid status for {}: {e}", spec.value))?;
+            let value = match spec.kind {
+                RuleKind::Creator => spec.value.trim_start_matches('@').to_string(),
+                _ => spec.value,
+            };
+            Ok(Rule {
+                value,
+                kind: spec.kind,
+                pattern,
+                status,
+                code: spec.code.unwrap_or_else(|| DEFAULT_CODE.to_string()),
+            })
+        })
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Background task: reload the blocklist whenever the file's mtime changes.
+pub fn spawn_blocklist_reload_task(tasks: &TaskManager, blocklist: Arc<Blocklist>, interval_secs: u64) {
+    if blocklist.path.is_none() {
+        return;
+    }
+    tasks.spawn("blocklist_reload", |cancel| async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        while tick(&mut interval, &cancel).await {
+            let blocklist = blocklist.clone();
+            let _ = tokio::task::spawn_blocking(move || blocklist.reload_if_changed()).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocklist_rules() {
+        let rules = parse_rules(
+            r#"{"rules": [
+                {"type": "video_id", "value": "111", "code": "DMCA_TAKEDOWN"},
+                {"type": "creator", "value": "@BadUser", "status": 403},
+                {"type": "url_pattern", "value": "tiktok\\.com/music/"}
+            ]}"#,
+        )
+        .unwrap();
+        let list = Blocklist {
+            path: None,
+            inner: RwLock::new(Loaded {
+                rules,
+                ..Default::default()
+            }),
+        };
+
+        let hit = list
+            .check(&Subject::from_url("https://www.tiktok.com/@someone/video/111?lang=en"))
+            .unwrap();
+        assert_eq!(hit.status, StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+        assert_eq!(hit.code, "DMCA_TAKEDOWN");
+
+        let hit = list
+            .check(&Subject::from_url("https://www.tiktok.com/@baduser/photo/222"))
+            .unwrap();
+        assert_eq!(hit.status, StatusCode::FORBIDDEN);
+        assert_eq!(hit.code, DEFAULT_CODE);
+
+        assert!(list
+            .check(&Subject::from_url("https://www.tiktok.com/music/x-123"))
+            .is_some());
+        assert!(list
+            .check(&Subject::from_url("https://www.tiktok.com/@someone/video/222"))
+            .is_none());
+
+        assert!(parse_rules(r#"{"rules": [{"type": "url_pattern", "value": "("}]}"#).is_err());
+    }
+}
@@ -1,15 +1,51 @@
 use md5::{Digest, Md5};
+#[cfg(feature = "redis")]
 use redis::aio::ConnectionManager;
+#[cfg(feature = "redis")]
 use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
+use crate::metrics::{CacheResult, Metrics};
+
+/// How long extraction results stay in the metadata cache.
+pub const METADATA_TTL_SECS: u64 = 300;
+
+/// Entries kept by the in-memory backend; the one closest to expiry is
+/// dropped beyond this.
+const MEMORY_MAX_ENTRIES: usize = 10_000;
+
+/// Cache for extraction metadata and file checksums — Redis, or an
+/// in-process map when built without the `redis` feature. Hits, misses,
+/// failures and GET latency are recorded in `Metrics`.
 #[derive(Clone)]
 pub struct RedisCache {
-    conn: ConnectionManager,
+    backend: Backend,
+    /// Start of every key (REDIS_KEY_PREFIX), so deployments can share a Redis
+    key_prefix: String,
+    /// Metadata TTLs are spread by ± this percentage (CACHE_TTL_JITTER_PCT)
+    ttl_jitter_pct: u64,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Clone)]
+enum Backend {
+    #[cfg(feature = "redis")]
+    Redis(Box<ConnectionManager>),
+    Memory(Arc<MemoryStore>),
 }
 
 impl RedisCache {
-    pub async fn connect(host: &str, port: u16) -> Option<Self> {
+    #[cfg(feature = "redis")]
+    pub async fn connect(
+        host: &str,
+        port: u16,
+        key_prefix: &str,
+        ttl_jitter_pct: u64,
+        metrics: Arc<Metrics>,
+    ) -> Option<Self> {
         let url = format!("redis://{host}:{port}");
         match redis::Client::open(url.as_str()) {
             Ok(client) => {
@@ -20,7 +56,12 @@ impl RedisCache {
                 ).await {
                     Ok(Ok(conn)) => {
                         info!("✅ Redis connected at {host}:{port}");
-                        Some(Self { conn })
+                        Some(Self {
+                            backend: Backend::Redis(Box::new(conn)),
+                            key_prefix: key_prefix.to_string(),
+                            ttl_jitter_pct,
+                            metrics,
+                        })
                     }
                     Ok(Err(e)) => {
                         warn!("⚠️ Redis connection failed: {e}. Caching disabled.");
@@ -39,62 +80,711 @@ impl RedisCache {
         }
     }
 
-    pub async fn get_metadata(&self, url: &str) -> Option<String> {
-        let cache_key = format!("tiktok:metadata:{}", url_hash(url));
-        let mut conn = self.conn.clone();
-        match conn.get::<_, Option<String>>(&cache_key).await {
-            Ok(Some(cached)) => {
-                info!("✅ Cache HIT for {}...", &url[..url.len().min(50)]);
-                Some(cached)
-            }
-            Ok(None) => {
-                debug!("Cache MISS for {}...", &url[..url.len().min(50)]);
-                None
+    /// Per-instance cache for standalone builds (no `redis` feature).
+    #[cfg_attr(feature = "redis", allow(dead_code))]
+    pub fn in_memory(key_prefix: &str, ttl_jitter_pct: u64, metrics: Arc<Metrics>) -> Self {
+        info!("✅ Using in-memory cache (built without Redis)");
+        Self {
+            backend: Backend::Memory(Arc::new(MemoryStore::default())),
+            key_prefix: key_prefix.to_string(),
+            ttl_jitter_pct,
+            metrics,
+        }
+    }
+
+    fn key(&self, kind: &str, url: &str) -> String {
+        format!("{}:{kind}:{}", self.key_prefix, url_hash(url))
+    }
+
+    /// "redis" or "memory", for /health.
+    pub fn backend_name(&self) -> &'static str {
+        match self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(_) => "redis",
+            Backend::Memory(_) => "memory",
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => ConnectionManager::clone(conn).get(key).await.map_err(|e| e.to_string()),
+            Backend::Memory(store) => Ok(store.get(key)),
+        }
+    }
+
+    async fn set_ex(&self, key: &str, data: &str, ttl_secs: u64) -> Result<(), String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => ConnectionManager::clone(conn)
+                .set_ex(key, data, ttl_secs)
+                .await
+                .map_err(|e| e.to_string()),
+            Backend::Memory(store) => {
+                store.set(key, data, Duration::from_secs(ttl_secs));
+                Ok(())
             }
-            Err(e) => {
-                warn!("Redis get error: {e}");
-                None
+        }
+    }
+
+    async fn del(&self, keys: &[String]) -> Result<usize, String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => ConnectionManager::clone(conn).del(keys).await.map_err(|e| e.to_string()),
+            Backend::Memory(store) => Ok(store.remove(keys)),
+        }
+    }
+
+    /// Keys starting with `prefix`.
+    async fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            // SCAN in batches rather than KEYS, which blocks Redis while it
+            // walks the whole keyspace
+            Backend::Redis(conn) => {
+                let mut conn = ConnectionManager::clone(conn);
+                let mut keys = Vec::new();
+                let mut cursor = 0u64;
+                loop {
+                    let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                        .arg(cursor)
+                        .arg("MATCH")
+                        .arg(format!("{prefix}*"))
+                        .arg("COUNT")
+                        .arg(1000)
+                        .query_async(&mut conn)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    keys.extend(batch);
+                    if next == 0 {
+                        break;
+                    }
+                    cursor = next;
+                }
+                // A key can be returned more than once while the keyspace changes
+                keys.sort_unstable();
+                keys.dedup();
+                Ok(keys)
             }
+            Backend::Memory(store) => Ok(store.keys_with_prefix(prefix)),
         }
     }
 
-    pub async fn set_metadata(&self, url: &str, data: &str, ttl_secs: u64) {
-        let cache_key = format!("tiktok:metadata:{}", url_hash(url));
-        let mut conn = self.conn.clone();
-        if let Err(e) = conn
-            .set_ex::<_, _, ()>(&cache_key, data, ttl_secs)
+    /// INCRBY `key` and (re)set its TTL; the new value. The in-memory
+    /// backend keeps the TTL of an existing key.
+    async fn incr_by(&self, key: &str, n: u64, ttl_secs: u64) -> Result<u64, String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => redis::pipe()
+                .atomic()
+                .incr(key, n)
+                .expire(key, ttl_secs as i64)
+                .ignore()
+                .query_async::<(u64,)>(&mut ConnectionManager::clone(conn))
+                .await
+                .map(|(total,)| total)
+                .map_err(|e| e.to_string()),
+            Backend::Memory(store) => Ok(store.incr_by(key, n, Duration::from_secs(ttl_secs))),
+        }
+    }
+
+    /// SET `key` only if it doesn't exist; whether it was set.
+    async fn set_nx(&self, key: &str, data: &str, ttl_secs: u64) -> Result<bool, String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => redis::cmd("SET")
+                .arg(key)
+                .arg(data)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl_secs)
+                .query_async::<Option<String>>(&mut ConnectionManager::clone(conn))
+                .await
+                .map(|ok| ok.is_some())
+                .map_err(|e| e.to_string()),
+            Backend::Memory(store) => Ok(store.set_nx(key, data, Duration::from_secs(ttl_secs))),
+        }
+    }
+
+    /// DEL `key` if it still holds `data` (a lock we own, not one taken over
+    /// after ours expired).
+    async fn del_if_eq(&self, key: &str, data: &str) -> Result<(), String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => redis::Script::new(
+                "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end",
+            )
+            .key(key)
+            .arg(data)
+            .invoke_async::<i32>(&mut ConnectionManager::clone(conn))
             .await
-        {
-            warn!("Redis set error: {e}");
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+            Backend::Memory(store) => {
+                store.remove_if_eq(key, data);
+                Ok(())
+            }
+        }
+    }
+
+    /// GET `key`, counting the hit/miss/error and its latency under `cache`.
+    async fn get_counted(&self, cache: &'static str, key: &str) -> Option<String> {
+        let started = Instant::now();
+        let result = self.get(key).await;
+        self.metrics.observe_cache_get(cache, started.elapsed());
+        let (value, outcome) = match result {
+            Ok(Some(v)) => (Some(v), CacheResult::Hit),
+            Ok(None) => (None, CacheResult::Miss),
+            Err(e) => {
+                warn!("Cache get error: {e}");
+                (None, CacheResult::GetError)
+            }
+        };
+        self.metrics.record_cache(cache, outcome);
+        value
+    }
+
+    /// SET `key` with a TTL, counting success/failure under `cache`.
+    async fn set_counted(&self, cache: &'static str, key: &str, data: &str, ttl_secs: u64) -> bool {
+        match self.set_ex(key, data, ttl_secs).await {
+            Ok(()) => {
+                self.metrics.record_cache(cache, CacheResult::Set);
+                true
+            }
+            Err(e) => {
+                warn!("Cache set error: {e}");
+                self.metrics.record_cache(cache, CacheResult::SetError);
+                false
+            }
+        }
+    }
+
+    pub async fn get_metadata(&self, url: &str) -> Option<String> {
+        let cache_key = self.key("metadata", url);
+        let cached = self.get_counted("metadata", &cache_key).await;
+        if cached.is_some() {
+            info!("✅ Cache HIT for {}...", url.chars().take(50).collect::<String>());
         } else {
+            debug!("Cache MISS for {}...", url.chars().take(50).collect::<String>());
+        }
+        cached
+    }
+
+    /// Whether `url` is cached, without counting towards the hit ratio
+    /// (used by the pre-warmer).
+    pub async fn has_metadata(&self, url: &str) -> bool {
+        let cache_key = self.key("metadata", url);
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => ConnectionManager::clone(conn).exists(&cache_key).await.unwrap_or(false),
+            Backend::Memory(store) => store.get(&cache_key).is_some(),
+        }
+    }
+
+    /// Cache `data` for about `ttl_secs`, randomly spread by the TTL jitter so
+    /// a popular URL cached by many requests doesn't expire everywhere at once.
+    pub async fn set_metadata(&self, url: &str, data: &str, ttl_secs: u64) {
+        let cache_key = self.key("metadata", url);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        let ttl_secs = jittered_ttl(ttl_secs, self.ttl_jitter_pct, nanos as u64);
+        if self.set_counted("metadata", &cache_key, data, ttl_secs).await {
             debug!(
                 "Cached metadata for {}... (TTL: {ttl_secs}s)",
-                &url[..url.len().min(50)]
+                url.chars().take(50).collect::<String>()
             );
         }
     }
 
     pub async fn invalidate(&self, url: &str) {
-        let cache_key = format!("tiktok:metadata:{}", url_hash(url));
-        let mut conn = self.conn.clone();
-        if let Err(e) = conn.del::<_, ()>(&cache_key).await {
-            warn!("Redis delete error: {e}");
+        let cache_key = self.key("metadata", url);
+        if let Err(e) = self.del(&[cache_key]).await {
+            warn!("Cache delete error: {e}");
         } else {
-            debug!("Invalidated cache for {}...", &url[..url.len().min(50)]);
+            debug!("Invalidated cache for {}...", url.chars().take(50).collect::<String>());
         }
     }
 
-    pub async fn ping(&self) -> bool {
-        let mut conn = self.conn.clone();
-        redis::cmd("PING")
-            .query_async::<String>(&mut conn)
+    /// Drop every cached metadata entry (e.g. after cookies changed, since
+    /// cached formats carry the old cookies). Returns the number removed.
+    pub async fn clear_metadata(&self) -> usize {
+        let keys = match self.keys_with_prefix(&format!("{}:metadata:", self.key_prefix)).await {
+            Ok(k) => k,
+            Err(e) => {
+                warn!("Cache keys error: {e}");
+                return 0;
+            }
+        };
+        if keys.is_empty() {
+            return 0;
+        }
+        match self.del(&keys).await {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("Cache delete error: {e}");
+                0
+            }
+        }
+    }
+
+    /// Take the extraction lock for `url` for up to `ttl_secs`, so only one
+    /// instance extracts a URL missing from the cache. `None` while another
+    /// request holds it. Fails open: a cache error counts as acquired.
+    pub async fn try_lock_extraction(&self, url: &str, owner: &str, ttl_secs: u64) -> Option<ExtractionLock> {
+        let key = self.key("lock", url);
+        match self.set_nx(&key, owner, ttl_secs).await {
+            Ok(false) => return None,
+            Ok(true) => {}
+            Err(e) => warn!("Extraction lock error: {e}"),
+        }
+        Some(ExtractionLock {
+            cache: self.clone(),
+            key,
+            owner: owner.to_string(),
+        })
+    }
+
+    /// Add `bytes` to an API key's usage on `day` (YYYY-MM-DD, UTC).
+    pub async fn add_usage(&self, day: &str, api_key: &str, bytes: u64, retention_days: u64) {
+        let key = format!("{}:usage:{day}:{api_key}", self.key_prefix);
+        if let Err(e) = self.incr_by(&key, bytes, retention_days * 86_400).await {
+            warn!("Usage update error: {e}");
+        }
+    }
+
+    /// Bytes an API key used on `day`; 0 when unknown or on cache errors.
+    pub async fn usage(&self, day: &str, api_key: &str) -> u64 {
+        let key = format!("{}:usage:{day}:{api_key}", self.key_prefix);
+        match self.get(&key).await {
+            Ok(v) => v.and_then(|v| v.parse().ok()).unwrap_or(0),
+            Err(e) => {
+                warn!("Usage read error: {e}");
+                0
+            }
+        }
+    }
+
+    /// Bytes used on `day` per API key.
+    pub async fn usage_for_day(&self, day: &str) -> Result<Vec<(String, u64)>, String> {
+        let prefix = format!("{}:usage:{day}:", self.key_prefix);
+        let mut usage = Vec::new();
+        for key in self.keys_with_prefix(&prefix).await? {
+            let bytes = self.get(&key).await?.and_then(|v| v.parse().ok()).unwrap_or(0);
+            usage.push((key[prefix.len()..].to_string(), bytes));
+        }
+        usage.sort();
+        Ok(usage)
+    }
+
+    /// Take one of `limit` concurrent stream slots for `client` for
+    /// `lease_secs`; a heartbeat keeps renewing it until the slot is
+    /// dropped, so slots of crashed instances run out on their own. `None`
+    /// when the client already has `limit` streams. Fails open like the
+    /// extraction lock.
+    pub async fn try_acquire_stream_slot(
+        &self,
+        client: &str,
+        id: &str,
+        limit: u32,
+        lease_secs: u64,
+    ) -> Option<StreamSlot> {
+        let key = format!("{}:streams:{client}", self.key_prefix);
+        match self.slot_acquire(&key, id, limit, lease_secs).await {
+            Ok(false) => return None,
+            Ok(true) => {}
+            Err(e) => warn!("Stream slot error: {e}"),
+        }
+        let cache = self.clone();
+        let (hb_key, hb_id) = (key.clone(), id.to_string());
+        let heartbeat = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs((lease_secs / 3).max(1)));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = cache.slot_renew(&hb_key, &hb_id, lease_secs).await {
+                    warn!("Stream slot heartbeat error: {e}");
+                }
+            }
+        });
+        Some(StreamSlot {
+            cache: self.clone(),
+            key,
+            id: id.to_string(),
+            heartbeat,
+        })
+    }
+
+    /// Add `id` to the set of live slots at `key` unless it holds `limit`
+    /// unexpired ones; whether it was added.
+    async fn slot_acquire(&self, key: &str, id: &str, limit: u32, lease_secs: u64) -> Result<bool, String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => redis::Script::new(
+                "redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', ARGV[1]) \
+                 if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[2]) then return 0 end \
+                 redis.call('ZADD', KEYS[1], ARGV[3], ARGV[4]) \
+                 redis.call('EXPIRE', KEYS[1], ARGV[5]) \
+                 return 1",
+            )
+            .key(key)
+            .arg(unix_millis())
+            .arg(limit)
+            .arg(unix_millis() + lease_secs * 1000)
+            .arg(id)
+            .arg(lease_secs)
+            .invoke_async::<i32>(&mut ConnectionManager::clone(conn))
             .await
-            .is_ok()
+            .map(|added| added == 1)
+            .map_err(|e| e.to_string()),
+            Backend::Memory(store) => Ok(store.slot_acquire(key, id, limit, Duration::from_secs(lease_secs))),
+        }
+    }
+
+    async fn slot_renew(&self, key: &str, id: &str, lease_secs: u64) -> Result<(), String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => redis::pipe()
+                .cmd("ZADD")
+                .arg(key)
+                .arg("XX")
+                .arg(unix_millis() + lease_secs * 1000)
+                .arg(id)
+                .ignore()
+                .expire(key, lease_secs as i64)
+                .ignore()
+                .query_async::<()>(&mut ConnectionManager::clone(conn))
+                .await
+                .map_err(|e| e.to_string()),
+            Backend::Memory(store) => {
+                store.slot_renew(key, id, Duration::from_secs(lease_secs));
+                Ok(())
+            }
+        }
+    }
+
+    async fn slot_release(&self, key: &str, id: &str) -> Result<(), String> {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => ConnectionManager::clone(conn)
+                .zrem::<_, _, ()>(key, id)
+                .await
+                .map_err(|e| e.to_string()),
+            Backend::Memory(store) => {
+                store.slot_release(key, id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Cached `{"sha256", "size"}` for a CDN URL downloaded in file mode.
+    pub async fn get_checksum(&self, url: &str) -> Option<String> {
+        let cache_key = self.key("checksum", url);
+        self.get_counted("checksum", &cache_key).await
+    }
+
+    pub async fn set_checksum(&self, url: &str, data: &str, ttl_secs: u64) {
+        let cache_key = self.key("checksum", url);
+        self.set_counted("checksum", &cache_key, data, ttl_secs).await;
+    }
+
+    /// Path and query a /s/{id} short link points to.
+    pub async fn get_short_link(&self, id: &str) -> Option<String> {
+        let cache_key = format!("{}:short:{id}", self.key_prefix);
+        self.get_counted("short_link", &cache_key).await
+    }
+
+    /// Store `target` under `id` unless it's there already: ids derive from
+    /// their target, so an existing one already points at the same link.
+    pub async fn set_short_link(&self, id: &str, target: &str, ttl_secs: u64) -> bool {
+        let cache_key = format!("{}:short:{id}", self.key_prefix);
+        match self.set_nx(&cache_key, target, ttl_secs).await {
+            Ok(set) => {
+                if set {
+                    self.metrics.record_cache("short_link", CacheResult::Set);
+                }
+                true
+            }
+            Err(e) => {
+                warn!("Cache set error: {e}");
+                self.metrics.record_cache("short_link", CacheResult::SetError);
+                false
+            }
+        }
+    }
+
+    pub async fn ping(&self) -> bool {
+        match &self.backend {
+            #[cfg(feature = "redis")]
+            Backend::Redis(conn) => redis::cmd("PING")
+                .query_async::<String>(&mut ConnectionManager::clone(conn))
+                .await
+                .is_ok(),
+            Backend::Memory(_) => true,
+        }
     }
 }
 
+/// Held extraction lock; released (in the background) when dropped.
+pub struct ExtractionLock {
+    cache: RedisCache,
+    key: String,
+    owner: String,
+}
+
+impl Drop for ExtractionLock {
+    fn drop(&mut self) {
+        let cache = self.cache.clone();
+        let key = std::mem::take(&mut self.key);
+        let owner = std::mem::take(&mut self.owner);
+        tokio::spawn(async move {
+            if let Err(e) = cache.del_if_eq(&key, &owner).await {
+                warn!("Failed to release extraction lock: {e}");
+            }
+        });
+    }
+}
+
+/// Held concurrent stream slot; the heartbeat stops and the slot is
+/// released (in the background) when dropped.
+pub struct StreamSlot {
+    cache: RedisCache,
+    key: String,
+    id: String,
+    heartbeat: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+        let cache = self.cache.clone();
+        let key = std::mem::take(&mut self.key);
+        let id = std::mem::take(&mut self.id);
+        tokio::spawn(async move {
+            if let Err(e) = cache.slot_release(&key, &id).await {
+                warn!("Failed to release stream slot: {e}");
+            }
+        });
+    }
+}
+
+/// In-process stand-in for Redis: values with an expiry, swept on write,
+/// and stream slot sets (slot id -> lease expiry).
+#[derive(Default)]
+struct MemoryStore {
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+    slots: Mutex<HashMap<String, HashMap<String, Instant>>>,
+}
+
+impl MemoryStore {
+    fn get(&self, key: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(v, _)| v.clone())
+    }
+
+    fn set(&self, key: &str, data: &str, ttl: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, expires)| *expires > now);
+        if entries.len() >= MEMORY_MAX_ENTRIES && !entries.contains_key(key) {
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, (_, expires))| *expires)
+                .map(|(k, _)| k.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(key.to_string(), (data.to_string(), now + ttl));
+    }
+
+    fn incr_by(&self, key: &str, n: u64, ttl: Duration) -> u64 {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        let (value, expires) = entries
+            .get(key)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(v, expires)| (v.parse().unwrap_or(0), *expires))
+            .unwrap_or((0, now + ttl));
+        let total: u64 = value + n;
+        entries.insert(key.to_string(), (total.to_string(), expires));
+        total
+    }
+
+    fn set_nx(&self, key: &str, data: &str, ttl: Duration) -> bool {
+        if self.get(key).is_some() {
+            return false;
+        }
+        self.set(key, data, ttl);
+        true
+    }
+
+    fn remove_if_eq(&self, key: &str, data: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.get(key).is_some_and(|(v, _)| v == data) {
+            entries.remove(key);
+        }
+    }
+
+    fn remove(&self, keys: &[String]) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        keys.iter().filter(|k| entries.remove(k.as_str()).is_some()).count()
+    }
+
+    fn slot_acquire(&self, key: &str, id: &str, limit: u32, lease: Duration) -> bool {
+        let now = Instant::now();
+        let mut slots = self.slots.lock().unwrap();
+        slots.retain(|_, set| {
+            set.retain(|_, expires| *expires > now);
+            !set.is_empty()
+        });
+        let set = slots.entry(key.to_string()).or_default();
+        if set.len() >= limit as usize {
+            return false;
+        }
+        set.insert(id.to_string(), now + lease);
+        true
+    }
+
+    fn slot_renew(&self, key: &str, id: &str, lease: Duration) {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some(expires) = slots.get_mut(key).and_then(|set| set.get_mut(id)) {
+            *expires = Instant::now() + lease;
+        }
+    }
+
+    fn slot_release(&self, key: &str, id: &str) {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some(set) = slots.get_mut(key) {
+            set.remove(id);
+        }
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|(k, (_, expires))| k.starts_with(prefix) && *expires > now)
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+}
+
+/// `ttl_secs` moved by up to ± `pct` percent, picked by `seed`; never 0
+/// unless `ttl_secs` is.
+fn jittered_ttl(ttl_secs: u64, pct: u64, seed: u64) -> u64 {
+    let span = ttl_secs * pct.min(100) / 100;
+    if span == 0 {
+        return ttl_secs;
+    }
+    (ttl_secs - span + seed % (2 * span + 1)).max(1)
+}
+
+#[cfg(feature = "redis")]
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
 fn url_hash(url: &str) -> String {
     let mut hasher = Md5::new();
     hasher.update(url.as_bytes());
     format!("{:x}", hasher.finalize())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_cache() {
+        let cache = RedisCache::in_memory("tiktok", 0, Arc::new(Metrics::default()));
+        assert_eq!(cache.backend_name(), "memory");
+        assert!(cache.ping().await);
+
+        let url = "https://www.tiktok.com/@a/video/1";
+        assert!(cache.get_metadata(url).await.is_none());
+        cache.set_metadata(url, "{}", 60).await;
+        assert!(cache.has_metadata(url).await);
+        assert_eq!(cache.get_metadata(url).await.as_deref(), Some("{}"));
+        cache.set_checksum(url, "{\"size\":1}", 60).await;
+
+        cache.set_metadata("https://www.tiktok.com/@a/video/2", "{}", 60).await;
+        assert_eq!(cache.clear_metadata().await, 2);
+        assert!(!cache.has_metadata(url).await);
+        assert!(cache.get_checksum(url).await.is_some());
+
+        cache.set_metadata(url, "{}", 0).await;
+        assert!(cache.get_metadata(url).await.is_none());
+
+        let other = RedisCache::in_memory("eu", 0, Arc::new(Metrics::default()));
+        assert_eq!(other.key("metadata", url), format!("eu:metadata:{}", url_hash(url)));
+    }
+
+    #[tokio::test]
+    async fn test_extraction_lock() {
+        let cache = RedisCache::in_memory("tiktok", 0, Arc::new(Metrics::default()));
+        let url = "https://www.tiktok.com/@a/video/1";
+        let lock = cache.try_lock_extraction(url, "a", 60).await.unwrap();
+        assert!(cache.try_lock_extraction(url, "b", 60).await.is_none());
+        assert!(cache.try_lock_extraction("https://www.tiktok.com/@a/video/2", "b", 60).await.is_some());
+
+        // Someone else's value is left alone
+        cache.del_if_eq(&lock.key, "b").await.unwrap();
+        assert!(cache.try_lock_extraction(url, "b", 60).await.is_none());
+
+        drop(lock);
+        tokio::task::yield_now().await;
+        assert!(cache.try_lock_extraction(url, "b", 60).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stream_slots() {
+        let cache = RedisCache::in_memory("tiktok", 0, Arc::new(Metrics::default()));
+        let a = cache.try_acquire_stream_slot("ip:1.2.3.4", "a", 2, 30).await.unwrap();
+        let _b = cache.try_acquire_stream_slot("ip:1.2.3.4", "b", 2, 30).await.unwrap();
+        assert!(cache.try_acquire_stream_slot("ip:1.2.3.4", "c", 2, 30).await.is_none());
+        assert!(cache.try_acquire_stream_slot("ip:5.6.7.8", "c", 2, 30).await.is_some());
+
+        drop(a);
+        tokio::task::yield_now().await;
+        assert!(cache.try_acquire_stream_slot("ip:1.2.3.4", "c", 2, 30).await.is_some());
+
+        // Leases that aren't renewed run out
+        let key = "tiktok:streams:ip:9.9.9.9";
+        assert!(cache.slot_acquire(key, "x", 1, 0).await.unwrap());
+        assert!(cache.slot_acquire(key, "y", 1, 30).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_usage_counters() {
+        let cache = RedisCache::in_memory("tiktok", 0, Arc::new(Metrics::default()));
+        assert_eq!(cache.usage("2026-10-16", "k1").await, 0);
+        cache.add_usage("2026-10-16", "k1", 1000, 90).await;
+        cache.add_usage("2026-10-16", "k1", 24, 90).await;
+        cache.add_usage("2026-10-16", "k:2", 5, 90).await;
+        cache.add_usage("2026-10-15", "k1", 7, 90).await;
+        assert_eq!(cache.usage("2026-10-16", "k1").await, 1024);
+        assert_eq!(
+            cache.usage_for_day("2026-10-16").await.unwrap(),
+            vec![("k1".to_string(), 1024), ("k:2".to_string(), 5)]
+        );
+    }
+
+    #[test]
+    fn test_jittered_ttl() {
+        assert_eq!(jittered_ttl(300, 0, 12345), 300);
+        assert_eq!(jittered_ttl(300, 10, 0), 270);
+        assert_eq!(jittered_ttl(300, 10, 60), 330);
+        assert!((0..1000).all(|seed| (270..=330).contains(&jittered_ttl(300, 10, seed))));
+        assert_eq!(jittered_ttl(0, 10, 7), 0);
+        assert_eq!(jittered_ttl(2, 100, 0), 1);
+    }
+}
@@ -0,0 +1,35 @@
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Response header carrying the SHA-256 of the served body.
+pub const CHECKSUM_HEADER: &str = "x-content-sha256";
+
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Hash a file without loading it into memory. Blocking — call from spawn_blocking.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_file_matches_bytes() {
+        let path = std::env::temp_dir().join(format!("serverrs-checksum-{}", std::process::id()));
+        std::fs::write(&path, b"abc").unwrap();
+        let from_file = sha256_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file, sha256_bytes(b"abc"));
+        assert_eq!(
+            from_file,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
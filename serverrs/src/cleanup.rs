@@ -2,6 +2,8 @@ use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{error, info};
 
+use crate::tasks::{tick, TaskManager};
+
 /// Remove a folder and all its contents (blocking)
 pub fn cleanup_folder(folder_path: &str) {
     let path = Path::new(folder_path);
@@ -14,7 +16,8 @@ pub fn cleanup_folder(folder_path: &str) {
     }
 }
 
-/// Remove folders older than max_age_seconds. Returns number of folders removed.
+/// Remove folders older than max_age_seconds. Hidden folders (the slideshow
+/// asset cache) are left alone. Returns number of folders removed.
 pub fn cleanup_old_folders(base_dir: &str, max_age_seconds: u64) -> usize {
     let base = Path::new(base_dir);
     if !base.exists() {
@@ -38,7 +41,7 @@ pub fn cleanup_old_folders(base_dir: &str, max_age_seconds: u64) -> usize {
 
     for entry in entries.flatten() {
         let path = entry.path();
-        if !path.is_dir() {
+        if !path.is_dir() || entry.file_name().to_string_lossy().starts_with('.') {
             continue;
         }
 
@@ -64,15 +67,14 @@ pub fn cleanup_old_folders(base_dir: &str, max_age_seconds: u64) -> usize {
 
 /// Spawn a background cleanup task that runs every 15 minutes.
 /// Call this once at startup.
-pub fn spawn_cleanup_task(temp_dir: String) {
-    tokio::spawn(async move {
+pub fn spawn_cleanup_task(tasks: &TaskManager, temp_dir: String) {
+    tasks.spawn("cleanup", |cancel| async move {
         info!("Initializing cleanup schedule for: {temp_dir}");
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(15 * 60));
         // Skip the first immediate tick
         interval.tick().await;
 
-        loop {
-            interval.tick().await;
+        while tick(&mut interval, &cancel).await {
             let dir = temp_dir.clone();
             let removed = tokio::task::spawn_blocking(move || {
                 cleanup_old_folders(&dir, 3600) // 1 hour max age
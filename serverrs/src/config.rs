@@ -1,18 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::path::PathBuf;
 
+use crate::features::FeatureFlags;
+use crate::streams::IpNet;
+
 #[derive(Clone, Debug)]
 pub struct Settings {
     pub port: u16,
     pub base_url: String,
+    /// Add the `formats` catalog to /tiktok responses (`formats` overrides)
+    pub response_formats: bool,
     pub encryption_key: String,
     pub temp_dir: PathBuf,
-    pub cookies_path: PathBuf,
+    /// /health fails the temp_dir check below this much free space
+    pub health_min_free_mb: u64,
+    /// Cookie profiles rotated on AUTH_REQUIRED / rate limits (COOKIES_PATHS, or COOKIES_PATH)
+    pub cookies_paths: Vec<PathBuf>,
+    /// Douyin's own profiles (DOUYIN_COOKIES_PATHS, or DOUYIN_COOKIES_PATH);
+    /// optional, Douyin is extracted without cookies until one is uploaded
+    pub douyin_cookies_paths: Vec<PathBuf>,
+    pub cookie_cooldown_secs: u64,
+    pub cookie_check_interval_secs: u64,
+    pub cookie_expiry_warn_days: u64,
+    pub admin_token: String,
+    pub user_agent_rotation: bool,
+    pub user_agents_file: String,
+    /// Extra outbound headers per platform (PLATFORM_HEADERS, JSON)
+    pub platform_headers: HashMap<String, BTreeMap<String, String>>,
     pub max_workers: usize,
+    pub extraction_queue_size: usize,
+    /// API key -> tier ("free", "paid", "premium") from API_KEY_TIERS=key:tier,...
+    pub api_key_tiers: HashMap<String, String>,
+    /// Tier -> daily /stream + /download bandwidth in bytes (BANDWIDTH_QUOTA_MB);
+    /// tiers not listed are unlimited
+    pub bandwidth_quota: HashMap<String, u64>,
+    /// Days the per-key daily usage counters are kept
+    pub usage_retention_days: u64,
+    /// Simultaneous /stream + /download responses per API key or client IP
+    /// across all instances; 0 = unlimited
+    pub max_streams_per_client: u32,
+    /// Reverse proxies whose X-Forwarded-For / X-Real-IP name the client
+    /// (TRUSTED_PROXIES); anyone else is known by their TCP address
+    pub trusted_proxies: Vec<IpNet>,
+    /// Stream slots not renewed by their instance for this long are freed
+    pub stream_slot_lease_secs: u64,
+    /// Seconds a queued extraction waits per extra priority level
+    pub priority_aging_secs: u64,
+    /// Finished jobs kept for GET /admin/jobs
+    pub job_history: usize,
+    /// Scheduled jobs (jobs.json) and their prefetched files
+    pub schedule_dir: PathBuf,
+    pub schedule_poll_secs: u64,
+    /// Scheduled jobs running at once
+    pub schedule_concurrency: usize,
+    /// Scheduled or running jobs allowed before POST /jobs returns 429
+    pub schedule_max_jobs: usize,
+    /// Jobs one API key / client address may submit per hour; 0 = no limit
+    pub schedule_client_jobs_per_hour: usize,
+    pub schedule_max_ahead_days: u64,
+    /// Finished scheduled jobs (and prefetched files) are kept this long
+    pub schedule_retention_hours: u64,
+    /// Creator watchers (POST /admin/watchers), persisted as JSON
+    pub watchers_path: PathBuf,
+    pub watch_min_interval_secs: u64,
+    /// Newest posts listed per watcher check
+    pub watch_playlist_limit: usize,
+    pub watch_webhook_timeout_secs: u64,
+    /// URLs extracted at once by POST /admin/prewarm
+    pub prewarm_concurrency: usize,
+    pub prewarm_max_urls: usize,
+    /// Concurrent slideshow asset downloads / ffprobe checks
+    pub slideshow_workers: usize,
+    /// Slideshow FFmpeg runs at once, and how many more may wait
+    pub ffmpeg_concurrency: usize,
+    pub ffmpeg_queue_size: usize,
+    /// Downloaded slideshow assets are reused for this long (0 = off)
+    pub slideshow_asset_ttl_secs: u64,
+    /// /tiktok image-post sessions usable by /download-slideshow (0 = off)
+    pub slideshow_session_ttl_secs: u64,
+    /// Video codec for `output=webm` slideshows: "vp9" or "av1"
+    pub slideshow_webm_codec: String,
+    /// Largest audio file accepted by POST /download-slideshow
+    pub slideshow_max_audio_mb: usize,
+    /// Default slideshow caption: none, title, author or both (`caption=` overrides)
+    pub slideshow_caption: String,
+    /// top or bottom (`caption_position=` overrides)
+    pub slideshow_caption_position: String,
+    /// Fontconfig family name or font file path for captions
+    pub slideshow_caption_font: String,
+    pub slideshow_caption_size: u32,
+    /// Default slideshow timing: fixed, audio or native (`timing=` overrides)
+    pub slideshow_timing: String,
+    /// Longest slideshow generated with audio timing
+    pub slideshow_max_secs: u64,
+    pub max_blocking_threads: usize,
     pub ytdlp_timeout: u64,
+    pub ytdlp_max_timeout: u64,
+    pub ytdlp_retries: u32,
+    pub ytdlp_retry_backoff_ms: u64,
+    pub ytdlp_retry_rotate_vpn: bool,
+    /// How long one instance may hold a URL's extraction lock while others
+    /// wait for its cached result; 0 disables the lock
+    pub extraction_lock_ttl_secs: u64,
+    pub fallback_providers: Vec<String>,
+    /// Platforms / per-platform capabilities switched off (DISABLED_FEATURES)
+    pub features: FeatureFlags,
+    pub preload_extractors: bool,
     pub download_timeout: u64,
+    /// Largest file downloaded to disk (mode=file, /checksum, prefetch); 0
+    /// for no limit
+    pub max_download_bytes: u64,
+    /// Range-resume attempts after the upstream breaks mid-stream
+    pub stream_max_resumes: u32,
+    /// JSON blocklist of video ids / creators / URL patterns; empty disables it
+    pub blocklist_path: String,
+    pub blocklist_reload_interval_secs: u64,
+    /// Moderation webhook called before download links are generated; empty disables it
+    pub moderation_webhook_url: String,
+    pub moderation_webhook_token: String,
+    pub moderation_timeout_secs: u64,
+    /// "block" or "tag" flagged content
+    pub moderation_policy: String,
+    /// Serve content when the webhook is unreachable
+    pub moderation_fail_open: bool,
+    /// Send X-Content-SHA256 on file-mode and slideshow downloads
+    pub checksum_header: bool,
+    /// How long computed checksums stay queryable via /checksum
+    pub checksum_ttl_secs: u64,
+    /// Add /s/{id} short links for the download links to /tiktok responses
+    pub short_links: bool,
+    /// Include the `watermark` / `watermark_hd` links in /tiktok responses
+    pub watermark_links: bool,
+    /// Tenant profiles (JSON); empty serves every request with these settings
+    pub tenants_path: String,
+    /// Discord webhook for operational notifications; empty disables them
+    pub discord_webhook_url: String,
+    /// Events posted to Discord: vpn, cookies, error_spike, jobs
+    pub discord_events: Vec<String>,
+    /// Extraction failures are compared against the threshold per window
+    pub error_spike_window_secs: u64,
+    pub error_spike_threshold_pct: u64,
+    /// Windows with fewer yt-dlp attempts never count as a spike
+    pub error_spike_min_attempts: u64,
+    /// "native" or "aria2c" for file-mode and slideshow downloads
+    pub download_backend: String,
+    pub aria2c_path: String,
+    /// External aria2 JSON-RPC endpoint; empty spawns a local aria2c
+    pub aria2_rpc_url: String,
+    pub aria2_rpc_port: u16,
+    pub aria2_rpc_secret: String,
+    pub aria2_connections: u32,
+    /// Upper bound for the per-request `connections` parameter
+    pub segmented_max_connections: usize,
+    pub segment_size_mb: u64,
+    /// Range-resume attempts for /stream?mode=file
+    pub file_mode_max_resumes: u32,
     pub redis_host: String,
     pub redis_port: u16,
+    /// Start of every cache key, so several deployments can share one Redis
+    pub redis_key_prefix: String,
+    /// Metadata cache TTLs are randomly spread by ± this percentage
+    pub cache_ttl_jitter_pct: u64,
+    /// Seconds background tasks get to stop after the server has drained
+    pub shutdown_timeout_secs: u64,
     pub instance_id: String,
     pub instance_region: String,
     pub gluetun_control_port: u16,
@@ -25,17 +176,103 @@ impl Settings {
         Self {
             port: env_parse("PORT", 3021),
             base_url: env_str("BASE_URL", "http://localhost:3021"),
+            response_formats: env_parse("RESPONSE_FORMATS", false),
             encryption_key: env_str("ENCRYPTION_KEY", "overflow"),
             temp_dir: PathBuf::from(env_str("TEMP_DIR", "./temp")),
-            cookies_path: PathBuf::from(env_str(
-                "COOKIES_PATH",
-                "./cookies/www.tiktok.com_cookies.txt",
-            )),
-            max_workers: env_parse("MAX_WORKERS", 20),
+            health_min_free_mb: env_parse("HEALTH_MIN_FREE_MB", 1024),
+            cookies_paths: cookies_paths("", "./cookies/www.tiktok.com_cookies.txt"),
+            douyin_cookies_paths: cookies_paths("DOUYIN_", "./cookies/www.douyin.com_cookies.txt"),
+            cookie_cooldown_secs: env_parse("COOKIE_COOLDOWN_SECS", 900),
+            cookie_check_interval_secs: env_parse("COOKIE_CHECK_INTERVAL_SECS", 3600),
+            cookie_expiry_warn_days: env_parse("COOKIE_EXPIRY_WARN_DAYS", 3),
+            admin_token: env_str("ADMIN_TOKEN", ""),
+            user_agent_rotation: env_parse("USER_AGENT_ROTATION", true),
+            user_agents_file: env_str("USER_AGENTS_FILE", ""),
+            platform_headers: platform_headers(),
+            max_workers: env_parse::<usize>("MAX_WORKERS", 20).max(1),
+            extraction_queue_size: env_parse("EXTRACTION_QUEUE_SIZE", 50),
+            api_key_tiers: api_key_tiers(),
+            bandwidth_quota: bandwidth_quota(),
+            usage_retention_days: env_parse::<u64>("USAGE_RETENTION_DAYS", 90).max(1),
+            max_streams_per_client: env_parse("MAX_STREAMS_PER_CLIENT", 0),
+            trusted_proxies: trusted_proxies(),
+            stream_slot_lease_secs: env_parse::<u64>("STREAM_SLOT_LEASE_SECS", 30).max(3),
+            priority_aging_secs: env_parse("PRIORITY_AGING_SECS", 10),
+            job_history: env_parse("JOB_HISTORY", 200),
+            schedule_dir: PathBuf::from(env_str("SCHEDULE_DIR", "./data/schedule")),
+            schedule_poll_secs: env_parse("SCHEDULE_POLL_SECS", 5),
+            schedule_concurrency: env_parse("SCHEDULE_CONCURRENCY", 2),
+            schedule_max_jobs: env_parse("SCHEDULE_MAX_JOBS", 1000),
+            schedule_client_jobs_per_hour: env_parse("SCHEDULE_CLIENT_JOBS_PER_HOUR", 30),
+            schedule_max_ahead_days: env_parse("SCHEDULE_MAX_AHEAD_DAYS", 30),
+            schedule_retention_hours: env_parse("SCHEDULE_RETENTION_HOURS", 48),
+            watchers_path: PathBuf::from(env_str("WATCHERS_PATH", "./data/watchers.json")),
+            watch_min_interval_secs: env_parse("WATCH_MIN_INTERVAL_SECS", 300),
+            watch_playlist_limit: env_parse("WATCH_PLAYLIST_LIMIT", 20),
+            watch_webhook_timeout_secs: env_parse("WATCH_WEBHOOK_TIMEOUT_SECS", 10),
+            prewarm_concurrency: env_parse("PREWARM_CONCURRENCY", 2),
+            prewarm_max_urls: env_parse("PREWARM_MAX_URLS", 500),
+            slideshow_workers: env_parse::<usize>("SLIDESHOW_WORKERS", 4).max(1),
+            ffmpeg_concurrency: env_parse("FFMPEG_CONCURRENCY", 2),
+            ffmpeg_queue_size: env_parse("FFMPEG_QUEUE_SIZE", 20),
+            slideshow_asset_ttl_secs: env_parse("SLIDESHOW_ASSET_TTL_SECS", 600),
+            slideshow_session_ttl_secs: env_parse("SLIDESHOW_SESSION_TTL_SECS", 360),
+            slideshow_webm_codec: env_str("SLIDESHOW_WEBM_CODEC", "vp9").to_lowercase(),
+            slideshow_max_audio_mb: env_parse("SLIDESHOW_MAX_AUDIO_MB", 20),
+            slideshow_caption: env_str("SLIDESHOW_CAPTION", "none"),
+            slideshow_caption_position: env_str("SLIDESHOW_CAPTION_POSITION", "bottom"),
+            slideshow_caption_font: env_str("SLIDESHOW_CAPTION_FONT", "DejaVu Sans"),
+            slideshow_caption_size: env_parse("SLIDESHOW_CAPTION_SIZE", 48),
+            slideshow_timing: env_str("SLIDESHOW_TIMING", "fixed"),
+            slideshow_max_secs: env_parse("SLIDESHOW_MAX_SECS", 180),
+            max_blocking_threads: env_parse::<usize>("MAX_BLOCKING_THREADS", 512).max(1),
             ytdlp_timeout: env_parse("YTDLP_TIMEOUT", 30),
+            ytdlp_max_timeout: env_parse("YTDLP_MAX_TIMEOUT", 120),
+            ytdlp_retries: env_parse("YTDLP_RETRIES", 2),
+            extraction_lock_ttl_secs: env_parse("EXTRACTION_LOCK_TTL_SECS", 60),
+            ytdlp_retry_backoff_ms: env_parse("YTDLP_RETRY_BACKOFF_MS", 500),
+            ytdlp_retry_rotate_vpn: env_parse("YTDLP_RETRY_ROTATE_VPN", false),
+            fallback_providers: env_list("FALLBACK_PROVIDERS"),
+            features: FeatureFlags::from_env(),
+            preload_extractors: env_parse("PRELOAD_EXTRACTORS", true),
             download_timeout: env_parse("DOWNLOAD_TIMEOUT", 120),
+            max_download_bytes: env_parse("MAX_DOWNLOAD_BYTES", 0),
+            stream_max_resumes: env_parse("STREAM_MAX_RESUMES", 3),
+            file_mode_max_resumes: env_parse("FILE_MODE_MAX_RESUMES", 5),
+            segmented_max_connections: env_parse("SEGMENTED_MAX_CONNECTIONS", 8),
+            blocklist_path: env_str("BLOCKLIST_PATH", ""),
+            blocklist_reload_interval_secs: env_parse("BLOCKLIST_RELOAD_INTERVAL", 30),
+            moderation_webhook_url: env_str("MODERATION_WEBHOOK_URL", ""),
+            moderation_webhook_token: env_str("MODERATION_WEBHOOK_TOKEN", ""),
+            moderation_timeout_secs: env_parse("MODERATION_TIMEOUT", 5),
+            moderation_policy: env_str("MODERATION_POLICY", "block"),
+            moderation_fail_open: env_parse("MODERATION_FAIL_OPEN", true),
+            checksum_header: env_parse("CHECKSUM_HEADER", false),
+            checksum_ttl_secs: env_parse("CHECKSUM_TTL", 86400),
+            short_links: env_parse("SHORT_LINKS", true),
+            watermark_links: env_parse("WATERMARK_LINKS", true),
+            tenants_path: env_str("TENANTS_PATH", ""),
+            discord_webhook_url: env_str("DISCORD_WEBHOOK_URL", ""),
+            discord_events: env_str("DISCORD_EVENTS", "vpn,cookies,error_spike")
+                .split(',')
+                .map(|e| e.trim().to_lowercase())
+                .filter(|e| !e.is_empty())
+                .collect(),
+            error_spike_window_secs: env_parse("ERROR_SPIKE_WINDOW_SECS", 300),
+            error_spike_threshold_pct: env_parse("ERROR_SPIKE_THRESHOLD_PCT", 50),
+            error_spike_min_attempts: env_parse("ERROR_SPIKE_MIN_ATTEMPTS", 20),
+            download_backend: env_str("DOWNLOAD_BACKEND", "native"),
+            aria2c_path: env_str("ARIA2C_PATH", "aria2c"),
+            aria2_rpc_url: env_str("ARIA2_RPC_URL", ""),
+            aria2_rpc_port: env_parse("ARIA2_RPC_PORT", 6800),
+            aria2_rpc_secret: env_str("ARIA2_RPC_SECRET", ""),
+            aria2_connections: env_parse("ARIA2_CONNECTIONS", 8),
+            segment_size_mb: env_parse("SEGMENT_SIZE_MB", 4),
             redis_host: env_str("REDIS_HOST", "redis"),
             redis_port: env_parse("REDIS_PORT", 6379),
+            redis_key_prefix: env_str("REDIS_KEY_PREFIX", "tiktok").trim_end_matches(':').to_string(),
+            cache_ttl_jitter_pct: env_parse::<u64>("CACHE_TTL_JITTER_PCT", 10).min(50),
+            shutdown_timeout_secs: env_parse("SHUTDOWN_TIMEOUT_SECS", 10),
             instance_id: env_str("INSTANCE_ID", "unknown"),
             instance_region: env_str("INSTANCE_REGION", "unknown"),
             gluetun_control_port: env_parse("GLUETUN_CONTROL_PORT", 8000),
@@ -45,10 +282,157 @@ impl Settings {
     }
 }
 
+impl Settings {
+    /// Extraction timeout for a request: the client-supplied value capped at
+    /// `ytdlp_max_timeout`, or the global `ytdlp_timeout` when none was given.
+    pub fn extraction_timeout(&self, requested: Option<u64>) -> u64 {
+        match requested {
+            Some(secs) => secs.clamp(1, self.ytdlp_max_timeout.max(1)),
+            None => self.ytdlp_timeout,
+        }
+    }
+
+    /// Tier of the request's `X-API-Key` ("free" when missing or unknown).
+    pub fn tier_for(&self, api_key: Option<&str>) -> &str {
+        api_key
+            .and_then(|k| self.api_key_tiers.get(k))
+            .map(String::as_str)
+            .unwrap_or("free")
+    }
+
+    /// The request's `X-API-Key` if it is one of API_KEY_TIERS; only those
+    /// keys are metered, so made-up keys can't fill the usage store.
+    pub fn billing_key<'a>(&self, api_key: Option<&'a str>) -> Option<&'a str> {
+        api_key.filter(|k| self.api_key_tiers.contains_key(*k))
+    }
+
+    /// Daily bandwidth quota of an API key's tier; None when unlimited.
+    pub fn daily_quota_for(&self, api_key: &str) -> Option<u64> {
+        self.bandwidth_quota.get(self.tier_for(Some(api_key))).copied()
+    }
+
+    /// Extraction queue priority for the request's `X-API-Key`.
+    pub fn priority_for(&self, api_key: Option<&str>) -> u8 {
+        tier_priority(self.tier_for(api_key))
+    }
+
+    /// Configured extra headers for a platform, e.g. `Referer` / `Origin`.
+    pub fn headers_for(&self, platform: &str) -> Vec<(String, String)> {
+        self.platform_headers
+            .get(platform)
+            .map(|h| h.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Extraction queue priority of an API key tier (higher runs first).
+fn tier_priority(tier: &str) -> u8 {
+    match tier {
+        "premium" => 2,
+        "paid" => 1,
+        _ => 0,
+    }
+}
+
+/// Platform label used for metrics, cookie pools and header config.
+pub fn platform_for_url(url: &str) -> &'static str {
+    if url.to_lowercase().contains("douyin.com") {
+        "douyin"
+    } else {
+        "tiktok"
+    }
+}
+
+/// PLATFORM_HEADERS='{"tiktok": {"Referer": "https://www.tiktok.com/"}}'
+fn platform_headers() -> HashMap<String, BTreeMap<String, String>> {
+    let raw = env_str("PLATFORM_HEADERS", "");
+    if raw.trim().is_empty() {
+        return HashMap::new();
+    }
+    serde_json::from_str(&raw).unwrap_or_else(|e| {
+        tracing::warn!("Ignoring invalid PLATFORM_HEADERS: {e}");
+        HashMap::new()
+    })
+}
+
+/// API_KEY_TIERS=key1:paid,key2:premium
+fn api_key_tiers() -> HashMap<String, String> {
+    env_list("API_KEY_TIERS")
+        .into_iter()
+        .filter_map(|entry| match entry.split_once(':') {
+            Some((key, tier)) if !key.trim().is_empty() => {
+                let tier = tier.trim().to_lowercase();
+                if !matches!(tier.as_str(), "free" | "paid" | "premium") {
+                    tracing::warn!("Unknown API key tier {tier:?}, treating as free");
+                }
+                Some((key.trim().to_string(), tier))
+            }
+            _ => {
+                tracing::warn!("Ignoring API_KEY_TIERS entry without a tier");
+                None
+            }
+        })
+        .collect()
+}
+
+/// BANDWIDTH_QUOTA_MB=free:1024,paid:20480
+fn bandwidth_quota() -> HashMap<String, u64> {
+    env_list("BANDWIDTH_QUOTA_MB")
+        .into_iter()
+        .filter_map(|entry| {
+            let parsed = entry
+                .split_once(':')
+                .and_then(|(tier, mb)| Some((tier.trim().to_lowercase(), mb.trim().parse::<u64>().ok()?)));
+            if parsed.is_none() {
+                tracing::warn!("Ignoring invalid BANDWIDTH_QUOTA_MB entry {entry:?}");
+            }
+            parsed.map(|(tier, mb)| (tier, mb * 1024 * 1024))
+        })
+        .collect()
+}
+
+/// TRUSTED_PROXIES: comma-separated addresses or CIDR blocks, loopback
+/// when unset. Malformed entries are skipped with a warning.
+fn trusted_proxies() -> Vec<IpNet> {
+    let raw = env::var("TRUSTED_PROXIES").unwrap_or_else(|_| "127.0.0.1/8,::1".to_string());
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| {
+            let net = IpNet::parse(s);
+            if net.is_none() {
+                tracing::warn!("Ignoring invalid TRUSTED_PROXIES entry: {s}");
+            }
+            net
+        })
+        .collect()
+}
+
+/// `{prefix}COOKIES_PATHS` (comma-separated) if set, otherwise the single
+/// `{prefix}COOKIES_PATH`.
+fn cookies_paths(prefix: &str, default: &str) -> Vec<PathBuf> {
+    let paths = env_list(&format!("{prefix}COOKIES_PATHS"));
+    if paths.is_empty() {
+        vec![PathBuf::from(env_str(&format!("{prefix}COOKIES_PATH"), default))]
+    } else {
+        paths.into_iter().map(PathBuf::from).collect()
+    }
+}
+
 fn env_str(key: &str, default: &str) -> String {
     env::var(key).unwrap_or_else(|_| default.to_string())
 }
 
+/// Comma-separated list, empty entries dropped
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
 fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
     env::var(key)
         .ok()
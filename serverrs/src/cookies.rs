@@ -0,0 +1,526 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+use crate::error::ExtractError;
+use crate::metrics::Metrics;
+use crate::notify::Notifier;
+use crate::tasks::{tick, TaskManager};
+
+/// Login cookies whose expiry decides whether a TikTok cookie file still works.
+const SESSION_COOKIES: [&str; 5] = ["sessionid", "sessionid_ss", "sid_tt", "sid_guard", "uid_tt"];
+
+/// One cookie file in the pool and how it has been doing.
+struct CookieProfile {
+    path: PathBuf,
+    cooldown_until: Option<Instant>,
+    successes: u64,
+    failures: u64,
+    last_error: Option<String>,
+    check: Option<CookieFileCheck>,
+}
+
+/// Result of validating a cookie file on disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CookieState {
+    Valid,
+    /// Login cookies expire within COOKIE_EXPIRY_WARN_DAYS
+    Expiring,
+    /// No login cookies — extraction works as a logged-out user
+    NoSession,
+    Expired,
+    Missing,
+    Invalid,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CookieFileCheck {
+    pub state: CookieState,
+    pub cookies: usize,
+    /// Earliest expiry (unix seconds) among the login cookies
+    pub expires_at: Option<u64>,
+    pub error: Option<String>,
+    pub checked_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct ProfileStatus {
+    pub path: String,
+    pub exists: bool,
+    pub active: bool,
+    pub cooling_down: bool,
+    pub cooldown_remaining_secs: u64,
+    pub successes: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+    pub check: Option<CookieFileCheck>,
+}
+
+/// Pool of cookie files for one platform. Extractions use the active profile
+/// until it hits AUTH_REQUIRED or a rate limit; it is then put on cooldown
+/// and the next healthy profile becomes active.
+pub struct CookiePool {
+    platform: &'static str,
+    cooldown: Duration,
+    /// A missing file is a problem (/health, Discord) rather than "no
+    /// cookies yet"; off for pools nobody configured
+    required: bool,
+    inner: Mutex<PoolInner>,
+}
+
+struct PoolInner {
+    profiles: Vec<CookieProfile>,
+    active: usize,
+}
+
+impl CookiePool {
+    pub fn new(platform: &'static str, paths: &[PathBuf], cooldown_secs: u64) -> Self {
+        let profiles = paths
+            .iter()
+            .map(|path| CookieProfile {
+                path: path.clone(),
+                cooldown_until: None,
+                successes: 0,
+                failures: 0,
+                last_error: None,
+                check: None,
+            })
+            .collect();
+        Self {
+            platform,
+            cooldown: Duration::from_secs(cooldown_secs),
+            required: true,
+            inner: Mutex::new(PoolInner {
+                profiles,
+                active: 0,
+            }),
+        }
+    }
+
+    /// Don't report missing files: the platform works without cookies
+    /// until some are uploaded.
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    pub fn platform(&self) -> &'static str {
+        self.platform
+    }
+
+    /// Whether a check in `state` means the profile needs attention.
+    fn is_bad(&self, state: CookieState) -> bool {
+        match state {
+            CookieState::Valid | CookieState::NoSession => false,
+            CookieState::Missing => self.required,
+            _ => true,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().profiles.len()
+    }
+
+    /// Index and path of a profile; `None` picks the active one.
+    pub fn profile(&self, idx: Option<usize>) -> Option<(usize, PathBuf)> {
+        let inner = self.inner.lock().unwrap();
+        let idx = idx.unwrap_or(inner.active);
+        inner.profiles.get(idx).map(|p| (idx, p.path.clone()))
+    }
+
+    /// The file behind profile `idx` was replaced: forget its cooldown and last error.
+    pub fn reset(&self, idx: usize) {
+        if let Some(p) = self.inner.lock().unwrap().profiles.get_mut(idx) {
+            p.cooldown_until = None;
+            p.last_error = None;
+        }
+    }
+
+    /// The profile to use for the next extraction: the active one, or the
+    /// next one not on cooldown. When every profile is cooling down, the one
+    /// that recovers first is used rather than none at all.
+    pub fn checkout(&self) -> Option<(usize, PathBuf)> {
+        let mut inner = self.inner.lock().unwrap();
+        let n = inner.profiles.len();
+        if n == 0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        let start = inner.active;
+        let idx = (0..n)
+            .map(|i| (start + i) % n)
+            .find(|&i| inner.profiles[i].cooldown_until.is_none_or(|t| t <= now))
+            .unwrap_or_else(|| {
+                (0..n)
+                    .min_by_key(|&i| inner.profiles[i].cooldown_until)
+                    .unwrap_or(start)
+            });
+
+        if idx != inner.active {
+            info!(
+                "🍪 Switching {} cookie profile to {}",
+                self.platform,
+                inner.profiles[idx].path.display()
+            );
+            inner.active = idx;
+        }
+        Some((idx, inner.profiles[idx].path.clone()))
+    }
+
+    /// Record the outcome of an extraction made with profile `idx`. Returns
+    /// true when the profile was put on cooldown and another one should be tried.
+    pub fn report(&self, idx: usize, result: &Result<String, ExtractError>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let n = inner.profiles.len();
+        let Some(profile) = inner.profiles.get_mut(idx) else {
+            return false;
+        };
+
+        match result {
+            Ok(_) => {
+                profile.successes += 1;
+                profile.cooldown_until = None;
+                false
+            }
+            Err(e) if needs_rotation(e) => {
+                profile.failures += 1;
+                profile.last_error = Some(e.to_string());
+                profile.cooldown_until = Some(Instant::now() + self.cooldown);
+                warn!(
+                    "🍪 Cookie profile {} put on cooldown for {}s: {}",
+                    profile.path.display(),
+                    self.cooldown.as_secs(),
+                    e.kind()
+                );
+                inner.active = (idx + 1) % n;
+                n > 1
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn status(&self) -> Vec<ProfileStatus> {
+        let inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        inner
+            .profiles
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let remaining = p
+                    .cooldown_until
+                    .map(|t| t.saturating_duration_since(now))
+                    .unwrap_or_default();
+                ProfileStatus {
+                    path: p.path.to_string_lossy().to_string(),
+                    exists: p.path.exists(),
+                    active: i == inner.active,
+                    cooling_down: !remaining.is_zero(),
+                    cooldown_remaining_secs: remaining.as_secs(),
+                    successes: p.successes,
+                    failures: p.failures,
+                    last_error: p.last_error.clone(),
+                    check: p.check.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Re-validate every profile's file and update the cookie metrics.
+    /// Returns the profiles whose state just turned bad (expired, missing,
+    /// invalid or expiring). Blocking — call from spawn_blocking.
+    pub fn run_checks(&self, warn_secs: u64, metrics: &Metrics) -> Vec<(PathBuf, CookieFileCheck)> {
+        let paths: Vec<PathBuf> = {
+            let inner = self.inner.lock().unwrap();
+            inner.profiles.iter().map(|p| p.path.clone()).collect()
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut changed = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let check = check_cookie_file(path, now, warn_secs);
+            // Not uploaded yet: nothing to log or export
+            if !self.required && check.state == CookieState::Missing {
+                if let Some(p) = self.inner.lock().unwrap().profiles.get_mut(i) {
+                    p.check = Some(check);
+                }
+                continue;
+            }
+            match check.state {
+                CookieState::Expired | CookieState::Missing | CookieState::Invalid => error!(
+                    "🍪 Cookie profile {} is {:?}",
+                    path.display(),
+                    check.state
+                ),
+                CookieState::Expiring => warn!(
+                    "🍪 Cookie profile {} expires in {}h",
+                    path.display(),
+                    check.expires_at.unwrap_or(now).saturating_sub(now) / 3600
+                ),
+                _ => {}
+            }
+            metrics.set_cookie_state(
+                self.platform,
+                &path.to_string_lossy(),
+                matches!(check.state, CookieState::Valid | CookieState::Expiring),
+                check.expires_at,
+            );
+            if let Some(p) = self.inner.lock().unwrap().profiles.get_mut(i) {
+                let before = p.check.as_ref().map(|c| c.state);
+                if self.is_bad(check.state) && before != Some(check.state) {
+                    changed.push((path.clone(), check.clone()));
+                }
+                p.check = Some(check);
+            }
+        }
+        changed
+    }
+
+    /// Worst file state across all checked profiles (None until the first
+    /// check); an optional pool's missing files don't count.
+    pub fn worst_state(&self) -> Option<CookieState> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .profiles
+            .iter()
+            .filter_map(|p| p.check.as_ref().map(|c| c.state))
+            .filter(|s| self.required || *s != CookieState::Missing)
+            .max()
+    }
+}
+
+/// One cookie pool per platform: Douyin's cookies are douyin.com's, and a
+/// TikTok login wall shouldn't cool down the Douyin profiles.
+pub struct CookiePools {
+    pools: Vec<Arc<CookiePool>>,
+}
+
+impl CookiePools {
+    pub fn new(pools: Vec<CookiePool>) -> Self {
+        Self { pools: pools.into_iter().map(Arc::new).collect() }
+    }
+
+    /// The pool of `platform` (as named by `platform_for_url`).
+    pub fn get(&self, platform: &str) -> Option<&Arc<CookiePool>> {
+        self.pools.iter().find(|p| p.platform == platform)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<CookiePool>> {
+        self.pools.iter()
+    }
+
+    /// Worst state across every pool.
+    pub fn worst_state(&self) -> Option<CookieState> {
+        self.pools.iter().filter_map(|p| p.worst_state()).max()
+    }
+}
+
+/// Spawn a background task that validates the cookie files every
+/// `interval_secs` (first check runs immediately). Call this once at startup.
+pub fn spawn_cookie_check_task(
+    tasks: &TaskManager,
+    pool: Arc<CookiePool>,
+    metrics: Arc<Metrics>,
+    notifier: Arc<Notifier>,
+    interval_secs: u64,
+    warn_secs: u64,
+) {
+    tasks.spawn("cookie_check", |cancel| async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        while tick(&mut interval, &cancel).await {
+            let checked = pool.clone();
+            let metrics = metrics.clone();
+            let changed = tokio::task::spawn_blocking(move || checked.run_checks(warn_secs, &metrics)).await;
+            for (path, check) in changed.unwrap_or_default() {
+                notifier.cookie_state(pool.platform(), &path.to_string_lossy(), &check);
+            }
+        }
+    });
+}
+
+/// Validate a Netscape cookie file and find when its login cookies expire.
+pub fn check_cookie_file(path: &Path, now: u64, warn_secs: u64) -> CookieFileCheck {
+    let mut check = CookieFileCheck {
+        state: CookieState::Missing,
+        cookies: 0,
+        expires_at: None,
+        error: None,
+        checked_at: now,
+    };
+
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) => {
+            check.error = Some(e.to_string());
+            return check;
+        }
+    };
+    let cookies = match parse_netscape(&text) {
+        Ok(c) => c,
+        Err(e) => {
+            check.state = CookieState::Invalid;
+            check.error = Some(e);
+            return check;
+        }
+    };
+
+    check.cookies = cookies.len();
+    // Session cookies (expiry 0) last as long as the browser session; treat
+    // them as non-expiring here
+    check.expires_at = cookies
+        .iter()
+        .filter(|c| SESSION_COOKIES.contains(&c.name.as_str()) && c.expires > 0)
+        .map(|c| c.expires)
+        .min();
+    let has_session = cookies
+        .iter()
+        .any(|c| SESSION_COOKIES.contains(&c.name.as_str()));
+
+    check.state = match check.expires_at {
+        _ if !has_session => CookieState::NoSession,
+        Some(t) if t <= now => CookieState::Expired,
+        Some(t) if t <= now + warn_secs => CookieState::Expiring,
+        _ => CookieState::Valid,
+    };
+    check
+}
+
+pub struct NetscapeCookie {
+    pub name: String,
+    pub expires: u64,
+}
+
+/// Parse a Netscape/Mozilla cookies.txt (the format yt-dlp's `cookiefile` reads).
+pub fn parse_netscape(text: &str) -> Result<Vec<NetscapeCookie>, String> {
+    let mut cookies = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        // "#HttpOnly_" prefixes a real cookie line; other "#" lines are comments
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return Err(format!(
+                "line {}: expected 7 tab-separated fields, got {}",
+                n + 1,
+                fields.len()
+            ));
+        }
+        let expires = fields[4]
+            .parse::<u64>()
+            .map_err(|_| format!("line {}: invalid expiry {:?}", n + 1, fields[4]))?;
+        cookies.push(NetscapeCookie {
+            name: fields[5].to_string(),
+            expires,
+        });
+    }
+
+    if cookies.is_empty() {
+        return Err("no cookies found".into());
+    }
+    Ok(cookies)
+}
+
+/// Failures caused by the cookies themselves: login walls and rate limits
+/// tied to the logged-in account.
+fn needs_rotation(e: &ExtractError) -> bool {
+    if matches!(e, ExtractError::AuthRequired(_)) {
+        return true;
+    }
+    let lower = e.message().to_lowercase();
+    lower.contains("429") || lower.contains("too many requests") || lower.contains("rate limit")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotates_on_auth_failure_and_recovers() {
+        let pool = CookiePool::new("tiktok", &["a.txt".into(), "b.txt".into()], 60);
+
+        let (idx, path) = pool.checkout().unwrap();
+        assert_eq!((idx, path), (0, PathBuf::from("a.txt")));
+
+        let auth = Err(ExtractError::AuthRequired("login required".into()));
+        assert!(pool.report(0, &auth));
+        assert_eq!(pool.checkout().unwrap().0, 1);
+
+        // Non-cookie failures don't rotate
+        let not_found = Err(ExtractError::NotFound("Video not found".into()));
+        assert!(!pool.report(1, &not_found));
+
+        let limited = Err(ExtractError::Failed("HTTP Error 429: Too Many Requests".into()));
+        assert!(pool.report(1, &limited));
+
+        // Both cooling down: fall back to the one that recovers first
+        assert_eq!(pool.checkout().unwrap().0, 0);
+        let status = pool.status();
+        assert!(status.iter().all(|p| p.cooling_down));
+        assert_eq!(status[1].failures, 1);
+
+        assert!(!pool.report(0, &Ok("{}".into())));
+        assert!(!pool.status()[0].cooling_down);
+    }
+
+    #[test]
+    fn test_check_cookie_file_expiry() {
+        let dir = std::env::temp_dir().join(format!("cookie-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cookies.txt");
+        let now = 1_700_000_000;
+
+        std::fs::write(
+            &path,
+            "# Netscape HTTP Cookie File\n\
+             .tiktok.com\tTRUE\t/\tTRUE\t1700500000\tttwid\tx\n\
+             #HttpOnly_.tiktok.com\tTRUE\t/\tTRUE\t1700100000\tsessionid\tabc\n",
+        )
+        .unwrap();
+        let check = check_cookie_file(&path, now, 86_400);
+        assert_eq!(check.cookies, 2);
+        assert_eq!(check.expires_at, Some(1_700_100_000));
+        assert_eq!(check.state, CookieState::Valid);
+        assert_eq!(check_cookie_file(&path, now, 200_000).state, CookieState::Expiring);
+        assert_eq!(check_cookie_file(&path, 1_700_100_000, 0).state, CookieState::Expired);
+
+        std::fs::write(&path, "not a cookie file\n").unwrap();
+        assert_eq!(check_cookie_file(&path, now, 0).state, CookieState::Invalid);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(check_cookie_file(&path, now, 0).state, CookieState::Missing);
+    }
+
+    #[test]
+    fn test_pools_per_platform() {
+        let metrics = Metrics::default();
+        let missing = std::env::temp_dir().join(format!("cookie-pools-{}/none.txt", std::process::id()));
+        let pools = CookiePools::new(vec![
+            CookiePool::new("tiktok", std::slice::from_ref(&missing), 60),
+            CookiePool::new("douyin", std::slice::from_ref(&missing), 60).optional(),
+        ]);
+        let (tiktok, douyin) = (pools.get("tiktok").unwrap(), pools.get("douyin").unwrap());
+        assert!(pools.get("x").is_none());
+
+        // Cooling down one platform's profile leaves the other's alone
+        tiktok.report(0, &Err(ExtractError::AuthRequired("login required".into())));
+        assert!(tiktok.status()[0].cooling_down);
+        assert!(!douyin.status()[0].cooling_down);
+
+        // A missing file only counts against the required pool
+        assert_eq!(tiktok.run_checks(0, &metrics).len(), 1);
+        assert!(douyin.run_checks(0, &metrics).is_empty());
+        assert_eq!(douyin.worst_state(), None);
+        assert_eq!(pools.worst_state(), Some(CookieState::Missing));
+    }
+}
@@ -1,8 +1,17 @@
 use base64::{engine::general_purpose::URL_SAFE, Engine};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// `bytes` random bytes from the OS, hex-encoded: for ids and secrets that
+/// must not be guessable.
+pub fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    getrandom::getrandom(&mut buf).expect("OS random number generator unavailable");
+    buf.iter().map(|b| format!("{b:02x}")).collect()
+}
+
 /// Encrypt text using XOR cipher with base64url encoding.
-/// Compatible with serverjs/serverpy encryption.
+/// Compatible with serverjs/serverpy encryption. `key` must not be empty;
+/// startup refuses an empty ENCRYPTION_KEY.
 pub fn encrypt(text: &str, key: &str, expiry_minutes: Option<u64>) -> String {
     let text_with_expiry = if let Some(minutes) = expiry_minutes {
         let now = SystemTime::now()
@@ -35,6 +44,9 @@ pub fn decrypt(encrypted_text: &str, key: &str) -> Result<String, String> {
         .map_err(|e| format!("Base64 decode failed: {e}"))?;
 
     let key_bytes = key.as_bytes();
+    if key_bytes.is_empty() {
+        return Err("Encryption key is empty".to_string());
+    }
 
     let decrypted: Vec<u8> = encrypted_bytes
         .iter()
@@ -64,6 +76,22 @@ pub fn decrypt(encrypted_text: &str, key: &str) -> Result<String, String> {
     Ok(decrypted_text)
 }
 
+/// Unix time the token expires at; None when it has no expiry or can't be
+/// decoded.
+pub fn expires_at(encrypted_text: &str, key: &str) -> Option<u64> {
+    let encrypted_bytes = URL_SAFE.decode(encrypted_text.as_bytes()).ok()?;
+    let key_bytes = Some(key.as_bytes()).filter(|k| !k.is_empty())?;
+    // The expiry is ASCII digits up to the first '|'
+    let prefix: String = encrypted_bytes
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| (b ^ key_bytes[i % key_bytes.len()]) as char)
+        .take_while(|c| *c != '|')
+        .take(20)
+        .collect();
+    prefix.parse().ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,6 +114,18 @@ mod tests {
         assert_eq!(decrypted, text);
     }
 
+    #[test]
+    fn test_expires_at() {
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let at = expires_at(&encrypt("payload", "testkey", Some(360)), "testkey").unwrap();
+        assert!((before + 360 * 60..=before + 360 * 60 + 1).contains(&at));
+        assert_eq!(expires_at(&encrypt("payload", "testkey", None), "testkey"), None);
+        assert_eq!(expires_at("!!!", "testkey"), None);
+        let token = encrypt("payload", "testkey", Some(360));
+        assert_eq!(expires_at(&token, ""), None);
+        assert!(decrypt(&token, "").is_err());
+    }
+
     #[test]
     fn test_json_payload() {
         let key = "overflow";
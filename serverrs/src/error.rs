@@ -0,0 +1,178 @@
+use axum::http::StatusCode;
+use std::fmt;
+
+/// Typed error returned by extract_with_ytdlp(); each variant keeps the raw
+/// message from yt-dlp (or PyO3) for logging.
+#[derive(Debug, Clone)]
+pub enum ExtractError {
+    /// Video deleted/private or URL points nowhere
+    NotFound(String),
+    /// HTTP 403 from the platform — usually an IP block
+    Forbidden(String),
+    /// Content needs login/valid cookies
+    AuthRequired(String),
+    /// Network hiccup or 5xx from the platform; likely to succeed on retry
+    Transient(String),
+    /// Extraction did not finish within the request's timeout
+    Timeout(String),
+    /// yt-dlp has no extractor for this URL
+    Unsupported(String),
+    /// The request's format selector is invalid or matches no format
+    InvalidFormat(String),
+    /// Any other extractor failure
+    Failed(String),
+    /// Python environment / serialization problems on our side
+    Internal(String),
+}
+
+impl ExtractError {
+    /// Classify an `extract_info` exception message.
+    pub fn classify(raw: String) -> Self {
+        let lower = raw.to_lowercase();
+        if lower.contains("requested format is not available") || lower.contains("invalid format specification") {
+            Self::InvalidFormat(raw)
+        } else if lower.contains("not found") || lower.contains("unable to download") {
+            Self::NotFound(raw)
+        } else if raw.contains("403") || lower.contains("forbidden") {
+            Self::Forbidden(raw)
+        } else if lower.contains("login") || lower.contains("authentication") {
+            Self::AuthRequired(raw)
+        } else if lower.contains("unsupported url") {
+            Self::Unsupported(raw)
+        } else if is_transient(&lower) {
+            Self::Transient(raw)
+        } else {
+            Self::Failed(raw)
+        }
+    }
+
+    /// Short label used for metrics and logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NotFound(_) => "not_found",
+            Self::Forbidden(_) => "forbidden",
+            Self::AuthRequired(_) => "auth_required",
+            Self::Transient(_) => "transient",
+            Self::Timeout(_) => "timeout",
+            Self::Unsupported(_) => "unsupported",
+            Self::InvalidFormat(_) => "invalid_format",
+            Self::Failed(_) => "extraction_failed",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    /// Inverse of kind(), for errors reported by a remote extraction worker.
+    /// Unknown kinds become `Failed`.
+    pub fn from_kind(kind: &str, message: String) -> Self {
+        match kind {
+            "not_found" => Self::NotFound(message),
+            "forbidden" => Self::Forbidden(message),
+            "auth_required" => Self::AuthRequired(message),
+            "transient" => Self::Transient(message),
+            "timeout" => Self::Timeout(message),
+            "unsupported" => Self::Unsupported(message),
+            "invalid_format" => Self::InvalidFormat(message),
+            "internal" => Self::Internal(message),
+            _ => Self::Failed(message),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotFound(m)
+            | Self::Forbidden(m)
+            | Self::AuthRequired(m)
+            | Self::Transient(m)
+            | Self::Timeout(m)
+            | Self::Unsupported(m)
+            | Self::InvalidFormat(m)
+            | Self::Failed(m)
+            | Self::Internal(m) => m,
+        }
+    }
+
+    /// Whether the same request may succeed if tried again (e.g. from a new IP).
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Forbidden(_) | Self::Transient(_) | Self::Timeout(_))
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::AuthRequired(_) => StatusCode::UNAUTHORIZED,
+            Self::Transient(_) => StatusCode::BAD_GATEWAY,
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::Unsupported(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidFormat(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Failed(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Timeouts, dropped connections and 5xx responses seen by the extractor.
+fn is_transient(lower: &str) -> bool {
+    const HINTS: [&str; 7] = [
+        "timed out",
+        "timeout",
+        "connection reset",
+        "connection aborted",
+        "connection refused",
+        "remote end closed",
+        "temporarily unavailable",
+    ];
+    HINTS.iter().any(|h| lower.contains(h))
+        || lower
+            .split("http error ")
+            .skip(1)
+            .any(|rest| rest.starts_with('5'))
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_ytdlp_messages() {
+        let e = ExtractError::classify("ERROR: [TikTok] 123: Video not found".into());
+        assert_eq!(e.kind(), "not_found");
+        assert_eq!(e.status(), StatusCode::NOT_FOUND);
+
+        let e = ExtractError::classify("HTTP Error 403: Forbidden".into());
+        assert!(matches!(e, ExtractError::Forbidden(_)));
+        assert!(e.is_retryable());
+
+        let e = ExtractError::classify("ERROR: Unsupported URL: https://example.com".into());
+        assert_eq!(e.status(), StatusCode::BAD_REQUEST);
+        assert!(!e.is_retryable());
+
+        let e = ExtractError::classify("ERROR: [twitter] 1: Requested format is not available. Use --list-formats".into());
+        assert_eq!(e.kind(), "invalid_format");
+        assert_eq!(e.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(matches!(ExtractError::from_kind("invalid_format", String::new()), ExtractError::InvalidFormat(_)));
+
+        let e = ExtractError::classify("HTTP Error 502: Bad Gateway".into());
+        assert_eq!(e.kind(), "transient");
+        assert!(e.is_retryable());
+
+        let e = ExtractError::classify("<urlopen error [Errno 104] Connection reset by peer>".into());
+        assert!(matches!(e, ExtractError::Transient(_)));
+
+        let e = ExtractError::classify("something odd".into());
+        assert_eq!(e.message(), "something odd");
+        assert_eq!(e.to_string(), "extraction_failed: something odd");
+
+        let e = ExtractError::from_kind(e.kind(), e.message().to_string());
+        assert!(matches!(e, ExtractError::Failed(_)));
+        let e = ExtractError::from_kind("forbidden", "HTTP Error 403".into());
+        assert!(e.is_retryable());
+    }
+}
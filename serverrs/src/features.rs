@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+use std::env;
+use tracing::warn;
+
+/// Capabilities that can be switched off per platform.
+pub const FEATURES: [&str; 4] = ["video", "audio", "images", "slideshow"];
+
+/// DISABLED_FEATURES: comma-separated `platform` (disable it entirely),
+/// `platform.feature` or `*.feature` entries, e.g. `douyin,tiktok.slideshow`.
+#[derive(Clone, Debug, Default)]
+pub struct FeatureFlags {
+    disabled: HashSet<String>,
+}
+
+impl FeatureFlags {
+    pub fn from_env() -> Self {
+        let raw = env::var("DISABLED_FEATURES").unwrap_or_default();
+        Self::parse(&raw.split(',').map(String::from).collect::<Vec<_>>())
+    }
+
+    pub fn parse(entries: &[String]) -> Self {
+        let disabled: HashSet<String> = entries
+            .iter()
+            .map(|e| e.trim().to_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect();
+        for entry in &disabled {
+            if let Some((_, feature)) = entry.split_once('.') {
+                if !FEATURES.contains(&feature) {
+                    warn!("Unknown feature in DISABLED_FEATURES: {entry}");
+                }
+            }
+        }
+        Self { disabled }
+    }
+
+    /// Switch a whole platform off, e.g. for a tenant limited to others.
+    pub fn disable_platform(&mut self, platform: &str) {
+        self.disabled.insert(platform.to_lowercase());
+    }
+
+    pub fn platform_enabled(&self, platform: &str) -> bool {
+        !self.disabled.contains(platform)
+    }
+
+    pub fn enabled(&self, platform: &str, feature: &str) -> bool {
+        self.platform_enabled(platform)
+            && !self.disabled.contains(&format!("{platform}.{feature}"))
+            && !self.disabled.contains(&format!("*.{feature}"))
+    }
+}
+
+/// Platform name used in DISABLED_FEATURES; unlike the response's `platform`
+/// field, Douyin is its own platform here.
+pub fn platform_for_url(url: &str) -> &'static str {
+    let lower = url.to_lowercase();
+    if lower.contains("douyin.com") {
+        "douyin"
+    } else if lower.contains("tiktok.com") {
+        "tiktok"
+    } else if lower.contains("twitter.com") || lower.contains("x.com") {
+        "x"
+    } else {
+        "unknown"
+    }
+}
+
+/// Feature a download token's `type` belongs to.
+pub fn feature_for_type(file_type: &str) -> &'static str {
+    match file_type {
+        "image" => "images",
+        "mp3" | "audio" => "audio",
+        _ => "video",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_flags() {
+        let flags = FeatureFlags::parse(&["douyin".into(), " TikTok.Slideshow ".into(), "*.audio".into(), " X.Images".into()]);
+        assert!(!flags.platform_enabled(platform_for_url("https://www.douyin.com/video/1")));
+        assert!(!flags.enabled("douyin", "video"));
+        assert!(flags.enabled("tiktok", "video"));
+        assert!(!flags.enabled("tiktok", "slideshow"));
+        assert!(!flags.enabled("tiktok", "audio"));
+        assert!(flags.enabled("tiktok", feature_for_type("image")));
+        assert_eq!(feature_for_type("mp3"), "audio");
+        assert!(flags.enabled("x", "video"));
+        assert!(!flags.enabled(platform_for_url("https://x.com/a/status/1"), "images"));
+
+        let mut flags = FeatureFlags::default();
+        flags.disable_platform("TikTok");
+        assert!(!flags.enabled("tiktok", "video"));
+    }
+}
@@ -0,0 +1,165 @@
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use crate::schedule::{ScheduleStatus, ScheduledJob};
+use crate::watch::Watch;
+use crate::AppState;
+
+/// Newest finished posts listed in a feed.
+const FEED_MAX_ITEMS: usize = 50;
+
+#[derive(serde::Deserialize)]
+pub struct FeedQuery {
+    #[serde(default)]
+    key: String,
+}
+
+/// GET /feeds/{watch_id}.xml?key= — RSS 2.0 feed of a watcher's archived
+/// posts. `key` is the watcher's random feed secret (its `feed_url`), so no
+/// admin token is needed.
+pub async fn feed_handler(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+    Query(query): Query<FeedQuery>,
+) -> Response {
+    let Some(watch) = file
+        .strip_suffix(".xml")
+        .and_then(|id| state.watchers.get(id))
+        .filter(|w| w.feed_key_matches(&query.key))
+    else {
+        return (StatusCode::NOT_FOUND, "Feed not found").into_response();
+    };
+
+    let mut jobs = state.schedule.by_watch(&watch.id);
+    jobs.retain(|j| j.status == ScheduleStatus::Done);
+    jobs.sort_by_key(|j| std::cmp::Reverse(j.created_at));
+    jobs.truncate(FEED_MAX_ITEMS);
+
+    (
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        render_feed(&watch, &jobs, &state.settings.base_url),
+    )
+        .into_response()
+}
+
+fn render_feed(watch: &Watch, jobs: &[ScheduledJob], base_url: &str) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\" xmlns:media=\"http://search.yahoo.com/mrss/\">\n<channel>\n",
+    );
+    let creator = jobs
+        .iter()
+        .find_map(|j| j.result.as_ref()?["author"]["nickname"].as_str())
+        .unwrap_or(&watch.url);
+    xml.push_str(&format!("<title>{}</title>\n", escape(creator)));
+    xml.push_str(&format!("<link>{}</link>\n", escape(&watch.url)));
+    xml.push_str(&format!(
+        "<description>New posts from {}</description>\n",
+        escape(&watch.url)
+    ));
+    for job in jobs {
+        xml.push_str(&render_item(job, base_url));
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn render_item(job: &ScheduledJob, base_url: &str) -> String {
+    let empty = serde_json::Value::Null;
+    let result = job.result.as_ref().unwrap_or(&empty);
+    let title = result["title"]
+        .as_str()
+        .filter(|t| !t.is_empty())
+        .unwrap_or(&job.url);
+    let cover = result["cover"].as_str().unwrap_or("");
+
+    let mut item = String::from("<item>\n");
+    item.push_str(&format!("<title>{}</title>\n", escape(title)));
+    item.push_str(&format!("<link>{}</link>\n", escape(&job.url)));
+    item.push_str(&format!("<guid isPermaLink=\"false\">{}</guid>\n", job.id));
+    if let Some(date) = chrono::DateTime::from_timestamp(job.created_at as i64, 0) {
+        item.push_str(&format!("<pubDate>{}</pubDate>\n", date.to_rfc2822()));
+    }
+    if !cover.is_empty() {
+        item.push_str(&format!("<media:thumbnail url=\"{}\"/>\n", escape(cover)));
+        item.push_str(&format!(
+            "<description>{}</description>\n",
+            escape(&format!("<img src=\"{cover}\"/>"))
+        ));
+    }
+
+    // The prefetched copy lasts until SCHEDULE_RETENTION_HOURS; /stream links
+    // expire with the token, like any other /tiktok result
+    match &job.file {
+        Some(file) => item.push_str(&format!(
+            "<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+            escape(&format!("{base_url}/jobs/{}/file", job.id)),
+            file.size,
+            escape(&file.content_type)
+        )),
+        None => {
+            let link = ["no_watermark_hd", "no_watermark", "watermark"]
+                .iter()
+                .find_map(|k| result["download_link"][k].as_str());
+            if let Some(link) = link {
+                item.push_str(&format!(
+                    "<enclosure url=\"{}\" length=\"0\" type=\"video/mp4\"/>\n",
+                    escape(link)
+                ));
+            }
+        }
+    }
+    item.push_str("</item>\n");
+    item
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_item_escapes_and_prefers_prefetched_file() {
+        let job: ScheduledJob = serde_json::from_value(serde_json::json!({
+            "id": "abc",
+            "url": "https://www.tiktok.com/@a/video/1",
+            "run_at": 0,
+            "prefetch": true,
+            "priority": 0,
+            "timeout": null,
+            "status": "done",
+            "created_at": 1_700_000_000,
+            "finished_at": 1_700_000_010,
+            "error": null,
+            "result": {
+                "title": "Tom & Jerry <3",
+                "cover": "https://cdn/cover.jpg?a=1&b=2",
+                "download_link": {"no_watermark": "https://dl/stream?data=x"}
+            },
+            "file": {
+                "path": "abc.download",
+                "filename": "a.mp4",
+                "content_type": "video/mp4",
+                "size": 1234,
+                "sha256": null
+            }
+        }))
+        .unwrap();
+
+        let item = render_item(&job, "https://dl");
+        assert!(item.contains("<title>Tom &amp; Jerry &lt;3</title>"));
+        assert!(item.contains("<media:thumbnail url=\"https://cdn/cover.jpg?a=1&amp;b=2\"/>"));
+        assert!(item.contains(
+            "<enclosure url=\"https://dl/jobs/abc/file\" length=\"1234\" type=\"video/mp4\"/>"
+        ));
+        assert!(item.contains("<pubDate>Tue, 14 Nov 2023 22:13:20 +0000</pubDate>"));
+    }
+}
@@ -0,0 +1,214 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cookies::CookieState;
+use crate::AppState;
+
+/// Outcome of one dependency check in the /health report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Works, but needs attention soon (e.g. cookies about to expire)
+    Warn,
+    Fail,
+    /// Not configured on this instance
+    Skip,
+}
+
+fn check(status: CheckStatus, mut details: Value) -> Value {
+    details["status"] = json!(status);
+    details
+}
+
+/// Run every dependency check concurrently. Returns `{name: {"status", ...}}`
+/// and the names of the failed checks.
+pub async fn run_checks(state: &AppState) -> (Value, Vec<String>) {
+    let (ffmpeg, vpn) = tokio::join!(check_ffmpeg(), check_vpn(state));
+    let python = state.python_status.read().await.clone();
+
+    let ytdlp = match &python.error {
+        None => check(CheckStatus::Pass, json!({"version": python.ytdlp_version})),
+        Some(e) => check(CheckStatus::Fail, json!({"error": e})),
+    };
+    let python = match &python.python_version {
+        Some(version) => check(CheckStatus::Pass, json!({"version": version})),
+        None => check(CheckStatus::Fail, json!({"error": "Python version unknown"})),
+    };
+
+    let checks = json!({
+        "ffmpeg": ffmpeg,
+        "ytdlp": ytdlp,
+        "python": python,
+        "cookies": check_cookies(state),
+        "temp_dir": check_temp_dir(&state.settings.temp_dir, state.settings.health_min_free_mb),
+        "vpn": vpn,
+    });
+    let failed = checks
+        .as_object()
+        .unwrap()
+        .iter()
+        .filter(|(_, c)| c["status"] == "fail")
+        .map(|(name, _)| name.clone())
+        .collect();
+    (checks, failed)
+}
+
+async fn check_ffmpeg() -> Value {
+    let output = tokio::time::timeout(
+        Duration::from_secs(5),
+        tokio::process::Command::new("ffmpeg")
+            .arg("-version")
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await;
+    match output {
+        Ok(Ok(out)) if out.status.success() => {
+            let stdout = String::from_utf8_lossy(&out.stdout);
+            check(CheckStatus::Pass, json!({"version": parse_ffmpeg_version(&stdout)}))
+        }
+        Ok(Ok(out)) => check(
+            CheckStatus::Fail,
+            json!({"error": format!("ffmpeg -version exited with {:?}", out.status.code())}),
+        ),
+        Ok(Err(e)) => check(CheckStatus::Fail, json!({"error": format!("ffmpeg not found: {e}")})),
+        Err(_) => check(CheckStatus::Fail, json!({"error": "ffmpeg -version timed out"})),
+    }
+}
+
+/// "ffmpeg version 6.1.1-3ubuntu5 Copyright ..." -> "6.1.1-3ubuntu5"
+fn parse_ffmpeg_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .next()?
+        .strip_prefix("ffmpeg version ")?
+        .split_whitespace()
+        .next()
+        .map(String::from)
+}
+
+/// Fails when a cookie profile is expired, missing or unreadable; warns when
+/// login cookies expire within COOKIE_EXPIRY_WARN_DAYS.
+fn check_cookies(state: &AppState) -> Value {
+    let profiles: Vec<_> = state.cookies.iter().flat_map(|pool| pool.status()).collect();
+    if profiles.is_empty() {
+        return check(CheckStatus::Skip, json!({}));
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let expires_at = profiles
+        .iter()
+        .filter_map(|p| p.check.as_ref()?.expires_at)
+        .min();
+    let worst = state.cookies.worst_state();
+    let status = match worst {
+        Some(CookieState::Expired | CookieState::Missing | CookieState::Invalid) => CheckStatus::Fail,
+        Some(CookieState::Expiring) => CheckStatus::Warn,
+        _ => CheckStatus::Pass,
+    };
+    check(
+        status,
+        json!({
+            "state": worst,
+            "profiles": profiles.len(),
+            "expires_at": expires_at,
+            "expires_in_secs": expires_at.map(|t| t.saturating_sub(now)),
+            "checked_at": profiles.iter().filter_map(|p| Some(p.check.as_ref()?.checked_at)).min(),
+        }),
+    )
+}
+
+/// Fails below HEALTH_MIN_FREE_MB of free space where downloads are written.
+fn check_temp_dir(dir: &Path, min_free_mb: u64) -> Value {
+    match disk_space(dir) {
+        Ok((free, total)) => {
+            let status = if free < min_free_mb * 1024 * 1024 {
+                CheckStatus::Fail
+            } else {
+                CheckStatus::Pass
+            };
+            check(
+                status,
+                json!({
+                    "path": dir.to_string_lossy(),
+                    "free_bytes": free,
+                    "total_bytes": total,
+                    "min_free_bytes": min_free_mb * 1024 * 1024,
+                }),
+            )
+        }
+        Err(e) => check(
+            CheckStatus::Fail,
+            json!({"path": dir.to_string_lossy(), "error": e}),
+        ),
+    }
+}
+
+/// (available, total) bytes of the filesystem holding `dir`.
+fn disk_space(dir: &Path) -> Result<(u64, u64), String> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out-pointer
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    let block = stat.f_frsize as u64;
+    Ok((stat.f_bavail as u64 * block, stat.f_blocks as u64 * block))
+}
+
+/// Public IP from the Gluetun control server; skipped without a custom
+/// GLUETUN_CONTROL_PORT.
+async fn check_vpn(state: &AppState) -> Value {
+    let settings = &state.settings;
+    if settings.gluetun_control_port == 8000 {
+        return check(CheckStatus::Skip, json!({}));
+    }
+    let result = state
+        .http_client
+        .get(format!(
+            "http://localhost:{}/v1/publicip/ip",
+            settings.gluetun_control_port
+        ))
+        .basic_auth(&settings.gluetun_username, Some(&settings.gluetun_password))
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await;
+    match result {
+        Ok(resp) if resp.status().is_success() => match resp.json::<Value>().await {
+            Ok(ip_data) => check(CheckStatus::Pass, json!({"public_ip": ip_data["public_ip"]})),
+            Err(e) => check(CheckStatus::Fail, json!({"error": e.to_string()})),
+        },
+        Ok(resp) => check(
+            CheckStatus::Fail,
+            json!({"error": format!("HTTP {}", resp.status())}),
+        ),
+        Err(e) => check(CheckStatus::Fail, json!({"error": e.to_string()})),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ffmpeg_version_and_disk_space() {
+        assert_eq!(
+            parse_ffmpeg_version("ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023\nbuilt with gcc"),
+            Some("6.1.1-3ubuntu5".into())
+        );
+        assert_eq!(parse_ffmpeg_version("not ffmpeg"), None);
+
+        let (free, total) = disk_space(Path::new(".")).unwrap();
+        assert!(total > 0 && free <= total);
+
+        let low = check_temp_dir(Path::new("."), u64::MAX / (1024 * 1024));
+        assert_eq!(low["status"], "fail");
+    }
+}
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Extraction,
+    Slideshow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for an extraction worker / slideshow slot; can be cancelled
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub kind: JobKind,
+    pub platform: String,
+    pub url: String,
+    pub status: JobStatus,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+/// Filters for GET /admin/jobs.
+#[derive(Debug, Default)]
+pub struct JobFilter {
+    pub status: Option<JobStatus>,
+    pub kind: Option<JobKind>,
+    pub platform: Option<String>,
+    /// Unix seconds; only jobs created at or after this time
+    pub since: Option<u64>,
+}
+
+impl JobFilter {
+    fn matches(&self, job: &JobInfo) -> bool {
+        self.status.is_none_or(|s| s == job.status)
+            && self.kind.is_none_or(|k| k == job.kind)
+            && self.platform.as_ref().is_none_or(|p| *p == job.platform)
+            && self.since.is_none_or(|t| job.created_at >= t)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CancelError {
+    NotFound,
+    /// Only queued jobs can be cancelled; yt-dlp/FFmpeg can't be interrupted
+    NotQueued(JobStatus),
+}
+
+struct Entry {
+    info: JobInfo,
+    cancel: CancellationToken,
+}
+
+#[derive(Default)]
+struct Inner {
+    active: BTreeMap<u64, Entry>,
+    /// Most recent finished jobs, newest last
+    finished: VecDeque<JobInfo>,
+}
+
+/// In-process registry of extraction and slideshow jobs, so operators can see
+/// what is queued or running and cancel queued work.
+pub struct JobRegistry {
+    next_id: AtomicU64,
+    history: usize,
+    inner: Mutex<Inner>,
+}
+
+impl JobRegistry {
+    /// `history`: how many finished jobs are kept for listing.
+    pub fn new(history: usize) -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            history,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Register a queued job; it is finished when the handle is dropped.
+    pub fn register(self: &Arc<Self>, kind: JobKind, platform: &str, url: &str) -> JobHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let cancel = CancellationToken::new();
+        let info = JobInfo {
+            id,
+            kind,
+            platform: platform.to_string(),
+            url: url.to_string(),
+            status: JobStatus::Queued,
+            created_at: unix_now(),
+            started_at: None,
+            finished_at: None,
+        };
+        self.inner.lock().unwrap().active.insert(
+            id,
+            Entry {
+                info,
+                cancel: cancel.clone(),
+            },
+        );
+        JobHandle {
+            registry: self.clone(),
+            id,
+            cancel,
+            abandoned: CancellationToken::new(),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// Matching jobs, newest first: `(total matches, page)`.
+    pub fn list(&self, filter: &JobFilter, offset: usize, limit: usize) -> (usize, Vec<JobInfo>) {
+        let inner = self.inner.lock().unwrap();
+        let mut jobs: Vec<&JobInfo> = inner
+            .active
+            .values()
+            .map(|e| &e.info)
+            .chain(inner.finished.iter())
+            .filter(|j| filter.matches(j))
+            .collect();
+        jobs.sort_by_key(|j| std::cmp::Reverse(j.id));
+        let total = jobs.len();
+        let page = jobs.into_iter().skip(offset).take(limit).cloned().collect();
+        (total, page)
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<(), CancelError> {
+        let inner = self.inner.lock().unwrap();
+        let entry = match inner.active.get(&id) {
+            Some(e) => e,
+            None => {
+                return Err(match inner.finished.iter().find(|j| j.id == id) {
+                    Some(j) => CancelError::NotQueued(j.status),
+                    None => CancelError::NotFound,
+                })
+            }
+        };
+        if entry.info.status != JobStatus::Queued {
+            return Err(CancelError::NotQueued(entry.info.status));
+        }
+        entry.cancel.cancel();
+        Ok(())
+    }
+
+    fn set_status(&self, id: u64, status: JobStatus) {
+        if let Some(entry) = self.inner.lock().unwrap().active.get_mut(&id) {
+            if status == JobStatus::Running && entry.info.started_at.is_none() {
+                entry.info.started_at = Some(unix_now());
+            }
+            entry.info.status = status;
+        }
+    }
+
+    fn finish(&self, id: u64, status: JobStatus) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(mut entry) = inner.active.remove(&id) {
+            entry.info.status = status;
+            entry.info.finished_at = Some(unix_now());
+            inner.finished.push_back(entry.info);
+            while inner.finished.len() > self.history {
+                inner.finished.pop_front();
+            }
+        }
+    }
+}
+
+/// A registered job; marks it finished when dropped.
+pub struct JobHandle {
+    registry: Arc<JobRegistry>,
+    id: u64,
+    cancel: CancellationToken,
+    /// Cancelled on drop: the request was answered or the client went away
+    abandoned: CancellationToken,
+    done: AtomicBool,
+}
+
+impl JobHandle {
+    pub fn queued(&self) {
+        self.registry.set_status(self.id, JobStatus::Queued);
+    }
+
+    pub fn running(&self) {
+        self.registry.set_status(self.id, JobStatus::Running);
+    }
+
+    /// Mark the job successful; dropping it without this counts as failed.
+    pub fn done(&self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+
+    /// Resolves when an operator cancels the job.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
+    /// Token cancelled once the handle is dropped, e.g. with the request
+    /// future when the client disconnects, for blocking work (FFmpeg, asset
+    /// downloads) that would otherwise run on for nobody.
+    pub fn abandoned(&self) -> CancellationToken {
+        self.abandoned.clone()
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        let status = if self.cancel.is_cancelled() {
+            JobStatus::Cancelled
+        } else if self.done.load(Ordering::Relaxed) {
+            JobStatus::Done
+        } else {
+            JobStatus::Failed
+        };
+        self.registry.finish(self.id, status);
+        self.abandoned.cancel();
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_lifecycle() {
+        let jobs = Arc::new(JobRegistry::new(10));
+        let queued = jobs.register(JobKind::Extraction, "tiktok", "https://a");
+        let running = jobs.register(JobKind::Slideshow, "douyin", "https://b");
+        running.running();
+
+        let filter = JobFilter {
+            status: Some(JobStatus::Queued),
+            ..Default::default()
+        };
+        let (total, page) = jobs.list(&filter, 0, 10);
+        assert_eq!(total, 1);
+        assert_eq!(page[0].url, "https://a");
+
+        assert_eq!(jobs.cancel(running.id), Err(CancelError::NotQueued(JobStatus::Running)));
+        assert_eq!(jobs.cancel(999), Err(CancelError::NotFound));
+        assert!(jobs.cancel(queued.id).is_ok());
+        drop(queued);
+        let abandoned = running.abandoned();
+        assert!(!abandoned.is_cancelled());
+        drop(running);
+        assert!(abandoned.is_cancelled());
+
+        let (total, page) = jobs.list(&JobFilter::default(), 0, 1);
+        assert_eq!(total, 2);
+        assert_eq!(page[0].status, JobStatus::Failed);
+        let (_, page) = jobs.list(&JobFilter::default(), 1, 1);
+        assert_eq!(page[0].status, JobStatus::Cancelled);
+    }
+}
@@ -1,40 +1,130 @@
+mod admin;
+mod aria2;
+mod assets;
+mod blocklist;
 mod cache;
+mod checksum;
 mod cleanup;
 mod config;
+mod cookies;
 mod encryption;
+// Same file as serverx-rs/src/error.rs; remote worker error kinds go unused here
+#[allow(dead_code)]
+mod error;
+// Same file as serverx-rs/src/features.rs; its URL-to-platform mapping goes unused here
+#[allow(dead_code)]
+mod features;
+mod feed;
+mod health;
+mod jobs;
+mod metrics;
+mod moderation;
+mod notify;
+mod oembed;
+mod prewarm;
+mod providers;
+mod queue;
 mod response;
+mod schedule;
+mod session;
+mod shortlink;
 mod slideshow;
 mod stream;
+mod streams;
+mod tasks;
+mod tenants;
+mod usage;
+mod user_agents;
 mod vpn;
+mod watch;
 mod ytdlp;
 
 use axum::body::Body;
-use axum::extract::{Json, Query, State};
-use axum::http::{HeaderValue, StatusCode};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Json, Multipart, Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post, put};
 use axum::Router;
 use serde::Deserialize;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::{error, info, warn};
 
+use blocklist::{Blocklist, Subject};
 use cache::RedisCache;
-use config::Settings;
+use config::{platform_for_url, Settings};
+use cookies::{CookiePool, CookiePools};
 use encryption::decrypt;
+use error::ExtractError;
+use jobs::{JobHandle, JobKind, JobRegistry};
+use metrics::{CacheResult, Metrics};
+use providers::ExtractionProvider;
+use queue::{ExtractionQueue, NamedTickets, TicketState};
+use streams::StreamRegistry;
+use tenants::TenantState;
+use user_agents::UserAgentPool;
 use vpn::{VpnManager, VpnReconnectState};
+use ytdlp::PythonStatus;
+
+/// Seconds each image is shown in a generated slideshow (fixed timing)
+const SLIDESHOW_SECS_PER_IMAGE: f64 = 4.0;
 
 // ============= Application State =============
 
 #[derive(Clone)]
 pub struct AppState {
-    pub settings: Settings,
+    /// Shared, so cloning the state per request (and per tenant) is cheap
+    pub settings: Arc<Settings>,
     pub http_client: reqwest::Client,
     pub redis: Option<RedisCache>,
     pub vpn_manager: Arc<VpnManager>,
     pub vpn_state: Arc<Mutex<VpnReconnectState>>,
+    pub python_status: Arc<RwLock<PythonStatus>>,
+    pub metrics: Arc<Metrics>,
+    /// Bounds concurrent yt-dlp extractions (MAX_WORKERS) and waiting requests
+    pub extraction_queue: Arc<ExtractionQueue>,
+    /// Bounds concurrent slideshow downloads / ffprobe checks (SLIDESHOW_WORKERS)
+    pub slideshow_permits: Arc<Semaphore>,
+    /// Slideshow FFmpeg runs (FFMPEG_CONCURRENCY) and requests waiting for one
+    pub ffmpeg_queue: Arc<ExtractionQueue>,
+    /// Slideshow requests sent with X-Queue-Ticket (GET /slideshow/queue/{ticket})
+    pub slideshow_tickets: Arc<NamedTickets>,
+    /// Queued/running extractions and slideshows (GET /admin/jobs)
+    pub jobs: Arc<JobRegistry>,
+    /// Responses being proxied by /stream and /download (GET /admin/streams)
+    pub streams: Arc<StreamRegistry>,
+    /// aria2c backend for file-mode and slideshow downloads (DOWNLOAD_BACKEND=aria2c)
+    pub aria2: Option<Arc<aria2::Aria2>>,
+    /// Recently downloaded slideshow assets (SLIDESHOW_ASSET_TTL_SECS)
+    pub slideshow_assets: Option<Arc<assets::AssetCache>>,
+    /// Image-post extraction results for /download-slideshow?session=
+    /// (SLIDESHOW_SESSION_TTL_SECS)
+    pub sessions: Option<Arc<session::SessionStore>>,
+    /// Rotating User-Agents for extraction and CDN fetches
+    pub user_agents: Arc<UserAgentPool>,
+    /// Cookie profiles used by the yt-dlp provider, per platform
+    pub cookies: Arc<CookiePools>,
+    /// Takedown rules checked before extraction and when tokens are served
+    pub blocklist: Arc<blocklist::Blocklist>,
+    /// Scheduled/delayed jobs (POST /jobs), persisted in SCHEDULE_DIR
+    pub schedule: Arc<schedule::ScheduleStore>,
+    /// Creator profiles polled for new posts (/admin/watchers)
+    pub watchers: Arc<watch::WatchStore>,
+    /// Background cache pre-warm batches (POST /admin/prewarm)
+    pub prewarm: Arc<prewarm::PrewarmRegistry>,
+    /// yt-dlp first, then optional fallbacks (FALLBACK_PROVIDERS)
+    pub providers: Arc<Vec<Arc<dyn ExtractionProvider>>>,
+    /// Discord webhook notifications (DISCORD_WEBHOOK_URL)
+    pub notifier: Arc<notify::Notifier>,
+    /// White-label tenant profiles (TENANTS_PATH)
+    pub tenants: Arc<tenants::Tenants>,
+    /// Background work awaited on shutdown (SHUTDOWN_TIMEOUT_SECS)
+    pub tasks: Arc<tasks::TaskManager>,
 }
 
 // ============= Request/Response Models =============
@@ -42,112 +132,497 @@ pub struct AppState {
 #[derive(Deserialize)]
 struct TikTokRequest {
     url: String,
+    /// Optional extraction timeout in seconds (capped by YTDLP_MAX_TIMEOUT)
+    timeout: Option<u64>,
+    /// Add every format as `formats` (default RESPONSE_FORMATS)
+    formats: Option<bool>,
 }
 
 #[derive(Deserialize)]
 struct SlideshowQuery {
+    /// Encrypted post URL; not needed with `session`
+    #[serde(default)]
     url: String,
+    /// `session` from a /tiktok response, reusing its extraction result
+    session: Option<String>,
+    /// mp4 (default), webm or gif
+    output: Option<String>,
+    /// `data` token of another post's download_link.mp3 to use as the sound
+    audio: Option<String>,
+    /// none, title, author or both (default SLIDESHOW_CAPTION)
+    caption: Option<String>,
+    /// top or bottom (default SLIDESHOW_CAPTION_POSITION)
+    caption_position: Option<String>,
+    /// fixed (4s per image) or audio (the track's length split across the
+    /// images); default SLIDESHOW_TIMING
+    timing: Option<String>,
+}
+
+/// Where a slideshow's sound comes from.
+enum SlideshowAudio {
+    /// The post's own sound
+    Original,
+    /// Another post's sound, from the `data` token of its download_link.mp3
+    Token(String),
+    /// A file uploaded with POST /download-slideshow
+    Upload(axum::body::Bytes),
+}
+
+/// A replacement sound: fetched from a CDN or written from an upload.
+enum AudioInput {
+    Download {
+        url: String,
+        headers: Vec<(String, String)>,
+    },
+    Upload(axum::body::Bytes),
 }
 
 // ============= Handlers =============
 
 /// POST /tiktok — Process TikTok URL and return metadata with encrypted download links
 async fn tiktok_handler(
-    State(state): State<AppState>,
+    TenantState(state): TenantState,
+    headers: HeaderMap,
     Json(req): Json<TikTokRequest>,
 ) -> impl IntoResponse {
     let url = req.url.trim().to_string();
+    if let Some(resp) = reject_unsupported_url(&url) {
+        return resp;
+    }
 
+    let timeout_secs = state.settings.extraction_timeout(req.timeout);
+    let priority = request_priority(&headers, &state);
+    let formats = req.formats.unwrap_or(state.settings.response_formats);
+    match process_url(&state, &url, timeout_secs, priority, formats).await {
+        Ok(mut response) => {
+            shortlink::add_short_links(&state, &mut response).await;
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// 400 for an empty URL or one that isn't TikTok/Douyin.
+fn reject_unsupported_url(url: &str) -> Option<Response> {
     if url.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "URL parameter is required"})),
-        )
-            .into_response();
+        return Some(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "URL parameter is required"})),
+            )
+                .into_response(),
+        );
     }
 
     let url_lower = url.to_lowercase();
     if !url_lower.contains("tiktok.com") && !url_lower.contains("douyin.com") {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "Only TikTok and Douyin URLs are supported"})),
-        )
-            .into_response();
+        return Some(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "Only TikTok and Douyin URLs are supported"})),
+            )
+                .into_response(),
+        );
     }
+    None
+}
 
-    // Fetch data (with cache)
-    let data = match fetch_tiktok_data(&url, &state).await {
-        Ok(d) => d,
-        Err(resp) => return resp,
+/// Run the moderation webhook on an extraction result: 403 CONTENT_FLAGGED
+/// when it is flagged under the block policy, 503 MODERATION_UNAVAILABLE
+/// when the webhook fails and MODERATION_FAIL_OPEN is off. Every endpoint
+/// that serves media from a fresh extraction goes through this.
+async fn moderate(
+    state: &AppState,
+    url: &str,
+    data: &serde_json::Value,
+) -> Result<Option<moderation::Verdict>, Response> {
+    let verdict = match moderation::check(&state.http_client, &state.settings, data, url).await {
+        Ok(v) => v,
+        Err(e) if moderation::fail_open(&state.settings, &e) => None,
+        Err(_) => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({
+                    "error": "Content moderation unavailable",
+                    "code": "MODERATION_UNAVAILABLE",
+                })),
+            )
+                .into_response())
+        }
     };
+    let policy = moderation::Policy::from_settings(&state.settings);
+    if let Some(ref v) = verdict {
+        if v.flagged {
+            state.metrics.record_outcome(platform_for_url(url), "moderation_flagged");
+        }
+        if v.flagged && policy == moderation::Policy::Block {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({
+                    "error": "Content blocked by moderation policy",
+                    "code": "CONTENT_FLAGGED",
+                    "labels": v.labels,
+                })),
+            )
+                .into_response());
+        }
+    }
+    Ok(verdict)
+}
+
+/// 403 for a request that needs a disabled platform or feature.
+pub fn feature_disabled(platform: &str, feature: Option<&str>) -> Response {
+    let what = match feature {
+        Some(feature) => format!("{feature} for {platform}"),
+        None => platform.to_string(),
+    };
+    (
+        StatusCode::FORBIDDEN,
+        Json(serde_json::json!({
+            "error": format!("{what} is disabled on this server"),
+            "code": "FEATURE_DISABLED",
+        })),
+    )
+        .into_response()
+}
+
+/// Feature/blocklist checks, extraction (with cache), moderation and the
+/// /tiktok response with encrypted download links (plus the `formats`
+/// catalog when asked). Shared by POST /tiktok, scheduled jobs and the
+/// embeds; callers handing links out add the short ones.
+async fn process_url(
+    state: &AppState,
+    url: &str,
+    timeout_secs: u64,
+    priority: u8,
+    include_formats: bool,
+) -> Result<serde_json::Value, Response> {
+    let platform = platform_for_url(url);
+    if !state.settings.features.platform_enabled(platform) {
+        return Err(feature_disabled(platform, None));
+    }
+
+    if let Some(hit) = state.blocklist.check(&Subject::from_url(url)) {
+        return Err(hit.into_response());
+    }
+
+    // Fetch data (with cache)
+    let data = fetch_tiktok_data(url, state, timeout_secs, priority).await?;
+    if let Some(hit) = state.blocklist.check(&data_subject(url, &data)) {
+        return Err(hit.into_response());
+    }
+    let feature = if response::is_image_post(&data) { "images" } else { "video" };
+    if !state.settings.features.enabled(platform, feature) {
+        return Err(feature_disabled(platform, Some(feature)));
+    }
+
+    // Moderation webhook, before any download link is generated
+    let verdict = moderate(state, url, &data).await?;
 
     // Generate response
-    let response = response::generate_json_response(&data, &url, &state.settings);
-    (StatusCode::OK, Json(response)).into_response()
+    let mut response = response::generate_json_response(&data, url, &state.settings);
+    if let Some(v) = verdict.filter(|v| v.flagged) {
+        response["moderation"] = serde_json::to_value(&v).unwrap();
+    }
+    if include_formats {
+        response["formats"] =
+            serde_json::to_value(response::format_catalog(&data, url, &state.settings)).unwrap();
+    }
+
+    // Slideshows from this result skip a second extraction
+    if let Some(ref sessions) = state.sessions {
+        if response.get("download_slideshow_link").is_some() {
+            let id = sessions.insert(url, Arc::new(data));
+            response["download_slideshow_link"] = serde_json::Value::String(format!(
+                "{}/download-slideshow?session={id}",
+                state.settings.base_url
+            ));
+            response["session"] = serde_json::Value::String(id);
+        }
+    }
+    Ok(response)
+}
+
+/// Blocklist subject for an extraction result (short links resolve to ids here).
+fn data_subject<'a>(url: &'a str, data: &'a serde_json::Value) -> Subject<'a> {
+    Subject {
+        url: Some(url),
+        video_id: data["id"].as_str(),
+        creator: data["uploader"].as_str(),
+    }
 }
 
 /// GET /download — Download file using encrypted data
 async fn download_handler(
-    State(state): State<AppState>,
+    TenantState(state): TenantState,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     Query(query): Query<stream::DownloadQuery>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    stream::download_handler(Query(query), state.settings, state.http_client).await
+    let client = streams::client_addr(&headers, peer.ip(), &state.settings.trusted_proxies);
+    stream::download_handler(Query(query), &headers, client, &state).await
 }
 
 /// GET /stream — Stream video/audio directly
 async fn stream_handler(
-    State(state): State<AppState>,
+    TenantState(state): TenantState,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<stream::DownloadQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let client = streams::client_addr(&headers, peer.ip(), &state.settings.trusted_proxies);
+    stream::stream_handler(Query(query), &headers, client, &state).await
+}
+
+/// GET /checksum — SHA-256 of the file behind a download/stream token
+async fn checksum_handler(
+    TenantState(state): TenantState,
     Query(query): Query<stream::DownloadQuery>,
 ) -> impl IntoResponse {
-    stream::stream_handler(Query(query), state.settings, state.http_client).await
+    stream::checksum_handler(Query(query), &state).await
 }
 
 /// GET /download-slideshow — Generate and download slideshow video from image post
 async fn slideshow_handler(
-    State(state): State<AppState>,
+    TenantState(state): TenantState,
+    headers: HeaderMap,
     Query(query): Query<SlideshowQuery>,
-) -> impl IntoResponse {
-    if query.url.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "URL parameter is required"})),
-        )
-            .into_response();
+) -> Response {
+    let audio = match &query.audio {
+        Some(token) if !token.is_empty() => SlideshowAudio::Token(token.clone()),
+        _ => SlideshowAudio::Original,
+    };
+    generate_slideshow(&state, &headers, &query, audio).await
+}
+
+/// POST /download-slideshow — Same, as multipart (`url` or `session`, `output`,
+/// `caption`, `caption_position`, `timing`, `audio`) with an uploaded file replacing the
+/// post's sound
+async fn slideshow_upload_handler(
+    TenantState(state): TenantState,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    let max_bytes = state.settings.slideshow_max_audio_mb * 1024 * 1024;
+    let mut query = SlideshowQuery {
+        url: String::new(),
+        session: None,
+        output: None,
+        audio: None,
+        caption: None,
+        caption_position: None,
+        timing: None,
+    };
+    let mut audio = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(f)) => f,
+            Ok(None) => break,
+            Err(e) => {
+                return (e.status(), Json(serde_json::json!({"error": e.body_text()}))).into_response()
+            }
+        };
+        let name = field.name().unwrap_or("").to_string();
+        let value = match field.bytes().await {
+            Ok(v) => v,
+            Err(e) => {
+                return (e.status(), Json(serde_json::json!({"error": e.body_text()}))).into_response()
+            }
+        };
+        match name.as_str() {
+            "url" => query.url = String::from_utf8_lossy(&value).into_owned(),
+            "session" => query.session = Some(String::from_utf8_lossy(&value).into_owned()),
+            "output" => query.output = Some(String::from_utf8_lossy(&value).into_owned()),
+            "caption" => query.caption = Some(String::from_utf8_lossy(&value).into_owned()),
+            "caption_position" => {
+                query.caption_position = Some(String::from_utf8_lossy(&value).into_owned())
+            }
+            "timing" => query.timing = Some(String::from_utf8_lossy(&value).into_owned()),
+            "audio" => audio = Some(value),
+            _ => {}
+        }
     }
 
+    let audio = match audio {
+        Some(a) if a.len() > max_bytes => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(serde_json::json!({
+                    "error": format!("Audio file exceeds {} MB", state.settings.slideshow_max_audio_mb),
+                    "code": "AUDIO_TOO_LARGE",
+                })),
+            )
+                .into_response()
+        }
+        Some(a) if !a.is_empty() => a,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "Audio file is required"})),
+            )
+                .into_response()
+        }
+    };
+    generate_slideshow(&state, &headers, &query, SlideshowAudio::Upload(audio)).await
+}
+
+/// Shared body of GET and POST /download-slideshow
+async fn generate_slideshow(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: &SlideshowQuery,
+    audio: SlideshowAudio,
+) -> Response {
+    // A session carries the URL and the extraction result of a /tiktok call
+    let session = match query.session.as_deref().filter(|s| !s.is_empty()) {
+        Some(id) => match state.sessions.as_ref().and_then(|s| s.get(id)) {
+            Some(s) => Some(s),
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(serde_json::json!({
+                        "error": "Session not found or expired",
+                        "code": "SESSION_NOT_FOUND",
+                    })),
+                )
+                    .into_response()
+            }
+        },
+        None if query.url.is_empty() => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "URL or session parameter is required"})),
+            )
+                .into_response();
+        }
+        None => None,
+    };
+
     // Decrypt URL
-    let decrypted_url = match decrypt(&query.url, &state.settings.encryption_key) {
-        Ok(u) => u,
+    let decrypted_url = match &session {
+        Some(s) => s.url.clone(),
+        None => match decrypt(&query.url, &state.settings.encryption_key) {
+            Ok(u) => u,
+            Err(e) => {
+                error!("Decryption failed: {e}");
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": format!("Decryption failed: {e}")})),
+                )
+                    .into_response();
+            }
+        },
+    };
+
+    let format = match slideshow::OutputFormat::parse(
+        query.output.as_deref().unwrap_or("mp4"),
+        &state.settings.slideshow_webm_codec,
+    ) {
+        Ok(f) => f,
         Err(e) => {
-            error!("Decryption failed: {e}");
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e}))).into_response()
+        }
+    };
+    let caption_options = slideshow::CaptionContent::parse(
+        query.caption.as_deref().unwrap_or(&state.settings.slideshow_caption),
+    )
+    .and_then(|content| {
+        let position = slideshow::CaptionPosition::parse(
+            query
+                .caption_position
+                .as_deref()
+                .unwrap_or(&state.settings.slideshow_caption_position),
+        )?;
+        Ok((content, position))
+    });
+    let (caption_content, caption_position) = match caption_options {
+        Ok(c) => c,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e}))).into_response()
+        }
+    };
+    let timing = match slideshow::Timing::parse(
+        query.timing.as_deref().unwrap_or(&state.settings.slideshow_timing),
+    ) {
+        Ok(t) => t,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e}))).into_response()
+        }
+    };
+
+    let platform = platform_for_url(&decrypted_url);
+    if !state.settings.features.enabled(platform, "slideshow") {
+        return feature_disabled(platform, Some("slideshow"));
+    }
+
+    if let Some(hit) = state.blocklist.check(&Subject::from_url(&decrypted_url)) {
+        return hit.into_response();
+    }
+
+    // Lets the client poll GET /slideshow/queue/{ticket} while this waits
+    let named = match headers.get("X-Queue-Ticket").map(|v| v.to_str().unwrap_or("")) {
+        Some(name) if !valid_ticket_name(name) => {
             return (
                 StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({"error": format!("Decryption failed: {e}")})),
+                Json(serde_json::json!({
+                    "error": "X-Queue-Ticket must be 1-64 letters, digits, '-' or '_'",
+                    "code": "INVALID_QUEUE_TICKET",
+                })),
             )
                 .into_response();
         }
+        Some(name) => match state.slideshow_tickets.register(name) {
+            Some(named) => Some(named),
+            None => {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(serde_json::json!({
+                        "error": "X-Queue-Ticket is already in use",
+                        "code": "QUEUE_TICKET_IN_USE",
+                    })),
+                )
+                    .into_response();
+            }
+        },
+        None => None,
     };
 
-    // Fetch TikTok data
-    let data = match fetch_tiktok_data(&decrypted_url, &state).await {
-        Ok(d) => d,
-        Err(resp) => return resp,
+    // GIFs have no sound; a borrowed sound is checked before the extraction
+    let mut audio_input = match audio {
+        _ if !format.has_audio() => None,
+        SlideshowAudio::Original => None,
+        SlideshowAudio::Token(token) => match stream::audio_from_token(state, &token) {
+            Ok((url, headers)) => Some(AudioInput::Download { url, headers }),
+            Err(resp) => return resp,
+        },
+        SlideshowAudio::Upload(bytes) => Some(AudioInput::Upload(bytes)),
     };
 
-    // Check if it's an image post
-    let is_image = data["formats"]
-        .as_array()
-        .map(|fmts| {
-            fmts.iter().any(|f| {
-                f["format_id"]
-                    .as_str()
-                    .unwrap_or("")
-                    .starts_with("image-")
-            })
-        })
-        .unwrap_or(false);
+    // Fetch TikTok data, unless the session already has it
+    let priority = request_priority(headers, state);
+    let from_session = session.is_some();
+    let data = match session {
+        Some(s) => s.data,
+        None => {
+            let timeout_secs = state.settings.ytdlp_timeout;
+            match fetch_tiktok_data(&decrypted_url, state, timeout_secs, priority).await {
+                Ok(d) => Arc::new(d),
+                Err(resp) => return resp,
+            }
+        }
+    };
+    if let Some(hit) = state.blocklist.check(&data_subject(&decrypted_url, &data)) {
+        return hit.into_response();
+    }
+    // Sessions are only created for results that already passed moderation
+    if !from_session {
+        if let Err(resp) = moderate(state, &decrypted_url, &data).await {
+            return resp;
+        }
+    }
 
-    if !is_image {
+    // Check if it's an image post
+    if !response::is_image_post(&data) {
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({"error": "Only image posts are supported"})),
@@ -173,6 +648,7 @@ async fn slideshow_handler(
                 .as_str()
                 .unwrap_or("")
                 .starts_with("image-")
+                && f["url"].is_string()
         })
         .collect();
     let audio_format = formats
@@ -187,21 +663,41 @@ async fn slideshow_handler(
             .into_response();
     }
 
-    let audio_url = match audio_format.and_then(|af| af["url"].as_str()) {
-        Some(u) if !u.is_empty() => u.to_string(),
-        _ => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({"error": "Could not find audio URL"})),
-            )
-                .into_response()
+    // Download audio and images with the extraction's UA and the platform's
+    // configured headers
+    let mut fetch_headers = Vec::new();
+    if let Some(ua) = data["http_headers"]["User-Agent"].as_str() {
+        fetch_headers.push(("User-Agent".to_string(), ua.to_string()));
+    }
+    fetch_headers.extend(state.settings.headers_for(platform));
+
+    if format.has_audio() && audio_input.is_none() {
+        match audio_format.and_then(|af| af["url"].as_str()) {
+            Some(u) if !u.is_empty() => {
+                audio_input = Some(AudioInput::Download {
+                    url: u.to_string(),
+                    headers: fetch_headers.clone(),
+                })
+            }
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": "Could not find audio URL"})),
+                )
+                    .into_response()
+            }
         }
-    };
+    }
 
     let image_urls: Vec<String> = image_formats
         .iter()
         .filter_map(|f| f["url"].as_str().map(|s| s.to_string()))
         .collect();
+    let max_secs = state.settings.slideshow_max_secs as f64;
+    let native_durations = match timing {
+        slideshow::Timing::Native => slideshow::native_image_durations(&image_formats, max_secs),
+        _ => None,
+    };
 
     // Create work directory
     let video_id = data["id"].as_str().unwrap_or("unknown");
@@ -224,17 +720,24 @@ async fn slideshow_handler(
 
     let work_dir_str = work_dir.to_string_lossy().to_string();
     let audio_path = work_dir.join("audio.mp3").to_string_lossy().to_string();
-    let output_path = work_dir.join("slideshow.mp4").to_string_lossy().to_string();
+    let output_path = work_dir
+        .join(format!("slideshow.{}", format.extension()))
+        .to_string_lossy()
+        .to_string();
 
-    // Download audio and images in spawn_blocking
-    let audio_url_clone = audio_url.clone();
-    let audio_path_clone = audio_path.clone();
-    let dl_result = tokio::task::spawn_blocking(move || {
-        slideshow::download_file(&audio_url_clone, &audio_path_clone, 120)
-    })
-    .await;
+    let job = state.jobs.register(JobKind::Slideshow, platform, &decrypted_url);
+
+    let dl_result = match &audio_input {
+        Some(AudioInput::Download { url, headers }) => {
+            download_asset(state, &job, url, &audio_path, headers).await
+        }
+        Some(AudioInput::Upload(bytes)) => tokio::fs::write(&audio_path, bytes)
+            .await
+            .map_err(|e| format!("Failed to save uploaded audio: {e}")),
+        None => Ok(()),
+    };
 
-    if let Err(e) = dl_result.unwrap_or(Err("Task join error".into())) {
+    if let Err(e) = dl_result {
         error!("Failed to download audio: {e}");
         let wd = work_dir_str.clone();
         tokio::task::spawn_blocking(move || cleanup::cleanup_folder(&wd));
@@ -245,20 +748,48 @@ async fn slideshow_handler(
             .into_response();
     }
 
+    // Uploads are checked here rather than failing deep inside ffmpeg
+    if matches!(audio_input, Some(AudioInput::Upload(_))) {
+        let ap = audio_path.clone();
+        if let Err(e) = run_slideshow_io(state, &job, move || slideshow::probe_audio(&ap)).await {
+            let wd = work_dir_str.clone();
+            tokio::task::spawn_blocking(move || cleanup::cleanup_folder(&wd));
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": format!("Invalid audio file: {e}"),
+                    "code": "INVALID_AUDIO",
+                })),
+            )
+                .into_response();
+        }
+    }
+
+    // Native timing uses the post's own per-image times. Audio timing plays
+    // the whole track once across the images; GIFs have no audio, and an
+    // unreadable duration falls back to fixed timing.
+    let mut durations =
+        native_durations.unwrap_or_else(|| vec![SLIDESHOW_SECS_PER_IMAGE; image_urls.len()]);
+    if timing == slideshow::Timing::Audio && audio_input.is_some() {
+        let ap = audio_path.clone();
+        match run_slideshow_io(state, &job, move || slideshow::audio_duration(&ap)).await {
+            Ok(audio_secs) => {
+                let secs = slideshow::audio_timed_secs_per_image(audio_secs, image_urls.len(), max_secs);
+                durations = vec![secs; image_urls.len()];
+            }
+            Err(e) => warn!("Audio duration unknown, using fixed timing: {e}"),
+        }
+    }
+
     let mut image_paths = Vec::new();
     for (i, img_url) in image_urls.iter().enumerate() {
         let img_path = work_dir
             .join(format!("image_{i}.jpg"))
             .to_string_lossy()
             .to_string();
-        let url_clone = img_url.clone();
-        let path_clone = img_path.clone();
-        let dl_result = tokio::task::spawn_blocking(move || {
-            slideshow::download_file(&url_clone, &path_clone, 120)
-        })
-        .await;
+        let dl_result = download_asset(state, &job, img_url, &img_path, &fetch_headers).await;
 
-        if let Err(e) = dl_result.unwrap_or(Err("Task join error".into())) {
+        if let Err(e) = dl_result {
             error!("Failed to download image {i}: {e}");
             let wd = work_dir_str.clone();
             tokio::task::spawn_blocking(move || cleanup::cleanup_folder(&wd));
@@ -272,21 +803,127 @@ async fn slideshow_handler(
     }
 
     // Create slideshow
+    let caption = caption_content
+        .text(
+            data["title"].as_str().unwrap_or(""),
+            data["uploader_id"]
+                .as_str()
+                .or_else(|| data["uploader"].as_str())
+                .unwrap_or(""),
+        )
+        .map(|text| slideshow::Caption {
+            text,
+            position: caption_position,
+            font: state.settings.slideshow_caption_font.clone(),
+            font_size: state.settings.slideshow_caption_size,
+        });
+
+    // Wait for an FFmpeg slot; a full queue is rejected like /tiktok's
+    let ticket = match state.ffmpeg_queue.enter(priority) {
+        Ok(t) => t,
+        Err(full) => {
+            let wd = work_dir_str.clone();
+            tokio::task::spawn_blocking(move || cleanup::cleanup_folder(&wd));
+            let retry_after = state
+                .ffmpeg_queue
+                .retry_after_secs(state.metrics.mean_ffmpeg_secs().unwrap_or(15.0));
+            warn!(
+                "FFmpeg queue full ({} waiting), rejecting with Retry-After {retry_after}s",
+                full.queued
+            );
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("Retry-After", retry_after.to_string())],
+                Json(serde_json::json!({
+                    "error": "Slideshow queue is full, please retry later",
+                    "code": "SLIDESHOW_QUEUE_FULL",
+                })),
+            )
+                .into_response();
+        }
+    };
+    let queue_position = state.ffmpeg_queue.position(&ticket);
+    if let Some(named) = &named {
+        named.queued(&ticket);
+    }
+    if queue_position > 0 {
+        info!("🎞️ Slideshow for {decrypted_url} queued at position {queue_position}");
+    }
+    let queued_at = std::time::Instant::now();
+    job.queued();
+    let permit = tokio::select! {
+        permit = ticket.wait() => permit,
+        _ = job.cancelled() => {
+            let wd = work_dir_str.clone();
+            tokio::task::spawn_blocking(move || cleanup::cleanup_folder(&wd));
+            return job_cancelled_response();
+        }
+    };
+    job.running();
+    if let Some(named) = &named {
+        named.running();
+    }
+    let queue_wait = queued_at.elapsed();
+
+    // Generate and verify the file before serving it. The permit moves into
+    // the blocking task so the slot stays taken until FFmpeg exits; if the
+    // client goes away meanwhile, FFmpeg is killed and the folder removed.
     let imgs = image_paths.clone();
-    let ap = audio_path.clone();
+    let ap = audio_input.is_some().then(|| audio_path.clone());
     let op = output_path.clone();
-    let ss_result =
-        tokio::task::spawn_blocking(move || slideshow::create_slideshow(&imgs, &ap, &op, 4)).await;
+    let wd = work_dir_str.clone();
+    let expected = format.expected(durations.iter().sum());
+    let metrics = state.metrics.clone();
+    let stop = job.abandoned();
+    let generated = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let started = std::time::Instant::now();
+        let created = slideshow::create_slideshow(
+            &imgs,
+            ap.as_deref(),
+            &op,
+            &durations,
+            format,
+            caption.as_ref(),
+            &stop,
+        );
+        if stop.is_cancelled() {
+            cleanup::cleanup_folder(&wd);
+            return Err("Client went away".into());
+        }
+        let verified = created.map(|()| slideshow::verify_output(&op, &expected));
+        metrics.observe_ffmpeg(format.extension(), started.elapsed());
+        verified
+    })
+    .await
+    .unwrap_or(Err("Task join error".into()));
 
-    if let Err(e) = ss_result.unwrap_or(Err("Task join error".into())) {
-        error!("Slideshow creation failed: {e}");
-        let wd = work_dir_str.clone();
-        tokio::task::spawn_blocking(move || cleanup::cleanup_folder(&wd));
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": format!("Slideshow creation failed: {e}")})),
-        )
-            .into_response();
+    match generated {
+        Err(e) => {
+            error!("Slideshow creation failed: {e}");
+            let wd = work_dir_str.clone();
+            tokio::task::spawn_blocking(move || cleanup::cleanup_folder(&wd));
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": format!("Slideshow creation failed: {e}")})),
+            )
+                .into_response();
+        }
+        Ok(Err(e)) => {
+            error!("Generated slideshow is invalid: {e}");
+            let wd = work_dir_str.clone();
+            tokio::task::spawn_blocking(move || cleanup::cleanup_folder(&wd));
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": "Generated slideshow failed verification",
+                    "code": "GENERATION_INVALID",
+                    "detail": e,
+                })),
+            )
+                .into_response();
+        }
+        Ok(Ok(())) => {}
     }
 
     // Read output file and stream it
@@ -298,7 +935,7 @@ async fn slideshow_handler(
         .chars()
         .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
         .collect();
-    let filename = format!("{sanitized}_{now_ts}.mp4");
+    let filename = format!("{sanitized}_{now_ts}.{}", format.extension());
 
     let file_bytes = match tokio::fs::read(&output_path).await {
         Ok(b) => b,
@@ -322,20 +959,78 @@ async fn slideshow_handler(
             .ok();
     });
 
+    let sha256 = state
+        .settings
+        .checksum_header
+        .then(|| checksum::sha256_bytes(&file_bytes));
+    job.done();
+
     let body = Body::from(file_bytes);
     let mut resp = Response::new(body);
     *resp.status_mut() = StatusCode::OK;
     resp.headers_mut().insert(
         "Content-Type",
-        HeaderValue::from_static("video/mp4"),
+        HeaderValue::from_static(format.content_type()),
     );
     resp.headers_mut().insert(
         "Content-Disposition",
         HeaderValue::from_str(&format!("attachment; filename=\"{filename}\"")).unwrap(),
     );
+    if let Some(sha256) = sha256.and_then(|h| HeaderValue::from_str(&h).ok()) {
+        resp.headers_mut().insert(checksum::CHECKSUM_HEADER, sha256);
+    }
+    resp.headers_mut()
+        .insert("X-Queue-Position", HeaderValue::from(queue_position));
+    resp.headers_mut().insert(
+        "X-Queue-Wait-Ms",
+        HeaderValue::from(queue_wait.as_millis() as u64),
+    );
     resp
 }
 
+/// GET /slideshow/queue — FFmpeg queue load, so clients can show the
+/// expected wait before or while requesting a slideshow
+async fn slideshow_queue_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let status = state.ffmpeg_queue.status();
+    let mean_secs = state.metrics.mean_ffmpeg_secs();
+    let estimated_wait_secs = (status.in_flight >= status.workers)
+        .then(|| state.ffmpeg_queue.retry_after_secs(mean_secs.unwrap_or(15.0)));
+    let mut body = serde_json::to_value(status).unwrap();
+    body["mean_generation_secs"] = serde_json::json!(mean_secs);
+    body["estimated_wait_secs"] = serde_json::json!(estimated_wait_secs.unwrap_or(0));
+    Json(body)
+}
+
+fn valid_ticket_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// GET /slideshow/queue/{ticket} — where the slideshow request sent with
+/// `X-Queue-Ticket: {ticket}` stands while it waits: `preparing`, `queued`
+/// (with its position and estimated wait) or `running`. 404 once it has
+/// been answered, or for an unknown ticket.
+async fn slideshow_ticket_handler(
+    State(state): State<AppState>,
+    Path(ticket): Path<String>,
+) -> impl IntoResponse {
+    let Some(ticket_state) = state.slideshow_tickets.state(&ticket, &state.ffmpeg_queue) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Unknown or finished queue ticket"})),
+        )
+            .into_response();
+    };
+    let mut body = serde_json::to_value(&ticket_state).unwrap();
+    if let TicketState::Queued { position } = ticket_state {
+        let mean_secs = state.metrics.mean_ffmpeg_secs().unwrap_or(15.0);
+        body["estimated_wait_secs"] = state.ffmpeg_queue.wait_secs(position, mean_secs).into();
+    }
+    Json(body).into_response()
+}
+
 /// GET /health — Health check endpoint
 async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
     let now = SystemTime::now()
@@ -353,9 +1048,31 @@ async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
         "disconnected"
     };
 
-    // Check VPN connectivity if configured
+    let python = state.python_status.read().await.clone();
+    let (status_code, status) = if python.is_healthy() {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+    };
+
+    // Per-dependency pass/warn/fail/skip, so monitoring can see what broke
+    let (checks, failed_checks) = health::run_checks(&state).await;
+    // Kept for existing consumers; the same data is in checks.vpn
+    let vpn = &checks["vpn"];
+    let legacy_vpn = match vpn["status"].as_str() {
+        Some("pass") => Some(serde_json::json!({
+            "public_ip": vpn["public_ip"],
+            "status": "connected"
+        })),
+        Some("fail") => Some(serde_json::json!({
+            "status": "error",
+            "error": vpn["error"]
+        })),
+        _ => None,
+    };
+
     let mut health = serde_json::json!({
-        "status": "ok",
+        "status": status,
         "instance_id": state.settings.instance_id,
         "instance_region": state.settings.instance_region,
         "port": state.settings.port,
@@ -363,54 +1080,40 @@ async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
         "runtime": "Rust + Tokio + PyO3 (yt-dlp)",
         "redis": {
             "status": redis_status,
+            "backend": state.redis.as_ref().map(|r| r.backend_name()),
             "caching_enabled": state.redis.is_some()
-        }
+        },
+        "python": python.to_json(),
+        "extraction_queue": serde_json::to_value(state.extraction_queue.status()).unwrap(),
+        "ffmpeg_queue": serde_json::to_value(state.ffmpeg_queue.status()).unwrap(),
+        "cookies": {
+            "status": state.cookies.worst_state().map(|s| serde_json::to_value(s).unwrap()),
+            "profiles": state.cookies.iter().flat_map(|pool| pool.status().into_iter().map(|p| serde_json::json!({
+                "platform": pool.platform(),
+                "path": p.path,
+                "active": p.active,
+                "cooling_down": p.cooling_down,
+                "check": p.check,
+            }))).collect::<Vec<_>>()
+        },
+        "checks": checks,
+        "failed_checks": failed_checks,
     });
 
-    if state.settings.gluetun_control_port != 8000 {
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(5))
-            .build()
-            .ok();
-
-        if let Some(client) = client {
-            match client
-                .get(format!(
-                    "http://localhost:{}/v1/publicip/ip",
-                    state.settings.gluetun_control_port
-                ))
-                .basic_auth(
-                    &state.settings.gluetun_username,
-                    Some(&state.settings.gluetun_password),
-                )
-                .send()
-                .await
-            {
-                Ok(resp) if resp.status().is_success() => {
-                    if let Ok(ip_data) = resp.json::<serde_json::Value>().await {
-                        health["vpn"] = serde_json::json!({
-                            "public_ip": ip_data["public_ip"],
-                            "status": "connected"
-                        });
-                    }
-                }
-                Ok(resp) => {
-                    health["vpn"] = serde_json::json!({
-                        "status": "error",
-                        "error": format!("HTTP {}", resp.status())
-                    });
-                }
-                Err(e) => {
-                    health["vpn"] = serde_json::json!({
-                        "status": "error",
-                        "error": e.to_string()
-                    });
-                }
-            }
-        }
+    if let Some(vpn) = legacy_vpn {
+        health["vpn"] = vpn;
     }
 
-    (StatusCode::OK, Json(health))
+    (status_code, Json(health))
+}
+
+/// GET /metrics — Prometheus text exposition
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [("Content-Type", "text/plain; version=0.0.4")],
+        state.metrics.render() + &state.streams.render_metrics(),
+    )
 }
 
 /// 404 handler
@@ -423,10 +1126,79 @@ async fn not_found_handler() -> impl IntoResponse {
 
 // ============= Core Logic =============
 
+/// Reconnect the local VPN after a 403, reporting it to Discord. True when
+/// the egress IP was actually rotated (not skipped by the cooldown).
+async fn reconnect_vpn(state: &AppState) -> bool {
+    let result = vpn::trigger_local_vpn_reconnect(
+        &state.vpn_state,
+        &state.settings.instance_id,
+        state.settings.gluetun_control_port,
+        &state.settings.gluetun_username,
+        &state.settings.gluetun_password,
+    )
+    .await;
+    state.notifier.vpn_reconnect(&result);
+    matches!(result, Ok(true))
+}
+
+/// Extraction queue priority from the request's API key tier.
+fn request_priority(headers: &HeaderMap, state: &AppState) -> u8 {
+    let key = headers.get("x-api-key").and_then(|v| v.to_str().ok());
+    state.settings.priority_for(key)
+}
+
+enum LockWait {
+    /// Another request extracted the URL while we waited
+    Cached(serde_json::Value),
+    /// Extract it ourselves; the lock, if we got it, is released on drop
+    Proceed(Option<cache::ExtractionLock>),
+}
+
+/// How often a waiting request checks whether the lock holder is done.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Take the URL's extraction lock, or wait (at most `timeout_secs`, the time
+/// we'd spend extracting ourselves) for the holder to cache the result. When
+/// the holder fails, the lock is released and one waiter takes over.
+async fn wait_for_extraction_lock(url: &str, state: &AppState, timeout_secs: u64) -> LockWait {
+    let ttl = state.settings.extraction_lock_ttl_secs;
+    let Some(redis) = state.redis.as_ref().filter(|_| ttl > 0) else {
+        return LockWait::Proceed(None);
+    };
+    let owner = format!(
+        "{}:{}",
+        state.settings.instance_id,
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos()
+    );
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(timeout_secs);
+    let mut waited = false;
+    loop {
+        if let Some(lock) = redis.try_lock_extraction(url, &owner, ttl).await {
+            return LockWait::Proceed(Some(lock));
+        }
+        if !waited {
+            info!("⏳ Waiting for another extraction of {}...", url.chars().take(50).collect::<String>());
+            waited = true;
+        }
+        if tokio::time::Instant::now() + LOCK_POLL_INTERVAL > deadline {
+            warn!("Extraction lock wait timed out, extracting anyway");
+            return LockWait::Proceed(None);
+        }
+        tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+        if redis.has_metadata(url).await {
+            if let Some(data) = redis.get_metadata(url).await.and_then(|c| serde_json::from_str(&c).ok()) {
+                return LockWait::Cached(data);
+            }
+        }
+    }
+}
+
 /// Fetch TikTok data via yt-dlp with Redis caching
 async fn fetch_tiktok_data(
     url: &str,
     state: &AppState,
+    timeout_secs: u64,
+    priority: u8,
 ) -> Result<serde_json::Value, axum::response::Response> {
     // Check cache first
     if let Some(ref redis) = state.redis {
@@ -437,21 +1209,73 @@ async fn fetch_tiktok_data(
         }
     }
 
-    // Cache miss — extract via yt-dlp
-    let url_clone = url.to_string();
-    let cookies_path = state.settings.cookies_path.to_string_lossy().to_string();
-    let timeout_secs = state.settings.ytdlp_timeout;
-
-    let result = tokio::time::timeout(
-        std::time::Duration::from_secs(timeout_secs),
-        tokio::task::spawn_blocking(move || {
-            ytdlp::extract_with_ytdlp(&url_clone, Some(&cookies_path))
-        }),
-    )
-    .await;
+    // Cache miss — let one instance extract while the others wait for its result
+    let _lock = match wait_for_extraction_lock(url, state, timeout_secs).await {
+        LockWait::Cached(data) => return Ok(data),
+        LockWait::Proceed(lock) => lock,
+    };
+
+    // Extract via yt-dlp, retrying transient failures with backoff
+    let platform = platform_for_url(url);
+    let primary = state.providers[0].clone();
+    let mut attempt = 0;
+    let mut deadline = Deadline::new(timeout_secs);
+    let result = loop {
+        let result = extract_once(url, state, &primary, platform, &mut deadline, priority).await?;
+        let outcome = match &result {
+            Ok(_) => "success",
+            Err(e) => e.kind(),
+        };
+        state.metrics.record_outcome(platform, outcome);
+
+        match result {
+            Err(e) if e.is_retryable() && attempt < state.settings.ytdlp_retries && !deadline.passed() => {
+                // A 403 from the same egress IP would only be blocked again
+                if matches!(e, ExtractError::Forbidden(_))
+                    && !(state.settings.ytdlp_retry_rotate_vpn && reconnect_vpn(state).await)
+                {
+                    break Err(e);
+                }
+                attempt += 1;
+                let delay = retry_backoff_ms(state.settings.ytdlp_retry_backoff_ms, attempt);
+                warn!(
+                    "Extraction attempt {attempt}/{} failed ({}), retrying in {delay}ms",
+                    state.settings.ytdlp_retries + 1,
+                    e.kind()
+                );
+                tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+            }
+            other => break other,
+        }
+    };
+
+    // yt-dlp is broken for this URL (not a user error) — try fallback providers
+    let result = match result {
+        Err(e) if !matches!(
+            e,
+            ExtractError::NotFound(_) | ExtractError::Unsupported(_) | ExtractError::AuthRequired(_)
+        ) =>
+        {
+            match try_fallback_providers(url, state, platform, timeout_secs, priority).await? {
+                Some(json_str) => {
+                    // Not cached, so yt-dlp is retried on the next request
+                    return serde_json::from_str(&json_str).map_err(|e| {
+                        error!("Fallback JSON parse error: {e}");
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(serde_json::json!({"error": "Failed to parse extraction result"})),
+                        )
+                            .into_response()
+                    });
+                }
+                None => Err(e),
+            }
+        }
+        other => other,
+    };
 
     match result {
-        Ok(Ok(Ok(json_str))) => {
+        Ok(json_str) => {
             let data: serde_json::Value = serde_json::from_str(&json_str).map_err(|e| {
                 error!("JSON parse error: {e}");
                 (
@@ -463,74 +1287,318 @@ async fn fetch_tiktok_data(
 
             // Cache the result
             if let Some(ref redis) = state.redis {
-                redis.set_metadata(url, &json_str, 300).await;
+                redis.set_metadata(url, &json_str, cache::METADATA_TTL_SECS).await;
             }
 
             Ok(data)
         }
-        Ok(Ok(Err(e))) => {
+        Err(e) => {
             // yt-dlp error
-            let (status, msg) = if e.starts_with("NOT_FOUND:") {
-                (
-                    StatusCode::NOT_FOUND,
+            let (status, msg) = match &e {
+                ExtractError::NotFound(_) => (
+                    e.status(),
                     "Video not found. Please check the URL and make sure the video exists.",
-                )
-            } else if e.starts_with("FORBIDDEN:") {
-                // Trigger VPN reconnect
-                warn!("403 Forbidden detected on {}, triggering VPN reconnect", state.settings.instance_id);
-                let _ = vpn::trigger_local_vpn_reconnect(
-                    &state.vpn_state,
-                    &state.settings.instance_id,
-                    state.settings.gluetun_control_port,
-                    &state.settings.gluetun_username,
-                    &state.settings.gluetun_password,
-                )
-                .await;
-                (
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    "Service temporarily unavailable due to IP block, retrying with different endpoint",
-                )
-            } else if e.starts_with("AUTH_REQUIRED:") {
-                (
-                    StatusCode::UNAUTHORIZED,
-                    "This content requires login/authentication",
-                )
-            } else if e.starts_with("UNSUPPORTED:") {
-                (StatusCode::BAD_REQUEST, "Unsupported or invalid URL")
-            } else {
-                error!("yt-dlp error: {e}");
-                (StatusCode::INTERNAL_SERVER_ERROR, "Extraction failed")
+                ),
+                ExtractError::Forbidden(_) => {
+                    // Trigger VPN reconnect
+                    warn!("403 Forbidden detected on {}, triggering VPN reconnect", state.settings.instance_id);
+                    let _ = reconnect_vpn(state).await;
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Service temporarily unavailable due to IP block, retrying with different endpoint",
+                    )
+                }
+                ExtractError::AuthRequired(_) => {
+                    (e.status(), "This content requires login/authentication")
+                }
+                ExtractError::Unsupported(_) => (e.status(), "Unsupported or invalid URL"),
+                ExtractError::InvalidFormat(_) => (e.status(), "No downloadable format available"),
+                ExtractError::Transient(_) => {
+                    warn!("yt-dlp transient error after {} attempts: {e}", attempt + 1);
+                    (e.status(), "Upstream temporarily unavailable, please try again")
+                }
+                ExtractError::Timeout(_) => (
+                    StatusCode::REQUEST_TIMEOUT,
+                    "Request timeout after extraction took too long",
+                ),
+                ExtractError::Failed(_) | ExtractError::Internal(_) => {
+                    error!("yt-dlp error: {e}");
+                    (e.status(), "Extraction failed")
+                }
             };
-            Err((status, Json(serde_json::json!({"error": msg}))).into_response())
-        }
-        Ok(Err(e)) => {
-            error!("Task join error: {e}");
             Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": "Internal server error"})),
+                status,
+                Json(serde_json::json!({"error": msg, "retryable": e.is_retryable()})),
             )
                 .into_response())
         }
-        Err(_) => {
-            // Timeout
-            Err((
-                StatusCode::REQUEST_TIMEOUT,
-                Json(serde_json::json!({"error": "Request timeout after extraction took too long"})),
+    }
+}
+
+/// Try each fallback provider once, in order, within one `timeout_secs`
+/// between them; first success wins.
+async fn try_fallback_providers(
+    url: &str,
+    state: &AppState,
+    platform: &'static str,
+    timeout_secs: u64,
+    priority: u8,
+) -> Result<Option<String>, axum::response::Response> {
+    let mut deadline = Deadline::new(timeout_secs);
+    for provider in state.providers.iter().skip(1) {
+        match extract_once(url, state, provider, platform, &mut deadline, priority).await? {
+            Ok(json_str) => {
+                warn!("Served {url} from fallback provider {}", provider.name());
+                state.metrics.record_outcome(platform, "fallback");
+                return Ok(Some(json_str));
+            }
+            Err(e) => warn!("Fallback provider {} failed: {e}", provider.name()),
+        }
+    }
+    Ok(None)
+}
+
+/// Time budget shared by the extraction attempts of one request: counted
+/// from when the first of them got a worker, so waiting in the queue doesn't
+/// use it up and a retry doesn't get a fresh one.
+struct Deadline {
+    secs: u64,
+    at: Option<tokio::time::Instant>,
+}
+
+impl Deadline {
+    fn new(secs: u64) -> Self {
+        Self { secs, at: None }
+    }
+
+    /// When the budget runs out, starting it now if no attempt ran yet.
+    fn start(&mut self) -> tokio::time::Instant {
+        *self.at.get_or_insert_with(|| tokio::time::Instant::now() + Duration::from_secs(self.secs))
+    }
+
+    fn passed(&self) -> bool {
+        self.at.is_some_and(|at| at <= tokio::time::Instant::now())
+    }
+}
+
+/// Run a single extraction: take an extraction queue slot at `priority` (429
+/// when full) and run the provider on the blocking pool, giving up at
+/// `deadline`.
+async fn extract_once(
+    url: &str,
+    state: &AppState,
+    provider: &Arc<dyn ExtractionProvider>,
+    platform: &'static str,
+    deadline: &mut Deadline,
+    priority: u8,
+) -> Result<Result<String, ExtractError>, axum::response::Response> {
+    let url_clone = url.to_string();
+    let provider = provider.clone();
+    let metrics = state.metrics.clone();
+    let job = state.jobs.register(JobKind::Extraction, platform, url);
+
+    // Reject immediately when every worker is busy and the queue is full
+    let ticket = match state.extraction_queue.enter(priority) {
+        Ok(t) => t,
+        Err(full) => {
+            state.metrics.record_outcome(platform, "saturated");
+            let retry_after = state.extraction_queue.retry_after_secs(
+                state.metrics.mean_extraction_secs().unwrap_or(5.0),
+            );
+            warn!(
+                "Extraction queue full ({} waiting), rejecting with Retry-After {retry_after}s",
+                full.queued
+            );
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                [("Retry-After", retry_after.to_string())],
+                Json(serde_json::json!({"error": "Server is busy, please retry later"})),
             )
-                .into_response())
+                .into_response());
+        }
+    };
+
+    let permit = tokio::select! {
+        permit = ticket.wait() => permit,
+        _ = job.cancelled() => {
+            info!("Extraction job for {url} cancelled by operator");
+            return Err(job_cancelled_response());
+        }
+    };
+    let timeout_secs = deadline.secs;
+    let at = deadline.start();
+    job.running();
+    // The permit moves into the blocking task so it is only released once
+    // yt-dlp actually returns, even if the request timed out first: yt-dlp
+    // can't be interrupted, and a retry mustn't run beside it.
+    let result = tokio::time::timeout_at(at, tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let started = std::time::Instant::now();
+        let result = provider.extract(&url_clone);
+        // Latency histogram tracks yt-dlp only
+        if provider.name() == "yt-dlp" {
+            metrics.observe_extraction(platform, started.elapsed());
+        }
+        result
+    }))
+    .await;
+
+    let result = match result {
+        Ok(Ok(r)) => r,
+        Ok(Err(e)) => {
+            error!("Task join error: {e}");
+            Err(ExtractError::Internal(format!("Task join error: {e}")))
+        }
+        Err(_) => Err(ExtractError::Timeout(format!(
+            "Extraction exceeded {timeout_secs}s"
+        ))),
+    };
+    if result.is_ok() {
+        job.done();
+    }
+    Ok(result)
+}
+
+fn job_cancelled_response() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(serde_json::json!({
+            "error": "Job cancelled by operator",
+            "code": "JOB_CANCELLED",
+        })),
+    )
+        .into_response()
+}
+
+/// Exponential backoff (base * 2^(attempt-1)) plus up to `base` ms of jitter.
+fn retry_backoff_ms(base_ms: u64, attempt: u32) -> u64 {
+    let jitter = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos() as u64
+        % base_ms.max(1);
+    base_ms.saturating_mul(1 << attempt.saturating_sub(1).min(10)) + jitter
+}
+
+/// Download a slideshow asset, reusing a recent download of the same URL.
+async fn download_asset(
+    state: &AppState,
+    job: &JobHandle,
+    url: &str,
+    path: &str,
+    headers: &[(String, String)],
+) -> Result<(), String> {
+    let Some(assets) = state.slideshow_assets.clone() else {
+        return fetch_asset(state, job, url, path, headers).await;
+    };
+    let (cache, key, dest) = (assets.clone(), url.to_string(), std::path::PathBuf::from(path));
+    let hit = tokio::task::spawn_blocking(move || cache.fetch(&key, &dest))
+        .await
+        .unwrap_or(false);
+    if hit {
+        state.metrics.record_cache("slideshow_asset", CacheResult::Hit);
+        return Ok(());
+    }
+    state.metrics.record_cache("slideshow_asset", CacheResult::Miss);
+
+    fetch_asset(state, job, url, path, headers).await?;
+    let (key, src) = (url.to_string(), std::path::PathBuf::from(path));
+    let _ = tokio::task::spawn_blocking(move || assets.store(&key, &src)).await;
+    state.metrics.record_cache("slideshow_asset", CacheResult::Set);
+    Ok(())
+}
+
+/// Download a slideshow asset from the CDN, through aria2c when configured.
+async fn fetch_asset(
+    state: &AppState,
+    job: &JobHandle,
+    url: &str,
+    path: &str,
+    headers: &[(String, String)],
+) -> Result<(), String> {
+    let Some(aria2) = state.aria2.clone() else {
+        let (url, path, headers) = (url.to_string(), path.to_string(), headers.to_vec());
+        let stop = job.abandoned();
+        return run_slideshow_io(state, job, move || {
+            slideshow::download_file(&url, &path, 120, &headers, &stop)
+        })
+        .await;
+    };
+
+    let _permit = slideshow_permit(state, job).await?;
+    let path = std::path::Path::new(path);
+    let (dir, out) = match (path.parent(), path.file_name()) {
+        (Some(d), Some(f)) => (d, f.to_string_lossy().to_string()),
+        _ => return Err(format!("Invalid download path: {}", path.display())),
+    };
+    let mut header_map = axum::http::HeaderMap::new();
+    for (k, v) in headers {
+        if let (Ok(name), Ok(value)) = (
+            axum::http::HeaderName::try_from(k.as_str()),
+            HeaderValue::from_str(v),
+        ) {
+            header_map.insert(name, value);
         }
     }
+    aria2.download(url, &header_map, dir, &out).await.map(|_| ())
+}
+
+/// Wait for a slideshow slot; the job shows as queued meanwhile and can be
+/// cancelled from /admin/jobs.
+async fn slideshow_permit(
+    state: &AppState,
+    job: &JobHandle,
+) -> Result<tokio::sync::OwnedSemaphorePermit, String> {
+    job.queued();
+    let permit = tokio::select! {
+        permit = state.slideshow_permits.clone().acquire_owned() => {
+            permit.map_err(|e| format!("Slideshow semaphore closed: {e}"))?
+        }
+        _ = job.cancelled() => return Err("Job cancelled by operator".into()),
+    };
+    job.running();
+    Ok(permit)
+}
+
+/// Run blocking slideshow work on the blocking pool, bounded by the slideshow semaphore
+/// so slow extractions can't starve slideshow IO (and vice versa).
+async fn run_slideshow_io<F, T>(state: &AppState, job: &JobHandle, f: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    let permit = slideshow_permit(state, job).await?;
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        f()
+    })
+    .await
+    .unwrap_or(Err("Task join error".into()))
 }
 
 // ============= Main =============
 
-#[tokio::main]
-async fn main() {
+fn main() {
     // Setup logging
     tracing_subscriber::fmt::init();
 
     let settings = Settings::from_env();
 
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .max_blocking_threads(settings.max_blocking_threads)
+        .build()
+        .expect("Failed to build Tokio runtime")
+        .block_on(run(settings));
+}
+
+async fn run(mut settings: Settings) {
+    // Every token is XORed with it; an empty key can't encrypt anything
+    if settings.encryption_key.is_empty() {
+        error!("❌ ENCRYPTION_KEY is empty");
+        std::process::exit(1);
+    }
+
     // Ensure temp directory exists
     std::fs::create_dir_all(&settings.temp_dir).ok();
 
@@ -542,6 +1610,23 @@ async fn main() {
         settings.instance_id, settings.instance_region
     );
 
+    // Warm up the embedded interpreter; a broken Python env is fatal at boot
+    let preload = settings.preload_extractors;
+    let warm_up = tokio::task::spawn_blocking(move || {
+        ytdlp::warm_up(preload).map(|version| (version, ytdlp::python_version()))
+    });
+    let (ytdlp_version, python_version) = match warm_up.await {
+        Ok(Ok(versions)) => versions,
+        Ok(Err(e)) => {
+            error!("❌ yt-dlp warm-up failed: {e}");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            error!("❌ yt-dlp warm-up task failed: {e}");
+            std::process::exit(1);
+        }
+    };
+
     // Initialize HTTP client with connection pooling
     let http_client = reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(settings.download_timeout))
@@ -551,8 +1636,26 @@ async fn main() {
         .build()
         .expect("Failed to create HTTP client");
 
-    // Initialize Redis
-    let redis = RedisCache::connect(&settings.redis_host, settings.redis_port).await;
+    // Initialize Redis (or the in-memory cache in standalone builds)
+    let metrics = Arc::new(Metrics::default());
+    let tasks = Arc::new(tasks::TaskManager::default());
+    let notifier = Arc::new(notify::Notifier::from_settings(&settings, http_client.clone(), &tasks));
+    notify::spawn_error_spike_task(&tasks, notifier.clone(), metrics.clone(), &settings);
+    #[cfg(feature = "redis")]
+    let redis = RedisCache::connect(
+        &settings.redis_host,
+        settings.redis_port,
+        &settings.redis_key_prefix,
+        settings.cache_ttl_jitter_pct,
+        metrics.clone(),
+    )
+    .await;
+    #[cfg(not(feature = "redis"))]
+    let redis = Some(RedisCache::in_memory(
+        &settings.redis_key_prefix,
+        settings.cache_ttl_jitter_pct,
+        metrics.clone(),
+    ));
 
     // Initialize VPN manager
     let vpn_manager = Arc::new(VpnManager::new(
@@ -561,15 +1664,104 @@ async fn main() {
     ));
 
     // Start cleanup scheduler
-    cleanup::spawn_cleanup_task(settings.temp_dir.to_string_lossy().to_string());
+    cleanup::spawn_cleanup_task(&tasks, settings.temp_dir.to_string_lossy().to_string());
+
+    // Start periodic yt_dlp import check (reported by /health)
+    let python_status = Arc::new(RwLock::new(PythonStatus {
+        ytdlp_version: Some(ytdlp_version),
+        python_version: Some(python_version),
+        ..Default::default()
+    }));
+    ytdlp::spawn_python_check_task(&tasks, python_status.clone());
+
+    let cookies = Arc::new(CookiePools::new(vec![
+        CookiePool::new("tiktok", &settings.cookies_paths, settings.cookie_cooldown_secs),
+        CookiePool::new("douyin", &settings.douyin_cookies_paths, settings.cookie_cooldown_secs).optional(),
+    ]));
+    for pool in cookies.iter() {
+        info!("🍪 {} {} cookie profile(s) loaded", pool.len(), pool.platform());
+        cookies::spawn_cookie_check_task(
+            &tasks,
+            pool.clone(),
+            metrics.clone(),
+            notifier.clone(),
+            settings.cookie_check_interval_secs,
+            settings.cookie_expiry_warn_days * 86_400,
+        );
+    }
+
+    let tenants = match tenants::Tenants::load(&mut settings) {
+        Ok(tenants) => Arc::new(tenants),
+        Err(e) => {
+            error!("❌ Invalid TENANTS_PATH: {e}");
+            std::process::exit(1);
+        }
+    };
+    if tenants.len() > 0 {
+        info!("🏷️ {} tenant profile(s) loaded", tenants.len());
+    }
+
+    let blocklist = Arc::new(Blocklist::new(&settings.blocklist_path));
+    blocklist::spawn_blocklist_reload_task(
+        &tasks,
+        blocklist.clone(),
+        settings.blocklist_reload_interval_secs,
+    );
+
+    let user_agents = Arc::new(UserAgentPool::from_settings(&settings));
+    let aria2 = aria2::Aria2::from_settings(&settings).await.map(Arc::new);
+    let slideshow_assets =
+        assets::AssetCache::new(&settings.temp_dir, settings.slideshow_asset_ttl_secs).map(Arc::new);
+    if let Some(ref cache) = slideshow_assets {
+        assets::spawn_sweep_task(&tasks, cache.clone());
+    }
 
     let state = AppState {
-        settings: settings.clone(),
+        settings: Arc::new(settings.clone()),
         http_client,
         redis,
         vpn_manager,
         vpn_state: Arc::new(Mutex::new(VpnReconnectState::default())),
+        python_status,
+        metrics,
+        extraction_queue: Arc::new(ExtractionQueue::new(
+            settings.max_workers,
+            settings.extraction_queue_size,
+            std::time::Duration::from_secs(settings.priority_aging_secs),
+        )),
+        slideshow_permits: Arc::new(Semaphore::new(settings.slideshow_workers)),
+        ffmpeg_queue: Arc::new(ExtractionQueue::new(
+            settings.ffmpeg_concurrency.max(1),
+            settings.ffmpeg_queue_size,
+            std::time::Duration::from_secs(settings.priority_aging_secs),
+        )),
+        slideshow_tickets: Arc::new(NamedTickets::default()),
+        jobs: Arc::new(JobRegistry::new(settings.job_history)),
+        streams: Arc::new(StreamRegistry::default()),
+        providers: Arc::new(providers::build_providers(
+            &settings,
+            cookies.clone(),
+            user_agents.clone(),
+        )),
+        user_agents,
+        aria2,
+        slideshow_assets,
+        sessions: session::SessionStore::new(
+            settings.slideshow_session_ttl_secs,
+            &settings.encryption_key,
+        )
+        .map(Arc::new),
+        cookies,
+        blocklist,
+        schedule: Arc::new(schedule::ScheduleStore::load(&settings.schedule_dir)),
+        watchers: Arc::new(watch::WatchStore::load(&settings.watchers_path)),
+        prewarm: Arc::new(prewarm::PrewarmRegistry::default()),
+        notifier,
+        tenants,
+        tasks: tasks.clone(),
     };
+    schedule::spawn_schedule_task(&tasks, state.clone());
+    watch::spawn_watch_task(&tasks, state.clone());
 
     // CORS
     let cors = CorsLayer::new()
@@ -577,6 +1769,8 @@ async fn main() {
         .allow_methods([
             axum::http::Method::GET,
             axum::http::Method::POST,
+            axum::http::Method::PUT,
+            axum::http::Method::DELETE,
             axum::http::Method::OPTIONS,
         ])
         .allow_headers(Any)
@@ -584,6 +1778,8 @@ async fn main() {
             "Content-Disposition".parse().unwrap(),
             "X-Filename".parse().unwrap(),
             "Content-Length".parse().unwrap(),
+            "X-Queue-Position".parse().unwrap(),
+            "X-Queue-Wait-Ms".parse().unwrap(),
         ]);
 
     // Router
@@ -591,17 +1787,116 @@ async fn main() {
         .route("/tiktok", post(tiktok_handler))
         .route("/download", get(download_handler))
         .route("/stream", get(stream_handler))
-        .route("/download-slideshow", get(slideshow_handler))
+        .route("/checksum", get(checksum_handler))
+        .route(
+            "/download-slideshow",
+            get(slideshow_handler)
+                .post(slideshow_upload_handler)
+                .layer(DefaultBodyLimit::max(
+                    (settings.slideshow_max_audio_mb + 1) * 1024 * 1024,
+                )),
+        )
+        .route("/slideshow/queue", get(slideshow_queue_handler))
+        .route("/slideshow/queue/{ticket}", get(slideshow_ticket_handler))
+        .route("/jobs", post(schedule::create_job_handler))
+        .route(
+            "/jobs/{id}",
+            get(schedule::get_job_handler).delete(schedule::cancel_job_handler),
+        )
+        .route("/jobs/{id}/file", get(schedule::job_file_handler))
+        .route("/feeds/{file}", get(feed::feed_handler))
+        .route("/oembed", get(oembed::oembed_handler))
+        .route("/s/{id}", get(shortlink::short_link_handler))
+        .route("/embed", get(oembed::embed_handler))
         .route("/health", get(health_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/admin/cookies", get(admin::cookies_handler))
+        .route("/admin/cookies/{platform}", put(admin::upload_cookies_handler))
+        .route("/admin/jobs", get(admin::jobs_handler))
+        .route("/admin/jobs/{id}", delete(admin::cancel_job_handler))
+        .route("/admin/streams", get(admin::streams_handler))
+        .route("/admin/streams/{id}", delete(admin::terminate_stream_handler))
+        .route("/admin/usage", get(admin::usage_handler))
+        .route(
+            "/admin/watchers",
+            get(watch::list_watchers_handler).post(watch::create_watcher_handler),
+        )
+        .route("/admin/watchers/{id}", delete(watch::delete_watcher_handler))
+        .route("/admin/prewarm", post(prewarm::prewarm_handler))
+        .route("/admin/prewarm/{id}", get(prewarm::get_prewarm_handler))
+        .route("/admin/cache/stats", get(admin::cache_stats_handler))
+        .route("/admin/blocklist", get(admin::blocklist_handler))
+        .route("/admin/blocklist/reload", post(admin::reload_blocklist_handler))
         .fallback(not_found_handler)
         .layer(cors)
         .with_state(state);
 
     let addr = format!("0.0.0.0:{}", settings.port);
     info!("🚀 serverrs listening on {addr}");
-    info!("   Runtime: Tokio (auto-managed thread pool)");
+    info!(
+        "   Runtime: Tokio (max {} blocking threads; {} extraction / {} slideshow download / {} FFmpeg workers)",
+        settings.max_blocking_threads,
+        settings.max_workers,
+        settings.slideshow_workers,
+        settings.ffmpeg_concurrency
+    );
     info!("   Extraction: yt-dlp via PyO3");
 
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Peer addresses identify clients not behind TRUSTED_PROXIES
+    let stopping = CancellationToken::new();
+    let signalled = stopping.clone();
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            signalled.cancel();
+        })
+        .into_future();
+    let mut server = std::pin::pin!(server);
+    // One SHUTDOWN_TIMEOUT_SECS budget covers draining requests and then
+    // background tasks, so a stuck stream cannot hold the process open
+    let deadline = tokio::select! {
+        result = &mut server => {
+            result.unwrap();
+            tokio::time::Instant::now() + Duration::from_secs(settings.shutdown_timeout_secs)
+        }
+        _ = stopping.cancelled() => {
+            let deadline =
+                tokio::time::Instant::now() + Duration::from_secs(settings.shutdown_timeout_secs);
+            match tokio::time::timeout_at(deadline, server).await {
+                Ok(result) => result.unwrap(),
+                Err(_) => warn!("Requests still running after SHUTDOWN_TIMEOUT_SECS, closing them"),
+            }
+            deadline
+        }
+    };
+    tasks
+        .shutdown(deadline.saturating_duration_since(tokio::time::Instant::now()))
+        .await;
+}
+
+/// Resolves on Ctrl+C or SIGTERM (docker stop).
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("Shutdown signal received, draining requests and background tasks");
 }
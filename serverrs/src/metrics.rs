@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the extraction duration histogram buckets.
+const DURATION_BUCKETS: [f64; 10] = [0.5, 1.0, 2.0, 3.0, 5.0, 10.0, 15.0, 20.0, 30.0, 45.0];
+
+/// Upper bounds (seconds) of the Redis cache GET latency histogram buckets.
+const CACHE_LATENCY_BUCKETS: [f64; 10] = [0.0005, 0.001, 0.002, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0];
+
+/// Upper bounds (seconds) of the slideshow FFmpeg run histogram buckets.
+const FFMPEG_BUCKETS: [f64; 9] = [1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0];
+
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (i, bound) in self.bounds.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[i] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile (None above the last bucket).
+    fn quantile_bound(&self, q: f64) -> Option<f64> {
+        let target = (self.count as f64 * q).ceil() as u64;
+        self.bounds
+            .iter()
+            .zip(&self.buckets)
+            .find(|(_, count)| **count >= target.max(1))
+            .map(|(bound, _)| *bound)
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (bound, count) in self.bounds.iter().zip(self.buckets.iter()) {
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+/// Result of a metadata/checksum cache operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CacheResult {
+    Hit,
+    Miss,
+    /// GET failed (Redis error); the request went on as a miss
+    GetError,
+    Set,
+    SetError,
+}
+
+impl CacheResult {
+    fn as_str(self) -> &'static str {
+        match self {
+            CacheResult::Hit => "hit",
+            CacheResult::Miss => "miss",
+            CacheResult::GetError => "get_error",
+            CacheResult::Set => "set",
+            CacheResult::SetError => "set_error",
+        }
+    }
+}
+
+/// (usable, login cookie expiry) of a cookie profile
+type CookieGauge = (bool, Option<u64>);
+
+/// In-process metrics, rendered in Prometheus text format by GET /metrics.
+#[derive(Default)]
+pub struct Metrics {
+    extraction_duration: Mutex<BTreeMap<String, Histogram>>,
+    extraction_outcomes: Mutex<BTreeMap<(String, String), u64>>,
+    cookie_profiles: Mutex<BTreeMap<(String, String), CookieGauge>>,
+    cache_operations: Mutex<BTreeMap<(&'static str, CacheResult), u64>>,
+    cache_get_latency: Mutex<BTreeMap<&'static str, Histogram>>,
+    ffmpeg_duration: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    /// Record how long a single extract_with_ytdlp() call took.
+    pub fn observe_extraction(&self, platform: &str, elapsed: Duration) {
+        let mut hists = self.extraction_duration.lock().unwrap();
+        hists
+            .entry(platform.to_string())
+            .or_insert_with(|| Histogram::new(&DURATION_BUCKETS))
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Count an extraction outcome: "success", "timeout" or an error class.
+    pub fn record_outcome(&self, platform: &str, outcome: &str) {
+        let mut outcomes = self.extraction_outcomes.lock().unwrap();
+        *outcomes
+            .entry((platform.to_string(), outcome.to_string()))
+            .or_default() += 1;
+    }
+
+    /// yt-dlp attempts and how many failed on our side (blocks, timeouts,
+    /// crashes), across platforms; not-found and unsupported URLs aren't
+    /// failures.
+    pub fn extraction_totals(&self) -> (u64, u64) {
+        let mut attempts = 0;
+        let mut failures = 0;
+        for ((_, outcome), count) in self.extraction_outcomes.lock().unwrap().iter() {
+            match outcome.as_str() {
+                "success" | "not_found" | "unsupported" => attempts += count,
+                "forbidden" | "auth_required" | "transient" | "timeout" | "extraction_failed" | "internal" => {
+                    attempts += count;
+                    failures += count;
+                }
+                // fallback, saturated, moderation_flagged: not yt-dlp attempts
+                _ => {}
+            }
+        }
+        (attempts, failures)
+    }
+
+    /// Latest cookie file check for a profile.
+    pub fn set_cookie_state(&self, platform: &str, profile: &str, valid: bool, expires_at: Option<u64>) {
+        let mut profiles = self.cookie_profiles.lock().unwrap();
+        profiles.insert((platform.to_string(), profile.to_string()), (valid, expires_at));
+    }
+
+    /// Count a cache operation on `cache` ("metadata", "checksum", "slideshow_asset" or "short_link").
+    pub fn record_cache(&self, cache: &'static str, result: CacheResult) {
+        *self.cache_operations.lock().unwrap().entry((cache, result)).or_default() += 1;
+    }
+
+    /// Record how long a cache GET took (hit, miss or error).
+    pub fn observe_cache_get(&self, cache: &'static str, elapsed: Duration) {
+        self.cache_get_latency
+            .lock()
+            .unwrap()
+            .entry(cache)
+            .or_insert_with(|| Histogram::new(&CACHE_LATENCY_BUCKETS))
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Record how long generating (and verifying) a slideshow took, by output extension.
+    pub fn observe_ffmpeg(&self, output: &'static str, elapsed: Duration) {
+        self.ffmpeg_duration
+            .lock()
+            .unwrap()
+            .entry(output)
+            .or_insert_with(|| Histogram::new(&FFMPEG_BUCKETS))
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Mean slideshow FFmpeg run across all outputs, if any were recorded.
+    pub fn mean_ffmpeg_secs(&self) -> Option<f64> {
+        let hists = self.ffmpeg_duration.lock().unwrap();
+        let (sum, count) = hists
+            .values()
+            .fold((0.0, 0u64), |(sum, count), h| (sum + h.sum, count + h.count));
+        (count > 0).then(|| sum / count as f64)
+    }
+
+    /// Per-cache counters, hit ratio and GET latency since startup
+    /// (GET /admin/cache/stats).
+    pub fn cache_stats(&self) -> serde_json::Value {
+        let ops = self.cache_operations.lock().unwrap();
+        let latency = self.cache_get_latency.lock().unwrap();
+        let mut caches: Vec<&'static str> = ops.keys().map(|(c, _)| *c).collect();
+        caches.dedup();
+
+        let mut stats = serde_json::Map::new();
+        for cache in caches {
+            let count = |r: CacheResult| ops.get(&(cache, r)).copied().unwrap_or(0);
+            let (hits, misses) = (count(CacheResult::Hit), count(CacheResult::Miss));
+            let lookups = hits + misses + count(CacheResult::GetError);
+            let mut entry = serde_json::json!({
+                "hits": hits,
+                "misses": misses,
+                "get_errors": count(CacheResult::GetError),
+                "sets": count(CacheResult::Set),
+                "set_failures": count(CacheResult::SetError),
+                "hit_ratio": (lookups > 0).then(|| hits as f64 / lookups as f64),
+            });
+            if let Some(h) = latency.get(cache).filter(|h| h.count > 0) {
+                entry["get_latency_ms"] = serde_json::json!({
+                    "count": h.count,
+                    "mean": h.sum / h.count as f64 * 1000.0,
+                    "p50_le": h.quantile_bound(0.5).map(|b| b * 1000.0),
+                    "p95_le": h.quantile_bound(0.95).map(|b| b * 1000.0),
+                    "p99_le": h.quantile_bound(0.99).map(|b| b * 1000.0),
+                });
+            }
+            stats.insert(cache.to_string(), entry);
+        }
+        serde_json::Value::Object(stats)
+    }
+
+    /// Mean extract_with_ytdlp() duration across all platforms, if any were recorded.
+    pub fn mean_extraction_secs(&self) -> Option<f64> {
+        let hists = self.extraction_duration.lock().unwrap();
+        let (sum, count) = hists
+            .values()
+            .fold((0.0, 0u64), |(sum, count), h| (sum + h.sum, count + h.count));
+        (count > 0).then(|| sum / count as f64)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP ytdlp_extraction_duration_seconds Duration of yt-dlp extract_info calls.\n");
+        out.push_str("# TYPE ytdlp_extraction_duration_seconds histogram\n");
+        for (platform, hist) in self.extraction_duration.lock().unwrap().iter() {
+            hist.render(
+                &mut out,
+                "ytdlp_extraction_duration_seconds",
+                &format!("platform=\"{platform}\""),
+            );
+        }
+
+        out.push_str("# HELP ytdlp_extractions_total Extraction attempts by outcome (success, timeout or error class).\n");
+        out.push_str("# TYPE ytdlp_extractions_total counter\n");
+        for ((platform, outcome), count) in self.extraction_outcomes.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "ytdlp_extractions_total{{platform=\"{platform}\",outcome=\"{outcome}\"}} {count}"
+            );
+        }
+
+        let cookie_profiles = self.cookie_profiles.lock().unwrap();
+        out.push_str("# HELP cookie_profile_valid Whether a cookie profile's file is present, parseable and not expired.\n");
+        out.push_str("# TYPE cookie_profile_valid gauge\n");
+        for ((platform, profile), (valid, _)) in cookie_profiles.iter() {
+            let _ = writeln!(
+                out,
+                "cookie_profile_valid{{platform=\"{platform}\",profile=\"{profile}\"}} {}",
+                u8::from(*valid)
+            );
+        }
+        out.push_str("# HELP cookie_profile_expiry_timestamp_seconds Earliest login cookie expiry of a cookie profile.\n");
+        out.push_str("# TYPE cookie_profile_expiry_timestamp_seconds gauge\n");
+        for ((platform, profile), (_, expires_at)) in cookie_profiles.iter() {
+            if let Some(t) = expires_at {
+                let _ = writeln!(
+                    out,
+                    "cookie_profile_expiry_timestamp_seconds{{platform=\"{platform}\",profile=\"{profile}\"}} {t}"
+                );
+            }
+        }
+
+        out.push_str("# HELP cache_operations_total Cache lookups and writes by result (hit, miss, get_error, set, set_error).\n");
+        out.push_str("# TYPE cache_operations_total counter\n");
+        for ((cache, result), count) in self.cache_operations.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "cache_operations_total{{cache=\"{cache}\",result=\"{}\"}} {count}",
+                result.as_str()
+            );
+        }
+        out.push_str("# HELP cache_get_duration_seconds Latency of Redis cache GETs.\n");
+        out.push_str("# TYPE cache_get_duration_seconds histogram\n");
+        for (cache, hist) in self.cache_get_latency.lock().unwrap().iter() {
+            hist.render(&mut out, "cache_get_duration_seconds", &format!("cache=\"{cache}\""));
+        }
+
+        out.push_str("# HELP slideshow_ffmpeg_duration_seconds Duration of slideshow FFmpeg runs (generation + verification).\n");
+        out.push_str("# TYPE slideshow_ffmpeg_duration_seconds histogram\n");
+        for (output, hist) in self.ffmpeg_duration.lock().unwrap().iter() {
+            hist.render(&mut out, "slideshow_ffmpeg_duration_seconds", &format!("output=\"{output}\""));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_histogram_and_outcomes() {
+        let metrics = Metrics::default();
+        metrics.observe_extraction("tiktok", Duration::from_millis(1500));
+        metrics.observe_extraction("tiktok", Duration::from_secs(60));
+        metrics.record_outcome("tiktok", "success");
+        metrics.record_outcome("tiktok", "timeout");
+        metrics.record_outcome("tiktok", "timeout");
+
+        let out = metrics.render();
+        assert!(out.contains("ytdlp_extraction_duration_seconds_bucket{platform=\"tiktok\",le=\"1\"} 0"));
+        assert!(out.contains("ytdlp_extraction_duration_seconds_bucket{platform=\"tiktok\",le=\"2\"} 1"));
+        assert!(out.contains("ytdlp_extraction_duration_seconds_bucket{platform=\"tiktok\",le=\"+Inf\"} 2"));
+        assert!(out.contains("ytdlp_extraction_duration_seconds_count{platform=\"tiktok\"} 2"));
+        assert!(out.contains("ytdlp_extractions_total{platform=\"tiktok\",outcome=\"timeout\"} 2"));
+        assert_eq!(metrics.extraction_totals(), (3, 2));
+    }
+
+    #[test]
+    fn test_cache_stats_hit_ratio_and_latency() {
+        let metrics = Metrics::default();
+        for _ in 0..3 {
+            metrics.record_cache("metadata", CacheResult::Hit);
+            metrics.observe_cache_get("metadata", Duration::from_micros(800));
+        }
+        metrics.record_cache("metadata", CacheResult::Miss);
+        metrics.observe_cache_get("metadata", Duration::from_millis(20));
+        metrics.record_cache("metadata", CacheResult::SetError);
+
+        let stats = metrics.cache_stats();
+        assert_eq!(stats["metadata"]["hits"], 3);
+        assert_eq!(stats["metadata"]["set_failures"], 1);
+        assert_eq!(stats["metadata"]["hit_ratio"], 0.75);
+        assert_eq!(stats["metadata"]["get_latency_ms"]["p50_le"], 1.0);
+        assert_eq!(stats["metadata"]["get_latency_ms"]["p99_le"], 25.0);
+
+        let out = metrics.render();
+        assert!(out.contains("cache_operations_total{cache=\"metadata\",result=\"hit\"} 3"));
+        assert!(out.contains("cache_get_duration_seconds_bucket{cache=\"metadata\",le=\"+Inf\"} 4"));
+    }
+}
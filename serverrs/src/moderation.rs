@@ -0,0 +1,104 @@
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::{platform_for_url, Settings};
+
+/// Moderation outcome for one extraction result.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verdict {
+    pub flagged: bool,
+    pub labels: Vec<String>,
+}
+
+/// What to do with flagged content (MODERATION_POLICY).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Refuse to generate download links
+    Block,
+    /// Serve as usual with a `moderation` field in the response
+    Tag,
+}
+
+impl Policy {
+    pub fn from_settings(settings: &Settings) -> Self {
+        match settings.moderation_policy.as_str() {
+            "tag" => Self::Tag,
+            _ => Self::Block,
+        }
+    }
+}
+
+/// POST the result's thumbnail and metadata to MODERATION_WEBHOOK_URL.
+///
+/// The webhook answers `{"flagged": bool, "labels": ["nsfw", ...]}`; a local
+/// classifier can be plugged in by exposing it behind the same contract.
+/// Returns `Ok(None)` when moderation is disabled.
+pub async fn check(
+    client: &reqwest::Client,
+    settings: &Settings,
+    data: &serde_json::Value,
+    url: &str,
+) -> Result<Option<Verdict>, String> {
+    if settings.moderation_webhook_url.is_empty() {
+        return Ok(None);
+    }
+
+    let payload = serde_json::json!({
+        "url": url,
+        "platform": platform_for_url(url),
+        "id": data["id"],
+        "title": data["title"],
+        "description": data["description"],
+        "uploader": data["uploader"],
+        "uploader_id": data["uploader_id"],
+        "thumbnail": data["thumbnail"],
+    });
+
+    let mut request = client
+        .post(&settings.moderation_webhook_url)
+        .timeout(Duration::from_secs(settings.moderation_timeout_secs))
+        .json(&payload);
+    if !settings.moderation_webhook_token.is_empty() {
+        request = request.bearer_auth(&settings.moderation_webhook_token);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("moderation webhook failed: {e}"))?;
+    if !response.status().is_success() {
+        return Err(format!("moderation webhook returned {}", response.status()));
+    }
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| format!("moderation webhook returned invalid JSON: {e}"))?;
+
+    let verdict = parse_verdict(&body);
+    if verdict.flagged {
+        info!("🚩 Moderation flagged {url}: {:?}", verdict.labels);
+    }
+    Ok(Some(verdict))
+}
+
+fn parse_verdict(body: &serde_json::Value) -> Verdict {
+    let labels = body["labels"]
+        .as_array()
+        .map(|l| {
+            l.iter()
+                .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    Verdict {
+        flagged: body["flagged"].as_bool().unwrap_or(false),
+        labels,
+    }
+}
+
+/// Log and decide what a webhook failure means for the request: `true` when
+/// the content should be served anyway (MODERATION_FAIL_OPEN).
+pub fn fail_open(settings: &Settings, err: &str) -> bool {
+    warn!("⚠️ {err}");
+    settings.moderation_fail_open
+}
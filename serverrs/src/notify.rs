@@ -0,0 +1,294 @@
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::config::Settings;
+use crate::cookies::{CookieFileCheck, CookieState};
+use crate::metrics::Metrics;
+use crate::schedule::{ScheduleStatus, ScheduledJob};
+use crate::tasks::{tick, TaskManager};
+use tokio_util::sync::CancellationToken;
+
+/// Embeds waiting to be posted; more than this while Discord is slow are dropped.
+const QUEUE_SIZE: usize = 100;
+
+const RED: u32 = 0xE74C3C;
+const ORANGE: u32 = 0xF39C12;
+const GREEN: u32 = 0x2ECC71;
+const BLUE: u32 = 0x3498DB;
+
+/// Posts embeds for operational events to a Discord webhook
+/// (DISCORD_WEBHOOK_URL). Events are queued and sent one at a time by a
+/// background task, honouring Discord's rate limit; a no-op when unset.
+/// On shutdown the task posts what is already queued, then stops.
+pub struct Notifier {
+    tx: Option<mpsc::Sender<Value>>,
+    /// DISCORD_EVENTS: vpn, cookies, error_spike, jobs
+    events: HashSet<String>,
+    instance_id: String,
+}
+
+impl Notifier {
+    pub fn from_settings(settings: &Settings, client: reqwest::Client, tasks: &TaskManager) -> Self {
+        let tx = (!settings.discord_webhook_url.is_empty()).then(|| {
+            let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+            let url = settings.discord_webhook_url.clone();
+            tasks.spawn("discord", move |cancel| post_loop(client, url, rx, cancel));
+            tx
+        });
+        Self {
+            tx,
+            events: settings.discord_events.iter().cloned().collect(),
+            instance_id: settings.instance_id.clone(),
+        }
+    }
+
+    pub fn enabled(&self, event: &str) -> bool {
+        self.tx.is_some() && self.events.contains(event)
+    }
+
+    fn send(&self, event: &str, mut embed: Value) {
+        if !self.enabled(event) {
+            return;
+        }
+        embed["footer"] = json!({ "text": format!("serverrs · {}", self.instance_id) });
+        embed["timestamp"] = json!(chrono::Utc::now().to_rfc3339());
+        if let Some(tx) = &self.tx {
+            if tx.try_send(embed).is_err() {
+                warn!("Discord notification queue full, dropping {event} event");
+            }
+        }
+    }
+
+    /// A VPN reconnect triggered by a 403, and whether Gluetun accepted it.
+    pub fn vpn_reconnect(&self, result: &Result<bool, String>) {
+        let (title, color, detail) = match result {
+            Ok(true) => ("🔄 VPN reconnected", BLUE, "Gluetun reconnect triggered after a 403".to_string()),
+            Ok(false) => return,
+            Err(e) => ("❌ VPN reconnect failed", RED, e.clone()),
+        };
+        self.send("vpn", json!({ "title": title, "description": detail, "color": color }));
+    }
+
+    /// A cookie profile whose check just turned expired, missing, invalid
+    /// or expiring.
+    pub fn cookie_state(&self, platform: &str, path: &str, check: &CookieFileCheck) {
+        let color = match check.state {
+            CookieState::Expiring => ORANGE,
+            _ => RED,
+        };
+        let mut fields = vec![
+            json!({ "name": "Platform", "value": platform, "inline": true }),
+            json!({ "name": "State", "value": format!("{:?}", check.state), "inline": true }),
+        ];
+        if let Some(at) = check.expires_at {
+            fields.push(json!({ "name": "Expires", "value": format!("<t:{at}:R>"), "inline": true }));
+        }
+        if let Some(ref e) = check.error {
+            fields.push(json!({ "name": "Error", "value": e }));
+        }
+        self.send(
+            "cookies",
+            json!({
+                "title": "🍪 Cookie profile needs attention",
+                "description": format!("`{path}`"),
+                "color": color,
+                "fields": fields,
+            }),
+        );
+    }
+
+    /// Extraction failures crossed (or fell back under) the spike threshold.
+    pub fn error_spike(&self, spike: bool, failures: u64, attempts: u64, window_secs: u64) {
+        let pct = failures * 100 / attempts.max(1);
+        let (title, color) = if spike {
+            ("🚨 Extraction error rate spike", RED)
+        } else {
+            ("✅ Extraction error rate back to normal", GREEN)
+        };
+        self.send(
+            "error_spike",
+            json!({
+                "title": title,
+                "description": format!("{failures}/{attempts} extractions failed ({pct}%) in the last {}m", window_secs / 60),
+                "color": color,
+            }),
+        );
+    }
+
+    /// A scheduled job finished, with the post's cover and stats.
+    pub fn job_finished(&self, job: &ScheduledJob) {
+        if !self.enabled("jobs") {
+            return;
+        }
+        self.send("jobs", job_embed(job));
+    }
+}
+
+fn job_embed(job: &ScheduledJob) -> Value {
+    let empty = Value::Null;
+    let result = job.result.as_ref().unwrap_or(&empty);
+    let done = job.status == ScheduleStatus::Done;
+    let title = result["title"].as_str().filter(|t| !t.is_empty()).unwrap_or("Scheduled job");
+    let mut embed = json!({
+        "title": truncate(title, 256),
+        "url": job.url,
+        "color": if done { GREEN } else { RED },
+        "description": match &job.error {
+            Some(e) => format!("Job `{}` failed: {}", job.id, truncate(e, 1000)),
+            None => format!("Job `{}` finished", job.id),
+        },
+    });
+    if let Some(author) = result["author"]["nickname"].as_str().filter(|a| !a.is_empty()) {
+        embed["author"] = json!({ "name": author });
+    }
+    if let Some(cover) = result["cover"].as_str().filter(|c| !c.is_empty()) {
+        embed["thumbnail"] = json!({ "url": cover });
+    }
+    let stats = &result["statistics"];
+    if stats.is_object() {
+        embed["fields"] = json!(
+            [("▶️ Views", "play_count"), ("❤️ Likes", "digg_count"), ("💬 Comments", "comment_count"), ("🔁 Shares", "share_count")]
+                .iter()
+                .map(|(name, key)| json!({ "name": name, "value": stats[key].as_i64().unwrap_or(0).to_string(), "inline": true }))
+                .collect::<Vec<_>>()
+        );
+    }
+    embed
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    match s.char_indices().nth(max) {
+        Some((i, _)) => format!("{}…", &s[..i]),
+        None => s.to_string(),
+    }
+}
+
+async fn post_loop(
+    client: reqwest::Client,
+    url: String,
+    mut rx: mpsc::Receiver<Value>,
+    cancel: CancellationToken,
+) {
+    let mut closing = false;
+    loop {
+        let embed = tokio::select! {
+            embed = rx.recv() => embed,
+            // Stop accepting events, then drain the queue
+            _ = cancel.cancelled(), if !closing => {
+                closing = true;
+                rx.close();
+                continue;
+            }
+        };
+        let Some(embed) = embed else {
+            break;
+        };
+        let body = json!({ "embeds": [embed] });
+        for _ in 0..3 {
+            match client.post(&url).timeout(Duration::from_secs(10)).json(&body).send().await {
+                Ok(resp) if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    let wait = resp
+                        .json::<Value>()
+                        .await
+                        .ok()
+                        .and_then(|v| v["retry_after"].as_f64())
+                        .unwrap_or(1.0);
+                    tokio::time::sleep(Duration::from_secs_f64(wait.clamp(0.1, 60.0))).await;
+                }
+                Ok(resp) => {
+                    if let Err(e) = resp.error_for_status() {
+                        warn!("Discord webhook rejected notification: {e}");
+                    }
+                    break;
+                }
+                Err(e) => {
+                    warn!("Discord webhook failed: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Whether the failure rate over a window is a spike: at least
+/// `min_attempts` extractions and `threshold_pct`% of them failing.
+fn is_spike(failures: u64, attempts: u64, threshold_pct: u64, min_attempts: u64) -> bool {
+    attempts >= min_attempts.max(1) && failures * 100 >= threshold_pct * attempts
+}
+
+/// Every ERROR_SPIKE_WINDOW_SECS, compare the extraction failures in the
+/// window against the threshold; notify when a spike starts and ends.
+pub fn spawn_error_spike_task(
+    tasks: &TaskManager,
+    notifier: Arc<Notifier>,
+    metrics: Arc<Metrics>,
+    settings: &Settings,
+) {
+    if !notifier.enabled("error_spike") {
+        return;
+    }
+    let window = settings.error_spike_window_secs.max(10);
+    let threshold_pct = settings.error_spike_threshold_pct;
+    let min_attempts = settings.error_spike_min_attempts;
+    tasks.spawn("error_spike", |cancel| async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(window));
+        interval.tick().await;
+        let (mut last_attempts, mut last_failures) = metrics.extraction_totals();
+        let mut spiking = false;
+        while tick(&mut interval, &cancel).await {
+            let (attempts, failures) = metrics.extraction_totals();
+            let (window_attempts, window_failures) = (attempts - last_attempts, failures - last_failures);
+            (last_attempts, last_failures) = (attempts, failures);
+            let spike = is_spike(window_failures, window_attempts, threshold_pct, min_attempts);
+            if spike != spiking {
+                spiking = spike;
+                notifier.error_spike(spike, window_failures, window_attempts, window);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spike_and_job_embed() {
+        assert!(is_spike(10, 20, 50, 20));
+        assert!(!is_spike(9, 20, 50, 20));
+        assert!(!is_spike(5, 5, 50, 20));
+
+        let job = ScheduledJob {
+            id: "job1".into(),
+            url: "https://www.tiktok.com/@creator/video/1".into(),
+            run_at: 0,
+            prefetch: false,
+            priority: 0,
+            timeout: None,
+            status: ScheduleStatus::Done,
+            created_at: 0,
+            finished_at: Some(1),
+            error: None,
+            result: Some(json!({
+                "title": "A video",
+                "cover": "https://p16.tiktokcdn.com/cover.jpg",
+                "author": {"nickname": "Creator"},
+                "statistics": {"play_count": 1200, "digg_count": 300, "comment_count": 12, "share_count": 4},
+            })),
+            file: None,
+            watch_id: None,
+            webhook_url: None,
+            client: None,
+        };
+        let embed = job_embed(&job);
+        assert_eq!(embed["thumbnail"]["url"], "https://p16.tiktokcdn.com/cover.jpg");
+        assert_eq!(embed["author"]["name"], "Creator");
+        assert_eq!(embed["fields"][0]["value"], "1200");
+        assert_eq!(embed["color"], GREEN);
+        assert_eq!(truncate("abcdef", 3), "abc…");
+    }
+}
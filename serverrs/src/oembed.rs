@@ -0,0 +1,226 @@
+use axum::extract::Query;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::platform_for_url;
+use crate::feed::escape;
+use crate::tenants::TenantState;
+use crate::AppState;
+
+/// Player size when the consumer sets no maxwidth/maxheight (TikTok's 9:16).
+const DEFAULT_WIDTH: u32 = 325;
+const DEFAULT_HEIGHT: u32 = 578;
+
+#[derive(Deserialize)]
+pub struct OEmbedQuery {
+    url: String,
+    maxwidth: Option<u32>,
+    maxheight: Option<u32>,
+    /// Only "json" is supported
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EmbedQuery {
+    url: String,
+}
+
+/// GET /oembed?url= — oEmbed 1.0 JSON for a TikTok/Douyin post, with an
+/// iframe of GET /embed as the player.
+pub async fn oembed_handler(
+    TenantState(state): TenantState,
+    headers: HeaderMap,
+    Query(query): Query<OEmbedQuery>,
+) -> Response {
+    if query.format.as_deref().is_some_and(|f| f != "json") {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(serde_json::json!({"error": "Only format=json is supported", "code": "FORMAT_NOT_SUPPORTED"})),
+        )
+            .into_response();
+    }
+    let url = query.url.trim();
+    let result = match extract(&state, &headers, url).await {
+        Ok(r) => r,
+        Err(resp) => return resp,
+    };
+    let (width, height) = fit(query.maxwidth, query.maxheight);
+    Json(oembed_json(&result, url, &state.settings.base_url, width, height)).into_response()
+}
+
+/// GET /embed?url= — minimal HTML player for the oEmbed iframe. Links are
+/// generated on each load, so the iframe keeps working after they expire.
+pub async fn embed_handler(
+    TenantState(state): TenantState,
+    headers: HeaderMap,
+    Query(query): Query<EmbedQuery>,
+) -> Response {
+    let result = match extract(&state, &headers, query.url.trim()).await {
+        Ok(r) => r,
+        Err(resp) => return resp,
+    };
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        render_player(&result),
+    )
+        .into_response()
+}
+
+/// The /tiktok response for `url` (cached extraction, blocklist, moderation).
+async fn extract(state: &AppState, headers: &HeaderMap, url: &str) -> Result<Value, Response> {
+    if let Some(resp) = crate::reject_unsupported_url(url) {
+        return Err(resp);
+    }
+    let timeout_secs = state.settings.extraction_timeout(None);
+    let priority = crate::request_priority(headers, state);
+    crate::process_url(state, url, timeout_secs, priority, false).await
+}
+
+/// Largest 9:16 player within maxwidth x maxheight, at most the default size.
+fn fit(maxwidth: Option<u32>, maxheight: Option<u32>) -> (u32, u32) {
+    let mut width = maxwidth.map_or(DEFAULT_WIDTH, |w| w.clamp(1, DEFAULT_WIDTH));
+    let mut height = width * DEFAULT_HEIGHT / DEFAULT_WIDTH;
+    if let Some(max) = maxheight.filter(|m| *m > 0 && height > *m) {
+        height = max;
+        width = (height * DEFAULT_WIDTH / DEFAULT_HEIGHT).max(1);
+    }
+    (width, height)
+}
+
+fn oembed_json(result: &Value, url: &str, base_url: &str, width: u32, height: u32) -> Value {
+    let is_photo = result["status"] == "picker";
+    let player = reqwest::Url::parse_with_params(&format!("{base_url}/embed"), &[("url", url)])
+        .map(|u| u.to_string())
+        .unwrap_or_default();
+    let html = format!(
+        "<iframe src=\"{}\" width=\"{width}\" height=\"{height}\" frameborder=\"0\" allow=\"autoplay; fullscreen\" allowfullscreen></iframe>",
+        escape(&player)
+    );
+    let platform = platform_for_url(url);
+    let mut body = serde_json::json!({
+        "version": "1.0",
+        // Photo posts embed an image/audio page rather than a plain video
+        "type": if is_photo { "rich" } else { "video" },
+        "title": text(&result["title"]),
+        "author_name": text(&result["author"]["nickname"]),
+        "provider_name": if platform == "douyin" { "Douyin" } else { "TikTok" },
+        "provider_url": base_url,
+        "html": html,
+        "width": width,
+        "height": height,
+    });
+    let unique_id = text(&result["author"]["unique_id"]);
+    if platform == "tiktok" && !unique_id.is_empty() {
+        body["author_url"] = Value::String(format!("https://www.tiktok.com/@{unique_id}"));
+    }
+    if let Some(thumbnail) = thumbnail(result) {
+        body["thumbnail_url"] = Value::String(thumbnail.to_string());
+    }
+    body
+}
+
+fn render_player(result: &Value) -> String {
+    let title = escape(text(&result["title"]));
+    let links = &result["download_link"];
+    let media = if result["status"] == "picker" {
+        let mut html = String::from("<div class=\"photos\">");
+        for link in links["no_watermark"].as_array().into_iter().flatten() {
+            if let Some(src) = link.as_str() {
+                html.push_str(&format!("<img src=\"{}\" alt=\"\">", escape(src)));
+            }
+        }
+        html.push_str("</div>");
+        if let Some(audio) = links["mp3"].as_str() {
+            html.push_str(&format!("<audio controls src=\"{}\"></audio>", escape(&inline(audio))));
+        }
+        html
+    } else {
+        let src = ["no_watermark_hd", "no_watermark", "watermark"]
+            .iter()
+            .find_map(|k| links[*k].as_str())
+            .unwrap_or("");
+        format!(
+            "<video controls playsinline poster=\"{}\" src=\"{}\"></video>",
+            escape(thumbnail(result).unwrap_or("")),
+            escape(&inline(src))
+        )
+    };
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title>\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <style>html,body{{margin:0;height:100%;background:#000}}\
+         video{{width:100%;height:100%;object-fit:contain}}\
+         .photos{{display:flex;overflow-x:auto;scroll-snap-type:x mandatory;height:calc(100% - 40px)}}\
+         .photos img{{flex:0 0 100%;object-fit:contain;scroll-snap-align:start}}\
+         audio{{width:100%;height:40px}}</style></head>\n<body>{media}</body></html>\n"
+    )
+}
+
+/// Cover, or the first photo of a photo post.
+fn thumbnail(result: &Value) -> Option<&str> {
+    result["cover"]
+        .as_str()
+        .filter(|c| !c.is_empty())
+        .or_else(|| result["photos"][0]["url"].as_str())
+}
+
+/// Played in the page rather than saved, whatever the link's default.
+fn inline(link: &str) -> String {
+    if link.is_empty() {
+        return String::new();
+    }
+    format!("{link}&disposition=inline")
+}
+
+fn text(v: &Value) -> &str {
+    v.as_str().unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oembed_json_and_player() {
+        assert_eq!(fit(None, None), (325, 578));
+        assert_eq!(fit(Some(1000), None), (325, 578));
+        assert_eq!(fit(Some(200), None), (200, 355));
+        assert_eq!(fit(None, Some(289)), (162, 289));
+
+        let result = serde_json::json!({
+            "status": "tunnel",
+            "title": "A <b>video</b>",
+            "cover": "https://p16.tiktokcdn.com/cover.jpg",
+            "author": {"nickname": "Creator", "unique_id": "creator"},
+            "download_link": {"no_watermark": "https://dl.example.com/stream?data=abc"},
+        });
+        let url = "https://www.tiktok.com/@creator/video/123?lang=en";
+        let body = oembed_json(&result, url, "https://dl.example.com", 325, 578);
+        assert_eq!(body["type"], "video");
+        assert_eq!(body["author_url"], "https://www.tiktok.com/@creator");
+        assert_eq!(body["thumbnail_url"], "https://p16.tiktokcdn.com/cover.jpg");
+        let html = body["html"].as_str().unwrap();
+        assert!(html.contains(
+            "src=\"https://dl.example.com/embed?url=https%3A%2F%2Fwww.tiktok.com%2F%40creator%2Fvideo%2F123%3Flang%3Den\""
+        ));
+
+        let page = render_player(&result);
+        assert!(page.contains("<title>A &lt;b&gt;video&lt;/b&gt;</title>"));
+        assert!(page.contains("src=\"https://dl.example.com/stream?data=abc&amp;disposition=inline\""));
+
+        let photos = serde_json::json!({
+            "status": "picker",
+            "photos": [{"type": "photo", "url": "https://p16.tiktokcdn.com/1.jpg"}],
+            "download_link": {"no_watermark": ["https://dl.example.com/download?data=i1"]},
+        });
+        let body = oembed_json(&photos, "https://www.douyin.com/note/1", "https://dl.example.com", 325, 578);
+        assert_eq!(body["type"], "rich");
+        assert_eq!(body["provider_name"], "Douyin");
+        assert_eq!(body["thumbnail_url"], "https://p16.tiktokcdn.com/1.jpg");
+        assert!(body.get("author_url").is_none());
+        assert!(render_player(&photos).contains("<img src=\"https://dl.example.com/download?data=i1\""));
+    }
+}
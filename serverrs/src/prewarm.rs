@@ -0,0 +1,285 @@
+use axum::extract::{Json, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::admin::reject_non_admin;
+use crate::blocklist::Subject;
+use crate::config::platform_for_url;
+use crate::AppState;
+
+/// Finished and running batches kept for GET /admin/prewarm/{id}.
+const MAX_BATCHES: usize = 50;
+/// Failures listed per batch; the `failed` counter keeps counting.
+const MAX_ERRORS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct PrewarmRequest {
+    urls: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrewarmError {
+    pub url: String,
+    pub error: String,
+}
+
+/// Progress of one POST /admin/prewarm call.
+#[derive(Debug, Clone, Serialize)]
+pub struct PrewarmBatch {
+    pub id: String,
+    pub total: usize,
+    /// Extracted and written to the metadata cache
+    pub warmed: usize,
+    /// Already cached, nothing to do
+    pub cached: usize,
+    pub failed: usize,
+    pub errors: Vec<PrewarmError>,
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+impl PrewarmBatch {
+    fn pending(&self) -> usize {
+        self.total - self.warmed - self.cached - self.failed
+    }
+}
+
+/// Recent pre-warm batches, newest last.
+#[derive(Default)]
+pub struct PrewarmRegistry {
+    batches: Mutex<VecDeque<PrewarmBatch>>,
+}
+
+impl PrewarmRegistry {
+    fn insert(&self, batch: PrewarmBatch) {
+        let mut batches = self.batches.lock().unwrap();
+        batches.push_back(batch);
+        while batches.len() > MAX_BATCHES {
+            batches.pop_front();
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<PrewarmBatch> {
+        let batches = self.batches.lock().unwrap();
+        batches.iter().find(|b| b.id == id).cloned()
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut PrewarmBatch)) {
+        let mut batches = self.batches.lock().unwrap();
+        if let Some(batch) = batches.iter_mut().find(|b| b.id == id) {
+            f(batch);
+        }
+    }
+}
+
+enum Outcome {
+    Warmed,
+    Cached,
+    Failed(String),
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+fn batch_view(batch: &PrewarmBatch) -> serde_json::Value {
+    let mut view = serde_json::to_value(batch).unwrap();
+    view["pending"] = batch.pending().into();
+    view
+}
+
+/// POST /admin/prewarm — extract a list of URLs in the background (at most
+/// PREWARM_CONCURRENCY at once) so later /tiktok requests hit the cache.
+pub async fn prewarm_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<PrewarmRequest>,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+    if state.redis.is_none() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": "Caching is disabled (Redis unavailable)",
+                "code": "CACHE_DISABLED",
+            })),
+        )
+            .into_response();
+    }
+
+    let mut seen = HashSet::new();
+    let mut urls = Vec::new();
+    let mut rejected = Vec::new();
+    for url in req.urls.iter().map(|u| u.trim()) {
+        if !seen.insert(url.to_string()) {
+            continue;
+        }
+        if crate::reject_unsupported_url(url).is_some() {
+            rejected.push(PrewarmError {
+                url: url.to_string(),
+                error: "Only TikTok and Douyin URLs are supported".into(),
+            });
+        } else {
+            urls.push(url.to_string());
+        }
+    }
+    if urls.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "No supported URLs to pre-warm".into());
+    }
+    let max_urls = state.settings.prewarm_max_urls;
+    if urls.len() > max_urls {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("At most {max_urls} URLs per pre-warm request"),
+        );
+    }
+
+    let now = unix_now();
+    let mut hasher = Md5::new();
+    hasher.update(format!("{now}:{}", urls.join("\n")).as_bytes());
+    let batch = PrewarmBatch {
+        id: format!("{:x}", hasher.finalize())[..16].to_string(),
+        total: urls.len(),
+        warmed: 0,
+        cached: 0,
+        failed: 0,
+        errors: Vec::new(),
+        created_at: now,
+        finished_at: None,
+    };
+    info!("🔥 Pre-warming {} URL(s) ({})", urls.len(), batch.id);
+    state.prewarm.insert(batch.clone());
+    let (batch_state, id) = (state.clone(), batch.id.clone());
+    state
+        .tasks
+        .spawn("prewarm", move |cancel| run_batch(batch_state, id, urls, cancel));
+
+    let mut body = batch_view(&batch);
+    body["rejected"] = serde_json::to_value(rejected).unwrap();
+    (StatusCode::ACCEPTED, Json(body)).into_response()
+}
+
+/// GET /admin/prewarm/{id} — progress of a pre-warm batch
+pub async fn get_prewarm_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+    match state.prewarm.get(&id) {
+        Some(batch) => (StatusCode::OK, Json(batch_view(&batch))).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, "Pre-warm batch not found".into()),
+    }
+}
+
+/// Warm `urls` a few at a time; on shutdown no new URL is started and the
+/// batch ends once those in flight finish.
+async fn run_batch(state: AppState, id: String, urls: Vec<String>, cancel: CancellationToken) {
+    let permits = Arc::new(Semaphore::new(state.settings.prewarm_concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for url in urls {
+        let permit = tokio::select! {
+            _ = cancel.cancelled() => break,
+            permit = permits.clone().acquire_owned() => permit.unwrap(),
+        };
+        let state = state.clone();
+        let id = id.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let outcome = warm_one(&state, &url).await;
+            state.prewarm.update(&id, |b| match outcome {
+                Outcome::Warmed => b.warmed += 1,
+                Outcome::Cached => b.cached += 1,
+                Outcome::Failed(error) => {
+                    b.failed += 1;
+                    if b.errors.len() < MAX_ERRORS {
+                        b.errors.push(PrewarmError { url, error });
+                    }
+                }
+            });
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+
+    state.prewarm.update(&id, |b| {
+        b.finished_at = Some(unix_now());
+        info!(
+            "🔥 Pre-warm {} done: {} warmed, {} already cached, {} failed",
+            b.id, b.warmed, b.cached, b.failed
+        );
+    });
+}
+
+/// Extract one URL into the metadata cache. Disabled platforms and blocked
+/// URLs are skipped; runs at the lowest queue priority so live traffic wins.
+async fn warm_one(state: &AppState, url: &str) -> Outcome {
+    let platform = platform_for_url(url);
+    if !state.settings.features.platform_enabled(platform) {
+        return Outcome::Failed(format!("{platform} is disabled"));
+    }
+    if let Some(hit) = state.blocklist.check(&Subject::from_url(url)) {
+        return Outcome::Failed(format!("Blocked ({})", hit.code));
+    }
+    if let Some(ref redis) = state.redis {
+        if redis.has_metadata(url).await {
+            return Outcome::Cached;
+        }
+    }
+
+    let timeout_secs = state.settings.extraction_timeout(None);
+    match crate::fetch_tiktok_data(url, state, timeout_secs, 0).await {
+        Ok(_) => Outcome::Warmed,
+        Err(resp) => Outcome::Failed(format!("HTTP {}", resp.status().as_u16())),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_updates_and_keeps_recent_batches() {
+        let registry = PrewarmRegistry::default();
+        for i in 0..MAX_BATCHES + 1 {
+            registry.insert(PrewarmBatch {
+                id: i.to_string(),
+                total: 3,
+                warmed: 0,
+                cached: 0,
+                failed: 0,
+                errors: Vec::new(),
+                created_at: 0,
+                finished_at: None,
+            });
+        }
+        assert!(registry.get("0").is_none());
+
+        registry.update("1", |b| {
+            b.warmed += 1;
+            b.cached += 1;
+        });
+        let batch = registry.get("1").unwrap();
+        assert_eq!(batch.pending(), 1);
+        assert_eq!(batch_view(&batch)["pending"], 1);
+    }
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::config::{platform_for_url, Settings};
+use crate::cookies::CookiePools;
+use crate::user_agents::UserAgentPool;
+use crate::error::ExtractError;
+use crate::ytdlp;
+
+/// A source of yt-dlp-shaped info JSON for a URL. The first configured
+/// provider is yt-dlp; the rest are fallbacks tried when it breaks.
+pub trait ExtractionProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Blocking — call from spawn_blocking.
+    fn extract(&self, url: &str) -> Result<String, ExtractError>;
+}
+
+/// yt_dlp.YoutubeDL.extract_info() via PyO3, using the URL's platform's
+/// active cookie profile and moving on to the next one when the cookies are
+/// the problem.
+pub struct YtDlpProvider {
+    cookies: Arc<CookiePools>,
+    user_agents: Arc<UserAgentPool>,
+    settings: Settings,
+}
+
+impl ExtractionProvider for YtDlpProvider {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    fn extract(&self, url: &str) -> Result<String, ExtractError> {
+        let mut headers = Vec::new();
+        if let Some(ua) = self.user_agents.next() {
+            headers.push(("User-Agent".to_string(), ua));
+        }
+        let platform = platform_for_url(url);
+        headers.extend(self.settings.headers_for(platform));
+
+        let Some(cookies) = self.cookies.get(platform) else {
+            return ytdlp::extract_with_ytdlp(url, None, &headers);
+        };
+        let mut attempts = cookies.len().max(1);
+        loop {
+            let Some((idx, path)) = cookies.checkout() else {
+                return ytdlp::extract_with_ytdlp(url, None, &headers);
+            };
+            let result =
+                ytdlp::extract_with_ytdlp(url, Some(&path.to_string_lossy()), &headers);
+            attempts -= 1;
+            if !cookies.report(idx, &result) || attempts == 0 {
+                return result;
+            }
+        }
+    }
+}
+
+/// TikTok's public oEmbed API for the metadata (title, author, thumbnail),
+/// plus the embed player's play address as the `watermark` download, so the
+/// API keeps answering when the yt-dlp extractor is broken. Requests go
+/// through the async client on the server's runtime; `extract` blocks on
+/// them from the blocking pool.
+pub struct TikTokOEmbedProvider {
+    client: reqwest::Client,
+    runtime: tokio::runtime::Handle,
+}
+
+impl TikTokOEmbedProvider {
+    /// Call from within the Tokio runtime.
+    pub fn new(timeout_secs: u64) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(timeout_secs))
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {e}"))?;
+        let runtime = tokio::runtime::Handle::try_current().map_err(|e| format!("No Tokio runtime: {e}"))?;
+        Ok(Self { client, runtime })
+    }
+
+    async fn fetch(&self, url: &str) -> Result<String, ExtractError> {
+        let response = self
+            .client
+            .get("https://www.tiktok.com/oembed")
+            .query(&[("url", url)])
+            .send()
+            .await
+            .map_err(|e| ExtractError::Transient(format!("oEmbed request failed: {e}")))?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::BAD_REQUEST {
+            return Err(ExtractError::NotFound(format!("oEmbed returned {status}")));
+        }
+        if !status.is_success() {
+            return Err(ExtractError::classify(format!("oEmbed HTTP Error {status}")));
+        }
+
+        let oembed: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| ExtractError::Failed(format!("Invalid oEmbed response: {e}")))?;
+        let Some(id) = oembed["embed_product_id"].as_str() else {
+            return Err(ExtractError::Failed("oEmbed response has no video id".into()));
+        };
+        let play_url = self.embed_play_addr(id).await?;
+        Ok(oembed_to_info(&oembed, url, &play_url).to_string())
+    }
+
+    /// Play address of the video in TikTok's embed player page.
+    async fn embed_play_addr(&self, id: &str) -> Result<String, ExtractError> {
+        let response = self
+            .client
+            .get(format!("https://www.tiktok.com/embed/v2/{id}"))
+            .send()
+            .await
+            .map_err(|e| ExtractError::Transient(format!("Embed page request failed: {e}")))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ExtractError::classify(format!("Embed page HTTP Error {status}")));
+        }
+        let html = response
+            .text()
+            .await
+            .map_err(|e| ExtractError::Transient(format!("Embed page read failed: {e}")))?;
+        play_addr(&html).ok_or_else(|| ExtractError::Failed("No playAddr in the embed page".into()))
+    }
+}
+
+impl ExtractionProvider for TikTokOEmbedProvider {
+    fn name(&self) -> &'static str {
+        "tiktok_oembed"
+    }
+
+    fn extract(&self, url: &str) -> Result<String, ExtractError> {
+        self.runtime.block_on(self.fetch(url))
+    }
+}
+
+/// First `"playAddr":"..."` JSON string in the embed page, unescaped.
+fn play_addr(html: &str) -> Option<String> {
+    let start = html.find("\"playAddr\":\"")? + "\"playAddr\":".len();
+    let rest = &html[start..];
+    // Closing quote: the first one not escaped by a backslash
+    let mut escaped = false;
+    let end = rest[1..].char_indices().find_map(|(i, c)| {
+        let closes = c == '"' && !escaped;
+        escaped = c == '\\' && !escaped;
+        closes.then_some(i + 2)
+    })?;
+    serde_json::from_str::<String>(&rest[..end]).ok().filter(|u| u.starts_with("http"))
+}
+
+/// Map an oEmbed response and the embed player's play address onto the yt-dlp
+/// info fields the response builder reads. The play address is the
+/// watermarked `download` format, the only media the embed exposes.
+fn oembed_to_info(oembed: &serde_json::Value, url: &str, play_url: &str) -> serde_json::Value {
+    let thumbnail = oembed["thumbnail_url"].as_str().unwrap_or("");
+    serde_json::json!({
+        "id": oembed["embed_product_id"],
+        "title": oembed["title"],
+        "uploader": oembed["author_name"],
+        "uploader_id": oembed["author_unique_id"],
+        "thumbnail": thumbnail,
+        "thumbnails": [{"url": thumbnail}],
+        "webpage_url": url,
+        "extractor": "tiktok_oembed",
+        "formats": [{"format_id": "download", "url": play_url, "ext": "mp4", "format_note": "watermarked"}],
+    })
+}
+
+/// Build the provider chain: yt-dlp first, then FALLBACK_PROVIDERS in order.
+pub fn build_providers(
+    settings: &Settings,
+    cookies: Arc<CookiePools>,
+    user_agents: Arc<UserAgentPool>,
+) -> Vec<Arc<dyn ExtractionProvider>> {
+    let mut providers: Vec<Arc<dyn ExtractionProvider>> = vec![Arc::new(YtDlpProvider {
+        cookies,
+        user_agents,
+        settings: settings.clone(),
+    })];
+
+    for name in &settings.fallback_providers {
+        match name.as_str() {
+            "tiktok_oembed" => match TikTokOEmbedProvider::new(settings.ytdlp_timeout) {
+                Ok(p) => providers.push(Arc::new(p)),
+                Err(e) => warn!("Skipping fallback provider {name}: {e}"),
+            },
+            other => warn!("Unknown fallback provider: {other}"),
+        }
+    }
+
+    providers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oembed_to_info() {
+        let oembed = serde_json::json!({
+            "title": "funny cat",
+            "author_name": "Cat Person",
+            "author_unique_id": "catperson",
+            "thumbnail_url": "https://p16.tiktokcdn.com/thumb.jpg",
+            "embed_product_id": "7300000000000000000"
+        });
+        let info = oembed_to_info(&oembed, "https://www.tiktok.com/@catperson/video/7300000000000000000", "https://v16.tiktokcdn.com/play.mp4");
+        assert_eq!(info["id"], "7300000000000000000");
+        assert_eq!(info["uploader_id"], "catperson");
+        assert_eq!(info["thumbnails"][0]["url"], "https://p16.tiktokcdn.com/thumb.jpg");
+        assert_eq!(info["formats"][0]["format_id"], "download");
+        assert_eq!(info["formats"][0]["url"], "https://v16.tiktokcdn.com/play.mp4");
+
+        let html = r#"<script>{"video":{"playAddr":"https:\u002F\u002Fv16.tiktokcdn.com\u002Fplay.mp4?a=\"b\"","cover":"x"}}</script>"#;
+        assert_eq!(play_addr(html).as_deref(), Some("https://v16.tiktokcdn.com/play.mp4?a=\"b\""));
+        assert_eq!(play_addr(r#"{"playAddr":""}"#), None);
+        assert_eq!(play_addr("<html></html>"), None);
+    }
+}
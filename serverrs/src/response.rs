@@ -1,7 +1,7 @@
 use serde::Serialize;
 use serde_json::Value;
 
-use crate::config::Settings;
+use crate::config::{platform_for_url, Settings};
 use crate::encryption::encrypt;
 
 #[derive(Serialize)]
@@ -19,6 +19,40 @@ pub struct AuthorInfo {
     pub avatar_larger: String,
 }
 
+/// Values copied into every download/stream token of one result.
+struct LinkContext<'a> {
+    author: &'a str,
+    platform: &'a str,
+    /// Checked against the blocklist when the token is served
+    video_id: &'a str,
+    creator: &'a str,
+}
+
+impl<'a> LinkContext<'a> {
+    fn new(data: &'a Value, url: &str, author: &'a str) -> Self {
+        Self {
+            author,
+            platform: platform_for_url(url),
+            video_id: data["id"].as_str().unwrap_or(""),
+            creator: data["uploader"].as_str().unwrap_or(""),
+        }
+    }
+}
+
+/// One entry of the optional `formats` catalog.
+#[derive(Serialize)]
+pub struct FormatEntry {
+    pub format_id: String,
+    /// video, audio or image
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub quality: String,
+    pub resolution: String,
+    pub size_bytes: Option<i64>,
+    /// Encrypted /stream (video, audio) or /download (image) link
+    pub url: String,
+}
+
 #[derive(Serialize)]
 pub struct Statistics {
     pub play_count: i64,
@@ -30,18 +64,7 @@ pub struct Statistics {
 /// Generate JSON response matching serverpy format.
 /// Returns a serde_json::Value with status "picker" (images) or "tunnel" (video).
 pub fn generate_json_response(data: &Value, url: &str, settings: &Settings) -> Value {
-    let formats = data["formats"].as_array();
-
-    let is_image = formats
-        .map(|fmts| {
-            fmts.iter().any(|f| {
-                f["format_id"]
-                    .as_str()
-                    .unwrap_or("")
-                    .starts_with("image-")
-            })
-        })
-        .unwrap_or(false);
+    let is_image = is_image_post(data);
 
     // Extract author info
     let avatar_url = data["thumbnails"]
@@ -51,7 +74,7 @@ pub fn generate_json_response(data: &Value, url: &str, settings: &Settings) -> V
         .unwrap_or("")
         .to_string();
 
-    let nickname = str_or(data, "uploader", str_or(data, "channel", "unknown".into()));
+    let nickname = author_name(data);
     let unique_id = str_or(data, "uploader_id", nickname.clone());
 
     let author = AuthorInfo {
@@ -90,18 +113,34 @@ pub fn generate_json_response(data: &Value, url: &str, settings: &Settings) -> V
         "author": serde_json::to_value(&author).unwrap(),
     });
 
+    let ctx = LinkContext::new(data, url, &author.nickname);
     if is_image {
-        build_image_response(&mut base, data, url, &author.nickname, settings)
+        build_image_response(&mut base, data, url, &ctx, settings)
     } else {
-        build_video_response(&mut base, data, &author.nickname, settings)
+        build_video_response(&mut base, data, &ctx, settings)
     }
 }
 
+/// Whether yt-dlp returned a photo post (image-* formats) rather than a video.
+pub fn is_image_post(data: &Value) -> bool {
+    data["formats"]
+        .as_array()
+        .map(|fmts| {
+            fmts.iter().any(|f| {
+                f["format_id"]
+                    .as_str()
+                    .unwrap_or("")
+                    .starts_with("image-")
+            })
+        })
+        .unwrap_or(false)
+}
+
 fn build_image_response(
     base: &mut Value,
     data: &Value,
     url: &str,
-    author_nickname: &str,
+    ctx: &LinkContext,
     settings: &Settings,
 ) -> Value {
     let formats = data["formats"].as_array().unwrap();
@@ -136,19 +175,7 @@ fn build_image_response(
     // Create encrypted download links for images
     let encrypted_image_urls: Vec<Value> = image_formats
         .iter()
-        .map(|img| {
-            let payload = serde_json::json!({
-                "url": img["url"].as_str().unwrap_or(""),
-                "author": author_nickname,
-                "type": "image"
-            });
-            let encrypted = encrypt(
-                &payload.to_string(),
-                &settings.encryption_key,
-                Some(360),
-            );
-            Value::String(format!("{}/download?data={encrypted}", settings.base_url))
-        })
+        .map(|img| Value::String(gen_image_link(img, data, ctx, settings)))
         .collect();
 
     let mut download_link = serde_json::json!({
@@ -156,7 +183,8 @@ fn build_image_response(
     });
 
     // Audio download link
-    if let Some(af) = audio_format {
+    let audio_enabled = settings.features.enabled(ctx.platform, "audio");
+    if let Some(af) = audio_format.filter(|_| audio_enabled) {
         let mut audio_stream_headers = serde_json::Map::new();
         if let Some(headers) = af["http_headers"].as_object() {
             for (k, v) in headers {
@@ -170,9 +198,12 @@ fn build_image_response(
 
         let payload = serde_json::json!({
             "url": af["url"].as_str().unwrap_or(""),
-            "author": author_nickname,
+            "author": ctx.author,
             "filesize": af["filesize"].as_i64().unwrap_or(0),
             "http_headers": Value::Object(audio_stream_headers),
+            "platform": ctx.platform,
+            "video_id": ctx.video_id,
+            "creator": ctx.creator,
             "type": "mp3"
         });
         let encrypted = encrypt(
@@ -186,9 +217,11 @@ fn build_image_response(
     base["download_link"] = download_link;
 
     // Slideshow download link
-    let encrypted_url = encrypt(url, &settings.encryption_key, Some(360));
-    base["download_slideshow_link"] =
-        Value::String(format!("{}/download-slideshow?url={encrypted_url}", settings.base_url));
+    if settings.features.enabled(ctx.platform, "slideshow") {
+        let encrypted_url = encrypt(url, &settings.encryption_key, Some(360));
+        base["download_slideshow_link"] =
+            Value::String(format!("{}/download-slideshow?url={encrypted_url}", settings.base_url));
+    }
 
     let mut result = serde_json::json!({ "status": "picker", "photos": picker });
     // Merge base into result
@@ -203,7 +236,7 @@ fn build_image_response(
 fn build_video_response(
     base: &mut Value,
     data: &Value,
-    author_nickname: &str,
+    ctx: &LinkContext,
     settings: &Settings,
 ) -> Value {
     let empty_vec = Vec::new();
@@ -254,31 +287,31 @@ fn build_video_response(
 
     let mut download_link = serde_json::Map::new();
 
-    if let Some(df) = download_format {
-        if let Some(link) = gen_stream_link(df, author_nickname, "video", settings) {
+    if let Some(df) = download_format.filter(|_| settings.watermark_links) {
+        if let Some(link) = gen_stream_link(df, ctx, "video", settings) {
             download_link.insert("watermark".to_string(), Value::String(link));
         }
     }
 
     if let Some(sd) = sd_formats.first() {
-        if let Some(link) = gen_stream_link(sd, author_nickname, "video", settings) {
+        if let Some(link) = gen_stream_link(sd, ctx, "video", settings) {
             download_link.insert("no_watermark".to_string(), Value::String(link));
         }
     }
 
     if let Some(hd) = hd_formats.first() {
-        if let Some(link) = gen_stream_link(hd, author_nickname, "video", settings) {
+        if let Some(link) = gen_stream_link(hd, ctx, "video", settings) {
             download_link.insert("no_watermark_hd".to_string(), Value::String(link));
         }
-        if hd_formats.len() > 1 {
-            if let Some(link) = gen_stream_link(hd_formats[1], author_nickname, "video", settings) {
+        if hd_formats.len() > 1 && settings.watermark_links {
+            if let Some(link) = gen_stream_link(hd_formats[1], ctx, "video", settings) {
                 download_link.insert("watermark_hd".to_string(), Value::String(link));
             }
         }
     }
 
-    if let Some(af) = audio_format {
-        if let Some(link) = gen_stream_link(af, author_nickname, "mp3", settings) {
+    if let Some(af) = audio_format.filter(|_| settings.features.enabled(ctx.platform, "audio")) {
+        if let Some(link) = gen_stream_link(af, ctx, "mp3", settings) {
             download_link.insert("mp3".to_string(), Value::String(link));
         }
     }
@@ -294,10 +327,99 @@ fn build_video_response(
     result
 }
 
+/// Every video, audio and image format of a result with its own encrypted
+/// link — the `formats` array for clients offering a quality picker. Videos
+/// come best first; audio is left out when the platform's audio is disabled.
+pub fn format_catalog(data: &Value, url: &str, settings: &Settings) -> Vec<FormatEntry> {
+    let author = author_name(data);
+    let ctx = LinkContext::new(data, url, &author);
+    let audio_enabled = settings.features.enabled(ctx.platform, "audio");
+    let empty_vec = Vec::new();
+    let formats = data["formats"].as_array().unwrap_or(&empty_vec);
+
+    let mut videos = Vec::new();
+    let mut audios = Vec::new();
+    let mut images = Vec::new();
+    for f in formats.iter().filter(|f| f["url"].is_string()) {
+        let format_id = f["format_id"].as_str().unwrap_or("");
+        let vcodec = f["vcodec"].as_str().unwrap_or("none");
+        let acodec = f["acodec"].as_str().unwrap_or("none");
+        let audio_only = acodec != "none" && (vcodec == "none" || vcodec.is_empty());
+        if format_id.starts_with("image-") {
+            images.push(f);
+        } else if vcodec != "none" && acodec != "none" {
+            videos.push(f);
+        } else if (format_id == "audio" || audio_only) && audio_enabled {
+            audios.push(f);
+        }
+    }
+    videos.sort_by_key(|f| {
+        std::cmp::Reverse(f["height"].as_i64().unwrap_or(0) * f["width"].as_i64().unwrap_or(0))
+    });
+
+    let entry = |f: &Value, kind: &'static str, quality: String, url: Option<String>| {
+        let width = f["width"].as_i64().unwrap_or(0);
+        let height = f["height"].as_i64().unwrap_or(0);
+        Some(FormatEntry {
+            format_id: f["format_id"].as_str().unwrap_or("").to_string(),
+            kind,
+            quality,
+            resolution: match kind {
+                "audio" => "audio only".into(),
+                _ if width > 0 && height > 0 => format!("{width}x{height}"),
+                _ => str_or(f, "resolution", String::new()),
+            },
+            size_bytes: f["filesize"].as_i64().or_else(|| f["filesize_approx"].as_i64()),
+            url: url?,
+        })
+    };
+
+    let videos = videos.into_iter().filter_map(|f| {
+        let quality = format!("{}p", f["height"].as_i64().unwrap_or(0));
+        entry(f, "video", quality, gen_stream_link(f, &ctx, "video", settings))
+    });
+    let audios = audios.into_iter().filter_map(|f| {
+        let quality = match f["abr"].as_f64().or_else(|| f["tbr"].as_f64()) {
+            Some(abr) if abr > 0.0 => format!("{}kbps", abr as i64),
+            _ => "audio".into(),
+        };
+        entry(f, "audio", quality, gen_stream_link(f, &ctx, "mp3", settings))
+    });
+    let images = images.into_iter().filter_map(|f| {
+        let quality = f["format_id"].as_str().unwrap_or("").to_uppercase();
+        entry(f, "image", quality, Some(gen_image_link(f, data, &ctx, settings)))
+    });
+    videos.chain(audios).chain(images).collect()
+}
+
+/// Generate an encrypted /download link for an image, fetched with the UA
+/// used for extraction.
+fn gen_image_link(img: &Value, data: &Value, ctx: &LinkContext, settings: &Settings) -> String {
+    let image_headers = match data["http_headers"]["User-Agent"].as_str() {
+        Some(ua) => serde_json::json!({ "User-Agent": ua }),
+        None => serde_json::json!({}),
+    };
+    let payload = serde_json::json!({
+        "url": img["url"].as_str().unwrap_or(""),
+        "author": ctx.author,
+        "http_headers": image_headers,
+        "platform": ctx.platform,
+        "video_id": ctx.video_id,
+        "creator": ctx.creator,
+        "type": "image"
+    });
+    let encrypted = encrypt(
+        &payload.to_string(),
+        &settings.encryption_key,
+        Some(360),
+    );
+    format!("{}/download?data={encrypted}", settings.base_url)
+}
+
 /// Generate an encrypted stream link for a format.
 fn gen_stream_link(
     format_obj: &Value,
-    author_nickname: &str,
+    ctx: &LinkContext,
     file_type: &str,
     settings: &Settings,
 ) -> Option<String> {
@@ -320,9 +442,12 @@ fn gen_stream_link(
 
     let payload = serde_json::json!({
         "url": url,
-        "author": author_nickname,
+        "author": ctx.author,
         "filesize": filesize,
         "http_headers": Value::Object(stream_headers),
+        "platform": ctx.platform,
+        "video_id": ctx.video_id,
+        "creator": ctx.creator,
         "type": file_type
     });
 
@@ -334,6 +459,10 @@ fn gen_stream_link(
     Some(format!("{}/stream?data={encrypted}", settings.base_url))
 }
 
+fn author_name(data: &Value) -> String {
+    str_or(data, "uploader", str_or(data, "channel", "unknown".into()))
+}
+
 fn str_or(v: &Value, key: &str, default: String) -> String {
     v[key]
         .as_str()
@@ -341,3 +470,44 @@ fn str_or(v: &Value, key: &str, default: String) -> String {
         .map(|s| s.to_string())
         .unwrap_or(default)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_catalog() {
+        let settings = Settings::from_env();
+        let video = serde_json::json!({
+            "id": "1",
+            "formats": [
+                {"format_id": "h264_540p", "url": "https://v/540", "vcodec": "h264", "acodec": "aac", "width": 576, "height": 1024, "filesize": 10},
+                {"format_id": "bytevc1_1080p", "url": "https://v/1080", "vcodec": "h265", "acodec": "aac", "width": 1080, "height": 1920},
+                {"format_id": "video_only", "url": "https://v/vo", "vcodec": "h264", "acodec": "none", "height": 2160},
+                {"format_id": "mp3", "url": "https://v/a", "vcodec": "none", "acodec": "mp3", "abr": 128.0},
+                {"format_id": "no_url", "vcodec": "h264", "acodec": "aac"},
+            ],
+        });
+        let catalog = format_catalog(&video, "https://www.tiktok.com/@a/video/1", &settings);
+        let ids: Vec<&str> = catalog.iter().map(|f| f.format_id.as_str()).collect();
+        assert_eq!(ids, ["bytevc1_1080p", "h264_540p", "mp3"]);
+        assert_eq!(catalog[0].quality, "1920p");
+        assert_eq!(catalog[1].resolution, "576x1024");
+        assert_eq!(catalog[1].size_bytes, Some(10));
+        assert_eq!((catalog[2].kind, catalog[2].quality.as_str()), ("audio", "128kbps"));
+        let stream = format!("{}/stream?data=", settings.base_url);
+        assert!(catalog.iter().all(|f| f.url.starts_with(&stream)));
+
+        let photo = serde_json::json!({
+            "formats": [
+                {"format_id": "image-1", "url": "https://p/1", "width": 1080, "height": 1440},
+                {"format_id": "audio", "url": "https://p/a"},
+            ],
+        });
+        let catalog = format_catalog(&photo, "https://www.tiktok.com/@a/photo/2", &settings);
+        assert_eq!(catalog.len(), 2);
+        assert_eq!((catalog[0].kind, catalog[0].quality.as_str()), ("audio", "audio"));
+        assert_eq!(catalog[1].kind, "image");
+        assert!(catalog[1].url.starts_with(&format!("{}/download?data=", settings.base_url)));
+    }
+}
@@ -0,0 +1,698 @@
+use axum::extract::{ConnectInfo, Json, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+use crate::checksum::sha256_bytes;
+use crate::encryption::random_hex;
+use crate::stream::{prefetch_link, serve_prefetched};
+use crate::config::Settings;
+use crate::streams::client_addr;
+use crate::tasks::{tick, TaskManager};
+use crate::AppState;
+
+/// Scheduled jobs are persisted here, inside SCHEDULE_DIR.
+const JOBS_FILE: &str = "jobs.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleStatus {
+    Scheduled,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// A CDN file downloaded by a job with `prefetch: true`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefetchedFile {
+    /// File name inside SCHEDULE_DIR
+    pub path: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub id: String,
+    pub url: String,
+    /// Unix seconds
+    pub run_at: u64,
+    pub prefetch: bool,
+    /// Extraction queue priority of the submitting API key
+    pub priority: u8,
+    pub timeout: Option<u64>,
+    pub status: ScheduleStatus,
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+    /// The POST /tiktok response; its links expire like any other
+    pub result: Option<serde_json::Value>,
+    pub file: Option<PrefetchedFile>,
+    /// Watcher that found this post (see watch.rs)
+    #[serde(default)]
+    pub watch_id: Option<String>,
+    /// Notified when the job finishes; only set for watcher jobs
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Who submitted it (see `client_key`), for the per-client rate limit
+    #[serde(default)]
+    pub client: Option<String>,
+}
+
+impl ScheduledJob {
+    fn finish(&mut self, status: ScheduleStatus, error: Option<String>) {
+        self.status = status;
+        self.error = error;
+        self.finished_at = Some(unix_now());
+    }
+}
+
+/// Why a job wasn't added.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddError {
+    /// SCHEDULE_MAX_JOBS jobs are scheduled or running
+    Full,
+    /// The client submitted SCHEDULE_CLIENT_JOBS_PER_HOUR jobs in the last hour
+    RateLimited,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CancelError {
+    NotFound,
+    /// Only jobs that haven't started can be cancelled
+    NotScheduled(ScheduleStatus),
+}
+
+/// Scheduled/delayed extraction jobs, persisted to SCHEDULE_DIR/jobs.json so
+/// they survive a restart.
+pub struct ScheduleStore {
+    dir: PathBuf,
+    jobs: Mutex<BTreeMap<String, ScheduledJob>>,
+    /// Serializes writes so an older snapshot never replaces a newer one
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl ScheduleStore {
+    /// Load the persisted jobs; jobs interrupted by a restart run again.
+    pub fn load(dir: &std::path::Path) -> Self {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            warn!("⚠️ Cannot create schedule dir {}: {e}", dir.display());
+        }
+        let path = dir.join(JOBS_FILE);
+        let mut jobs = BTreeMap::new();
+        match std::fs::read_to_string(&path) {
+            Ok(text) => match serde_json::from_str::<Vec<ScheduledJob>>(&text) {
+                Ok(list) => {
+                    for mut job in list {
+                        if job.status == ScheduleStatus::Running {
+                            job.status = ScheduleStatus::Scheduled;
+                        }
+                        jobs.insert(job.id.clone(), job);
+                    }
+                }
+                Err(e) => warn!("⚠️ Ignoring unreadable {}: {e}", path.display()),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("⚠️ Cannot read {}: {e}", path.display()),
+        }
+
+        let store = Self {
+            dir: dir.to_path_buf(),
+            jobs: Mutex::new(jobs),
+            write_lock: tokio::sync::Mutex::new(()),
+        };
+        info!("⏰ {} scheduled job(s) pending", store.pending());
+        store
+    }
+
+    async fn persist(&self) {
+        let _guard = self.write_lock.lock().await;
+        let snapshot = {
+            let jobs = self.jobs.lock().unwrap();
+            serde_json::to_vec_pretty(&jobs.values().collect::<Vec<_>>()).unwrap()
+        };
+        let path = self.dir.join(JOBS_FILE);
+        if let Err(e) = write_atomic(&path, snapshot).await {
+            error!("Failed to save scheduled jobs to {}: {e}", path.display());
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|j| j.status == ScheduleStatus::Scheduled)
+            .count()
+    }
+
+    /// Jobs still to run or running; finished ones don't count against
+    /// SCHEDULE_MAX_JOBS.
+    pub fn unfinished(&self) -> usize {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|j| matches!(j.status, ScheduleStatus::Scheduled | ScheduleStatus::Running))
+            .count()
+    }
+
+    /// A scheduled job for `url`, not yet added. The id is random, since job
+    /// results are readable without auth.
+    pub fn new_job(&self, url: String, run_at: u64, prefetch: bool, priority: u8) -> ScheduledJob {
+        ScheduledJob {
+            id: random_hex(16),
+            url,
+            run_at,
+            prefetch,
+            priority,
+            timeout: None,
+            status: ScheduleStatus::Scheduled,
+            created_at: unix_now(),
+            finished_at: None,
+            error: None,
+            result: None,
+            file: None,
+            watch_id: None,
+            webhook_url: None,
+            client: None,
+        }
+    }
+
+    /// Add `job` unless `max_jobs` jobs are unfinished, or its client already
+    /// submitted `per_client_hour` jobs (0 for no limit) in the last hour.
+    pub async fn add_limited(&self, job: ScheduledJob, max_jobs: usize, per_client_hour: usize) -> Result<(), AddError> {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let unfinished = jobs
+                .values()
+                .filter(|j| matches!(j.status, ScheduleStatus::Scheduled | ScheduleStatus::Running))
+                .count();
+            if unfinished >= max_jobs {
+                return Err(AddError::Full);
+            }
+            let since = job.created_at.saturating_sub(3600);
+            let recent = jobs
+                .values()
+                .filter(|j| j.client.is_some() && j.client == job.client && j.created_at >= since)
+                .count();
+            if per_client_hour > 0 && recent >= per_client_hour {
+                return Err(AddError::RateLimited);
+            }
+            jobs.insert(job.id.clone(), job);
+        }
+        self.persist().await;
+        Ok(())
+    }
+
+    pub async fn add(&self, job: ScheduledJob) {
+        self.jobs.lock().unwrap().insert(job.id.clone(), job);
+        self.persist().await;
+    }
+
+    pub fn get(&self, id: &str) -> Option<ScheduledJob> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    /// Jobs created by a watcher.
+    pub fn by_watch(&self, watch_id: &str) -> Vec<ScheduledJob> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|j| j.watch_id.as_deref() == Some(watch_id))
+            .cloned()
+            .collect()
+    }
+
+    pub async fn cancel(&self, id: &str) -> Result<ScheduledJob, CancelError> {
+        let job = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let job = jobs.get_mut(id).ok_or(CancelError::NotFound)?;
+            if job.status != ScheduleStatus::Scheduled {
+                return Err(CancelError::NotScheduled(job.status));
+            }
+            job.finish(ScheduleStatus::Cancelled, None);
+            job.clone()
+        };
+        self.persist().await;
+        Ok(job)
+    }
+
+    /// The most overdue scheduled job, marked running.
+    fn take_due(&self, now: u64) -> Option<ScheduledJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs
+            .values_mut()
+            .filter(|j| j.status == ScheduleStatus::Scheduled && j.run_at <= now)
+            .min_by_key(|j| j.run_at)?;
+        job.status = ScheduleStatus::Running;
+        Some(job.clone())
+    }
+
+    async fn update(&self, id: &str, f: impl FnOnce(&mut ScheduledJob)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            f(job);
+        }
+        self.persist().await;
+    }
+
+    /// Drop finished jobs older than `cutoff` and return them.
+    fn remove_finished_before(&self, cutoff: u64) -> Vec<ScheduledJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let expired: Vec<String> = jobs
+            .values()
+            .filter(|j| j.finished_at.is_some_and(|t| t < cutoff))
+            .map(|j| j.id.clone())
+            .collect();
+        expired.iter().filter_map(|id| jobs.remove(id)).collect()
+    }
+}
+
+/// `run_at` as unix seconds or an RFC 3339 timestamp.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RunAt {
+    Unix(u64),
+    Rfc3339(String),
+}
+
+impl RunAt {
+    fn to_unix(&self) -> Result<u64, String> {
+        match self {
+            RunAt::Unix(secs) => Ok(*secs),
+            RunAt::Rfc3339(s) => chrono::DateTime::parse_from_rfc3339(s)
+                .ok()
+                .and_then(|t| u64::try_from(t.timestamp()).ok())
+                .ok_or_else(|| format!("Invalid run_at timestamp: {s}")),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleRequest {
+    url: String,
+    run_at: RunAt,
+    /// Also download the video when the job runs (GET /jobs/{id}/file)
+    #[serde(default)]
+    prefetch: bool,
+    /// Extraction timeout in seconds (capped by YTDLP_MAX_TIMEOUT)
+    timeout: Option<u64>,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+/// Write `path` via a temp file and rename, so readers never see a partial file.
+pub async fn write_atomic(path: &std::path::Path, contents: Vec<u8>) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, contents).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+/// Who submitted a job: a hash of the API key when it is one of
+/// API_KEY_TIERS, else the client address. Unknown keys don't count, or a
+/// client could send a new one with every job.
+fn client_key(settings: &Settings, headers: &HeaderMap, peer: IpAddr) -> String {
+    let api_key = headers.get("x-api-key").and_then(|v| v.to_str().ok());
+    if let Some(key) = settings.billing_key(api_key) {
+        return format!("key:{}", &sha256_bytes(key.as_bytes())[..16]);
+    }
+    format!("ip:{}", client_addr(headers, peer, &settings.trusted_proxies))
+}
+
+/// Job JSON plus `file_url` once a prefetched file is available.
+fn job_view(job: &ScheduledJob, base_url: &str) -> serde_json::Value {
+    let mut view = serde_json::to_value(job).unwrap();
+    if let Some(obj) = view.as_object_mut() {
+        obj.remove("webhook_url");
+        obj.remove("client");
+    }
+    if job.file.is_some() {
+        view["file_url"] = format!("{base_url}/jobs/{}/file", job.id).into();
+    }
+    view
+}
+
+/// POST /jobs — Schedule an extraction (and optional prefetch) for `run_at`.
+pub async fn create_job_handler(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<ScheduleRequest>,
+) -> Response {
+    let settings = &state.settings;
+    let url = req.url.trim().to_string();
+    if let Some(resp) = crate::reject_unsupported_url(&url) {
+        return resp;
+    }
+    let run_at = match req.run_at.to_unix() {
+        Ok(t) => t,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let now = unix_now();
+    let max_ahead = settings.schedule_max_ahead_days * 86_400;
+    if run_at > now + max_ahead {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("run_at is more than {} days ahead", settings.schedule_max_ahead_days),
+        );
+    }
+    let priority = crate::request_priority(&headers, &state);
+    let job = ScheduledJob {
+        timeout: req.timeout,
+        client: Some(client_key(settings, &headers, peer.ip())),
+        ..state.schedule.new_job(url, run_at, req.prefetch, priority)
+    };
+    let added = state
+        .schedule
+        .add_limited(job.clone(), settings.schedule_max_jobs, settings.schedule_client_jobs_per_hour)
+        .await;
+    match added {
+        Ok(()) => {}
+        Err(AddError::Full) => {
+            return error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many scheduled jobs, please retry later".into(),
+            )
+        }
+        Err(AddError::RateLimited) => {
+            warn!("⏰ Job rate limit reached for {}", job.client.as_deref().unwrap_or("?"));
+            return error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many jobs submitted, please retry later".into(),
+            );
+        }
+    }
+    info!("⏰ Scheduled job {} for {} at {run_at}", job.id, job.url);
+    (StatusCode::CREATED, Json(job_view(&job, &settings.base_url))).into_response()
+}
+
+/// GET /jobs/{id} — Status and, once done, the extraction result.
+pub async fn get_job_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.schedule.get(&id) {
+        Some(job) => Json(job_view(&job, &state.settings.base_url)).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, "Job not found".into()),
+    }
+}
+
+/// GET /jobs/{id}/file — The prefetched video.
+pub async fn job_file_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.schedule.get(&id).and_then(|j| j.file) {
+        Some(file) => {
+            let path = state.schedule.dir.join(&file.path);
+            serve_prefetched(&path, &file.content_type, &file.filename).await
+        }
+        None => error_response(StatusCode::NOT_FOUND, "No prefetched file for this job".into()),
+    }
+}
+
+/// DELETE /jobs/{id} — Cancel a job that hasn't run yet.
+pub async fn cancel_job_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.schedule.cancel(&id).await {
+        Ok(job) => {
+            info!("⏰ Scheduled job {id} cancelled");
+            Json(job_view(&job, &state.settings.base_url)).into_response()
+        }
+        Err(CancelError::NotFound) => error_response(StatusCode::NOT_FOUND, "Job not found".into()),
+        Err(CancelError::NotScheduled(status)) => error_response(
+            StatusCode::CONFLICT,
+            format!("Job is {}, only scheduled jobs can be cancelled", status_label(status)),
+        ),
+    }
+}
+
+fn status_label(status: ScheduleStatus) -> &'static str {
+    match status {
+        ScheduleStatus::Scheduled => "scheduled",
+        ScheduleStatus::Running => "running",
+        ScheduleStatus::Done => "done",
+        ScheduleStatus::Failed => "failed",
+        ScheduleStatus::Cancelled => "cancelled",
+    }
+}
+
+/// Background task: start due jobs (at most SCHEDULE_CONCURRENCY at once)
+/// and drop finished ones after SCHEDULE_RETENTION_HOURS. On shutdown no
+/// new jobs are started and the running ones are waited for.
+pub fn spawn_schedule_task(tasks: &TaskManager, state: AppState) {
+    tasks.spawn("schedule", |cancel| async move {
+        let settings = &state.settings;
+        let permits = Arc::new(Semaphore::new(settings.schedule_concurrency.max(1)));
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(settings.schedule_poll_secs.max(1)));
+        let mut running = JoinSet::new();
+        while tick(&mut interval, &cancel).await {
+            while running.try_join_next().is_some() {}
+            remove_expired(&state).await;
+
+            while let Ok(permit) = permits.clone().try_acquire_owned() {
+                let Some(job) = state.schedule.take_due(unix_now()) else {
+                    break;
+                };
+                state.schedule.persist().await;
+                let state = state.clone();
+                running.spawn(async move {
+                    let _permit = permit;
+                    run_job(&state, job).await;
+                });
+            }
+        }
+        if !running.is_empty() {
+            info!("⏰ Waiting for {} running scheduled job(s)", running.len());
+        }
+        while running.join_next().await.is_some() {}
+    });
+}
+
+async fn remove_expired(state: &AppState) {
+    let cutoff = unix_now().saturating_sub(state.settings.schedule_retention_hours * 3600);
+    let expired = state.schedule.remove_finished_before(cutoff);
+    if expired.is_empty() {
+        return;
+    }
+    for file in expired.iter().filter_map(|j| j.file.as_ref()) {
+        let _ = tokio::fs::remove_file(state.schedule.dir.join(&file.path)).await;
+    }
+    info!("⏰ Removed {} expired scheduled job(s)", expired.len());
+    state.schedule.persist().await;
+}
+
+async fn run_job(state: &AppState, job: ScheduledJob) {
+    info!("⏰ Running scheduled job {} for {}", job.id, job.url);
+    let timeout_secs = state.settings.extraction_timeout(job.timeout);
+    let formats = state.settings.response_formats;
+    let response = match crate::process_url(state, &job.url, timeout_secs, job.priority, formats).await {
+        Ok(mut r) => {
+            crate::shortlink::add_short_links(state, &mut r).await;
+            r
+        }
+        Err(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+            // Extraction queue is full; try again later instead of failing
+            let retry_after = resp
+                .headers()
+                .get("retry-after")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(60);
+            warn!("Scheduled job {} deferred {retry_after}s, extraction queue full", job.id);
+            state
+                .schedule
+                .update(&job.id, |j| {
+                    j.status = ScheduleStatus::Scheduled;
+                    j.run_at = unix_now() + retry_after;
+                })
+                .await;
+            return;
+        }
+        Err(resp) => {
+            let error = response_error(resp).await;
+            warn!("Scheduled job {} failed: {error}", job.id);
+            state
+                .schedule
+                .update(&job.id, |j| j.finish(ScheduleStatus::Failed, Some(error)))
+                .await;
+            notify_webhook(state, &job.id).await;
+            return;
+        }
+    };
+
+    let mut file = None;
+    let mut prefetch_error = None;
+    if let Some(link) = job.prefetch.then(|| video_link(&response)).flatten() {
+        let name = format!("{}.download", job.id);
+        match prefetch_link(state, link, &state.schedule.dir.join(&name)).await {
+            Ok(p) => {
+                info!("⏰ Prefetched {} ({} bytes) for job {}", p.filename, p.size, job.id);
+                file = Some(PrefetchedFile {
+                    path: name,
+                    filename: p.filename,
+                    content_type: p.content_type.to_string(),
+                    size: p.size,
+                    sha256: p.sha256,
+                });
+            }
+            Err(e) => {
+                warn!("Prefetch for scheduled job {} failed: {e}", job.id);
+                prefetch_error = Some(format!("Prefetch failed: {e}"));
+            }
+        }
+    }
+
+    let status = match prefetch_error {
+        Some(_) => ScheduleStatus::Failed,
+        None => ScheduleStatus::Done,
+    };
+    state
+        .schedule
+        .update(&job.id, |j| {
+            j.result = Some(response);
+            j.file = file;
+            j.finish(status, prefetch_error);
+        })
+        .await;
+    notify_webhook(state, &job.id).await;
+}
+
+/// POST the finished job to its webhook as a `new_post` event, and to
+/// Discord when DISCORD_EVENTS includes `jobs`.
+async fn notify_webhook(state: &AppState, id: &str) {
+    let Some(job) = state.schedule.get(id) else {
+        return;
+    };
+    state.notifier.job_finished(&job);
+    let Some(ref url) = job.webhook_url else {
+        return;
+    };
+    let body = serde_json::json!({
+        "event": "new_post",
+        "watch_id": job.watch_id,
+        "job": job_view(&job, &state.settings.base_url),
+    });
+    let sent = state
+        .http_client
+        .post(url)
+        .timeout(Duration::from_secs(state.settings.watch_webhook_timeout_secs))
+        .json(&body)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    if let Err(e) = sent {
+        warn!("Webhook for scheduled job {id} failed: {e}");
+    }
+}
+
+/// Best video link of a /tiktok response; image posts have none.
+fn video_link(response: &serde_json::Value) -> Option<&str> {
+    ["no_watermark_hd", "no_watermark", "watermark"]
+        .iter()
+        .find_map(|k| response["download_link"][k].as_str())
+}
+
+/// `"<status> <error>"` from a JSON error response.
+async fn response_error(resp: Response) -> String {
+    let status = resp.status();
+    let body = axum::body::to_bytes(resp.into_body(), 64 * 1024)
+        .await
+        .unwrap_or_default();
+    let message = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|v| {
+            let error = v["error"].as_str()?.to_string();
+            Some(match v["code"].as_str() {
+                Some(code) => format!("{error} ({code})"),
+                None => error,
+            })
+        })
+        .unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned());
+    format!("{} {message}", status.as_u16())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: &str, run_at: u64, status: ScheduleStatus) -> ScheduledJob {
+        ScheduledJob {
+            id: id.to_string(),
+            url: format!("https://www.tiktok.com/@a/video/{id}"),
+            run_at,
+            prefetch: false,
+            priority: 0,
+            timeout: None,
+            status,
+            created_at: 0,
+            finished_at: None,
+            error: None,
+            result: None,
+            file: None,
+            watch_id: None,
+            webhook_url: None,
+            client: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_schedule_store_survives_restart() {
+        let dir = std::env::temp_dir().join(format!("schedule_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let store = ScheduleStore::load(&dir);
+        store.add(job("late", 200, ScheduleStatus::Scheduled)).await;
+        store.add(job("early", 100, ScheduleStatus::Scheduled)).await;
+        store.add(job("future", 10_000, ScheduleStatus::Scheduled)).await;
+
+        assert!(store.take_due(50).is_none());
+        assert_eq!(store.take_due(300).unwrap().id, "early");
+        store.persist().await;
+        assert_eq!(store.cancel("late").await.unwrap().status, ScheduleStatus::Cancelled);
+        assert_eq!(
+            store.cancel("early").await.unwrap_err(),
+            CancelError::NotScheduled(ScheduleStatus::Running)
+        );
+
+        // The interrupted job runs again after a restart
+        let reloaded = ScheduleStore::load(&dir);
+        assert_eq!(reloaded.get("early").unwrap().status, ScheduleStatus::Scheduled);
+        assert_eq!(reloaded.get("late").unwrap().status, ScheduleStatus::Cancelled);
+        assert_eq!(reloaded.pending(), 2);
+        assert_eq!(reloaded.remove_finished_before(u64::MAX).len(), 1);
+
+        // Unfinished jobs are bounded overall, and submissions per client per hour
+        let limited = |id: &str, client: &str| ScheduledJob {
+            client: Some(client.to_string()),
+            created_at: 10_000,
+            ..job(id, 20_000, ScheduleStatus::Scheduled)
+        };
+        assert_eq!(reloaded.unfinished(), 2);
+        assert_eq!(reloaded.add_limited(limited("a1", "ip:1"), 3, 1).await, Ok(()));
+        assert_eq!(reloaded.add_limited(limited("b1", "ip:2"), 3, 1).await, Err(AddError::Full));
+        assert_eq!(reloaded.add_limited(limited("a2", "ip:1"), 5, 1).await, Err(AddError::RateLimited));
+        assert_eq!(reloaded.add_limited(limited("b1", "ip:2"), 5, 1).await, Ok(()));
+        assert_ne!(reloaded.new_job("u".into(), 0, false, 0).id, reloaded.new_job("u".into(), 0, false, 0).id);
+
+        assert_eq!(RunAt::Rfc3339("1970-01-01T00:01:40Z".into()).to_unix(), Ok(100));
+        assert!(RunAt::Rfc3339("tomorrow".into()).to_unix().is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,113 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::checksum::sha256_bytes;
+
+/// Sessions kept at once; the oldest is dropped beyond this.
+const MAX_SESSIONS: usize = 1000;
+
+/// Extraction result of one /tiktok call.
+#[derive(Clone)]
+pub struct Session {
+    pub url: String,
+    pub data: Arc<Value>,
+    created: Instant,
+}
+
+/// Image-post extraction results kept for SLIDESHOW_SESSION_TTL_SECS, so
+/// /download-slideshow?session= reuses them instead of extracting the post a
+/// second time. In-memory, per instance.
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    ttl: Duration,
+    /// Mixed into ids so they can't be guessed from the time
+    secret: String,
+    counter: AtomicU64,
+}
+
+impl SessionStore {
+    /// None when the TTL is 0 (sessions disabled).
+    pub fn new(ttl_secs: u64, secret: &str) -> Option<Self> {
+        if ttl_secs == 0 {
+            return None;
+        }
+        Some(Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(ttl_secs),
+            secret: secret.to_string(),
+            counter: AtomicU64::new(0),
+        })
+    }
+
+    /// Keep `data` for `url` and return the new session id.
+    pub fn insert(&self, url: &str, data: Arc<Value>) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        let seed = format!("{}:{nanos}:{n}:{url}", self.secret);
+        let id = sha256_bytes(seed.as_bytes())[..32].to_string();
+
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, s| now.duration_since(s.created) < self.ttl);
+        if sessions.len() >= MAX_SESSIONS {
+            let oldest = sessions
+                .iter()
+                .min_by_key(|(_, s)| s.created)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+        sessions.insert(
+            id.clone(),
+            Session {
+                url: url.to_string(),
+                data,
+                created: now,
+            },
+        );
+        id
+    }
+
+    /// The session, unless unknown or expired.
+    pub fn get(&self, id: &str) -> Option<Session> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(id)
+            .filter(|s| s.created.elapsed() < self.ttl)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_get_and_evict() {
+        assert!(SessionStore::new(0, "k").is_none());
+        let store = SessionStore::new(60, "k").unwrap();
+        let data = Arc::new(serde_json::json!({"id": "1"}));
+        let first = store.insert("https://www.tiktok.com/@a/photo/1", data.clone());
+        let second = store.insert("https://www.tiktok.com/@a/photo/1", data);
+        assert_ne!(first, second);
+        assert_eq!(first.len(), 32);
+
+        let session = store.get(&first).unwrap();
+        assert_eq!(session.url, "https://www.tiktok.com/@a/photo/1");
+        assert_eq!(session.data["id"], "1");
+        assert!(store.get("unknown").is_none());
+
+        for i in 0..MAX_SESSIONS {
+            store.insert(&i.to_string(), Arc::new(Value::Null));
+        }
+        assert!(store.get(&first).is_none());
+        assert_eq!(store.sessions.lock().unwrap().len(), MAX_SESSIONS);
+    }
+}
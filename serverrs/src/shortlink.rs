@@ -0,0 +1,111 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Json;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::encryption;
+use crate::streams::unix_now;
+use crate::tenants::TenantState;
+use crate::AppState;
+
+/// Characters of a short id: 62^8 ids, derived from the link itself so the
+/// same link always gets the same id.
+const SHORT_ID_LEN: usize = 8;
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// GET /s/{id} — redirect to the /stream or /download link behind a short id.
+pub async fn short_link_handler(TenantState(state): TenantState, Path(id): Path<String>) -> Response {
+    let target = match state.redis.as_ref() {
+        Some(cache) if valid_id(&id) => cache.get_short_link(&id).await,
+        _ => None,
+    };
+    match target {
+        Some(path) => Redirect::temporary(&format!("{}{path}", state.settings.base_url)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Short link expired or not found", "code": "SHORT_LINK_NOT_FOUND"})),
+        )
+            .into_response(),
+    }
+}
+
+/// Add `short_link`, the `download_link` object with every link replaced by
+/// its /s/{id} form. Links that can't be stored are left out.
+pub async fn add_short_links(state: &AppState, response: &mut Value) {
+    if !state.settings.short_links {
+        return;
+    }
+    let Some(links) = response["download_link"].as_object() else {
+        return;
+    };
+    let mut short = serde_json::Map::new();
+    for (name, link) in links {
+        match link {
+            Value::String(url) => {
+                if let Some(s) = shorten(state, url).await {
+                    short.insert(name.clone(), Value::String(s));
+                }
+            }
+            Value::Array(urls) => {
+                let mut list = Vec::new();
+                for url in urls.iter().filter_map(Value::as_str) {
+                    if let Some(s) = shorten(state, url).await {
+                        list.push(Value::String(s));
+                    }
+                }
+                short.insert(name.clone(), Value::Array(list));
+            }
+            _ => {}
+        }
+    }
+    if !short.is_empty() {
+        response["short_link"] = Value::Object(short);
+    }
+}
+
+/// Store one of our links under its short id until its token expires.
+async fn shorten(state: &AppState, url: &str) -> Option<String> {
+    let cache = state.redis.as_ref()?;
+    let base_url = &state.settings.base_url;
+    let path = url.strip_prefix(base_url.as_str())?;
+    let token = path.split_once("data=")?.1.split('&').next()?;
+    let expires_at = encryption::expires_at(token, &state.settings.encryption_key)?;
+    let ttl = expires_at.checked_sub(unix_now()).filter(|t| *t > 0)?;
+
+    let id = short_id(path);
+    cache.set_short_link(&id, path, ttl).await.then(|| format!("{base_url}/s/{id}"))
+}
+
+fn short_id(path: &str) -> String {
+    let digest = Sha256::digest(path.as_bytes());
+    let mut n = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    (0..SHORT_ID_LEN)
+        .map(|_| {
+            let c = ALPHABET[(n % 62) as usize] as char;
+            n /= 62;
+            c
+        })
+        .collect()
+}
+
+fn valid_id(id: &str) -> bool {
+    id.len() == SHORT_ID_LEN && id.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_id() {
+        let a = short_id("/stream?data=abc");
+        assert_eq!(a.len(), SHORT_ID_LEN);
+        assert!(valid_id(&a));
+        assert_eq!(a, short_id("/stream?data=abc"));
+        assert_ne!(a, short_id("/stream?data=abd"));
+        assert!(!valid_id("abc"));
+        assert!(!valid_id("../../x"));
+    }
+}
@@ -1,17 +1,33 @@
+use std::io::{Read, Write};
 use std::path::Path;
-use std::process::Command;
-use tracing::{error, info};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
-/// Download file from URL to local path (blocking, for use in spawn_blocking)
-pub fn download_file(url: &str, output_path: &str, timeout_secs: u64) -> Result<(), String> {
+/// How often a running FFmpeg is checked for cancellation.
+const FFMPEG_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Download file from URL to local path (blocking, for use in spawn_blocking).
+/// Stops, dropping the connection, once `stop` is cancelled.
+pub fn download_file(
+    url: &str,
+    output_path: &str,
+    timeout_secs: u64,
+    headers: &[(String, String)],
+    stop: &CancellationToken,
+) -> Result<(), String> {
     let client = reqwest::blocking::Client::builder()
         .timeout(std::time::Duration::from_secs(timeout_secs))
         .redirect(reqwest::redirect::Policy::limited(10))
         .build()
         .map_err(|e| format!("Failed to create HTTP client: {e}"))?;
 
-    let mut response = client
-        .get(url)
+    let mut request = client.get(url);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let mut response = request
         .send()
         .map_err(|e| format!("Failed to download file: {e}"))?;
 
@@ -22,26 +38,351 @@ pub fn download_file(url: &str, output_path: &str, timeout_secs: u64) -> Result<
     let mut file =
         std::fs::File::create(output_path).map_err(|e| format!("Failed to create file: {e}"))?;
 
-    std::io::copy(&mut response, &mut file).map_err(|e| format!("Failed to write file: {e}"))?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        if stop.is_cancelled() {
+            return Err("Download cancelled: client went away".into());
+        }
+        let n = response
+            .read(&mut buf)
+            .map_err(|e| format!("Failed to download file: {e}"))?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])
+            .map_err(|e| format!("Failed to write file: {e}"))?;
+    }
 
     info!("Downloaded file: {output_path}");
     Ok(())
 }
 
-/// Create a slideshow video from images and audio using FFmpeg.
-/// Blocking — call from spawn_blocking.
+/// GIF frame rate and width; full 1080x1920 at 30fps would be enormous.
+const GIF_FPS: u32 = 10;
+const GIF_WIDTH: u32 = 540;
+
+/// Container and codecs of a generated slideshow (`output=` on /download-slideshow).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// H.264 + AAC
+    Mp4,
+    /// VP9 + Opus
+    WebmVp9,
+    /// AV1 (SVT-AV1) + Opus
+    WebmAv1,
+    /// Animated GIF, no audio
+    Gif,
+}
+
+impl OutputFormat {
+    /// `output` is mp4, webm or gif; `webm_codec` (SLIDESHOW_WEBM_CODEC) picks
+    /// vp9 or av1 for webm.
+    pub fn parse(output: &str, webm_codec: &str) -> Result<Self, String> {
+        match (output.to_lowercase().as_str(), webm_codec) {
+            ("mp4", _) => Ok(OutputFormat::Mp4),
+            ("webm", "av1") => Ok(OutputFormat::WebmAv1),
+            ("webm", _) => Ok(OutputFormat::WebmVp9),
+            ("gif", _) => Ok(OutputFormat::Gif),
+            (other, _) => Err(format!("Unsupported output '{other}' (expected mp4, webm or gif)")),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 => "mp4",
+            OutputFormat::WebmVp9 | OutputFormat::WebmAv1 => "webm",
+            OutputFormat::Gif => "gif",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 => "video/mp4",
+            OutputFormat::WebmVp9 | OutputFormat::WebmAv1 => "video/webm",
+            OutputFormat::Gif => "image/gif",
+        }
+    }
+
+    pub fn has_audio(self) -> bool {
+        self != OutputFormat::Gif
+    }
+
+    /// What ffprobe must report for a correctly generated file.
+    pub fn expected(self, duration_secs: f64) -> ExpectedOutput {
+        let (video_codec, audio_codec) = match self {
+            OutputFormat::Mp4 => ("h264", Some("aac")),
+            OutputFormat::WebmVp9 => ("vp9", Some("opus")),
+            OutputFormat::WebmAv1 => ("av1", Some("opus")),
+            OutputFormat::Gif => ("gif", None),
+        };
+        ExpectedOutput {
+            duration_secs,
+            video_codec,
+            audio_codec,
+        }
+    }
+
+    fn encoder_args(self) -> &'static [&'static str] {
+        match self {
+            OutputFormat::Mp4 => &[
+                "-pix_fmt", "yuv420p", "-c:v", "libx264", "-preset", "medium", "-crf", "23",
+                "-c:a", "aac",
+            ],
+            OutputFormat::WebmVp9 => &[
+                "-pix_fmt", "yuv420p", "-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "32",
+                "-row-mt", "1", "-deadline", "good", "-cpu-used", "4", "-c:a", "libopus",
+            ],
+            OutputFormat::WebmAv1 => &[
+                "-pix_fmt", "yuv420p", "-c:v", "libsvtav1", "-preset", "8", "-crf", "35",
+                "-c:a", "libopus",
+            ],
+            OutputFormat::Gif => &["-loop", "0"],
+        }
+    }
+}
+
+/// How long each image is shown (`timing=` / SLIDESHOW_TIMING).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timing {
+    /// A fixed time per image; the audio is looped or cut to fit
+    Fixed,
+    /// The audio track's real length, split evenly across the images
+    Audio,
+    /// Per-image display times from the post's metadata, as TikTok plays
+    /// it; fixed timing when the post has none
+    Native,
+}
+
+impl Timing {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "fixed" => Ok(Timing::Fixed),
+            "audio" => Ok(Timing::Audio),
+            "native" => Ok(Timing::Native),
+            other => Err(format!(
+                "Unsupported timing '{other}' (expected fixed, audio or native)"
+            )),
+        }
+    }
+}
+
+/// Frame rate of the looped image inputs; per-image times are rounded to
+/// whole frames so the concatenated length doesn't drift from the audio.
+const IMAGE_INPUT_FPS: f64 = 25.0;
+const MIN_SECS_PER_IMAGE: f64 = 0.5;
+
+/// Seconds per image so `images` images last as long as `audio_secs` (capped
+/// at `max_total_secs`).
+pub fn audio_timed_secs_per_image(audio_secs: f64, images: usize, max_total_secs: f64) -> f64 {
+    round_to_frames(audio_secs.min(max_total_secs) / images.max(1) as f64)
+}
+
+fn round_to_frames(secs: f64) -> f64 {
+    ((secs * IMAGE_INPUT_FPS).round() / IMAGE_INPUT_FPS).max(MIN_SECS_PER_IMAGE)
+}
+
+/// Per-image display times (seconds) carried by the image formats'
+/// `duration`, capped at `max_total_secs` overall. None unless every image
+/// has one, so a partly timed post doesn't mix native and fixed timing.
+pub fn native_image_durations(
+    image_formats: &[&serde_json::Value],
+    max_total_secs: f64,
+) -> Option<Vec<f64>> {
+    let mut total = 0.0;
+    let mut durations = Vec::with_capacity(image_formats.len());
+    for f in image_formats {
+        let secs = match &f["duration"] {
+            serde_json::Value::String(s) => s.parse::<f64>().ok(),
+            v => v.as_f64(),
+        }
+        .filter(|d| d.is_finite() && *d > 0.0)?;
+        let secs = round_to_frames(secs.min(max_total_secs - total));
+        total += secs;
+        durations.push(secs);
+    }
+    (!durations.is_empty()).then_some(durations)
+}
+
+/// Caption lines are wrapped at this many characters, and at most this many
+/// lines are drawn, so long titles stay inside the 1080px frame.
+const CAPTION_LINE_CHARS: usize = 32;
+const CAPTION_MAX_LINES: usize = 3;
+
+/// What to draw over a slideshow (`caption=` / SLIDESHOW_CAPTION).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptionContent {
+    None,
+    Title,
+    /// The author's @handle
+    Author,
+    /// @handle on the first line, title below
+    Both,
+}
+
+impl CaptionContent {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "" | "none" | "off" => Ok(CaptionContent::None),
+            "title" => Ok(CaptionContent::Title),
+            "author" => Ok(CaptionContent::Author),
+            "both" => Ok(CaptionContent::Both),
+            other => Err(format!(
+                "Unsupported caption '{other}' (expected none, title, author or both)"
+            )),
+        }
+    }
+
+    /// The text to draw, wrapped; None when there is nothing to show.
+    pub fn text(self, title: &str, handle: &str) -> Option<String> {
+        let handle = (!handle.is_empty()).then(|| format!("@{}", handle.trim_start_matches('@')));
+        let title = wrap_caption(title);
+        match self {
+            CaptionContent::None => None,
+            CaptionContent::Title => title,
+            CaptionContent::Author => handle,
+            CaptionContent::Both => match (handle, title) {
+                (Some(h), Some(t)) => Some(format!("{h}\n{t}")),
+                (h, t) => h.or(t),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptionPosition {
+    Top,
+    Bottom,
+}
+
+impl CaptionPosition {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "top" => Ok(CaptionPosition::Top),
+            "bottom" => Ok(CaptionPosition::Bottom),
+            other => Err(format!(
+                "Unsupported caption position '{other}' (expected top or bottom)"
+            )),
+        }
+    }
+
+    /// drawtext `y`, clear of the platform UI at the top and bottom edges.
+    fn y(self) -> &'static str {
+        match self {
+            CaptionPosition::Top => "160",
+            CaptionPosition::Bottom => "h-text_h-240",
+        }
+    }
+}
+
+/// Text overlay for a slideshow, drawn on every frame.
+pub struct Caption {
+    pub text: String,
+    pub position: CaptionPosition,
+    /// Fontconfig family name, or a path to a font file
+    pub font: String,
+    pub font_size: u32,
+}
+
+impl Caption {
+    /// drawtext filter options. `expansion=none` keeps `%{...}` in titles literal.
+    fn drawtext(&self) -> String {
+        let font = if self.font.contains('/') {
+            format!("fontfile={}", escape_filter_arg(&self.font))
+        } else {
+            format!("font={}", escape_filter_arg(&self.font))
+        };
+        format!(
+            "drawtext={font}:text={}:expansion=none:fontsize={}:fontcolor=white:\
+             box=1:boxcolor=black@0.5:boxborderw=16:line_spacing=8:x=(w-text_w)/2:y={}",
+            escape_filter_arg(&self.text),
+            self.font_size,
+            self.position.y()
+        )
+    }
+}
+
+/// Collapse whitespace and wrap at word boundaries; text beyond
+/// CAPTION_MAX_LINES is cut with an ellipsis.
+fn wrap_caption(text: &str) -> Option<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty()
+            && current.chars().count() + 1 + word.chars().count() > CAPTION_LINE_CHARS
+        {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    if lines.len() > CAPTION_MAX_LINES {
+        lines.truncate(CAPTION_MAX_LINES);
+        lines[CAPTION_MAX_LINES - 1].push('…');
+    }
+    // A single word longer than a line is cut too
+    for line in &mut lines {
+        if line.chars().count() > CAPTION_LINE_CHARS + 1 {
+            *line = line.chars().take(CAPTION_LINE_CHARS).collect::<String>() + "…";
+        }
+    }
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Escape a value for a filter option inside -filter_complex: once for the
+/// option parser (`\ ' :`), then again for the filtergraph parser.
+fn escape_filter_arg(value: &str) -> String {
+    let escape = |s: &str, special: &[char]| {
+        let mut out = String::with_capacity(s.len());
+        for c in s.chars() {
+            if special.contains(&c) {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    };
+    escape(
+        &escape(value, &['\\', '\'', ':']),
+        &['\\', '\'', '[', ']', ',', ';'],
+    )
+}
+
+/// Create a slideshow from images (and audio, except for GIF) using FFmpeg,
+/// with an optional caption drawn over it. FFmpeg is killed if `stop` is
+/// cancelled first. Blocking — call from spawn_blocking.
 pub fn create_slideshow(
     image_paths: &[String],
-    audio_path: &str,
+    audio_path: Option<&str>,
     output_path: &str,
-    duration_per_image: u32,
+    durations: &[f64],
+    format: OutputFormat,
+    caption: Option<&Caption>,
+    stop: &CancellationToken,
 ) -> Result<(), String> {
     if image_paths.is_empty() {
         return Err("No image paths provided".into());
     }
-    if !Path::new(audio_path).exists() {
-        return Err(format!("Audio file not found: {audio_path}"));
+    if durations.len() != image_paths.len() {
+        return Err(format!(
+            "{} durations for {} images",
+            durations.len(),
+            image_paths.len()
+        ));
     }
+    let audio_path = if format.has_audio() {
+        let path = audio_path.ok_or("No audio provided")?;
+        if !Path::new(path).exists() {
+            return Err(format!("Audio file not found: {path}"));
+        }
+        Some(path)
+    } else {
+        None
+    };
     for img in image_paths {
         if !Path::new(img).exists() {
             return Err(format!("Image file not found: {img}"));
@@ -51,13 +392,15 @@ pub fn create_slideshow(
     let mut cmd = Command::new("ffmpeg");
     cmd.arg("-y");
 
-    // Add each image as input with duration
-    for img_path in image_paths {
-        cmd.args(["-loop", "1", "-t", &duration_per_image.to_string(), "-i", img_path]);
+    // Add each image as input with its duration
+    for (img_path, secs) in image_paths.iter().zip(durations) {
+        cmd.args(["-loop", "1", "-t", &format!("{secs:.3}"), "-i", img_path]);
     }
 
     // Add audio with loop
-    cmd.args(["-stream_loop", "-1", "-i", audio_path]);
+    if let Some(audio_path) = audio_path {
+        cmd.args(["-stream_loop", "-1", "-i", audio_path]);
+    }
 
     // Build complex filter
     let mut filter_parts = Vec::new();
@@ -78,45 +421,61 @@ pub fn create_slideshow(
     ));
 
     // Calculate total video duration and trim audio
-    let video_duration = image_paths.len() as u32 * duration_per_image;
-    filter_parts.push(format!("[{}:a]atrim=0:{video_duration}[aout]", image_paths.len()));
+    let video_duration: f64 = durations.iter().sum();
+    if audio_path.is_some() {
+        filter_parts.push(format!(
+            "[{}:a]atrim=0:{video_duration:.3}[aout]",
+            image_paths.len()
+        ));
+    }
+
+    let mut video_out = "[vout]";
+    if let Some(caption) = caption {
+        filter_parts.push(format!("[vout]{}[vtext]", caption.drawtext()));
+        video_out = "[vtext]";
+    }
+
+    // GIF: one shared 256-colour palette generated from the whole slideshow
+    let video_out = if format == OutputFormat::Gif {
+        filter_parts.push(format!(
+            "{video_out}fps={GIF_FPS},scale={GIF_WIDTH}:-1:flags=lanczos,split[g0][g1];\
+             [g0]palettegen[pal];[g1][pal]paletteuse[gout]"
+        ));
+        "[gout]"
+    } else {
+        video_out
+    };
 
     let filter_complex = filter_parts.join(";");
 
-    cmd.args([
-        "-filter_complex",
-        &filter_complex,
-        "-map",
-        "[vout]",
-        "-map",
-        "[aout]",
-        "-pix_fmt",
-        "yuv420p",
-        "-fps_mode",
-        "cfr",
-        "-c:v",
-        "libx264",
-        "-preset",
-        "medium",
-        "-crf",
-        "23",
-        "-c:a",
-        "aac",
-        output_path,
-    ]);
-
-    info!("Creating slideshow with {} images", image_paths.len());
-
-    let output = cmd
-        .output()
-        .map_err(|e| format!("Failed to run FFmpeg: {e}"))?;
+    cmd.args(["-filter_complex", &filter_complex, "-map", video_out]);
+    if audio_path.is_some() {
+        cmd.args(["-map", "[aout]"]);
+    }
+    cmd.args(["-fps_mode", "cfr"]);
+    cmd.args(format.encoder_args());
+    cmd.arg(output_path);
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
+    info!(
+        "Creating {} slideshow with {} images",
+        format.extension(),
+        image_paths.len()
+    );
+
+    let (status, stderr) = match run_until_stopped(cmd, stop)? {
+        Some(done) => done,
+        None => {
+            warn!("🔌 Client went away, killed FFmpeg for {output_path}");
+            let _ = std::fs::remove_file(output_path);
+            return Err("FFmpeg cancelled: client went away".into());
+        }
+    };
+
+    if !status.success() {
         error!("FFmpeg error: {stderr}");
         // Clean up partial output
         let _ = std::fs::remove_file(output_path);
-        return Err(format!("FFmpeg failed with code {:?}", output.status.code()));
+        return Err(format!("FFmpeg failed with code {:?}", status.code()));
     }
 
     if !Path::new(output_path).exists() {
@@ -126,3 +485,281 @@ pub fn create_slideshow(
     info!("Slideshow created successfully: {output_path}");
     Ok(())
 }
+
+/// Run `cmd` to completion and return its exit status and stderr, or kill
+/// it and return None once `stop` is cancelled. Blocking.
+fn run_until_stopped(
+    mut cmd: Command,
+    stop: &CancellationToken,
+) -> Result<Option<(std::process::ExitStatus, String)>, String> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run FFmpeg: {e}"))?;
+    // Drained on its own thread so a chatty FFmpeg can't block on a full pipe
+    let mut pipe = child.stderr.take().expect("stderr is piped");
+    let stderr = std::thread::spawn(move || {
+        let mut out = String::new();
+        let _ = pipe.read_to_string(&mut out);
+        out
+    });
+
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| format!("Failed to wait for FFmpeg: {e}"))? {
+            return Ok(Some((status, stderr.join().unwrap_or_default())));
+        }
+        if stop.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(FFMPEG_POLL_INTERVAL);
+    }
+}
+
+/// What a generated file must contain to be served.
+pub struct ExpectedOutput {
+    pub duration_secs: f64,
+    pub video_codec: &'static str,
+    /// None for outputs without an audio stream (GIF)
+    pub audio_codec: Option<&'static str>,
+}
+
+/// Allowed drift between the expected and probed duration.
+const DURATION_TOLERANCE_SECS: f64 = 1.0;
+
+/// Run ffprobe on a generated file and check duration, stream counts and
+/// codecs, so a partially failed ffmpeg run isn't served as a broken file.
+/// Blocking — call from spawn_blocking.
+pub fn verify_output(path: &str, expected: &ExpectedOutput) -> Result<(), String> {
+    let size = std::fs::metadata(path)
+        .map_err(|e| format!("Output file missing: {e}"))?
+        .len();
+    if size == 0 {
+        return Err("Output file is empty".into());
+    }
+
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+            path,
+        ])
+        .output()
+        .map_err(|e| format!("Failed to run ffprobe: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("ffprobe failed: {}", stderr.trim()));
+    }
+    let probe: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Invalid ffprobe output: {e}"))?;
+    check_probe(&probe, expected)
+}
+
+/// Check that an uploaded soundtrack actually contains an audio stream before
+/// it reaches ffmpeg. Blocking — call from spawn_blocking.
+pub fn probe_audio(path: &str) -> Result<(), String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-print_format", "json", "-show_streams", path])
+        .output()
+        .map_err(|e| format!("Failed to run ffprobe: {e}"))?;
+    if !output.status.success() {
+        return Err("Not a readable media file".into());
+    }
+    let probe: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Invalid ffprobe output: {e}"))?;
+    if has_audio_stream(&probe) {
+        Ok(())
+    } else {
+        Err("File has no audio stream".into())
+    }
+}
+
+/// Length of an audio file in seconds. Blocking — call from spawn_blocking.
+pub fn audio_duration(path: &str) -> Result<f64, String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-print_format", "json", "-show_format", path])
+        .output()
+        .map_err(|e| format!("Failed to run ffprobe: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("ffprobe failed: {}", stderr.trim()));
+    }
+    let probe: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Invalid ffprobe output: {e}"))?;
+    probe_duration(&probe)
+        .filter(|d| *d > 0.0)
+        .ok_or_else(|| "Audio has no duration".into())
+}
+
+/// ffprobe reports durations as strings
+fn probe_duration(probe: &serde_json::Value) -> Option<f64> {
+    probe["format"]["duration"].as_str()?.parse().ok()
+}
+
+fn has_audio_stream(probe: &serde_json::Value) -> bool {
+    probe["streams"]
+        .as_array()
+        .is_some_and(|streams| streams.iter().any(|s| s["codec_type"] == "audio"))
+}
+
+fn check_probe(probe: &serde_json::Value, expected: &ExpectedOutput) -> Result<(), String> {
+    let empty = Vec::new();
+    let streams = probe["streams"].as_array().unwrap_or(&empty);
+    let of_type = |kind: &str| -> Vec<&serde_json::Value> {
+        streams
+            .iter()
+            .filter(|s| s["codec_type"].as_str() == Some(kind))
+            .collect()
+    };
+    let (video, audio) = (of_type("video"), of_type("audio"));
+    let audio_streams = usize::from(expected.audio_codec.is_some());
+    if video.len() != 1 || audio.len() != audio_streams {
+        return Err(format!(
+            "Expected 1 video and {audio_streams} audio stream, found {} video and {} audio",
+            video.len(),
+            audio.len()
+        ));
+    }
+
+    let video_codec = video[0]["codec_name"].as_str().unwrap_or("unknown");
+    if video_codec != expected.video_codec {
+        return Err(format!("Video codec is {video_codec}, expected {}", expected.video_codec));
+    }
+    if let Some(expected_audio) = expected.audio_codec {
+        let audio_codec = audio[0]["codec_name"].as_str().unwrap_or("unknown");
+        if audio_codec != expected_audio {
+            return Err(format!("Audio codec is {audio_codec}, expected {expected_audio}"));
+        }
+    }
+
+    let duration = probe_duration(probe).ok_or("Output has no duration")?;
+    if (duration - expected.duration_secs).abs() > DURATION_TOLERANCE_SECS {
+        return Err(format!(
+            "Duration is {duration:.2}s, expected {:.2}s",
+            expected.duration_secs
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_until_stopped() {
+        let stop = CancellationToken::new();
+        let (status, _) = run_until_stopped(Command::new("true"), &stop).unwrap().unwrap();
+        assert!(status.success());
+
+        stop.cancel();
+        let mut sleep = Command::new("sleep");
+        sleep.arg("30");
+        let started = std::time::Instant::now();
+        assert!(run_until_stopped(sleep, &stop).unwrap().is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_check_probe() {
+        let expected = OutputFormat::Mp4.expected(8.0);
+        let probe = serde_json::json!({
+            "streams": [
+                {"codec_type": "video", "codec_name": "h264"},
+                {"codec_type": "audio", "codec_name": "aac"}
+            ],
+            "format": {"duration": "8.021000"}
+        });
+        assert!(check_probe(&probe, &expected).is_ok());
+
+        let no_audio = serde_json::json!({
+            "streams": [{"codec_type": "video", "codec_name": "h264"}],
+            "format": {"duration": "8.0"}
+        });
+        assert!(check_probe(&no_audio, &expected).unwrap_err().contains("0 audio"));
+
+        let mut short = probe.clone();
+        short["format"]["duration"] = serde_json::json!("2.5");
+        assert!(check_probe(&short, &expected).unwrap_err().starts_with("Duration"));
+
+        let gif = OutputFormat::parse("GIF", "vp9").unwrap();
+        let gif_probe = serde_json::json!({
+            "streams": [{"codec_type": "video", "codec_name": "gif"}],
+            "format": {"duration": "8.0"}
+        });
+        assert!(check_probe(&gif_probe, &gif.expected(8.0)).is_ok());
+        assert!(check_probe(&probe, &gif.expected(8.0)).is_err());
+        assert_eq!(OutputFormat::parse("webm", "av1").unwrap().expected(8.0).video_codec, "av1");
+        assert!(OutputFormat::parse("mov", "vp9").is_err());
+    }
+
+    #[test]
+    fn test_caption_text_and_escaping() {
+        assert_eq!(CaptionContent::parse("").unwrap(), CaptionContent::None);
+        assert!(CaptionContent::parse("subtitle").is_err());
+        assert_eq!(CaptionContent::None.text("hi", "me"), None);
+        assert_eq!(CaptionContent::Author.text("hi", "@me").as_deref(), Some("@me"));
+        assert_eq!(CaptionContent::Both.text("hi", "me").as_deref(), Some("@me\nhi"));
+        assert_eq!(CaptionContent::Both.text("  ", "me").as_deref(), Some("@me"));
+
+        let long = "word ".repeat(40);
+        let wrapped = CaptionContent::Title.text(&long, "").unwrap();
+        assert_eq!(wrapped.lines().count(), CAPTION_MAX_LINES);
+        assert!(wrapped.ends_with('…'));
+        assert!(wrapped.lines().all(|l| l.chars().count() <= CAPTION_LINE_CHARS + 1));
+
+        assert_eq!(escape_filter_arg("it's 1:1, ok"), r"it\\\'s 1\\:1\, ok");
+        let caption = Caption {
+            text: "[a]".into(),
+            position: CaptionPosition::parse("TOP").unwrap(),
+            font: "/fonts/x.ttf".into(),
+            font_size: 48,
+        };
+        let filter = caption.drawtext();
+        assert!(filter.starts_with(r"drawtext=fontfile=/fonts/x.ttf:text=\[a\]:"));
+        assert!(filter.ends_with(":y=160"));
+    }
+
+    #[test]
+    fn test_audio_timed_secs_per_image() {
+        assert_eq!(audio_timed_secs_per_image(30.0, 3, 180.0), 10.0);
+        // Rounded to whole frames at 25fps
+        assert_eq!(audio_timed_secs_per_image(10.0, 3, 180.0), 3.32);
+        assert_eq!(audio_timed_secs_per_image(600.0, 2, 180.0), 90.0);
+        assert_eq!(audio_timed_secs_per_image(2.0, 10, 180.0), MIN_SECS_PER_IMAGE);
+        assert_eq!(Timing::parse("Audio").unwrap(), Timing::Audio);
+        assert!(Timing::parse("beat").is_err());
+    }
+
+    #[test]
+    fn test_native_image_durations() {
+        let timed = [
+            serde_json::json!({"format_id": "image-1", "duration": 2.4}),
+            serde_json::json!({"format_id": "image-2", "duration": "1.01"}),
+        ];
+        let refs: Vec<&serde_json::Value> = timed.iter().collect();
+        assert_eq!(native_image_durations(&refs, 180.0), Some(vec![2.4, 1.0]));
+        // Capped overall; later images keep the minimum
+        assert_eq!(native_image_durations(&refs, 2.0), Some(vec![2.0, MIN_SECS_PER_IMAGE]));
+
+        let untimed = serde_json::json!({"format_id": "image-3"});
+        let partial = vec![&timed[0], &untimed];
+        assert_eq!(native_image_durations(&partial, 180.0), None);
+    }
+
+    #[test]
+    fn test_has_audio_stream() {
+        assert!(has_audio_stream(&serde_json::json!({
+            "streams": [{"codec_type": "video"}, {"codec_type": "audio"}]
+        })));
+        assert!(!has_audio_stream(&serde_json::json!({"streams": [{"codec_type": "video"}]})));
+        assert!(!has_audio_stream(&serde_json::json!({})));
+    }
+}
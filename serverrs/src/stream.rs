@@ -1,21 +1,78 @@
 use axum::body::Body;
-use axum::extract::Query;
+use axum::extract::{Json, Query};
 use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
-use futures_util::StreamExt;
+use futures_util::stream::BoxStream;
+use futures_util::{Stream, StreamExt};
 use serde::Deserialize;
-use tracing::error;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
 
+use crate::blocklist::Subject;
+use crate::cache::StreamSlot;
+use crate::checksum::{sha256_file, CHECKSUM_HEADER};
 use crate::config::Settings;
 use crate::encryption::decrypt;
+use crate::features::feature_for_type;
+use crate::streams::{self, StreamMeta};
+use crate::usage;
+use crate::user_agents::UserAgentPool;
+use crate::{feature_disabled, AppState};
 
 #[derive(Deserialize)]
 pub struct DownloadQuery {
     pub data: String,
+    /// "file": download to the server first, then serve the complete file
+    pub mode: Option<String>,
+    /// Parallel range requests for large files (capped by SEGMENTED_MAX_CONNECTIONS)
+    pub connections: Option<usize>,
+    /// inline (play/show in the page) or attachment (save dialog); by
+    /// default images are inline, video and audio attachments
+    pub disposition: Option<Disposition>,
+}
+
+/// Content-Disposition of a served file.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+impl Disposition {
+    /// `requested`, else inline for images so they can be embedded with
+    /// <img>, attachment for everything else.
+    fn resolve(requested: Option<Self>, content_type: &str) -> Self {
+        requested.unwrap_or(if content_type.starts_with("image/") {
+            Self::Inline
+        } else {
+            Self::Attachment
+        })
+    }
+
+    fn header_value(self, filename: &str) -> String {
+        let kind = match self {
+            Self::Inline => "inline",
+            Self::Attachment => "attachment",
+        };
+        format!("{kind}; filename=\"{filename}\"")
+    }
+}
+
+/// A resolved CDN download: where to fetch it and how to present it.
+struct CdnTarget {
+    url: String,
+    headers: HeaderMap,
+    content_type: &'static str,
+    filename: String,
+    disposition: Disposition,
+    filesize: Option<i64>,
 }
 
 /// Content type mapping
-fn content_type_info(file_type: &str) -> (&str, &str) {
+fn content_type_info(file_type: &str) -> (&'static str, &'static str) {
     match file_type {
         "mp3" => ("audio/mpeg", "mp3"),
         "video" => ("video/mp4", "mp4"),
@@ -36,9 +93,11 @@ fn safe_filename(author: &str, ext: &str) -> String {
 /// GET /download — Download file using encrypted data token
 pub async fn download_handler(
     Query(query): Query<DownloadQuery>,
-    settings: Settings,
-    http_client: reqwest::Client,
+    headers: &HeaderMap,
+    client: String,
+    state: &AppState,
 ) -> impl IntoResponse {
+    let settings = &state.settings;
     if query.data.is_empty() {
         return (
             StatusCode::BAD_REQUEST,
@@ -88,18 +147,36 @@ pub async fn download_handler(
         _ => return (StatusCode::BAD_REQUEST, "No download URL provided").into_response(),
     };
 
+    if let Some(hit) = state.blocklist.check(&token_subject(&download_data)) {
+        return hit.into_response();
+    }
+    if let Some(resp) = reject_disabled(&download_data, settings) {
+        return resp;
+    }
+
     let (content_type, ext) = content_type_info(file_type);
     let filename = safe_filename(author, ext);
 
-    stream_from_cdn(http_client, &url, None, content_type, &filename, download_data["filesize"].as_i64()).await
+    let meta = stream_meta("download", &download_data, client, &filename);
+    let target = CdnTarget {
+        url,
+        headers: outbound_headers(&download_data, settings, &state.user_agents),
+        content_type,
+        filename,
+        disposition: Disposition::resolve(query.disposition, content_type),
+        filesize: download_data["filesize"].as_i64(),
+    };
+    deliver(state, target, &query, headers, meta).await
 }
 
 /// GET /stream — Stream video/audio directly via pre-extracted CDN URL + auth headers
 pub async fn stream_handler(
     Query(query): Query<DownloadQuery>,
-    settings: Settings,
-    http_client: reqwest::Client,
+    headers: &HeaderMap,
+    client: String,
+    state: &AppState,
 ) -> impl IntoResponse {
+    let settings = &state.settings;
     if query.data.is_empty() {
         return (
             StatusCode::BAD_REQUEST,
@@ -145,6 +222,13 @@ pub async fn stream_handler(
         }
     };
 
+    if let Some(hit) = state.blocklist.check(&token_subject(&stream_data)) {
+        return hit.into_response();
+    }
+    if let Some(resp) = reject_disabled(&stream_data, settings) {
+        return resp;
+    }
+
     let file_type = stream_data["type"].as_str().unwrap_or("video");
     let (content_type, ext) = if file_type == "mp3" || file_type == "audio" {
         ("audio/mpeg", "mp3")
@@ -153,46 +237,285 @@ pub async fn stream_handler(
     };
     let filename = safe_filename(author, ext);
 
-    // Build request headers from pre-extracted auth data
-    let req_headers = stream_data["http_headers"].as_object().cloned();
-
-    stream_from_cdn(
-        http_client,
-        &url,
-        req_headers,
+    let meta = stream_meta("stream", &stream_data, client, &filename);
+    let target = CdnTarget {
+        url,
+        headers: outbound_headers(&stream_data, settings, &state.user_agents),
         content_type,
-        &filename,
-        stream_data["filesize"].as_i64(),
-    )
-    .await
+        filename,
+        disposition: Disposition::resolve(query.disposition, content_type),
+        filesize: stream_data["filesize"].as_i64(),
+    };
+    deliver(state, target, &query, headers, meta).await
 }
 
-/// Stream content from CDN URL, proxying through our server
-async fn stream_from_cdn(
-    http_client: reqwest::Client,
-    url: &str,
-    req_headers: Option<serde_json::Map<String, serde_json::Value>>,
-    content_type: &str,
-    filename: &str,
-    filesize: Option<i64>,
+/// What GET /admin/streams shows for a /stream or /download request.
+fn stream_meta(endpoint: &'static str, token: &serde_json::Value, client: String, filename: &str) -> StreamMeta {
+    StreamMeta {
+        endpoint,
+        platform: token["platform"].as_str().unwrap_or("tiktok").to_string(),
+        format: token["type"].as_str().unwrap_or("video").to_string(),
+        video_id: token["video_id"].as_str().map(String::from),
+        client,
+        filename: filename.to_string(),
+        started_at: streams::unix_now(),
+    }
+}
+
+/// Serve the target and, when it succeeds, count its body as an active
+/// stream until the client has it all (or disconnects, or an admin ends it).
+/// Requests with a metered API key are refused once the key's daily
+/// bandwidth quota is used up, and their bytes are added to its usage.
+async fn deliver(
+    state: &AppState,
+    target: CdnTarget,
+    query: &DownloadQuery,
+    headers: &HeaderMap,
+    meta: StreamMeta,
 ) -> Response {
-    let mut request = http_client.get(url);
+    let api_key = state
+        .settings
+        .billing_key(headers.get("x-api-key").and_then(|v| v.to_str().ok()));
+    let remaining = match api_key {
+        Some(key) => usage::remaining(state, key).await,
+        None => None,
+    };
+    if remaining == Some(0) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [
+                ("Retry-After", usage::secs_until_reset().to_string()),
+                ("X-Quota-Remaining", "0".to_string()),
+            ],
+            Json(serde_json::json!({
+                "error": "Daily bandwidth quota exceeded",
+                "code": "QUOTA_EXCEEDED",
+            })),
+        )
+            .into_response();
+    }
+
+    let slot = match acquire_stream_slot(state, api_key, &meta.client).await {
+        Ok(slot) => slot,
+        Err(resp) => return resp,
+    };
+
+    let resp = serve_target(state, target, query).await;
+    if !resp.status().is_success() {
+        return resp;
+    }
+    let mut handle = state.streams.register(meta);
+    if let Some(key) = api_key {
+        usage::meter(&mut handle, state, key);
+    }
+    if let Some(slot) = slot {
+        handle.on_finish(move |_| drop(slot));
+    }
+    let (mut parts, body) = resp.into_parts();
+    if let Some(remaining) = remaining {
+        parts.headers.insert("X-Quota-Remaining", HeaderValue::from(remaining));
+    }
+    Response::from_parts(parts, Body::from_stream(streams::track(body.into_data_stream(), handle)))
+}
+
+/// One of the client's MAX_STREAMS_PER_CLIENT slots, held until the
+/// response body is done; 429 when they are all taken. Clients are told
+/// apart by metered API key, else by address (streams::client_addr);
+/// instances without a cache aren't limited.
+#[allow(clippy::result_large_err)]
+async fn acquire_stream_slot(
+    state: &AppState,
+    api_key: Option<&str>,
+    client_addr: &str,
+) -> Result<Option<StreamSlot>, Response> {
+    let limit = state.settings.max_streams_per_client;
+    let client = match api_key {
+        _ if limit == 0 => return Ok(None),
+        Some(key) => format!("key:{key}"),
+        None => format!("ip:{client_addr}"),
+    };
+    let Some(cache) = &state.redis else {
+        return Ok(None);
+    };
+    let id = format!("{}:{}", state.settings.instance_id, streams::unix_nanos());
+    match cache
+        .try_acquire_stream_slot(&client, &id, limit, state.settings.stream_slot_lease_secs)
+        .await
+    {
+        Some(slot) => Ok(Some(slot)),
+        None => {
+            warn!("🚦 {client} already has {limit} streams open");
+            Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                [("Retry-After", "10")],
+                Json(serde_json::json!({
+                    "error": format!("Too many simultaneous streams (max {limit})"),
+                    "code": "TOO_MANY_STREAMS",
+                })),
+            )
+                .into_response())
+        }
+    }
+}
+
+async fn serve_target(state: &AppState, target: CdnTarget, query: &DownloadQuery) -> Response {
+    let settings = &state.settings;
+    let http_client = state.http_client.clone();
+    let connections = query
+        .connections
+        .unwrap_or(1)
+        .clamp(1, settings.segmented_max_connections.max(1));
+    match query.mode.as_deref() {
+        Some("file") => serve_via_file(state, target).await,
+        None | Some("stream") if connections > 1 => {
+            stream_segmented(http_client, settings, target, connections).await
+        }
+        None | Some("stream") => {
+            stream_from_cdn(http_client, target, settings.stream_max_resumes).await
+        }
+        Some(other) => (
+            StatusCode::BAD_REQUEST,
+            format!("Unknown mode: {other} (expected \"stream\" or \"file\")"),
+        )
+            .into_response(),
+    }
+}
+
+/// CDN URL and request headers for the sound behind another post's
+/// `download_link.mp3` token, for slideshows that borrow it. Blocklist and
+/// feature flags apply as if the link itself were used.
+#[allow(clippy::result_large_err)]
+pub fn audio_from_token(state: &AppState, data: &str) -> Result<(String, Vec<(String, String)>), Response> {
+    let settings = &state.settings;
+    let invalid = |message: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": message, "code": "INVALID_AUDIO"})),
+        )
+            .into_response()
+    };
+    let token: serde_json::Value = match decrypt(data, &settings.encryption_key) {
+        Ok(d) => serde_json::from_str(&d).map_err(|_| invalid("Invalid audio token"))?,
+        Err(e) => return Err(invalid(&format!("Audio token decryption failed: {e}"))),
+    };
+    if feature_for_type(token["type"].as_str().unwrap_or("video")) != "audio" {
+        return Err(invalid("Audio token is not an audio link"));
+    }
+    let url = match token["url"].as_str() {
+        Some(u) if !u.is_empty() => u.to_string(),
+        _ => return Err(invalid("Audio token has no URL")),
+    };
+
+    if let Some(hit) = state.blocklist.check(&token_subject(&token)) {
+        return Err(hit.into_response());
+    }
+    if let Some(resp) = reject_disabled(&token, settings) {
+        return Err(resp);
+    }
 
-    // Forward pre-extracted headers (Referer, Cookie, etc.)
-    if let Some(headers) = req_headers {
-        for (k, v) in &headers {
+    let headers = outbound_headers(&token, settings, &state.user_agents)
+        .iter()
+        .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+        .collect();
+    Ok((url, headers))
+}
+
+/// Blocklist subject for a token; links issued before a takedown stop
+/// working as soon as the rule is loaded.
+fn token_subject(token: &serde_json::Value) -> Subject<'_> {
+    Subject {
+        url: token["url"].as_str(),
+        video_id: token["video_id"].as_str(),
+        creator: token["creator"].as_str(),
+    }
+}
+
+/// Tokens issued before a platform/feature was disabled stop working too.
+fn reject_disabled(token: &serde_json::Value, settings: &Settings) -> Option<Response> {
+    let platform = token["platform"].as_str().unwrap_or("tiktok");
+    let feature = feature_for_type(token["type"].as_str().unwrap_or("video"));
+    if settings.features.enabled(platform, feature) {
+        None
+    } else {
+        Some(feature_disabled(platform, Some(feature)))
+    }
+}
+
+/// Headers for the CDN request: the token's pre-extracted headers (Referer,
+/// Cookie, etc.), a pooled UA if the token has none, then PLATFORM_HEADERS
+/// overriding both.
+fn outbound_headers(
+    token: &serde_json::Value,
+    settings: &Settings,
+    user_agents: &UserAgentPool,
+) -> HeaderMap {
+    let mut outbound = HeaderMap::new();
+
+    if let Some(headers) = token["http_headers"].as_object() {
+        for (k, v) in headers {
             if let Some(val) = v.as_str() {
                 if let (Ok(name), Ok(value)) = (
                     HeaderName::try_from(k.as_str()),
                     HeaderValue::from_str(val),
                 ) {
-                    request = request.header(name, value);
+                    outbound.insert(name, value);
                 }
             }
         }
     }
 
-    let response = match request.send().await {
+    // Tokens without the extraction's UA (older links) get one from the pool
+    if !outbound.contains_key("user-agent") {
+        if let Some(ua) = user_agents.next().and_then(|ua| HeaderValue::from_str(&ua).ok()) {
+            outbound.insert("user-agent", ua);
+        }
+    }
+
+    let platform = token["platform"].as_str().unwrap_or("tiktok");
+    for (k, v) in settings.headers_for(platform) {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(k.as_str()), HeaderValue::from_str(&v)) {
+            outbound.insert(name, value);
+        }
+    }
+
+    outbound
+}
+
+/// Response carrying a downloaded file, inline or as an attachment.
+fn file_response(
+    body: Body,
+    content_type: &str,
+    filename: &str,
+    disposition: Disposition,
+    content_length: Option<HeaderValue>,
+) -> Response {
+    let mut resp = Response::new(body);
+    *resp.status_mut() = StatusCode::OK;
+    let headers = resp.headers_mut();
+    headers.insert("Content-Type", HeaderValue::from_str(content_type).unwrap());
+    headers.insert(
+        "Content-Disposition",
+        HeaderValue::from_str(&disposition.header_value(filename)).unwrap(),
+    );
+    headers.insert(
+        "X-Filename",
+        HeaderValue::from_str(filename).unwrap_or_else(|_| HeaderValue::from_static("download")),
+    );
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+    if let Some(len) = content_length {
+        headers.insert("Content-Length", len);
+    }
+    resp
+}
+
+/// Stream content from CDN URL, proxying through our server
+async fn stream_from_cdn(
+    http_client: reqwest::Client,
+    target: CdnTarget,
+    max_resumes: u32,
+) -> Response {
+    let url = &target.url;
+    let response = match http_client.get(url).headers(target.headers.clone()).send().await {
         Ok(r) => r,
         Err(e) => {
             error!("HTTP error streaming from CDN: {e}");
@@ -204,7 +527,7 @@ async fn stream_from_cdn(
         error!(
             "CDN returned status {} for {}",
             response.status(),
-            &url[..url.len().min(80)]
+            url.chars().take(80).collect::<String>()
         );
         return (
             StatusCode::BAD_GATEWAY,
@@ -213,51 +536,609 @@ async fn stream_from_cdn(
             .into_response();
     }
 
-    // Build response headers
-    let mut resp_headers = HeaderMap::new();
-    resp_headers.insert(
-        "Content-Disposition",
-        HeaderValue::from_str(&format!("attachment; filename=\"{filename}\"")).unwrap(),
+    // Content-Length from token or upstream
+    let content_length = target
+        .filesize
+        .filter(|size| *size > 0)
+        .map(|size| HeaderValue::from(size as u64))
+        .or_else(|| response.headers().get("content-length").cloned());
+
+    // Stream body, resuming from the current offset if the upstream drops
+    // or ends short of the announced length
+    let stream = resumable_stream(ResumeState {
+        inner: response.bytes_stream().boxed(),
+        http_client,
+        url: target.url,
+        headers: target.headers,
+        offset: 0,
+        expected: content_length.as_ref().and_then(|v| v.to_str().ok()?.parse().ok()),
+        resumes_left: max_resumes,
+    });
+
+    file_response(
+        Body::from_stream(stream),
+        target.content_type,
+        &target.filename,
+        target.disposition,
+        content_length,
+    )
+}
+
+struct ResumeState {
+    inner: BoxStream<'static, reqwest::Result<bytes::Bytes>>,
+    http_client: reqwest::Client,
+    url: String,
+    headers: HeaderMap,
+    /// Bytes already sent to the client
+    offset: u64,
+    /// Content-Length sent to the client; a body ending before it is truncated
+    expected: Option<u64>,
+    resumes_left: u32,
+}
+
+/// Upstream body that, on a chunk error or a clean end before `expected`
+/// bytes, re-requests the rest with `Range: bytes=<offset>-` so the client
+/// doesn't get a truncated file.
+fn resumable_stream(state: ResumeState) -> impl Stream<Item = std::io::Result<bytes::Bytes>> {
+    futures_util::stream::unfold(Some(state), |state| async move {
+        let mut st = state?;
+        loop {
+            let broken = match st.inner.next().await {
+                Some(Ok(chunk)) => {
+                    st.offset += chunk.len() as u64;
+                    return Some((Ok(chunk), Some(st)));
+                }
+                None if st.expected.is_none_or(|n| st.offset >= n) => return None,
+                None => format!("body ended at {} of {} bytes", st.offset, st.expected.unwrap_or(0)),
+                Some(Err(e)) => e.to_string(),
+            };
+            if st.resumes_left == 0 {
+                error!("Error streaming chunk at {} bytes: {broken}", st.offset);
+                return Some((Err(std::io::Error::other(broken)), None));
+            }
+            st.resumes_left -= 1;
+            warn!(
+                "Upstream broke at {} bytes ({broken}), resuming with Range ({} left)",
+                st.offset, st.resumes_left
+            );
+            let resumed = st
+                .http_client
+                .get(&st.url)
+                .headers(st.headers.clone())
+                .header("Range", format!("bytes={}-", st.offset))
+                .send()
+                .await;
+            st.inner = match resumed {
+                Ok(r) if r.status() == reqwest::StatusCode::PARTIAL_CONTENT => {
+                    r.bytes_stream().boxed()
+                }
+                Ok(r) => {
+                    // Can't splice a full 200 response into a half-sent body
+                    error!("CDN rejected resume at {} bytes: {}", st.offset, r.status());
+                    let err = std::io::Error::other(format!("resume rejected: {}", r.status()));
+                    return Some((Err(err), None));
+                }
+                Err(e) => futures_util::stream::once(async move { Err(e) }).boxed(),
+            };
+        }
+    })
+}
+
+/// Fetch a large file over `connections` parallel range requests and
+/// stitch the segments back together in order. Falls back to a plain
+/// stream when the file is small or the CDN doesn't support ranges.
+async fn stream_segmented(
+    http_client: reqwest::Client,
+    settings: &Settings,
+    target: CdnTarget,
+    connections: usize,
+) -> Response {
+    let segment_size = settings.segment_size_mb.max(1) * 1024 * 1024;
+    // Confirms range support and the exact size before committing to a 200
+    let size = match probe_size(&http_client, &target).await {
+        Some(s) if s > segment_size => s,
+        _ => return stream_from_cdn(http_client, target, settings.stream_max_resumes).await,
+    };
+
+    info!(
+        "⚡ Segmented download of {} ({size} bytes, {connections} connections)",
+        target.filename
     );
-    resp_headers.insert(
-        "X-Filename",
-        HeaderValue::from_str(filename).unwrap_or_else(|_| HeaderValue::from_static("download")),
+    let ranges: Vec<(u64, u64)> = (0..size)
+        .step_by(segment_size as usize)
+        .map(|start| (start, (start + segment_size).min(size) - 1))
+        .collect();
+    let url = target.url.clone();
+    let headers = target.headers.clone();
+    // `buffered` runs up to `connections` fetches at once but yields in order
+    let stream = futures_util::stream::iter(ranges)
+        .map(move |(start, end)| fetch_segment(http_client.clone(), url.clone(), headers.clone(), start, end))
+        .buffered(connections);
+
+    file_response(
+        Body::from_stream(stream),
+        target.content_type,
+        &target.filename,
+        target.disposition,
+        Some(HeaderValue::from(size)),
+    )
+}
+
+/// Total size from a 1-byte range request, if the CDN supports ranges.
+async fn probe_size(http_client: &reqwest::Client, target: &CdnTarget) -> Option<u64> {
+    let response = http_client
+        .get(&target.url)
+        .headers(target.headers.clone())
+        .header("Range", "bytes=0-0")
+        .send()
+        .await
+        .ok()?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return None;
+    }
+    content_range_total(response.headers())
+}
+
+/// "bytes 0-0/12345" -> 12345
+fn content_range_total(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    headers
+        .get("content-range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit('/').next())
+        .and_then(|v| v.parse().ok())
+}
+
+/// One segment `start..=end`, retried once before failing the response.
+async fn fetch_segment(
+    http_client: reqwest::Client,
+    url: String,
+    headers: HeaderMap,
+    start: u64,
+    end: u64,
+) -> std::io::Result<bytes::Bytes> {
+    let mut last_error = String::new();
+    for attempt in 0..2 {
+        if attempt > 0 {
+            warn!("Retrying segment {start}-{end}: {last_error}");
+        }
+        let response = match http_client
+            .get(&url)
+            .headers(headers.clone())
+            .header("Range", format!("bytes={start}-{end}"))
+            .send()
+            .await
+        {
+            Ok(r) if r.status() == reqwest::StatusCode::PARTIAL_CONTENT => r,
+            Ok(r) => {
+                last_error = format!("CDN returned status {}", r.status());
+                continue;
+            }
+            Err(e) => {
+                last_error = e.to_string();
+                continue;
+            }
+        };
+        match response.bytes().await {
+            Ok(b) if b.len() as u64 == end - start + 1 => return Ok(b),
+            Ok(b) => last_error = format!("short segment: {} bytes", b.len()),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    error!("Segment {start}-{end} failed: {last_error}");
+    Err(std::io::Error::other(last_error))
+}
+
+/// mode=file: download the whole file to TEMP_DIR first (resuming dropped
+/// connections with Range requests, or via aria2c when configured), then
+/// serve it with an exact Content-Length.
+async fn serve_via_file(state: &AppState, target: CdnTarget) -> Response {
+    let hash = state.settings.checksum_header;
+    let downloaded = match download_to_file(state, &target, hash).await {
+        Ok(d) => d,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+
+    info!("📦 Serving {} ({} bytes) from file", target.filename, downloaded.size);
+    let mut resp = file_response(
+        Body::from_stream(tokio_util::io::ReaderStream::new(downloaded.file)),
+        target.content_type,
+        &target.filename,
+        target.disposition,
+        Some(HeaderValue::from(downloaded.size)),
     );
-    resp_headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+    if let Some(sha256) = downloaded.sha256 {
+        cache_checksum(state, &target.url, &sha256, downloaded.size).await;
+        if let Ok(value) = HeaderValue::from_str(&sha256) {
+            resp.headers_mut().insert(CHECKSUM_HEADER, value);
+        }
+    }
+    resp
+}
 
-    // Content-Length from token or upstream
-    if let Some(size) = filesize {
-        if size > 0 {
-            resp_headers.insert(
-                "Content-Length",
-                HeaderValue::from_str(&size.to_string()).unwrap(),
-            );
+/// A completed file-mode download.
+struct Downloaded {
+    /// Open handle; the data stays readable after the work dir is removed
+    file: tokio::fs::File,
+    size: u64,
+    sha256: Option<String>,
+}
+
+/// Download `target` into a temp folder, optionally hashing it, and return
+/// an open handle to the result.
+async fn download_to_file(
+    state: &AppState,
+    target: &CdnTarget,
+    hash: bool,
+) -> Result<Downloaded, (StatusCode, String)> {
+    let settings = &state.settings;
+    let limit = settings.max_download_bytes;
+    if let Some(size) = target.filesize.map(|s| s as u64).filter(|s| limit > 0 && *s > limit) {
+        info!("Refusing {size} byte download (MAX_DOWNLOAD_BYTES {limit})");
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, too_large(limit)));
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    // A folder, so the periodic cleanup removes it if we crash mid-download
+    let work_dir = settings.temp_dir.join(format!("file_{nanos}"));
+    if let Err(e) = tokio::fs::create_dir_all(&work_dir).await {
+        error!("Failed to create work dir: {e}");
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to create work dir".into()));
+    }
+    // Also removed if the client disconnects and this future is dropped
+    let work_dir_guard = RemoveDirOnDrop(work_dir.clone());
+    let part_path = work_dir.join("download.part");
+    let final_path = work_dir.join("download");
+
+    let fetched = match state.aria2.as_deref() {
+        Some(aria2) => aria2.download(&target.url, &target.headers, &work_dir, "download").await,
+        None => {
+            fetch_to_part_file(
+                &state.http_client,
+                target,
+                &part_path,
+                settings.file_mode_max_resumes,
+                limit,
+            )
+            .await
+        }
+    };
+    let size = match fetched {
+        Ok(size) => size,
+        Err(e) => {
+            drop(work_dir_guard);
+            if is_too_large(&e) {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, e));
+            }
+            error!("File-mode download failed: {e}");
+            return Err((StatusCode::BAD_GATEWAY, format!("CDN download failed: {e}")));
         }
+    };
+
+    let renamed = match state.aria2 {
+        Some(_) => Ok(()),
+        None => tokio::fs::rename(&part_path, &final_path).await,
+    };
+    let sha256 = match (&renamed, hash) {
+        (Ok(()), true) => {
+            let path = final_path.clone();
+            match tokio::task::spawn_blocking(move || sha256_file(&path)).await {
+                Ok(Ok(sha256)) => Some(sha256),
+                Ok(Err(e)) => {
+                    warn!("Failed to hash downloaded file: {e}");
+                    None
+                }
+                Err(e) => {
+                    warn!("Hash task failed: {e}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let opened = match renamed {
+        Ok(()) => tokio::fs::File::open(&final_path).await,
+        Err(e) => Err(e),
+    };
+    drop(work_dir_guard);
+    match opened {
+        Ok(file) => Ok(Downloaded { file, size, sha256 }),
+        Err(e) => {
+            error!("Failed to open downloaded file: {e}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to read downloaded file".into()))
+        }
+    }
+}
+
+/// Work folder removed with everything in it when dropped.
+struct RemoveDirOnDrop(std::path::PathBuf);
+
+impl Drop for RemoveDirOnDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A CDN file saved to disk ahead of time (scheduled job prefetch).
+pub struct Prefetched {
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub content_type: &'static str,
+    pub filename: String,
+}
+
+/// Download the file behind a /download or /stream link into `dest`.
+pub async fn prefetch_link(state: &AppState, link: &str, dest: &Path) -> Result<Prefetched, String> {
+    let settings = &state.settings;
+    let (_, data) = link.split_once("data=").ok_or("link has no data token")?;
+    let token: serde_json::Value = serde_json::from_str(&decrypt(data, &settings.encryption_key)?)
+        .map_err(|e| format!("invalid token: {e}"))?;
+    let url = token["url"].as_str().filter(|u| !u.is_empty()).ok_or("token has no URL")?;
+
+    let (content_type, ext) = content_type_info(token["type"].as_str().unwrap_or("video"));
+    let target = CdnTarget {
+        url: url.to_string(),
+        headers: outbound_headers(&token, settings, &state.user_agents),
+        content_type,
+        filename: safe_filename(token["author"].as_str().unwrap_or("download"), ext),
+        disposition: Disposition::Attachment,
+        filesize: token["filesize"].as_i64(),
+    };
+    let mut downloaded = download_to_file(state, &target, true)
+        .await
+        .map_err(|(_, e)| e)?;
+
+    let part = dest.with_extension("part");
+    let copied = async {
+        let mut out = tokio::fs::File::create(&part).await?;
+        tokio::io::copy(&mut downloaded.file, &mut out).await?;
+        out.flush().await?;
+        tokio::fs::rename(&part, dest).await
     }
-    if !resp_headers.contains_key("Content-Length") {
-        if let Some(cl) = response.headers().get("content-length") {
-            resp_headers.insert("Content-Length", cl.clone());
+    .await;
+    if let Err(e) = copied {
+        let _ = tokio::fs::remove_file(&part).await;
+        return Err(format!("failed to save {}: {e}", dest.display()));
+    }
+    Ok(Prefetched {
+        size: downloaded.size,
+        sha256: downloaded.sha256,
+        content_type,
+        filename: target.filename,
+    })
+}
+
+/// Serve a prefetched file as an attachment.
+pub async fn serve_prefetched(path: &Path, content_type: &str, filename: &str) -> Response {
+    let file = match tokio::fs::File::open(path).await {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Failed to open prefetched file {}: {e}", path.display());
+            return (StatusCode::NOT_FOUND, "Prefetched file not found").into_response();
         }
+    };
+    let size = file.metadata().await.ok().map(|m| HeaderValue::from(m.len()));
+    file_response(
+        Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+        content_type,
+        filename,
+        Disposition::Attachment,
+        size,
+    )
+}
+
+async fn cache_checksum(state: &AppState, url: &str, sha256: &str, size: u64) {
+    if let Some(ref redis) = state.redis {
+        let entry = serde_json::json!({ "sha256": sha256, "size": size });
+        redis
+            .set_checksum(url, &entry.to_string(), state.settings.checksum_ttl_secs)
+            .await;
     }
+}
 
-    // Stream body
-    let stream = response.bytes_stream().map(|result| {
-        result.map_err(|e| {
-            error!("Error streaming chunk: {e}");
-            std::io::Error::new(std::io::ErrorKind::Other, e)
-        })
-    });
+/// GET /checksum — SHA-256 and size of the file behind a download/stream
+/// token. Served from cache when the file was already downloaded in file
+/// mode; otherwise the file is fetched and hashed server-side.
+pub async fn checksum_handler(Query(query): Query<DownloadQuery>, state: &AppState) -> Response {
+    let settings = &state.settings;
+    let decrypted = match decrypt(&query.data, &settings.encryption_key) {
+        Ok(d) => d,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": format!("Decryption failed: {e}")})),
+            )
+                .into_response()
+        }
+    };
+    let token: serde_json::Value = match serde_json::from_str(&decrypted) {
+        Ok(d) => d,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "Invalid decrypted data"})),
+            )
+                .into_response()
+        }
+    };
+    let url = match token["url"].as_str() {
+        Some(u) if !u.is_empty() => u.to_string(),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "Invalid decrypted data: missing url"})),
+            )
+                .into_response()
+        }
+    };
 
-    let body = Body::from_stream(stream);
+    if let Some(hit) = state.blocklist.check(&token_subject(&token)) {
+        return hit.into_response();
+    }
+    if let Some(resp) = reject_disabled(&token, settings) {
+        return resp;
+    }
 
-    let mut resp = Response::new(body);
-    *resp.status_mut() = StatusCode::OK;
-    resp.headers_mut()
-        .insert("Content-Type", HeaderValue::from_str(content_type).unwrap());
-    for (k, v) in resp_headers {
-        if let Some(key) = k {
-            resp.headers_mut().insert(key, v);
+    if let Some(ref redis) = state.redis {
+        if let Some(entry) = redis.get_checksum(&url).await {
+            if let Ok(mut entry) = serde_json::from_str::<serde_json::Value>(&entry) {
+                entry["cached"] = serde_json::Value::Bool(true);
+                return Json(entry).into_response();
+            }
         }
     }
-    resp
+
+    let (content_type, ext) = content_type_info(token["type"].as_str().unwrap_or(""));
+    let target = CdnTarget {
+        headers: outbound_headers(&token, settings, &state.user_agents),
+        filename: safe_filename(token["author"].as_str().unwrap_or("file"), ext),
+        url,
+        content_type,
+        disposition: Disposition::Attachment,
+        filesize: token["filesize"].as_i64(),
+    };
+    let downloaded = match download_to_file(state, &target, true).await {
+        Ok(d) => d,
+        Err((status, msg)) => {
+            return (status, Json(serde_json::json!({"error": msg}))).into_response()
+        }
+    };
+    let Some(sha256) = downloaded.sha256 else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to hash downloaded file"})),
+        )
+            .into_response();
+    };
+    cache_checksum(state, &target.url, &sha256, downloaded.size).await;
+    Json(serde_json::json!({
+        "sha256": sha256,
+        "size": downloaded.size,
+        "cached": false,
+    }))
+    .into_response()
+}
+
+/// Error for a download over MAX_DOWNLOAD_BYTES; see `is_too_large`.
+pub fn too_large(limit: u64) -> String {
+    format!("{TOO_LARGE} ({limit} bytes)")
+}
+
+pub fn is_too_large(error: &str) -> bool {
+    error.starts_with(TOO_LARGE)
+}
+
+const TOO_LARGE: &str = "File exceeds MAX_DOWNLOAD_BYTES";
+
+/// Outcome of one upstream request in file mode.
+enum Fetch {
+    Complete,
+    /// Connection dropped or body ended early; resume from the current offset
+    Interrupted(String),
+}
+
+/// Download into `part_path`, re-requesting the remainder with `Range` after
+/// interruptions (at most `max_resumes` times), giving up once it is over
+/// `max_bytes` (0 for no limit). Returns the final size.
+async fn fetch_to_part_file(
+    http_client: &reqwest::Client,
+    target: &CdnTarget,
+    part_path: &Path,
+    max_resumes: u32,
+    max_bytes: u64,
+) -> Result<u64, String> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(part_path)
+        .await
+        .map_err(|e| format!("Failed to create {}: {e}", part_path.display()))?;
+    let mut written = 0u64;
+    let mut total: Option<u64> = None;
+    let mut resumes = 0u32;
+
+    loop {
+        match fetch_range(http_client, target, &mut file, &mut written, &mut total, max_bytes).await? {
+            Fetch::Complete => break,
+            Fetch::Interrupted(reason) => {
+                resumes += 1;
+                if resumes > max_resumes {
+                    return Err(format!("gave up after {max_resumes} resumes: {reason}"));
+                }
+                warn!("Upstream interrupted at {written} bytes ({reason}), resuming ({resumes}/{max_resumes})");
+            }
+        }
+    }
+
+    file.flush()
+        .await
+        .map_err(|e| format!("Failed to flush {}: {e}", part_path.display()))?;
+    Ok(written)
+}
+
+/// One request for the bytes from `written` onwards, appended to `file`.
+async fn fetch_range(
+    http_client: &reqwest::Client,
+    target: &CdnTarget,
+    file: &mut tokio::fs::File,
+    written: &mut u64,
+    total: &mut Option<u64>,
+    max_bytes: u64,
+) -> Result<Fetch, String> {
+    let mut request = http_client.get(&target.url).headers(target.headers.clone());
+    if *written > 0 {
+        request = request.header("Range", format!("bytes={}-", *written));
+    }
+
+    let response = match request.send().await {
+        Ok(r) => r,
+        Err(e) => return Ok(Fetch::Interrupted(e.to_string())),
+    };
+
+    let status = response.status();
+    if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE && *total == Some(*written) {
+        return Ok(Fetch::Complete);
+    }
+    if status.is_server_error() {
+        return Ok(Fetch::Interrupted(format!("CDN returned status {status}")));
+    }
+    if !status.is_success() {
+        return Err(format!("CDN returned status {status}"));
+    }
+
+    if *written > 0 && status != reqwest::StatusCode::PARTIAL_CONTENT {
+        // Range ignored: start over
+        warn!("CDN ignored Range request, restarting download");
+        file.set_len(0).await.map_err(|e| e.to_string())?;
+        *written = 0;
+    }
+    if total.is_none() {
+        *total = content_range_total(response.headers())
+            .or_else(|| response.content_length().map(|len| len + *written));
+    }
+    let over = |size: u64| max_bytes > 0 && size > max_bytes;
+    if total.is_some_and(over) {
+        return Err(too_large(max_bytes));
+    }
+
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(c) => c,
+            Err(e) => return Ok(Fetch::Interrupted(e.to_string())),
+        };
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| format!("Failed to write download: {e}"))?;
+        *written += chunk.len() as u64;
+        if over(*written) {
+            return Err(too_large(max_bytes));
+        }
+    }
+
+    match *total {
+        Some(t) if *written < t => Ok(Fetch::Interrupted(format!("body ended at {}/{t} bytes", *written))),
+        _ => Ok(Fetch::Complete),
+    }
 }
@@ -0,0 +1,409 @@
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// What is being proxied, as shown by GET /admin/streams.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamMeta {
+    /// "stream" or "download"
+    pub endpoint: &'static str,
+    pub platform: String,
+    /// Token type: video, mp3, image...
+    pub format: String,
+    pub video_id: Option<String>,
+    /// Address of the client, see client_addr
+    pub client: String,
+    pub filename: String,
+    /// Unix seconds
+    pub started_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreamInfo {
+    pub id: u64,
+    #[serde(flatten)]
+    pub meta: StreamMeta,
+    pub bytes: u64,
+    pub bytes_per_sec: u64,
+}
+
+struct Entry {
+    meta: StreamMeta,
+    bytes: Arc<AtomicU64>,
+    started: Instant,
+    cancel: CancellationToken,
+}
+
+/// Bytes and streams per platform since startup.
+#[derive(Default)]
+struct PlatformTotals {
+    streams: AtomicU64,
+    bytes: AtomicU64,
+    /// Streams the client disconnected from before the end
+    client_aborts: AtomicU64,
+}
+
+/// In-process registry of responses being proxied by /stream and /download,
+/// for the active-stream and bandwidth metrics and /admin/streams.
+#[derive(Default)]
+pub struct StreamRegistry {
+    next_id: AtomicU64,
+    active: Mutex<BTreeMap<u64, Entry>>,
+    totals: Mutex<BTreeMap<String, Arc<PlatformTotals>>>,
+}
+
+impl StreamRegistry {
+    /// Register a stream; it is removed when the handle is dropped.
+    pub fn register(self: &Arc<Self>, meta: StreamMeta) -> StreamHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let totals = self
+            .totals
+            .lock()
+            .unwrap()
+            .entry(meta.platform.clone())
+            .or_default()
+            .clone();
+        totals.streams.fetch_add(1, Ordering::Relaxed);
+        let bytes = Arc::new(AtomicU64::new(0));
+        let cancel = CancellationToken::new();
+        self.active.lock().unwrap().insert(
+            id,
+            Entry {
+                meta,
+                bytes: bytes.clone(),
+                started: Instant::now(),
+                cancel: cancel.clone(),
+            },
+        );
+        StreamHandle {
+            registry: self.clone(),
+            id,
+            bytes,
+            totals,
+            cancel,
+            ended: false,
+            on_finish: Vec::new(),
+        }
+    }
+
+    /// Active streams, oldest first.
+    pub fn list(&self) -> Vec<StreamInfo> {
+        self.active
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, e)| {
+                let bytes = e.bytes.load(Ordering::Relaxed);
+                StreamInfo {
+                    id: *id,
+                    meta: e.meta.clone(),
+                    bytes,
+                    bytes_per_sec: rate(bytes, e.started),
+                }
+            })
+            .collect()
+    }
+
+    /// Cut an active stream off; false when it isn't active.
+    pub fn terminate(&self, id: u64) -> bool {
+        match self.active.lock().unwrap().get(&id) {
+            Some(entry) => {
+                entry.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Prometheus lines for active streams, current throughput and totals.
+    pub fn render_metrics(&self) -> String {
+        let mut active: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for e in self.active.lock().unwrap().values() {
+            let slot = active.entry(e.meta.platform.clone()).or_default();
+            slot.0 += 1;
+            slot.1 += rate(e.bytes.load(Ordering::Relaxed), e.started);
+        }
+        let totals = self.totals.lock().unwrap();
+
+        let mut out = String::new();
+        out.push_str("# HELP active_streams Responses currently proxied by /stream and /download.\n");
+        out.push_str("# TYPE active_streams gauge\n");
+        for platform in totals.keys() {
+            let (count, _) = active.get(platform).copied().unwrap_or_default();
+            let _ = writeln!(out, "active_streams{{platform=\"{platform}\"}} {count}");
+        }
+        out.push_str("# HELP stream_bytes_per_second Summed average throughput of the active streams.\n");
+        out.push_str("# TYPE stream_bytes_per_second gauge\n");
+        for platform in totals.keys() {
+            let (_, bps) = active.get(platform).copied().unwrap_or_default();
+            let _ = writeln!(out, "stream_bytes_per_second{{platform=\"{platform}\"}} {bps}");
+        }
+        out.push_str("# HELP stream_bytes_total Bytes proxied to clients.\n");
+        out.push_str("# TYPE stream_bytes_total counter\n");
+        for (platform, t) in totals.iter() {
+            let _ = writeln!(out, "stream_bytes_total{{platform=\"{platform}\"}} {}", t.bytes.load(Ordering::Relaxed));
+        }
+        out.push_str("# HELP streams_total Proxied responses started.\n");
+        out.push_str("# TYPE streams_total counter\n");
+        for (platform, t) in totals.iter() {
+            let _ = writeln!(out, "streams_total{{platform=\"{platform}\"}} {}", t.streams.load(Ordering::Relaxed));
+        }
+        out.push_str("# HELP stream_client_aborts_total Streams the client disconnected from mid-transfer (upstream fetch dropped).\n");
+        out.push_str("# TYPE stream_client_aborts_total counter\n");
+        for (platform, t) in totals.iter() {
+            let _ = writeln!(
+                out,
+                "stream_client_aborts_total{{platform=\"{platform}\"}} {}",
+                t.client_aborts.load(Ordering::Relaxed)
+            );
+        }
+        out
+    }
+}
+
+fn rate(bytes: u64, started: Instant) -> u64 {
+    let secs = started.elapsed().as_secs_f64();
+    if secs < 0.001 {
+        return 0;
+    }
+    (bytes as f64 / secs) as u64
+}
+
+/// A registered stream; unregisters it when dropped.
+pub struct StreamHandle {
+    registry: Arc<StreamRegistry>,
+    id: u64,
+    bytes: Arc<AtomicU64>,
+    totals: Arc<PlatformTotals>,
+    cancel: CancellationToken,
+    /// The body ran to its end (or failed upstream, or was terminated), as
+    /// opposed to being dropped because the client went away
+    ended: bool,
+    on_finish: Vec<Box<dyn FnOnce(u64) + Send>>,
+}
+
+impl StreamHandle {
+    /// Run `f` with the bytes sent once the stream ends, however it ends.
+    pub fn on_finish(&mut self, f: impl FnOnce(u64) + Send + 'static) {
+        self.on_finish.push(Box::new(f));
+    }
+
+    fn end(&mut self) {
+        self.ended = true;
+    }
+
+    fn add(&self, n: usize) {
+        self.bytes.fetch_add(n as u64, Ordering::Relaxed);
+        self.totals.bytes.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        let entry = self.registry.active.lock().unwrap().remove(&self.id);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        if !self.ended {
+            self.totals.client_aborts.fetch_add(1, Ordering::Relaxed);
+            if let Some(e) = entry {
+                info!(
+                    "🔌 Client left {} {} after {bytes} bytes, upstream fetch dropped",
+                    e.meta.endpoint, e.meta.filename
+                );
+            }
+        }
+        for f in self.on_finish.drain(..) {
+            f(bytes);
+        }
+    }
+}
+
+/// Count the bytes of `body` and end it early when the stream is terminated.
+/// The stream stays registered until the body is dropped; hyper drops it
+/// when the client disconnects, which drops the upstream request with it.
+pub fn track<S, E>(body: S, handle: StreamHandle) -> impl Stream<Item = Result<Bytes, E>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+{
+    let cancelled = handle.cancel.clone().cancelled_owned();
+    let body = Box::pin(body.take_until(cancelled));
+    futures_util::stream::unfold((body, handle), |(mut body, mut handle)| async move {
+        match body.next().await {
+            Some(Ok(bytes)) => {
+                handle.add(bytes.len());
+                Some((Ok(bytes), (body, handle)))
+            }
+            Some(Err(e)) => {
+                handle.end();
+                Some((Err(e), (body, handle)))
+            }
+            None => {
+                handle.end();
+                None
+            }
+        }
+    })
+}
+
+/// An address or CIDR block of TRUSTED_PROXIES.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl IpNet {
+    /// "10.0.0.0/8", "fd00::/8" or a single address; None when malformed.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().ok()?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse().ok().filter(|p| *p <= bits)?,
+            None => bits,
+        };
+        Some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                (u32::from(net) ^ u32::from(ip)).checked_shr(32 - self.prefix).unwrap_or(0) == 0
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                (u128::from(net) ^ u128::from(ip)).checked_shr(128 - self.prefix).unwrap_or(0) == 0
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The client's address: the TCP peer, unless the peer is one of the
+/// `trusted` reverse proxies — then the last X-Forwarded-For hop that isn't
+/// a trusted proxy, else X-Real-IP. Anyone else's headers are ignored, so
+/// clients can't pick the address their per-client limits are kept under.
+pub fn client_addr(headers: &HeaderMap, peer: IpAddr, trusted: &[IpNet]) -> String {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|net| net.contains(ip));
+    let peer = peer.to_canonical();
+    if !is_trusted(peer) {
+        return peer.to_string();
+    }
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+    for hop in hops.into_iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) if is_trusted(ip) => continue,
+            Ok(ip) => return ip.to_canonical().to_string(),
+            Err(_) => break,
+        }
+    }
+    headers
+        .get("x-real-ip")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<IpAddr>().ok())
+        .unwrap_or(peer)
+        .to_string()
+}
+
+pub fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos()
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(platform: &str) -> StreamMeta {
+        StreamMeta {
+            endpoint: "stream",
+            platform: platform.to_string(),
+            format: "video".into(),
+            video_id: Some("123".into()),
+            client: "203.0.113.7".into(),
+            filename: "user.mp4".into(),
+            started_at: unix_now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_track_count_and_terminate() {
+        let registry = Arc::new(StreamRegistry::default());
+        let chunks = vec![Ok::<_, std::io::Error>(Bytes::from_static(b"abcd")), Ok(Bytes::from_static(b"ef"))];
+        let finished = Arc::new(AtomicU64::new(0));
+        let mut handle = registry.register(meta("tiktok"));
+        let sent = finished.clone();
+        handle.on_finish(move |bytes| sent.store(bytes, Ordering::Relaxed));
+        let body = track(futures_util::stream::iter(chunks), handle);
+        let streams = registry.list();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].meta.video_id.as_deref(), Some("123"));
+
+        assert_eq!(body.collect::<Vec<_>>().await.len(), 2);
+        assert!(registry.list().is_empty());
+        assert_eq!(finished.load(Ordering::Relaxed), 6);
+        let out = registry.render_metrics();
+        assert!(out.contains("stream_bytes_total{platform=\"tiktok\"} 6"));
+        assert!(out.contains("active_streams{platform=\"tiktok\"} 0"));
+        assert!(out.contains("streams_total{platform=\"tiktok\"} 1"));
+
+        let handle = registry.register(meta("tiktok"));
+        let id = registry.list()[0].id;
+        let pending = futures_util::stream::pending::<Result<Bytes, std::io::Error>>();
+        let body = track(pending, handle);
+        assert!(registry.terminate(id));
+        assert!(body.collect::<Vec<_>>().await.is_empty());
+        assert!(!registry.terminate(id));
+
+        // Dropped mid-transfer: the client went away
+        let pending = futures_util::stream::pending::<Result<Bytes, std::io::Error>>();
+        drop(track(pending, registry.register(meta("tiktok"))));
+        assert!(registry
+            .render_metrics()
+            .contains("stream_client_aborts_total{platform=\"tiktok\"} 1"));
+
+        let trusted: Vec<IpNet> = ["127.0.0.1", "10.0.0.0/8", "::1"]
+            .iter()
+            .map(|n| IpNet::parse(n).unwrap())
+            .collect();
+        assert!(IpNet::parse("10.0.0.0/33").is_none());
+        assert!(IpNet::parse("0.0.0.0/0").unwrap().contains("198.51.100.2".parse().unwrap()));
+        let proxy: IpAddr = "10.1.2.3".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "198.51.100.2".parse().unwrap());
+        assert_eq!(client_addr(&headers, proxy, &trusted), "198.51.100.2");
+        // Whatever the client prepends, the hop the proxies saw wins
+        headers.insert("x-forwarded-for", "1.1.1.1, 203.0.113.7, 10.0.0.1".parse().unwrap());
+        assert_eq!(client_addr(&headers, proxy, &trusted), "203.0.113.7");
+        assert_eq!(client_addr(&headers, "::ffff:127.0.0.1".parse().unwrap(), &trusted), "203.0.113.7");
+        // Headers from a client connecting directly are ignored
+        let direct: IpAddr = "192.0.2.9".parse().unwrap();
+        assert_eq!(client_addr(&headers, direct, &trusted), "192.0.2.9");
+        assert_eq!(client_addr(&HeaderMap::new(), proxy, &trusted), "10.1.2.3");
+    }
+}
@@ -0,0 +1,84 @@
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Interval;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Owns the long-running background tasks (cleanup, reloads, checks, the
+/// schedule and watcher loops). Each gets a child of one cancellation token
+/// and is awaited by `shutdown`, so a stopping server leaves none behind.
+#[derive(Default)]
+pub struct TaskManager {
+    cancel: CancellationToken,
+    tasks: Mutex<Vec<(&'static str, JoinHandle<()>)>>,
+}
+
+impl TaskManager {
+    /// Spawn `task` with a token that is cancelled on shutdown; the task
+    /// should return soon after, e.g. by looping on `tick`.
+    pub fn spawn<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(task(self.cancel.child_token()));
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|(_, h)| !h.is_finished());
+        tasks.push((name, handle));
+    }
+
+    /// Cancel every task and wait for them, aborting those still running
+    /// after `timeout`.
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.cancel.cancel();
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let deadline = tokio::time::Instant::now() + timeout;
+        for (name, mut handle) in tasks {
+            if tokio::time::timeout_at(deadline, &mut handle).await.is_err() {
+                warn!("Background task {name} did not stop in time, aborting it");
+                handle.abort();
+            }
+        }
+        info!("Background tasks stopped");
+    }
+}
+
+/// Wait for the next tick of `interval`; false once `cancel` fires.
+pub async fn tick(interval: &mut Interval, cancel: &CancellationToken) -> bool {
+    tokio::select! {
+        _ = cancel.cancelled() => false,
+        _ = interval.tick() => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_shutdown_drains_and_aborts() {
+        let manager = TaskManager::default();
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counted = ticks.clone();
+        manager.spawn("ticker", |cancel| async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(5));
+            while tick(&mut interval, &cancel).await {
+                counted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        // Ignores its token; shutdown has to abort it
+        manager.spawn("stuck", |_| std::future::pending());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        manager.shutdown(Duration::from_millis(50)).await;
+        let after = ticks.load(Ordering::SeqCst);
+        assert!(after > 0);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), after);
+        assert!(manager.tasks.lock().unwrap().is_empty());
+    }
+}
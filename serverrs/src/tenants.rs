@@ -0,0 +1,240 @@
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::config::Settings;
+use crate::streams::IpNet;
+use crate::AppState;
+
+/// Platforms a tenant can be limited to with `platforms`.
+const PLATFORMS: [&str; 2] = ["tiktok", "douyin"];
+
+/// TENANTS_PATH file format — one profile per white-label frontend, every
+/// field but `api_keys` optional and falling back to the global setting:
+///
+/// ```json
+/// {"acme": {
+///   "api_keys": ["acme-key-1"],
+///   "base_url": "https://dl.acme.example",
+///   "encryption_key": "acme-secret",
+///   "watermark": false,
+///   "platforms": ["tiktok"],
+///   "tier": "paid"
+/// }}
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TenantSpec {
+    #[serde(default)]
+    api_keys: Vec<String>,
+    base_url: Option<String>,
+    encryption_key: Option<String>,
+    /// Include the `watermark` / `watermark_hd` links (WATERMARK_LINKS)
+    watermark: Option<bool>,
+    /// Platforms the tenant may use; the others are disabled for it
+    platforms: Option<Vec<String>>,
+    /// API key tier of every key in `api_keys` (default: its API_KEY_TIERS
+    /// entry, else "free")
+    tier: Option<String>,
+}
+
+struct Tenant {
+    name: String,
+    api_keys: Vec<String>,
+    /// `host[:port]` of the tenant's base URL; link requests on that host
+    /// are decrypted with the tenant's key
+    host: Option<String>,
+    settings: Arc<Settings>,
+}
+
+/// Tenant profiles loaded from TENANTS_PATH. Requests are matched by their
+/// `X-API-Key`, then by host, and served with that tenant's settings;
+/// anything else uses the global ones.
+#[derive(Default)]
+pub struct Tenants {
+    tenants: Vec<Tenant>,
+}
+
+impl Tenants {
+    /// Load TENANTS_PATH and register every tenant key's tier in
+    /// `settings.api_key_tiers`, so quotas and queue priority apply to them.
+    /// An empty path means a single-tenant deployment.
+    pub fn load(settings: &mut Settings) -> Result<Self, String> {
+        let path = settings.tenants_path.clone();
+        if path.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let raw = std::fs::read_to_string(&path).map_err(|e| format!("{path}: {e}"))?;
+        Self::parse(&raw, settings).map_err(|e| format!("{path}: {e}"))
+    }
+
+    fn parse(raw: &str, settings: &mut Settings) -> Result<Self, String> {
+        let specs: BTreeMap<String, TenantSpec> = serde_json::from_str(raw).map_err(|e| e.to_string())?;
+        let mut seen = HashSet::new();
+        for (name, spec) in &specs {
+            for key in &spec.api_keys {
+                if !seen.insert(key) {
+                    return Err(format!("API key of tenant {name} belongs to another tenant"));
+                }
+                match &spec.tier {
+                    Some(tier) => {
+                        settings.api_key_tiers.insert(key.clone(), tier.clone());
+                    }
+                    None => {
+                        settings.api_key_tiers.entry(key.clone()).or_insert_with(|| "free".to_string());
+                    }
+                }
+            }
+            if let Some(p) = spec.platforms.iter().flatten().find(|p| !PLATFORMS.contains(&p.as_str())) {
+                return Err(format!("unknown platform {p} for tenant {name}"));
+            }
+        }
+
+        let mut tenants: Vec<Tenant> = Vec::new();
+        for (name, spec) in specs {
+            let mut tenant_settings = settings.clone();
+            if let Some(base_url) = spec.base_url {
+                tenant_settings.base_url = base_url.trim_end_matches('/').to_string();
+            }
+            if let Some(key) = spec.encryption_key {
+                if key.is_empty() {
+                    return Err(format!("empty encryption_key for tenant {name}"));
+                }
+                tenant_settings.encryption_key = key;
+            }
+            if let Some(watermark) = spec.watermark {
+                tenant_settings.watermark_links = watermark;
+            }
+            if let Some(platforms) = &spec.platforms {
+                for platform in PLATFORMS.iter().filter(|p| !platforms.iter().any(|a| a == *p)) {
+                    tenant_settings.features.disable_platform(platform);
+                }
+            }
+            let host = (tenant_settings.base_url != settings.base_url)
+                .then(|| url_host(&tenant_settings.base_url))
+                .flatten();
+            if let Some(other) = tenants.iter().find(|t| host.is_some() && t.host == host) {
+                return Err(format!("tenants {} and {name} share a base_url host", other.name));
+            }
+            tenants.push(Tenant { name, api_keys: spec.api_keys, host, settings: Arc::new(tenant_settings) });
+        }
+        Ok(Self { tenants })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tenants.len()
+    }
+
+    /// The tenant of a request from `peer`, by API key, else by host.
+    fn resolve(&self, headers: &HeaderMap, peer: Option<IpAddr>, trusted: &[IpNet]) -> Option<&Tenant> {
+        let key = headers.get("x-api-key").and_then(|v| v.to_str().ok());
+        if let Some(key) = key {
+            if let Some(t) = self.tenants.iter().find(|t| t.api_keys.iter().any(|k| k == key)) {
+                return Some(t);
+            }
+        }
+        let host = request_host(headers, peer, trusted)?;
+        self.tenants.iter().find(|t| t.host.as_deref() == Some(host.as_str()))
+    }
+
+    /// Settings of the tenant a request from `peer` belongs to, None for the
+    /// global ones. `trusted` are the TRUSTED_PROXIES.
+    pub fn settings_for(&self, headers: &HeaderMap, peer: Option<IpAddr>, trusted: &[IpNet]) -> Option<&Arc<Settings>> {
+        self.resolve(headers, peer, trusted).map(|t| &t.settings)
+    }
+}
+
+/// `host[:port]` of a URL, lowercased.
+fn url_host(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?.to_lowercase();
+    Some(match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host,
+    })
+}
+
+/// Host the request was sent to: Host, or the first X-Forwarded-Host when
+/// `peer` is one of the `trusted` reverse proxies. Anyone else's
+/// X-Forwarded-Host is ignored, so clients can't pick a tenant by it.
+fn request_host(headers: &HeaderMap, peer: Option<IpAddr>, trusted: &[IpNet]) -> Option<String> {
+    let proxied = peer.is_some_and(|ip| trusted.iter().any(|net| net.contains(ip)));
+    let names: &[&str] = if proxied { &["x-forwarded-host", "host"] } else { &["host"] };
+    names
+        .iter()
+        .filter_map(|name| headers.get(*name)?.to_str().ok())
+        .map(|v| v.split(',').next().unwrap_or("").trim().to_lowercase())
+        .find(|v| !v.is_empty())
+}
+
+/// AppState with the settings of the request's tenant — use in place of
+/// `State<AppState>` in handlers that build or decrypt links.
+pub struct TenantState(pub AppState);
+
+impl FromRequestParts<AppState> for TenantState {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip());
+        Ok(Self(match state.tenants.settings_for(&parts.headers, peer, &state.settings.trusted_proxies) {
+            Some(settings) => AppState { settings: settings.clone(), ..state.clone() },
+            None => state.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenants() {
+        let mut settings = Settings::from_env();
+        let raw = r#"{
+            "acme": {"api_keys": ["acme-1"], "base_url": "https://DL.acme.example/", "encryption_key": "k1",
+                     "watermark": false, "platforms": ["tiktok"], "tier": "paid"},
+            "beta": {"api_keys": ["beta-1"], "base_url": "http://beta.example:8080"}
+        }"#;
+        let tenants = Tenants::parse(raw, &mut settings).unwrap();
+        assert_eq!(tenants.len(), 2);
+        assert_eq!(settings.tier_for(Some("acme-1")), "paid");
+        assert_eq!(settings.tier_for(Some("beta-1")), "free");
+
+        let trusted = [IpNet::parse("10.0.0.0/8").unwrap()];
+        let (proxy, client) = (Some("10.0.0.2".parse().unwrap()), Some("203.0.113.7".parse().unwrap()));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "acme-1".parse().unwrap());
+        let acme = tenants.settings_for(&headers, client, &trusted).unwrap();
+        assert_eq!(acme.base_url, "https://DL.acme.example");
+        assert_eq!(acme.encryption_key, "k1");
+        assert!(!acme.watermark_links);
+        assert!(acme.features.platform_enabled("tiktok"));
+        assert!(!acme.features.platform_enabled("douyin"));
+        assert_eq!(acme.tier_for(Some("beta-1")), "free");
+
+        // Link requests carry no key; the host picks the tenant
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "beta.example:8080".parse().unwrap());
+        let beta = tenants.settings_for(&headers, client, &trusted).unwrap();
+        assert_eq!(beta.encryption_key, settings.encryption_key);
+        assert!(beta.watermark_links);
+        // X-Forwarded-Host counts only from a trusted proxy
+        headers.insert("x-forwarded-host", "dl.acme.example".parse().unwrap());
+        assert_eq!(tenants.settings_for(&headers, proxy, &trusted).unwrap().encryption_key, "k1");
+        assert!(tenants.settings_for(&headers, client, &trusted).unwrap().watermark_links);
+        assert!(tenants.settings_for(&headers, None, &trusted).unwrap().watermark_links);
+        headers.insert("x-api-key", "unknown".parse().unwrap());
+        headers.insert("x-forwarded-host", "other.example".parse().unwrap());
+        assert!(tenants.settings_for(&headers, proxy, &trusted).is_none());
+
+        let dup = r#"{"a": {"api_keys": ["x"]}, "b": {"api_keys": ["x"]}}"#;
+        assert!(Tenants::parse(dup, &mut Settings::from_env()).is_err());
+        let platform = r#"{"a": {"platforms": ["youtube"]}}"#;
+        assert!(Tenants::parse(platform, &mut Settings::from_env()).is_err());
+    }
+}
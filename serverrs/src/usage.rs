@@ -0,0 +1,68 @@
+use chrono::{Duration, NaiveDate, Utc};
+
+use crate::streams::StreamHandle;
+use crate::AppState;
+
+/// Longest range GET /admin/usage returns at once.
+pub const MAX_USAGE_DAYS: u32 = 92;
+
+/// Usage day of `now`: the UTC date, YYYY-MM-DD.
+pub fn today() -> String {
+    Utc::now().date_naive().format("%Y-%m-%d").to_string()
+}
+
+/// Seconds until the quotas reset at the next UTC midnight.
+pub fn secs_until_reset() -> u64 {
+    let now = Utc::now();
+    let midnight = (now.date_naive() + Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc();
+    (midnight - now).num_seconds().max(1) as u64
+}
+
+/// `days` dates ending with `last`, oldest first.
+pub fn days_ending(last: NaiveDate, days: u32) -> Vec<String> {
+    (0..days.clamp(1, MAX_USAGE_DAYS) as i64)
+        .rev()
+        .map(|back| (last - Duration::days(back)).format("%Y-%m-%d").to_string())
+        .collect()
+}
+
+/// Bytes the key may still stream today; None when its tier is unlimited
+/// or there is no cache to meter against.
+pub async fn remaining(state: &AppState, api_key: &str) -> Option<u64> {
+    let quota = state.settings.daily_quota_for(api_key)?;
+    let cache = state.redis.as_ref()?;
+    Some(quota.saturating_sub(cache.usage(&today(), api_key).await))
+}
+
+/// Add the stream's bytes to the key's usage for the day it ends on.
+pub fn meter(handle: &mut StreamHandle, state: &AppState, api_key: &str) {
+    let Some(cache) = state.redis.clone() else {
+        return;
+    };
+    let api_key = api_key.to_string();
+    let retention_days = state.settings.usage_retention_days;
+    // Through the task manager, so a stream ending during shutdown is still billed
+    let tasks = state.tasks.clone();
+    handle.on_finish(move |bytes| {
+        if bytes == 0 {
+            return;
+        }
+        tasks.spawn("usage", move |_| async move {
+            cache.add_usage(&today(), &api_key, bytes, retention_days).await;
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_days_ending() {
+        let last = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        assert_eq!(days_ending(last, 3), vec!["2026-02-27", "2026-02-28", "2026-03-01"]);
+        assert_eq!(days_ending(last, 0), vec!["2026-03-01"]);
+        assert_eq!(days_ending(last, 1000).len(), MAX_USAGE_DAYS as usize);
+        assert!((1..=86_400).contains(&secs_until_reset()));
+    }
+}
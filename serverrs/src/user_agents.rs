@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{info, warn};
+
+use crate::config::Settings;
+
+/// Built-in pool used when USER_AGENTS_FILE is not set.
+const DEFAULT_USER_AGENTS: [&str; 6] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1",
+];
+
+/// Round-robin User-Agent pool. Each extraction takes the next UA; yt-dlp
+/// copies it into the formats' http_headers, so the CDN fetches for that
+/// result reuse the same UA.
+pub struct UserAgentPool {
+    agents: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl UserAgentPool {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let agents = if !settings.user_agent_rotation {
+            Vec::new()
+        } else if settings.user_agents_file.is_empty() {
+            DEFAULT_USER_AGENTS.iter().map(|s| s.to_string()).collect()
+        } else {
+            match std::fs::read_to_string(&settings.user_agents_file) {
+                Ok(text) => parse_user_agents(&text),
+                Err(e) => {
+                    warn!(
+                        "Failed to read USER_AGENTS_FILE {}: {e}; using built-in pool",
+                        settings.user_agents_file
+                    );
+                    DEFAULT_USER_AGENTS.iter().map(|s| s.to_string()).collect()
+                }
+            }
+        };
+        if !agents.is_empty() {
+            info!("🕵️ User-Agent rotation: {} agents", agents.len());
+        }
+        Self {
+            agents,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Next UA in the rotation; `None` when rotation is disabled.
+    pub fn next(&self) -> Option<String> {
+        if self.agents.is_empty() {
+            return None;
+        }
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.agents.len();
+        Some(self.agents[i].clone())
+    }
+}
+
+/// One UA per line; blank lines and `#` comments are ignored.
+fn parse_user_agents(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_string())
+        .collect()
+}
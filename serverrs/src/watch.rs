@@ -0,0 +1,456 @@
+use axum::extract::{Json, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+use crate::admin::{constant_time_eq, reject_non_admin};
+use crate::config::platform_for_url;
+use crate::encryption::random_hex;
+use crate::error::ExtractError;
+use crate::schedule::write_atomic;
+use crate::tasks::{tick, TaskManager};
+use crate::{ytdlp, AppState};
+
+/// How often watchers are checked for being due.
+const WATCH_TICK_SECS: u64 = 30;
+/// Post ids remembered per watcher; older ones drop off the listing anyway.
+const MAX_SEEN: usize = 1000;
+
+/// A creator profile polled for new posts. New posts become scheduled jobs
+/// (schedule.rs) that run right away and POST the result to `webhook_url`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Watch {
+    pub id: String,
+    /// Profile URL, e.g. https://www.tiktok.com/@creator
+    pub url: String,
+    pub interval_secs: u64,
+    pub webhook_url: String,
+    /// Download each new video (served from GET /jobs/{id}/file)
+    pub prefetch: bool,
+    /// Also archive the posts already on the profile at the first check
+    pub backfill: bool,
+    pub created_at: u64,
+    pub last_checked_at: Option<u64>,
+    pub last_error: Option<String>,
+    /// New posts handed to scheduled jobs so far
+    pub posts_found: u64,
+    /// Post ids already seen, newest first
+    seen: Vec<String>,
+    /// Random key the RSS feed (feed.rs) must be requested with
+    #[serde(default)]
+    feed_secret: String,
+}
+
+impl Watch {
+    fn is_due(&self, now: u64) -> bool {
+        self.last_checked_at
+            .is_none_or(|t| now >= t + self.interval_secs)
+    }
+
+    /// Entries not seen before, oldest first. The first check only records
+    /// the current posts unless `backfill` is set.
+    fn new_entries<'a>(&self, entries: &'a [Entry]) -> Vec<&'a Entry> {
+        if self.last_checked_at.is_none() && !self.backfill {
+            return Vec::new();
+        }
+        entries
+            .iter()
+            .rev()
+            .filter(|e| !self.seen.contains(&e.id))
+            .collect()
+    }
+
+    /// Whether `key` is this watcher's feed secret, compared in constant time.
+    pub fn feed_key_matches(&self, key: &str) -> bool {
+        !self.feed_secret.is_empty() && constant_time_eq(key.as_bytes(), self.feed_secret.as_bytes())
+    }
+
+    fn mark_seen<'a>(&mut self, ids: impl IntoIterator<Item = &'a str>) {
+        for id in ids {
+            if !self.seen.iter().any(|s| s == id) {
+                self.seen.insert(0, id.to_string());
+            }
+        }
+        self.seen.truncate(MAX_SEEN);
+    }
+}
+
+/// One post from a flat profile listing.
+#[derive(Debug, Clone)]
+struct Entry {
+    id: String,
+    url: String,
+}
+
+/// `entries` of a flat extraction, newest first.
+fn parse_entries(info_json: &str) -> Result<Vec<Entry>, String> {
+    let info: serde_json::Value =
+        serde_json::from_str(info_json).map_err(|e| format!("invalid listing JSON: {e}"))?;
+    let entries = info["entries"].as_array().ok_or("profile listing has no entries")?;
+    Ok(entries
+        .iter()
+        .filter_map(|e| {
+            let id = e["id"].as_str()?;
+            let url = e["url"].as_str().or_else(|| e["webpage_url"].as_str())?;
+            Some(Entry {
+                id: id.to_string(),
+                url: url.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Watchers, persisted to WATCHERS_PATH.
+pub struct WatchStore {
+    path: PathBuf,
+    watches: Mutex<BTreeMap<String, Watch>>,
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl WatchStore {
+    pub fn load(path: &std::path::Path) -> Self {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let mut watches = BTreeMap::new();
+        match std::fs::read_to_string(path) {
+            Ok(text) => match serde_json::from_str::<Vec<Watch>>(&text) {
+                Ok(list) => {
+                    for mut watch in list {
+                        // Watchers from before feed secrets; saved with the next check
+                        if watch.feed_secret.is_empty() {
+                            watch.feed_secret = random_hex(32);
+                        }
+                        watches.insert(watch.id.clone(), watch);
+                    }
+                }
+                Err(e) => warn!("⚠️ Ignoring unreadable {}: {e}", path.display()),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("⚠️ Cannot read {}: {e}", path.display()),
+        }
+        info!("👀 {} watcher(s) loaded", watches.len());
+        Self {
+            path: path.to_path_buf(),
+            watches: Mutex::new(watches),
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    async fn persist(&self) {
+        let _guard = self.write_lock.lock().await;
+        let snapshot = {
+            let watches = self.watches.lock().unwrap();
+            serde_json::to_vec_pretty(&watches.values().collect::<Vec<_>>()).unwrap()
+        };
+        if let Err(e) = write_atomic(&self.path, snapshot).await {
+            error!("Failed to save watchers to {}: {e}", self.path.display());
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Watch> {
+        self.watches.lock().unwrap().get(id).cloned()
+    }
+
+    fn list(&self) -> Vec<Watch> {
+        self.watches.lock().unwrap().values().cloned().collect()
+    }
+
+    fn due(&self, now: u64) -> Vec<Watch> {
+        self.watches
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|w| w.is_due(now))
+            .cloned()
+            .collect()
+    }
+
+    async fn update(&self, id: &str, f: impl FnOnce(&mut Watch)) {
+        if let Some(watch) = self.watches.lock().unwrap().get_mut(id) {
+            f(watch);
+        }
+        self.persist().await;
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WatchRequest {
+    url: String,
+    interval_secs: Option<u64>,
+    webhook_url: String,
+    #[serde(default)]
+    prefetch: bool,
+    #[serde(default)]
+    backfill: bool,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+/// Watcher JSON without the (potentially long) seen-id list, with the
+/// feed's URL in place of its secret.
+fn watch_view(watch: &Watch, base_url: &str) -> serde_json::Value {
+    let mut view = serde_json::to_value(watch).unwrap();
+    view["seen_posts"] = watch.seen.len().into();
+    view["feed_url"] = format!("{base_url}/feeds/{}.xml?key={}", watch.id, watch.feed_secret).into();
+    if let Some(obj) = view.as_object_mut() {
+        obj.remove("seen");
+        obj.remove("feed_secret");
+    }
+    view
+}
+
+/// GET /admin/watchers
+pub async fn list_watchers_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+    let base_url = &state.settings.base_url;
+    let watchers: Vec<_> = state.watchers.list().iter().map(|w| watch_view(w, base_url)).collect();
+    Json(serde_json::json!({ "watchers": watchers })).into_response()
+}
+
+/// POST /admin/watchers — Register a creator profile to archive.
+pub async fn create_watcher_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<WatchRequest>,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+    let url = req.url.trim().to_string();
+    if let Some(resp) = crate::reject_unsupported_url(&url) {
+        return resp;
+    }
+    if !req.webhook_url.starts_with("http://") && !req.webhook_url.starts_with("https://") {
+        return error_response(StatusCode::BAD_REQUEST, "webhook_url must be an http(s) URL".into());
+    }
+    let min_interval = state.settings.watch_min_interval_secs;
+    let interval_secs = req.interval_secs.unwrap_or(3600);
+    if interval_secs < min_interval {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("interval_secs must be at least {min_interval}"),
+        );
+    }
+
+    let now = unix_now();
+    let watch = Watch {
+        id: random_hex(8),
+        url,
+        interval_secs,
+        webhook_url: req.webhook_url,
+        prefetch: req.prefetch,
+        backfill: req.backfill,
+        created_at: now,
+        last_checked_at: None,
+        last_error: None,
+        posts_found: 0,
+        seen: Vec::new(),
+        feed_secret: random_hex(32),
+    };
+    info!("👀 Watching {} every {}s ({})", watch.url, watch.interval_secs, watch.id);
+    state
+        .watchers
+        .watches
+        .lock()
+        .unwrap()
+        .insert(watch.id.clone(), watch.clone());
+    state.watchers.persist().await;
+    (StatusCode::CREATED, Json(watch_view(&watch, &state.settings.base_url))).into_response()
+}
+
+/// DELETE /admin/watchers/{id}
+pub async fn delete_watcher_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    if let Some(resp) = reject_non_admin(&headers, &state) {
+        return resp;
+    }
+    let removed = state.watchers.watches.lock().unwrap().remove(&id);
+    match removed {
+        Some(watch) => {
+            state.watchers.persist().await;
+            info!("👀 Stopped watching {} ({id})", watch.url);
+            Json(watch_view(&watch, &state.settings.base_url)).into_response()
+        }
+        None => error_response(StatusCode::NOT_FOUND, "Watcher not found".into()),
+    }
+}
+
+/// Background task: check due watchers one at a time.
+pub fn spawn_watch_task(tasks: &TaskManager, state: AppState) {
+    tasks.spawn("watch", |cancel| async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(WATCH_TICK_SECS));
+        while tick(&mut interval, &cancel).await {
+            for watch in state.watchers.due(unix_now()) {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                check_watch(&state, watch).await;
+            }
+        }
+    });
+}
+
+async fn check_watch(state: &AppState, watch: Watch) {
+    let entries = match list_posts(state, &watch.url).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("👀 Watcher {} ({}) check failed: {e}", watch.id, watch.url);
+            state
+                .watchers
+                .update(&watch.id, |w| {
+                    w.last_checked_at = Some(unix_now());
+                    w.last_error = Some(e);
+                })
+                .await;
+            return;
+        }
+    };
+
+    let mut queued = Vec::new();
+    for entry in watch.new_entries(&entries) {
+        if state.schedule.unfinished() >= state.settings.schedule_max_jobs {
+            // Left unseen so the next check picks it up again
+            warn!("👀 Scheduled job limit reached, deferring new posts of {}", watch.url);
+            break;
+        }
+        let job = crate::schedule::ScheduledJob {
+            watch_id: Some(watch.id.clone()),
+            webhook_url: Some(watch.webhook_url.clone()),
+            ..state
+                .schedule
+                .new_job(entry.url.clone(), unix_now(), watch.prefetch, 0)
+        };
+        info!("👀 New post {} from {} (job {})", entry.id, watch.url, job.id);
+        state.schedule.add(job).await;
+        queued.push(entry.id.as_str());
+    }
+
+    let first_check = watch.last_checked_at.is_none();
+    state
+        .watchers
+        .update(&watch.id, |w| {
+            if first_check && !w.backfill {
+                w.mark_seen(entries.iter().rev().map(|e| e.id.as_str()));
+            } else {
+                w.mark_seen(queued.iter().copied());
+            }
+            w.posts_found += queued.len() as u64;
+            w.last_checked_at = Some(unix_now());
+            w.last_error = None;
+        })
+        .await;
+}
+
+/// Flat-list the newest posts of a profile through the extraction queue,
+/// using the active cookie profile.
+async fn list_posts(state: &AppState, url: &str) -> Result<Vec<Entry>, String> {
+    let ticket = state
+        .extraction_queue
+        .enter(0)
+        .map_err(|_| "extraction queue full".to_string())?;
+    let permit = ticket.wait().await;
+
+    let mut headers = Vec::new();
+    if let Some(ua) = state.user_agents.next() {
+        headers.push(("User-Agent".to_string(), ua));
+    }
+    let platform = platform_for_url(url);
+    headers.extend(state.settings.headers_for(platform));
+    let cookies = state.cookies.get(platform).cloned();
+    let limit = state.settings.watch_playlist_limit;
+    let url = url.to_string();
+
+    let listing = tokio::time::timeout(
+        Duration::from_secs(state.settings.ytdlp_max_timeout),
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let checkout = cookies.as_ref().and_then(|c| c.checkout());
+            let path = checkout.as_ref().map(|(_, p)| p.to_string_lossy().to_string());
+            let result = ytdlp::list_entries(&url, path.as_deref(), &headers, limit);
+            if let (Some(cookies), Some((idx, _))) = (&cookies, checkout) {
+                cookies.report(idx, &result);
+            }
+            result
+        }),
+    )
+    .await
+    .map_err(|_| "profile listing timed out".to_string())?
+    .map_err(|e| format!("listing task failed: {e}"))?;
+
+    listing
+        .map_err(|e: ExtractError| e.to_string())
+        .and_then(|json| parse_entries(&json))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_entries_skip_seen_and_first_check() {
+        let entries = parse_entries(
+            r#"{"entries": [
+                {"id": "3", "url": "https://www.tiktok.com/@a/video/3"},
+                {"id": "2", "url": "https://www.tiktok.com/@a/video/2"},
+                {"id": "1", "webpage_url": "https://www.tiktok.com/@a/video/1"},
+                {"title": "no id"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let mut watch = Watch {
+            id: "w".into(),
+            url: "https://www.tiktok.com/@a".into(),
+            interval_secs: 600,
+            webhook_url: "https://hook".into(),
+            prefetch: false,
+            backfill: false,
+            created_at: 0,
+            last_checked_at: None,
+            last_error: None,
+            posts_found: 0,
+            seen: Vec::new(),
+            feed_secret: "s3cret".into(),
+        };
+        assert!(watch.is_due(0));
+        assert!(watch.feed_key_matches("s3cret"));
+        assert!(!watch.feed_key_matches("s3cre"));
+        assert!(!watch.feed_key_matches(""));
+        assert_eq!(watch_view(&watch, "https://dl")["feed_url"], "https://dl/feeds/w.xml?key=s3cret");
+        assert!(watch_view(&watch, "https://dl").get("feed_secret").is_none());
+        assert!(watch.new_entries(&entries).is_empty());
+
+        watch.mark_seen(entries[1..].iter().rev().map(|e| e.id.as_str()));
+        watch.last_checked_at = Some(1000);
+        assert!(!watch.is_due(1500));
+        let new: Vec<_> = watch.new_entries(&entries).iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(new, ["3"]);
+
+        watch.backfill = true;
+        watch.last_checked_at = None;
+        watch.seen.clear();
+        let new: Vec<_> = watch.new_entries(&entries).iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(new, ["1", "2", "3"]);
+    }
+}
@@ -1,60 +1,228 @@
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::error::ExtractError;
+use crate::tasks::{tick, TaskManager};
+
+/// How often the background task re-checks that yt_dlp is importable.
+const PYTHON_CHECK_INTERVAL_SECS: u64 = 300;
+
+/// Cached result of the periodic yt_dlp import check, reported by /health.
+#[derive(Clone, Default)]
+pub struct PythonStatus {
+    pub ytdlp_version: Option<String>,
+    /// Embedded interpreter version, read once at startup
+    pub python_version: Option<String>,
+    pub error: Option<String>,
+    pub checked_at: Option<f64>,
+}
+
+impl PythonStatus {
+    /// Healthy until a check has actually failed (pending counts as healthy).
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let status = if self.error.is_some() {
+            "error"
+        } else if self.checked_at.is_none() {
+            "pending"
+        } else {
+            "ok"
+        };
+        serde_json::json!({
+            "status": status,
+            "ytdlp_version": self.ytdlp_version,
+            "python_version": self.python_version,
+            "error": self.error,
+            "checked_at": self.checked_at,
+        })
+    }
+}
+
+/// How long the check's interpreter may take to import yt_dlp.
+const CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Import yt_dlp in a fresh process of the embedded interpreter's python,
+/// with its sys.path, and return its version string. Importing again in the
+/// embedded interpreter would be answered from sys.modules, so a broken or
+/// removed install would never show up. Blocking — call from spawn_blocking.
+pub fn check_ytdlp() -> Result<String, String> {
+    let (executable, path) = Python::with_gil(|py| -> PyResult<(Option<std::path::PathBuf>, Vec<String>)> {
+        Ok((interpreter_executable(py)?, py.import("sys")?.getattr("path")?.extract()?))
+    })
+    .map_err(|e| format!("Failed to read the interpreter's sys.path: {e}"))?;
+    let Some(executable) = executable else {
+        // Only the shared library is installed; the embedded import is all there is
+        return import_ytdlp();
+    };
+    let pythonpath = std::env::join_paths(path.iter().filter(|p| !p.is_empty()))
+        .map_err(|e| format!("Invalid sys.path: {e}"))?;
+
+    let child = std::process::Command::new(&executable)
+        .args(["-c", "import yt_dlp, yt_dlp.version; print(yt_dlp.version.__version__)"])
+        .env("PYTHONPATH", pythonpath)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {e}", executable.display()))?;
+    // wait_with_output drains both pipes while waiting, so a chatty import
+    // can't fill one and hang
+    let pid = child.id();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(child.wait_with_output());
+    });
+    let output = match rx.recv_timeout(CHECK_TIMEOUT) {
+        Ok(output) => output.map_err(|e| format!("Failed to read {} output: {e}", executable.display()))?,
+        Err(_) => {
+            // The waiting thread reaps it
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            return Err(format!("Importing yt_dlp took over {}s", CHECK_TIMEOUT.as_secs()));
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("no output");
+        return Err(format!("Failed to import yt_dlp: {reason}"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The python binary of the interpreter PyO3 embeds: sys.executable when it
+/// names one (a venv's python, say), else python<major>.<minor> under
+/// sys.prefix or sys.base_prefix. Embedded, sys.executable is often empty or
+/// this binary, and whatever `python3` is on PATH may be another version.
+fn interpreter_executable(py: Python<'_>) -> PyResult<Option<std::path::PathBuf>> {
+    let sys = py.import("sys")?;
+    let executable: String = sys.getattr("executable")?.extract()?;
+    let is_self = std::env::current_exe().is_ok_and(|exe| exe == std::path::Path::new(&executable));
+    if !executable.is_empty() && !is_self {
+        return Ok(Some(executable.into()));
+    }
+    let version = py.version_info();
+    let name = format!("python{}.{}", version.major, version.minor);
+    for prefix in ["prefix", "base_prefix"] {
+        let prefix: String = sys.getattr(prefix)?.extract()?;
+        let candidate = std::path::Path::new(&prefix).join("bin").join(&name);
+        if candidate.is_file() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Import yt_dlp in the embedded interpreter and return its version string.
+/// Blocking — call from spawn_blocking.
+fn import_ytdlp() -> Result<String, String> {
+    Python::with_gil(|py| {
+        py.import("yt_dlp")
+            .map_err(|e| format!("Failed to import yt_dlp: {e}"))?;
+        py.import("yt_dlp.version")
+            .and_then(|m| m.getattr("__version__"))
+            .and_then(|v| v.extract::<String>())
+            .map_err(|e| format!("Failed to read yt_dlp version: {e}"))
+    })
+}
+
+/// Version of the embedded Python interpreter, e.g. "3.11.2".
+/// Blocking — call from spawn_blocking.
+pub fn python_version() -> String {
+    Python::with_gil(|py| {
+        let v = py.version_info();
+        format!("{}.{}.{}", v.major, v.minor, v.patch)
+    })
+}
+
+/// Import yt_dlp once at startup so the first request doesn't pay the import
+/// latency. With `preload_extractors`, also builds the extractor class list.
+/// Returns the yt_dlp version. Blocking — call from spawn_blocking.
+pub fn warm_up(preload_extractors: bool) -> Result<String, String> {
+    let started = std::time::Instant::now();
+    let version = import_ytdlp()?;
+
+    if preload_extractors {
+        let count = Python::with_gil(|py| {
+            py.import("yt_dlp.extractor")
+                .and_then(|m| m.call_method0("gen_extractor_classes"))
+                .and_then(|classes| classes.len())
+                .map_err(|e| format!("Failed to build extractor list: {e}"))
+        })?;
+        info!("Preloaded {count} yt-dlp extractors");
+    }
+
+    info!(
+        "🔥 yt_dlp {version} warmed up in {}ms",
+        started.elapsed().as_millis()
+    );
+    Ok(version)
+}
+
+/// Spawn a background task that re-runs check_ytdlp() every 5 minutes
+/// (first check runs immediately). Call this once at startup.
+pub fn spawn_python_check_task(tasks: &TaskManager, status: Arc<RwLock<PythonStatus>>) {
+    tasks.spawn("python_check", |cancel| async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(PYTHON_CHECK_INTERVAL_SECS));
+
+        while tick(&mut interval, &cancel).await {
+            let result = tokio::task::spawn_blocking(check_ytdlp)
+                .await
+                .unwrap_or_else(|e| Err(format!("Task join error: {e}")));
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64();
+
+            let mut st = status.write().await;
+            match result {
+                Ok(version) => {
+                    if st.ytdlp_version.as_deref() != Some(version.as_str()) || st.error.is_some() {
+                        info!("✅ yt_dlp {version} importable");
+                    }
+                    st.ytdlp_version = Some(version);
+                    st.error = None;
+                }
+                Err(e) => {
+                    error!("❌ Python environment check failed: {e}");
+                    st.ytdlp_version = None;
+                    st.error = Some(e);
+                }
+            }
+            st.checked_at = Some(now);
+        }
+    });
+}
 
 /// Call yt_dlp.YoutubeDL.extract_info() via PyO3 and return raw JSON string.
 /// Also extracts per-format cookies from ydl.cookiejar before closing.
 /// Runs inside spawn_blocking — Tokio auto-manages the thread pool.
-pub fn extract_with_ytdlp(url: &str, cookies_path: Option<&str>) -> Result<String, String> {
+pub fn extract_with_ytdlp(
+    url: &str,
+    cookies_path: Option<&str>,
+    http_headers: &[(String, String)],
+) -> Result<String, ExtractError> {
     Python::with_gil(|py| {
         let yt_dlp = py
             .import("yt_dlp")
-            .map_err(|e| format!("Failed to import yt_dlp: {e}"))?;
+            .map_err(|e| ExtractError::Internal(format!("Failed to import yt_dlp: {e}")))?;
 
-        // Build options dict
-        let opts = PyDict::new(py);
-        opts.set_item("quiet", true).unwrap();
-        opts.set_item("no_warnings", true).unwrap();
+        let opts = build_opts(py, cookies_path, http_headers);
         opts.set_item("extract_flat", false).unwrap();
-        opts.set_item("socket_timeout", 30).unwrap();
-
-        // Add cookies if path exists
-        if let Some(cp) = cookies_path {
-            if std::path::Path::new(cp).exists() {
-                opts.set_item("cookiefile", cp).unwrap();
-            }
-        }
-
-        // ydl = yt_dlp.YoutubeDL(opts)
-        let ydl_class = yt_dlp
-            .getattr("YoutubeDL")
-            .map_err(|e| format!("Failed to get YoutubeDL: {e}"))?;
-        let ydl = ydl_class
-            .call1((opts,))
-            .map_err(|e| format!("Failed to create YoutubeDL: {e}"))?;
+        let ydl = new_ydl(&yt_dlp, opts)?;
 
         // info = ydl.extract_info(url, download=False)
         let kwargs = PyDict::new(py);
         kwargs.set_item("download", false).unwrap();
         let info = ydl
             .call_method("extract_info", (url,), Some(&kwargs))
-            .map_err(|e| {
-                let err_str = e.to_string();
-                if err_str.to_lowercase().contains("not found")
-                    || err_str.to_lowercase().contains("unable to download")
-                {
-                    format!("NOT_FOUND:{err_str}")
-                } else if err_str.contains("403") || err_str.to_lowercase().contains("forbidden") {
-                    format!("FORBIDDEN:{err_str}")
-                } else if err_str.to_lowercase().contains("login")
-                    || err_str.to_lowercase().contains("authentication")
-                {
-                    format!("AUTH_REQUIRED:{err_str}")
-                } else if err_str.to_lowercase().contains("unsupported url") {
-                    format!("UNSUPPORTED:{err_str}")
-                } else {
-                    format!("EXTRACTION_FAILED:{err_str}")
-                }
-            })?;
+            .map_err(|e| ExtractError::classify(e.to_string()))?;
 
         // Extract per-format cookies from cookiejar before closing ydl.
         // After extract_info, each format has 'http_headers' but Cookie is stripped.
@@ -69,7 +237,7 @@ pub fn extract_with_ytdlp(url: &str, cookies_path: Option<&str>) -> Result<Strin
                 None => return Ok(()),
             };
 
-            if let Ok(iter) = formats.iter() {
+            if let Ok(iter) = formats.try_iter() {
                 for fmt in iter {
                     let fmt = match fmt {
                         Ok(f) => f,
@@ -99,16 +267,87 @@ pub fn extract_with_ytdlp(url: &str, cookies_path: Option<&str>) -> Result<Strin
         // Close ydl to release file descriptors
         let _ = ydl.call_method0("close");
 
-        // Convert Python dict to JSON string via json.dumps()
-        let json_mod = py
-            .import("json")
-            .map_err(|e| format!("Failed to import json: {e}"))?;
-        let json_str = json_mod
-            .call_method1("dumps", (info,))
-            .map_err(|e| format!("Failed to serialize: {e}"))?
-            .extract::<String>()
-            .map_err(|e| format!("Failed to extract string: {e}"))?;
-
-        Ok(json_str)
+        to_json(py, info)
     })
 }
+
+/// Flat-extract a profile URL: the newest `limit` entries (id, url, title)
+/// without resolving each post. Returns the info JSON string.
+/// Runs inside spawn_blocking.
+pub fn list_entries(
+    url: &str,
+    cookies_path: Option<&str>,
+    http_headers: &[(String, String)],
+    limit: usize,
+) -> Result<String, ExtractError> {
+    Python::with_gil(|py| {
+        let yt_dlp = py
+            .import("yt_dlp")
+            .map_err(|e| ExtractError::Internal(format!("Failed to import yt_dlp: {e}")))?;
+
+        let opts = build_opts(py, cookies_path, http_headers);
+        opts.set_item("extract_flat", "in_playlist").unwrap();
+        opts.set_item("playlistend", limit).unwrap();
+        let ydl = new_ydl(&yt_dlp, opts)?;
+
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("download", false).unwrap();
+        let info = ydl
+            .call_method("extract_info", (url,), Some(&kwargs))
+            .and_then(|info| ydl.call_method1("sanitize_info", (info,)))
+            .map_err(|e| ExtractError::classify(e.to_string()));
+        let _ = ydl.call_method0("close");
+        to_json(py, info?)
+    })
+}
+
+/// Options shared by every extraction.
+fn build_opts<'py>(
+    py: Python<'py>,
+    cookies_path: Option<&str>,
+    http_headers: &[(String, String)],
+) -> Bound<'py, PyDict> {
+    let opts = PyDict::new(py);
+    opts.set_item("quiet", true).unwrap();
+    opts.set_item("no_warnings", true).unwrap();
+    opts.set_item("socket_timeout", 30).unwrap();
+
+    // Merged over yt-dlp's defaults and copied into each format's http_headers
+    if !http_headers.is_empty() {
+        let headers = PyDict::new(py);
+        for (name, value) in http_headers {
+            headers.set_item(name, value).unwrap();
+        }
+        opts.set_item("http_headers", headers).unwrap();
+    }
+
+    // Add cookies if path exists
+    if let Some(cp) = cookies_path {
+        if std::path::Path::new(cp).exists() {
+            opts.set_item("cookiefile", cp).unwrap();
+        }
+    }
+    opts
+}
+
+/// ydl = yt_dlp.YoutubeDL(opts)
+fn new_ydl<'py>(
+    yt_dlp: &Bound<'py, PyModule>,
+    opts: Bound<'py, PyDict>,
+) -> Result<Bound<'py, PyAny>, ExtractError> {
+    yt_dlp
+        .getattr("YoutubeDL")
+        .map_err(|e| ExtractError::Internal(format!("Failed to get YoutubeDL: {e}")))?
+        .call1((opts,))
+        .map_err(|e| ExtractError::Internal(format!("Failed to create YoutubeDL: {e}")))
+}
+
+/// Convert a Python dict to a JSON string via json.dumps()
+fn to_json(py: Python<'_>, info: Bound<'_, PyAny>) -> Result<String, ExtractError> {
+    py.import("json")
+        .map_err(|e| ExtractError::Internal(format!("Failed to import json: {e}")))?
+        .call_method1("dumps", (info,))
+        .map_err(|e| ExtractError::Internal(format!("Failed to serialize: {e}")))?
+        .extract::<String>()
+        .map_err(|e| ExtractError::Internal(format!("Failed to extract string: {e}")))
+}
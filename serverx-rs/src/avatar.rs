@@ -0,0 +1,255 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Browser UA for the profile pages; TikTok serves an empty shell to bots.
+const USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Safari/537.36";
+
+/// Cached lookups; past this the oldest are dropped.
+const MAX_CACHED: usize = 10_000;
+
+/// Window AVATAR_LOOKUPS_PER_MIN counts profile page fetches over.
+const LOOKUP_WINDOW: Duration = Duration::from_secs(60);
+
+/// (platform, lowercased handle) → avatar URL, or None for "has none", and
+/// when it was looked up.
+type Cache = HashMap<(String, String), (Option<String>, Instant)>;
+
+/// Why an avatar couldn't be looked up.
+#[derive(Debug)]
+pub enum LookupError {
+    /// AVATAR_LOOKUPS_PER_MIN profile pages were already fetched; retry
+    /// after this many seconds
+    RateLimited(u64),
+    Http(reqwest::Error),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RateLimited(secs) => write!(f, "profile lookups rate-limited for {}s", secs),
+            Self::Http(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<reqwest::Error> for LookupError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+/// Resolves creator avatars for GET /avatar. yt-dlp doesn't report them for
+/// TikTok or X, so the uploader's public profile is fetched once and the
+/// image URL (or its absence) kept for AVATAR_CACHE_SECS. At most
+/// AVATAR_LOOKUPS_PER_MIN profiles are fetched a minute, so random handles
+/// can't turn the server into a scraper.
+pub struct AvatarResolver {
+    client: reqwest::Client,
+    ttl: Duration,
+    /// 0 for no limit
+    lookups_per_min: usize,
+    /// When the profile pages of the last minute were fetched, oldest first
+    lookups: Mutex<VecDeque<Instant>>,
+    cache: Mutex<Cache>,
+}
+
+impl AvatarResolver {
+    pub fn new(ttl: Duration, lookups_per_min: usize) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(15))
+            .build()?;
+        Ok(Self {
+            client,
+            ttl,
+            lookups_per_min,
+            lookups: Mutex::new(VecDeque::new()),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Keep an avatar URL found in an extraction, sparing the profile lookup.
+    pub fn remember(&self, platform: &str, user: &str, url: String) {
+        self.store(platform, user, Some(url));
+    }
+
+    /// The avatar image URL of `user`; None when the profile has none or
+    /// doesn't exist. Network errors and rate limiting aren't cached.
+    pub async fn resolve(&self, platform: &str, user: &str) -> Result<Option<String>, LookupError> {
+        let key = (platform.to_string(), user.to_lowercase());
+        if let Some((url, at)) = self.cache.lock().unwrap().get(&key) {
+            if at.elapsed() < self.ttl {
+                return Ok(url.clone());
+            }
+        }
+        if !matches!(platform, "tiktok" | "x") {
+            return Ok(None);
+        }
+        self.take_lookup(Instant::now())?;
+
+        let url = match platform {
+            "tiktok" => {
+                let html = self.page(&format!("https://www.tiktok.com/@{user}")).await?;
+                json_string_field(&html, "avatarLarger")
+                    .or_else(|| json_string_field(&html, "avatarMedium"))
+            }
+            "x" => {
+                let html = self
+                    .page(&format!("https://syndication.twitter.com/srv/timeline-profile/screen-name/{user}"))
+                    .await?;
+                // `_normal` is 48px; the same image is served at 400x400
+                json_string_field(&html, "profile_image_url_https").map(|u| u.replace("_normal.", "_400x400."))
+            }
+            _ => None,
+        };
+        if url.is_none() {
+            warn!("No avatar found for {} user {}", platform, user);
+        }
+        self.store(platform, user, url.clone());
+        Ok(url)
+    }
+
+    /// GET the avatar image itself.
+    pub async fn fetch(&self, url: &str) -> Result<reqwest::Response, reqwest::Error> {
+        self.client.get(url).send().await?.error_for_status()
+    }
+
+    async fn page(&self, url: &str) -> Result<String, reqwest::Error> {
+        let response = self.client.get(url).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(String::new());
+        }
+        response.error_for_status()?.text().await
+    }
+
+    /// Count a profile fetch at `now`, or the seconds until one is allowed.
+    fn take_lookup(&self, now: Instant) -> Result<(), LookupError> {
+        if self.lookups_per_min == 0 {
+            return Ok(());
+        }
+        let mut lookups = self.lookups.lock().unwrap();
+        while lookups.front().is_some_and(|at| now.duration_since(*at) >= LOOKUP_WINDOW) {
+            lookups.pop_front();
+        }
+        if lookups.len() >= self.lookups_per_min {
+            let oldest = lookups[0];
+            let wait = LOOKUP_WINDOW.saturating_sub(now.duration_since(oldest));
+            return Err(LookupError::RateLimited(wait.as_secs().max(1)));
+        }
+        lookups.push_back(now);
+        Ok(())
+    }
+
+    fn store(&self, platform: &str, user: &str, url: Option<String>) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, (_, at)| at.elapsed() < self.ttl);
+        }
+        while cache.len() >= MAX_CACHED {
+            let Some(oldest) = cache.iter().min_by_key(|(_, (_, at))| *at).map(|(k, _)| k.clone()) else {
+                break;
+            };
+            cache.remove(&oldest);
+        }
+        cache.insert((platform.to_string(), user.to_lowercase()), (url, Instant::now()));
+    }
+}
+
+/// Profile handle GET /avatar looks up: TikTok's `uploader` is the @handle
+/// (`uploader_id` is numeric), X's is `uploader_id`. Douyin profiles aren't
+/// on tiktok.com, so they get none.
+pub fn handle_for(platform: &str, info: &serde_json::Value) -> Option<String> {
+    let extractor = info["extractor"].as_str().unwrap_or("").to_lowercase();
+    let handle = match platform {
+        "tiktok" if !extractor.starts_with("douyin") => info["uploader"].as_str(),
+        "x" => info["uploader_id"].as_str(),
+        _ => None,
+    }?;
+    valid_handle(handle).then(|| handle.to_string())
+}
+
+/// An avatar the extractor did report, as a thumbnail with "avatar" in its id.
+pub fn from_info(info: &serde_json::Value) -> Option<String> {
+    info["thumbnails"]
+        .as_array()?
+        .iter()
+        .filter(|t| t["id"].as_str().is_some_and(|id| id.contains("avatar")))
+        .max_by_key(|t| t["width"].as_i64().unwrap_or(0))
+        .and_then(|t| t["url"].as_str())
+        .map(String::from)
+}
+
+/// TikTok and X handles: letters, digits, `_` and `.`; anything else is
+/// rejected before it reaches a profile URL.
+pub fn valid_handle(user: &str) -> bool {
+    (1..=50).contains(&user.len()) && user.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// First `"field":"..."` string in a page's embedded JSON, unescaped.
+fn json_string_field(html: &str, field: &str) -> Option<String> {
+    let pattern = format!(r#""{}"\s*:\s*("(?:[^"\\]|\\.)*")"#, regex_lite::escape(field));
+    let re = regex_lite::Regex::new(&pattern).ok()?;
+    let literal = re.captures(html)?.get(1)?.as_str();
+    serde_json::from_str::<String>(literal).ok().filter(|u| u.starts_with("https://"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_avatar_parsing() {
+        let tiktok = r#"<script>{"user":{"uniqueId":"creator","avatarLarger":"https://p16.tiktokcdn.com/a.jpeg?x=1&y=2"}}</script>"#;
+        assert_eq!(
+            json_string_field(tiktok, "avatarLarger").as_deref(),
+            Some("https://p16.tiktokcdn.com/a.jpeg?x=1&y=2")
+        );
+        assert_eq!(json_string_field(tiktok, "avatarMedium"), None);
+        assert_eq!(json_string_field(r#""avatarLarger":"javascript:x""#, "avatarLarger"), None);
+
+        let info = serde_json::json!({"extractor": "TikTok", "uploader": "creator", "uploader_id": "6812345"});
+        assert_eq!(handle_for("tiktok", &info).as_deref(), Some("creator"));
+        let info = serde_json::json!({"extractor": "Douyin", "uploader": "creator"});
+        assert_eq!(handle_for("tiktok", &info), None);
+        let info = serde_json::json!({"extractor": "twitter", "uploader": "Some Name", "uploader_id": "someone"});
+        assert_eq!(handle_for("x", &info).as_deref(), Some("someone"));
+        assert!(!valid_handle("../admin"));
+        assert!(!valid_handle(""));
+
+        let info = serde_json::json!({"thumbnails": [
+            {"id": "cover", "url": "https://c/cover.jpg"},
+            {"id": "avatar_small", "url": "https://c/s.jpg", "width": 100},
+            {"id": "avatar_large", "url": "https://c/l.jpg", "width": 720},
+        ]});
+        assert_eq!(from_info(&info).as_deref(), Some("https://c/l.jpg"));
+    }
+
+    #[test]
+    fn test_lookup_limit_and_eviction() {
+        let resolver = AvatarResolver::new(Duration::from_secs(60), 2).unwrap();
+        let start = Instant::now();
+        assert!(resolver.take_lookup(start).is_ok());
+        assert!(resolver.take_lookup(start + Duration::from_secs(10)).is_ok());
+        assert!(matches!(
+            resolver.take_lookup(start + Duration::from_secs(20)),
+            Err(LookupError::RateLimited(40))
+        ));
+        assert!(resolver.take_lookup(start + LOOKUP_WINDOW).is_ok());
+        let unlimited = AvatarResolver::new(Duration::from_secs(60), 0).unwrap();
+        assert!((0..100).all(|_| unlimited.take_lookup(start).is_ok()));
+
+        // A full cache drops its oldest lookup, not everything
+        for i in 0..MAX_CACHED {
+            resolver.remember("tiktok", &format!("user{i}"), format!("https://p16.tiktokcdn.com/{i}.jpeg"));
+        }
+        resolver.remember("x", "newcomer", "https://pbs.twimg.com/n.jpg".into());
+        let cache = resolver.cache.lock().unwrap();
+        assert_eq!(cache.len(), MAX_CACHED);
+        assert!(cache.contains_key(&("x".to_string(), "newcomer".to_string())));
+        assert!(cache.contains_key(&("tiktok".to_string(), format!("user{}", MAX_CACHED - 1))));
+    }
+}
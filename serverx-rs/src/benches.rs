@@ -0,0 +1,158 @@
+//! Criterion benchmarks of the per-request hot paths: format parsing and
+//! response building for large playlists, session (de)serialization,
+//! session encryption and forwarding a CDN body to the client. They live
+//! in the binary's test harness, which is what can see these functions,
+//! and are ignored by a plain `cargo test`:
+//!
+//! ```text
+//! cargo test --release --bin serverx-rs bench_ -- --ignored --nocapture
+//! ```
+//!
+//! Each benchmark fails the run when its median exceeds its budget (scaled
+//! by BENCH_BUDGET_SCALE, default 1), so CI catches large regressions while
+//! criterion's report in target/criterion compares against the last run.
+
+use criterion::{black_box, Criterion};
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// Entries in the benchmarked playlist; X caps galleries at 4, but
+/// TikTok/X playlists from profiles and threads run into the hundreds.
+const PLAYLIST_ENTRIES: usize = 200;
+
+/// A playlist of PLAYLIST_ENTRIES videos, each with the formats of the
+/// fixture X video (progressive, HLS and audio).
+fn large_playlist() -> serde_json::Value {
+    let raw = include_str!("../fixtures/x_1790000000000000001.json");
+    let video = serde_json::from_str::<serde_json::Value>(raw).unwrap()["info"].clone();
+    let entries: Vec<serde_json::Value> = (0..PLAYLIST_ENTRIES)
+        .map(|i| {
+            let mut entry = video.clone();
+            entry["id"] = format!("17900000000000{i:05}").into();
+            entry
+        })
+        .collect();
+    let mut playlist = video;
+    playlist["_type"] = "playlist".into();
+    playlist["formats"].take();
+    playlist["entries"] = entries.into();
+    playlist
+}
+
+/// Size of the benchmarked /stream body and of the chunks the CDN client
+/// hands it over in, which for HTTP/1.1 are often a few KB.
+const FORWARD_BYTES: usize = 64 << 20;
+const UPSTREAM_CHUNK: usize = 4 << 10;
+
+/// Pass FORWARD_BYTES through the /stream body pipeline, as `stream` builds
+/// it with a CDN_READ_BUFFER_KB of `read_buffer` bytes, to the end of the
+/// response body; returns the bytes forwarded.
+async fn forward(chunk: &axum::body::Bytes, read_buffer: usize, budget: &Arc<budget::EgressBudget>) -> usize {
+    let chunk = chunk.clone();
+    let source = futures_util::stream::iter((0..FORWARD_BYTES / UPSTREAM_CHUNK).map(move |_| Ok::<_, std::io::Error>(chunk.clone())));
+    let upstream = cdn::read_timeout(source, Duration::from_secs(30));
+    let mut upstream = cdn::coalesce(upstream, read_buffer);
+    let prefix = sniff::read_prefix(&mut upstream).await.unwrap();
+    let mut body = forward_body(prefix, upstream, Some(FORWARD_BYTES as u64), Some(budget)).into_data_stream();
+    let mut forwarded = 0;
+    while let Some(chunk) = body.next().await {
+        forwarded += chunk.unwrap().len();
+    }
+    forwarded
+}
+
+/// Benchmarks `routine` under `name`; an error when its median time per
+/// iteration exceeds `budget` times `scale`.
+fn bench<R>(c: &mut Criterion, name: &str, budget: Duration, scale: f64, mut routine: impl FnMut() -> R) -> Result<(), String> {
+    let mut samples = Vec::new();
+    c.bench_function(name, |b| {
+        b.iter_custom(|iters| {
+            let started = Instant::now();
+            for _ in 0..iters {
+                black_box(routine());
+            }
+            let elapsed = started.elapsed();
+            samples.push(elapsed / iters.max(1) as u32);
+            elapsed
+        })
+    });
+    samples.sort();
+    let median = samples[samples.len() / 2];
+    let budget = budget.mul_f64(scale);
+    if median > budget {
+        return Err(format!("{name}: median {median:?} > budget {budget:?}"));
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore = "benchmark, run with --release -- --ignored"]
+fn bench_hot_paths() {
+    let scale: f64 = env_parse("BENCH_BUDGET_SCALE", 1.0);
+    let mut c = Criterion::default()
+        .sample_size(20)
+        .warm_up_time(Duration::from_millis(300))
+        .measurement_time(Duration::from_secs(1))
+        .without_plots();
+
+    let playlist = large_playlist();
+    let url = "https://x.com/example/status/1790000000000000003";
+    let filter = FormatFilter::default();
+    let session = build_session_data(&[], &[], &[], &playlist, SessionMeta {
+        client_cookies: None,
+        job_id: "job",
+        platform: "x",
+        slideshow_enabled: true,
+        filter: &filter,
+        selected: &[],
+        url,
+    });
+    let json = serde_json::to_vec(&session).unwrap();
+    let cipher = SessionCipher::new("bench-key");
+    let sealed = cipher.seal(&json).unwrap();
+    let token = seal_session_token(&cipher, &session).unwrap();
+    let token_body = token.strip_prefix(SESSION_TOKEN_PREFIX).unwrap();
+    let entries = playlist["entries"].as_array().unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let chunk = axum::body::Bytes::from(vec![0; UPSTREAM_CHUNK]);
+    let budget = budget::EgressBudget::with_vars(&|key: &str| (key == "EGRESS_BUDGET_GB").then(|| "1000".into()));
+    let budget = Arc::new(budget.unwrap());
+
+    // Budgets are ~10x the medians of a release build on the machine they
+    // were set on: room for shared CI runners, not for a quadratic loop
+    let ms = Duration::from_millis;
+    let results = [
+        bench(&mut c, "parse_formats/playlist_entries", ms(15), scale, || {
+            entries.iter().map(|e| parse_formats(e["formats"].as_array().unwrap(), &filter).0.len()).sum::<usize>()
+        }),
+        bench(&mut c, "download/playlist_response", ms(30), scale, || {
+            build_response_with_session(&playlist, url, &[], &[], &[], &filter, "sid", "https://api.example")
+        }),
+        bench(&mut c, "download/playlist_session", ms(40), scale, || {
+            build_session_data(&[], &[], &[], &playlist, SessionMeta {
+                client_cookies: None,
+                job_id: "job",
+                platform: "x",
+                slideshow_enabled: true,
+                filter: &filter,
+                selected: &[],
+                url,
+            })
+        }),
+        bench(&mut c, "session/serialize", ms(10), scale, || serde_json::to_vec(&session).unwrap()),
+        bench(&mut c, "session/deserialize", ms(25), scale, || serde_json::from_slice::<SessionData>(&json).unwrap()),
+        bench(&mut c, "cipher/seal", ms(5), scale, || cipher.seal(&json).unwrap()),
+        bench(&mut c, "cipher/open", ms(5), scale, || cipher.open(&sealed).unwrap()),
+        bench(&mut c, "session_token/seal", ms(25), scale, || seal_session_token(&cipher, &session).unwrap()),
+        bench(&mut c, "session_token/open", ms(30), scale, || open_session_token(&cipher, token_body).unwrap()),
+        // Merging the 4 KB chunks (CDN_READ_BUFFER_KB=64) against passing
+        // them on as they come (the default)
+        bench(&mut c, "stream/forward_64mib", ms(20), scale, || runtime.block_on(forward(&chunk, 64 << 10, &budget))),
+        bench(&mut c, "stream/forward_64mib_unmerged", ms(20), scale, || runtime.block_on(forward(&chunk, 0, &budget))),
+    ];
+    c.final_summary();
+
+    let over: Vec<String> = results.into_iter().filter_map(Result::err).collect();
+    assert!(over.is_empty(), "Over budget (BENCH_BUDGET_SCALE={scale}):\n{}", over.join("\n"));
+}
@@ -0,0 +1,201 @@
+//! Extraction worker for serverx-rs's remote mode: runs yt-dlp through PyO3
+//! and nothing else, so the API servers (EXTRACTOR_WORKERS) can be built
+//! without Python and scaled separately. Protocol: see `RemoteWorkers`.
+
+// Shared with the API server, which uses the rest of them
+#[path = "../auth.rs"]
+mod auth;
+#[allow(dead_code)]
+#[path = "../cookies.rs"]
+mod cookies;
+#[allow(dead_code)]
+#[path = "../error.rs"]
+mod error;
+#[path = "../ytdlp.rs"]
+mod ytdlp;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use std::env;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::{error, info};
+
+use error::ExtractError;
+
+#[derive(Clone)]
+struct WorkerState {
+    /// Concurrent yt-dlp extractions (WORKER_CONCURRENCY); the rest wait
+    permits: Arc<Semaphore>,
+    /// Bearer token the API servers must send (EXTRACTOR_TOKEN)
+    token: Option<String>,
+    /// Longest wait for one extraction (WORKER_EXTRACT_TIMEOUT_SECS)
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct ExtractRequest {
+    url: String,
+    /// Netscape cookies.txt content
+    #[serde(default)]
+    cookies: Option<String>,
+    /// yt-dlp format selector
+    #[serde(default)]
+    format: Option<String>,
+    /// Local address yt-dlp binds to (the API server's EGRESS_IP_FAMILY)
+    #[serde(default)]
+    source_address: Option<String>,
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn error_response(e: &ExtractError) -> Response {
+    (
+        e.status(),
+        Json(serde_json::json!({ "kind": e.kind(), "message": e.message() })),
+    )
+        .into_response()
+}
+
+/// 401 unless the request carries EXTRACTOR_TOKEN.
+fn reject_unauthorized(headers: &HeaderMap, state: &WorkerState) -> Option<Response> {
+    let token = state.token.as_deref()?;
+    if auth::bearer(headers).is_some_and(|sent| auth::constant_time_eq(sent.as_bytes(), token.as_bytes())) {
+        return None;
+    }
+    // Reported as an internal error, since it's a deployment mistake
+    Some(
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "kind": "internal", "message": "Invalid extractor token" })),
+        )
+            .into_response(),
+    )
+}
+
+/// POST /extract — yt-dlp info JSON for `url`
+async fn extract(State(state): State<WorkerState>, headers: HeaderMap, Json(req): Json<ExtractRequest>) -> Response {
+    if let Some(resp) = reject_unauthorized(&headers, &state) {
+        return resp;
+    }
+    let cookie_file = match req.cookies.as_deref().map(cookies::write_temp_cookie_file).transpose() {
+        Ok(f) => f,
+        Err(e) => return error_response(&ExtractError::Internal(format!("Failed to write cookies: {e}"))),
+    };
+    let permit = state.permits.clone().acquire_owned().await.expect("worker semaphore closed");
+    let started = std::time::Instant::now();
+    let (url, format, source_address) = (req.url, req.format, req.source_address);
+    // yt-dlp can't be interrupted: a timed-out extraction keeps its permit
+    // until it returns, so a stuck worker stops taking more work
+    let task = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let cookie_path = cookie_file.as_ref().map(|f| f.path());
+        ytdlp::extract_with_ytdlp(&url, cookie_path.as_deref(), format.as_deref(), source_address.as_deref())
+    });
+    let result = match tokio::time::timeout(state.timeout, task).await {
+        Ok(joined) => joined.unwrap_or_else(|e| Err(ExtractError::Internal(format!("Task join error: {e}")))),
+        Err(_) => Err(ExtractError::Timeout(format!("Extraction exceeded {}s", state.timeout.as_secs()))),
+    };
+
+    match result {
+        Ok(json) => {
+            info!("Extracted in {}ms", started.elapsed().as_millis());
+            ([("Content-Type", "application/json")], json).into_response()
+        }
+        Err(e) => {
+            error!("Extraction failed: {}", e);
+            error_response(&e)
+        }
+    }
+}
+
+/// GET /health — yt-dlp version, 503 when the Python env is broken
+async fn health(State(state): State<WorkerState>, headers: HeaderMap) -> Response {
+    if let Some(resp) = reject_unauthorized(&headers, &state) {
+        return resp;
+    }
+    match tokio::task::spawn_blocking(ytdlp::check_ytdlp).await {
+        Ok(Ok(version)) => Json(serde_json::json!({
+            "status": "healthy",
+            "ytdlp_version": version,
+            "available_permits": state.permits.available_permits(),
+        }))
+        .into_response(),
+        Ok(Err(e)) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "unhealthy", "error": e })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "unhealthy", "error": format!("Task join error: {e}") })),
+        )
+            .into_response(),
+    }
+}
+
+fn is_loopback(host: &str) -> bool {
+    host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let port: u16 = env_parse("PORT", 8090);
+    let token = env::var("EXTRACTOR_TOKEN").ok().filter(|t| !t.is_empty());
+    // Without a token anyone who can reach the worker can use it as an
+    // extraction proxy, so it only listens on loopback then
+    let host = env::var("WORKER_HOST")
+        .ok()
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| if token.is_some() { "0.0.0.0" } else { "127.0.0.1" }.to_string());
+    if token.is_none() && !is_loopback(&host) {
+        error!("Refusing to listen on {} without EXTRACTOR_TOKEN", host);
+        std::process::exit(1);
+    }
+    let preload_extractors = env_parse("PRELOAD_EXTRACTORS", true);
+    match tokio::task::spawn_blocking(move || ytdlp::warm_up_ytdlp(preload_extractors)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => {
+            error!("yt-dlp warm-up failed: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            error!("yt-dlp warm-up task failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let concurrency: usize = env_parse("WORKER_CONCURRENCY", 4);
+    let state = WorkerState {
+        permits: Arc::new(Semaphore::new(concurrency.max(1))),
+        token,
+        timeout: Duration::from_secs(env_parse("WORKER_EXTRACT_TIMEOUT_SECS", 120).max(1)),
+    };
+    let app = Router::new()
+        .route("/extract", post(extract))
+        .route("/health", get(health))
+        .with_state(state);
+
+    let addr = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{ip}]:{port}"),
+        _ => format!("{host}:{port}"),
+    };
+    info!("🛠️  serverx-worker listening on {addr} ({concurrency} concurrent extractions)");
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
@@ -0,0 +1,338 @@
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::cdn::num_var;
+
+/// Slices of the rolling window; bytes leave it a slice at a time (an hour
+/// of a 30 day window).
+const BUCKETS: u64 = 720;
+
+/// Bytes a metered stream counts before adding them to the window, so
+/// streams don't take its lock for every chunk.
+const METER_BATCH_BYTES: u64 = 1 << 20;
+
+/// Request header that exempts a stream from shedding when it carries one of
+/// EGRESS_BUDGET_PRIORITY_TOKENS.
+pub const PRIORITY_HEADER: &str = "x-priority-token";
+
+/// What non-priority streams get at the current budget use.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shedding {
+    None,
+    /// Video is served at DOWNGRADE_HEIGHT or lower
+    Downgrade,
+    /// Streams are refused until enough bytes leave the window
+    Reject,
+}
+
+/// Budget state for /health.
+#[derive(Serialize, Clone)]
+pub struct BudgetStatus {
+    pub limit_bytes: u64,
+    pub used_bytes: u64,
+    pub remaining_bytes: u64,
+    pub used_ratio: f64,
+    pub window_secs: u64,
+    pub shedding: Shedding,
+}
+
+/// Media bytes sent to clients (/stream, chapter clips, /slideshow) over a
+/// rolling window, against a budget such as a VPS's monthly traffic cap
+/// (EGRESS_BUDGET_*). Past DOWNGRADE_AT of it, non-priority video is
+/// served in a lower resolution; past REJECT_AT, non-priority streams get
+/// 503 with a Retry-After.
+pub struct EgressBudget {
+    /// Bytes per window (EGRESS_BUDGET_GB, decimal gigabytes)
+    limit: u64,
+    /// EGRESS_BUDGET_WINDOW_DAYS
+    window: Duration,
+    /// Fractions of `limit` (EGRESS_BUDGET_DOWNGRADE_AT, EGRESS_BUDGET_REJECT_AT)
+    downgrade_at: f64,
+    reject_at: f64,
+    /// Tallest video served while downgrading (EGRESS_BUDGET_DOWNGRADE_HEIGHT)
+    pub downgrade_height: u32,
+    /// EGRESS_BUDGET_PRIORITY_TOKENS
+    priority_tokens: Vec<String>,
+    /// Where the window is saved every minute, so a restart doesn't reset
+    /// it (EGRESS_BUDGET_STATE_PATH)
+    state_path: Option<PathBuf>,
+    /// (bucket number since the epoch, bytes), oldest first
+    buckets: Mutex<VecDeque<(u64, u64)>>,
+}
+
+impl EgressBudget {
+    /// None unless EGRESS_BUDGET_GB is set.
+    pub fn from_env() -> Option<Self> {
+        Self::with_vars(&|key| std::env::var(key).ok())
+    }
+
+    pub(crate) fn with_vars(var: &impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let fraction = |key: &str, default: f64| match var(key).map(|v| v.trim().parse::<f64>()) {
+            Some(Ok(f)) if (0.0..=1.0).contains(&f) => f,
+            Some(_) => {
+                warn!("Ignoring invalid {} (a fraction between 0 and 1)", key);
+                default
+            }
+            None => default,
+        };
+        let gb = var("EGRESS_BUDGET_GB")?.trim().parse::<f64>().ok().filter(|gb| *gb > 0.0)?;
+        let days = num_var(var, "EGRESS_BUDGET_WINDOW_DAYS").filter(|d| *d > 0).unwrap_or(30);
+        let downgrade_at = fraction("EGRESS_BUDGET_DOWNGRADE_AT", 0.8);
+        let mut budget = Self {
+            limit: (gb * 1e9) as u64,
+            window: Duration::from_secs(days * 86400),
+            downgrade_at,
+            reject_at: fraction("EGRESS_BUDGET_REJECT_AT", 0.95).max(downgrade_at),
+            downgrade_height: num_var(var, "EGRESS_BUDGET_DOWNGRADE_HEIGHT").map_or(480, |h| h.min(u32::MAX as u64) as u32),
+            priority_tokens: var("EGRESS_BUDGET_PRIORITY_TOKENS")
+                .map(|v| v.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+                .unwrap_or_default(),
+            state_path: var("EGRESS_BUDGET_STATE_PATH").filter(|p| !p.is_empty()).map(PathBuf::from),
+            buckets: Mutex::new(VecDeque::new()),
+        };
+        budget.load();
+        Some(budget)
+    }
+
+    fn bucket_secs(&self) -> u64 {
+        (self.window.as_secs() / BUCKETS).max(1)
+    }
+
+    /// Count `bytes` sent now.
+    pub fn add(&self, bytes: u64) {
+        self.add_at(bytes, now_secs());
+    }
+
+    fn add_at(&self, bytes: u64, now: u64) {
+        let bucket = now / self.bucket_secs();
+        let mut buckets = self.buckets.lock().unwrap();
+        match buckets.back_mut() {
+            Some((b, sum)) if *b == bucket => *sum += bytes,
+            _ => buckets.push_back((bucket, bytes)),
+        }
+    }
+
+    /// Bytes sent within the window, dropping buckets that left it.
+    fn used_at(&self, now: u64) -> u64 {
+        self.prune(&mut self.buckets.lock().unwrap(), now)
+    }
+
+    /// Drop the buckets of `buckets` that left the window at `now` and sum
+    /// the rest.
+    fn prune(&self, buckets: &mut VecDeque<(u64, u64)>, now: u64) -> u64 {
+        let oldest = (now.saturating_sub(self.window.as_secs())) / self.bucket_secs();
+        while buckets.front().is_some_and(|(b, _)| *b <= oldest) {
+            buckets.pop_front();
+        }
+        buckets.iter().map(|(_, bytes)| bytes).sum()
+    }
+
+    fn shedding_at(&self, now: u64) -> Shedding {
+        let used = self.used_at(now) as f64 / self.limit as f64;
+        if used >= self.reject_at {
+            Shedding::Reject
+        } else if used >= self.downgrade_at {
+            Shedding::Downgrade
+        } else {
+            Shedding::None
+        }
+    }
+
+    /// Shedding for a stream; requests with a priority token are never shed.
+    pub fn shedding(&self, priority_token: Option<&str>) -> Shedding {
+        match priority_token {
+            Some(token) if self.priority_tokens.iter().any(|t| t == token) => Shedding::None,
+            _ => self.shedding_at(now_secs()),
+        }
+    }
+
+    /// Seconds until enough buckets leave the window to go back under
+    /// REJECT_AT.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after_at(now_secs())
+    }
+
+    fn retry_after_at(&self, now: u64) -> u64 {
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut used = self.prune(&mut buckets, now);
+        // With REJECT_AT 0 nothing is under it; an empty window is the best bet
+        let allowed = ((self.limit as f64 * self.reject_at) as u64).max(1);
+        for (bucket, bytes) in buckets.iter() {
+            used = used.saturating_sub(*bytes);
+            if used < allowed {
+                // The bucket leaves the window once the window's start passes its end
+                let leaves = (bucket + 1) * self.bucket_secs() + self.window.as_secs();
+                return leaves.saturating_sub(now).max(1);
+            }
+        }
+        1
+    }
+
+    pub fn status(&self) -> BudgetStatus {
+        let now = now_secs();
+        let used = self.used_at(now);
+        BudgetStatus {
+            limit_bytes: self.limit,
+            used_bytes: used,
+            remaining_bytes: self.limit.saturating_sub(used),
+            used_ratio: used as f64 / self.limit as f64,
+            window_secs: self.window.as_secs(),
+            shedding: self.shedding_at(now),
+        }
+    }
+
+    /// `body`, counting what is sent in batches of METER_BATCH_BYTES; the
+    /// rest is counted when the body is dropped.
+    pub fn meter<S, E>(self: &Arc<Self>, body: S) -> impl Stream<Item = Result<Bytes, E>> + Send + 'static
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    {
+        let mut tally = Tally { budget: self.clone(), unbatched: 0 };
+        body.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                tally.add(chunk.len() as u64);
+            }
+        })
+    }
+
+    fn load(&mut self) {
+        let Some(path) = &self.state_path else {
+            return;
+        };
+        let saved = std::fs::read(path).ok().and_then(|raw| serde_json::from_slice::<Vec<(u64, u64)>>(&raw).ok());
+        if let Some(saved) = saved {
+            info!("Loaded egress budget state from {}", path.display());
+            *self.buckets.get_mut().unwrap() = saved.into();
+        }
+    }
+
+    /// Write the window to EGRESS_BUDGET_STATE_PATH, if set.
+    pub fn save(&self) {
+        let Some(path) = &self.state_path else {
+            return;
+        };
+        let buckets: Vec<(u64, u64)> = self.buckets.lock().unwrap().iter().copied().collect();
+        let written = serde_json::to_vec(&buckets)
+            .map_err(std::io::Error::other)
+            .and_then(|raw| std::fs::write(path, raw));
+        if let Err(e) = written {
+            warn!("Failed to save egress budget state to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Bytes of a metered stream not yet added to its budget.
+struct Tally {
+    budget: Arc<EgressBudget>,
+    unbatched: u64,
+}
+
+impl Tally {
+    fn add(&mut self, bytes: u64) {
+        self.unbatched += bytes;
+        if self.unbatched >= METER_BATCH_BYTES {
+            self.budget.add(std::mem::take(&mut self.unbatched));
+        }
+    }
+}
+
+impl Drop for Tally {
+    fn drop(&mut self) {
+        if self.unbatched > 0 {
+            self.budget.add(self.unbatched);
+        }
+    }
+}
+
+/// Save `budget`'s window every minute. Call once at startup.
+pub fn spawn_save_task(budget: Arc<EgressBudget>) {
+    if budget.state_path.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let budget = budget.clone();
+            let _ = tokio::task::spawn_blocking(move || budget.save()).await;
+        }
+    });
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Height of a video format from its quality label ("720p (hls)"); None for
+/// audio and images.
+pub fn video_height(quality: &str) -> Option<u32> {
+    quality.split('p').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_budget_window_and_shedding() {
+        let vars = HashMap::from([
+            ("EGRESS_BUDGET_GB", "0.000001"),
+            ("EGRESS_BUDGET_WINDOW_DAYS", "30"),
+            ("EGRESS_BUDGET_REJECT_AT", "1.5"),
+            ("EGRESS_BUDGET_PRIORITY_TOKENS", "vip, ,staff"),
+        ]);
+        let budget = EgressBudget::with_vars(&|k: &str| vars.get(k).map(|v| v.to_string())).unwrap();
+        assert_eq!((budget.limit, budget.reject_at, budget.downgrade_height), (1000, 0.95, 480));
+        assert_eq!(budget.bucket_secs(), 3600);
+
+        let start = 1_700_000_000 / 3600 * 3600;
+        budget.add_at(500, start);
+        budget.add_at(300, start + 10);
+        assert_eq!(budget.shedding_at(start + 20), Shedding::Downgrade);
+        budget.add_at(200, start + 7200);
+        assert_eq!(budget.used_at(start + 7200), 1000);
+        assert_eq!(budget.shedding_at(start + 7200), Shedding::Reject);
+        // The first hour's 800 bytes leave the window 30 days after it ends
+        let window = 30 * 86400;
+        assert_eq!(budget.retry_after_at(start + 7200), 3600 + window - 7200);
+        assert_eq!(budget.used_at(start + 3600 + window), 200);
+        assert_eq!(budget.shedding_at(start + 3600 + window), Shedding::None);
+
+        // REJECT_AT 0 rejects until the window is empty
+        let vars = HashMap::from([("EGRESS_BUDGET_GB", "0.000001"), ("EGRESS_BUDGET_DOWNGRADE_AT", "0"), ("EGRESS_BUDGET_REJECT_AT", "0")]);
+        let strict = EgressBudget::with_vars(&|k: &str| vars.get(k).map(|v| v.to_string())).unwrap();
+        strict.add_at(100, start);
+        assert_eq!(strict.retry_after_at(start + 10), 3600 + window - 10);
+
+        assert_eq!(budget.priority_tokens, ["vip", "staff"]);
+        assert!(EgressBudget::with_vars(&|_: &str| None).is_none());
+        assert_eq!(video_height("1080p (progressive)"), Some(1080));
+        assert_eq!(video_height("128kbps"), None);
+    }
+
+    #[tokio::test]
+    async fn test_meter_batches() {
+        let budget = Arc::new(EgressBudget::with_vars(&|k: &str| (k == "EGRESS_BUDGET_GB").then(|| "1".into())).unwrap());
+        let chunk = Bytes::from(vec![0; 400 << 10]);
+        let three = futures_util::stream::iter([Ok::<_, ()>(chunk.clone()), Ok(chunk.clone()), Ok(chunk.clone())]);
+        let mut body = Box::pin(budget.meter(three));
+        body.next().await;
+        body.next().await;
+        assert_eq!(budget.status().used_bytes, 0);
+        body.next().await;
+        assert_eq!(budget.status().used_bytes, 1200 << 10);
+        // A body dropped mid-batch still counts what it sent
+        let mut body = Box::pin(budget.meter(futures_util::stream::iter([Ok::<_, ()>(chunk)])));
+        body.next().await;
+        drop(body);
+        assert_eq!(budget.status().used_bytes, 1600 << 10);
+    }
+}
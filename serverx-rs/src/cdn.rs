@@ -0,0 +1,736 @@
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+use tracing::warn;
+
+use crate::dns::{CdnResolver, DnsSettings};
+use crate::egress::{Egress, IpFamily};
+
+/// Platforms with their own CDN_{PLATFORM}_* / EGRESS_{PLATFORM}_* overrides
+/// (session platforms).
+pub const PLATFORMS: [&str; 2] = ["tiktok", "x"];
+
+/// Timeouts and retries for requests to the media CDNs — /stream and the
+/// /slideshow downloads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CdnPolicy {
+    /// Establishing the connection (CDN_CONNECT_TIMEOUT_SECS)
+    pub connect_timeout: Duration,
+    /// Longest wait for the response headers or the next body chunk
+    /// (CDN_READ_TIMEOUT_SECS); a stalled CDN is given up after this
+    pub read_timeout: Duration,
+    /// Cap on a whole transfer, None for no cap (CDN_TOTAL_TIMEOUT_SECS, 0)
+    pub total_timeout: Option<Duration>,
+    /// Extra attempts when the CDN can't be reached, doesn't answer or answers
+    /// 429/5xx — only before anything was sent to the client (CDN_RETRIES)
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each next (CDN_RETRY_BACKOFF_MS)
+    pub retry_backoff: Duration,
+}
+
+impl Default for CdnPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            total_timeout: Some(Duration::from_secs(300)),
+            retries: 2,
+            retry_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Numeric variable `key`, None (with a warning) when it isn't a number.
+pub fn num_var(var: &impl Fn(&str) -> Option<String>, key: &str) -> Option<u64> {
+    let raw = var(key)?;
+    match raw.trim().parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            warn!("Ignoring invalid {}={}", key, raw);
+            None
+        }
+    }
+}
+
+/// Boolean variable `key`: anything but false/0/no is true.
+pub fn bool_var(var: &impl Fn(&str) -> Option<String>, key: &str) -> Option<bool> {
+    var(key).map(|v| !matches!(v.trim().to_lowercase().as_str(), "false" | "0" | "no"))
+}
+
+impl CdnPolicy {
+    /// `base` with the `{prefix}_*` variables that are set applied.
+    fn with_vars(base: &CdnPolicy, prefix: &str, var: &impl Fn(&str) -> Option<String>) -> Self {
+        let num = |name: &str| num_var(var, &format!("{prefix}_{name}"));
+        Self {
+            connect_timeout: num("CONNECT_TIMEOUT_SECS").map_or(base.connect_timeout, Duration::from_secs),
+            read_timeout: num("READ_TIMEOUT_SECS").map_or(base.read_timeout, Duration::from_secs),
+            total_timeout: num("TOTAL_TIMEOUT_SECS").map_or(base.total_timeout, |s| (s > 0).then(|| Duration::from_secs(s))),
+            retries: num("RETRIES").map_or(base.retries, |n| n.min(u32::MAX as u64) as u32),
+            retry_backoff: num("RETRY_BACKOFF_MS").map_or(base.retry_backoff, Duration::from_millis),
+        }
+    }
+
+    /// Pooled HTTP client with this policy's connect and total timeouts,
+    /// connecting from `local_address`.
+    fn client(&self, pool: &CdnPool, dns: &Dns, local_address: Option<IpAddr>) -> reqwest::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .pool_max_idle_per_host(pool.max_idle_per_host)
+            .pool_idle_timeout(pool.idle_timeout)
+            .tcp_keepalive(pool.tcp_keepalive)
+            .tcp_nodelay(true);
+        builder = if pool.http2 {
+            // Negotiated through ALPN with CDNs that support it. Frames as
+            // large as the read buffer mean fewer, larger body chunks
+            let frame_size = (pool.read_buffer > 0).then(|| pool.read_buffer.clamp(16 << 10, (16 << 20) - 1) as u32);
+            builder.http2_adaptive_window(true).http2_max_frame_size(frame_size)
+        } else {
+            builder.http1_only()
+        };
+        if let Some(total) = self.total_timeout {
+            builder = builder.timeout(total);
+        }
+        if let Some(resolver) = &dns.resolver {
+            builder = builder.dns_resolver(resolver.clone());
+        }
+        for (host, addrs) in &dns.settings.overrides {
+            builder = builder.resolve_to_addrs(host, &dns.family.order(addrs.clone()));
+        }
+        builder = builder.local_address(local_address);
+        builder.build()
+    }
+
+    /// Send `request`, retrying failed attempts with exponential backoff. Each
+    /// attempt waits at most `read_timeout` for the response headers; the
+    /// last attempt's 429/5xx response is returned as is.
+    pub async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, String> {
+        let mut attempt = 0;
+        loop {
+            let Some(this) = request.try_clone() else {
+                return Err("CDN request can't be retried".into());
+            };
+            let error = match tokio::time::timeout(self.read_timeout, this.send()).await {
+                Ok(Ok(resp)) if attempt < self.retries && retryable(resp.status().as_u16()) => {
+                    format!("HTTP {}", resp.status().as_u16())
+                }
+                Ok(Ok(resp)) => return Ok(resp),
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => format!("no response within {}s", self.read_timeout.as_secs()),
+            };
+            if attempt >= self.retries {
+                return Err(error);
+            }
+            let delay = self.retry_backoff.saturating_mul(2u32.saturating_pow(attempt));
+            warn!("CDN request failed ({}), retry {}/{} in {}ms", error, attempt + 1, self.retries, delay.as_millis());
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn retryable(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+/// Connection reuse for CDN requests, shared by every platform.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CdnPool {
+    /// Idle connections kept per CDN host (CDN_POOL_MAX_IDLE_PER_HOST)
+    pub max_idle_per_host: usize,
+    /// How long an idle connection is kept (CDN_POOL_IDLE_TIMEOUT_SECS)
+    pub idle_timeout: Duration,
+    /// TCP keepalive probe interval, None when off (CDN_TCP_KEEPALIVE_SECS, 0)
+    pub tcp_keepalive: Option<Duration>,
+    /// Offer HTTP/2; off forces HTTP/1.1 (CDN_HTTP2)
+    pub http2: bool,
+    /// Size body chunks are read and forwarded in, bytes: the largest
+    /// HTTP/2 frame asked of the CDN, and what smaller chunks are merged up
+    /// to (CDN_READ_BUFFER_KB, default 0 = as they come). Merging copies the
+    /// small chunks, which costs more CPU than it saves in the benchmarks;
+    /// it only pays off where each write to the client is expensive
+    pub read_buffer: usize,
+}
+
+impl Default for CdnPool {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: 32,
+            idle_timeout: Duration::from_secs(90),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+            http2: true,
+            read_buffer: 0,
+        }
+    }
+}
+
+impl CdnPool {
+    fn with_vars(var: &impl Fn(&str) -> Option<String>) -> Self {
+        let base = Self::default();
+        Self {
+            max_idle_per_host: num_var(var, "CDN_POOL_MAX_IDLE_PER_HOST").map_or(base.max_idle_per_host, |n| n as usize),
+            idle_timeout: num_var(var, "CDN_POOL_IDLE_TIMEOUT_SECS").map_or(base.idle_timeout, Duration::from_secs),
+            tcp_keepalive: num_var(var, "CDN_TCP_KEEPALIVE_SECS")
+                .map_or(base.tcp_keepalive, |s| (s > 0).then(|| Duration::from_secs(s))),
+            http2: bool_var(var, "CDN_HTTP2").unwrap_or(base.http2),
+            read_buffer: num_var(var, "CDN_READ_BUFFER_KB").map_or(base.read_buffer, |kb| (kb as usize).saturating_mul(1024)),
+        }
+    }
+}
+
+/// DNS for the CDN clients: one resolver, so they share its cache.
+struct Dns {
+    settings: DnsSettings,
+    family: IpFamily,
+    resolver: Option<Arc<CdnResolver>>,
+}
+
+/// CDN_* policy with per-platform CDN_{PLATFORM}_* overrides, e.g.
+/// CDN_TIKTOK_READ_TIMEOUT_SECS=10, and the pooled clients built for them
+/// at startup: one shared by every platform, plus one for each platform
+/// whose connect/total timeouts or egress binding differ.
+#[derive(Debug)]
+pub struct CdnPolicies {
+    default: CdnPolicy,
+    platforms: HashMap<&'static str, CdnPolicy>,
+    default_client: reqwest::Client,
+    clients: HashMap<&'static str, reqwest::Client>,
+    read_buffer: usize,
+}
+
+impl CdnPolicies {
+    pub fn from_env(egress: &Egress) -> reqwest::Result<Self> {
+        Self::from_vars(|key| env::var(key).ok(), egress)
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>, egress: &Egress) -> reqwest::Result<Self> {
+        let pool = CdnPool::with_vars(&var);
+        let settings = DnsSettings::with_vars(&var);
+        let family = egress.family;
+        let dns = Dns { resolver: settings.resolver(family).map(Arc::new), settings, family };
+        let default = CdnPolicy::with_vars(&CdnPolicy::default(), "CDN", &var);
+        let platforms: HashMap<&'static str, CdnPolicy> = PLATFORMS
+            .iter()
+            .map(|p| (*p, CdnPolicy::with_vars(&default, &format!("CDN_{}", p.to_uppercase()), &var)))
+            .filter(|(_, policy)| *policy != default)
+            .collect();
+        let shared = (default.connect_timeout, default.total_timeout, egress.default_local_address());
+        let mut clients = HashMap::new();
+        for platform in PLATFORMS {
+            let policy = platforms.get(platform).unwrap_or(&default);
+            let local_address = egress.local_address(platform);
+            if (policy.connect_timeout, policy.total_timeout, local_address) != shared {
+                clients.insert(platform, policy.client(&pool, &dns, local_address)?);
+            }
+        }
+        Ok(Self {
+            default_client: default.client(&pool, &dns, shared.2)?,
+            default,
+            platforms,
+            clients,
+            read_buffer: pool.read_buffer,
+        })
+    }
+
+    pub fn for_platform(&self, platform: &str) -> &CdnPolicy {
+        self.platforms.get(platform).unwrap_or(&self.default)
+    }
+
+    /// Shared client for `platform`'s CDN requests; clones share its pool.
+    pub fn client_for(&self, platform: &str) -> &reqwest::Client {
+        self.clients.get(platform).unwrap_or(&self.default_client)
+    }
+
+    /// CDN_READ_BUFFER_KB in bytes, for coalesce.
+    pub fn read_buffer(&self) -> usize {
+        self.read_buffer
+    }
+}
+
+/// Domains sessions may fetch media from by default: the platforms' CDNs
+/// and their subdomains (CDN_ALLOWED_HOSTS).
+pub const DEFAULT_ALLOWED_HOSTS: &[&str] = &[
+    // TikTok
+    "tiktok.com",
+    "tiktokcdn.com",
+    "tiktokcdn-us.com",
+    "tiktokcdn-eu.com",
+    "tiktokv.com",
+    "tiktokv.us",
+    "tiktokv.eu",
+    "ibytedtos.com",
+    "ibyteimg.com",
+    "byteoversea.com",
+    "muscdn.com",
+    // Douyin
+    "douyin.com",
+    "douyinvod.com",
+    "douyincdn.com",
+    "douyinpic.com",
+    "douyinstatic.com",
+    "zjcdn.com",
+    "amemv.com",
+    "snssdk.com",
+    "bytecdn.cn",
+    "pstatp.com",
+    "ixigua.com",
+    // X
+    "twimg.com",
+];
+
+/// Hosts /stream and the FFmpeg endpoints may fetch a session's media from.
+/// A session carries its own CDN URLs, so without this a forged stateless
+/// token could point the server at internal services. Loopback, private
+/// and link-local addresses are never allowed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpstreamHosts {
+    /// Allowed domains, subdomains included; None allows any public host
+    /// (CDN_ALLOWED_HOSTS=*)
+    domains: Option<Vec<String>>,
+    /// Origins allowed whatever their address, like MOCK_EXTRACTOR's
+    /// /mock/media on this server
+    origins: Vec<String>,
+}
+
+impl UpstreamHosts {
+    pub fn from_env() -> Self {
+        Self::with_vars(&|key| env::var(key).ok())
+    }
+
+    fn with_vars(var: &impl Fn(&str) -> Option<String>) -> Self {
+        let domains = match var("CDN_ALLOWED_HOSTS").map(|v| v.trim().to_lowercase()) {
+            Some(v) if v == "*" => None,
+            Some(v) if !v.is_empty() => Some(
+                v.split(',')
+                    .map(|d| d.trim().trim_start_matches("*.").trim_matches('.').to_string())
+                    .filter(|d| !d.is_empty())
+                    .collect(),
+            ),
+            _ => Some(DEFAULT_ALLOWED_HOSTS.iter().map(|d| d.to_string()).collect()),
+        };
+        Self { domains, origins: Vec::new() }
+    }
+
+    /// Also allow `url`'s origin.
+    pub fn allow_origin(&mut self, url: &str) {
+        if let Ok(url) = reqwest::Url::parse(url) {
+            self.origins.push(url.origin().ascii_serialization());
+        }
+    }
+
+    /// Whether media may be fetched from `url`: http(s) on an allowed domain,
+    /// or with CDN_ALLOWED_HOSTS=* any host but a non-public address.
+    pub fn allows(&self, url: &str) -> bool {
+        let Ok(url) = reqwest::Url::parse(url) else {
+            return false;
+        };
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+        if self.origins.contains(&url.origin().ascii_serialization()) {
+            return true;
+        }
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            return self.domains.is_none() && is_public(ip);
+        }
+        let host = host.trim_end_matches('.');
+        if host == "localhost" || host.ends_with(".localhost") {
+            return false;
+        }
+        match &self.domains {
+            Some(domains) => domains
+                .iter()
+                .any(|d| host == d || host.strip_suffix(d.as_str()).is_some_and(|sub| sub.ends_with('.'))),
+            None => true,
+        }
+    }
+}
+
+/// Whether `ip` is a globally routable address: not loopback, private,
+/// link-local, shared (CGNAT), unspecified, multicast or documentation.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                let first = v6.segments()[0];
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80
+                    || (first == 0x2001 && v6.segments()[1] == 0xdb8))
+            }
+        },
+    }
+}
+
+/// `body`, ending with a TimedOut error when the next chunk takes longer
+/// than `read_timeout`.
+pub fn read_timeout<S>(body: S, read_timeout: Duration) -> ReadTimeout<S> {
+    ReadTimeout {
+        body,
+        read_timeout,
+        last_read: Instant::now(),
+        stall: Box::pin(tokio::time::sleep(read_timeout)),
+        done: false,
+    }
+}
+
+/// Stream of read_timeout. Moving the timer for every chunk would cost
+/// more than forwarding the chunk, so a chunk only records when it
+/// arrived; the timer is pushed back to that plus `read_timeout` when it
+/// fires early.
+pub struct ReadTimeout<S> {
+    body: S,
+    read_timeout: Duration,
+    last_read: Instant,
+    stall: Pin<Box<Sleep>>,
+    done: bool,
+}
+
+impl<S, E> Stream for ReadTimeout<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        match this.body.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                this.last_read = Instant::now();
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+            Poll::Ready(Some(Err(e))) => {
+                this.done = true;
+                return Poll::Ready(Some(Err(io::Error::other(e))));
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                return Poll::Ready(None);
+            }
+            Poll::Pending => {}
+        }
+        while this.stall.as_mut().poll(cx).is_ready() {
+            let deadline = this.last_read + this.read_timeout;
+            if deadline <= Instant::now() {
+                this.done = true;
+                return Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::TimedOut, "CDN read timed out"))));
+            }
+            this.stall.as_mut().reset(deadline);
+        }
+        Poll::Pending
+    }
+}
+
+/// `body` with chunks that are already read merged up to `size` bytes
+/// (CDN_READ_BUFFER_KB), so a CDN sending small chunks is forwarded in
+/// fewer, larger writes. Chunks of `size` or more pass through as they are,
+/// and nothing waits for more to arrive: what was merged is sent as soon as
+/// the CDN has nothing ready. A `size` of 0 passes every chunk through.
+pub fn coalesce<S>(body: S, size: usize) -> Coalesce<S> {
+    Coalesce { body, size, merged: Vec::new(), next: None, done: false }
+}
+
+/// Stream of coalesce.
+pub struct Coalesce<S> {
+    body: S,
+    size: usize,
+    merged: Vec<u8>,
+    /// Item read after `merged` that goes out after it
+    next: Option<Result<Bytes, io::Error>>,
+    done: bool,
+}
+
+impl<S> Stream for Coalesce<S>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin,
+{
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(next) = this.next.take() {
+            return Poll::Ready(Some(next));
+        }
+        // With a `size` of 0 nothing is merged and every chunk returns at once
+        while !this.done && this.next.is_none() && (this.merged.is_empty() || this.merged.len() < this.size) {
+            match this.body.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(chunk))) if this.merged.is_empty() && chunk.len() >= this.size => {
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+                Poll::Ready(Some(Ok(chunk))) if this.merged.len() + chunk.len() > this.size => this.next = Some(Ok(chunk)),
+                Poll::Ready(Some(Ok(chunk))) => {
+                    if this.merged.capacity() == 0 {
+                        this.merged.reserve_exact(this.size);
+                    }
+                    this.merged.extend_from_slice(&chunk);
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    this.next = Some(Err(e));
+                    this.done = true;
+                }
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+        if !this.merged.is_empty() {
+            return Poll::Ready(Some(Ok(Bytes::from(std::mem::take(&mut this.merged)))));
+        }
+        match this.next.take() {
+            Some(next) => Poll::Ready(Some(next)),
+            None if this.done => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Size of the whole file behind a CDN response: Content-Range's total for
+/// a 206, else Content-Length. None when the CDN doesn't say.
+pub fn total_size(partial: bool, content_length: Option<u64>, content_range: Option<&str>) -> Option<u64> {
+    if !partial {
+        return content_length;
+    }
+    // "bytes 0-1023/4096"; the total may be "*"
+    content_range?.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+/// `body`, ending with an error instead of the chunk that would take it
+/// past `limit` bytes (MAX_DOWNLOAD_BYTES), for CDNs that send more than
+/// they announced or announce nothing. No `limit` passes everything.
+pub fn cap_size<S>(body: S, limit: Option<u64>) -> CapSize<S> {
+    CapSize { body, limit, sent: 0, done: false }
+}
+
+/// Stream of cap_size.
+pub struct CapSize<S> {
+    body: S,
+    limit: Option<u64>,
+    sent: u64,
+    done: bool,
+}
+
+impl<S> Stream for CapSize<S>
+where
+    S: Stream<Item = Result<Bytes, io::Error>> + Unpin,
+{
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        match ready!(this.body.poll_next_unpin(cx)) {
+            Some(Ok(chunk)) => {
+                this.sent += chunk.len() as u64;
+                match this.limit {
+                    Some(limit) if this.sent > limit => {
+                        warn!("Stream passed MAX_DOWNLOAD_BYTES ({} bytes), aborting", limit);
+                        this.done = true;
+                        Poll::Ready(Some(Err(io::Error::other("MAX_DOWNLOAD_BYTES exceeded"))))
+                    }
+                    _ => Poll::Ready(Some(Ok(chunk))),
+                }
+            }
+            Some(Err(e)) => {
+                this.done = true;
+                Poll::Ready(Some(Err(e)))
+            }
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_policies_from_vars() {
+        let vars = HashMap::from([
+            ("CDN_READ_TIMEOUT_SECS", "20"),
+            ("CDN_TOTAL_TIMEOUT_SECS", "0"),
+            ("CDN_TIKTOK_CONNECT_TIMEOUT_SECS", "3"),
+            ("CDN_TIKTOK_RETRIES", "x"),
+            ("CDN_X_RETRIES", "5"),
+            ("CDN_TCP_KEEPALIVE_SECS", "0"),
+            ("CDN_HTTP2", "false"),
+            ("CDN_READ_BUFFER_KB", "256"),
+        ]);
+        let var = |k: &str| vars.get(k).map(|v| v.to_string());
+        let policies = CdnPolicies::from_vars(var, &Egress::default()).unwrap();
+        let x = policies.for_platform("x");
+        assert_eq!((x.read_timeout, x.total_timeout, x.retries), (Duration::from_secs(20), None, 5));
+        let tiktok = policies.for_platform("tiktok");
+        assert_eq!(tiktok.connect_timeout, Duration::from_secs(3));
+        assert_eq!((tiktok.read_timeout, tiktok.retries), (Duration::from_secs(20), 2));
+        assert_eq!(policies.for_platform("unknown").retries, 2);
+        // Only a different connect/total timeout needs a client of its own
+        assert_eq!(policies.clients.keys().collect::<Vec<_>>(), [&"tiktok"]);
+        // ...or a different egress binding
+        let bound = |k: &str| (k == "EGRESS_X_SOURCE_ADDRESS").then(|| "127.0.0.1".to_string());
+        let egress = Egress::with_vars(&bound, &|_: &str| Ok(vec![])).unwrap();
+        let mut own: Vec<_> = CdnPolicies::from_vars(var, &egress).unwrap().clients.into_keys().collect();
+        own.sort();
+        assert_eq!(own, ["tiktok", "x"]);
+
+        let pool = CdnPool::with_vars(&var);
+        assert_eq!((pool.tcp_keepalive, pool.http2, pool.max_idle_per_host), (None, false, 32));
+        assert_eq!((pool.read_buffer, policies.read_buffer()), (256 << 10, 256 << 10));
+    }
+
+    #[test]
+    fn test_upstream_hosts() {
+        let mut hosts = UpstreamHosts::with_vars(&|_: &str| None);
+        assert!(hosts.allows("https://v16-webapp-prime.tiktok.com/video/tos/1?a=1"));
+        assert!(hosts.allows("https://video.twimg.com/ext_tw_video/1/vid/720x1280/a.mp4"));
+        assert!(hosts.allows("http://TWIMG.com./a.jpg"));
+        assert!(!hosts.allows("https://eviltwimg.com/a.mp4"));
+        assert!(!hosts.allows("https://twimg.com.evil.net/a.mp4"));
+        assert!(!hosts.allows("file:///etc/passwd"));
+        assert!(!hosts.allows("http://127.0.0.1:8025/mock/media/audio.mp3"));
+        hosts.allow_origin("http://127.0.0.1:8025/mock/media");
+        assert!(hosts.allows("http://127.0.0.1:8025/mock/media/audio.mp3"));
+        assert!(!hosts.allows("http://127.0.0.1:6379/"));
+
+        let any = UpstreamHosts::with_vars(&|k: &str| (k == "CDN_ALLOWED_HOSTS").then(|| "*".to_string()));
+        assert!(any.allows("https://cdn.example.net/a.mp4"));
+        assert!(any.allows("http://93.184.216.34/a.mp4"));
+        for internal in [
+            "http://localhost/",
+            "http://api.localhost/",
+            "http://10.0.0.5/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://100.64.1.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:192.168.1.1]/",
+        ] {
+            assert!(!any.allows(internal), "{internal}");
+        }
+
+        let own = UpstreamHosts::with_vars(&|k: &str| (k == "CDN_ALLOWED_HOSTS").then(|| " *.Example.net, ".to_string()));
+        assert!(own.allows("https://media.example.net/a.mp4"));
+        assert!(!own.allows("https://video.twimg.com/a.mp4"));
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_and_read_timeout() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counted = hits.clone();
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::get(move || async move {
+                match counted.fetch_add(1, Ordering::SeqCst) {
+                    0 => (axum::http::StatusCode::SERVICE_UNAVAILABLE, "busy"),
+                    _ => (axum::http::StatusCode::OK, "ok"),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let policy = CdnPolicy { retry_backoff: Duration::from_millis(1), ..Default::default() };
+        let dns = Dns { settings: DnsSettings::default(), family: IpFamily::Any, resolver: None };
+        let client = policy.client(&CdnPool::default(), &dns, None).unwrap();
+        let resp = policy.send(client.get(&url)).await.unwrap();
+        assert_eq!((resp.status().as_u16(), hits.load(Ordering::SeqCst)), (200, 2));
+
+        // No retries left: the 503 itself is returned
+        hits.store(0, Ordering::SeqCst);
+        let once = CdnPolicy { retries: 0, ..policy };
+        assert_eq!(once.send(client.get(&url)).await.unwrap().status().as_u16(), 503);
+
+        let stalled = futures_util::stream::iter([Ok::<_, io::Error>(Bytes::from_static(b"head"))])
+            .chain(futures_util::stream::pending());
+        let mut body = read_timeout(stalled, Duration::from_millis(20));
+        assert_eq!(body.next().await.unwrap().unwrap(), Bytes::from_static(b"head"));
+        assert_eq!(body.next().await.unwrap().unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(body.next().await.is_none());
+
+        // Chunks arriving within the timeout of each other keep it from
+        // firing, however long the whole body takes
+        let trickle = futures_util::stream::iter(0..6).then(|_| async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok::<_, io::Error>(Bytes::from_static(b"x"))
+        });
+        let body: Vec<_> = read_timeout(Box::pin(trickle), Duration::from_millis(30)).collect().await;
+        assert_eq!(body.len(), 6);
+        assert!(body.iter().all(|c| c.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_total_size_and_cap() {
+        assert_eq!(total_size(false, Some(10), None), Some(10));
+        assert_eq!(total_size(true, Some(1024), Some("bytes 0-1023/4096")), Some(4096));
+        assert_eq!(total_size(true, Some(1024), Some("bytes 0-1023/*")), None);
+        assert_eq!(total_size(true, Some(1024), None), None);
+
+        let chunks = || futures_util::stream::iter(chunks_of(&[b"abcd", b"efgh", b"ij"]));
+        let body: Vec<_> = cap_size(chunks(), Some(8)).collect().await;
+        assert_eq!(body.len(), 3);
+        assert!(body[..2].iter().all(|c| c.is_ok()));
+        assert!(body[2].is_err());
+        let body: Vec<_> = cap_size(chunks(), None).collect().await;
+        assert!(body.iter().all(|c| c.is_ok()));
+    }
+
+    fn chunks_of(chunks: &[&'static [u8]]) -> Vec<Result<Bytes, io::Error>> {
+        chunks.iter().map(|c| Ok(Bytes::from_static(c))).collect()
+    }
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        let big = Bytes::from(vec![7; 8]);
+        let mut chunks = chunks_of(&[b"ab", b"cd", b"ef", b"gh", b"ij"]);
+        chunks.push(Ok(big.clone()));
+        let merged: Vec<Bytes> = coalesce(futures_util::stream::iter(chunks), 6).map(Result::unwrap).collect().await;
+        assert_eq!(merged, [Bytes::from_static(b"abcdef"), Bytes::from_static(b"ghij"), big.clone()]);
+        // Large chunks are passed on, not copied
+        assert_eq!(merged[2].as_ptr(), big.as_ptr());
+
+        // What was read is sent when the CDN pauses, and before an error
+        let stalled = futures_util::stream::iter(chunks_of(&[b"ab", b"cd"])).chain(futures_util::stream::pending());
+        let mut body = coalesce(stalled, 64);
+        assert_eq!(body.next().await.unwrap().unwrap(), Bytes::from_static(b"abcd"));
+        let failed = futures_util::stream::iter(chunks_of(&[b"ef"]).into_iter().chain([Err(io::Error::other("reset"))]));
+        let mut body = coalesce(failed, 64);
+        assert_eq!(body.next().await.unwrap().unwrap(), Bytes::from_static(b"ef"));
+        assert!(body.next().await.unwrap().is_err());
+        assert!(body.next().await.is_none());
+
+        assert_eq!(coalesce(futures_util::stream::iter(chunks_of(&[b"ab", b"cd"])), 0).count().await, 2);
+    }
+}
@@ -0,0 +1,64 @@
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use tracing::info;
+
+use crate::slideshow::run_ffmpeg;
+
+/// Stream copies start at the keyframe at or before the cut, so a clip may
+/// run up to this much longer than its chapter.
+pub const KEYFRAME_SLACK_SECS: f64 = 10.0;
+
+/// ffmpeg arguments copying `start..end` seconds of `input` (requested with
+/// `headers`) into `output_path`. Streams are copied, not re-encoded, so the
+/// clip starts at the keyframe at or before `start`.
+fn clip_args(input: &str, headers: &[(String, String)], start: f64, end: f64, output_path: &str) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    if !headers.is_empty() {
+        let joined: String = headers.iter().map(|(k, v)| format!("{k}: {v}\r\n")).collect();
+        args.extend(["-headers".to_string(), joined]);
+    }
+    args.extend(
+        ["-ss", &format!("{start:.3}"), "-t", &format!("{:.3}", (end - start).max(0.0)), "-i", input].map(String::from),
+    );
+    args.extend(
+        ["-map", "0:v?", "-map", "0:a?", "-c", "copy", "-avoid_negative_ts", "make_zero", "-movflags", "+faststart"]
+            .map(String::from),
+    );
+    args.push(output_path.to_string());
+    args
+}
+
+/// Run ffmpeg to cut `input` to `start..end` at `output_path`, killing it
+/// once `stop` is set. Blocking — call from spawn_blocking.
+pub fn create_clip(
+    input: &str,
+    headers: &[(String, String)],
+    start: f64,
+    end: f64,
+    output_path: &str,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    info!("Clipping {:.1}s-{:.1}s", start, end);
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(clip_args(input, headers, start, end, output_path));
+    run_ffmpeg(cmd, output_path, stop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clip_args() {
+        let headers = vec![("User-Agent".to_string(), "UA".to_string()), ("Cookie".to_string(), "a=b".to_string())];
+        let args = clip_args("https://v/a.m3u8", &headers, 60.0, 90.5, "clip.mp4");
+        assert_eq!(args[1..3], ["-headers", "User-Agent: UA\r\nCookie: a=b\r\n"]);
+        assert!(args.windows(2).any(|w| w == ["-ss", "60.000"]));
+        assert!(args.windows(2).any(|w| w == ["-t", "30.500"]));
+        assert!(args.windows(2).any(|w| w == ["-c", "copy"]));
+        assert_eq!(args.last().unwrap(), "clip.mp4");
+
+        let bare = clip_args("https://v/a.mp4", &[], 0.0, 10.0, "clip.m4a");
+        assert!(!bare.iter().any(|a| a == "-headers"));
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Largest `cookies` field accepted on /download.
+pub const MAX_CLIENT_COOKIES_LEN: usize = 64 * 1024;
+
+/// Cookies supplied by the client for a single /download request, either as
+/// a Netscape cookies.txt or as a `name=value; name2=value2` header string.
+pub struct ClientCookies {
+    /// Netscape file content handed to yt-dlp as `cookiefile`
+    netscape: String,
+    /// `Cookie` header for the CDN requests made by /stream
+    pub header: String,
+}
+
+impl ClientCookies {
+    pub fn parse(raw: &str, url: &str) -> Result<Self, String> {
+        if raw.len() > MAX_CLIENT_COOKIES_LEN {
+            return Err(format!("cookies exceed {MAX_CLIENT_COOKIES_LEN} bytes"));
+        }
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_lowercase()))
+            .ok_or("invalid URL")?;
+
+        let pairs = if raw.contains('\t') {
+            parse_netscape_pairs(raw, &host)?
+        } else {
+            parse_header_pairs(raw)?
+        };
+        if pairs.is_empty() {
+            return Err("no cookies for this site".into());
+        }
+
+        let domain = cookie_domain(&host);
+        let mut netscape = String::from("# Netscape HTTP Cookie File\n");
+        for (name, value) in &pairs {
+            netscape.push_str(&format!(".{domain}\tTRUE\t/\tTRUE\t0\t{name}\t{value}\n"));
+        }
+        let header = pairs
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        Ok(Self { netscape, header })
+    }
+
+    /// Write the cookies to a private temp file for yt-dlp; it is deleted
+    /// when the returned guard is dropped.
+    pub fn write_temp(&self) -> std::io::Result<TempCookieFile> {
+        write_temp_cookie_file(&self.netscape)
+    }
+}
+
+/// Write a Netscape cookies.txt to a private temp file, deleted when the
+/// returned guard is dropped.
+pub fn write_temp_cookie_file(netscape: &str) -> std::io::Result<TempCookieFile> {
+    let path = std::env::temp_dir().join(format!("serverx-cookies-{}.txt", Uuid::new_v4()));
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&path)?;
+    let guard = TempCookieFile { path };
+    file.write_all(netscape.as_bytes())?;
+    Ok(guard)
+}
+
+/// Per-request cookie file, removed on drop.
+pub struct TempCookieFile {
+    path: PathBuf,
+}
+
+impl TempCookieFile {
+    pub fn path(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempCookieFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove temp cookie file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// "www.tiktok.com" -> "tiktok.com", "x.com" -> "x.com"
+fn cookie_domain(host: &str) -> String {
+    let labels: Vec<&str> = host.split('.').collect();
+    labels[labels.len().saturating_sub(2)..].join(".")
+}
+
+fn valid_pair(name: &str, value: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_graphic() && !"=;,".contains(c))
+        && value.chars().all(|c| c.is_ascii_graphic() && c != ';' && c != ',')
+}
+
+fn parse_header_pairs(raw: &str) -> Result<Vec<(String, String)>, String> {
+    let raw = raw.trim();
+    let raw = raw
+        .strip_prefix("Cookie:")
+        .or_else(|| raw.strip_prefix("cookie:"))
+        .unwrap_or(raw);
+    raw.split(';')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (name, value) = p.split_once('=').ok_or(format!("invalid cookie pair: {p}"))?;
+            let (name, value) = (name.trim(), value.trim());
+            if !valid_pair(name, value) {
+                return Err(format!("invalid cookie pair: {name}"));
+            }
+            Ok((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Netscape cookies.txt lines, keeping only cookies that apply to `host`.
+fn parse_netscape_pairs(raw: &str, host: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    for (n, line) in raw.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return Err(format!("line {}: expected 7 tab-separated fields", n + 1));
+        }
+        let domain = fields[0].trim_start_matches('.').to_lowercase();
+        if host != domain && !host.ends_with(&format!(".{domain}")) {
+            continue;
+        }
+        if !valid_pair(fields[5], fields[6]) {
+            return Err(format!("line {}: invalid cookie", n + 1));
+        }
+        pairs.push((fields[5].to_string(), fields[6].to_string()));
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_client_cookies() {
+        let c = ClientCookies::parse("auth_token=abc; ct0=def", "https://x.com/u/status/1").unwrap();
+        assert_eq!(c.header, "auth_token=abc; ct0=def");
+        assert!(c.netscape.contains(".x.com\tTRUE\t/\tTRUE\t0\tct0\tdef\n"));
+
+        let netscape = "# Netscape HTTP Cookie File\n\
+                        .tiktok.com\tTRUE\t/\tTRUE\t1800000000\tsessionid\ts1\n\
+                        .example.com\tTRUE\t/\tTRUE\t0\tother\tx\n";
+        let c = ClientCookies::parse(netscape, "https://www.tiktok.com/@u/video/1").unwrap();
+        assert_eq!(c.header, "sessionid=s1");
+
+        assert!(ClientCookies::parse("no-equals-sign", "https://x.com/").is_err());
+        assert!(ClientCookies::parse("a=b\r\nX-Injected: 1", "https://x.com/").is_err());
+        assert!(ClientCookies::parse(netscape, "https://x.com/").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::cdn::{bool_var, num_var};
+use crate::egress::IpFamily;
+
+/// How CDN hostnames are resolved (CDN_DNS_*).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DnsSettings {
+    /// Resolve through the caching resolver below instead of the system's
+    /// getaddrinfo (CDN_DNS_CACHE)
+    pub cache: bool,
+    /// Upstream DNS servers; empty uses /etc/resolv.conf's (CDN_DNS_SERVERS)
+    pub servers: Vec<IpAddr>,
+    /// Cached names (CDN_DNS_CACHE_SIZE)
+    pub cache_size: usize,
+    /// Answers are cached at least this long, whatever their TTL
+    /// (CDN_DNS_MIN_TTL_SECS); CDN names often come with TTLs of seconds
+    pub min_ttl: Duration,
+    /// Fixed addresses per host, skipping DNS (CDN_DNS_OVERRIDES)
+    pub overrides: Vec<(String, Vec<SocketAddr>)>,
+}
+
+impl Default for DnsSettings {
+    fn default() -> Self {
+        Self {
+            cache: true,
+            servers: Vec::new(),
+            cache_size: 1024,
+            min_ttl: Duration::from_secs(60),
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl DnsSettings {
+    pub fn with_vars(var: &impl Fn(&str) -> Option<String>) -> Self {
+        let base = Self::default();
+        let ips = |key: &str, raw: &str| -> Option<IpAddr> {
+            raw.trim().parse().map_err(|_| warn!("Ignoring invalid address {} in {}", raw, key)).ok()
+        };
+        Self {
+            cache: bool_var(var, "CDN_DNS_CACHE").unwrap_or(base.cache),
+            servers: var("CDN_DNS_SERVERS")
+                .map(|v| v.split(',').filter(|s| !s.trim().is_empty()).filter_map(|s| ips("CDN_DNS_SERVERS", s)).collect())
+                .unwrap_or_default(),
+            cache_size: num_var(var, "CDN_DNS_CACHE_SIZE").map_or(base.cache_size, |n| n as usize),
+            min_ttl: num_var(var, "CDN_DNS_MIN_TTL_SECS").map_or(base.min_ttl, Duration::from_secs),
+            overrides: var("CDN_DNS_OVERRIDES").map(|v| parse_overrides(&v, &ips)).unwrap_or_default(),
+        }
+    }
+
+    /// The CDN clients' resolver: caching unless CDN_DNS_CACHE is off or the
+    /// system configuration can't be read, then getaddrinfo. None when that
+    /// leaves nothing to do over reqwest's own getaddrinfo resolver.
+    pub fn resolver(&self, family: IpFamily) -> Option<CdnResolver> {
+        let hickory = self.cache.then(|| self.hickory(family)).flatten();
+        if hickory.is_none() && family == IpFamily::Any {
+            return None;
+        }
+        Some(CdnResolver { hickory, family })
+    }
+
+    fn hickory(&self, family: IpFamily) -> Option<TokioAsyncResolver> {
+        let (config, opts) = if self.servers.is_empty() {
+            match hickory_resolver::system_conf::read_system_conf() {
+                Ok(conf) => conf,
+                Err(e) => {
+                    warn!("Can't read the system DNS configuration ({}), CDN DNS isn't cached", e);
+                    return None;
+                }
+            }
+        } else {
+            let servers = NameServerConfigGroup::from_ips_clear(&self.servers, 53, true);
+            (ResolverConfig::from_parts(None, vec![], servers), ResolverOpts::default())
+        };
+        Some(self.hickory_with(config, opts, family))
+    }
+
+    fn hickory_with(&self, config: ResolverConfig, mut opts: ResolverOpts, family: IpFamily) -> TokioAsyncResolver {
+        opts.cache_size = self.cache_size;
+        opts.positive_min_ttl = Some(self.min_ttl);
+        // Ask for the family's records; hickory's default (A, then AAAA only
+        // when there is no A) leaves IPv6-only egress nothing on dual-stack hosts
+        opts.ip_strategy = match family {
+            IpFamily::Any => LookupIpStrategy::Ipv4AndIpv6,
+            IpFamily::Ipv4 => LookupIpStrategy::Ipv4Only,
+            IpFamily::Ipv6 => LookupIpStrategy::Ipv6Only,
+            IpFamily::PreferIpv6 => LookupIpStrategy::Ipv6thenIpv4,
+        };
+        TokioAsyncResolver::tokio(config, opts)
+    }
+}
+
+/// `host=ip,host=ip,...`; a host listed several times gets every address.
+fn parse_overrides(raw: &str, ip: &impl Fn(&str, &str) -> Option<IpAddr>) -> Vec<(String, Vec<SocketAddr>)> {
+    let mut overrides: Vec<(String, Vec<SocketAddr>)> = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((host, addr)) = entry.split_once('=') else {
+            warn!("Ignoring CDN_DNS_OVERRIDES entry without '=': {}", entry);
+            continue;
+        };
+        let Some(addr) = ip("CDN_DNS_OVERRIDES", addr) else {
+            continue;
+        };
+        // The port is ignored; requests keep their URL's
+        let addr = SocketAddr::new(addr, 0);
+        let host = host.trim().to_lowercase();
+        match overrides.iter_mut().find(|(h, _)| *h == host) {
+            Some((_, addrs)) => addrs.push(addr),
+            None => overrides.push((host, vec![addr])),
+        }
+    }
+    overrides
+}
+
+/// hickory resolver with its own cache (or getaddrinfo), shared by every CDN
+/// client; answers are kept to EGRESS_IP_FAMILY's family, in its order.
+pub struct CdnResolver {
+    hickory: Option<TokioAsyncResolver>,
+    family: IpFamily,
+}
+
+impl Resolve for CdnResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (hickory, family) = (self.hickory.clone(), self.family);
+        Box::pin(async move {
+            let found: Vec<SocketAddr> = match hickory {
+                Some(resolver) => {
+                    resolver.lookup_ip(name.as_str()).await?.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect()
+                }
+                None => tokio::net::lookup_host((name.as_str(), 0)).await?.collect(),
+            };
+            let found = family.order(found);
+            if found.is_empty() {
+                return Err(format!("No {} address for {}", family, name.as_str()).into());
+            }
+            let addrs: Addrs = Box::new(found.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::op::{Message, MessageType, OpCode};
+    use hickory_resolver::proto::rr::rdata::{A, AAAA};
+    use hickory_resolver::proto::rr::{RData, Record, RecordType};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_dns_settings_and_resolver() {
+        let vars = HashMap::from([
+            ("CDN_DNS_SERVERS", "1.1.1.1, 2606:4700:4700::1111,bogus"),
+            ("CDN_DNS_MIN_TTL_SECS", "300"),
+            ("CDN_DNS_OVERRIDES", "V16.tiktokcdn.com=127.0.0.1,v16.tiktokcdn.com=127.0.0.2,broken,x=nope"),
+        ]);
+        let dns = DnsSettings::with_vars(&|k: &str| vars.get(k).map(|v| v.to_string()));
+        assert_eq!(dns.servers, ["1.1.1.1".parse::<IpAddr>().unwrap(), "2606:4700:4700::1111".parse().unwrap()]);
+        assert_eq!((dns.cache, dns.cache_size, dns.min_ttl), (true, 1024, Duration::from_secs(300)));
+        assert_eq!(dns.overrides.len(), 1);
+        assert_eq!(dns.overrides[0].0, "v16.tiktokcdn.com");
+        assert_eq!(dns.overrides[0].1, ["127.0.0.1:0".parse::<SocketAddr>().unwrap(), "127.0.0.2:0".parse().unwrap()]);
+
+        // IP literals resolve without asking the upstream servers
+        let resolver = dns.resolver(IpFamily::Any).unwrap();
+        let addrs: Vec<SocketAddr> = resolver.resolve("127.0.0.1".parse().unwrap()).await.unwrap().collect();
+        assert_eq!(addrs, ["127.0.0.1:0".parse::<SocketAddr>().unwrap()]);
+        assert!(resolver.resolve("::1".parse().unwrap()).await.is_ok());
+        let uncached = DnsSettings { cache: false, ..dns };
+        assert!(uncached.resolver(IpFamily::Any).is_none());
+        // getaddrinfo, filtered to the egress family
+        let v4_only = uncached.resolver(IpFamily::Ipv4).unwrap();
+        assert!(v4_only.resolve("::1".parse().unwrap()).await.is_err());
+        assert!(v4_only.resolve("127.0.0.1".parse().unwrap()).await.is_ok());
+    }
+
+    /// Answers A and AAAA queries for any name from 127.0.0.1, recording the
+    /// query types asked.
+    async fn dual_stack_server() -> (SocketAddr, Arc<Mutex<Vec<RecordType>>>) {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let seen = asked.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                seen.lock().unwrap().push(query.query_type());
+                let rdata = match query.query_type() {
+                    RecordType::A => Some(RData::A(A::new(192, 0, 2, 1))),
+                    RecordType::AAAA => Some(RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))),
+                    _ => None,
+                };
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .set_recursion_desired(true)
+                    .set_recursion_available(true)
+                    .add_query(query.clone());
+                if let Some(rdata) = rdata {
+                    response.add_answer(Record::from_rdata(query.name().clone(), 60, rdata));
+                }
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+        (addr, asked)
+    }
+
+    #[tokio::test]
+    async fn test_resolver_asks_for_the_egress_family() {
+        let (server, asked) = dual_stack_server().await;
+        let v4: SocketAddr = "192.0.2.1:0".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:0".parse().unwrap();
+        let cases = [
+            (IpFamily::Any, vec![v4, v6], vec![RecordType::A, RecordType::AAAA]),
+            (IpFamily::Ipv4, vec![v4], vec![RecordType::A]),
+            (IpFamily::Ipv6, vec![v6], vec![RecordType::AAAA]),
+            (IpFamily::PreferIpv6, vec![v6], vec![RecordType::AAAA]),
+        ];
+        for (family, expected, queries) in cases {
+            asked.lock().unwrap().clear();
+            let servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true);
+            let config = ResolverConfig::from_parts(None, vec![], servers);
+            let resolver = CdnResolver {
+                hickory: Some(DnsSettings::default().hickory_with(config, ResolverOpts::default(), family)),
+                family,
+            };
+            let mut addrs: Vec<SocketAddr> = resolver.resolve("dual.example.".parse().unwrap()).await.unwrap().collect();
+            let mut asked = asked.lock().unwrap().clone();
+            if family == IpFamily::Any {
+                // Both are queried at once, in either order
+                addrs.sort();
+                asked.sort();
+            }
+            assert_eq!(addrs, expected, "{}", family);
+            assert_eq!(asked, queries, "{}", family);
+        }
+    }
+}
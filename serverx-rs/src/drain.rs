@@ -0,0 +1,140 @@
+use axum::body::{Body, HttpBody};
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::StreamExt;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// Draining state of this instance and the media requests it is serving.
+/// Draining only fails GET /health/drain, so load balancers stop sending
+/// new requests; nothing is refused or cut off, and the instance can be
+/// stopped once `in_flight` reaches 0.
+#[derive(Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    /// When draining started
+    since: Mutex<Option<String>>,
+    in_flight: AtomicUsize,
+}
+
+/// Body of GET /health/drain and the admin toggle.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DrainStatus {
+    pub draining: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    /// Media requests still being served, streamed bodies included
+    pub in_flight: usize,
+}
+
+impl Drain {
+    /// Start (or stop) draining at `now`; draining again keeps the first
+    /// start time.
+    pub fn set(&self, draining: bool, now: &str) -> DrainStatus {
+        let was = self.draining.swap(draining, Ordering::SeqCst);
+        let mut since = self.since.lock().unwrap();
+        match (was, draining) {
+            (false, true) => *since = Some(now.to_string()),
+            (_, false) => *since = None,
+            (true, true) => {}
+        }
+        drop(since);
+        let status = self.status();
+        if was != draining {
+            let verb = if draining { "Draining" } else { "Stopped draining" };
+            info!("🚧 {}, {} requests in flight", verb, status.in_flight);
+        }
+        status
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn status(&self) -> DrainStatus {
+        DrainStatus {
+            draining: self.is_draining(),
+            since: self.since.lock().unwrap().clone(),
+            in_flight: self.in_flight.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// A request counted in Drain::in_flight until dropped.
+struct InFlight(Arc<Drain>);
+
+impl InFlight {
+    fn new(drain: Arc<Drain>) -> Self {
+        drain.in_flight.fetch_add(1, Ordering::SeqCst);
+        Self(drain)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Middleware counting the requests it wraps in Drain::in_flight. A
+/// streamed response counts until its body ends or the client goes away;
+/// bodies already in memory are done once the handler returns.
+pub async fn track(State(drain): State<Arc<Drain>>, request: Request, next: Next) -> Response {
+    let in_flight = InFlight::new(drain);
+    let response = next.run(request).await;
+    if response.body().size_hint().exact().is_some() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &in_flight;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_drain_and_track() {
+        let drain = Arc::new(Drain::default());
+        assert_eq!(drain.status(), DrainStatus { draining: false, since: None, in_flight: 0 });
+        assert_eq!(drain.set(true, "t1").since.as_deref(), Some("t1"));
+        assert_eq!(drain.set(true, "t2").since.as_deref(), Some("t1"));
+        assert_eq!(drain.set(false, "t3"), DrainStatus { draining: false, since: None, in_flight: 0 });
+
+        let slow = || async {
+            let tail = futures_util::stream::once(async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok::<_, std::io::Error>(axum::body::Bytes::from_static(b"tail"))
+            });
+            Body::from_stream(futures_util::stream::iter([Ok(axum::body::Bytes::from_static(b"head"))]).chain(tail))
+        };
+        let app = axum::Router::new()
+            .route("/slow", axum::routing::get(slow))
+            .route("/small", axum::routing::get(|| async { "ok" }))
+            .route_layer(axum::middleware::from_fn_with_state(drain.clone(), track));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let response = reqwest::get(format!("{url}/slow")).await.unwrap();
+        assert_eq!(drain.status().in_flight, 1);
+        assert_eq!(response.text().await.unwrap(), "headtail");
+        reqwest::get(format!("{url}/small")).await.unwrap().text().await.unwrap();
+        // The stream is dropped once hyper has written its end
+        for _ in 0..50 {
+            if drain.status().in_flight == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(drain.status().in_flight, 0);
+    }
+}
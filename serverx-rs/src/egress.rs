@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tracing::warn;
+
+use crate::cdn::PLATFORMS;
+
+/// Address family of outbound connections — yt-dlp's and the CDN clients'
+/// (EGRESS_IP_FAMILY: any, ipv4, ipv6 or prefer-ipv6).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IpFamily {
+    /// Whatever the resolver returns, in its order
+    #[default]
+    Any,
+    /// IPv4 only, e.g. behind a VPN exit with broken IPv6
+    Ipv4,
+    /// IPv6 only
+    Ipv6,
+    /// IPv6 addresses tried first, IPv4 as fallback. yt-dlp has no such
+    /// option and keeps the system's address order (IPv6 first per RFC 6724
+    /// when the host has a global IPv6 address).
+    PreferIpv6,
+}
+
+impl IpFamily {
+    pub fn with_vars(var: &impl Fn(&str) -> Option<String>) -> Self {
+        let Some(raw) = var("EGRESS_IP_FAMILY") else {
+            return Self::Any;
+        };
+        match raw.trim().to_lowercase().replace('_', "-").as_str() {
+            "" | "any" | "auto" => Self::Any,
+            "ipv4" | "4" => Self::Ipv4,
+            "ipv6" | "6" => Self::Ipv6,
+            "prefer-ipv6" => Self::PreferIpv6,
+            _ => {
+                warn!("Ignoring invalid EGRESS_IP_FAMILY={}", raw);
+                Self::Any
+            }
+        }
+    }
+
+    /// Unspecified address of the forced family. Binding to it keeps a
+    /// connection on that family: yt-dlp's `source_address` and reqwest's
+    /// `local_address` (which also covers IP literal URLs, never resolved).
+    pub fn local_address(self) -> Option<IpAddr> {
+        match self {
+            Self::Ipv4 => Some(Ipv4Addr::UNSPECIFIED.into()),
+            Self::Ipv6 => Some(Ipv6Addr::UNSPECIFIED.into()),
+            Self::Any | Self::PreferIpv6 => None,
+        }
+    }
+
+    /// Resolved `addrs` without the other family's, or IPv6 ones first; the
+    /// HTTP client tries them in this order (falling back to the other
+    /// family after a short delay).
+    pub fn order(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            Self::Any => {}
+            Self::Ipv4 => addrs.retain(SocketAddr::is_ipv4),
+            Self::Ipv6 => addrs.retain(SocketAddr::is_ipv6),
+            Self::PreferIpv6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+        }
+        addrs
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Any => "any",
+            Self::Ipv4 => "ipv4",
+            Self::Ipv6 => "ipv6",
+            Self::PreferIpv6 => "prefer-ipv6",
+        })
+    }
+}
+
+/// Where extraction and CDN connections leave from: EGRESS_SOURCE_ADDRESS
+/// (a local IP) or EGRESS_INTERFACE (that interface's address), overridden
+/// per platform by EGRESS_{PLATFORM}_SOURCE_ADDRESS / _INTERFACE, e.g. media
+/// over the direct uplink and extraction through the VPN tunnel. Routing
+/// by source address is up to the host (`ip rule`).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Egress {
+    pub family: IpFamily,
+    default: Option<IpAddr>,
+    /// Platforms with a binding of their own
+    platforms: HashMap<&'static str, IpAddr>,
+}
+
+impl Egress {
+    pub fn from_env() -> Result<Self, String> {
+        Self::with_vars(&|key| std::env::var(key).ok(), &interface_addresses)
+    }
+
+    /// Unlike a bad EGRESS_IP_FAMILY, a binding that can't be honoured is an
+    /// error: traffic would silently take the other route.
+    pub fn with_vars(
+        var: &impl Fn(&str) -> Option<String>,
+        interfaces: &impl Fn(&str) -> io::Result<Vec<IpAddr>>,
+    ) -> Result<Self, String> {
+        let family = IpFamily::with_vars(var);
+        let default = binding("EGRESS", family, var, interfaces)?;
+        let mut platforms = HashMap::new();
+        for platform in PLATFORMS {
+            if let Some(addr) = binding(&format!("EGRESS_{}", platform.to_uppercase()), family, var, interfaces)? {
+                platforms.insert(platform, addr);
+            }
+        }
+        Ok(Self { family, default, platforms })
+    }
+
+    /// Local address `platform`'s connections bind to: its own binding, the
+    /// default one, or the unspecified address of a forced family.
+    pub fn local_address(&self, platform: &str) -> Option<IpAddr> {
+        self.platforms.get(platform).copied().or(self.default_local_address())
+    }
+
+    /// Local address of the connections no platform binding applies to.
+    pub fn default_local_address(&self) -> Option<IpAddr> {
+        self.default.or(self.family.local_address())
+    }
+
+    /// yt-dlp's `source_address` option for `platform`'s extractions.
+    pub fn source_address(&self, platform: &str) -> Option<String> {
+        self.local_address(platform).map(|ip| ip.to_string())
+    }
+
+    /// "x=192.0.2.10" per bound platform, "*" for the default, for the log.
+    pub fn describe(&self) -> Vec<String> {
+        let mut bound: Vec<String> = self.platforms.iter().map(|(p, ip)| format!("{p}={ip}")).collect();
+        bound.sort();
+        if let Some(ip) = self.default {
+            bound.insert(0, format!("*={ip}"));
+        }
+        bound
+    }
+}
+
+/// `{prefix}_SOURCE_ADDRESS`, else the address of `{prefix}_INTERFACE` in
+/// `family` (IPv4 first unless IPv6 is preferred).
+fn binding(
+    prefix: &str,
+    family: IpFamily,
+    var: &impl Fn(&str) -> Option<String>,
+    interfaces: &impl Fn(&str) -> io::Result<Vec<IpAddr>>,
+) -> Result<Option<IpAddr>, String> {
+    let set = |name: &str| var(&format!("{prefix}_{name}")).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    let addr = if let Some(raw) = set("SOURCE_ADDRESS") {
+        raw.parse::<IpAddr>().map_err(|_| format!("Invalid {prefix}_SOURCE_ADDRESS={raw}"))?
+    } else if let Some(name) = set("INTERFACE") {
+        let addrs = interfaces(&name).map_err(|e| format!("Can't list addresses of {prefix}_INTERFACE={name}: {e}"))?;
+        pick_address(&addrs, family).ok_or_else(|| format!("{prefix}_INTERFACE={name} has no usable {family} address"))?
+    } else {
+        return Ok(None);
+    };
+    if family.order(vec![SocketAddr::new(addr, 0)]).is_empty() {
+        return Err(format!("{prefix} binding {addr} doesn't match EGRESS_IP_FAMILY={family}"));
+    }
+    Ok(Some(addr))
+}
+
+/// Link-local IPv6 addresses need a scope id to bind, so they're skipped.
+fn pick_address(addrs: &[IpAddr], family: IpFamily) -> Option<IpAddr> {
+    let v4 = addrs.iter().copied().find(IpAddr::is_ipv4);
+    let v6 = addrs.iter().copied().find(|ip| matches!(ip, IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 != 0xfe80));
+    match family {
+        IpFamily::Any => v4.or(v6),
+        IpFamily::Ipv4 => v4,
+        IpFamily::Ipv6 => v6,
+        IpFamily::PreferIpv6 => v6.or(v4),
+    }
+}
+
+/// Addresses of network interface `name` (getifaddrs).
+fn interface_addresses(name: &str) -> io::Result<Vec<IpAddr>> {
+    let mut addrs = Vec::new();
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: the list getifaddrs allocates is only read until it is freed,
+    // and each ifa_addr is read as the sockaddr type its family says
+    unsafe {
+        if libc::getifaddrs(&mut list) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut cur = list;
+        while let Some(ifa) = cur.as_ref() {
+            cur = ifa.ifa_next;
+            if ifa.ifa_addr.is_null() || CStr::from_ptr(ifa.ifa_name).to_bytes() != name.as_bytes() {
+                continue;
+            }
+            match (*ifa.ifa_addr).sa_family as libc::c_int {
+                libc::AF_INET => {
+                    let sa = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    addrs.push(Ipv4Addr::from(u32::from_be(sa.sin_addr.s_addr)).into());
+                }
+                libc::AF_INET6 => {
+                    let sa = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    addrs.push(Ipv6Addr::from(sa.sin6_addr.s6_addr).into());
+                }
+                _ => {}
+            }
+        }
+        libc::freeifaddrs(list);
+    }
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no such interface or no address"));
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_family() {
+        let family = |v: &str| IpFamily::with_vars(&|_: &str| Some(v.to_string()));
+        assert_eq!(family(" IPv4 "), IpFamily::Ipv4);
+        assert_eq!(family("prefer_ipv6"), IpFamily::PreferIpv6);
+        assert_eq!(family("both"), IpFamily::Any);
+        assert_eq!(IpFamily::with_vars(&|_: &str| None), IpFamily::Any);
+        assert_eq!(IpFamily::Ipv4.local_address(), Some(Ipv4Addr::UNSPECIFIED.into()));
+        assert_eq!(IpFamily::Ipv6.local_address(), Some(Ipv6Addr::UNSPECIFIED.into()));
+        assert_eq!(IpFamily::PreferIpv6.local_address(), None);
+
+        let addrs: Vec<SocketAddr> =
+            ["1.2.3.4:0", "[2001:db8::1]:0", "5.6.7.8:0", "[2001:db8::2]:0"].iter().map(|a| a.parse().unwrap()).collect();
+        let v6_first = IpFamily::PreferIpv6.order(addrs.clone());
+        assert_eq!(v6_first, [addrs[1], addrs[3], addrs[0], addrs[2]]);
+        assert_eq!(IpFamily::Ipv4.order(addrs.clone()), [addrs[0], addrs[2]]);
+        assert_eq!(IpFamily::Any.order(addrs.clone()), addrs);
+    }
+
+    #[test]
+    fn test_egress_bindings() {
+        let vars = HashMap::from([
+            ("EGRESS_INTERFACE", "wg0"),
+            ("EGRESS_TIKTOK_SOURCE_ADDRESS", "192.0.2.10"),
+            ("EGRESS_X_INTERFACE", ""),
+        ]);
+        let var = |k: &str| vars.get(k).map(|v| v.to_string());
+        let interfaces = |name: &str| match name {
+            "wg0" => Ok(vec!["fe80::1".parse().unwrap(), "2001:db8::5".parse().unwrap(), "10.8.0.2".parse().unwrap()]),
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+        };
+        let egress = Egress::with_vars(&var, &interfaces).unwrap();
+        assert_eq!(egress.source_address("tiktok").as_deref(), Some("192.0.2.10"));
+        assert_eq!(egress.source_address("x").as_deref(), Some("10.8.0.2"));
+        assert_eq!(egress.default_local_address(), Some("10.8.0.2".parse().unwrap()));
+        assert_eq!(egress.describe(), ["*=10.8.0.2", "tiktok=192.0.2.10"]);
+
+        let prefer_v6 = |k: &str| if k == "EGRESS_IP_FAMILY" { Some("prefer-ipv6".into()) } else { var(k) };
+        let egress = Egress::with_vars(&prefer_v6, &interfaces).unwrap();
+        assert_eq!(egress.source_address("unknown").as_deref(), Some("2001:db8::5"));
+
+        let forced_v6 = |k: &str| if k == "EGRESS_IP_FAMILY" { Some("ipv6".into()) } else { var(k) };
+        assert!(Egress::with_vars(&forced_v6, &interfaces).unwrap_err().contains("EGRESS_TIKTOK"));
+        let missing = |k: &str| (k == "EGRESS_X_INTERFACE").then(|| "eth9".to_string());
+        assert!(Egress::with_vars(&missing, &interfaces).is_err());
+        assert_eq!(Egress::with_vars(&|_: &str| None, &interfaces).unwrap(), Egress::default());
+
+        // Loopback is there even in a bare network namespace
+        assert!(interface_addresses("lo").unwrap().contains(&Ipv4Addr::LOCALHOST.into()));
+    }
+}
@@ -0,0 +1,272 @@
+#[cfg(feature = "redis")]
+use redis::AsyncCommands;
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+#[cfg(feature = "redis")]
+use crate::redis_conn::RedisConn;
+
+/// Lifecycle of one /download request and its session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// Request accepted, waiting for an extraction worker
+    Created,
+    /// yt-dlp is running
+    Extracting,
+    /// Session stored; `session_id` can be streamed
+    Ready,
+    /// A format of the session was served by /stream
+    Streamed,
+    /// Extraction, session storage or the CDN fetch failed
+    Failed,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Created => "created",
+            EventKind::Extracting => "extracting",
+            EventKind::Ready => "ready",
+            EventKind::Streamed => "streamed",
+            EventKind::Failed => "failed",
+        }
+    }
+}
+
+/// Payload published as JSON on `{EVENTS_CHANNEL_PREFIX}:{event}`:
+///
+/// ```json
+/// {"event": "ready", "job_id": "…", "session_id": "…", "platform": "x",
+///  "url": "https://x.com/…", "video_id": "123", "format": null,
+///  "error_code": null, "error": null, "timestamp": "2024-01-01T00:00:00.000Z"}
+/// ```
+///
+/// `job_id` is stable across all events of one /download request, including
+/// the `streamed` events of its session.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobEvent {
+    pub event: EventKind,
+    pub job_id: String,
+    pub session_id: Option<String>,
+    pub platform: String,
+    pub url: Option<String>,
+    pub video_id: Option<String>,
+    /// Format served (`streamed`, or `failed` while streaming)
+    pub format: Option<String>,
+    pub error_code: Option<String>,
+    pub error: Option<String>,
+    pub timestamp: String,
+}
+
+impl JobEvent {
+    pub fn new(event: EventKind, job_id: &str, platform: &str) -> Self {
+        Self {
+            event,
+            job_id: job_id.to_string(),
+            session_id: None,
+            platform: platform.to_string(),
+            url: None,
+            video_id: None,
+            format: None,
+            error_code: None,
+            error: None,
+            timestamp: crate::now_utc(),
+        }
+    }
+
+    /// A `failed` event with the error code the client received.
+    pub fn failed(job_id: &str, platform: &str, code: &str, error: impl Into<String>) -> Self {
+        Self {
+            error_code: Some(code.to_string()),
+            error: Some(error.into()),
+            ..Self::new(EventKind::Failed, job_id, platform)
+        }
+    }
+}
+
+/// Where events go (EVENTS_SINK). Each sink needs its cargo feature
+/// (`redis`, on by default, `nats` / `kafka`).
+enum Sink {
+    /// PUBLISH on `{prefix}:{event}`; events are dropped while Redis is down
+    #[cfg(feature = "redis")]
+    Redis(Arc<RedisConn>),
+    /// Subject `{prefix}.{event}` (`:` in the prefix becomes `.`)
+    #[cfg(feature = "nats")]
+    Nats(async_nats::Client),
+    /// One topic for all events, keyed by `job_id` so a job's events stay ordered
+    #[cfg(feature = "kafka")]
+    Kafka {
+        producer: rdkafka::producer::FutureProducer,
+        topic: String,
+    },
+}
+
+impl Sink {
+    async fn connect(kind: &str, #[cfg(feature = "redis")] redis: &Arc<RedisConn>) -> Result<Self, String> {
+        match kind {
+            #[cfg(feature = "redis")]
+            "redis" => Ok(Sink::Redis(redis.clone())),
+            #[cfg(feature = "nats")]
+            "nats" => {
+                let url = env::var("NATS_URL").unwrap_or_else(|_| "nats://127.0.0.1:4222".into());
+                // Keeps reconnecting in the background if NATS isn't up yet
+                async_nats::ConnectOptions::new()
+                    .retry_on_initial_connect()
+                    .connect(url.as_str())
+                    .await
+                    .map(Sink::Nats)
+                    .map_err(|e| format!("NATS connect to {url} failed: {e}"))
+            }
+            #[cfg(feature = "kafka")]
+            "kafka" => {
+                let brokers = env::var("KAFKA_BROKERS").unwrap_or_else(|_| "127.0.0.1:9092".into());
+                let topic = env::var("EVENTS_KAFKA_TOPIC").unwrap_or_else(|_| "serverx-events".into());
+                rdkafka::ClientConfig::new()
+                    .set("bootstrap.servers", &brokers)
+                    .set("message.timeout.ms", "5000")
+                    .create()
+                    .map(|producer| Sink::Kafka { producer, topic })
+                    .map_err(|e| format!("Kafka producer for {brokers} failed: {e}"))
+            }
+            #[cfg(not(feature = "redis"))]
+            "redis" => Err("serverx-rs was built without the redis feature".into()),
+            #[cfg(not(feature = "nats"))]
+            "nats" => Err("serverx-rs was built without the nats feature".into()),
+            #[cfg(not(feature = "kafka"))]
+            "kafka" => Err("serverx-rs was built without the kafka feature".into()),
+            other => Err(format!("unknown EVENTS_SINK {other:?} (expected redis, nats or kafka)")),
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "redis", feature = "nats", feature = "kafka")), allow(unused_variables))]
+    fn destination(&self, prefix: &str, event: EventKind) -> String {
+        match *self {
+            #[cfg(feature = "redis")]
+            Sink::Redis(_) => redis_channel(prefix, event),
+            #[cfg(feature = "nats")]
+            Sink::Nats(_) => nats_subject(prefix, event),
+            #[cfg(feature = "kafka")]
+            Sink::Kafka { ref topic, .. } => topic.clone(),
+        }
+    }
+
+    #[cfg_attr(not(feature = "kafka"), allow(unused_variables))]
+    async fn send(&self, destination: &str, event: &JobEvent, payload: String) -> Result<(), String> {
+        match *self {
+            #[cfg(feature = "redis")]
+            Sink::Redis(ref redis) => {
+                let mut conn = redis.get().await.ok_or("Redis unavailable")?;
+                let result = conn.publish::<_, _, ()>(destination, payload).await;
+                if let Err(e) = &result {
+                    redis.report(e).await;
+                }
+                result.map_err(|e| e.to_string())
+            }
+            #[cfg(feature = "nats")]
+            Sink::Nats(ref client) => client
+                .publish(destination.to_string(), payload.into())
+                .await
+                .map_err(|e| e.to_string()),
+            #[cfg(feature = "kafka")]
+            Sink::Kafka { ref producer, .. } => {
+                let record = rdkafka::producer::FutureRecord::to(destination)
+                    .key(&event.job_id)
+                    .payload(&payload);
+                producer
+                    .send(record, rdkafka::util::Timeout::After(std::time::Duration::from_secs(5)))
+                    .await
+                    .map(|_| ())
+                    .map_err(|(e, _)| e.to_string())
+            }
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+fn redis_channel(prefix: &str, event: EventKind) -> String {
+    format!("{}:{}", prefix, event.as_str())
+}
+
+#[cfg_attr(not(feature = "nats"), allow(dead_code))]
+fn nats_subject(prefix: &str, event: EventKind) -> String {
+    format!("{}.{}", prefix.replace(':', "."), event.as_str())
+}
+
+/// Publishes job lifecycle events (EVENTS_ENABLED=true) to Redis Pub/Sub,
+/// NATS or Kafka (EVENTS_SINK), so other services and analytics pipelines can
+/// react without polling the HTTP API.
+#[derive(Clone)]
+pub struct EventPublisher {
+    sink: Option<Arc<Sink>>,
+    prefix: String,
+}
+
+impl EventPublisher {
+    pub async fn from_env(#[cfg(feature = "redis")] redis: &Arc<RedisConn>) -> Self {
+        let enabled = env::var("EVENTS_ENABLED")
+            .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let prefix = env::var("EVENTS_CHANNEL_PREFIX").unwrap_or_else(|_| "serverx:events".into());
+        let kind = env::var("EVENTS_SINK").unwrap_or_else(|_| "redis".into()).to_lowercase();
+
+        let sink = if enabled {
+            #[cfg(feature = "redis")]
+            let sink = Sink::connect(&kind, redis).await;
+            #[cfg(not(feature = "redis"))]
+            let sink = Sink::connect(&kind).await;
+            match sink {
+                Ok(sink) => {
+                    info!("📣 Publishing job events to {} ({})", sink.destination(&prefix, EventKind::Created), kind);
+                    Some(Arc::new(sink))
+                }
+                Err(e) => {
+                    warn!("Job events disabled: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Self { sink, prefix }
+    }
+
+    /// Fire-and-forget; a slow or failing sink never delays the request.
+    pub fn publish(&self, event: JobEvent) {
+        let Some(sink) = self.sink.clone() else {
+            return;
+        };
+        let destination = sink.destination(&self.prefix, event.event);
+        let payload = serde_json::to_string(&event).unwrap();
+        tokio::spawn(async move {
+            if let Err(e) = sink.send(&destination, &event, payload).await {
+                warn!("Failed to publish {} event to {}: {}", event.event.as_str(), destination, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_payload_schema() {
+        let event = JobEvent {
+            session_id: Some("s1".into()),
+            format: Some("best".into()),
+            ..JobEvent::failed("j1", "x", "DOWNLOAD_ERROR", "CDN returned 403")
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "failed");
+        assert_eq!(json["job_id"], "j1");
+        assert_eq!(json["session_id"], "s1");
+        assert_eq!(json["error_code"], "DOWNLOAD_ERROR");
+        assert!(json["url"].is_null());
+
+        assert_eq!(redis_channel("serverx:events", EventKind::Streamed), "serverx:events:streamed");
+        assert_eq!(nats_subject("serverx:events", EventKind::Ready), "serverx.events.ready");
+    }
+}
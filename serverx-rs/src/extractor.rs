@@ -0,0 +1,263 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "python")]
+use tracing::error;
+use tracing::warn;
+
+use crate::error::ExtractError;
+use crate::mock::MockExtractor;
+
+/// Where yt-dlp runs: in this process through PyO3 (`python` cargo feature)
+/// or on a pool of `serverx-worker` processes (EXTRACTOR_WORKERS), so the API
+/// tier and the Python-heavy extraction tier can be scaled separately — or
+/// canned fixtures for local development (MOCK_EXTRACTOR).
+#[derive(Clone)]
+pub enum Extractor {
+    #[cfg(feature = "python")]
+    Embedded,
+    Remote(Arc<RemoteWorkers>),
+    Mock(Arc<MockExtractor>),
+}
+
+impl Extractor {
+    /// Extract `url` to yt-dlp's info JSON. `cookiefile` is a Netscape
+    /// cookies.txt path, `format` a yt-dlp format selector and
+    /// `source_address` the local address yt-dlp binds to; `guard` (queue
+    /// permit, temp cookie file) is held until yt-dlp is done, even if the
+    /// caller stops waiting.
+    pub async fn extract<G: Send + 'static>(
+        &self,
+        url: String,
+        cookiefile: Option<String>,
+        format: Option<String>,
+        source_address: Option<String>,
+        guard: G,
+    ) -> Result<String, ExtractError> {
+        match self {
+            #[cfg(feature = "python")]
+            Extractor::Embedded => tokio::task::spawn_blocking(move || {
+                let _guard = guard;
+                crate::ytdlp::extract_with_ytdlp(&url, cookiefile.as_deref(), format.as_deref(), source_address.as_deref())
+            })
+            .await
+            .unwrap_or_else(|e| {
+                error!("Task join error: {e}");
+                Err(ExtractError::Internal(format!("Task join error: {e}")))
+            }),
+            Extractor::Remote(workers) => {
+                let cookies = match cookiefile {
+                    Some(path) => Some(
+                        tokio::fs::read_to_string(&path)
+                            .await
+                            .map_err(|e| ExtractError::Internal(format!("Failed to read {path}: {e}")))?,
+                    ),
+                    None => None,
+                };
+                let result = workers.extract(&url, cookies.as_deref(), format.as_deref(), source_address.as_deref()).await;
+                drop(guard);
+                result
+            }
+            Extractor::Mock(mock) => {
+                drop(guard);
+                mock.extract(&url)
+            }
+        }
+    }
+
+    /// yt-dlp version, for the periodic Python environment check.
+    pub async fn check(&self) -> Result<String, String> {
+        match self {
+            #[cfg(feature = "python")]
+            Extractor::Embedded => tokio::task::spawn_blocking(crate::ytdlp::check_ytdlp)
+                .await
+                .unwrap_or_else(|e| Err(format!("Task join error: {e}"))),
+            Extractor::Remote(workers) => workers.check().await,
+            Extractor::Mock(_) => Ok("mock".into()),
+        }
+    }
+
+    /// yt-dlp runs in this process's interpreter.
+    pub fn is_embedded(&self) -> bool {
+        match self {
+            #[cfg(feature = "python")]
+            Extractor::Embedded => true,
+            _ => false,
+        }
+    }
+}
+
+/// Client for the extraction workers listed in EXTRACTOR_WORKERS.
+///
+/// Protocol: `POST {worker}/extract` with `{"url", "cookies", "format",
+/// "source_address"}` (cookies.txt content, yt-dlp format selector and local
+/// address, optional) answers 200 with yt-dlp's info JSON, or an error status
+/// with `{"kind", "message"}` (see `ExtractError::kind`). `GET {worker}/health`
+/// returns `{"ytdlp_version"}`. With EXTRACTOR_TOKEN set, both sides use it as
+/// a bearer token.
+pub struct RemoteWorkers {
+    urls: Vec<String>,
+    token: Option<String>,
+    client: reqwest::Client,
+    next: AtomicUsize,
+}
+
+impl RemoteWorkers {
+    /// None when EXTRACTOR_WORKERS is unset or empty (embedded extraction).
+    pub fn from_env() -> Option<Self> {
+        let urls = parse_worker_urls(&std::env::var("EXTRACTOR_WORKERS").unwrap_or_default());
+        if urls.is_empty() {
+            return None;
+        }
+        Some(Self {
+            urls,
+            token: std::env::var("EXTRACTOR_TOKEN").ok().filter(|t| !t.is_empty()),
+            client: reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(5))
+                .build()
+                .expect("Failed to create HTTP client"),
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.urls.len()
+    }
+
+    /// Workers in round-robin order, starting one further on each call.
+    fn rotation(&self) -> impl Iterator<Item = &String> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        self.urls.iter().cycle().skip(start % self.urls.len()).take(self.urls.len())
+    }
+
+    fn request(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    /// Ask each worker in turn until one answers. Extraction errors reported
+    /// by a worker are returned as-is; an unreachable worker, or a reply that
+    /// isn't part of the protocol (e.g. a proxy's 502), moves on to the next.
+    async fn extract(
+        &self,
+        url: &str,
+        cookies: Option<&str>,
+        format: Option<&str>,
+        source_address: Option<&str>,
+    ) -> Result<String, ExtractError> {
+        let body = serde_json::json!({ "url": url, "cookies": cookies, "format": format, "source_address": source_address })
+            .to_string();
+        let mut last_error = String::new();
+        for worker in self.rotation() {
+            let sent = self
+                .request(self.client.post(format!("{worker}/extract")))
+                .header("Content-Type", "application/json")
+                .body(body.clone())
+                .send()
+                .await;
+            let resp = match sent {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("Extraction worker {} unreachable: {}", worker, e);
+                    last_error = e.to_string();
+                    continue;
+                }
+            };
+            let status = resp.status();
+            let text = match resp.text().await {
+                Ok(t) => t,
+                Err(e) => {
+                    warn!("Extraction worker {} dropped the response: {}", worker, e);
+                    last_error = e.to_string();
+                    continue;
+                }
+            };
+            if status.is_success() {
+                return Ok(text);
+            }
+            match parse_worker_error(&text) {
+                Some(e) => return Err(e),
+                None => {
+                    warn!("Extraction worker {} answered {}", worker, status);
+                    last_error = format!("worker answered {status}");
+                }
+            }
+        }
+        Err(ExtractError::Transient(format!("No extraction worker available: {last_error}")))
+    }
+
+    /// Version reported by the first healthy worker.
+    async fn check(&self) -> Result<String, String> {
+        let mut last_error = String::new();
+        for worker in self.rotation() {
+            let resp = self
+                .request(self.client.get(format!("{worker}/health")))
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            match resp {
+                Ok(r) => match r.text().await.map(|t| serde_json::from_str::<serde_json::Value>(&t)) {
+                    Ok(Ok(v)) => {
+                        if let Some(version) = v["ytdlp_version"].as_str() {
+                            return Ok(version.to_string());
+                        }
+                        last_error = format!("{worker}: {}", v["error"].as_str().unwrap_or("no yt-dlp version"));
+                    }
+                    Ok(Err(e)) => last_error = format!("{worker}: {e}"),
+                    Err(e) => last_error = format!("{worker}: {e}"),
+                },
+                Err(e) => last_error = format!("{worker}: {e}"),
+            }
+        }
+        Err(format!("No healthy extraction worker ({last_error})"))
+    }
+}
+
+/// "http://a:8090/, http://b:8090" -> ["http://a:8090", "http://b:8090"]
+fn parse_worker_urls(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|u| u.trim().trim_end_matches('/'))
+        .filter(|u| !u.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// An error reply in the worker protocol.
+fn parse_worker_error(body: &str) -> Option<ExtractError> {
+    let v: serde_json::Value = serde_json::from_str(body).ok()?;
+    let kind = v["kind"].as_str()?;
+    let message = v["message"].as_str().unwrap_or_default().to_string();
+    Some(ExtractError::from_kind(kind, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worker_urls_and_errors() {
+        assert_eq!(
+            parse_worker_urls(" http://a:8090/, ,http://b:8090"),
+            vec!["http://a:8090", "http://b:8090"]
+        );
+        assert!(parse_worker_urls("").is_empty());
+
+        let e = parse_worker_error(r#"{"kind":"not_found","message":"Video not found"}"#).unwrap();
+        assert!(matches!(e, ExtractError::NotFound(ref m) if m == "Video not found"));
+        assert!(parse_worker_error("<html>502 Bad Gateway</html>").is_none());
+
+        let workers = RemoteWorkers {
+            urls: parse_worker_urls("http://a,http://b,http://c"),
+            token: None,
+            client: reqwest::Client::new(),
+            next: AtomicUsize::new(0),
+        };
+        let first: Vec<_> = workers.rotation().cloned().collect();
+        let second: Vec<_> = workers.rotation().cloned().collect();
+        assert_eq!(first, ["http://a", "http://b", "http://c"]);
+        assert_eq!(second, ["http://b", "http://c", "http://a"]);
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Replaces cookie values in recorded fixtures.
+pub const REDACTED: &str = "REDACTED";
+
+/// One post: yt-dlp's info JSON and the CDN exchange of each format streamed
+/// from it. The Recorder writes them as `{platform}_{id}.json` in
+/// RECORD_FIXTURES_DIR; copied into `fixtures/`, the replay test runs them
+/// through the format parsing and response building. The ones checked in
+/// are synthetic, written by hand in the same shape.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Fixture {
+    /// Post URL the info was extracted from
+    pub url: String,
+    pub info: serde_json::Value,
+    /// format id (as in /stream?format=) -> last exchange for it
+    #[serde(default)]
+    pub cdn: BTreeMap<String, CdnExchange>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CdnExchange {
+    /// Headers /stream sent, without Range (see `range`)
+    pub request_headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
+    pub status: u16,
+    pub response_headers: BTreeMap<String, String>,
+}
+
+/// Writes fixtures while RECORD_FIXTURES_DIR is set. Cookies are redacted;
+/// CDN URLs are kept as they are (they expire anyway). File I/O runs on the
+/// blocking pool.
+pub struct Recorder {
+    dir: PathBuf,
+    /// Serializes the read-modify-write of fixture files
+    lock: Arc<Mutex<()>>,
+}
+
+impl Recorder {
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, lock: Arc::new(Mutex::new(())) })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Record an extraction's info JSON, keeping the CDN exchanges already
+    /// recorded for the post.
+    pub async fn record_info(&self, url: &str, platform: &str, json: &str) {
+        let Ok(mut info) = serde_json::from_str::<serde_json::Value>(json) else {
+            return;
+        };
+        let Some(id) = info["id"].as_str().map(String::from) else {
+            return;
+        };
+        redact(&mut info);
+        let url = url.to_string();
+        self.update(platform, &id, move |fixture| {
+            fixture.url = url;
+            fixture.info = info;
+        })
+        .await;
+    }
+
+    /// Record what /stream sent to the CDN for `format_id` and what came back.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record_cdn(
+        &self,
+        platform: &str,
+        video_id: &str,
+        format_id: &str,
+        request_headers: &[(String, String)],
+        range: Option<String>,
+        status: u16,
+        response_headers: &reqwest::header::HeaderMap,
+    ) {
+        let exchange = CdnExchange {
+            request_headers: redact_headers(request_headers.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+            range,
+            status,
+            response_headers: redact_headers(
+                response_headers.iter().filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?))),
+            ),
+        };
+        let format_id = format_id.to_string();
+        self.update(platform, video_id, move |fixture| {
+            fixture.cdn.insert(format_id, exchange);
+        })
+        .await;
+    }
+
+    async fn update(&self, platform: &str, id: &str, change: impl FnOnce(&mut Fixture) + Send + 'static) {
+        let path = self.dir.join(fixture_name(platform, id));
+        let lock = self.lock.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            let _lock = lock.lock().unwrap();
+            let mut fixture = std::fs::read(&path)
+                .ok()
+                .and_then(|raw| serde_json::from_slice(&raw).ok())
+                .unwrap_or_default();
+            change(&mut fixture);
+            let result = serde_json::to_vec_pretty(&fixture).map_err(std::io::Error::other).and_then(|mut raw| {
+                raw.push(b'\n');
+                std::fs::write(&path, raw)
+            });
+            (path, result)
+        })
+        .await;
+        match written {
+            Ok((path, Ok(()))) => info!("Recorded fixture {}", path.display()),
+            Ok((path, Err(e))) => warn!("Failed to record fixture {}: {}", path.display(), e),
+            Err(e) => warn!("Fixture recording task failed: {}", e),
+        }
+    }
+}
+
+/// `{platform}_{id}.json`, keeping only characters safe in a file name.
+pub fn fixture_name(platform: &str, id: &str) -> String {
+    let safe = |s: &str| s.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_', "_");
+    format!("{}_{}.json", safe(platform), safe(id))
+}
+
+/// Blank every cookie yt-dlp put in the info: `cookies`, each format's
+/// `_cookies` and Cookie entries of `http_headers`, in entries too.
+pub fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if v.is_string() && matches!(key.as_str(), "cookie" | "cookies" | "_cookies") {
+                    *v = REDACTED.into();
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Headers keyed by lowercase name, with Cookie/Set-Cookie values redacted.
+pub fn redact_headers<'a>(headers: impl Iterator<Item = (&'a str, &'a str)>) -> BTreeMap<String, String> {
+    headers
+        .map(|(k, v)| {
+            let k = k.to_lowercase();
+            let v = if matches!(k.as_str(), "cookie" | "set-cookie") { REDACTED } else { v };
+            (k, v.to_string())
+        })
+        .collect()
+}
+
+/// Every `*.json` fixture in `dir`, sorted by file name.
+#[cfg(test)]
+pub fn load_dir(dir: &Path) -> Vec<(String, Fixture)> {
+    let mut fixtures: Vec<(String, Fixture)> = std::fs::read_dir(dir)
+        .map(|entries| entries.filter_map(Result::ok).map(|e| e.path()).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .map(|p| {
+            let name = p.file_name().unwrap().to_string_lossy().into_owned();
+            let raw = std::fs::read(&p).unwrap();
+            let fixture = serde_json::from_slice(&raw).unwrap_or_else(|e| panic!("{name}: {e}"));
+            (name, fixture)
+        })
+        .collect();
+    fixtures.sort_by(|a, b| a.0.cmp(&b.0));
+    fixtures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_redacts_and_merges() {
+        let dir = std::env::temp_dir().join(format!("serverx-fixtures-{}", uuid::Uuid::new_v4()));
+        let recorder = Recorder::new(&dir).unwrap();
+        let info = r#"{"id": "42", "cookies": "auth_token=secret", "formats": [
+            {"format_id": "http-720", "_cookies": "ct0=secret", "http_headers": {"Cookie": "a=b", "User-Agent": "UA"}}
+        ]}"#;
+        recorder.record_info("https://x.com/a/status/42", "x", info).await;
+        let mut response = reqwest::header::HeaderMap::new();
+        response.insert("content-type", "video/mp4".parse().unwrap());
+        response.insert("set-cookie", "tracking=1".parse().unwrap());
+        let sent = vec![("Cookie".to_string(), "a=b".to_string()), ("User-Agent".to_string(), "UA".to_string())];
+        recorder.record_cdn("x", "42", "http-720", &sent, Some("bytes=10-".into()), 206, &response).await;
+
+        let fixtures = load_dir(&dir);
+        assert!(std::fs::read(dir.join("x_42.json")).unwrap().ends_with(b"}\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(fixtures.len(), 1);
+        let (name, fixture) = &fixtures[0];
+        assert_eq!(name, "x_42.json");
+        assert_eq!(fixture.url, "https://x.com/a/status/42");
+        assert!(!serde_json::to_string(fixture).unwrap().contains("secret"));
+        assert_eq!(fixture.info["formats"][0]["http_headers"]["Cookie"], REDACTED);
+        assert_eq!(fixture.info["formats"][0]["http_headers"]["User-Agent"], "UA");
+        let exchange = &fixture.cdn["http-720"];
+        assert_eq!((exchange.status, exchange.range.as_deref()), (206, Some("bytes=10-")));
+        assert_eq!(exchange.request_headers["cookie"], REDACTED);
+        assert_eq!(exchange.response_headers["set-cookie"], REDACTED);
+        assert_eq!(exchange.response_headers["content-type"], "video/mp4");
+    }
+}
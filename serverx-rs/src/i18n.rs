@@ -0,0 +1,573 @@
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// Languages `message` is available in; English is what the handlers write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Es,
+    Id,
+    Pt,
+}
+
+impl Lang {
+    /// The supported language the client ranks highest in an Accept-Language
+    /// header ("pt-BR,pt;q=0.9,en;q=0.8"); English when there's none.
+    pub fn from_accept_language(header: &str) -> Lang {
+        let mut best: Option<(Lang, f32)> = None;
+        for part in header.split(',') {
+            let mut params = part.split(';');
+            let tag = params.next().unwrap_or("").trim();
+            let q = match params.find_map(|p| p.trim().strip_prefix("q=")) {
+                Some(q) => q.trim().parse().unwrap_or(0.0),
+                None => 1.0,
+            };
+            let lang = match tag.split('-').next().unwrap_or("").to_ascii_lowercase().as_str() {
+                "en" => Lang::En,
+                "es" => Lang::Es,
+                // "in" is the old code for Indonesian
+                "id" | "in" => Lang::Id,
+                "pt" => Lang::Pt,
+                _ => continue,
+            };
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((lang, q));
+            }
+        }
+        best.map_or(Lang::En, |(lang, _)| lang)
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Es => "es",
+            Lang::Id => "id",
+            Lang::Pt => "pt",
+        }
+    }
+}
+
+/// error_code → English message, Spanish, Indonesian, Portuguese. Success
+/// responses, which have no code, are keyed by their `data.content_type`.
+/// Codes shared by several messages (HTTP_400, DOWNLOAD_ERROR, ...) list
+/// each, and the English text picks one. `{}` stands for the one variable
+/// part (a count, a format id, ...), carried over as-is.
+const MESSAGES: &[(&str, &str, [&str; 3])] = &[
+    // /download
+    ("HTTP_400", "URL is required", ["La URL es obligatoria", "URL wajib diisi", "A URL é obrigatória"]),
+    (
+        "HTTP_400",
+        "Unsupported URL. Only TikTok and X (Twitter) URLs are supported.",
+        [
+            "URL no compatible. Solo se admiten URLs de TikTok y X (Twitter).",
+            "URL tidak didukung. Hanya URL TikTok dan X (Twitter) yang didukung.",
+            "URL não suportada. Apenas URLs do TikTok e do X (Twitter) são suportadas.",
+        ],
+    ),
+    (
+        "HTTP_400",
+        "Invalid format selector",
+        ["Selector de formato no válido", "Format selector tidak valid", "Seletor de formato inválido"],
+    ),
+    ("HTTP_400", "Invalid cookies: {}", ["Cookies no válidas: {}", "Cookies tidak valid: {}", "Cookies inválidos: {}"]),
+    (
+        "INTERNAL_ERROR",
+        "Failed to prepare cookies",
+        ["No se pudieron preparar las cookies", "Gagal menyiapkan cookies", "Falha ao preparar os cookies"],
+    ),
+    (
+        "HTTP_429",
+        "Server is busy, please retry later",
+        [
+            "El servidor está ocupado, inténtalo más tarde",
+            "Server sedang sibuk, silakan coba lagi nanti",
+            "O servidor está ocupado, tente novamente mais tarde",
+        ],
+    ),
+    (
+        "EGRESS_BUDGET_EXHAUSTED",
+        "Bandwidth budget exhausted, please retry later",
+        [
+            "Se agotó el presupuesto de ancho de banda, inténtalo más tarde",
+            "Kuota bandwidth habis, silakan coba lagi nanti",
+            "O limite de banda foi esgotado, tente novamente mais tarde",
+        ],
+    ),
+    (
+        "FEATURE_DISABLED",
+        "{} is disabled on this server",
+        ["{} está desactivado en este servidor", "{} dinonaktifkan di server ini", "{} está desativado neste servidor"],
+    ),
+    (
+        "NO_MATCHING_FORMATS",
+        "No formats match max_height/prefer/audio_only",
+        [
+            "Ningún formato coincide con max_height/prefer/audio_only",
+            "Tidak ada format yang cocok dengan max_height/prefer/audio_only",
+            "Nenhum formato corresponde a max_height/prefer/audio_only",
+        ],
+    ),
+    (
+        "REDIS_ERROR",
+        "Failed to create download session",
+        ["No se pudo crear la sesión de descarga", "Gagal membuat sesi download", "Falha ao criar a sessão de download"],
+    ),
+    (
+        "INTERNAL_ERROR",
+        "Failed to parse extraction result",
+        [
+            "No se pudo procesar el resultado de la extracción",
+            "Gagal membaca hasil ekstraksi",
+            "Falha ao processar o resultado da extração",
+        ],
+    ),
+    (
+        "HTTP_404",
+        "Video not found or may be private/deleted",
+        [
+            "Video no encontrado o puede ser privado/eliminado",
+            "Video tidak ditemukan atau mungkin privat/dihapus",
+            "Vídeo não encontrado ou pode ser privado/excluído",
+        ],
+    ),
+    (
+        "HTTP_403",
+        "Access forbidden - video may be private or region-restricted",
+        [
+            "Acceso prohibido: el video puede ser privado o estar restringido por región",
+            "Akses ditolak - video mungkin privat atau dibatasi wilayah",
+            "Acesso proibido - o vídeo pode ser privado ou restrito por região",
+        ],
+    ),
+    (
+        "HTTP_401",
+        "This content requires login/authentication",
+        [
+            "Este contenido requiere inicio de sesión/autenticación",
+            "Konten ini memerlukan login/autentikasi",
+            "Este conteúdo requer login/autenticação",
+        ],
+    ),
+    (
+        "HTTP_400",
+        "Unsupported or invalid URL",
+        ["URL no compatible o no válida", "URL tidak didukung atau tidak valid", "URL não suportada ou inválida"],
+    ),
+    (
+        "HTTP_422",
+        "Format selector is invalid or matches no format",
+        [
+            "El selector de formato no es válido o no coincide con ningún formato",
+            "Format selector tidak valid atau tidak cocok dengan format apa pun",
+            "O seletor de formato é inválido ou não corresponde a nenhum formato",
+        ],
+    ),
+    (
+        "HTTP_502",
+        "Upstream temporarily unavailable, please try again",
+        [
+            "El origen no está disponible temporalmente, inténtalo de nuevo",
+            "Sumber sedang tidak tersedia, silakan coba lagi",
+            "A origem está temporariamente indisponível, tente novamente",
+        ],
+    ),
+    (
+        "HTTP_504",
+        "Request timeout - video extraction took too long",
+        [
+            "Tiempo de espera agotado: la extracción del video tardó demasiado",
+            "Waktu habis - ekstraksi video terlalu lama",
+            "Tempo esgotado - a extração do vídeo demorou demais",
+        ],
+    ),
+    ("HTTP_500", "Extraction failed", ["La extracción falló", "Ekstraksi gagal", "Falha na extração"]),
+    (
+        "photo",
+        "Photo extracted successfully",
+        ["Foto extraída correctamente", "Foto berhasil diekstrak", "Foto extraída com sucesso"],
+    ),
+    (
+        "video",
+        "Video info extracted successfully",
+        [
+            "Información del video extraída correctamente",
+            "Info video berhasil diekstrak",
+            "Informações do vídeo extraídas com sucesso",
+        ],
+    ),
+    (
+        "audio",
+        "Audio extracted successfully",
+        ["Audio extraído correctamente", "Audio berhasil diekstrak", "Áudio extraído com sucesso"],
+    ),
+    (
+        "unknown",
+        "Media extracted successfully",
+        ["Contenido extraído correctamente", "Media berhasil diekstrak", "Mídia extraída com sucesso"],
+    ),
+    (
+        "photo",
+        "Photo gallery extracted successfully ({} images)",
+        [
+            "Galería de fotos extraída correctamente ({} imágenes)",
+            "Galeri foto berhasil diekstrak ({} gambar)",
+            "Galeria de fotos extraída com sucesso ({} imagens)",
+        ],
+    ),
+    (
+        "mixed",
+        "Mixed media extracted successfully ({} items)",
+        [
+            "Contenido mixto extraído correctamente ({} elementos)",
+            "Media campuran berhasil diekstrak ({} item)",
+            "Mídia mista extraída com sucesso ({} itens)",
+        ],
+    ),
+    (
+        "playlist",
+        "Playlist extracted successfully ({} items)",
+        [
+            "Lista extraída correctamente ({} elementos)",
+            "Playlist berhasil diekstrak ({} item)",
+            "Playlist extraída com sucesso ({} itens)",
+        ],
+    ),
+    // /stream, /slideshow, /avatar
+    (
+        "REDIS_ERROR",
+        "Session store unavailable, please retry shortly",
+        [
+            "El almacén de sesiones no está disponible, inténtalo en breve",
+            "Penyimpanan sesi tidak tersedia, silakan coba lagi sebentar lagi",
+            "O armazenamento de sessões está indisponível, tente novamente em breve",
+        ],
+    ),
+    (
+        "RESUME_TOKEN_INVALID",
+        "Resume token is invalid or expired",
+        [
+            "El token de reanudación no es válido o expiró",
+            "Token resume tidak valid atau kedaluwarsa",
+            "O token de retomada é inválido ou expirou",
+        ],
+    ),
+    (
+        "RESUME_FORMAT_GONE",
+        "The format is no longer offered for this post. Please extract again.",
+        [
+            "Este formato ya no está disponible para esta publicación. Vuelve a extraer.",
+            "Format ini tidak lagi tersedia untuk postingan ini. Silakan ekstrak ulang.",
+            "Este formato não está mais disponível para esta publicação. Extraia novamente.",
+        ],
+    ),
+    (
+        "SESSION_EXPIRED",
+        "Session expired or not found. Please extract again.",
+        [
+            "La sesión expiró o no existe. Vuelve a extraer.",
+            "Sesi kedaluwarsa atau tidak ditemukan. Silakan ekstrak ulang.",
+            "A sessão expirou ou não foi encontrada. Extraia novamente.",
+        ],
+    ),
+    (
+        "FORMAT_NOT_FOUND",
+        "Format '{}' not found in session",
+        ["Formato '{}' no encontrado en la sesión", "Format '{}' tidak ada di sesi", "Formato '{}' não encontrado na sessão"],
+    ),
+    (
+        "DOWNLOAD_ERROR",
+        "Failed to download media from source",
+        [
+            "No se pudo descargar el contenido del origen",
+            "Gagal mengunduh media dari sumber",
+            "Falha ao baixar a mídia da origem",
+        ],
+    ),
+    (
+        "CHAPTER_NOT_FOUND",
+        "Chapter {} not found",
+        ["Capítulo {} no encontrado", "Chapter {} tidak ditemukan", "Capítulo {} não encontrado"],
+    ),
+    (
+        "CLIP_UNAVAILABLE",
+        "Only video and audio formats can be clipped",
+        [
+            "Solo se pueden recortar formatos de video y audio",
+            "Hanya format video dan audio yang bisa dipotong",
+            "Apenas formatos de vídeo e áudio podem ser recortados",
+        ],
+    ),
+    (
+        "INTERNAL_ERROR",
+        "Failed to prepare clip",
+        ["No se pudo preparar el recorte", "Gagal menyiapkan potongan", "Falha ao preparar o recorte"],
+    ),
+    (
+        "CLIP_ERROR",
+        "Failed to clip media from source",
+        [
+            "No se pudo recortar el contenido del origen",
+            "Gagal memotong media dari sumber",
+            "Falha ao recortar a mídia da origem",
+        ],
+    ),
+    (
+        "WAVEFORM_UNAVAILABLE",
+        "Images have no waveform",
+        ["Las imágenes no tienen forma de onda", "Gambar tidak punya waveform", "Imagens não têm forma de onda"],
+    ),
+    (
+        "INTERNAL_ERROR",
+        "Failed to prepare waveform",
+        ["No se pudo preparar la forma de onda", "Gagal menyiapkan waveform", "Falha ao preparar a forma de onda"],
+    ),
+    (
+        "WAVEFORM_ERROR",
+        "Failed to decode audio from source",
+        [
+            "No se pudo decodificar el audio del origen",
+            "Gagal men-decode audio dari sumber",
+            "Falha ao decodificar o áudio da origem",
+        ],
+    ),
+    (
+        "INTERNAL_ERROR",
+        "Failed to prepare video",
+        ["No se pudo preparar el video", "Gagal menyiapkan video", "Falha ao preparar o vídeo"],
+    ),
+    (
+        "ROTATION_ERROR",
+        "Failed to fix video rotation",
+        ["No se pudo corregir la rotación del video", "Gagal memperbaiki rotasi video", "Falha ao corrigir a rotação do vídeo"],
+    ),
+    (
+        "TRANSCODE_ERROR",
+        "Failed to transcode video",
+        ["No se pudo transcodificar el video", "Gagal men-transcode video", "Falha ao transcodificar o vídeo"],
+    ),
+    (
+        "PREVIEW_UNAVAILABLE",
+        "Only video formats have previews",
+        ["Solo los formatos de video tienen vista previa", "Hanya format video yang punya preview", "Só formatos de vídeo têm prévia"],
+    ),
+    (
+        "INTERNAL_ERROR",
+        "Failed to prepare preview",
+        ["No se pudo preparar la vista previa", "Gagal menyiapkan preview", "Falha ao preparar a prévia"],
+    ),
+    (
+        "PREVIEW_ERROR",
+        "Failed to generate preview from source",
+        ["No se pudo generar la vista previa del origen", "Gagal membuat preview dari sumber", "Falha ao gerar a prévia da origem"],
+    ),
+    (
+        "GENERATION_INVALID",
+        "Generated file failed verification",
+        ["El archivo generado no pasó la verificación", "File hasil tidak lolos verifikasi", "O arquivo gerado falhou na verificação"],
+    ),
+    (
+        "SUBTITLES_NOT_FOUND",
+        "Subtitles '{}' not found",
+        ["Subtítulos '{}' no encontrados", "Subtitle '{}' tidak ditemukan", "Legendas '{}' não encontradas"],
+    ),
+    (
+        "SUBTITLES_ERROR",
+        "Failed to fetch subtitles",
+        ["No se pudieron obtener los subtítulos", "Gagal mengambil subtitle", "Falha ao obter as legendas"],
+    ),
+    (
+        "FILE_TOO_LARGE",
+        "File is too large ({} bytes)",
+        ["El archivo es demasiado grande ({} bytes)", "File terlalu besar ({} byte)", "O arquivo é grande demais ({} bytes)"],
+    ),
+    (
+        "DURATION_EXCEEDED",
+        "Media is too long ({} seconds)",
+        ["El contenido es demasiado largo ({} segundos)", "Media terlalu panjang ({} detik)", "A mídia é longa demais ({} segundos)"],
+    ),
+    (
+        "SCHEMA_CHANGED",
+        "Unexpected extraction result, the extractor may be outdated",
+        [
+            "Resultado de extracción inesperado, el extractor puede estar desactualizado",
+            "Hasil ekstraksi tidak terduga, extractor mungkin sudah usang",
+            "Resultado de extração inesperado, o extrator pode estar desatualizado",
+        ],
+    ),
+    (
+        "PROBE_UNAVAILABLE",
+        "Images can't be probed",
+        ["Las imágenes no se pueden analizar", "Gambar tidak bisa di-probe", "Imagens não podem ser analisadas"],
+    ),
+    (
+        "INTERNAL_ERROR",
+        "Failed to prepare probe",
+        ["No se pudo preparar el análisis", "Gagal menyiapkan probe", "Falha ao preparar a análise"],
+    ),
+    (
+        "PROBE_ERROR",
+        "Failed to probe source",
+        ["No se pudo analizar el origen", "Gagal mem-probe sumber", "Falha ao analisar a origem"],
+    ),
+    (
+        "SLIDESHOW_UNAVAILABLE",
+        "Slideshows are only available for photo posts",
+        [
+            "Las presentaciones solo están disponibles para publicaciones de fotos",
+            "Slideshow hanya tersedia untuk post foto",
+            "Apresentações só estão disponíveis para posts de fotos",
+        ],
+    ),
+    (
+        "INTERNAL_ERROR",
+        "Failed to prepare slideshow",
+        ["No se pudo preparar la presentación", "Gagal menyiapkan slideshow", "Falha ao preparar a apresentação"],
+    ),
+    (
+        "SLIDESHOW_ERROR",
+        "Failed to create slideshow",
+        ["No se pudo crear la presentación", "Gagal membuat slideshow", "Falha ao criar a apresentação"],
+    ),
+    (
+        "HTTP_503",
+        "Server is shutting down",
+        ["El servidor se está apagando", "Server sedang dimatikan", "O servidor está sendo desligado"],
+    ),
+    (
+        "HTTP_400",
+        "Unknown platform or invalid user",
+        ["Plataforma desconocida o usuario no válido", "Platform tidak dikenal atau user tidak valid", "Plataforma desconhecida ou usuário inválido"],
+    ),
+    ("AVATAR_NOT_FOUND", "Avatar not found", ["Avatar no encontrado", "Avatar tidak ditemukan", "Avatar não encontrado"]),
+    (
+        "DOWNLOAD_ERROR",
+        "Failed to look up avatar",
+        ["No se pudo buscar el avatar", "Gagal mencari avatar", "Falha ao buscar o avatar"],
+    ),
+    (
+        "DOWNLOAD_ERROR",
+        "Failed to download avatar",
+        ["No se pudo descargar el avatar", "Gagal mengunduh avatar", "Falha ao baixar o avatar"],
+    ),
+    // /admin
+    (
+        "ADMIN_DISABLED",
+        "Admin endpoints are disabled on this server",
+        [
+            "Los endpoints de administración están desactivados en este servidor",
+            "Endpoint admin dinonaktifkan di server ini",
+            "Os endpoints de administração estão desativados neste servidor",
+        ],
+    ),
+    (
+        "UNAUTHORIZED",
+        "Invalid admin token",
+        ["Token de administración no válido", "Token admin tidak valid", "Token de administração inválido"],
+    ),
+];
+
+/// `message`, sent under `key` (see MESSAGES), in `lang`; None when it's
+/// English or not in the catalog.
+pub fn translate(lang: Lang, key: &str, message: &str) -> Option<String> {
+    let column = match lang {
+        Lang::En => return None,
+        Lang::Es => 0,
+        Lang::Id => 1,
+        Lang::Pt => 2,
+    };
+    let shared = MESSAGES.iter().filter(|(k, ..)| *k == key).count() > 1;
+    MESSAGES.iter().filter(|(k, ..)| *k == key).find_map(|(_, english, translations)| {
+        let translated = translations[column];
+        match english.split_once("{}") {
+            // A code of its own keeps its translation when the English is reworded
+            None => (!shared || *english == message).then(|| translated.to_string()),
+            Some((prefix, suffix)) => {
+                let arg = message.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                Some(translated.replacen("{}", arg, 1))
+            }
+        }
+    })
+}
+
+/// Middleware: rewrite the top-level `message` of JSON responses into the
+/// client's Accept-Language. Error codes and every other field are left
+/// alone, so clients can keep matching on them.
+pub async fn localize(request: Request, next: Next) -> Response {
+    let lang = request
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(Lang::En, Lang::from_accept_language);
+    let mut response = next.run(request).await;
+    response.headers_mut().append(VARY, HeaderValue::from_static("accept-language"));
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+    if lang == Lang::En || !is_json {
+        return response;
+    }
+
+    // JSON bodies are built in memory, so buffering them costs nothing
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(b) => b,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let translated = serde_json::from_slice::<serde_json::Value>(&bytes).ok().and_then(|mut value| {
+        let key = value["error_code"].as_str().or_else(|| value["data"]["content_type"].as_str())?;
+        let message = translate(lang, key, value.get("message")?.as_str()?)?;
+        value["message"] = message.into();
+        serde_json::to_vec(&value).ok()
+    });
+    match translated {
+        Some(body) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.insert(CONTENT_LANGUAGE, HeaderValue::from_static(lang.code()));
+            Response::from_parts(parts, Body::from(body))
+        }
+        None => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_language_and_translate() {
+        assert_eq!(Lang::from_accept_language("pt-BR,pt;q=0.9,en;q=0.8"), Lang::Pt);
+        assert_eq!(Lang::from_accept_language("fr-FR, es;q=0.5, en;q=0.4"), Lang::Es);
+        assert_eq!(Lang::from_accept_language("en;q=0.9, id"), Lang::Id);
+        assert_eq!(Lang::from_accept_language("de, es;q=0"), Lang::En);
+        assert_eq!(Lang::from_accept_language(""), Lang::En);
+
+        assert_eq!(translate(Lang::Id, "HTTP_400", "URL is required").as_deref(), Some("URL wajib diisi"));
+        assert_eq!(
+            translate(Lang::Es, "FORMAT_NOT_FOUND", "Format 'http-2176' not found in session").as_deref(),
+            Some("Formato 'http-2176' no encontrado en la sesión")
+        );
+        assert_eq!(
+            translate(Lang::Pt, "playlist", "Playlist extracted successfully (3 items)").as_deref(),
+            Some("Playlist extraída com sucesso (3 itens)")
+        );
+        assert_eq!(
+            translate(Lang::Id, "photo", "Photo gallery extracted successfully (4 images)").as_deref(),
+            Some("Galeri foto berhasil diekstrak (4 gambar)")
+        );
+        assert_eq!(translate(Lang::En, "HTTP_400", "URL is required"), None);
+        // The code picks the message, not the English text
+        assert_eq!(
+            translate(Lang::Id, "SESSION_EXPIRED", "Session is gone").as_deref(),
+            Some("Sesi kedaluwarsa atau tidak ditemukan. Silakan ekstrak ulang.")
+        );
+        assert_eq!(translate(Lang::Es, "HTTP_400", "Something new"), None);
+        assert_eq!(translate(Lang::Es, "NEW_CODE", "URL is required"), None);
+    }
+}
@@ -1,43 +1,392 @@
+mod auth;
+mod avatar;
+#[cfg(test)]
+mod benches;
+mod budget;
+mod cdn;
+mod clip;
+mod cookies;
+mod dns;
+mod drain;
+mod egress;
+// Same file as serverrs/src/error.rs; only the embedded yt-dlp classifies messages
+#[cfg_attr(not(feature = "python"), allow(dead_code))]
+mod error;
+mod events;
+mod extractor;
+// Same file as serverrs/src/features.rs; its download token types and
+// per-tenant switches go unused here
+#[allow(dead_code)]
+mod features;
+mod fixtures;
+mod i18n;
+mod metrics;
+mod mock;
+mod orient;
+mod preview;
+mod probe;
+// Same file as serverrs/src/queue.rs; its priorities and named tickets go unused here
+#[allow(dead_code)]
+mod queue;
+#[cfg(feature = "redis")]
+mod redis_conn;
+mod schema;
+mod session_cache;
+mod slideshow;
+mod sniff;
+mod systemd;
+mod template;
+mod transcode;
+mod waveform;
+#[cfg(feature = "python")]
+mod ytdlp;
+
 use axum::{
     body::Body,
-    extract::{Json, Query},
-    http::StatusCode,
+    extract::{Json, Query, State},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
-use pyo3::prelude::*;
-use pyo3::types::PyDict;
-use redis::AsyncCommands;
+use base64::Engine;
+use futures_util::StreamExt;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{RwLock, Semaphore};
 use tower_http::cors::{Any, CorsLayer};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use avatar::AvatarResolver;
+use budget::Shedding;
+use cookies::ClientCookies;
+use error::ExtractError;
+use events::{EventKind, EventPublisher, JobEvent};
+use extractor::{Extractor, RemoteWorkers};
+use features::FeatureFlags;
+use metrics::Metrics;
+use queue::{ExtractionQueue, QueueStatus};
+#[cfg(feature = "redis")]
+use redis::AsyncCommands;
+#[cfg(feature = "redis")]
+use redis_conn::RedisConn;
+use session_cache::SessionCache;
+use template::ResponseTemplates;
+
+// ============= Application State =============
+
+#[derive(Clone)]
+struct AppState {
+    /// Connected lazily; sessions become stateless tokens while it's down
+    #[cfg(feature = "redis")]
+    redis: Arc<RedisConn>,
+    /// Hand out stateless session tokens when Redis is unavailable (STATELESS_FALLBACK)
+    #[cfg(feature = "redis")]
+    stateless_fallback: bool,
+    /// Redis keys are `{namespace}:{session_id}` (SESSION_NAMESPACE)
+    #[cfg(feature = "redis")]
+    session_namespace: String,
+    /// Public address put in every link (BASE_URL, no trailing slash); behind a
+    /// load balancer, the balancer's address, so links work on any instance
+    base_url: String,
+    /// Build links from X-Forwarded-Proto/Host instead, for proxies serving
+    /// several domains (TRUST_PROXY_HEADERS); only safe behind a proxy that
+    /// sets them
+    trust_proxy_headers: bool,
+    session_cipher: SessionCipher,
+    /// `t.` session tokens and /stream resume tokens may be issued and
+    /// opened: ENCRYPTION_KEY is set (or random, in standalone builds), not
+    /// the built-in default anyone could seal tokens with
+    stateless_tokens: bool,
+    /// Lifetime of the X-Resume-Token sent with /stream (RESUME_TOKEN_TTL_SECS)
+    resume_token_ttl_secs: u64,
+    /// Embedded yt-dlp, or remote workers (EXTRACTOR_WORKERS)
+    extractor: Extractor,
+    python_status: Arc<RwLock<PythonStatus>>,
+    metrics: Arc<Metrics>,
+    /// Bounds concurrent yt-dlp extractions (MAX_WORKERS) and waiting requests
+    extraction_queue: Arc<ExtractionQueue>,
+    /// Platforms / per-platform capabilities switched off (DISABLED_FEATURES)
+    features: Arc<FeatureFlags>,
+    /// Named reshaping templates for /download responses (RESPONSE_TEMPLATES_PATH)
+    templates: Arc<ResponseTemplates>,
+    /// Job lifecycle events on Redis Pub/Sub (EVENTS_ENABLED)
+    events: EventPublisher,
+    /// Default extraction timeout and the cap for per-request overrides (seconds)
+    ytdlp_timeout: u64,
+    ytdlp_max_timeout: u64,
+    /// Rotate JPEGs by their EXIF orientation and strip EXIF on /stream (IMAGE_AUTO_ORIENT)
+    image_auto_orient: bool,
+    /// Larger images are streamed untouched (IMAGE_AUTO_ORIENT_MAX_MB)
+    image_auto_orient_max_bytes: u64,
+    /// Netscape cookies.txt for extractions without user cookies (COOKIES_PATH)
+    cookies_path: Option<String>,
+    /// Concurrent FFmpeg runs: /slideshow builds, chapter clips, waveforms,
+    /// probes, re-encodes and previews (SLIDESHOW_WORKERS)
+    slideshow_permits: Arc<Semaphore>,
+    /// Per-request slideshow and clip work folders (TEMP_DIR)
+    temp_dir: PathBuf,
+    /// FFmpeg runs still going after this are killed (FFMPEG_TIMEOUT_SECS)
+    ffmpeg_timeout: std::time::Duration,
+    /// Creator avatars behind GET /avatar (AVATAR_CACHE_SECS)
+    avatars: Arc<AvatarResolver>,
+    /// Writes extractions and CDN exchanges to fixture files (RECORD_FIXTURES_DIR)
+    recorder: Option<Arc<fixtures::Recorder>>,
+    /// CDN timeouts and retries, per platform (CDN_*)
+    cdn: Arc<cdn::CdnPolicies>,
+    /// Hosts a session's media may be fetched from (CDN_ALLOWED_HOSTS)
+    upstream_hosts: Arc<cdn::UpstreamHosts>,
+    /// Address family and source address of extraction and CDN connections,
+    /// per platform (EGRESS_*)
+    egress: Arc<egress::Egress>,
+    /// Media bytes sent per rolling window and when to shed streams (EGRESS_BUDGET_*)
+    budget: Option<Arc<budget::EgressBudget>>,
+    /// GET /waveform peaks per session and format
+    waveforms: Arc<SessionCache<waveform::Waveform>>,
+    /// GET /preview clips per session, format and output
+    previews: Arc<SessionCache<axum::body::Bytes>>,
+    /// /stream re-encodes (?fix_rotation=true, ?compat=h264, ?burn_subs=)
+    /// per session, format and reason, on disk
+    reencoded: Arc<SessionCache<transcode::Reencoded>>,
+    /// Info JSON extracted for resume tokens whose session expired, by URL
+    resumed: Arc<SessionCache<serde_json::Value>>,
+    /// Most bytes ffprobe reads of a format on GET /probe and before
+    /// re-encoding (PROBE_MAX_BYTES)
+    probe_max_bytes: u64,
+    /// Files larger than this get 413 on /stream, None for no limit
+    /// (MAX_DOWNLOAD_BYTES, 0)
+    max_download_bytes: Option<u64>,
+    /// Posts longer than this are refused right after extraction, None for
+    /// no limit (MAX_DURATION_SECS, 0)
+    max_duration_secs: Option<u64>,
+    /// Draining for a restart, and the media requests still in flight
+    drain: Arc<drain::Drain>,
+    /// Bearer token for /admin/* (ADMIN_TOKEN); None disables them
+    admin_token: Option<String>,
+    /// Encoder for /stream?compat=h264 and ?fix_rotation=true (TRANSCODE_HWACCEL)
+    h264_encoder: Arc<transcode::H264Encoder>,
+}
+
 // ============= Request/Response Models =============
 
 #[derive(Deserialize)]
 struct DownloadRequest {
     url: String,
+    /// Optional extraction timeout in seconds (capped by YTDLP_MAX_TIMEOUT)
+    timeout: Option<u64>,
+    /// Optional user cookies (Netscape cookies.txt or "name=value; ..." header
+    /// string) for private/followers-only posts
+    cookies: Option<String>,
+    /// Response template name (RESPONSE_TEMPLATES_PATH); "raw" skips the default
+    template: Option<String>,
+    #[serde(flatten)]
+    filter: FormatFilter,
+    /// Raw yt-dlp format selector (e.g. "bv*[height<=1080]+ba/b"); what it
+    /// picks is returned as `format_selection`
+    format: Option<String>,
+}
+
+/// /download options narrowing the formats returned (and stored in the
+/// session), as JSON fields or query parameters.
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+struct FormatFilter {
+    /// Drop video formats taller than this
+    max_height: Option<i64>,
+    /// Which kind of video format comes first, and so what "best" is
+    /// (default progressive)
+    prefer: Option<Prefer>,
+    /// Only audio formats
+    audio_only: Option<bool>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Prefer {
+    Progressive,
+    Hls,
+}
+
+impl FormatFilter {
+    /// Fields set in `self` (the JSON body) win over `other` (the query).
+    fn or(self, other: FormatFilter) -> FormatFilter {
+        FormatFilter {
+            max_height: self.max_height.or(other.max_height),
+            prefer: self.prefer.or(other.prefer),
+            audio_only: self.audio_only.or(other.audio_only),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.max_height.is_none() && self.prefer.is_none() && self.audio_only.is_none()
+    }
+
+    fn allows(&self, kind: FormatKind, height: i64) -> bool {
+        match kind {
+            FormatKind::Audio => true,
+            _ if self.audio_only == Some(true) => false,
+            FormatKind::Image => true,
+            FormatKind::Progressive | FormatKind::VideoOnly => self.max_height.is_none_or(|max| height <= max),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct StreamRequest {
+    /// Session id; not needed with `resume`
+    #[serde(default)]
     id: String,
     format: Option<String>,  // Format ID to download (e.g., "http-2176", "best")
+    /// Overrides IMAGE_AUTO_ORIENT for this image
+    auto_orient: Option<bool>,
+    /// inline (embed in a page) or attachment (download); images default to
+    /// inline, video and audio to attachment
+    disposition: Option<Disposition>,
+    /// Index into `data.chapters`: send only that part of the format
+    chapter: Option<usize>,
+    /// Re-encode video that carries rotation metadata so it plays upright
+    /// in players that ignore it
+    fix_rotation: Option<bool>,
+    /// h264: serve video clients without HEVC decoders can play, picking an
+    /// H.264 format of the post or else transcoding
+    compat: Option<Compat>,
+    /// Language of `data.subtitles` to burn into the video (re-encoded)
+    burn_subs: Option<String>,
+    /// X-Resume-Token of an earlier /stream response; continues at its
+    /// offset (or the Range header), re-extracting the post if the session
+    /// has expired
+    resume: Option<String>,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Compat {
+    H264,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Disposition {
+    Inline,
+    Attachment,
+}
+
+/// Content-Disposition for a /stream response.
+fn content_disposition(requested: Option<Disposition>, content_type: &str, filename: &str) -> String {
+    let inline = match requested {
+        Some(d) => d == Disposition::Inline,
+        None => content_type.starts_with("image/"),
+    };
+    let kind = if inline { "inline" } else { "attachment" };
+    format!("{}; filename=\"{}\"", kind, filename)
+}
+
+#[derive(Deserialize)]
+struct SlideshowRequest {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct WaveformRequest {
+    id: String,
+    /// Format to draw; best_audio (else the best video's sound) by default
+    format: Option<String>,
+    /// Number of peaks, 1..=waveform::MAX_PEAKS (default 800)
+    peaks: Option<usize>,
+    /// json (default) or binary: one byte (0-255) per peak
+    output: Option<WaveformOutput>,
+}
+
+#[derive(Deserialize)]
+struct PreviewRequest {
+    id: String,
+    /// Video format to preview; best by default
+    format: Option<String>,
+    /// mp4 (default) or webp
+    #[serde(default)]
+    output: preview::PreviewOutput,
+    /// Where the preview starts, seconds (default 0)
+    start: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ProbeRequest {
+    id: String,
+    /// Format to probe; best by default
+    format: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum WaveformOutput {
+    Json,
+    Binary,
+}
+
+#[derive(Serialize, Clone, Default)]
 struct VideoFormat {
     quality: String,
     resolution: String,
     url: String,  // This will now be the stream URL
     size_bytes: Option<i64>,
     format_id: String,
+    /// e.g. "h264", "h265" (TikTok's bytevc1 included), "vp9"; None for audio/images
+    vcodec: Option<String>,
+    /// e.g. "aac", "mp3"; None when the format has no sound
+    acodec: Option<String>,
+    fps: Option<f64>,
+    /// Total bitrate in kbps
+    tbr: Option<f64>,
+    ext: Option<String>,
+    /// yt-dlp protocol: "https", "m3u8_native", ...
+    protocol: Option<String>,
+}
+
+impl VideoFormat {
+    /// Codec, frame rate, bitrate, container and protocol from a yt-dlp
+    /// format dict; the other fields are left for the caller.
+    fn details(fmt: &serde_json::Value) -> Self {
+        let text = |key: &str| {
+            fmt[key]
+                .as_str()
+                .filter(|v| !v.is_empty() && *v != "none")
+                .map(String::from)
+        };
+        Self {
+            vcodec: text("vcodec").map(|c| codec_family(&c)),
+            acodec: text("acodec").map(|c| codec_family(&c)),
+            fps: fmt["fps"].as_f64().filter(|f| *f > 0.0),
+            tbr: fmt["tbr"].as_f64().filter(|t| *t > 0.0),
+            ext: text("ext"),
+            protocol: text("protocol"),
+            ..Self::default()
+        }
+    }
+}
+
+/// Common name of a codec string: "avc1.64001F" -> "h264",
+/// "hvc1.1.6.L93" / "bytevc1" -> "h265", "mp4a.40.2" -> "aac".
+/// Unknown codecs are returned as-is.
+fn codec_family(codec: &str) -> String {
+    let lower = codec.to_lowercase();
+    let base = lower.split('.').next().unwrap_or("");
+    match base {
+        "avc1" | "avc3" | "h264" | "avc" => "h264",
+        "hvc1" | "hev1" | "h265" | "hevc" | "bytevc1" => "h265",
+        "vp09" | "vp9" => "vp9",
+        "av01" | "av1" => "av1",
+        "mp4a" | "aac" => "aac",
+        "mp3" => "mp3",
+        "opus" => "opus",
+        _ => return codec.to_string(),
+    }
+    .to_string()
 }
 
 #[derive(Serialize, Clone)]
@@ -52,6 +401,9 @@ struct MediaEntry {
     media_type: String,
     formats: Vec<VideoFormat>,
     best_url: Option<String>,
+    /// Audio-only formats of the entry (also in `formats` for audio entries)
+    audio_formats: Vec<VideoFormat>,
+    best_audio_url: Option<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -67,12 +419,74 @@ struct VideoData {
     thumbnail: Option<String>,
     duration_seconds: Option<f64>,
     duration_formatted: Option<String>,
-    stats: serde_json::Value,
+    stats: Stats,
+    /// RFC3339 UTC, from the post's timestamp (or its date at midnight)
     created_at: Option<String>,
+    /// YYYY-MM-DD (UTC)
+    upload_date: Option<String>,
     original_url: String,
     is_playlist: bool,
     playlist_count: Option<usize>,
     entries: Vec<MediaEntry>,
+    /// Sound used by the post (TikTok); None when yt-dlp reports no track
+    music: Option<MusicInfo>,
+    /// Chapters / key moments, in order; empty when the video has none
+    chapters: Vec<Chapter>,
+    /// Subtitle tracks /stream?burn_subs= can burn in; empty when none
+    subtitles: Vec<SubtitleTrack>,
+}
+
+#[derive(Serialize, Clone)]
+struct SubtitleTrack {
+    /// yt-dlp's language key, e.g. "en", "eng-US"; what burn_subs takes
+    lang: String,
+    /// Subtitle format: vtt, srt, ass or ssa
+    ext: String,
+    name: Option<String>,
+}
+
+/// A subtitle track and where it's fetched from, kept in the session.
+#[derive(Serialize, Deserialize, Clone)]
+struct SubtitleSource {
+    lang: String,
+    ext: String,
+    url: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Chapter {
+    /// Seconds from the start of the video
+    start: f64,
+    end: f64,
+    title: Option<String>,
+}
+
+/// Engagement counts; every key is always present, null when the platform
+/// doesn't report it (e.g. `quotes` on TikTok).
+#[derive(Serialize, Clone)]
+struct Stats {
+    views: Option<i64>,
+    likes: Option<i64>,
+    comments: Option<i64>,
+    /// Reposts / retweets
+    shares: Option<i64>,
+    /// TikTok favorites ("saves"), X bookmarks
+    bookmarks: Option<i64>,
+    /// X quote posts
+    quotes: Option<i64>,
+    /// The uploader's followers
+    followers: Option<i64>,
+}
+
+#[derive(Serialize, Clone)]
+struct MusicInfo {
+    title: Option<String>,
+    author: Option<String>,
+    duration_seconds: Option<f64>,
+    /// The uploader's own audio rather than a track from TikTok's library
+    original_sound: bool,
+    /// /stream link to the audio-only format, when there is one
+    audio_url: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -88,9 +502,64 @@ struct DownloadResponse {
     best_video_url: Option<String>,
     best_audio_url: Option<String>,
     best_image_url: Option<String>,
+    /// /slideshow link for TikTok photo posts
+    slideshow_url: Option<String>,
+    /// Result of the request's `format` selector
+    format_selection: Option<FormatSelection>,
     extracted_at: String,
 }
 
+#[derive(Serialize, Clone)]
+struct FormatSelection {
+    /// The selector as sent
+    selector: String,
+    /// yt-dlp's description of its pick, e.g. "http-2176 - 720x1280"
+    format: Option<String>,
+    /// The picked format, or the video and audio formats to merge
+    requested_formats: Vec<VideoFormat>,
+}
+
+#[derive(Deserialize)]
+struct EstimateRequest {
+    url: String,
+}
+
+/// GET /estimate — what /download + /stream would deliver, without a session.
+#[derive(Serialize)]
+struct EstimateResponse {
+    success: bool,
+    platform: String,
+    video_id: String,
+    /// video, audio, photo, playlist or unknown
+    content_type: String,
+    /// Sum over the entries for playlists
+    duration_seconds: Option<f64>,
+    duration_formatted: Option<String>,
+    /// What format=best (best_audio / best_image) would send; None for playlists
+    format: Option<EstimateFormat>,
+    /// Bytes of the best format (of every entry for playlists); None when
+    /// neither a filesize nor a bitrate and duration are known
+    size_bytes: Option<i64>,
+    /// size_bytes is bitrate × duration rather than a size reported by the platform
+    size_estimated: bool,
+    /// A single file needs FFmpeg: the /slideshow render of a photo post, or
+    /// remuxing an HLS stream
+    transcoding: bool,
+    /// "slideshow" or "hls"
+    transcoding_reason: Option<String>,
+    playlist_count: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct EstimateFormat {
+    format_id: String,
+    quality: String,
+    resolution: String,
+    vcodec: Option<String>,
+    acodec: Option<String>,
+    protocol: Option<String>,
+}
+
 #[derive(Serialize)]
 struct ErrorResponse {
     success: bool,
@@ -104,6 +573,23 @@ struct HealthResponse {
     timestamp: String,
     version: String,
     redis_connected: bool,
+    /// Redis is down; new sessions are stateless tokens (or rejected)
+    degraded: bool,
+    python: PythonStatus,
+    extraction_queue: QueueStatus,
+    /// Egress budget use, when EGRESS_BUDGET_GB is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    egress_budget: Option<budget::BudgetStatus>,
+    /// Marked for a restart; see /health/drain
+    draining: bool,
+}
+
+/// Cached result of the periodic yt_dlp import check
+#[derive(Serialize, Clone, Default)]
+struct PythonStatus {
+    ytdlp_version: Option<String>,
+    error: Option<String>,
+    checked_at: Option<String>,
 }
 
 // ============= Helper Functions =============
@@ -124,6 +610,12 @@ fn format_duration(seconds: Option<f64>) -> Option<String> {
     }
 }
 
+/// Whether `url` is on a platform this server extracts (TikTok, Douyin, X).
+fn is_supported_url(url: &str) -> bool {
+    let url_lower = url.to_lowercase();
+    ["tiktok.com", "douyin.com", "twitter.com", "x.com"].iter().any(|d| url_lower.contains(d))
+}
+
 fn detect_platform(url: &str, extractor: &str) -> String {
     let url_lower = url.to_lowercase();
     let ext_lower = extractor.to_lowercase();
@@ -139,69 +631,187 @@ fn detect_platform(url: &str, extractor: &str) -> String {
     }
 }
 
+fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
 fn now_utc() -> String {
     chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
 }
 
-// ============= PyO3 yt-dlp Integration =============
-
-fn extract_with_ytdlp(url: &str) -> Result<String, String> {
-    Python::with_gil(|py| {
-        let yt_dlp = py.import("yt_dlp").map_err(|e| format!("Failed to import yt_dlp: {e}"))?;
-
-        let opts = PyDict::new(py);
-        opts.set_item("quiet", true).unwrap();
-        opts.set_item("no_warnings", true).unwrap();
-        opts.set_item("extract_flat", false).unwrap();
-        opts.set_item("socket_timeout", 30).unwrap();
-
-        let ydl_class = yt_dlp
-            .getattr("YoutubeDL")
-            .map_err(|e| format!("Failed to get YoutubeDL: {e}"))?;
-        let ydl = ydl_class
-            .call1((opts,))
-            .map_err(|e| format!("Failed to create YoutubeDL: {e}"))?;
-
-        let kwargs = PyDict::new(py);
-        kwargs.set_item("download", false).unwrap();
-        let info = ydl
-            .call_method("extract_info", (url,), Some(&kwargs))
-            .map_err(|e| {
-                let err_str = e.to_string();
-                if err_str.to_lowercase().contains("not found")
-                    || err_str.to_lowercase().contains("unable to download")
-                {
-                    format!("NOT_FOUND:{err_str}")
-                } else if err_str.contains("403") || err_str.to_lowercase().contains("forbidden") {
-                    format!("FORBIDDEN:{err_str}")
-                } else if err_str.to_lowercase().contains("login")
-                    || err_str.to_lowercase().contains("authentication")
-                {
-                    format!("AUTH_REQUIRED:{err_str}")
-                } else if err_str.to_lowercase().contains("unsupported url") {
-                    format!("UNSUPPORTED:{err_str}")
-                } else {
-                    format!("EXTRACTION_FAILED:{err_str}")
+/// Re-check the yt-dlp environment (the embedded interpreter or the remote
+/// workers) every 5 minutes (first check immediately) and cache the result.
+fn spawn_python_check_task(status: Arc<RwLock<PythonStatus>>, extractor: Extractor) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(300));
+        loop {
+            interval.tick().await;
+            let result = extractor.check().await;
+
+            let mut st = status.write().await;
+            match result {
+                Ok(version) => {
+                    st.ytdlp_version = Some(version);
+                    st.error = None;
                 }
-            })?;
-
-        let json_mod = py
-            .import("json")
-            .map_err(|e| format!("Failed to import json: {e}"))?;
-        let json_str = json_mod
-            .call_method1("dumps", (info,))
-            .map_err(|e| format!("Failed to serialize: {e}"))?
-            .extract::<String>()
-            .map_err(|e| format!("Failed to extract string: {e}"))?;
-
-        Ok(json_str)
-    })
+                Err(e) => {
+                    error!("Python environment check failed: {}", e);
+                    st.ytdlp_version = None;
+                    st.error = Some(e);
+                }
+            }
+            st.checked_at = Some(now_utc());
+        }
+    });
+}
+
+/// Feed systemd's watchdog every `interval` while the process passes its
+/// self-check: the embedded interpreter still imports yt_dlp (the GIL isn't
+/// stuck) and, with WATCHDOG_REQUIRE_REDIS, Redis answers PING. A wedged
+/// process stops feeding it and systemd restarts it after WatchdogSec=.
+fn spawn_watchdog_task(notifier: Arc<systemd::Notifier>, interval: std::time::Duration, state: AppState, require_redis: bool) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        // A check stuck on the GIL is waited on again next round instead of
+        // piling up blocking threads
+        let mut python_check: Option<tokio::task::JoinHandle<Result<String, String>>> = None;
+        let mut failing = false;
+        loop {
+            ticks.tick().await;
+            let python = async {
+                if !state.extractor.is_embedded() {
+                    return None;
+                }
+                let check = python_check.get_or_insert_with(|| {
+                    let extractor = state.extractor.clone();
+                    tokio::spawn(async move { extractor.check().await })
+                });
+                match tokio::time::timeout(interval / 2, check).await {
+                    Ok(joined) => {
+                        python_check = None;
+                        Some(joined.unwrap_or_else(|e| Err(format!("Task join error: {e}"))))
+                    }
+                    Err(_) => Some(Err("yt_dlp import check did not return, the GIL may be stuck".into())),
+                }
+            };
+            #[cfg(feature = "redis")]
+            let redis = async { Some(tokio::time::timeout(interval / 2, redis_ping(&state.redis)).await.unwrap_or(false)) };
+            // Standalone builds have no Redis
+            #[cfg(not(feature = "redis"))]
+            let redis = async { None };
+            let (python, redis) = tokio::join!(python, redis);
+
+            match systemd::self_check(python, redis, require_redis) {
+                Ok(status) => {
+                    if failing {
+                        info!("✅ Self-check passes again, feeding the systemd watchdog");
+                    }
+                    failing = false;
+                    notifier.notify(&format!("WATCHDOG=1\nSTATUS={status}"));
+                }
+                Err(problem) => {
+                    if !failing {
+                        error!("Self-check failed, no longer feeding the systemd watchdog: {}", problem);
+                    }
+                    failing = true;
+                    notifier.notify(&format!("STATUS=Self-check failed: {problem}"));
+                }
+            }
+        }
+    });
 }
 
 // ============= Format Parsing =============
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FormatKind {
+    Image,
+    Audio,
+    /// HTTP file with both audio and video
+    Progressive,
+    /// HLS video stream
+    VideoOnly,
+}
+
+impl FormatKind {
+    /// DISABLED_FEATURES name gating this kind of format
+    fn feature(self) -> &'static str {
+        match self {
+            Self::Image => "images",
+            Self::Audio => "audio",
+            Self::Progressive | Self::VideoOnly => "video",
+        }
+    }
+}
+
+/// How parse_formats() treats a yt-dlp format; `None` for formats it skips.
+fn classify_format(fmt: &serde_json::Value) -> Option<FormatKind> {
+    let format_id = fmt["format_id"].as_str().unwrap_or("");
+    let vcodec = fmt["vcodec"].as_str().unwrap_or("none").to_lowercase();
+    let height = fmt["height"].as_i64().unwrap_or(0);
+    let url = fmt["url"].as_str().unwrap_or("");
+    let resolution = fmt["resolution"].as_str().unwrap_or("");
+    let video_ext = fmt["video_ext"].as_str().unwrap_or("").to_lowercase();
+    let protocol = fmt["protocol"].as_str().unwrap_or("");
+
+    if url.is_empty() {
+        return None;
+    }
+
+    let is_http = protocol == "https" || (url.starts_with("http") && !url.contains(".m3u8"));
+    let is_hls = url.to_lowercase().contains(".m3u8")
+        || protocol == "m3u8"
+        || protocol == "m3u8_native";
+
+    // TikTok photo posts: `image-N` formats carry no video_ext
+    let is_image_ext = matches!(video_ext.as_str(), "jpg" | "jpeg" | "png" | "webp" | "gif")
+        || slideshow::image_index(format_id).is_some();
+    if is_image_ext && is_http {
+        Some(FormatKind::Image)
+    } else if vcodec == "none"
+        && (format_id.to_lowercase().contains("audio") || resolution == "audio only")
+    {
+        Some(FormatKind::Audio)
+    } else if is_http && height > 0 {
+        Some(FormatKind::Progressive)
+    } else if is_hls && vcodec != "none" && height > 0 {
+        Some(FormatKind::VideoOnly)
+    } else {
+        None
+    }
+}
+
+/// Drop formats whose feature is disabled for `platform`, from the result and
+/// every playlist entry (entries left without formats are dropped too), before
+/// anything is parsed or stored in a session. Returns how many formats were removed.
+fn strip_disabled_formats(info: &mut serde_json::Value, features: &FeatureFlags, platform: &str) -> usize {
+    let strip = |item: &mut serde_json::Value| -> usize {
+        match item.get_mut("formats").and_then(|f| f.as_array_mut()) {
+            Some(arr) => {
+                let before = arr.len();
+                arr.retain(|f| classify_format(f).is_none_or(|k| features.enabled(platform, k.feature())));
+                before - arr.len()
+            }
+            None => 0,
+        }
+    };
+    let mut removed = strip(info);
+    if let Some(entries) = info.get_mut("entries").and_then(|e| e.as_array_mut()) {
+        entries.retain_mut(|entry| {
+            let n = strip(entry);
+            removed += n;
+            n == 0 || entry["formats"].as_array().is_some_and(|f| !f.is_empty())
+        });
+    }
+    removed
+}
+
 fn parse_formats(
     formats: &[serde_json::Value],
+    filter: &FormatFilter,
 ) -> (Vec<VideoFormat>, Vec<VideoFormat>, Vec<VideoFormat>) {
     let mut video_formats = Vec::new();
     let mut audio_formats = Vec::new();
@@ -210,43 +820,29 @@ fn parse_formats(
 
     let mut seen_video: std::collections::HashSet<String> = std::collections::HashSet::new();
     let mut seen_audio: std::collections::HashSet<String> = std::collections::HashSet::new();
-    let mut seen_progressive: std::collections::HashSet<i64> = std::collections::HashSet::new();
+    let mut seen_progressive: std::collections::HashSet<(i64, Option<String>)> = std::collections::HashSet::new();
     let mut seen_image: std::collections::HashSet<String> = std::collections::HashSet::new();
 
     let audio_re = regex_lite::Regex::new(r"audio-(\d+)").unwrap();
 
     for fmt in formats {
+        let Some(kind) = classify_format(fmt) else {
+            continue;
+        };
         let format_id = fmt["format_id"].as_str().unwrap_or("");
-        let vcodec = fmt["vcodec"].as_str().unwrap_or("none").to_lowercase();
-        let acodec = fmt["acodec"].as_str().unwrap_or("none").to_lowercase();
         let height = fmt["height"].as_i64().unwrap_or(0);
+        if !filter.allows(kind, height) {
+            continue;
+        }
         let width = fmt["width"].as_i64().unwrap_or(0);
         let url = fmt["url"].as_str().unwrap_or("");
         let resolution = fmt["resolution"].as_str().unwrap_or("");
-        let video_ext = fmt["video_ext"].as_str().unwrap_or("").to_lowercase();
-        let protocol = fmt["protocol"].as_str().unwrap_or("");
-
-        if url.is_empty() {
-            continue;
-        }
-
-        let is_http = protocol == "https" || (url.starts_with("http") && !url.contains(".m3u8"));
-        let is_hls = url.to_lowercase().contains(".m3u8")
-            || protocol == "m3u8"
-            || protocol == "m3u8_native";
-
-        let is_image = matches!(video_ext.as_str(), "jpg" | "jpeg" | "png" | "webp" | "gif")
-            && is_http;
-        let is_audio =
-            vcodec == "none" && (format_id.to_lowercase().contains("audio") || resolution == "audio only");
-        let is_combined = is_http && height > 0 && !is_image;
-        let is_video_only = is_hls && vcodec != "none" && height > 0;
 
         let size_bytes = fmt["filesize"]
             .as_i64()
             .or_else(|| fmt["filesize_approx"].as_i64());
 
-        if is_image {
+        if kind == FormatKind::Image {
             let res_str = if width > 0 && height > 0 {
                 format!("{width}x{height}")
             } else {
@@ -268,8 +864,9 @@ fn parse_formats(
                 url: url.to_string(),
                 size_bytes,
                 format_id: format_id.to_string(),
+                ..VideoFormat::details(fmt)
             });
-        } else if is_audio {
+        } else if kind == FormatKind::Audio {
             let mut abr = fmt["abr"].as_f64().or_else(|| fmt["tbr"].as_f64()).unwrap_or(0.0);
             if abr == 0.0 {
                 if let Some(caps) = audio_re.captures(&format_id.to_lowercase()) {
@@ -293,12 +890,16 @@ fn parse_formats(
                 url: url.to_string(),
                 size_bytes,
                 format_id: format_id.to_string(),
+                ..VideoFormat::details(fmt)
             });
-        } else if is_combined {
-            if seen_progressive.contains(&height) {
+        } else if kind == FormatKind::Progressive {
+            // One per height and codec, so H.264 isn't hidden behind H.265
+            let details = VideoFormat::details(fmt);
+            let key = (height, details.vcodec.clone());
+            if seen_progressive.contains(&key) {
                 continue;
             }
-            seen_progressive.insert(height);
+            seen_progressive.insert(key);
             let res_str = if width > 0 && height > 0 {
                 format!("{width}x{height}")
             } else {
@@ -310,9 +911,11 @@ fn parse_formats(
                 url: url.to_string(),
                 size_bytes,
                 format_id: format_id.to_string(),
+                ..details
             });
-        } else if is_video_only {
-            let key = format!("{height}_hls");
+        } else {
+            let details = VideoFormat::details(fmt);
+            let key = format!("{height}_hls_{}", details.vcodec.as_deref().unwrap_or(""));
             if seen_video.contains(&key) {
                 continue;
             }
@@ -328,6 +931,7 @@ fn parse_formats(
                 url: url.to_string(),
                 size_bytes,
                 format_id: format_id.to_string(),
+                ..details
             });
         }
     }
@@ -339,16 +943,19 @@ fn parse_formats(
             .and_then(|s| s.parse().ok())
             .unwrap_or(0)
     };
-    progressive_formats.sort_by(|a, b| get_height(b).cmp(&get_height(a)));
-    video_formats.sort_by(|a, b| get_height(b).cmp(&get_height(a)));
+    progressive_formats.sort_by_key(|f| std::cmp::Reverse(get_height(f)));
+    video_formats.sort_by_key(|f| std::cmp::Reverse(get_height(f)));
 
-    let mut all_videos = progressive_formats;
-    all_videos.extend(video_formats);
+    let all_videos = if filter.prefer == Some(Prefer::Hls) {
+        video_formats.extend(progressive_formats);
+        video_formats
+    } else {
+        progressive_formats.extend(video_formats);
+        progressive_formats
+    };
 
-    audio_formats.sort_by(|a, b| {
-        let ba = a.quality.replace("kbps", "").parse::<i64>().unwrap_or(0);
-        let bb = b.quality.replace("kbps", "").parse::<i64>().unwrap_or(0);
-        bb.cmp(&ba)
+    audio_formats.sort_by_key(|f| {
+        std::cmp::Reverse(f.quality.replace("kbps", "").parse::<i64>().unwrap_or(0))
     });
 
     let priority = |q: &str| -> i32 {
@@ -361,50 +968,458 @@ fn parse_formats(
             _ => 5,
         }
     };
-    image_formats.sort_by(|a, b| priority(&a.quality).cmp(&priority(&b.quality)));
+    image_formats.sort_by_key(|f| priority(&f.quality));
 
     (all_videos, audio_formats, image_formats)
 }
 
+/// What yt-dlp's format selector picked: `requested_formats` when formats
+/// are to be merged, otherwise the one format, whose fields yt-dlp copies
+/// into the info itself.
+fn selected_formats(info: &serde_json::Value) -> Vec<VideoFormat> {
+    let picked: Vec<&serde_json::Value> = match info["requested_formats"].as_array() {
+        Some(list) => list.iter().collect(),
+        None if info["format_id"].is_string() && info["url"].is_string() => vec![info],
+        None => vec![],
+    };
+    picked
+        .into_iter()
+        .map(|fmt| {
+            let details = VideoFormat::details(fmt);
+            let height = fmt["height"].as_i64().unwrap_or(0);
+            let width = fmt["width"].as_i64().unwrap_or(0);
+            let abr = fmt["abr"].as_f64().unwrap_or(0.0);
+            let (quality, resolution) = if details.vcodec.is_none() && height == 0 {
+                let quality = if abr > 0.0 { format!("{}kbps", abr as i64) } else { "audio".into() };
+                (quality, "audio only".to_string())
+            } else if width > 0 && height > 0 {
+                (format!("{height}p"), format!("{width}x{height}"))
+            } else {
+                (format!("{height}p"), fmt["resolution"].as_str().unwrap_or("").to_string())
+            };
+            VideoFormat {
+                quality,
+                resolution,
+                url: fmt["url"].as_str().unwrap_or("").to_string(),
+                size_bytes: fmt["filesize"].as_i64().or_else(|| fmt["filesize_approx"].as_i64()),
+                format_id: fmt["format_id"].as_str().unwrap_or("").to_string(),
+                ..details
+            }
+        })
+        .filter(|f| !f.url.is_empty() && !f.format_id.is_empty())
+        .collect()
+}
+
+// ============= Session Encryption =============
+
+/// ChaCha20-Poly1305 cipher for session data stored in Redis.
+/// The key is SHA-256(ENCRYPTION_KEY); sealed values are `nonce || ciphertext`.
+#[derive(Clone)]
+struct SessionCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl SessionCipher {
+    const NONCE_LEN: usize = 12;
+
+    fn new(secret: &str) -> Self {
+        let key = Sha256::digest(secret.as_bytes());
+        Self {
+            cipher: ChaCha20Poly1305::new(&key),
+        }
+    }
+
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|e| format!("Session encryption failed: {e}"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        if sealed.len() < Self::NONCE_LEN {
+            return Err("Sealed session is too short".into());
+        }
+        let (nonce, ciphertext) = sealed.split_at(Self::NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| format!("Session decryption failed: {e}"))
+    }
+}
+
 // ============= Response Builder =============
 
 #[derive(Serialize, Deserialize, Clone)]
 struct FormatInfo {
     url: String,
     http_headers: HashMap<String, String>,
+    /// Cookie header yt-dlp's cookiejar held for `url` after extraction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cookies: Option<String>,
     quality: String,
     resolution: String,
     content_type: String,
+    /// VideoFormat::vcodec; None for audio, images and older sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vcodec: Option<String>,
+    /// yt-dlp's filesize (or filesize_approx), checked against MAX_DOWNLOAD_BYTES
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size_bytes: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 struct SessionData {
     video_id: String,
+    /// Links /stream events back to the /download request
+    #[serde(default)]
+    job_id: String,
+    #[serde(default)]
+    platform: String,
     cookies: Option<String>,
     formats: HashMap<String, FormatInfo>,  // format_id -> FormatInfo
+    /// A photo post that GET /slideshow may turn into a video
+    #[serde(default)]
+    slideshow: bool,
+    /// What /stream?chapter= cuts to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    chapters: Vec<Chapter>,
+    /// What /stream?burn_subs= burns in
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    subtitles: Vec<SubtitleSource>,
+    /// What format=best / best_audio resolve to: the first format listed in
+    /// the response, after the request's FormatFilter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    best_video: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    best_audio: Option<String>,
+    /// Post URL the session was extracted from; resume tokens re-extract it
+    #[serde(default)]
+    source_url: String,
+    /// Length of the post, where /preview?start= is clamped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration_secs: Option<f64>,
+}
+
+impl SessionData {
+    /// Every URL /stream and the FFmpeg endpoints may fetch for this session.
+    fn upstream_urls(&self) -> impl Iterator<Item = &str> {
+        let formats = self.formats.values().map(|f| f.url.as_str());
+        formats.chain(self.subtitles.iter().map(|s| s.url.as_str()))
+    }
+
+    /// The format /stream?format= names — "best", "best_audio", "best_image"
+    /// or a format id — with its id.
+    fn resolve_format(&self, requested: &str) -> Option<(String, FormatInfo)> {
+        let by_id = |id: &String| self.formats.get(id).map(|f| (id.clone(), f.clone()));
+        let first = |pred: &dyn Fn(&FormatInfo) -> bool| {
+            self.formats.iter().find(|(_, f)| pred(f)).map(|(id, f)| (id.clone(), f.clone()))
+        };
+        match requested {
+            // Sessions from before best_video: find first video format
+            "best" => self.best_video.as_ref().and_then(by_id)
+                .or_else(|| first(&|f| !f.resolution.is_empty() && f.resolution != "audio only")),
+            "best_audio" => self.best_audio.as_ref().and_then(by_id)
+                .or_else(|| first(&|f| f.resolution == "audio only")),
+            "best_image" => first(&|f| f.content_type.starts_with("image/")),
+            specific_id => by_id(&specific_id.to_string()),
+        }
+    }
+
+    /// A video format of the same post (or playlist entry) as `id`, at most
+    /// `max_height` tall — the tallest such, else the shortest there is.
+    /// None when `id` isn't video or nothing shorter is offered.
+    fn downgrade(&self, id: &str, max_height: u32) -> Option<(String, FormatInfo)> {
+        let (height, siblings) = self.video_siblings(id)?;
+        let (picked, key, format) = tallest_up_to(&siblings, max_height)?;
+        (picked < height).then(|| (key.clone(), format.clone()))
+    }
+
+    /// An H.264 video format of the same post (or playlist entry) as `id`,
+    /// for /stream?compat=h264: the tallest no taller than `id`, else the
+    /// shortest. None when `id` isn't video, is H.264 already, or there's no
+    /// such format.
+    fn h264_alternative(&self, id: &str) -> Option<(String, FormatInfo)> {
+        if self.formats.get(id)?.vcodec.as_deref() == Some("h264") {
+            return None;
+        }
+        let (height, siblings) = self.video_siblings(id)?;
+        let h264: Vec<_> = siblings.into_iter().filter(|(_, _, f)| f.vcodec.as_deref() == Some("h264")).collect();
+        let (_, key, format) = tallest_up_to(&h264, height)?;
+        Some((key.clone(), format.clone()))
+    }
+
+    /// Subtitles for `lang`, matched without case, else by its primary
+    /// subtag ("en" finds "en-US").
+    fn subtitle(&self, lang: &str) -> Option<&SubtitleSource> {
+        let primary = |l: &str| l.split(['-', '_']).next().unwrap_or("").to_lowercase();
+        self.subtitles
+            .iter()
+            .find(|s| s.lang.eq_ignore_ascii_case(lang))
+            .or_else(|| self.subtitles.iter().find(|s| primary(&s.lang) == primary(lang)))
+    }
+
+    /// Height of video format `id` and the video formats (with their
+    /// heights) of the same post or entry and delivery, `id` included.
+    fn video_siblings(&self, id: &str) -> Option<(u32, Vec<Sibling<'_>>)> {
+        let video_height = |f: &FormatInfo| {
+            budget::video_height(&f.quality).filter(|_| f.resolution != "audio only" && !f.content_type.starts_with("image/"))
+        };
+        let requested = self.formats.get(id)?;
+        let height = video_height(requested)?;
+        // Same delivery as asked for: "(progressive)" stays progressive, "(hls)" HLS
+        let delivery = |f: &FormatInfo| f.quality.trim_start_matches(|c: char| c.is_ascii_digit()).to_string();
+        // Entry formats are keyed "{entry id}_{format id}" (numeric ids)
+        fn entry(key: &str) -> Option<&str> {
+            key.split_once('_')
+                .map(|(prefix, _)| prefix)
+                .filter(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()))
+        }
+        let siblings = self
+            .formats
+            .iter()
+            .filter(|(k, f)| entry(k) == entry(id) && delivery(f) == delivery(requested))
+            .filter_map(|(k, f)| Some((video_height(f)?, k, f)))
+            .collect();
+        Some((height, siblings))
+    }
+}
+
+/// A video format of a session: height, format id, format.
+type Sibling<'a> = (u32, &'a String, &'a FormatInfo);
+
+/// The tallest of `formats` at most `max_height` tall, else the shortest.
+fn tallest_up_to<'a>(formats: &[Sibling<'a>], max_height: u32) -> Option<Sibling<'a>> {
+    formats
+        .iter()
+        .filter(|(h, _, _)| *h <= max_height)
+        .max_by_key(|(h, k, _)| (*h, *k))
+        .or_else(|| formats.iter().min_by_key(|(h, k, _)| (*h, *k)))
+        .copied()
+}
+
+/// 503 for a non-priority stream while the egress budget is past REJECT_AT.
+fn budget_exhausted(budget: &budget::EgressBudget) -> Response {
+    let mut resp = error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Bandwidth budget exhausted, please retry later",
+        "EGRESS_BUDGET_EXHAUSTED",
+    );
+    resp.headers_mut().insert("Retry-After", budget.retry_after_secs().into());
+    resp
+}
+
+/// 413 for a file larger than MAX_DOWNLOAD_BYTES, with both sizes.
+fn file_too_large(size: u64, limit: u64) -> Response {
+    info!("Refusing {} byte file (MAX_DOWNLOAD_BYTES {})", size, limit);
+    let body = serde_json::json!({
+        "success": false,
+        "message": format!("File is too large ({size} bytes)"),
+        "error_code": "FILE_TOO_LARGE",
+        "size_bytes": size,
+        "max_bytes": limit,
+    });
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(body)).into_response()
+}
+
+/// Duration of the longest post in `info` (the post itself, or a playlist
+/// entry) if it runs past `limit` seconds. Live streams without a duration
+/// pass.
+fn duration_exceeding(info: &serde_json::Value, limit: u64) -> Option<f64> {
+    let entries = info["entries"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    std::iter::once(info)
+        .chain(entries)
+        .filter_map(|post| post["duration"].as_f64())
+        .fold(None, |longest: Option<f64>, d| Some(longest.map_or(d, |l| l.max(d))))
+        .filter(|longest| *longest > limit as f64)
+}
+
+/// 502 for an info dict that lacks fields the response is built from,
+/// counting each field in the metrics.
+fn schema_changed(metrics: &Metrics, platform: &str, violations: &[String]) -> Response {
+    error!("yt-dlp output for {} changed shape, missing or mistyped: {}", platform, violations.join(", "));
+    for field in violations {
+        metrics.record_schema_violation(platform, field);
+    }
+    let mut body = serde_json::to_value(ErrorResponse {
+        success: false,
+        message: "Unexpected extraction result, the extractor may be outdated".into(),
+        error_code: Some("SCHEMA_CHANGED".into()),
+    })
+    .unwrap();
+    body["retryable"] = false.into();
+    (StatusCode::BAD_GATEWAY, Json(body)).into_response()
+}
+
+/// 422 for media longer than MAX_DURATION_SECS, with both durations.
+fn duration_exceeded(duration: f64, limit: u64) -> Response {
+    info!("Refusing {:.0}s media (MAX_DURATION_SECS {})", duration, limit);
+    let seconds = duration.ceil() as u64;
+    let body = serde_json::json!({
+        "success": false,
+        "message": format!("Media is too long ({seconds} seconds)"),
+        "error_code": "DURATION_EXCEEDED",
+        "duration_seconds": seconds,
+        "max_duration_seconds": limit,
+    });
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response()
+}
+
+/// Sessions live this long, in Redis or in a stateless token.
+const SESSION_TTL_SECS: u64 = 300;
+
+/// Marks a stateless session token; never appears in a UUID session id.
+const SESSION_TOKEN_PREFIX: &str = "t.";
+
+/// Redis key of a session; the namespace lets several deployments share one
+/// Redis, while instances of the same deployment see each other's sessions.
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+fn session_key(namespace: &str, session_id: &str) -> String {
+    format!("{namespace}:{session_id}")
+}
+
+/// BASE_URL without its trailing slash (default http://localhost:8025).
+fn base_url_from(value: Option<String>) -> String {
+    value
+        .map(|v| v.trim().trim_end_matches('/').to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "http://localhost:8025".to_string())
+}
+
+/// Base for a request's links: with TRUST_PROXY_HEADERS, the scheme and host
+/// the client used (X-Forwarded-Proto/Host, first hop) with BASE_URL's path;
+/// otherwise, or when the headers are missing or malformed, BASE_URL.
+fn public_base_url(base_url: &str, headers: &HeaderMap, trusted: bool) -> String {
+    let forwarded = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+            .filter(|v| !v.is_empty())
+    };
+    let Some(host) = forwarded("x-forwarded-host").filter(|_| trusted) else {
+        return base_url.to_string();
+    };
+    let valid_host = host.len() <= 255
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'));
+    let Some((scheme, rest)) = base_url.split_once("://").filter(|_| valid_host) else {
+        return base_url.to_string();
+    };
+    let proto = forwarded("x-forwarded-proto").filter(|p| p == "http" || p == "https");
+    let path = rest.find('/').map_or("", |i| &rest[i..]);
+    format!("{}://{host}{path}", proto.as_deref().unwrap_or(scheme))
+}
+
+/// Payload of a stateless session token (degraded mode).
+#[derive(Serialize, Deserialize)]
+struct SessionToken {
+    /// Unix seconds
+    exp: i64,
+    session: SessionData,
+}
+
+/// Seal a session into a self-contained `/stream` id, used while Redis is down.
+/// It carries the same encrypted data Redis would hold, so it is longer than
+/// a session id but needs no server-side state.
+fn seal_session_token(cipher: &SessionCipher, data: &SessionData) -> Result<String, String> {
+    let token = SessionToken {
+        exp: chrono::Utc::now().timestamp() + SESSION_TTL_SECS as i64,
+        session: data.clone(),
+    };
+    let sealed = cipher.seal(&serde_json::to_vec(&token).unwrap())?;
+    Ok(format!(
+        "{}{}",
+        SESSION_TOKEN_PREFIX,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(sealed)
+    ))
+}
+
+/// `None` for a forged, corrupt or expired token.
+fn open_session_token(cipher: &SessionCipher, token: &str) -> Option<SessionData> {
+    let sealed = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(token).ok()?;
+    let json_data = cipher
+        .open(&sealed)
+        .map_err(|e| error!("Failed to open session token: {}", e))
+        .ok()?;
+    let token: SessionToken = serde_json::from_slice(&json_data).ok()?;
+    (token.exp > chrono::Utc::now().timestamp()).then_some(token.session)
+}
+
+/// Payload of a /stream resume token (X-Resume-Token). Unlike a session it
+/// holds no cookies or CDN URLs, so it can live for hours.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ResumeToken {
+    /// Unix seconds
+    exp: i64,
+    /// Session it was issued for, used while it still exists
+    session_id: String,
+    /// Post URL extracted again once the session is gone
+    url: String,
+    /// Concrete format id ("best" etc. resolved)
+    format_id: String,
+    /// First byte of the response it was issued with
+    offset: u64,
+}
+
+fn seal_resume_token(cipher: &SessionCipher, token: &ResumeToken) -> Result<String, String> {
+    let sealed = cipher.seal(&serde_json::to_vec(token).unwrap())?;
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(sealed))
+}
+
+/// `None` for a forged, corrupt or expired token.
+fn open_resume_token(cipher: &SessionCipher, token: &str) -> Option<ResumeToken> {
+    let sealed = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(token).ok()?;
+    let json_data = cipher.open(&sealed).ok()?;
+    let token: ResumeToken = serde_json::from_slice(&json_data).ok()?;
+    (token.exp > chrono::Utc::now().timestamp()).then_some(token)
+}
+
+/// First byte of a `Range: bytes=N-` / `bytes=N-M` header.
+fn range_start(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get("range")?.to_str().ok()?;
+    let (start, _) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    start.trim().parse().ok()
 }
 
+#[cfg(feature = "redis")]
 async fn store_session_in_redis(
     redis: &mut redis::aio::MultiplexedConnection,
-    session_id: &str,
+    cipher: &SessionCipher,
+    key: &str,
     data: &SessionData,
 ) -> Result<(), redis::RedisError> {
-    let json_data = serde_json::to_string(data).unwrap();
-    redis.set_ex(format!("download:{session_id}"), json_data, 300).await?;
+    let json_data = serde_json::to_vec(data).unwrap();
+    let sealed = cipher.seal(&json_data).map_err(|e| {
+        redis::RedisError::from((redis::ErrorKind::ClientError, "Session encryption failed", e))
+    })?;
+    redis.set_ex::<_, _, ()>(key, sealed, SESSION_TTL_SECS).await?;
     Ok(())
 }
 
+#[cfg(feature = "redis")]
 async fn get_session_from_redis(
     redis: &mut redis::aio::MultiplexedConnection,
-    session_id: &str,
+    cipher: &SessionCipher,
+    key: &str,
 ) -> Result<Option<SessionData>, redis::RedisError> {
-    let key = format!("download:{session_id}");
-    let data: Option<String> = redis.get(&key).await?;
+    let data: Option<Vec<u8>> = redis.get(key).await?;
     
-    if let Some(json_str) = data {
+    if let Some(sealed) = data {
         // Session will auto-expire after 5 minutes (300s), don't delete immediately
-        match serde_json::from_str(&json_str) {
-            Ok(session_data) => Ok(Some(session_data)),
+        let json_data = match cipher.open(&sealed) {
+            Ok(d) => d,
+            Err(e) => {
+                // Usually another instance sealed it with a different ENCRYPTION_KEY
+                error!("Failed to decrypt session data (ENCRYPTION_KEY differs between instances?): {}", e);
+                return Ok(None);
+            }
+        };
+        match serde_json::from_slice(&json_data) {
+            Ok(session_data) => Ok(Some(session_data)),
             Err(e) => {
                 error!("Failed to parse session data: {}", e);
                 Ok(None)
@@ -415,12 +1430,14 @@ async fn get_session_from_redis(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_response_with_session(
     info: &serde_json::Value,
     original_url: &str,
     video_fmts: &[VideoFormat],
     audio_fmts: &[VideoFormat],
     image_fmts: &[VideoFormat],
+    filter: &FormatFilter,
     session_id: &str,
     base_url: &str,
 ) -> DownloadResponse {
@@ -435,7 +1452,7 @@ fn build_response_with_session(
     if is_playlist {
         if let Some(entries_arr) = entries {
             if !entries_arr.is_empty() {
-                return build_playlist_response(info, entries_arr, &platform, original_url, video_fmts, image_fmts, session_id, base_url);
+                return build_playlist_response(info, entries_arr, &platform, original_url, video_fmts, audio_fmts, image_fmts, filter, session_id, base_url);
             }
         }
     }
@@ -469,16 +1486,16 @@ fn build_response_with_session(
         fmt
     }).collect();
 
-    let best_video = video_fmts.first().map(|f| format!("{}/stream?id={}&format=best", base_url, session_id));
-    let best_audio = audio_fmts.first().map(|f| format!("{}/stream?id={}&format=best_audio", base_url, session_id));
-    let best_image = image_fmts.first().map(|f| format!("{}/stream?id={}&format=best_image", base_url, session_id));
+    let best_video = video_fmts.first().map(|_| format!("{}/stream?id={}&format=best", base_url, session_id));
+    let best_audio = audio_fmts.first().map(|_| format!("{}/stream?id={}&format=best_audio", base_url, session_id));
+    let best_image = image_fmts.first().map(|_| format!("{}/stream?id={}&format=best_image", base_url, session_id));
 
     let thumbnail = get_best_thumbnail(info);
     let duration = info["duration"].as_f64();
-    let upload_date = info["upload_date"].as_str().unwrap_or("");
-    let created_at = parse_upload_date(upload_date);
+    let (created_at, upload_date) = parse_dates(info);
 
-    let stats = build_stats(info);
+    let stats = build_stats(info, &platform);
+    let author_avatar = avatar_link(base_url, &platform, info);
 
     let data = VideoData {
         platform,
@@ -488,23 +1505,27 @@ fn build_response_with_session(
         description: str_opt(info, "description"),
         author_name: str_opt(info, "uploader"),
         author_username: str_opt(info, "uploader_id"),
-        author_avatar: Some(String::new()),
+        author_avatar,
         thumbnail: Some(thumbnail),
         duration_seconds: duration,
         duration_formatted: format_duration(duration),
         stats,
         created_at,
+        upload_date,
         original_url: original_url.into(),
         is_playlist: false,
         playlist_count: None,
         entries: vec![],
+        music: build_music(info, best_audio.clone()),
+        chapters: build_chapters(info),
+        subtitles: build_subtitles(info).into_iter().map(|(track, _)| track).collect(),
     };
 
     DownloadResponse {
         success: true,
         message: message.into(),
         session_id: Some(session_id.to_string()),
-        expires_in: Some(300),
+        expires_in: Some(SESSION_TTL_SECS),
         data: Some(data),
         video_formats: video_fmts_masked,
         audio_formats: audio_fmts_masked,
@@ -512,17 +1533,22 @@ fn build_response_with_session(
         best_video_url: best_video,
         best_audio_url: best_audio,
         best_image_url: best_image,
+        slideshow_url: None,
+        format_selection: None,
         extracted_at: now_utc(),
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_playlist_response(
     info: &serde_json::Value,
     entries_arr: &[serde_json::Value],
     platform: &str,
     original_url: &str,
     video_fmts: &[VideoFormat],
+    audio_fmts: &[VideoFormat],
     image_fmts: &[VideoFormat],
+    filter: &FormatFilter,
     session_id: &str,
     base_url: &str,
 ) -> DownloadResponse {
@@ -531,7 +1557,7 @@ fn build_playlist_response(
     for (idx, entry) in entries_arr.iter().enumerate() {
         let entry_id = entry["id"].as_str().unwrap_or("");
         let fmts = entry["formats"].as_array().map(|v| v.as_slice()).unwrap_or(&[]);
-        let (vf, _af, imf) = parse_formats(fmts);
+        let (vf, af, imf) = parse_formats(fmts, filter);
 
         // Helper function to create prefixed format_id for entries
         let prefixed_format_id = |format_id: &str| -> String {
@@ -542,6 +1568,14 @@ fn build_playlist_response(
             }
         };
 
+        // Every entry carries its audio; audio-only entries also list it as `formats`
+        let audio_formats: Vec<VideoFormat> = af.iter().map(|f| {
+            let mut fmt = f.clone();
+            fmt.url = format!("{}/stream?id={}&format={}", base_url, session_id, prefixed_format_id(&f.format_id));
+            fmt
+        }).collect();
+        let best_audio_url = audio_formats.first().map(|f| f.url.clone());
+
         let (media_type, best_url, formats) = if !imf.is_empty() && vf.is_empty() {
             ("photo", imf.first().map(|f| format!("{}/stream?id={}&format={}", base_url, session_id, prefixed_format_id(&f.format_id))), 
              imf.iter().map(|f| {
@@ -556,6 +1590,8 @@ fn build_playlist_response(
                  fmt.url = format!("{}/stream?id={}&format={}", base_url, session_id, prefixed_format_id(&f.format_id));
                  fmt
              }).collect())
+        } else if !audio_formats.is_empty() {
+            ("audio", best_audio_url.clone(), audio_formats.clone())
         } else {
             ("unknown", None, vec![])
         };
@@ -580,6 +1616,8 @@ fn build_playlist_response(
             media_type: media_type.into(),
             formats,
             best_url,
+            audio_formats,
+            best_audio_url,
         });
     }
 
@@ -593,7 +1631,7 @@ fn build_playlist_response(
                 parsed_entries.len()
             ),
         )
-    } else if content_types.contains("photo") && content_types.contains("video") {
+    } else if ["photo", "video", "audio"].iter().filter(|t| content_types.contains(*t)).count() > 1 {
         (
             "mixed",
             format!(
@@ -620,19 +1658,26 @@ fn build_playlist_response(
         fmt
     }).collect();
 
+    let audio_fmts_masked: Vec<VideoFormat> = audio_fmts.iter().map(|f| {
+        let mut fmt = f.clone();
+        fmt.url = format!("{}/stream?id={}&format={}", base_url, session_id, f.format_id);
+        fmt
+    }).collect();
+
     let image_fmts_masked: Vec<VideoFormat> = image_fmts.iter().map(|f| {
         let mut fmt = f.clone();
         fmt.url = format!("{}/stream?id={}&format={}", base_url, session_id, f.format_id);
         fmt
     }).collect();
 
-    let best_video = video_fmts_masked.first().map(|f| format!("{}/stream?id={}&format=best", base_url, session_id));
+    let best_video = video_fmts_masked.first().map(|_| format!("{}/stream?id={}&format=best", base_url, session_id));
+    let best_audio = audio_fmts_masked.first().map(|_| format!("{}/stream?id={}&format=best_audio", base_url, session_id));
     let best_image = image_fmts_masked
         .first()
-        .map(|f| format!("{}/stream?id={}&format=best_image", base_url, session_id));
+        .map(|_| format!("{}/stream?id={}&format=best_image", base_url, session_id));
 
-    let created_at = parse_upload_date(info["upload_date"].as_str().unwrap_or(""));
-    let stats = build_stats(info);
+    let (created_at, upload_date) = parse_dates(info);
+    let stats = build_stats(info, platform);
 
     let data = VideoData {
         platform: platform.into(),
@@ -642,34 +1687,47 @@ fn build_playlist_response(
         description: str_opt(info, "description"),
         author_name: str_opt(info, "uploader"),
         author_username: str_opt(info, "uploader_id"),
-        author_avatar: Some(String::new()),
+        author_avatar: avatar_link(base_url, platform, info),
         thumbnail: first.and_then(|f| f.thumbnail.clone()),
         duration_seconds: None,
         duration_formatted: None,
         stats,
         created_at,
+        upload_date,
         original_url: original_url.into(),
         is_playlist: true,
         playlist_count: Some(parsed_entries.len()),
         entries: parsed_entries,
+        music: build_music(info, None),
+        chapters: vec![],
+        subtitles: vec![],
     };
 
     DownloadResponse {
         success: true,
         message,
         session_id: Some(session_id.to_string()),
-        expires_in: Some(300),
+        expires_in: Some(SESSION_TTL_SECS),
         data: Some(data),
         video_formats: video_fmts_masked,
-        audio_formats: vec![],
+        audio_formats: audio_fmts_masked,
         image_formats: image_fmts_masked,
         best_video_url: best_video,
-        best_audio_url: None,
+        best_audio_url: best_audio,
         best_image_url: best_image,
+        slideshow_url: None,
+        format_selection: None,
         extracted_at: now_utc(),
     }
 }
 
+/// Stable /avatar link for the uploader; None when the platform's profiles
+/// can't be looked up.
+fn avatar_link(base_url: &str, platform: &str, info: &serde_json::Value) -> Option<String> {
+    let user = avatar::handle_for(platform, info)?;
+    Some(format!("{}/avatar?platform={}&user={}", base_url, platform, user))
+}
+
 fn str_opt(v: &serde_json::Value, key: &str) -> Option<String> {
     v[key].as_str().map(|s| s.to_string())
 }
@@ -689,58 +1747,249 @@ fn get_best_thumbnail(info: &serde_json::Value) -> String {
     info["thumbnail"].as_str().unwrap_or("").to_string()
 }
 
-fn parse_upload_date(date: &str) -> Option<String> {
-    if date.len() == 8 {
-        Some(format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]))
-    } else {
-        None
+/// (created_at, upload_date) from yt-dlp's `timestamp`, else
+/// `release_timestamp`, else the YYYYMMDD `upload_date`/`release_date` at
+/// midnight UTC. Many X posts only carry the timestamp.
+fn parse_dates(info: &serde_json::Value) -> (Option<String>, Option<String>) {
+    let from_timestamp = ["timestamp", "release_timestamp"].iter().find_map(|f| {
+        let secs = info[*f].as_f64()?;
+        chrono::DateTime::from_timestamp(secs.trunc() as i64, 0)
+    });
+    let from_date = || {
+        ["upload_date", "release_date"].iter().find_map(|f| {
+            let date = chrono::NaiveDate::parse_from_str(info[*f].as_str()?, "%Y%m%d").ok()?;
+            Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+        })
+    };
+    let Some(at) = from_timestamp.or_else(from_date) else {
+        return (None, None);
+    };
+    (
+        Some(at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+        Some(at.format("%Y-%m-%d").to_string()),
+    )
+}
+
+fn build_stats(info: &serde_json::Value, platform: &str) -> Stats {
+    // First of the yt-dlp fields that is set; counts sometimes come as floats
+    let count = |fields: &[&str]| {
+        fields
+            .iter()
+            .find_map(|f| info[*f].as_i64().or_else(|| info[*f].as_f64().map(|v| v as i64)))
+    };
+    let (bookmarks, quotes): (&[&str], &[&str]) = match platform {
+        "tiktok" => (&["save_count", "collect_count"], &[]),
+        "x" => (&["bookmark_count"], &["quote_count"]),
+        _ => (&["save_count", "bookmark_count"], &["quote_count"]),
+    };
+    Stats {
+        views: count(&["view_count"]),
+        likes: count(&["like_count"]),
+        comments: count(&["comment_count"]),
+        shares: count(&["repost_count"]),
+        bookmarks: count(bookmarks),
+        quotes: count(quotes),
+        followers: count(&["channel_follower_count", "uploader_follower_count"]),
     }
 }
 
-fn build_stats(info: &serde_json::Value) -> serde_json::Value {
-    let mut map = serde_json::Map::new();
-    for (key, field) in [
-        ("views", "view_count"),
-        ("likes", "like_count"),
-        ("comments", "comment_count"),
-        ("shares", "repost_count"),
-    ] {
-        if let Some(v) = info[field].as_i64() {
-            map.insert(key.into(), serde_json::Value::Number(v.into()));
-        }
+/// yt-dlp's `chapters` (start_time, end_time, title), dropping any without
+/// a positive length.
+fn build_chapters(info: &serde_json::Value) -> Vec<Chapter> {
+    info["chapters"]
+        .as_array()
+        .map(|list| {
+            list.iter()
+                .filter_map(|c| {
+                    let (start, end) = (c["start_time"].as_f64()?, c["end_time"].as_f64()?);
+                    (end > start).then(|| Chapter {
+                        start,
+                        end,
+                        title: c["title"].as_str().filter(|t| !t.is_empty()).map(String::from),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Subtitle formats FFmpeg's subtitles filter reads, preferred first.
+const BURNABLE_SUBTITLES: [&str; 4] = ["vtt", "srt", "ass", "ssa"];
+
+/// Largest subtitle file /stream?burn_subs= downloads; real ones are kilobytes.
+const MAX_SUBTITLE_BYTES: usize = 4 * 1024 * 1024;
+
+/// yt-dlp's `subtitles` (language → formats), one burnable format per
+/// language, by language. Automatic captions aren't included.
+fn build_subtitles(info: &serde_json::Value) -> Vec<(SubtitleTrack, SubtitleSource)> {
+    let Some(languages) = info["subtitles"].as_object() else {
+        return Vec::new();
+    };
+    let mut subtitles: Vec<(SubtitleTrack, SubtitleSource)> = languages
+        .iter()
+        .filter_map(|(lang, formats)| {
+            let formats = formats.as_array()?;
+            let (ext, format) = BURNABLE_SUBTITLES
+                .iter()
+                .find_map(|ext| formats.iter().find(|f| f["ext"] == *ext && f["url"].is_string()).map(|f| (*ext, f)))?;
+            let track = SubtitleTrack { lang: lang.clone(), ext: ext.into(), name: str_opt(format, "name") };
+            let source = SubtitleSource { lang: lang.clone(), ext: ext.into(), url: format["url"].as_str()?.into() };
+            Some((track, source))
+        })
+        .collect();
+    subtitles.sort_by(|(a, _), (b, _)| a.lang.cmp(&b.lang));
+    subtitles
+}
+
+/// Track info yt-dlp exposes as `track` / `artists` (TikTok). The sound's own
+/// length isn't reported, so the post's duration stands in for it.
+fn build_music(info: &serde_json::Value, audio_url: Option<String>) -> Option<MusicInfo> {
+    let title = str_opt(info, "track").filter(|t| !t.is_empty());
+    let author = info["artists"]
+        .as_array()
+        .and_then(|a| a.first())
+        .and_then(|a| a.as_str())
+        .map(String::from)
+        .or_else(|| str_opt(info, "artist"))
+        .filter(|a| !a.is_empty());
+    if title.is_none() && author.is_none() {
+        return None;
     }
-    serde_json::Value::Object(map)
+    // "original sound - user" (or credited to the uploader themselves)
+    let uploader = [str_opt(info, "uploader"), str_opt(info, "uploader_id"), str_opt(info, "channel")];
+    let original_sound = title
+        .as_deref()
+        .is_some_and(|t| t.to_lowercase().starts_with("original sound"))
+        || author.as_ref().is_some_and(|a| uploader.iter().flatten().any(|u| u == a));
+    Some(MusicInfo {
+        title,
+        author,
+        duration_seconds: info["duration"].as_f64(),
+        original_sound,
+        audio_url,
+    })
 }
 
 // ============= API Handlers =============
 
+/// Reported by `/` and logged at startup.
+const RUNTIME: &str = match (cfg!(feature = "python"), cfg!(feature = "redis")) {
+    (true, true) => "Rust + Tokio + PyO3 (yt-dlp) + Redis",
+    (true, false) => "Rust + Tokio + PyO3 (yt-dlp), standalone",
+    (false, true) => "Rust + Tokio + Redis, remote yt-dlp workers",
+    (false, false) => "Rust + Tokio, remote yt-dlp workers, standalone",
+};
+
 async fn root() -> impl IntoResponse {
     Json(serde_json::json!({
         "name": "TikTok/X Video Downloader API (Rust)",
         "version": "2.1.0",
         "endpoints": {
-            "POST /download": "Extract video/photo info - body: {\"url\": \"media_url\"}",
+            "POST /download": "Extract video/photo info - body: {\"url\": \"media_url\", \"timeout\": optional_seconds}",
             "GET /stream?id=xxx": "Stream video using session_id from /download",
-            "GET /health": "Health check"
+            "GET /waveform?id=xxx": "Audio peaks for a waveform preview",
+            "GET /probe?id=xxx": "Codec, resolution, rotation and duration read by ffprobe",
+            "GET /preview?id=xxx": "3-second muted MP4 or animated WebP for hover previews",
+            "GET /avatar?platform=tiktok&user=xxx": "Creator profile picture",
+            "GET /health": "Health check",
+            "GET /metrics": "Prometheus metrics"
         },
         "supported_platforms": ["TikTok", "X (Twitter)"],
-        "runtime": "Rust + Tokio + PyO3 (yt-dlp) + Redis"
+        "runtime": RUNTIME
     }))
 }
 
-async fn health(redis: Arc<Mutex<redis::aio::MultiplexedConnection>>) -> impl IntoResponse {
-    let mut redis_guard = redis.lock().await;
-    let redis_connected = redis::cmd("PING")
-        .query_async::<_, String>(&mut *redis_guard)
-        .await
-        .is_ok();
+/// Whether Redis answers PING; a dropped connection is reported so it's
+/// reconnected.
+#[cfg(feature = "redis")]
+async fn redis_ping(redis: &RedisConn) -> bool {
+    match redis.get().await {
+        Some(mut conn) => match redis::cmd("PING").query_async::<_, String>(&mut conn).await {
+            Ok(_) => true,
+            Err(e) => {
+                redis.report(&e).await;
+                false
+            }
+        },
+        None => false,
+    }
+}
+
+async fn health(State(state): State<AppState>) -> impl IntoResponse {
+    #[cfg(feature = "redis")]
+    let redis_connected = redis_ping(&state.redis).await;
+    // Standalone builds have no Redis to lose
+    #[cfg(not(feature = "redis"))]
+    let redis_connected = false;
 
-    Json(HealthResponse {
-        status: "healthy".into(),
-        timestamp: now_utc(),
-        version: "2.1.0".into(),
-        redis_connected,
-    })
+    let python = state.python_status.read().await.clone();
+    let (status_code, status) = if python.error.is_none() {
+        (StatusCode::OK, "healthy")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+    };
+
+    (
+        status_code,
+        Json(HealthResponse {
+            status: status.into(),
+            timestamp: now_utc(),
+            version: "2.1.0".into(),
+            redis_connected,
+            degraded: cfg!(feature = "redis") && !redis_connected,
+            python,
+            extraction_queue: state.extraction_queue.status(),
+            egress_budget: state.budget.as_ref().map(|b| b.status()),
+            draining: state.drain.is_draining(),
+        }),
+    )
+}
+
+/// Readiness for load balancers: 503 while draining, so new requests go to
+/// other instances while the running ones finish here. `in_flight` tells
+/// the orchestrator when stopping this one cuts nothing off.
+async fn drain_status(State(state): State<AppState>) -> impl IntoResponse {
+    let status = state.drain.status();
+    let code = if status.draining { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK };
+    (code, Json(status))
+}
+
+/// POST /admin/drain: fail readiness until DELETE /admin/drain.
+async fn start_draining(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    set_draining(&state, &headers, true)
+}
+
+async fn stop_draining(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    set_draining(&state, &headers, false)
+}
+
+fn set_draining(state: &AppState, headers: &HeaderMap, draining: bool) -> Response {
+    if let Some(denied) = admin_denied(state, headers) {
+        return denied;
+    }
+    Json(state.drain.set(draining, &now_utc())).into_response()
+}
+
+/// Error response for an admin request, None when it may go ahead. Admin
+/// endpoints need `Authorization: Bearer <ADMIN_TOKEN>`, and are off while
+/// ADMIN_TOKEN isn't set.
+fn admin_denied(state: &AppState, headers: &HeaderMap) -> Option<Response> {
+    let Some(expected) = &state.admin_token else {
+        return Some(error_response(StatusCode::FORBIDDEN, "Admin endpoints are disabled on this server", "ADMIN_DISABLED"));
+    };
+    if !auth::bearer(headers).is_some_and(|given| auth::constant_time_eq(given.as_bytes(), expected.as_bytes())) {
+        warn!("Rejected admin request with a missing or wrong token");
+        return Some(error_response(StatusCode::UNAUTHORIZED, "Invalid admin token", "UNAUTHORIZED"));
+    }
+    None
+}
+
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [("Content-Type", "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
 }
 
 // Helper function to extract headers from format/info
@@ -770,8 +2019,12 @@ fn extract_headers(format_data: &serde_json::Value, info: &serde_json::Value) ->
     headers
 }
 
-fn determine_content_type(resolution: &str, format_id: &str, quality: &str) -> String {
-    if resolution == "audio only" {
+/// `image`: the format is one parse_formats returned as an image (X
+/// gallery photos have ids like "orig", which say nothing).
+fn determine_content_type(resolution: &str, format_id: &str, quality: &str, image: bool) -> String {
+    if image {
+        "image/jpeg".to_string()
+    } else if resolution == "audio only" {
         "audio/mp4".to_string()
     } else if quality.contains("IMAGE") || format_id.to_lowercase().contains("thumb") {
         "image/jpeg".to_string()
@@ -780,30 +2033,52 @@ fn determine_content_type(resolution: &str, format_id: &str, quality: &str) -> S
     }
 }
 
-async fn store_formats_in_session(
-    redis: &mut redis::aio::MultiplexedConnection,
+/// Request details stored alongside a session's formats.
+struct SessionMeta<'a> {
+    /// The user's own cookies, used for the CDN requests
+    client_cookies: Option<&'a str>,
+    job_id: &'a str,
+    platform: &'a str,
+    /// Slideshows are enabled for the post's platform
+    slideshow_enabled: bool,
+    /// Applied to playlist entries' formats too
+    filter: &'a FormatFilter,
+    /// Formats picked by the request's yt-dlp format selector
+    selected: &'a [VideoFormat],
+    /// The requested post URL
+    url: &'a str,
+}
+
+fn build_session_data(
     video_fmts: &[VideoFormat],
     audio_fmts: &[VideoFormat],
     image_fmts: &[VideoFormat],
     info: &serde_json::Value,
-) -> Result<String, redis::RedisError> {
-    let session_id = Uuid::new_v4().to_string();
-    let cookies = info["cookies"].as_str().map(|s| s.to_string());
+    meta: SessionMeta<'_>,
+) -> SessionData {
+    // The user's own cookies take precedence for the CDN requests
+    let cookies = meta
+        .client_cookies
+        .map(|s| s.to_string())
+        .or_else(|| info["cookies"].as_str().map(|s| s.to_string()));
     let video_id = info["id"].as_str().unwrap_or("unknown").to_string();
 
     let mut formats_map: HashMap<String, FormatInfo> = HashMap::new();
 
     // Helper closure to process format and add to map with optional prefix
-    let mut process_format = |fmt: &VideoFormat, format_data: &serde_json::Value, source_info: &serde_json::Value, format_id_prefix: Option<&str>| {
+    let mut process_format = |fmt: &VideoFormat, image: bool, format_data: &serde_json::Value, source_info: &serde_json::Value, format_id_prefix: Option<&str>| {
         let headers = extract_headers(format_data, source_info);
-        let content_type = determine_content_type(&fmt.resolution, &fmt.format_id, &fmt.quality);
+        let content_type = determine_content_type(&fmt.resolution, &fmt.format_id, &fmt.quality, image);
 
         let format_info = FormatInfo {
             url: fmt.url.clone(),
             http_headers: headers,
+            cookies: format_data["_cookies"].as_str().map(String::from),
             quality: fmt.quality.clone(),
             resolution: fmt.resolution.clone(),
             content_type,
+            vcodec: fmt.vcodec.clone(),
+            size_bytes: fmt.size_bytes.and_then(|s| u64::try_from(s).ok()),
         };
 
         // Use prefixed format_id if provided (for entries to avoid collision)
@@ -817,13 +2092,14 @@ async fn store_formats_in_session(
     };
 
     // Process top-level formats
-    for fmt in video_fmts.iter().chain(audio_fmts.iter()).chain(image_fmts.iter()) {
+    let tagged = video_fmts.iter().chain(audio_fmts).map(|f| (f, false)).chain(image_fmts.iter().map(|f| (f, true)));
+    for (fmt, image) in tagged.chain(meta.selected.iter().map(|f| (f, false))) {
         let format_data = info["formats"]
             .as_array()
             .and_then(|arr| arr.iter().find(|f| f["format_id"].as_str() == Some(&fmt.format_id)))
             .unwrap_or(&serde_json::Value::Null);
 
-        process_format(fmt, format_data, info, None);
+        process_format(fmt, image, format_data, info, None);
     }
 
     // Process formats from entries (for playlists/galleries like Twitter/X images)
@@ -834,68 +2110,71 @@ async fn store_formats_in_session(
                 continue;
             }
 
-            if let Some(entry_formats) = entry["formats"].as_array() {
-                for fmt_data in entry_formats {
-                    let format_id = fmt_data["format_id"].as_str().unwrap_or("");
-                    if format_id.is_empty() {
-                        continue;
-                    }
-
-                    // Check if this is an image format
-                    let video_ext = fmt_data["video_ext"].as_str().unwrap_or("").to_lowercase();
-                    let protocol = fmt_data["protocol"].as_str().unwrap_or("");
-                    let url = fmt_data["url"].as_str().unwrap_or("");
-                    let is_http = protocol == "https" || (url.starts_with("http") && !url.contains(".m3u8"));
-                    let is_image = matches!(video_ext.as_str(), "jpg" | "jpeg" | "png" | "webp" | "gif") && is_http;
-
-                    if is_image {
-                        let width = fmt_data["width"].as_i64().unwrap_or(0);
-                        let height = fmt_data["height"].as_i64().unwrap_or(0);
-                        let resolution = if width > 0 && height > 0 {
-                            format!("{}x{}", width, height)
-                        } else {
-                            fmt_data["resolution"].as_str().unwrap_or("").to_string()
-                        };
-
-                        let quality = if format_id.is_empty() {
-                            "IMAGE".to_string()
-                        } else {
-                            format_id.to_uppercase()
-                        };
-
-                        let size_bytes = fmt_data["filesize"]
-                            .as_i64()
-                            .or_else(|| fmt_data["filesize_approx"].as_i64());
-
-                        let fmt = VideoFormat {
-                            quality: quality.clone(),
-                            resolution: resolution.clone(),
-                            url: url.to_string(),
-                            size_bytes,
-                            format_id: format_id.to_string(),
-                        };
-
-                        // Use entry_id as prefix to make format_id unique
-                        process_format(&fmt, fmt_data, entry, Some(entry_id));
-                    }
-                }
+            // Same formats (and ids) build_playlist_response links to
+            let entry_formats = entry["formats"].as_array().map(|v| v.as_slice()).unwrap_or(&[]);
+            let (vf, af, imf) = parse_formats(entry_formats, meta.filter);
+            for (fmt, image) in vf.iter().chain(&af).map(|f| (f, false)).chain(imf.iter().map(|f| (f, true))) {
+                let fmt_data = entry_formats
+                    .iter()
+                    .find(|f| f["format_id"].as_str() == Some(&fmt.format_id))
+                    .unwrap_or(&serde_json::Value::Null);
+                // Use entry_id as prefix to make format_id unique
+                process_format(fmt, image, fmt_data, entry, Some(entry_id));
             }
         }
     }
 
-    let session_data = SessionData {
+    let slideshow = meta.slideshow_enabled && formats_map.keys().any(|k| slideshow::image_index(k).is_some());
+    SessionData {
         video_id,
+        job_id: meta.job_id.to_string(),
+        platform: meta.platform.to_string(),
         cookies,
         formats: formats_map,
-    };
+        slideshow,
+        chapters: build_chapters(info),
+        subtitles: build_subtitles(info).into_iter().map(|(_, source)| source).collect(),
+        best_video: video_fmts.first().map(|f| f.format_id.clone()),
+        best_audio: audio_fmts.first().map(|f| f.format_id.clone()),
+        source_url: meta.url.to_string(),
+        duration_secs: info["duration"].as_f64(),
+    }
+}
 
-    store_session_in_redis(redis, &session_id, &session_data).await?;
-    Ok(session_id)
+/// Store the session in Redis and return its id, or — while Redis is down and
+/// STATELESS_FALLBACK is on, or always in standalone builds — return a
+/// stateless session token instead, if ENCRYPTION_KEY allows them.
+async fn create_session(state: &AppState, data: &SessionData) -> Result<String, String> {
+    #[cfg(feature = "redis")]
+    {
+        let mut last_error = "Redis unavailable".to_string();
+        if let Some(mut conn) = state.redis.get().await {
+            let session_id = Uuid::new_v4().to_string();
+            let key = session_key(&state.session_namespace, &session_id);
+            match store_session_in_redis(&mut conn, &state.session_cipher, &key, data).await {
+                Ok(()) => return Ok(session_id),
+                Err(e) => {
+                    error!("Failed to store session in Redis: {}", e);
+                    state.redis.report(&e).await;
+                    last_error = e.to_string();
+                }
+            }
+        }
+        if !state.stateless_fallback {
+            return Err(last_error);
+        }
+    }
+    if !state.stateless_tokens {
+        return Err("ENCRYPTION_KEY is not set, no stateless session token issued".into());
+    }
+    seal_session_token(&state.session_cipher, data)
 }
 
 async fn download(
+    State(state): State<AppState>,
+    Query(query_filter): Query<FormatFilter>,
+    headers: HeaderMap,
     Json(req): Json<DownloadRequest>,
-    redis: Arc<Mutex<redis::aio::MultiplexedConnection>>,
 ) -> impl IntoResponse {
     let url = req.url.trim().to_string();
 
@@ -908,12 +2187,11 @@ async fn download(
                 error_code: Some("HTTP_400".into()),
             })
             .unwrap()),
-        );
+        )
+            .into_response();
     }
 
-    let url_lower = url.to_lowercase();
-    let supported = ["tiktok.com", "douyin.com", "twitter.com", "x.com"];
-    if !supported.iter().any(|d| url_lower.contains(d)) {
+    if !is_supported_url(&url) {
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::to_value(ErrorResponse {
@@ -922,59 +2200,270 @@ async fn download(
                 error_code: Some("HTTP_400".into()),
             })
             .unwrap()),
-        );
+        )
+            .into_response();
+    }
+
+    let template = match state.templates.select(req.template.as_deref()) {
+        Ok(t) => t.cloned(),
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::to_value(ErrorResponse {
+                    success: false,
+                    message: e,
+                    error_code: Some("HTTP_400".into()),
+                })
+                .unwrap()),
+            )
+                .into_response();
+        }
+    };
+
+    let filter = req.filter.or(query_filter);
+    let format = req.format.as_deref().map(str::trim).filter(|f| !f.is_empty()).map(String::from);
+    if format.as_deref().is_some_and(|f| !valid_format_selector(f)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::to_value(ErrorResponse {
+                success: false,
+                message: "Invalid format selector".into(),
+                error_code: Some("HTTP_400".into()),
+            })
+            .unwrap()),
+        )
+            .into_response();
     }
 
+    let feature_platform = features::platform_for_url(&url);
+    if !state.features.platform_enabled(feature_platform) {
+        return feature_disabled(feature_platform, None);
+    }
+
+    let client_cookies = match req.cookies.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+        Some(raw) => match ClientCookies::parse(raw, &url) {
+            Ok(c) => Some(c),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::to_value(ErrorResponse {
+                        success: false,
+                        message: format!("Invalid cookies: {e}"),
+                        error_code: Some("HTTP_400".into()),
+                    })
+                    .unwrap()),
+                )
+                    .into_response();
+            }
+        },
+        None => None,
+    };
+    let cookie_file = match client_cookies.as_ref().map(|c| c.write_temp()).transpose() {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Failed to write temp cookie file: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::to_value(ErrorResponse {
+                    success: false,
+                    message: "Failed to prepare cookies".into(),
+                    error_code: Some("INTERNAL_ERROR".into()),
+                })
+                .unwrap()),
+            )
+                .into_response();
+        }
+    };
+
+    // The server's cookies.txt when the user sent none
+    let server_cookies = state
+        .cookies_path
+        .clone()
+        .filter(|p| client_cookies.is_none() && std::path::Path::new(p).exists());
     let url_clone = url.clone();
-    let result = tokio::time::timeout(
-        std::time::Duration::from_secs(45),
-        tokio::task::spawn_blocking(move || extract_with_ytdlp(&url_clone)),
-    )
+    let platform = detect_platform(&url, "");
+    let metrics = state.metrics.clone();
+    let metrics_platform = platform.clone();
+
+    let job_id = Uuid::new_v4().to_string();
+    state.events.publish(JobEvent {
+        url: Some(url.clone()),
+        ..JobEvent::new(EventKind::Created, &job_id, &platform)
+    });
+    let events = state.events.clone();
+    let extracting = JobEvent {
+        url: Some(url.clone()),
+        ..JobEvent::new(EventKind::Extracting, &job_id, &platform)
+    };
+
+    // Reject immediately when every worker is busy and the queue is full
+    let ticket = match state.extraction_queue.enter(0) {
+        Ok(t) => t,
+        Err(full) => {
+            state.metrics.record_outcome(&platform, "saturated");
+            state.events.publish(JobEvent::failed(&job_id, &platform, "HTTP_429", "Extraction queue full"));
+            let retry_after = state.extraction_queue.retry_after_secs(
+                state.metrics.mean_extraction_secs().unwrap_or(5.0),
+            );
+            info!("Extraction queue full ({} waiting), Retry-After {}s", full.queued, retry_after);
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("Retry-After", retry_after.to_string())],
+                Json(serde_json::to_value(ErrorResponse {
+                    success: false,
+                    message: "Server is busy, please retry later".into(),
+                    error_code: Some("HTTP_429".into()),
+                })
+                .unwrap()),
+            )
+                .into_response();
+        }
+    };
+
+    let timeout_secs = match req.timeout {
+        Some(secs) => secs.clamp(1, state.ytdlp_max_timeout.max(1)),
+        None => state.ytdlp_timeout,
+    };
+    let extractor = state.extractor.clone();
+    let ytdlp_format = format.clone();
+    let source_address = state.egress.source_address(&platform);
+    let result = tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), async move {
+        // Permit is released when yt-dlp returns, not when the request times out
+        let permit = ticket.wait().await;
+        events.publish(extracting);
+        // Dropping the guard deletes the temp cookie file once yt-dlp is done
+        let cookie_path = cookie_file.as_ref().map(|f| f.path()).or(server_cookies);
+        let started = std::time::Instant::now();
+        let result = extractor
+            .extract(url_clone, cookie_path, ytdlp_format, source_address, (permit, cookie_file))
+            .await;
+        metrics.observe_extraction(&metrics_platform, started.elapsed());
+        result
+    })
     .await;
 
+    let result = match result {
+        Ok(r) => r,
+        Err(_) => Err(ExtractError::Timeout(format!("Extraction exceeded {timeout_secs}s"))),
+    };
+
+    let outcome = match &result {
+        Ok(_) => "success",
+        Err(e) => e.kind(),
+    };
+    state.metrics.record_outcome(&platform, outcome);
+
+    if let (Some(recorder), Ok(json)) = (&state.recorder, &result) {
+        recorder.record_info(&url, &platform, json).await;
+    }
+
     match result {
-        Ok(Ok(Ok(json_str))) => {
+        Ok(json_str) => {
             match serde_json::from_str::<serde_json::Value>(&json_str) {
-                Ok(info) => {
-                    let base_url = env::var("BASE_URL").unwrap_or_else(|_| "http://localhost:8025".to_string());
+                Ok(mut info) => {
+                    let violations = schema::violations(&info, &platform);
+                    if !violations.is_empty() {
+                        state.events.publish(JobEvent::failed(&job_id, &platform, "SCHEMA_CHANGED", "Unexpected extraction result"));
+                        return schema_changed(&state.metrics, &platform, &violations);
+                    }
+                    if let Some((duration, limit)) =
+                        state.max_duration_secs.and_then(|limit| Some((duration_exceeding(&info, limit)?, limit)))
+                    {
+                        state.events.publish(JobEvent::failed(&job_id, &platform, "DURATION_EXCEEDED", "Media is too long"));
+                        return duration_exceeded(duration, limit);
+                    }
+                    let removed = strip_disabled_formats(&mut info, &state.features, feature_platform);
+                    let base_url = &public_base_url(&state.base_url, &headers, state.trust_proxy_headers);
                     let formats_arr = info["formats"].as_array().map(|v| v.as_slice()).unwrap_or(&[]);
-                    let (video_fmts, audio_fmts, image_fmts) = parse_formats(formats_arr);
+                    let (video_fmts, audio_fmts, image_fmts) = parse_formats(formats_arr, &filter);
+                    let has_entries = info["entries"].as_array().is_some_and(|e| !e.is_empty());
+                    if removed > 0 && video_fmts.is_empty() && audio_fmts.is_empty() && image_fmts.is_empty() && !has_entries {
+                        state.events.publish(JobEvent::failed(&job_id, &platform, "FEATURE_DISABLED", "All formats are disabled"));
+                        return feature_disabled(feature_platform, Some("requested media"));
+                    }
+                    if !filter.is_empty() && video_fmts.is_empty() && audio_fmts.is_empty() && image_fmts.is_empty() && !has_entries {
+                        state.events.publish(JobEvent::failed(&job_id, &platform, "NO_MATCHING_FORMATS", "No formats match the requested filters"));
+                        return (
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            Json(serde_json::to_value(ErrorResponse {
+                                success: false,
+                                message: "No formats match max_height/prefer/audio_only".into(),
+                                error_code: Some("NO_MATCHING_FORMATS".into()),
+                            }).unwrap()),
+                        )
+                            .into_response();
+                    }
                     
-                    // Store all formats in single Redis session
-                    let mut redis_guard = redis.lock().await;
-                    let session_id = match store_formats_in_session(&mut *redis_guard, &video_fmts, &audio_fmts, &image_fmts, &info).await {
+                    let selected = if format.is_some() { selected_formats(&info) } else { vec![] };
+
+                    // Store all formats in a single session (Redis, or a stateless token)
+                    let session_data = build_session_data(&video_fmts, &audio_fmts, &image_fmts, &info, SessionMeta {
+                        client_cookies: client_cookies.as_ref().map(|c| c.header.as_str()),
+                        job_id: &job_id,
+                        platform: &platform,
+                        slideshow_enabled: state.features.enabled(feature_platform, "slideshow"),
+                        filter: &filter,
+                        selected: &selected,
+                        url: &url,
+                    });
+                    let session_id = match create_session(&state, &session_data).await {
                         Ok(id) => id,
                         Err(e) => {
-                            error!("Failed to store session in Redis: {}", e);
+                            state.events.publish(JobEvent::failed(&job_id, &platform, "REDIS_ERROR", e));
                             return (
-                                StatusCode::INTERNAL_SERVER_ERROR,
+                                StatusCode::SERVICE_UNAVAILABLE,
                                 Json(serde_json::to_value(ErrorResponse {
                                     success: false,
                                     message: "Failed to create download session".into(),
                                     error_code: Some("REDIS_ERROR".into()),
                                 }).unwrap()),
-                            );
+                            )
+                                .into_response();
                         }
                     };
-                    drop(redis_guard);
+                    state.events.publish(JobEvent {
+                        session_id: Some(session_id.clone()),
+                        url: Some(url.clone()),
+                        video_id: info["id"].as_str().map(String::from),
+                        ..JobEvent::new(EventKind::Ready, &job_id, &platform)
+                    });
                     
-                    let response = build_response_with_session(
+                    let mut response = build_response_with_session(
                         &info, 
                         &url, 
                         &video_fmts,
                         &audio_fmts,
                         &image_fmts,
+                        &filter,
                         &session_id,
-                        &base_url
+                        base_url
                     );
+                    if session_data.slideshow {
+                        response.slideshow_url = Some(format!("{}/slideshow?id={}", base_url, session_id));
+                    }
+                    if let Some(selector) = format {
+                        response.format_selection = Some(FormatSelection {
+                            selector,
+                            format: info["format"].as_str().map(String::from),
+                            requested_formats: selected.into_iter().map(|mut f| {
+                                f.url = format!("{}/stream?id={}&format={}", base_url, session_id, f.format_id);
+                                f
+                            }).collect(),
+                        });
+                    }
+                    if let (Some(user), Some(avatar_url)) = (avatar::handle_for(&platform, &info), avatar::from_info(&info)) {
+                        state.avatars.remember(&platform, &user, avatar_url);
+                    }
                     
-                    (
-                        StatusCode::OK,
-                        Json(serde_json::to_value(response).unwrap()),
-                    )
+                    let mut body = serde_json::to_value(response).unwrap();
+                    if let Some(template) = template {
+                        body = template::apply(&template, &body);
+                    }
+                    (StatusCode::OK, Json(body)).into_response()
                 }
                 Err(e) => {
                     error!("JSON parse error: {e}");
+                    state.events.publish(JobEvent::failed(&job_id, &platform, "INTERNAL_ERROR", e.to_string()));
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         Json(serde_json::to_value(ErrorResponse {
@@ -984,120 +2473,329 @@ async fn download(
                         })
                         .unwrap()),
                     )
+                        .into_response()
                 }
             }
         }
-        Ok(Ok(Err(e))) => {
-            let (status, msg) = if e.starts_with("NOT_FOUND:") {
-                (StatusCode::NOT_FOUND, "Video not found or may be private/deleted")
-            } else if e.starts_with("FORBIDDEN:") {
-                (StatusCode::FORBIDDEN, "Access forbidden - video may be private or region-restricted")
-            } else if e.starts_with("AUTH_REQUIRED:") {
-                (StatusCode::UNAUTHORIZED, "This content requires login/authentication")
-            } else if e.starts_with("UNSUPPORTED:") {
-                (StatusCode::BAD_REQUEST, "Unsupported or invalid URL")
-            } else {
-                error!("yt-dlp error: {e}");
-                (StatusCode::INTERNAL_SERVER_ERROR, "Extraction failed")
-            };
-            (
-                status,
-                Json(serde_json::to_value(ErrorResponse {
-                    success: false,
-                    message: msg.into(),
-                    error_code: Some(format!("HTTP_{}", status.as_u16())),
-                })
-                .unwrap()),
-            )
+        Err(e) => {
+            let msg = extract_error_message(&e);
+            let status = e.status();
+            let error_code = format!("HTTP_{}", status.as_u16());
+            state.events.publish(JobEvent::failed(&job_id, &platform, &error_code, e.to_string()));
+            let mut body = serde_json::to_value(ErrorResponse {
+                success: false,
+                message: msg.into(),
+                error_code: Some(error_code),
+            })
+            .unwrap();
+            body["retryable"] = e.is_retryable().into();
+            (status, Json(body)).into_response()
+        }
+    }
+}
+
+/// Message returned to the client for a failed extraction.
+fn extract_error_message(e: &ExtractError) -> &'static str {
+    match e {
+        ExtractError::NotFound(_) => "Video not found or may be private/deleted",
+        ExtractError::Forbidden(_) => "Access forbidden - video may be private or region-restricted",
+        ExtractError::AuthRequired(_) => "This content requires login/authentication",
+        ExtractError::Unsupported(_) => "Unsupported or invalid URL",
+        ExtractError::InvalidFormat(_) => "Format selector is invalid or matches no format",
+        ExtractError::Transient(_) => "Upstream temporarily unavailable, please try again",
+        ExtractError::Timeout(_) => "Request timeout - video extraction took too long",
+        ExtractError::Failed(_) | ExtractError::Internal(_) => {
+            error!("yt-dlp error: {e}");
+            "Extraction failed"
+        }
+    }
+}
+
+/// GET /estimate?url= — predicted size, duration and FFmpeg need of what
+/// /download would return (same max_height/prefer/audio_only filters),
+/// without creating a session.
+async fn estimate(
+    State(state): State<AppState>,
+    Query(req): Query<EstimateRequest>,
+    Query(filter): Query<FormatFilter>,
+) -> Response {
+    let url = req.url.trim().to_string();
+    if url.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "URL is required", "HTTP_400");
+    }
+    if !is_supported_url(&url) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Unsupported URL. Only TikTok and X (Twitter) URLs are supported.",
+            "HTTP_400",
+        );
+    }
+    let feature_platform = features::platform_for_url(&url);
+    if !state.features.platform_enabled(feature_platform) {
+        return feature_disabled(feature_platform, None);
+    }
+
+    let mut info = match extract_info(&state, &url).await {
+        Ok(info) => info,
+        Err(resp) => return resp,
+    };
+    strip_disabled_formats(&mut info, &state.features, feature_platform);
+    let slideshow_enabled = state.features.enabled(feature_platform, "slideshow");
+    let estimate = build_estimate(&info, &url, &filter, slideshow_enabled);
+    if !filter.is_empty() && estimate.content_type == "unknown" {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "No formats match max_height/prefer/audio_only",
+            "NO_MATCHING_FORMATS",
+        );
+    }
+    (StatusCode::OK, Json(estimate)).into_response()
+}
+
+/// Extract `url` with the server's cookies and no format selector, through
+/// the extraction queue and YTDLP_TIMEOUT — for requests that don't create a
+/// /download job (estimates, resumed streams). Errors are ready to return.
+async fn extract_info(state: &AppState, url: &str) -> Result<serde_json::Value, Response> {
+    let platform = detect_platform(url, "");
+    let ticket = match state.extraction_queue.enter(0) {
+        Ok(t) => t,
+        Err(_) => {
+            state.metrics.record_outcome(&platform, "saturated");
+            let retry_after = state.extraction_queue.retry_after_secs(
+                state.metrics.mean_extraction_secs().unwrap_or(5.0),
+            );
+            let mut resp = error_response(StatusCode::TOO_MANY_REQUESTS, "Server is busy, please retry later", "HTTP_429");
+            resp.headers_mut().insert("Retry-After", retry_after.into());
+            return Err(resp);
+        }
+    };
+    let server_cookies = state.cookies_path.clone().filter(|p| std::path::Path::new(p).exists());
+    let timeout_secs = state.ytdlp_timeout;
+    let extractor = state.extractor.clone();
+    let metrics = state.metrics.clone();
+    let metrics_platform = platform.clone();
+    let extract_url = url.to_string();
+    let source_address = state.egress.source_address(&platform);
+    let result = tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), async move {
+        let permit = ticket.wait().await;
+        let started = std::time::Instant::now();
+        let result = extractor.extract(extract_url, server_cookies, None, source_address, permit).await;
+        metrics.observe_extraction(&metrics_platform, started.elapsed());
+        result
+    })
+    .await
+    .unwrap_or_else(|_| Err(ExtractError::Timeout(format!("Extraction exceeded {timeout_secs}s"))));
+    state.metrics.record_outcome(&platform, result.as_ref().map_or_else(|e| e.kind(), |_| "success"));
+    if let (Some(recorder), Ok(json)) = (&state.recorder, &result) {
+        recorder.record_info(url, &platform, json).await;
+    }
+
+    match result.map(|json| serde_json::from_str::<serde_json::Value>(&json)) {
+        Ok(Ok(info)) => {
+            let violations = schema::violations(&info, &platform);
+            if !violations.is_empty() {
+                return Err(schema_changed(&state.metrics, &platform, &violations));
+            }
+            match state.max_duration_secs.and_then(|limit| Some((duration_exceeding(&info, limit)?, limit))) {
+                Some((duration, limit)) => Err(duration_exceeded(duration, limit)),
+                None => Ok(info),
+            }
         }
         Ok(Err(e)) => {
-            error!("Task join error: {e}");
+            error!("JSON parse error: {e}");
+            Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse extraction result", "INTERNAL_ERROR"))
+        }
+        Err(e) => {
+            let status = e.status();
+            let mut body = serde_json::to_value(ErrorResponse {
+                success: false,
+                message: extract_error_message(&e).into(),
+                error_code: Some(format!("HTTP_{}", status.as_u16())),
+            })
+            .unwrap();
+            body["retryable"] = e.is_retryable().into();
+            Err((status, Json(body)).into_response())
+        }
+    }
+}
+
+/// One post's share of an estimate.
+struct ItemEstimate {
+    content_type: &'static str,
+    format: Option<VideoFormat>,
+    size_bytes: Option<i64>,
+    size_estimated: bool,
+    transcoding_reason: Option<&'static str>,
+}
+
+/// The format /stream would send for `item` and its size: the reported
+/// filesize, else its bitrate (kbps) over `duration`.
+fn estimate_item(item: &serde_json::Value, filter: &FormatFilter, slideshow_enabled: bool) -> ItemEstimate {
+    let formats = item["formats"].as_array().map(|v| v.as_slice()).unwrap_or(&[]);
+    let (video_fmts, audio_fmts, image_fmts) = parse_formats(formats, filter);
+    let (content_type, best) = if !image_fmts.is_empty() && video_fmts.is_empty() {
+        ("photo", image_fmts.first())
+    } else if !video_fmts.is_empty() {
+        ("video", video_fmts.first())
+    } else if !audio_fmts.is_empty() {
+        ("audio", audio_fmts.first())
+    } else {
+        ("unknown", None)
+    };
+    let by_bitrate = best
+        .and_then(|f| Some(f.tbr? * item["duration"].as_f64()? * 1000.0 / 8.0))
+        .map(|bytes| bytes.round() as i64);
+    let reported = best.and_then(|f| f.size_bytes);
+    let transcoding_reason = if content_type == "photo" {
+        let slideshow = image_fmts.iter().any(|f| slideshow::image_index(&f.format_id).is_some());
+        (slideshow_enabled && slideshow).then_some("slideshow")
+    } else if best.and_then(|f| f.protocol.as_deref()).is_some_and(|p| p.starts_with("m3u8")) {
+        Some("hls")
+    } else {
+        None
+    };
+    ItemEstimate {
+        content_type,
+        format: best.cloned(),
+        size_bytes: reported.or(by_bitrate),
+        size_estimated: reported.is_none() && by_bitrate.is_some(),
+        transcoding_reason,
+    }
+}
+
+fn build_estimate(info: &serde_json::Value, original_url: &str, filter: &FormatFilter, slideshow_enabled: bool) -> EstimateResponse {
+    let platform = detect_platform(original_url, info["extractor"].as_str().unwrap_or(""));
+    let entries = info["entries"].as_array().filter(|e| info["_type"].as_str() == Some("playlist") && !e.is_empty());
+
+    let (content_type, format, size_bytes, size_estimated, transcoding_reason, duration) = match entries {
+        Some(entries) => {
+            let items: Vec<ItemEstimate> = entries.iter().map(|e| estimate_item(e, filter, slideshow_enabled)).collect();
+            let known: Vec<&ItemEstimate> = items.iter().filter(|i| i.content_type != "unknown").collect();
+            let content_type = if known.is_empty() { "unknown" } else { "playlist" };
+            // A total is only given when every entry's size is known
+            let size = known.iter().map(|i| i.size_bytes).sum::<Option<i64>>();
+            let durations: Vec<f64> = entries.iter().filter_map(|e| e["duration"].as_f64()).collect();
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::to_value(ErrorResponse {
-                    success: false,
-                    message: "Internal server error".into(),
-                    error_code: Some("INTERNAL_ERROR".into()),
-                })
-                .unwrap()),
+                content_type,
+                None,
+                size,
+                size.is_some() && known.iter().any(|i| i.size_estimated),
+                known.iter().find_map(|i| i.transcoding_reason),
+                (!durations.is_empty()).then(|| durations.iter().sum()),
             )
         }
-        Err(_) => {
+        None => {
+            let item = estimate_item(info, filter, slideshow_enabled);
             (
-                StatusCode::GATEWAY_TIMEOUT,
-                Json(serde_json::to_value(ErrorResponse {
-                    success: false,
-                    message: "Request timeout - video extraction took too long".into(),
-                    error_code: Some("HTTP_504".into()),
-                })
-                .unwrap()),
+                item.content_type,
+                item.format,
+                item.size_bytes,
+                item.size_estimated,
+                item.transcoding_reason,
+                info["duration"].as_f64(),
             )
         }
+    };
+
+    EstimateResponse {
+        success: true,
+        platform,
+        video_id: info["id"].as_str().unwrap_or("").into(),
+        content_type: content_type.into(),
+        duration_seconds: duration,
+        duration_formatted: format_duration(duration),
+        format: format.map(|f| EstimateFormat {
+            format_id: f.format_id,
+            quality: f.quality,
+            resolution: f.resolution,
+            vcodec: f.vcodec,
+            acodec: f.acodec,
+            protocol: f.protocol,
+        }),
+        size_bytes,
+        size_estimated,
+        transcoding: transcoding_reason.is_some(),
+        transcoding_reason: transcoding_reason.map(String::from),
+        playlist_count: entries.map(|e| e.len()),
+    }
+}
+
+/// A yt-dlp format selector: printable ASCII, bounded in length.
+fn valid_format_selector(selector: &str) -> bool {
+    selector.len() <= 256 && selector.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+}
+
+/// 403 for a request that needs a disabled platform or feature.
+fn feature_disabled(platform: &str, feature: Option<&str>) -> Response {
+    let what = match feature {
+        Some(feature) => format!("{feature} for {platform}"),
+        None => platform.to_string(),
+    };
+    (
+        StatusCode::FORBIDDEN,
+        Json(serde_json::to_value(ErrorResponse {
+            success: false,
+            message: format!("{what} is disabled on this server"),
+            error_code: Some("FEATURE_DISABLED".into()),
+        })
+        .unwrap()),
+    )
+        .into_response()
+}
+
+/// Response body of a proxied file: `prefix`, the bytes read for sniffing,
+/// then the rest of `upstream`, cut off past `limit` bytes and counted
+/// against the egress budget. Chunks coalesce didn't merge are passed on
+/// as the CDN client read them, without copying.
+fn forward_body<S>(prefix: axum::body::Bytes, upstream: S, limit: Option<u64>, budget: Option<&Arc<budget::EgressBudget>>) -> Body
+where
+    S: futures_util::Stream<Item = Result<axum::body::Bytes, std::io::Error>> + Send + Unpin + 'static,
+{
+    let body = cdn::cap_size(futures_util::stream::iter([Ok(prefix)]).chain(upstream), limit);
+    match budget {
+        Some(budget) => Body::from_stream(budget.meter(body)),
+        None => Body::from_stream(body),
     }
 }
 
 async fn stream(
+    State(state): State<AppState>,
     Query(params): Query<StreamRequest>,
-    redis: Arc<Mutex<redis::aio::MultiplexedConnection>>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    let session_id = params.id;
-    let format_id = params.format.unwrap_or_else(|| "best".to_string());
-    
-    // Get session data from Redis
-    let session_data = {
-        let mut redis_guard = redis.lock().await;
-        match get_session_from_redis(&mut *redis_guard, &session_id).await {
-            Ok(data) => data,
-            Err(e) => {
-                error!("Redis error: {}", e);
-                None
+    let chapter = params.chapter;
+    let resume = match params.resume.as_deref() {
+        Some(token) => match state.stateless_tokens.then(|| open_resume_token(&state.session_cipher, token)).flatten() {
+            Some(t) => Some(t),
+            None => {
+                return error_response(StatusCode::GONE, "Resume token is invalid or expired", "RESUME_TOKEN_INVALID");
             }
-        }
+        },
+        None => None,
     };
-    
-    let session_data = match session_data {
-        Some(data) => data,
-        None => {
-            return (
-                StatusCode::GONE,
-                Json(serde_json::to_value(ErrorResponse {
-                    success: false,
-                    message: "Session expired or not found. Please extract again.".into(),
-                    error_code: Some("SESSION_EXPIRED".into()),
-                })
-                .unwrap()),
-            )
-                .into_response();
-        }
+    let (session_id, session_data, format_id) = match resume {
+        Some(ref token) => match resume_session(&state, token).await {
+            Ok((id, data)) => (id, data, token.format_id.clone()),
+            Err(resp) => return resp,
+        },
+        None => match load_session(&state, &params.id).await {
+            Ok(data) => (params.id, data, params.format.unwrap_or_else(|| "best".to_string())),
+            Err(resp) => return resp,
+        },
     };
-    
-    // Select format based on format_id
-    let format_info = match format_id.as_str() {
-        "best" => {
-            // Find first video format
-            session_data.formats.values()
-                .find(|f| !f.resolution.is_empty() && f.resolution != "audio only")
-                .cloned()
-        }
-        "best_audio" => {
-            // Find first audio format
-            session_data.formats.values()
-                .find(|f| f.resolution == "audio only")
-                .cloned()
-        }
-        "best_image" => {
-            // Find first image format
-            session_data.formats.values()
-                .find(|f| f.content_type.starts_with("image/"))
-                .cloned()
-        }
-        specific_id => {
-            // Look for specific format ID
-            session_data.formats.get(specific_id).cloned()
+
+    let format_info = match session_data.resolve_format(&format_id) {
+        Some(f) => Some(f),
+        None if resume.is_some() => {
+            return error_response(
+                StatusCode::GONE,
+                "The format is no longer offered for this post. Please extract again.",
+                "RESUME_FORMAT_GONE",
+            );
         }
+        None => None,
     };
-    
-    let format_info = match format_info {
+    let (mut resolved_id, mut format_info) = match format_info {
         Some(f) => f,
         None => {
             return (
@@ -1112,21 +2810,92 @@ async fn stream(
                 .into_response();
         }
     };
+    // Resumed and ranged requests continue a file, so they keep its format;
+    // a downgrade mid-file would splice two encodings together
+    let priority_token = headers.get(budget::PRIORITY_HEADER).and_then(|v| v.to_str().ok());
+    let mut downgraded_from = None;
+    if let Some(budget) = &state.budget {
+        match budget.shedding(priority_token) {
+            Shedding::Reject => return budget_exhausted(budget),
+            Shedding::Downgrade if resume.is_none() && range_start(&headers).is_none() => {
+                if let Some((id, info)) = session_data.downgrade(&resolved_id, budget.downgrade_height) {
+                    info!("Egress budget low: serving {} instead of {}", id, resolved_id);
+                    downgraded_from = Some(std::mem::replace(&mut resolved_id, id));
+                    format_info = info;
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(index) = chapter {
+        return stream_chapter(&state, &session_id, &session_data, &format_id, &format_info, index, params.disposition).await;
+    }
+    // A resume token's offset points into the original file
+    let subtitles = match params.burn_subs.as_deref().filter(|l| !l.is_empty()) {
+        Some(lang) => match session_data.subtitle(lang) {
+            Some(s) => Some(s.clone()),
+            None => {
+                return error_response(StatusCode::NOT_FOUND, &format!("Subtitles '{lang}' not found"), "SUBTITLES_NOT_FOUND");
+            }
+        },
+        None => None,
+    };
+    let wanted = Reencode {
+        upright: params.fix_rotation == Some(true),
+        h264: params.compat == Some(Compat::H264),
+        subtitles,
+    };
+    if resume.is_none() && wanted.h264 {
+        if let Some((id, info)) = session_data.h264_alternative(&resolved_id) {
+            info!("compat=h264: serving {} instead of {}", id, resolved_id);
+            resolved_id = id;
+            format_info = info;
+        }
+    }
+    // Chapter clips above are a way to get part of a file that's too large
+    if let (Some(limit), Some(size)) = (state.max_download_bytes, format_info.size_bytes) {
+        if size > limit {
+            return file_too_large(size, limit);
+        }
+    }
+    if resume.is_none() && (wanted.upright || wanted.h264 || wanted.subtitles.is_some()) {
+        let reencoded = stream_reencoded(&state, &session_id, &session_data, &format_id, &format_info, params.disposition, wanted);
+        if let Some(resp) = reencoded.await {
+            return resp;
+        }
+    }
     
     // Download using reqwest with yt-dlp headers
-    let client = match reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(300))
-        .build()
-    {
-        Ok(c) => c,
+    let policy = state.cdn.for_platform(&session_data.platform);
+    let client = state.cdn.client_for(&session_data.platform);
+    
+    // The client's Range, else the resume token's offset
+    let offset = range_start(&headers).or(resume.as_ref().map(|t| t.offset)).unwrap_or(0);
+    let mut request = source_request(client, &format_info, &session_data);
+    let range = match headers.get("range").filter(|_| range_start(&headers).is_some()).and_then(|v| v.to_str().ok()) {
+        Some(range) => Some(range.to_string()),
+        None => (offset > 0).then(|| format!("bytes={offset}-")),
+    };
+    if let Some(range) = &range {
+        request = request.header("Range", range);
+    }
+
+    // Send request
+    let response = match policy.send(request).await {
+        Ok(resp) => resp,
         Err(e) => {
-            error!("Failed to build reqwest client: {}", e);
+            error!("Failed to download from URL: {}", e);
+            state.events.publish(JobEvent {
+                session_id: Some(session_id.clone()),
+                format: Some(format_id.clone()),
+                ..JobEvent::failed(&session_data.job_id, &session_data.platform, "DOWNLOAD_ERROR", e.to_string())
+            });
             return (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
                 Json(serde_json::to_value(ErrorResponse {
                     success: false,
-                    message: "Failed to initialize download client".into(),
-                    error_code: Some("CLIENT_ERROR".into()),
+                    message: "Failed to download media from source".into(),
+                    error_code: Some("DOWNLOAD_ERROR".into()),
                 })
                 .unwrap()),
             )
@@ -1134,28 +2903,44 @@ async fn stream(
         }
     };
     
-    let mut request = client.get(&format_info.url);
-    
-    // Add headers from yt-dlp
-    for (key, value) in &format_info.http_headers {
-        if key.to_lowercase() != "cookie" {
-            request = request.header(key, value);
-        }
+    if let Some(recorder) = &state.recorder {
+        recorder.record_cdn(
+            &session_data.platform,
+            &session_data.video_id,
+            &resolved_id,
+            &source_headers(&format_info, &session_data),
+            range,
+            response.status().as_u16(),
+            response.headers(),
+        )
+        .await;
     }
-    
-    // Add Accept-Encoding: identity
-    request = request.header("Accept-Encoding", "identity");
-    
-    // Add cookies if present
-    if let Some(cookies) = &session_data.cookies {
-        request = request.header("Cookie", cookies);
+
+    // Content type from the first bytes of the file; CDNs often send
+    // application/octet-stream, and the format's own type is only a guess
+    let upstream_type = upstream_content_type(
+        response.headers().get("content-type").and_then(|v| v.to_str().ok()),
+        &format_info.content_type,
+    );
+    let content_length = response.content_length();
+    let partial = response.status().as_u16() == 206;
+    let content_range = response
+        .headers()
+        .get("content-range")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    if let Some(limit) = state.max_download_bytes {
+        let total = cdn::total_size(partial, content_length, content_range.as_deref());
+        if let Some(size) = total.filter(|size| *size > limit) {
+            return file_too_large(size, limit);
+        }
     }
-    
-    // Send request
-    let response = match request.send().await {
-        Ok(resp) => resp,
+    let upstream = cdn::read_timeout(response.bytes_stream(), policy.read_timeout);
+    let mut upstream = cdn::coalesce(upstream, state.cdn.read_buffer());
+    let prefix = match sniff::read_prefix(&mut upstream).await {
+        Ok(p) => p,
         Err(e) => {
-            error!("Failed to download from URL: {}", e);
+            error!("Failed to read from source: {}", e);
             return (
                 StatusCode::BAD_GATEWAY,
                 Json(serde_json::to_value(ErrorResponse {
@@ -1168,22 +2953,12 @@ async fn stream(
                 .into_response();
         }
     };
-    
-    // Get content type from source or use default
-    let content_type = response
-        .headers()
-        .get("content-type")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or(&format_info.content_type)
-        .to_string();
-    
-    // Generate filename
-    let ext = if content_type.starts_with("audio/") {
-        "m4a"
-    } else if content_type.starts_with("image/") {
-        "jpg"
-    } else {
-        "mp4"
+    let (content_type, ext) = match sniff::sniff(&prefix, &format_info.content_type) {
+        Some((mime, ext)) => (mime.to_string(), ext),
+        None => {
+            let ext = sniff::extension_for(&upstream_type);
+            (upstream_type, ext)
+        }
     };
     let filename = format!("{}_{}_{}.{}", 
         session_data.video_id, 
@@ -1192,79 +2967,2153 @@ async fn stream(
         ext
     );
     
-    // Stream response
-    let stream = response.bytes_stream();
-    let body = Body::from_stream(stream);
+    state.events.publish(JobEvent {
+        session_id: Some(session_id.clone()),
+        video_id: Some(session_data.video_id.clone()),
+        format: Some(format_id.clone()),
+        ..JobEvent::new(EventKind::Streamed, &session_data.job_id, &session_data.platform)
+    });
+
+    let mut auto_orient = params.auto_orient.unwrap_or(state.image_auto_orient)
+        && !partial
+        && content_type.starts_with("image/")
+        && content_length.unwrap_or(0) <= state.image_auto_orient_max_bytes;
+    let mut prefix = prefix;
+    let mut original = None;
+    if auto_orient {
+        // Rotating needs the whole image. Without a Content-Length, buffering
+        // stops past IMAGE_AUTO_ORIENT_MAX_MB and the image is sent as-is
+        let mut buffered = prefix.to_vec();
+        while buffered.len() as u64 <= state.image_auto_orient_max_bytes {
+            match upstream.next().await {
+                Some(Ok(c)) => buffered.extend_from_slice(&c),
+                Some(Err(e)) => {
+                    error!("Failed to read image from source: {}", e);
+                    return (
+                        StatusCode::BAD_GATEWAY,
+                        Json(serde_json::to_value(ErrorResponse {
+                            success: false,
+                            message: "Failed to download media from source".into(),
+                            error_code: Some("DOWNLOAD_ERROR".into()),
+                        })
+                        .unwrap()),
+                    )
+                        .into_response();
+                }
+                None => break,
+            }
+        }
+        if buffered.len() as u64 > state.image_auto_orient_max_bytes {
+            info!("Image passed IMAGE_AUTO_ORIENT_MAX_MB, streaming it as-is");
+            auto_orient = false;
+            prefix = axum::body::Bytes::from(buffered);
+        } else {
+            original = Some(axum::body::Bytes::from(buffered));
+        }
+    }
+    let body = if let Some(original) = original {
+        let bytes = original.to_vec();
+        let image = match tokio::task::spawn_blocking(move || orient::auto_orient(bytes)).await {
+            Ok(Ok(oriented)) => axum::body::Bytes::from(oriented),
+            Ok(Err(e)) => {
+                warn!("EXIF auto-orient failed, serving original image: {}", e);
+                original
+            }
+            Err(e) => {
+                warn!("EXIF auto-orient task failed, serving original image: {}", e);
+                original
+            }
+        };
+        if let Some(budget) = &state.budget {
+            budget.add(image.len() as u64);
+        }
+        Body::from(image)
+    } else {
+        // A 206 starts `offset` bytes into the file
+        let limit = state.max_download_bytes.map(|limit| limit.saturating_sub(if partial { offset } else { 0 }));
+        forward_body(prefix, upstream, limit, state.budget.as_ref())
+    };
     
+    let disposition = content_disposition(params.disposition, &content_type, &filename);
+    let mut builder = Response::builder()
+        .header("Content-Type", content_type)
+        .header("Content-Disposition", disposition);
+    // An auto-oriented image is re-encoded: its bytes can't be ranged into
+    if !auto_orient {
+        builder = builder.header("Accept-Ranges", "bytes");
+    }
+    if partial {
+        builder = builder.status(StatusCode::PARTIAL_CONTENT);
+        if let Some(range) = content_range {
+            builder = builder.header("Content-Range", range);
+        }
+    }
+    if let (false, Some(len)) = (auto_orient, content_length) {
+        builder = builder.header("Content-Length", len);
+    }
+    if let Some(original) = downgraded_from {
+        builder = builder.header("X-Downgraded-From", original);
+    }
+    // Under the built-in key anyone could forge a token for any URL
+    if state.stateless_tokens && !auto_orient && !session_data.source_url.is_empty() {
+        let token = ResumeToken {
+            exp: chrono::Utc::now().timestamp() + state.resume_token_ttl_secs as i64,
+            session_id: session_id.clone(),
+            url: session_data.source_url.clone(),
+            format_id: resolved_id,
+            // The upstream ignored the Range: this response starts at 0
+            offset: if partial { offset } else { 0 },
+        };
+        match seal_resume_token(&state.session_cipher, &token) {
+            Ok(sealed) => builder = builder.header("X-Resume-Token", sealed),
+            Err(e) => warn!("Failed to seal resume token: {}", e),
+        }
+    }
+    builder.body(body).unwrap()
+}
+
+/// Most posts whose info JSON is kept for resumes, so that every connection
+/// of a download manager presenting the same expired token shares one
+/// extraction.
+const RESUME_CACHE_ENTRIES: usize = 256;
+
+/// The session a resume token refers to, or — once it has expired — a new
+/// session from extracting the token's post again (server cookies only,
+/// reused for SESSION_TTL_SECS).
+async fn resume_session(state: &AppState, token: &ResumeToken) -> Result<(String, SessionData), Response> {
+    match load_session(state, &token.session_id).await {
+        Ok(data) => return Ok((token.session_id.clone(), data)),
+        Err(resp) if resp.status() != StatusCode::GONE => return Err(resp),
+        Err(_) => {}
+    }
+    info!("Session for resume token expired, extracting {} again", token.url);
+    if !is_supported_url(&token.url) {
+        let message = "Unsupported URL. Only TikTok and X (Twitter) URLs are supported.";
+        return Err(error_response(StatusCode::BAD_REQUEST, message, "HTTP_400"));
+    }
+    let feature_platform = features::platform_for_url(&token.url);
+    if !state.features.platform_enabled(feature_platform) {
+        return Err(feature_disabled(feature_platform, None));
+    }
+    let mut info = match state.resumed.get(&token.url, "info") {
+        Some(info) => info.as_ref().clone(),
+        None => {
+            let info = extract_info(state, &token.url).await?;
+            state.resumed.insert(&token.url, "info", Arc::new(info.clone()));
+            info
+        }
+    };
+    strip_disabled_formats(&mut info, &state.features, feature_platform);
+    let filter = FormatFilter::default();
+    let formats_arr = info["formats"].as_array().map(|v| v.as_slice()).unwrap_or(&[]);
+    let (video_fmts, audio_fmts, image_fmts) = parse_formats(formats_arr, &filter);
+    let job_id = Uuid::new_v4().to_string();
+    let data = build_session_data(&video_fmts, &audio_fmts, &image_fmts, &info, SessionMeta {
+        client_cookies: None,
+        job_id: &job_id,
+        platform: &detect_platform(&token.url, info["extractor"].as_str().unwrap_or("")),
+        slideshow_enabled: state.features.enabled(feature_platform, "slideshow"),
+        filter: &filter,
+        selected: &[],
+        url: &token.url,
+    });
+    match create_session(state, &data).await {
+        Ok(id) => Ok((id, data)),
+        Err(e) => {
+            error!("Failed to create resumed session: {}", e);
+            Err(error_response(StatusCode::SERVICE_UNAVAILABLE, "Failed to create download session", "REDIS_ERROR"))
+        }
+    }
+}
+
+/// /stream?chapter=N — the format cut to chapter N by FFmpeg in a work
+/// folder, then sent whole.
+async fn stream_chapter(
+    state: &AppState,
+    session_id: &str,
+    session_data: &SessionData,
+    format_id: &str,
+    format_info: &FormatInfo,
+    index: usize,
+    disposition: Option<Disposition>,
+) -> Response {
+    let Some(chapter) = session_data.chapters.get(index) else {
+        return error_response(StatusCode::NOT_FOUND, &format!("Chapter {index} not found"), "CHAPTER_NOT_FOUND");
+    };
+    if format_info.content_type.starts_with("image/") {
+        return error_response(StatusCode::BAD_REQUEST, "Only video and audio formats can be clipped", "CLIP_UNAVAILABLE");
+    }
+    let is_audio = format_info.resolution == "audio only";
+
+    let job = match FfmpegJob::start(state, "clip").await {
+        Ok(job) => job,
+        Err(resp) => return resp,
+    };
+    let headers = ffmpeg_headers(format_info, session_data);
+    let (ext, content_type) = if is_audio { ("m4a", "audio/mp4") } else { ("mp4", "video/mp4") };
+    let output_path = job.work_dir.file(&format!("clip.{ext}"));
+    let (input, out, start, end) = (format_info.url.clone(), output_path.clone(), chapter.start, chapter.end);
+    let length = (end - start).max(0.0);
+    let expected = probe::ExpectedOutput {
+        video_streams: usize::from(!is_audio),
+        video_codec: None,
+        audio_streams: None,
+        duration_secs: Some((
+            length - probe::DURATION_TOLERANCE_SECS,
+            length + probe::DURATION_TOLERANCE_SECS + clip::KEYFRAME_SLACK_SECS,
+        )),
+    };
+    let created = job
+        .run(state, move |stop| {
+            clip::create_clip(&input, &headers, start, end, &out, stop)?;
+            Ok(probe::verify_output(&out, &expected, stop))
+        })
+        .await;
+    let clip = match created {
+        Ok(Ok(())) => job.stream_file(&output_path).await.map_err(|e| ("CLIP_ERROR", e)),
+        Ok(Err(e)) => Err(("GENERATION_INVALID", e)),
+        Err(e) => Err(("CLIP_ERROR", e)),
+    };
+    let (clip, len) = match clip {
+        Ok(c) => c,
+        Err((code, e)) => {
+            error!("Chapter clip failed: {}", e);
+            state.events.publish(JobEvent {
+                session_id: Some(session_id.to_string()),
+                format: Some(format_id.to_string()),
+                ..JobEvent::failed(&session_data.job_id, &session_data.platform, code, e)
+            });
+            return error_response(StatusCode::BAD_GATEWAY, failed_job_message(code, "Failed to clip media from source"), code);
+        }
+    };
+
+    state.events.publish(JobEvent {
+        session_id: Some(session_id.to_string()),
+        video_id: Some(session_data.video_id.clone()),
+        format: Some(format_id.to_string()),
+        ..JobEvent::new(EventKind::Streamed, &session_data.job_id, &session_data.platform)
+    });
+    if let Some(budget) = &state.budget {
+        budget.add(len);
+    }
+    let filename = format!("{}_{}_chapter{}.{}", session_data.video_id, format_id, index, ext);
     Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", content_type)
-        .header(
-            "Content-Disposition",
-            format!("attachment; filename=\"{}\"", filename),
-        )
-        .body(body)
+        .header("Content-Length", len)
+        .header("Content-Disposition", content_disposition(disposition, content_type, &filename))
+        .body(clip)
         .unwrap()
 }
 
-// ============= Main =============
-
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
+/// Why /stream re-encodes video (?fix_rotation=true, ?compat=h264,
+/// ?burn_subs=).
+struct Reencode {
+    upright: bool,
+    h264: bool,
+    subtitles: Option<SubtitleSource>,
+}
 
-    let port: u16 = env::var("PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(8025);
-    
-    let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
-    
-    // Connect to Redis
-    let redis_client = match redis::Client::open(redis_url.clone()) {
-        Ok(client) => client,
+/// /stream?fix_rotation=true / ?compat=h264 / ?burn_subs=: probe the format
+/// and, when it carries rotation metadata or isn't H.264 respectively, or
+/// to burn in subtitles, send it re-encoded to upright H.264 (whole, like
+/// chapter clips), kept for the session's other requests. None when there's
+/// nothing to do, so the original is streamed.
+async fn stream_reencoded(
+    state: &AppState,
+    session_id: &str,
+    session_data: &SessionData,
+    format_id: &str,
+    format_info: &FormatInfo,
+    disposition: Option<Disposition>,
+    wanted: Reencode,
+) -> Option<Response> {
+    if format_info.content_type.starts_with("image/") || format_info.resolution == "audio only" {
+        return None;
+    }
+    let suffix = match (&wanted.subtitles, wanted.h264) {
+        (Some(subtitles), _) => format!("subs_{}", subtitles.lang.replace(|c: char| !c.is_alphanumeric(), "_")),
+        (None, true) => "h264".to_string(),
+        (None, false) => "upright".to_string(),
+    };
+    let key = format!("{format_id}\n{suffix}");
+    let cached = match state.reencoded.get(session_id, &key) {
+        Some(cached) => cached,
+        None => match reencode(state, session_id, session_data, format_id, format_info, &wanted).await {
+            Ok(Some(reencoded)) => {
+                let reencoded = Arc::new(reencoded);
+                state.reencoded.insert(session_id, &key, reencoded.clone());
+                reencoded
+            }
+            Ok(None) => return None,
+            Err(resp) => return Some(resp),
+        },
+    };
+    let (video, len) = match stream_reencoded_file(cached).await {
+        Ok(v) => v,
         Err(e) => {
-            error!("Failed to create Redis client: {}", e);
-            std::process::exit(1);
+            error!("Failed to open re-encoded video: {}", e);
+            return Some(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read re-encoded video", "INTERNAL_ERROR"));
         }
     };
-    
-    let redis_conn = match redis_client.get_multiplexed_async_connection().await {
-        Ok(conn) => Arc::new(Mutex::new(conn)),
-        Err(e) => {
-            error!("Failed to connect to Redis: {}", e);
-            std::process::exit(1);
+
+    state.events.publish(JobEvent {
+        session_id: Some(session_id.to_string()),
+        video_id: Some(session_data.video_id.clone()),
+        format: Some(format_id.to_string()),
+        ..JobEvent::new(EventKind::Streamed, &session_data.job_id, &session_data.platform)
+    });
+    if let Some(budget) = &state.budget {
+        budget.add(len);
+    }
+    let filename = format!("{}_{}_{}.mp4", session_data.video_id, format_id, suffix);
+    Some(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "video/mp4")
+            .header("Content-Length", len)
+            .header("Content-Disposition", content_disposition(disposition, "video/mp4", &filename))
+            .body(video)
+            .unwrap(),
+    )
+}
+
+/// Stream a cached re-encode, holding on to it until the body is sent or
+/// dropped.
+async fn stream_reencoded_file(reencoded: Arc<transcode::Reencoded>) -> Result<(Body, u64), String> {
+    let file = tokio::fs::File::open(reencoded.path()).await.map_err(|e| e.to_string())?;
+    let len = reencoded.len;
+    let body = tokio_util::io::ReaderStream::new(file).map(move |chunk| {
+        let _ = &reencoded;
+        chunk
+    });
+    Ok((Body::from_stream(body), len))
+}
+
+/// The FFmpeg side of stream_reencoded: Ok(None) when the format needs no
+/// re-encoding, errors as responses (and failed events).
+async fn reencode(
+    state: &AppState,
+    session_id: &str,
+    session_data: &SessionData,
+    format_id: &str,
+    format_info: &FormatInfo,
+    wanted: &Reencode,
+) -> Result<Option<transcode::Reencoded>, Response> {
+    let job = FfmpegJob::start(state, "video").await?;
+
+    let subtitle_path = match &wanted.subtitles {
+        Some(subtitles) => match fetch_subtitles(state, session_data, format_info, subtitles, &job.work_dir).await {
+            Ok(path) => Some(path),
+            Err(e) => {
+                error!("Failed to fetch {} subtitles: {}", subtitles.lang, e);
+                return Err(error_response(StatusCode::BAD_GATEWAY, "Failed to fetch subtitles", "SUBTITLES_ERROR"));
+            }
+        },
+        None => None,
+    };
+
+    let headers = ffmpeg_headers(format_info, session_data);
+    let (input, reported_codec) = (format_info.url.clone(), format_info.vcodec.clone());
+    const OUTPUT: &str = "h264.mp4";
+    let (probe_path, output_path) = (job.work_dir.file("probe.json"), job.work_dir.file(OUTPUT));
+    let out = output_path.clone();
+    let (max_bytes, encoder) = (state.probe_max_bytes, state.h264_encoder.clone());
+    let (upright, h264) = (wanted.upright, wanted.h264);
+    let reencoded = job.run(state, move |stop| {
+        // yt-dlp's codec when the probe fails; nothing is turned unprobed.
+        // Burning subtitles re-encodes anyway, turning the video upright too.
+        let probed = match subtitle_path {
+            Some(_) => None,
+            None => probe::probe(&input, &headers, max_bytes, &probe_path, stop)
+                .inspect_err(|e| warn!("Can't probe format, trusting reported codec: {}", e))
+                .ok(),
+        };
+        let rotation = probed.as_ref().map_or(0, |p| p.rotation);
+        let codec = probed.as_ref().and_then(|p| p.video_codec.clone()).or(reported_codec);
+        let sideways = upright && rotation != 0;
+        let not_h264 = h264 && codec.as_deref().is_some_and(|c| c != "h264");
+        if !sideways && !not_h264 && subtitle_path.is_none() {
+            return Ok(None);
         }
+        info!("Re-encoding {} video (rotation {}°)", codec.as_deref().unwrap_or("unknown"), rotation);
+        transcode::to_h264(&input, &headers, &encoder, subtitle_path.as_deref(), &out, stop)?;
+        // Audio is copied and nothing is cut, so the probe says what to expect
+        let expected = probe::ExpectedOutput {
+            video_streams: 1,
+            video_codec: Some("h264"),
+            audio_streams: probed.as_ref().map(|p| usize::from(p.audio_codec.is_some())),
+            duration_secs: probed.and_then(|p| p.duration_seconds).and_then(probe::ExpectedOutput::around),
+        };
+        Ok(Some(probe::verify_output(&out, &expected, stop)))
+    })
+    .await;
+    let (message, code) = if wanted.h264 || wanted.subtitles.is_some() {
+        ("Failed to transcode video", "TRANSCODE_ERROR")
+    } else {
+        ("Failed to fix video rotation", "ROTATION_ERROR")
+    };
+    let result = match reencoded {
+        Ok(None) => return Ok(None),
+        Ok(Some(Ok(()))) => match tokio::fs::metadata(&output_path).await {
+            Ok(meta) => Ok(transcode::Reencoded::new(job.into_work_dir(), OUTPUT, meta.len())),
+            Err(e) => Err((code, e.to_string())),
+        },
+        Ok(Some(Err(e))) => Err(("GENERATION_INVALID", e)),
+        Err(e) => Err((code, e)),
     };
+    result.map(Some).map_err(|(code, e)| {
+        error!("Re-encoding failed: {}", e);
+        state.events.publish(JobEvent {
+            session_id: Some(session_id.to_string()),
+            format: Some(format_id.to_string()),
+            ..JobEvent::failed(&session_data.job_id, &session_data.platform, code, e)
+        });
+        error_response(StatusCode::BAD_GATEWAY, failed_job_message(code, message), code)
+    })
+}
+
+/// Download `subtitles` into `work_dir` with the headers and cookies of the
+/// video format they go on, from CDN_ALLOWED_HOSTS and up to MAX_SUBTITLE_BYTES.
+async fn fetch_subtitles(
+    state: &AppState,
+    session_data: &SessionData,
+    format_info: &FormatInfo,
+    subtitles: &SubtitleSource,
+    work_dir: &slideshow::WorkDir,
+) -> Result<PathBuf, String> {
+    let policy = state.cdn.for_platform(&session_data.platform);
+    let client = state.cdn.client_for(&session_data.platform);
+    if !state.upstream_hosts.allows(&subtitles.url) {
+        return Err("Subtitle host is not in CDN_ALLOWED_HOSTS".to_string());
+    }
+    let source = FormatInfo { url: subtitles.url.clone(), cookies: None, ..format_info.clone() };
+    let mut response = policy.send(source_request(client, &source, session_data)).await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+    let too_large = || format!("Subtitles larger than {} bytes", MAX_SUBTITLE_BYTES);
+    if response.content_length().is_some_and(|len| len > MAX_SUBTITLE_BYTES as u64) {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        if body.len() + chunk.len() > MAX_SUBTITLE_BYTES {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let path = PathBuf::from(work_dir.file(&format!("subs.{}", subtitles.ext)));
+    tokio::fs::write(&path, &body).await.map_err(|e| e.to_string())?;
+    Ok(path)
+}
 
-    info!("✅ Connected to Redis at {}", redis_url);
+/// Same headers and cookies /stream sends to the CDN, for FFmpeg's -headers.
+fn ffmpeg_headers(format_info: &FormatInfo, session_data: &SessionData) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = format_info
+        .http_headers
+        .iter()
+        .filter(|(k, _)| !k.eq_ignore_ascii_case("cookie") && !k.eq_ignore_ascii_case("accept-encoding"))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    if let Some(cookies) = format_info.cookies.as_ref().or(session_data.cookies.as_ref()) {
+        headers.push(("Cookie".into(), cookies.clone()));
+    }
+    headers
+}
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
-        .allow_headers(Any);
+/// One FFmpeg job of a request: a SLIDESHOW_WORKERS permit and a work
+/// folder under TEMP_DIR.
+struct FfmpegJob {
+    work_dir: slideshow::WorkDir,
+    _permit: tokio::sync::OwnedSemaphorePermit,
+}
 
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/health", get({
-            let redis = redis_conn.clone();
-            move || health(redis.clone())
-        }))
-        .route("/download", post({
-            let redis = redis_conn.clone();
-            move |body| download(body, redis.clone())
-        }))
-        .route("/stream", get({
-            let redis = redis_conn.clone();
-            move |query| stream(query, redis.clone())
+impl FfmpegJob {
+    /// Wait for a permit and create the work folder; `what` names the job
+    /// in errors.
+    async fn start(state: &AppState, what: &str) -> Result<Self, Response> {
+        let permit = match state.slideshow_permits.clone().acquire_owned().await {
+            Ok(p) => p,
+            Err(_) => return Err(error_response(StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down", "HTTP_503")),
+        };
+        match slideshow::WorkDir::create(&state.temp_dir, &Uuid::new_v4().to_string()) {
+            Ok(work_dir) => Ok(Self { work_dir, _permit: permit }),
+            Err(e) => {
+                error!("Failed to create {} folder: {}", what, e);
+                let message = format!("Failed to prepare {what}");
+                Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, &message, "INTERNAL_ERROR"))
+            }
+        }
+    }
+
+    /// Run `job` on the blocking pool. The flag it gets is set, and the
+    /// FFmpeg it runs killed, once the client goes away (this future is
+    /// dropped) or after FFMPEG_TIMEOUT_SECS.
+    async fn run<T: Send + 'static>(
+        &self,
+        state: &AppState,
+        job: impl FnOnce(&std::sync::atomic::AtomicBool) -> Result<T, String> + Send + 'static,
+    ) -> Result<T, String> {
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let stop_on_drop = slideshow::StopOnDrop::new(stop.clone());
+        let task = tokio::task::spawn_blocking(move || job(&stop));
+        let result = match tokio::time::timeout(state.ffmpeg_timeout, task).await {
+            Ok(joined) => joined.unwrap_or_else(|e| Err(format!("Task join error: {e}"))),
+            Err(_) => {
+                warn!("⏱️ FFmpeg still running after {}s, killing it", state.ffmpeg_timeout.as_secs());
+                Err(format!("FFmpeg timed out after {}s", state.ffmpeg_timeout.as_secs()))
+            }
+        };
+        stop_on_drop.finish();
+        result
+    }
+
+    /// Stream the file at `path`, returning the body and its length. The
+    /// permit is released now; the work folder is removed once the body is
+    /// sent or dropped.
+    async fn stream_file(self, path: &str) -> Result<(Body, u64), String> {
+        let file = tokio::fs::File::open(path).await.map_err(|e| e.to_string())?;
+        let len = file.metadata().await.map_err(|e| e.to_string())?.len();
+        let work_dir = self.work_dir;
+        let body = tokio_util::io::ReaderStream::new(file).map(move |chunk| {
+            let _ = &work_dir;
+            chunk
+        });
+        Ok((Body::from_stream(body), len))
+    }
+
+    /// Keep the work folder past the job, releasing the permit.
+    fn into_work_dir(self) -> slideshow::WorkDir {
+        self.work_dir
+    }
+}
+
+/// Error message of a failed FFmpeg job: `message`, unless ffprobe found
+/// the output broken (GENERATION_INVALID).
+fn failed_job_message<'a>(code: &str, message: &'a str) -> &'a str {
+    if code == "GENERATION_INVALID" {
+        "Generated file failed verification"
+    } else {
+        message
+    }
+}
+
+/// GET /waveform?id=<session> — normalized audio peaks for a waveform
+/// preview, decoded by FFmpeg once per session and format.
+async fn waveform_handler(State(state): State<AppState>, Query(params): Query<WaveformRequest>) -> Response {
+    let session_data = match load_session(&state, &params.id).await {
+        Ok(data) => data,
+        Err(resp) => return resp,
+    };
+    let requested = params.format.as_deref();
+    let resolved = match requested {
+        Some(id) => session_data.resolve_format(id),
+        None => session_data.resolve_format("best_audio").or_else(|| session_data.resolve_format("best")),
+    };
+    let Some((format_id, format_info)) = resolved else {
+        let message = format!("Format '{}' not found in session", requested.unwrap_or("best_audio"));
+        return error_response(StatusCode::BAD_REQUEST, &message, "FORMAT_NOT_FOUND");
+    };
+    if format_info.content_type.starts_with("image/") {
+        return error_response(StatusCode::BAD_REQUEST, "Images have no waveform", "WAVEFORM_UNAVAILABLE");
+    }
+    // FFmpeg downloads the format up to waveform::MAX_SECONDS, video included
+    // when it's the video's sound
+    if let (Some(limit), Some(size)) = (state.max_download_bytes, format_info.size_bytes) {
+        if size > limit {
+            return file_too_large(size, limit);
+        }
+    }
+
+    let waveform = match state.waveforms.get(&params.id, &format_id) {
+        Some(w) => w,
+        None => {
+            let job = match FfmpegJob::start(&state, "waveform").await {
+                Ok(job) => job,
+                Err(resp) => return resp,
+            };
+            let headers = ffmpeg_headers(&format_info, &session_data);
+            let (input, work_path) = (format_info.url.clone(), job.work_dir.file("audio.raw"));
+            match job.run(&state, move |stop| waveform::generate(&input, &headers, &work_path, stop)).await {
+                Ok(w) => {
+                    let w = Arc::new(w);
+                    state.waveforms.insert(&params.id, &format_id, w.clone());
+                    w
+                }
+                Err(e) => {
+                    error!("Waveform generation failed: {}", e);
+                    return error_response(StatusCode::BAD_GATEWAY, "Failed to decode audio from source", "WAVEFORM_ERROR");
+                }
+            }
+        }
+    };
+
+    let peaks = waveform.resample(params.peaks.unwrap_or(800).clamp(1, waveform::MAX_PEAKS));
+    match params.output.unwrap_or(WaveformOutput::Json) {
+        WaveformOutput::Json => Json(serde_json::json!({
+            "success": true,
+            "format": format_id,
+            "duration_seconds": waveform.duration_seconds,
+            "peaks": peaks,
         }))
-        .layer(cors);
+        .into_response(),
+        WaveformOutput::Binary => {
+            let bytes: Vec<u8> = peaks.iter().map(|p| (p * 255.0).round() as u8).collect();
+            (
+                [
+                    ("Content-Type", "application/octet-stream".to_string()),
+                    ("X-Waveform-Duration", waveform.duration_seconds.to_string()),
+                ],
+                bytes,
+            )
+                .into_response()
+        }
+    }
+}
 
-    let addr = format!("0.0.0.0:{port}");
-    info!("🚀 serverx-rs listening on {addr}");
-    info!("   Runtime: Tokio + PyO3 (yt-dlp) + Redis");
-    info!("   Endpoints: /download, /stream, /health");
+/// GET /probe?id=<session> — codec, resolution, rotation and duration as
+/// ffprobe reads them from the format's first bytes (or HLS segment), next
+/// to what yt-dlp reported, which TikTok HD formats sometimes get wrong.
+async fn probe_handler(State(state): State<AppState>, Query(params): Query<ProbeRequest>) -> Response {
+    let session_data = match load_session(&state, &params.id).await {
+        Ok(data) => data,
+        Err(resp) => return resp,
+    };
+    let requested = params.format.as_deref().unwrap_or("best");
+    let Some((format_id, format_info)) = session_data.resolve_format(requested) else {
+        let message = format!("Format '{}' not found in session", requested);
+        return error_response(StatusCode::BAD_REQUEST, &message, "FORMAT_NOT_FOUND");
+    };
+    if format_info.content_type.starts_with("image/") {
+        return error_response(StatusCode::BAD_REQUEST, "Images can't be probed", "PROBE_UNAVAILABLE");
+    }
 
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
\ No newline at end of file
+    let job = match FfmpegJob::start(&state, "probe").await {
+        Ok(job) => job,
+        Err(resp) => return resp,
+    };
+    let headers = ffmpeg_headers(&format_info, &session_data);
+    let (input, output_path, max_bytes) = (format_info.url.clone(), job.work_dir.file("probe.json"), state.probe_max_bytes);
+    match job.run(&state, move |stop| probe::probe(&input, &headers, max_bytes, &output_path, stop)).await {
+        Ok(probed) => Json(serde_json::json!({
+            "success": true,
+            "format": format_id,
+            "reported": {
+                "quality": format_info.quality,
+                "resolution": format_info.resolution,
+            },
+            "probed": probed,
+        }))
+        .into_response(),
+        Err(e) => {
+            error!("Probe failed: {}", e);
+            error_response(StatusCode::BAD_GATEWAY, "Failed to probe source", "PROBE_ERROR")
+        }
+    }
+}
+
+/// GET /preview?id=<session> — a 3-second muted MP4 or animated WebP of
+/// a video format for hover previews, made by FFmpeg once per session,
+/// format and output.
+async fn preview_handler(
+    State(state): State<AppState>,
+    Query(params): Query<PreviewRequest>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(budget) = &state.budget {
+        let priority_token = headers.get(budget::PRIORITY_HEADER).and_then(|v| v.to_str().ok());
+        if budget.shedding(priority_token) == Shedding::Reject {
+            return budget_exhausted(budget);
+        }
+    }
+    let session_data = match load_session(&state, &params.id).await {
+        Ok(data) => data,
+        Err(resp) => return resp,
+    };
+    let requested = params.format.as_deref().unwrap_or("best");
+    let Some((format_id, format_info)) = session_data.resolve_format(requested) else {
+        let message = format!("Format '{}' not found in session", requested);
+        return error_response(StatusCode::BAD_REQUEST, &message, "FORMAT_NOT_FOUND");
+    };
+    if format_info.content_type.starts_with("image/") || format_info.resolution == "audio only" {
+        return error_response(StatusCode::BAD_REQUEST, "Only video formats have previews", "PREVIEW_UNAVAILABLE");
+    }
+
+    let (output, start) = (params.output, preview::start_secs(params.start, session_data.duration_secs));
+    let key = format!("{format_id}\n{}\n{start}", output.extension());
+    let (preview, len) = match state.previews.get(&params.id, &key) {
+        Some(p) => (Body::from((*p).clone()), p.len() as u64),
+        None => {
+            let job = match FfmpegJob::start(&state, "preview").await {
+                Ok(job) => job,
+                Err(resp) => return resp,
+            };
+            let headers = ffmpeg_headers(&format_info, &session_data);
+            let output_path = job.work_dir.file(&format!("preview.{}", output.extension()));
+            let (input, out) = (format_info.url.clone(), output_path.clone());
+            let expected = probe::ExpectedOutput {
+                video_streams: 1,
+                video_codec: Some(output.codec()),
+                audio_streams: Some(0),
+                // Shorter near the end of the video; animated WebP has no duration
+                duration_secs: match output {
+                    preview::PreviewOutput::Mp4 => Some((0.0, preview::PREVIEW_SECONDS + probe::DURATION_TOLERANCE_SECS)),
+                    preview::PreviewOutput::Webp => None,
+                },
+            };
+            let generated = job
+                .run(&state, move |stop| {
+                    let size = preview::generate(&input, &headers, start as f64, output, &out, stop)?;
+                    Ok(probe::verify_output(&out, &expected, stop).map(|()| size))
+                })
+                .await;
+            let generated = match generated {
+                // Small previews are kept for the next hover, bigger ones streamed once
+                Ok(Ok(size)) if size <= preview::MAX_CACHED_BYTES => match tokio::fs::read(&output_path).await {
+                    Ok(p) => {
+                        let p = Arc::new(axum::body::Bytes::from(p));
+                        state.previews.insert(&params.id, &key, p.clone());
+                        Ok((Body::from((*p).clone()), size))
+                    }
+                    Err(e) => Err(("PREVIEW_ERROR", e.to_string())),
+                },
+                Ok(Ok(_)) => job.stream_file(&output_path).await.map_err(|e| ("PREVIEW_ERROR", e)),
+                Ok(Err(e)) => Err(("GENERATION_INVALID", e)),
+                Err(e) => Err(("PREVIEW_ERROR", e)),
+            };
+            match generated {
+                Ok(p) => p,
+                Err((code, e)) => {
+                    error!("Preview generation failed: {}", e);
+                    let message = failed_job_message(code, "Failed to generate preview from source");
+                    return error_response(StatusCode::BAD_GATEWAY, message, code);
+                }
+            }
+        }
+    };
+
+    if let Some(budget) = &state.budget {
+        budget.add(len);
+    }
+    let filename = format!("{}_{}_preview.{}", session_data.video_id, format_id, output.extension());
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", output.content_type())
+        .header("Content-Length", len)
+        .header("Content-Disposition", content_disposition(Some(Disposition::Inline), output.content_type(), &filename))
+        .body(preview)
+        .unwrap()
+}
+
+/// Load a session from a stateless token or from Redis: 503 REDIS_ERROR while
+/// the store is unreachable, 410 SESSION_EXPIRED when it's gone, 403
+/// HOST_NOT_ALLOWED when its media lives outside CDN_ALLOWED_HOSTS.
+async fn load_session(state: &AppState, session_id: &str) -> Result<SessionData, Response> {
+    let session_data = match session_id.strip_prefix(SESSION_TOKEN_PREFIX) {
+        Some(token) => Ok(state.stateless_tokens.then(|| open_session_token(&state.session_cipher, token)).flatten()),
+        #[cfg(feature = "redis")]
+        None => match state.redis.get().await {
+            Some(mut conn) => match get_session_from_redis(
+                &mut conn,
+                &state.session_cipher,
+                &session_key(&state.session_namespace, session_id),
+            )
+            .await
+            {
+                Ok(data) => Ok(data),
+                Err(e) => {
+                    error!("Redis error: {}", e);
+                    state.redis.report(&e).await;
+                    Err(())
+                }
+            },
+            None => Err(()),
+        },
+        // Standalone builds only hand out tokens
+        #[cfg(not(feature = "redis"))]
+        None => Ok(None),
+    };
+    
+    let session_data = match session_data {
+        Ok(Some(data)) => data,
+        Err(()) => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::to_value(ErrorResponse {
+                    success: false,
+                    message: "Session store unavailable, please retry shortly".into(),
+                    error_code: Some("REDIS_ERROR".into()),
+                })
+                .unwrap()),
+            )
+                .into_response());
+        }
+        Ok(None) => {
+            return Err((
+                StatusCode::GONE,
+                Json(serde_json::to_value(ErrorResponse {
+                    success: false,
+                    message: "Session expired or not found. Please extract again.".into(),
+                    error_code: Some("SESSION_EXPIRED".into()),
+                })
+                .unwrap()),
+            )
+                .into_response());
+        }
+    };
+    if let Some(url) = session_data.upstream_urls().find(|url| !state.upstream_hosts.allows(url)) {
+        warn!("Refusing session {} with media on a host that isn't allowed: {}", session_id, url);
+        return Err(error_response(StatusCode::FORBIDDEN, "Media host is not allowed", "HOST_NOT_ALLOWED"));
+    }
+    
+    Ok(session_data)
+}
+
+/// GET request for one of a session's formats with yt-dlp's headers and cookies.
+fn source_request(client: &reqwest::Client, format_info: &FormatInfo, session_data: &SessionData) -> reqwest::RequestBuilder {
+    source_headers(format_info, session_data)
+        .into_iter()
+        .fold(client.get(&format_info.url), |request, (key, value)| request.header(key, value))
+}
+
+/// Headers sent to the CDN for `format_info`, sorted by name.
+fn source_headers(format_info: &FormatInfo, session_data: &SessionData) -> Vec<(String, String)> {
+    // Headers from yt-dlp
+    let mut headers: Vec<(String, String)> = format_info
+        .http_headers
+        .iter()
+        .filter(|(key, _)| key.to_lowercase() != "cookie")
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    headers.sort();
+
+    headers.push(("Accept-Encoding".into(), "identity".into()));
+
+    // Add cookies if present; the format's own come from the same cookiejar
+    // the user's cookies (or COOKIES_PATH) were loaded into
+    if let Some(cookies) = format_info.cookies.as_ref().or(session_data.cookies.as_ref()) {
+        headers.push(("Cookie".into(), cookies.clone()));
+    }
+    headers
+}
+
+/// Content type /stream falls back to when sniffing the first bytes fails:
+/// the CDN's, unless it's missing or generic, else the format's guess.
+fn upstream_content_type(cdn_type: Option<&str>, format_type: &str) -> String {
+    cdn_type
+        .filter(|ct| !ct.starts_with("application/octet-stream") && !ct.is_empty())
+        .unwrap_or(format_type)
+        .to_string()
+}
+
+#[derive(Deserialize)]
+struct AvatarRequest {
+    platform: String,
+    user: String,
+}
+
+/// GET /avatar?platform=tiktok&user=<handle> — the creator's profile picture,
+/// proxied so links in /download responses don't expire with the CDN's
+async fn avatar_handler(
+    State(state): State<AppState>,
+    Query(params): Query<AvatarRequest>,
+) -> Response {
+    if !matches!(params.platform.as_str(), "tiktok" | "x") || !avatar::valid_handle(&params.user) {
+        return error_response(StatusCode::BAD_REQUEST, "Unknown platform or invalid user", "HTTP_400");
+    }
+    let url = match state.avatars.resolve(&params.platform, &params.user).await {
+        Ok(Some(url)) => url,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Avatar not found", "AVATAR_NOT_FOUND"),
+        Err(avatar::LookupError::RateLimited(retry_after)) => {
+            let mut resp = error_response(StatusCode::TOO_MANY_REQUESTS, "Too many avatar lookups, please retry later", "HTTP_429");
+            resp.headers_mut().insert("Retry-After", retry_after.into());
+            return resp;
+        }
+        Err(e) => {
+            error!("Avatar lookup for {} failed: {}", params.user, e);
+            return error_response(StatusCode::BAD_GATEWAY, "Failed to look up avatar", "DOWNLOAD_ERROR");
+        }
+    };
+    // Profile pages (and extractions) name the image; it's fetched like media
+    if !state.upstream_hosts.allows(&url) {
+        warn!("Refusing avatar on a host that isn't allowed: {}", url);
+        return error_response(StatusCode::FORBIDDEN, "Media host is not allowed", "HOST_NOT_ALLOWED");
+    }
+    let response = match state.avatars.fetch(&url).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            error!("Failed to download avatar {}: {}", url, e);
+            return error_response(StatusCode::BAD_GATEWAY, "Failed to download avatar", "DOWNLOAD_ERROR");
+        }
+    };
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .filter(|ct| ct.starts_with("image/"))
+        .unwrap_or("image/jpeg")
+        .to_string();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .header("Cache-Control", "public, max-age=86400")
+        .body(Body::from_stream(response.bytes_stream()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str, code: &str) -> Response {
+    (
+        status,
+        Json(serde_json::to_value(ErrorResponse {
+            success: false,
+            message: message.into(),
+            error_code: Some(code.into()),
+        })
+        .unwrap()),
+    )
+        .into_response()
+}
+
+/// GET /slideshow?id=<session> — MP4 of a TikTok photo post's images with its sound
+async fn slideshow_handler(
+    State(state): State<AppState>,
+    Query(params): Query<SlideshowRequest>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(budget) = &state.budget {
+        let priority_token = headers.get(budget::PRIORITY_HEADER).and_then(|v| v.to_str().ok());
+        if budget.shedding(priority_token) == Shedding::Reject {
+            return budget_exhausted(budget);
+        }
+    }
+    let session_id = params.id;
+    let session_data = match load_session(&state, &session_id).await {
+        Ok(data) => data,
+        Err(resp) => return resp,
+    };
+    if !session_data.slideshow {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Slideshows are only available for photo posts",
+            "SLIDESHOW_UNAVAILABLE",
+        );
+    }
+
+    let mut images: Vec<(u32, &FormatInfo)> = session_data
+        .formats
+        .iter()
+        .filter_map(|(id, f)| Some((slideshow::image_index(id)?, f)))
+        .collect();
+    images.sort_by_key(|(index, _)| *index);
+    let audio = session_data
+        .formats
+        .get("audio")
+        .or_else(|| session_data.formats.values().find(|f| f.resolution == "audio only"));
+
+    // Bounds concurrent downloads + ffmpeg runs; released once the video is made
+    let job = match FfmpegJob::start(&state, "slideshow").await {
+        Ok(job) => job,
+        Err(resp) => return resp,
+    };
+    let work_dir = &job.work_dir;
+
+    let policy = state.cdn.for_platform(&session_data.platform).clone();
+    let client = state.cdn.client_for(&session_data.platform);
+    let mut downloads: Vec<(&FormatInfo, String)> = images
+        .iter()
+        .enumerate()
+        .map(|(i, (_, f))| (*f, work_dir.file(&format!("image_{i}.jpg"))))
+        .collect();
+    if let Some(audio) = audio {
+        downloads.push((audio, work_dir.file("audio.m4a")));
+    }
+    let mut fetches = tokio::task::JoinSet::new();
+    for (format_info, path) in downloads {
+        let request = source_request(client, format_info, &session_data);
+        let policy = policy.clone();
+        fetches.spawn(async move {
+            let response = policy.send(request).await?.error_for_status()?;
+            let mut body = cdn::read_timeout(response.bytes_stream(), policy.read_timeout);
+            let mut bytes = Vec::new();
+            while let Some(chunk) = body.next().await {
+                bytes.extend_from_slice(&chunk?);
+            }
+            tokio::fs::write(path, bytes).await?;
+            Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+        });
+    }
+    let mut failed = None;
+    while let Some(result) = fetches.join_next().await {
+        let result = result.map_err(|e| e.to_string()).and_then(|r| r.map_err(|e| e.to_string()));
+        if let (Err(e), None) = (result, &failed) {
+            // The rest are cancelled; keep the first real error
+            failed = Some(e);
+            fetches.abort_all();
+        }
+    }
+    if let Some(e) = failed {
+        error!("Slideshow asset download failed: {}", e);
+        state.events.publish(JobEvent {
+            session_id: Some(session_id.clone()),
+            format: Some("slideshow".into()),
+            ..JobEvent::failed(&session_data.job_id, &session_data.platform, "DOWNLOAD_ERROR", e.to_string())
+        });
+        return error_response(StatusCode::BAD_GATEWAY, "Failed to download media from source", "DOWNLOAD_ERROR");
+    }
+
+    let image_paths: Vec<String> = (0..images.len()).map(|i| work_dir.file(&format!("image_{i}.jpg"))).collect();
+    let audio_path = audio.map(|_| work_dir.file("audio.m4a"));
+    let output_path = work_dir.file("slideshow.mp4");
+    let out = output_path.clone();
+    let expected = probe::ExpectedOutput {
+        video_streams: 1,
+        video_codec: Some("h264"),
+        audio_streams: Some(usize::from(audio.is_some())),
+        duration_secs: probe::ExpectedOutput::around(images.len() as f64 * slideshow::SECS_PER_IMAGE),
+    };
+    let created = job
+        .run(&state, move |stop| {
+            slideshow::create_slideshow(&image_paths, audio_path.as_deref(), &out, stop)?;
+            Ok(probe::verify_output(&out, &expected, stop))
+        })
+        .await;
+    let video = match created {
+        Ok(Ok(())) => job.stream_file(&output_path).await.map_err(|e| ("SLIDESHOW_ERROR", e)),
+        Ok(Err(e)) => Err(("GENERATION_INVALID", e)),
+        Err(e) => Err(("SLIDESHOW_ERROR", e)),
+    };
+    let (video, len) = match video {
+        Ok(v) => v,
+        Err((code, e)) => {
+            error!("Slideshow creation failed: {}", e);
+            state.events.publish(JobEvent {
+                session_id: Some(session_id.clone()),
+                format: Some("slideshow".into()),
+                ..JobEvent::failed(&session_data.job_id, &session_data.platform, code, e)
+            });
+            let message = failed_job_message(code, "Failed to create slideshow");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, message, code);
+        }
+    };
+
+    state.events.publish(JobEvent {
+        session_id: Some(session_id.clone()),
+        video_id: Some(session_data.video_id.clone()),
+        format: Some("slideshow".into()),
+        ..JobEvent::new(EventKind::Streamed, &session_data.job_id, &session_data.platform)
+    });
+    if let Some(budget) = &state.budget {
+        budget.add(len);
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "video/mp4")
+        .header("Content-Length", len)
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}_slideshow.mp4\"", session_data.video_id),
+        )
+        .body(video)
+        .unwrap()
+}
+
+// ============= Main =============
+
+fn main() {
+    tracing_subscriber::fmt::init();
+
+    // 0 would panic building the runtime
+    let max_blocking_threads = env_parse::<usize>("MAX_BLOCKING_THREADS", 512).max(1);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .max_blocking_threads(max_blocking_threads)
+        .build()
+        .expect("Failed to build Tokio runtime")
+        .block_on(run());
+}
+
+/// Where MOCK_EXTRACTOR's formats point: this server's GET /mock/media.
+fn mock_media_base(port: u16) -> String {
+    format!("http://127.0.0.1:{port}/mock/media")
+}
+
+/// Canned fixtures with MOCK_EXTRACTOR, remote workers when
+/// EXTRACTOR_WORKERS is set, otherwise the embedded interpreter, warmed up
+/// here (a broken Python env is fatal at boot).
+async fn init_extractor(port: u16) -> (Extractor, Option<String>) {
+    if env_parse("MOCK_EXTRACTOR", false) {
+        let mock = mock::MockExtractor::new(&mock_media_base(port));
+        warn!("⚠️  MOCK_EXTRACTOR is on: serving canned extractions, no real downloads");
+        for url in mock.urls() {
+            info!("   Mock post: {}", url);
+        }
+        return (Extractor::Mock(Arc::new(mock)), Some("mock".into()));
+    }
+    if let Some(workers) = RemoteWorkers::from_env() {
+        info!("🛰️  Forwarding extraction to {} worker(s)", workers.worker_count());
+        return (Extractor::Remote(Arc::new(workers)), None);
+    }
+    #[cfg(feature = "python")]
+    {
+        let preload_extractors = env_parse("PRELOAD_EXTRACTORS", true);
+        match tokio::task::spawn_blocking(move || ytdlp::warm_up_ytdlp(preload_extractors)).await {
+            Ok(Ok(version)) => (Extractor::Embedded, Some(version)),
+            Ok(Err(e)) => {
+                error!("yt-dlp warm-up failed: {}", e);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                error!("yt-dlp warm-up task failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(not(feature = "python"))]
+    {
+        error!("serverx-rs was built without the python feature; set EXTRACTOR_WORKERS");
+        std::process::exit(1);
+    }
+}
+
+async fn run() {
+    let port: u16 = env::var("PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(8025);
+    
+    let (extractor, ytdlp_version) = init_extractor(port).await;
+    let mut upstream_hosts = cdn::UpstreamHosts::from_env();
+    if matches!(extractor, Extractor::Mock(_)) {
+        upstream_hosts.allow_origin(&mock_media_base(port));
+    }
+
+    // Only a malformed REDIS_URL is fatal; an unreachable Redis is retried
+    #[cfg(feature = "redis")]
+    let redis_conn = match RedisConn::from_env() {
+        Ok(conn) => Arc::new(conn),
+        Err(e) => {
+            error!("Failed to create Redis client: {}", e);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(feature = "redis")]
+    if redis_conn.get().await.is_none() {
+        info!("⚠️  Starting in degraded mode until Redis is reachable");
+    }
+    #[cfg(not(feature = "redis"))]
+    info!("📦 Standalone build: sessions are stateless tokens, no Redis");
+
+    // Every instance behind the same load balancer needs the same key, or
+    // sessions and tokens from one can't be opened by another
+    let (encryption_key, stateless_tokens) = match env::var("ENCRYPTION_KEY").ok().filter(|k| !k.is_empty()) {
+        Some(key) => (key, true),
+        #[cfg(feature = "redis")]
+        None => {
+            warn!("⚠️  ENCRYPTION_KEY not set: using the built-in default, no stateless session tokens");
+            ("overflow".to_string(), false)
+        }
+        // Every session is a token here, so they get a key only this process knows
+        #[cfg(not(feature = "redis"))]
+        None => {
+            warn!("⚠️  ENCRYPTION_KEY not set: using a random key, session tokens end with this process");
+            let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+            (base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(key), true)
+        }
+    };
+    let base_url = base_url_from(env::var("BASE_URL").ok());
+    let trust_proxy_headers = env_parse("TRUST_PROXY_HEADERS", false);
+    if trust_proxy_headers {
+        info!("🔀 Links follow X-Forwarded-Proto/Host, falling back to {}", base_url);
+    } else if env::var("BASE_URL").is_err() {
+        warn!("⚠️  BASE_URL not set, links point to {}", base_url);
+    }
+    let python_status = Arc::new(RwLock::new(PythonStatus {
+        ytdlp_version,
+        ..Default::default()
+    }));
+    spawn_python_check_task(python_status.clone(), extractor.clone());
+
+    let max_workers = env_parse::<usize>("MAX_WORKERS", 20).max(1);
+
+    let extraction_queue_size: usize = env_parse("EXTRACTION_QUEUE_SIZE", 50);
+
+    #[cfg(feature = "redis")]
+    let events = EventPublisher::from_env(&redis_conn).await;
+    #[cfg(not(feature = "redis"))]
+    let events = EventPublisher::from_env().await;
+
+    let cookies_path = env::var("COOKIES_PATH").ok().filter(|p| !p.is_empty());
+    if let Some(ref path) = cookies_path {
+        if std::path::Path::new(path).exists() {
+            info!("🍪 Using cookies from {}", path);
+        } else {
+            warn!("⚠️  COOKIES_PATH {} not found, extracting without cookies", path);
+        }
+    }
+
+    let recorder = match env::var("RECORD_FIXTURES_DIR").ok().filter(|d| !d.is_empty()) {
+        Some(dir) => match fixtures::Recorder::new(&dir) {
+            Ok(r) => {
+                warn!("⚠️  Recording fixtures to {} (RECORD_FIXTURES_DIR); don't enable in production", r.dir().display());
+                Some(Arc::new(r))
+            }
+            Err(e) => {
+                error!("Failed to create RECORD_FIXTURES_DIR {}: {}", dir, e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let egress = match egress::Egress::from_env() {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Invalid egress configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if egress.family != egress::IpFamily::Any {
+        info!("🌐 Outbound connections: {} (EGRESS_IP_FAMILY)", egress.family);
+    }
+    if !egress.describe().is_empty() {
+        info!("🌐 Outbound source addresses: {}", egress.describe().join(", "));
+    }
+
+    let temp_dir = env::var("TEMP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| env::temp_dir().join("serverx-rs"));
+    slideshow::spawn_cleanup_task(temp_dir.clone());
+
+    let h264_encoder = Arc::new(transcode::H264Encoder::from_env());
+    if *h264_encoder != transcode::H264Encoder::Software {
+        info!("🎞️  H.264 re-encoding with {} (TRANSCODE_HWACCEL)", h264_encoder.name());
+    }
+
+    let budget = budget::EgressBudget::from_env().map(Arc::new);
+    if let Some(budget) = &budget {
+        let status = budget.status();
+        info!(
+            "📉 Egress budget: {:.1}/{:.1} GB used over {} days",
+            status.used_bytes as f64 / 1e9,
+            status.limit_bytes as f64 / 1e9,
+            status.window_secs / 86400
+        );
+        budget::spawn_save_task(budget.clone());
+    }
+
+    let state = AppState {
+        #[cfg(feature = "redis")]
+        redis: redis_conn,
+        #[cfg(feature = "redis")]
+        stateless_fallback: env_parse("STATELESS_FALLBACK", false),
+        #[cfg(feature = "redis")]
+        session_namespace: env::var("SESSION_NAMESPACE")
+            .ok()
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| "download".to_string()),
+        base_url,
+        trust_proxy_headers,
+        session_cipher: SessionCipher::new(&encryption_key),
+        stateless_tokens,
+        resume_token_ttl_secs: env_parse("RESUME_TOKEN_TTL_SECS", 86400),
+        extractor,
+        python_status,
+        metrics: Arc::new(Metrics::default()),
+        extraction_queue: Arc::new(ExtractionQueue::new(
+            max_workers,
+            extraction_queue_size,
+            // Every extraction has the same priority: first come, first served
+            std::time::Duration::from_secs(60),
+        )),
+        features: Arc::new(FeatureFlags::from_env()),
+        templates: Arc::new(ResponseTemplates::from_env()),
+        events,
+        ytdlp_timeout: env_parse("YTDLP_TIMEOUT", 45),
+        ytdlp_max_timeout: env_parse("YTDLP_MAX_TIMEOUT", 120),
+        image_auto_orient: env_parse("IMAGE_AUTO_ORIENT", false),
+        image_auto_orient_max_bytes: env_parse::<u64>("IMAGE_AUTO_ORIENT_MAX_MB", 20) * 1024 * 1024,
+        cookies_path,
+        slideshow_permits: Arc::new(Semaphore::new(env_parse::<usize>("SLIDESHOW_WORKERS", 2).max(1))),
+        temp_dir,
+        ffmpeg_timeout: std::time::Duration::from_secs(env_parse::<u64>("FFMPEG_TIMEOUT_SECS", 600).max(1)),
+        avatars: Arc::new(
+            AvatarResolver::new(
+                std::time::Duration::from_secs(env_parse("AVATAR_CACHE_SECS", 86400)),
+                env_parse("AVATAR_LOOKUPS_PER_MIN", 30),
+            )
+            .expect("Failed to build avatar HTTP client"),
+        ),
+        recorder,
+        cdn: Arc::new(cdn::CdnPolicies::from_env(&egress).expect("Failed to build CDN HTTP client")),
+        upstream_hosts: Arc::new(upstream_hosts),
+        egress: Arc::new(egress),
+        budget,
+        waveforms: Arc::new(SessionCache::new(std::time::Duration::from_secs(SESSION_TTL_SECS))),
+        previews: Arc::new(SessionCache::bounded(
+            std::time::Duration::from_secs(SESSION_TTL_SECS),
+            preview::CACHE_ENTRIES,
+            preview::CACHE_BYTES,
+            |p| p.len() as u64,
+        )),
+        reencoded: Arc::new(SessionCache::bounded(
+            std::time::Duration::from_secs(SESSION_TTL_SECS),
+            transcode::CACHE_ENTRIES,
+            transcode::CACHE_BYTES,
+            |r| r.len,
+        )),
+        resumed: Arc::new(SessionCache::bounded(
+            std::time::Duration::from_secs(SESSION_TTL_SECS),
+            RESUME_CACHE_ENTRIES,
+            u64::MAX,
+            |_| 0,
+        )),
+        probe_max_bytes: env_parse("PROBE_MAX_BYTES", 5_000_000),
+        h264_encoder,
+        max_download_bytes: Some(env_parse("MAX_DOWNLOAD_BYTES", 0)).filter(|b| *b > 0),
+        max_duration_secs: Some(env_parse("MAX_DURATION_SECS", 0)).filter(|s| *s > 0),
+        drain: Arc::new(drain::Drain::default()),
+        admin_token: env::var("ADMIN_TOKEN").ok().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()),
+    };
+
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
+        .allow_headers(Any)
+        .expose_headers([
+            axum::http::HeaderName::from_static("x-resume-token"),
+            axum::http::HeaderName::from_static("x-downgraded-from"),
+            axum::http::HeaderName::from_static("x-waveform-duration"),
+            axum::http::header::CONTENT_RANGE,
+        ]);
+
+    let mock_media = matches!(state.extractor, Extractor::Mock(_));
+    // Media routes count as in flight for draining; probes and admin don't
+    let mut app = Router::new()
+        .route("/download", post(download))
+        .route("/estimate", get(estimate))
+        .route("/stream", get(stream))
+        .route("/slideshow", get(slideshow_handler))
+        .route("/waveform", get(waveform_handler))
+        .route("/probe", get(probe_handler))
+        .route("/preview", get(preview_handler))
+        .route("/avatar", get(avatar_handler))
+        .route_layer(axum::middleware::from_fn_with_state(state.drain.clone(), drain::track))
+        .route("/", get(root))
+        .route("/health", get(health))
+        .route("/health/drain", get(drain_status))
+        .route("/admin/drain", post(start_draining).delete(stop_draining))
+        .route("/metrics", get(metrics_handler));
+    if mock_media {
+        app = app.route("/mock/media/{file}", get(mock::media));
+    }
+    let watchdog_state = state.clone();
+    let app = app
+        .layer(axum::middleware::from_fn(i18n::localize))
+        .layer(cors)
+        .with_state(state);
+
+    let addr = format!("0.0.0.0:{port}");
+    info!("🚀 serverx-rs listening on {addr}");
+    info!("   Runtime: {}", RUNTIME);
+    info!("   Endpoints: /download, /estimate, /stream, /slideshow, /waveform, /probe, /preview, /avatar, /health, /health/drain");
+
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    // Type=notify units are started once yt-dlp is warmed up and the port is bound
+    if let Some(notifier) = systemd::Notifier::from_env().map(Arc::new) {
+        notifier.notify("READY=1\nSTATUS=Serving");
+        if let Some(interval) = systemd::watchdog_interval(&|key| env::var(key).ok()) {
+            info!("🐕 Feeding the systemd watchdog every {}ms", interval.as_millis());
+            spawn_watchdog_task(notifier, interval, watchdog_state, env_parse("WATCHDOG_REQUIRE_REDIS", false));
+        }
+    }
+    axum::serve(listener, app).await.unwrap();
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_cipher_roundtrip() {
+        let cipher = SessionCipher::new("testkey");
+        let sealed = cipher.seal(b"{\"video_id\":\"123\"}").unwrap();
+        assert_ne!(&sealed[SessionCipher::NONCE_LEN..], b"{\"video_id\":\"123\"}");
+        assert_eq!(cipher.open(&sealed).unwrap(), b"{\"video_id\":\"123\"}");
+    }
+
+    #[test]
+    fn test_session_cipher_rejects_wrong_key() {
+        let sealed = SessionCipher::new("testkey").seal(b"cookie=secret").unwrap();
+        assert!(SessionCipher::new("otherkey").open(&sealed).is_err());
+        assert!(SessionCipher::new("testkey").open(&sealed[..4]).is_err());
+    }
+
+    #[test]
+    fn test_session_token_roundtrip_and_tamper() {
+        let cipher = SessionCipher::new("testkey");
+        let data = SessionData {
+            video_id: "123".into(),
+            job_id: "j1".into(),
+            platform: "x".into(),
+            cookies: Some("auth_token=secret".into()),
+            formats: HashMap::new(),
+            slideshow: false,
+            chapters: vec![],
+            subtitles: vec![],
+            best_video: None,
+            best_audio: None,
+            source_url: String::new(),
+            duration_secs: None,
+        };
+        let token = seal_session_token(&cipher, &data).unwrap();
+        let body = token.strip_prefix(SESSION_TOKEN_PREFIX).unwrap();
+        assert!(!body.contains("secret"));
+        assert_eq!(open_session_token(&cipher, body).unwrap().video_id, "123");
+
+        assert!(open_session_token(&SessionCipher::new("otherkey"), body).is_none());
+        assert!(open_session_token(&cipher, &body[1..]).is_none());
+    }
+
+    #[test]
+    fn test_resume_token() {
+        let cipher = SessionCipher::new("testkey");
+        let token = ResumeToken {
+            exp: chrono::Utc::now().timestamp() + 60,
+            session_id: "s1".into(),
+            url: "https://x.com/a/status/1".into(),
+            format_id: "http-2176".into(),
+            offset: 1_048_576,
+        };
+        let sealed = seal_resume_token(&cipher, &token).unwrap();
+        assert!(!sealed.contains("status"));
+        assert_eq!(open_resume_token(&cipher, &sealed).unwrap(), token);
+        assert!(open_resume_token(&SessionCipher::new("otherkey"), &sealed).is_none());
+        let expired = ResumeToken { exp: chrono::Utc::now().timestamp() - 1, ..token };
+        assert!(open_resume_token(&cipher, &seal_resume_token(&cipher, &expired).unwrap()).is_none());
+        assert!(is_supported_url(&expired.url));
+        assert!(!is_supported_url("http://169.254.169.254/latest/meta-data"));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(range_start(&headers), None);
+        headers.insert("range", "bytes=500-".parse().unwrap());
+        assert_eq!(range_start(&headers), Some(500));
+        headers.insert("range", "bytes=0-99".parse().unwrap());
+        assert_eq!(range_start(&headers), Some(0));
+        headers.insert("range", "bytes=-100".parse().unwrap());
+        assert_eq!(range_start(&headers), None);
+
+        // Tokens carry the concrete id behind "best"
+        let info = serde_json::json!({"id": "1", "formats": [
+            {"format_id": "http-720", "url": "https://v/720.mp4", "protocol": "https", "height": 720, "vcodec": "avc1"},
+            {"format_id": "http-1080", "url": "https://v/1080.mp4", "protocol": "https", "height": 1080, "vcodec": "avc1"},
+        ]});
+        let (video, audio, images) = parse_formats(info["formats"].as_array().unwrap(), &FormatFilter::default());
+        let meta = SessionMeta { client_cookies: None, job_id: "j1", platform: "x", slideshow_enabled: false, filter: &FormatFilter::default(), selected: &[], url: "https://x.com/a/status/1" };
+        let data = build_session_data(&video, &audio, &images, &info, meta);
+        assert_eq!(data.source_url, "https://x.com/a/status/1");
+        assert_eq!(data.resolve_format("best").unwrap().0, "http-1080");
+        assert_eq!(data.resolve_format("http-720").unwrap().0, "http-720");
+        assert!(data.resolve_format("best_audio").is_none());
+    }
+
+    #[test]
+    fn test_session_keeps_format_cookies() {
+        let info = serde_json::json!({
+            "id": "123",
+            "formats": [{
+                "format_id": "http-720",
+                "url": "https://video.twimg.com/a.mp4",
+                "_cookies": "auth_token=secret",
+            }],
+        });
+        let fmt = VideoFormat {
+            quality: "720P".into(),
+            resolution: "1280x720".into(),
+            url: "https://video.twimg.com/a.mp4".into(),
+            size_bytes: None,
+            format_id: "http-720".into(),
+            ..Default::default()
+        };
+        let meta = SessionMeta { client_cookies: None, job_id: "j1", platform: "x", slideshow_enabled: true, filter: &FormatFilter::default(), selected: &[] , url: "https://x.com/a/status/1" };
+        let data = build_session_data(&[fmt], &[], &[], &info, meta);
+        assert_eq!(data.formats["http-720"].cookies.as_deref(), Some("auth_token=secret"));
+        assert!(!data.slideshow);
+
+        // Sessions stored before formats carried cookies still load
+        let old: FormatInfo = serde_json::from_value(serde_json::json!({
+            "url": "u", "http_headers": {}, "quality": "q", "resolution": "r", "content_type": "video/mp4",
+        }))
+        .unwrap();
+        assert!(old.cookies.is_none());
+    }
+
+    #[test]
+    fn test_gallery_photos_are_images() {
+        // X gallery photos are playlist entries whose format ids ("orig",
+        // "large") don't say they're images
+        let info = serde_json::json!({"_type": "playlist", "id": "1", "entries": [{"id": "11", "formats": [
+            {"format_id": "orig", "url": "https://pbs.twimg.com/media/a.jpg?name=orig", "ext": "jpg", "video_ext": "jpg", "protocol": "https", "width": 2048, "height": 1536},
+            {"format_id": "large", "url": "https://pbs.twimg.com/media/a.jpg?name=large", "ext": "jpg", "video_ext": "jpg", "protocol": "https", "width": 2048, "height": 1536},
+        ]}]});
+        let meta = SessionMeta { client_cookies: None, job_id: "j1", platform: "x", slideshow_enabled: false, filter: &FormatFilter::default(), selected: &[], url: "https://x.com/a/status/1" };
+        let data = build_session_data(&[], &[], &[], &info, meta);
+        assert_eq!(data.formats.len(), 2);
+        assert!(data.formats.values().all(|f| f.content_type == "image/jpeg"));
+        assert!(data.resolve_format("best_image").is_some());
+    }
+
+    #[test]
+    fn test_budget_downgrade() {
+        let format = |quality: &str| FormatInfo {
+            url: String::new(),
+            http_headers: HashMap::new(),
+            cookies: None,
+            quality: quality.into(),
+            resolution: "WxH".into(),
+            content_type: "video/mp4".into(),
+            vcodec: None,
+            size_bytes: None,
+        };
+        let data = SessionData {
+            video_id: "1".into(),
+            job_id: "j1".into(),
+            platform: "x".into(),
+            cookies: None,
+            formats: HashMap::from([
+                ("http-1080".to_string(), format("1080p (progressive)")),
+                ("http-720".to_string(), format("720p (progressive)")),
+                ("http-360".to_string(), format("360p (progressive)")),
+                ("hls-480".to_string(), format("480p (hls)")),
+                ("audio".to_string(), FormatInfo { resolution: "audio only".into(), ..format("128kbps") }),
+                ("17_http-480".to_string(), format("480p (progressive)")),
+                ("17_http-1080".to_string(), format("1080p (progressive)")),
+            ]),
+            slideshow: false,
+            chapters: vec![],
+            subtitles: vec![],
+            best_video: None,
+            best_audio: None,
+            source_url: String::new(),
+            duration_secs: None,
+        };
+        let picked = |id: &str, max: u32| data.downgrade(id, max).map(|(id, _)| id);
+        assert_eq!(picked("http-1080", 480).as_deref(), Some("http-360"));
+        assert_eq!(picked("http-1080", 720).as_deref(), Some("http-720"));
+        assert_eq!(picked("http-720", 240).as_deref(), Some("http-360"));
+        assert_eq!(picked("17_http-1080", 720).as_deref(), Some("17_http-480"));
+        assert_eq!(picked("http-360", 480), None);
+        assert_eq!(picked("hls-480", 240), None);
+        assert_eq!(picked("audio", 240), None);
+    }
+
+    #[test]
+    fn test_h264_alternative() {
+        let format = |quality: &str, vcodec: &str| FormatInfo {
+            url: String::new(),
+            http_headers: HashMap::new(),
+            cookies: None,
+            quality: quality.into(),
+            resolution: "WxH".into(),
+            content_type: "video/mp4".into(),
+            vcodec: Some(vcodec.into()),
+            size_bytes: None,
+        };
+        let data = SessionData {
+            video_id: "1".into(),
+            job_id: "j1".into(),
+            platform: "tiktok".into(),
+            cookies: None,
+            formats: HashMap::from([
+                ("bytevc1_1080p".to_string(), format("1080p (progressive)", "h265")),
+                ("bytevc1_540p".to_string(), format("540p (progressive)", "h265")),
+                ("h264_720p".to_string(), format("720p (progressive)", "h264")),
+                ("h264_540p".to_string(), format("540p (progressive)", "h264")),
+                ("hls-1080".to_string(), format("1080p (hls)", "h265")),
+            ]),
+            slideshow: false,
+            chapters: vec![],
+            subtitles: vec![],
+            best_video: None,
+            best_audio: None,
+            source_url: String::new(),
+            duration_secs: None,
+        };
+        let picked = |id: &str| data.h264_alternative(id).map(|(id, _)| id);
+        assert_eq!(picked("bytevc1_1080p").as_deref(), Some("h264_720p"));
+        assert_eq!(picked("bytevc1_540p").as_deref(), Some("h264_540p"));
+        assert_eq!(picked("h264_540p"), None);
+        assert_eq!(picked("hls-1080"), None);
+        // The downgrade still works on the shared siblings lookup
+        assert_eq!(data.downgrade("bytevc1_1080p", 600).map(|(id, _)| id).as_deref(), Some("h264_540p"));
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(content_disposition(None, "image/jpeg", "a.jpg"), "inline; filename=\"a.jpg\"");
+        assert_eq!(content_disposition(None, "video/mp4", "a.mp4"), "attachment; filename=\"a.mp4\"");
+        assert_eq!(
+            content_disposition(Some(Disposition::Inline), "video/mp4", "a.mp4"),
+            "inline; filename=\"a.mp4\""
+        );
+        assert_eq!(
+            content_disposition(Some(Disposition::Attachment), "image/jpeg", "a.jpg"),
+            "attachment; filename=\"a.jpg\""
+        );
+    }
+
+    #[test]
+    fn test_format_codec_details() {
+        assert_eq!(codec_family("avc1.64001F"), "h264");
+        assert_eq!(codec_family("bytevc1"), "h265");
+        assert_eq!(codec_family("hev1.1.6.L93.B0"), "h265");
+        assert_eq!(codec_family("mp4a.40.2"), "aac");
+        assert_eq!(codec_family("theora"), "theora");
+
+        let formats = vec![
+            serde_json::json!({"format_id": "h264_540p", "url": "https://v/1.mp4", "ext": "mp4", "protocol": "https",
+                "width": 576, "height": 1024, "vcodec": "h264", "acodec": "aac", "fps": 30, "tbr": 1210.5}),
+            serde_json::json!({"format_id": "bytevc1_540p", "url": "https://v/2.mp4", "ext": "mp4", "protocol": "https",
+                "width": 576, "height": 1024, "vcodec": "bytevc1", "acodec": "aac", "fps": 30, "tbr": 880}),
+            serde_json::json!({"format_id": "h264_540p_dup", "url": "https://v/3.mp4", "ext": "mp4",
+                "width": 576, "height": 1024, "vcodec": "avc1.64001F", "acodec": "mp4a.40.2"}),
+        ];
+        let (video, _, _) = parse_formats(&formats, &FormatFilter::default());
+        assert_eq!(video.len(), 2);
+        assert_eq!(video[0].vcodec.as_deref(), Some("h264"));
+        assert_eq!(video[0].acodec.as_deref(), Some("aac"));
+        assert_eq!(video[0].fps, Some(30.0));
+        assert_eq!(video[0].tbr, Some(1210.5));
+        assert_eq!(video[0].protocol.as_deref(), Some("https"));
+        assert_eq!(video[1].vcodec.as_deref(), Some("h265"));
+
+        let silent = VideoFormat::details(&serde_json::json!({"vcodec": "vp9", "acodec": "none", "fps": null}));
+        assert_eq!(silent.vcodec.as_deref(), Some("vp9"));
+        assert!(silent.acodec.is_none() && silent.fps.is_none() && silent.ext.is_none());
+    }
+
+    #[test]
+    fn test_build_stats() {
+        let tiktok = serde_json::json!({
+            "view_count": 1200, "like_count": 300, "comment_count": 12, "repost_count": 4,
+            "save_count": 25, "quote_count": 9, "channel_follower_count": 50000,
+        });
+        let stats = build_stats(&tiktok, "tiktok");
+        assert_eq!(stats.bookmarks, Some(25));
+        assert_eq!(stats.quotes, None);
+        assert_eq!(stats.followers, Some(50000));
+
+        let x = serde_json::json!({"like_count": 10.0, "repost_count": 2, "quote_count": 1});
+        let stats = build_stats(&x, "x");
+        assert_eq!(stats.likes, Some(10));
+        assert_eq!(stats.quotes, Some(1));
+        assert_eq!(stats.views, None);
+        let json = serde_json::to_value(&stats).unwrap();
+        assert!(json["bookmarks"].is_null() && json.as_object().unwrap().len() == 7);
+    }
+
+    #[test]
+    fn test_build_music() {
+        let info = serde_json::json!({
+            "uploader": "Some Creator", "uploader_id": "creator", "duration": 15.2,
+            "track": "original sound - creator", "artists": ["Some Creator"],
+        });
+        let music = build_music(&info, Some("http://x/stream?id=s&format=best_audio".into())).unwrap();
+        assert!(music.original_sound);
+        assert_eq!(music.author.as_deref(), Some("Some Creator"));
+        assert_eq!(music.duration_seconds, Some(15.2));
+        assert!(music.audio_url.is_some());
+
+        let info = serde_json::json!({"uploader_id": "creator", "track": "Espresso", "artist": "Sabrina Carpenter"});
+        let music = build_music(&info, None).unwrap();
+        assert!(!music.original_sound);
+        assert_eq!(music.title.as_deref(), Some("Espresso"));
+
+        assert!(build_music(&serde_json::json!({"uploader": "x", "track": ""}), None).is_none());
+    }
+
+    #[test]
+    fn test_format_filter() {
+        let formats = vec![
+            serde_json::json!({"format_id": "http-1080", "url": "https://v/1080.mp4", "protocol": "https", "height": 1080, "vcodec": "avc1"}),
+            serde_json::json!({"format_id": "http-720", "url": "https://v/720.mp4", "protocol": "https", "height": 720, "vcodec": "avc1"}),
+            serde_json::json!({"format_id": "hls-2160", "url": "https://v/2160.m3u8", "protocol": "m3u8_native", "height": 2160, "vcodec": "avc1"}),
+            serde_json::json!({"format_id": "hls-480", "url": "https://v/480.m3u8", "protocol": "m3u8_native", "height": 480, "vcodec": "avc1"}),
+            serde_json::json!({"format_id": "hls-audio-128000", "url": "https://v/a.m3u8", "protocol": "m3u8_native", "vcodec": "none"}),
+        ];
+        let ids = |v: &[VideoFormat]| v.iter().map(|f| f.format_id.clone()).collect::<Vec<_>>();
+
+        let (video, audio, _) = parse_formats(&formats, &FormatFilter::default());
+        assert_eq!(ids(&video), ["http-1080", "http-720", "hls-2160", "hls-480"]);
+        assert_eq!(audio.len(), 1);
+
+        let filter = FormatFilter { max_height: Some(720), prefer: Some(Prefer::Hls), audio_only: None };
+        let (video, _, _) = parse_formats(&formats, &filter);
+        assert_eq!(ids(&video), ["hls-480", "http-720"]);
+
+        let filter = FormatFilter { audio_only: Some(true), ..Default::default() };
+        let (video, audio, _) = parse_formats(&formats, &filter);
+        assert!(video.is_empty());
+        assert_eq!(ids(&audio), ["hls-audio-128000"]);
+
+        // Body fields win over query parameters
+        let body = FormatFilter { max_height: Some(480), ..Default::default() };
+        let query = FormatFilter { max_height: Some(1080), audio_only: Some(false), ..Default::default() };
+        let merged = body.or(query);
+        assert_eq!((merged.max_height, merged.audio_only), (Some(480), Some(false)));
+        assert!(FormatFilter::default().is_empty());
+
+        // format=best is the first listed format, not any video in the session
+        let meta = SessionMeta { client_cookies: None, job_id: "j1", platform: "x", slideshow_enabled: false, filter: &filter, selected: &[] , url: "https://x.com/a/status/1" };
+        let (video, audio, images) = parse_formats(&formats, &FormatFilter { prefer: Some(Prefer::Hls), ..Default::default() });
+        let info = serde_json::json!({"id": "1", "formats": formats});
+        let data = build_session_data(&video, &audio, &images, &info, meta);
+        assert_eq!(data.best_video.as_deref(), Some("hls-2160"));
+        assert_eq!(data.best_audio.as_deref(), Some("hls-audio-128000"));
+    }
+
+    #[test]
+    fn test_build_estimate() {
+        let video = serde_json::json!({"id": "1", "extractor": "TikTok", "duration": 600.0, "formats": [
+            {"format_id": "http-720", "url": "https://v/720.mp4", "protocol": "https", "height": 720, "vcodec": "avc1", "filesize": 52_000_000},
+            {"format_id": "hls-1080", "url": "https://v/1080.m3u8", "protocol": "m3u8_native", "height": 1080, "vcodec": "avc1", "tbr": 4000.0},
+        ]});
+        let est = build_estimate(&video, "https://www.tiktok.com/@a/video/1", &FormatFilter::default(), true);
+        assert_eq!(est.content_type, "video");
+        assert_eq!(est.format.as_ref().unwrap().format_id, "http-720");
+        assert_eq!((est.size_bytes, est.size_estimated, est.transcoding), (Some(52_000_000), false, false));
+        assert_eq!(est.duration_formatted.as_deref(), Some("10:00"));
+
+        // HLS without a filesize: bitrate × duration, and FFmpeg for one file
+        let filter = FormatFilter { prefer: Some(Prefer::Hls), ..Default::default() };
+        let est = build_estimate(&video, "https://www.tiktok.com/@a/video/1", &filter, true);
+        assert_eq!((est.size_bytes, est.size_estimated), (Some(300_000_000), true));
+        assert_eq!(est.transcoding_reason.as_deref(), Some("hls"));
+
+        let photo = serde_json::json!({"id": "2", "formats": [
+            {"format_id": "image-1", "url": "https://i/1.jpg", "protocol": "https", "filesize": 200_000},
+            {"format_id": "audio", "url": "https://a/1.mp3", "protocol": "https", "vcodec": "none", "resolution": "audio only"},
+        ]});
+        let est = build_estimate(&photo, "https://www.tiktok.com/@a/photo/2", &FormatFilter::default(), true);
+        assert_eq!(est.content_type, "photo");
+        assert_eq!(est.transcoding_reason.as_deref(), Some("slideshow"));
+        assert!(!build_estimate(&photo, "https://www.tiktok.com/@a/photo/2", &FormatFilter::default(), false).transcoding);
+
+        let playlist = serde_json::json!({"_type": "playlist", "id": "3", "entries": [
+            {"id": "a", "duration": 10.0, "formats": [{"format_id": "http-720", "url": "https://v/a.mp4", "protocol": "https", "height": 720, "vcodec": "avc1", "filesize": 1000}]},
+            {"id": "b", "duration": 20.0, "formats": [{"format_id": "http-720", "url": "https://v/b.mp4", "protocol": "https", "height": 720, "vcodec": "avc1", "tbr": 8.0}]},
+        ]});
+        let est = build_estimate(&playlist, "https://x.com/a/status/3", &FormatFilter::default(), true);
+        assert_eq!(est.content_type, "playlist");
+        assert!(est.format.is_none());
+        assert_eq!((est.size_bytes, est.size_estimated), (Some(21_000), true));
+        assert_eq!((est.duration_seconds, est.playlist_count), (Some(30.0), Some(2)));
+    }
+
+    /// Session and /download response for `info`, the way /download builds
+    /// them (session "sid", base URL https://api.example).
+    fn download_of(info: &serde_json::Value, url: &str, filter: &FormatFilter) -> (SessionData, DownloadResponse) {
+        let formats = info["formats"].as_array().map(|v| v.as_slice()).unwrap_or(&[]);
+        let (video, audio, image) = parse_formats(formats, filter);
+        let platform = detect_platform(url, "");
+        let session = build_session_data(&video, &audio, &image, info, SessionMeta {
+            client_cookies: None,
+            job_id: "job",
+            platform: &platform,
+            slideshow_enabled: true,
+            filter,
+            selected: &[],
+            url,
+        });
+        let response = build_response_with_session(info, url, &video, &audio, &image, filter, "sid", "https://api.example");
+        (session, response)
+    }
+
+    /// Format of every /stream link in `response`.
+    fn stream_links(response: &DownloadResponse) -> Vec<String> {
+        let body = serde_json::to_string(response).unwrap();
+        body.split("https://api.example/stream?id=sid&format=")
+            .skip(1)
+            .map(|rest| rest[..rest.find(['"', '&']).unwrap()].to_string())
+            .collect()
+    }
+
+    /// error.rs, queue.rs and features.rs are copied into serverrs, which
+    /// builds on its own; the copies must not drift apart.
+    #[test]
+    fn test_shared_modules_match_serverrs() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        for name in ["error.rs", "queue.rs", "features.rs"] {
+            let ours = std::fs::read_to_string(root.join("src").join(name)).unwrap();
+            let theirs = std::fs::read_to_string(root.join("../serverrs/src").join(name))
+                .unwrap_or_else(|e| panic!("serverrs/src/{name}: {e}"));
+            assert!(ours == theirs, "src/{name} differs from serverrs/src/{name}; change both");
+        }
+    }
+
+    /// Replays the posts in `fixtures/` (see RECORD_FIXTURES_DIR)
+    /// through format parsing, the session and the /download response, and
+    /// their CDN exchanges through what /stream would send and serve.
+    #[test]
+    fn test_replay_fixtures() {
+        let fixtures = fixtures::load_dir(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures"));
+        assert!(!fixtures.is_empty());
+        for (name, fixture) in fixtures {
+            let info = &fixture.info;
+            let platform = detect_platform(&fixture.url, "");
+            assert_eq!(schema::violations(info, &platform), Vec::<String>::new(), "{name}");
+            let (session, response) = download_of(info, &fixture.url, &FormatFilter::default());
+            assert!(response.success, "{name}");
+
+            // Every link in the response streams a format the session holds
+            let links = stream_links(&response);
+            assert!(!links.is_empty(), "{name}");
+            for format in links {
+                assert!(session.resolve_format(&format).is_some(), "{name}: {format} not in session");
+            }
+
+            for (format_id, exchange) in &fixture.cdn {
+                let (_, format_info) = session.resolve_format(format_id).unwrap_or_else(|| panic!("{name}: {format_id} not in session"));
+                let sent = source_headers(&format_info, &session);
+                let sent = fixtures::redact_headers(sent.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+                assert_eq!(sent, exchange.request_headers, "{name}: {format_id}");
+                let served = upstream_content_type(exchange.response_headers.get("content-type").map(String::as_str), &format_info.content_type);
+                assert!(["video/", "audio/", "image/"].iter().any(|t| served.starts_with(t)), "{name}: {format_id} served as {served}");
+            }
+        }
+    }
+
+    /// The /download response of each fixture post, compared with
+    /// `fixtures/golden/`. After an intended change, UPDATE_GOLDEN=1 rewrites
+    /// the golden files for review in the diff.
+    #[test]
+    fn test_golden_responses() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let update = env::var_os("UPDATE_GOLDEN").is_some();
+        for (name, fixture) in fixtures::load_dir(&dir) {
+            let (_, response) = download_of(&fixture.info, &fixture.url, &FormatFilter::default());
+            let mut response = serde_json::to_value(&response).unwrap();
+            response["extracted_at"] = "(now)".into();
+            let actual = serde_json::to_string_pretty(&response).unwrap() + "\n";
+            let path = dir.join("golden").join(&name);
+            if update {
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, &actual).unwrap();
+                continue;
+            }
+            let expected = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("{name}: {e} (run with UPDATE_GOLDEN=1 to create it)"));
+            assert!(expected == actual, "{name} differs from fixtures/golden (UPDATE_GOLDEN=1 to accept):\n{actual}");
+        }
+    }
+
+    /// yt-dlp format dicts built from the values extractors actually use,
+    /// with any field missing.
+    fn arb_format() -> impl proptest::strategy::Strategy<Value = serde_json::Value> {
+        use proptest::prelude::*;
+        use proptest::sample::select;
+        (
+            select(vec!["http-632", "hls-832", "hls-audio-64000-Audio", "audio", "image-1", "download", "bytevc1_720p_900000-0", ""]),
+            select(vec!["https://cdn.example/v.mp4", "https://cdn.example/pl.m3u8", "https://cdn.example/i.jpeg", ""]),
+            proptest::option::of(select(vec!["none", "avc1", "avc1.640020", "h264", "h265"])),
+            proptest::option::of(select(vec!["none", "aac", "mp4a.40.2", "mp3"])),
+            proptest::option::of(select(vec!["https", "m3u8_native", "m3u8", "http_dash_segments"])),
+            proptest::option::of(-1i64..2500),
+            proptest::option::of(0i64..2500),
+            proptest::option::of(select(vec!["jpg", "webp", "mp4", "none"])),
+            proptest::option::of(select(vec!["audio only", "720x1280", ""])),
+            proptest::option::of(0.0f64..5000.0),
+            proptest::option::of(0i64..4_000_000_000),
+        )
+            .prop_map(|(format_id, url, vcodec, acodec, protocol, height, width, video_ext, resolution, tbr, filesize)| {
+                let mut fmt = serde_json::json!({"format_id": format_id, "url": url});
+                let fields = [
+                    ("vcodec", vcodec.map(serde_json::Value::from)),
+                    ("acodec", acodec.map(Into::into)),
+                    ("protocol", protocol.map(Into::into)),
+                    ("height", height.map(Into::into)),
+                    ("width", width.map(Into::into)),
+                    ("video_ext", video_ext.map(Into::into)),
+                    ("resolution", resolution.map(Into::into)),
+                    ("tbr", tbr.map(Into::into)),
+                    ("filesize", filesize.map(Into::into)),
+                ];
+                for (key, value) in fields {
+                    if let Some(value) = value {
+                        fmt[key] = value;
+                    }
+                }
+                fmt
+            })
+    }
+
+    fn arb_filter() -> impl proptest::strategy::Strategy<Value = FormatFilter> {
+        use proptest::prelude::*;
+        (
+            proptest::option::of(0i64..2500),
+            proptest::option::of(prop_oneof![Just(Prefer::Progressive), Just(Prefer::Hls)]),
+            proptest::option::of(any::<bool>()),
+        )
+            .prop_map(|(max_height, prefer, audio_only)| FormatFilter { max_height, prefer, audio_only })
+    }
+
+    /// Any JSON, with keys and strings mostly from the info dict so the
+    /// response builders get past their first lookups.
+    fn arb_json() -> impl proptest::strategy::Strategy<Value = serde_json::Value> {
+        use proptest::prelude::*;
+        use proptest::sample::select;
+        let words = vec![
+            "formats", "entries", "_type", "playlist", "id", "url", "format_id", "vcodec", "acodec", "none", "height",
+            "width", "protocol", "m3u8_native", "https", "duration", "timestamp", "upload_date", "title", "thumbnail",
+            "thumbnails", "chapters", "subtitles", "uploader", "uploader_id", "extractor", "resolution", "audio only",
+            "video_ext", "ext", "abr", "tbr", "filesize", "http_headers", "track", "artist", "image-1", "https://a/b.m3u8",
+        ];
+        let leaf = prop_oneof![
+            Just(serde_json::Value::Null),
+            any::<bool>().prop_map(Into::into),
+            any::<i64>().prop_map(Into::into),
+            any::<f64>().prop_map(Into::into),
+            select(words.clone()).prop_map(Into::into),
+            ".{0,6}".prop_map(Into::into),
+        ];
+        leaf.prop_recursive(4, 64, 8, move |inner| {
+            prop_oneof![
+                proptest::collection::vec(inner.clone(), 0..6).prop_map(serde_json::Value::Array),
+                proptest::collection::vec((select(words.clone()), inner), 0..8)
+                    .prop_map(|fields| serde_json::Value::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())),
+            ]
+        })
+    }
+
+    proptest::proptest! {
+        /// Every format parse_formats returns comes from the input, landed in
+        /// the list of its kind, and passes the filter; videos are tallest
+        /// first within each protocol.
+        #[test]
+        fn prop_parse_formats(formats in proptest::collection::vec(arb_format(), 0..12), filter in arb_filter()) {
+            let (video, audio, image) = parse_formats(&formats, &filter);
+            // Input formats `f` may have come from (ids can repeat), with their kind
+            let sources = |f: &VideoFormat| {
+                formats
+                    .iter()
+                    .filter(|s| s["format_id"] == f.format_id.as_str() && s["url"] == f.url.as_str())
+                    .filter_map(|s| Some((classify_format(s)?, s["height"].as_i64().unwrap_or(0))))
+                    .collect::<Vec<_>>()
+            };
+            let mut last: Option<(bool, i64)> = None;
+            for f in &video {
+                let height: i64 = f.quality.split('p').next().unwrap().parse().unwrap();
+                let hls = f.quality.ends_with("(hls)");
+                let kind = if hls { FormatKind::VideoOnly } else { FormatKind::Progressive };
+                proptest::prop_assert!(sources(f).contains(&(kind, height)), "{} {}", f.format_id, f.quality);
+                proptest::prop_assert!(filter.max_height.is_none_or(|max| height <= max));
+                if let Some((last_hls, last_height)) = last {
+                    proptest::prop_assert!(last_hls != hls || height <= last_height);
+                }
+                last = Some((hls, height));
+            }
+            for f in &audio {
+                proptest::prop_assert!(sources(f).iter().any(|(kind, _)| *kind == FormatKind::Audio), "{}", f.format_id);
+            }
+            for f in &image {
+                proptest::prop_assert!(sources(f).iter().any(|(kind, _)| *kind == FormatKind::Image), "{}", f.format_id);
+            }
+            if filter.audio_only == Some(true) {
+                proptest::prop_assert!(video.is_empty() && image.is_empty());
+            }
+            let qualities: std::collections::HashSet<&str> = audio.iter().map(|f| f.quality.as_str()).collect();
+            proptest::prop_assert_eq!(qualities.len(), audio.len());
+        }
+
+        /// Posts and playlists of arbitrary formats: the response links only
+        /// to formats the session can stream.
+        #[test]
+        fn prop_download_links_resolve(
+            formats in proptest::collection::vec(arb_format(), 0..8),
+            entries in proptest::option::of(proptest::collection::vec(proptest::collection::vec(arb_format(), 0..4), 0..4)),
+            filter in arb_filter(),
+            tiktok in proptest::bool::ANY,
+        ) {
+            let url = if tiktok { "https://www.tiktok.com/@a/video/1" } else { "https://x.com/a/status/1" };
+            let mut info = serde_json::json!({"id": "1", "formats": formats});
+            if let Some(entries) = entries {
+                info["_type"] = "playlist".into();
+                info["entries"] = entries.into_iter().enumerate().map(|(i, f)| serde_json::json!({"id": i.to_string(), "formats": f})).collect();
+            }
+            let (session, response) = download_of(&info, url, &filter);
+            proptest::prop_assert!(response.success);
+            for format in stream_links(&response) {
+                proptest::prop_assert!(session.resolve_format(&format).is_some(), "{} not in session", format);
+            }
+        }
+
+        /// Whatever yt-dlp returns, building the response doesn't panic.
+        #[test]
+        fn prop_download_of_any_json(info in arb_json(), tiktok in proptest::bool::ANY) {
+            let url = if tiktok { "https://www.tiktok.com/@a/video/1" } else { "https://x.com/a/status/1" };
+            let (_, response) = download_of(&info, url, &FormatFilter::default());
+            proptest::prop_assert!(response.success);
+            let _ = schema::violations(&info, "x");
+        }
+    }
+
+    #[test]
+    fn test_selected_formats() {
+        let info = serde_json::json!({
+            "id": "1", "format": "hls-2176 - 720x1280+hls-audio-128000 - audio only", "format_id": "hls-2176+hls-audio-128000",
+            "requested_formats": [
+                {"format_id": "hls-2176", "url": "https://v/720.m3u8", "width": 720, "height": 1280, "vcodec": "avc1.64001F", "acodec": "none"},
+                {"format_id": "hls-audio-128000", "url": "https://v/a.m3u8", "vcodec": "none", "acodec": "mp4a.40.2", "abr": 128.0},
+            ],
+        });
+        let picked = selected_formats(&info);
+        assert_eq!(picked.len(), 2);
+        assert_eq!((picked[0].quality.as_str(), picked[0].resolution.as_str()), ("1280p", "720x1280"));
+        assert_eq!((picked[1].quality.as_str(), picked[1].resolution.as_str()), ("128kbps", "audio only"));
+
+        let meta = SessionMeta { client_cookies: None, job_id: "j1", platform: "x", slideshow_enabled: false, filter: &FormatFilter::default(), selected: &picked , url: "https://x.com/a/status/1" };
+        let data = build_session_data(&[], &[], &[], &info, meta);
+        assert!(data.formats.contains_key("hls-2176") && data.formats.contains_key("hls-audio-128000"));
+
+        // A single pick is described by the info itself
+        let info = serde_json::json!({"format_id": "http-832", "url": "https://v/480.mp4", "width": 480, "height": 852, "vcodec": "avc1"});
+        assert_eq!(selected_formats(&info)[0].format_id, "http-832");
+        assert!(selected_formats(&serde_json::json!({"id": "1"})).is_empty());
+
+        assert!(valid_format_selector("bv*[height<=1080]+ba/b"));
+        assert!(!valid_format_selector("b\n"));
+        assert!(!valid_format_selector(&"b".repeat(300)));
+    }
+
+    #[test]
+    fn test_playlist_entry_audio() {
+        let entries = vec![
+            serde_json::json!({"id": "e1", "formats": [
+                {"format_id": "http-720", "url": "https://v/720.mp4", "protocol": "https", "height": 720, "width": 1280, "vcodec": "avc1"},
+                {"format_id": "audio-128", "url": "https://v/a.m4a", "protocol": "https", "vcodec": "none", "acodec": "mp4a.40.2"},
+            ]}),
+            serde_json::json!({"id": "e2", "formats": [
+                {"format_id": "audio-64", "url": "https://v/b.m4a", "protocol": "https", "vcodec": "none", "resolution": "audio only"},
+            ]}),
+        ];
+        let info = serde_json::json!({"id": "p", "entries": entries});
+        let resp = build_playlist_response(&info, &entries, "x", "https://x.com/i/status/1", &[], &[], &[], &FormatFilter::default(), "sid", "http://h");
+        let data = resp.data.unwrap();
+        let entries_out = &data.entries;
+        assert_eq!(entries_out[0].media_type, "video");
+        assert_eq!(entries_out[0].best_audio_url.as_deref(), Some("http://h/stream?id=sid&format=e1_audio-128"));
+        assert_eq!(entries_out[1].media_type, "audio");
+        assert_eq!(entries_out[1].formats.len(), 1);
+        assert_eq!(data.content_type, "mixed");
+
+        // Every linked entry format is in the session
+        let meta = SessionMeta { client_cookies: None, job_id: "j1", platform: "x", slideshow_enabled: false, filter: &FormatFilter::default(), selected: &[] , url: "https://x.com/a/status/1" };
+        let data = build_session_data(&[], &[], &[], &info, meta);
+        for key in ["e1_http-720", "e1_audio-128", "e2_audio-64"] {
+            assert!(data.formats.contains_key(key), "{key}");
+        }
+    }
+
+    #[test]
+    fn test_parse_dates() {
+        let info = serde_json::json!({"timestamp": 1714567890.0, "upload_date": "20240501"});
+        let (created_at, upload_date) = parse_dates(&info);
+        assert_eq!(created_at.as_deref(), Some("2024-05-01T12:51:30Z"));
+        assert_eq!(upload_date.as_deref(), Some("2024-05-01"));
+
+        let (created_at, upload_date) = parse_dates(&serde_json::json!({"release_timestamp": 1714608000}));
+        assert_eq!(created_at.as_deref(), Some("2024-05-02T00:00:00Z"));
+        assert_eq!(upload_date.as_deref(), Some("2024-05-02"));
+
+        let (created_at, upload_date) = parse_dates(&serde_json::json!({"upload_date": "20240501"}));
+        assert_eq!(created_at.as_deref(), Some("2024-05-01T00:00:00Z"));
+        assert_eq!(upload_date.as_deref(), Some("2024-05-01"));
+
+        assert_eq!(parse_dates(&serde_json::json!({"upload_date": "2024"})), (None, None));
+    }
+
+    #[test]
+    fn test_build_chapters() {
+        let info = serde_json::json!({"chapters": [
+            {"start_time": 0.0, "end_time": 95.5, "title": "Intro"},
+            {"start_time": 95.5, "end_time": 95.5, "title": "Empty"},
+            {"start_time": 95.5, "end_time": 1800.0, "title": ""},
+        ]});
+        let chapters = build_chapters(&info);
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title.as_deref(), Some("Intro"));
+        assert_eq!((chapters[1].start, chapters[1].end), (95.5, 1800.0));
+        assert!(chapters[1].title.is_none());
+        assert!(build_chapters(&serde_json::json!({"chapters": null})).is_empty());
+    }
+
+    #[test]
+    fn test_build_subtitles() {
+        let info = serde_json::json!({"subtitles": {
+            "eng-US": [{"ext": "json3", "url": "https://s/en.json"}, {"ext": "srt", "url": "https://s/en.srt"},
+                       {"ext": "vtt", "url": "https://s/en.vtt", "name": "English"}],
+            "ind-ID": [{"ext": "srt", "url": "https://s/id.srt"}],
+            "fr": [{"ext": "json3", "url": "https://s/fr.json"}]
+        }, "automatic_captions": {"es": [{"ext": "vtt", "url": "https://s/es.vtt"}]}});
+        let subtitles = build_subtitles(&info);
+        let langs: Vec<(&str, &str)> = subtitles.iter().map(|(t, _)| (t.lang.as_str(), t.ext.as_str())).collect();
+        assert_eq!(langs, [("eng-US", "vtt"), ("ind-ID", "srt")]);
+        assert_eq!(subtitles[0].0.name.as_deref(), Some("English"));
+        assert_eq!(subtitles[0].1.url, "https://s/en.vtt");
+        assert!(build_subtitles(&serde_json::json!({})).is_empty());
+
+        let data = SessionData {
+            video_id: "1".into(),
+            job_id: "j1".into(),
+            platform: "tiktok".into(),
+            cookies: None,
+            formats: HashMap::new(),
+            slideshow: false,
+            chapters: vec![],
+            subtitles: subtitles.into_iter().map(|(_, source)| source).collect(),
+            best_video: None,
+            best_audio: None,
+            source_url: String::new(),
+            duration_secs: None,
+        };
+        let lang = |l: &str| data.subtitle(l).map(|s| s.lang.as_str());
+        assert_eq!(lang("ENG-us"), Some("eng-US"));
+        assert_eq!(lang("ind"), Some("ind-ID"));
+        assert_eq!(lang("ind_ID"), Some("ind-ID"));
+        assert_eq!(lang("es"), None);
+    }
+
+    #[test]
+    fn test_duration_exceeding() {
+        let post = serde_json::json!({"duration": 7300.5});
+        assert_eq!(duration_exceeding(&post, 7200), Some(7300.5));
+        assert_eq!(duration_exceeding(&post, 7301), None);
+        // Live streams have no duration yet
+        assert_eq!(duration_exceeding(&serde_json::json!({"is_live": true}), 60), None);
+        let playlist = serde_json::json!({"entries": [{"duration": 30}, {"duration": 9000}, {}]});
+        assert_eq!(duration_exceeding(&playlist, 7200), Some(9000.0));
+        assert_eq!(duration_exceeding(&playlist, 9000), None);
+    }
+
+    #[test]
+    fn test_session_key_and_base_url() {
+        assert_eq!(session_key("download", "abc"), "download:abc");
+        assert_eq!(session_key("eu:serverx", "abc"), "eu:serverx:abc");
+
+        assert_eq!(base_url_from(Some("https://dl.example.com/ ".into())), "https://dl.example.com");
+        assert_eq!(base_url_from(Some(String::new())), "http://localhost:8025");
+        assert_eq!(base_url_from(None), "http://localhost:8025");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-host", "dl.example.org, proxy.internal".parse().unwrap());
+        headers.insert("x-forwarded-proto", "http".parse().unwrap());
+        assert_eq!(public_base_url("https://dl.example.com", &headers, false), "https://dl.example.com");
+        assert_eq!(public_base_url("https://dl.example.com", &headers, true), "http://dl.example.org");
+        assert_eq!(public_base_url("https://dl.example.com/api", &headers, true), "http://dl.example.org/api");
+        headers.remove("x-forwarded-proto");
+        assert_eq!(public_base_url("https://dl.example.com", &headers, true), "https://dl.example.org");
+        headers.insert("x-forwarded-host", "evil.com/path?".parse().unwrap());
+        assert_eq!(public_base_url("https://dl.example.com", &headers, true), "https://dl.example.com");
+        assert_eq!(public_base_url("https://dl.example.com", &HeaderMap::new(), true), "https://dl.example.com");
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the extraction duration histogram buckets.
+const DURATION_BUCKETS: [f64; 10] = [0.5, 1.0, 2.0, 3.0, 5.0, 10.0, 15.0, 20.0, 30.0, 45.0];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (i, bound) in DURATION_BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[i] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// In-process metrics, rendered in Prometheus text format by GET /metrics.
+#[derive(Default)]
+pub struct Metrics {
+    extraction_duration: Mutex<BTreeMap<String, Histogram>>,
+    extraction_outcomes: Mutex<BTreeMap<(String, String), u64>>,
+    schema_violations: Mutex<BTreeMap<(String, String), u64>>,
+}
+
+impl Metrics {
+    /// Record how long a single extract_with_ytdlp() call took.
+    pub fn observe_extraction(&self, platform: &str, elapsed: Duration) {
+        let mut hists = self.extraction_duration.lock().unwrap();
+        hists
+            .entry(platform.to_string())
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Count an extraction outcome: "success", "timeout" or an error class.
+    pub fn record_outcome(&self, platform: &str, outcome: &str) {
+        let mut outcomes = self.extraction_outcomes.lock().unwrap();
+        *outcomes
+            .entry((platform.to_string(), outcome.to_string()))
+            .or_default() += 1;
+    }
+
+    /// Count an info dict field that was missing or of the wrong type.
+    pub fn record_schema_violation(&self, platform: &str, field: &str) {
+        let mut violations = self.schema_violations.lock().unwrap();
+        *violations
+            .entry((platform.to_string(), field.to_string()))
+            .or_default() += 1;
+    }
+
+    /// Mean extract_with_ytdlp() duration across all platforms, if any were recorded.
+    pub fn mean_extraction_secs(&self) -> Option<f64> {
+        let hists = self.extraction_duration.lock().unwrap();
+        let (sum, count) = hists
+            .values()
+            .fold((0.0, 0u64), |(sum, count), h| (sum + h.sum, count + h.count));
+        (count > 0).then(|| sum / count as f64)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP ytdlp_extraction_duration_seconds Duration of yt-dlp extract_info calls.\n");
+        out.push_str("# TYPE ytdlp_extraction_duration_seconds histogram\n");
+        for (platform, hist) in self.extraction_duration.lock().unwrap().iter() {
+            for (bound, count) in DURATION_BUCKETS.iter().zip(hist.buckets.iter()) {
+                let _ = writeln!(
+                    out,
+                    "ytdlp_extraction_duration_seconds_bucket{{platform=\"{platform}\",le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "ytdlp_extraction_duration_seconds_bucket{{platform=\"{platform}\",le=\"+Inf\"}} {}",
+                hist.count
+            );
+            let _ = writeln!(
+                out,
+                "ytdlp_extraction_duration_seconds_sum{{platform=\"{platform}\"}} {}",
+                hist.sum
+            );
+            let _ = writeln!(
+                out,
+                "ytdlp_extraction_duration_seconds_count{{platform=\"{platform}\"}} {}",
+                hist.count
+            );
+        }
+
+        out.push_str("# HELP ytdlp_extractions_total Extraction attempts by outcome (success, timeout or error class).\n");
+        out.push_str("# TYPE ytdlp_extractions_total counter\n");
+        for ((platform, outcome), count) in self.extraction_outcomes.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "ytdlp_extractions_total{{platform=\"{platform}\",outcome=\"{outcome}\"}} {count}"
+            );
+        }
+
+        out.push_str("# HELP ytdlp_schema_violations_total Info dict fields missing or of the wrong type (SCHEMA_CHANGED).\n");
+        out.push_str("# TYPE ytdlp_schema_violations_total counter\n");
+        for ((platform, field), count) in self.schema_violations.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "ytdlp_schema_violations_total{{platform=\"{platform}\",field=\"{field}\"}} {count}"
+            );
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_histogram_and_outcomes() {
+        let metrics = Metrics::default();
+        metrics.observe_extraction("x", Duration::from_millis(1500));
+        metrics.observe_extraction("x", Duration::from_secs(60));
+        metrics.record_outcome("x", "success");
+        metrics.record_outcome("x", "timeout");
+        metrics.record_outcome("x", "timeout");
+        metrics.record_schema_violation("tiktok", "formats[].url");
+
+        let out = metrics.render();
+        assert!(out.contains("ytdlp_extraction_duration_seconds_bucket{platform=\"x\",le=\"1\"} 0"));
+        assert!(out.contains("ytdlp_extraction_duration_seconds_bucket{platform=\"x\",le=\"2\"} 1"));
+        assert!(out.contains("ytdlp_extraction_duration_seconds_bucket{platform=\"x\",le=\"+Inf\"} 2"));
+        assert!(out.contains("ytdlp_extraction_duration_seconds_count{platform=\"x\"} 2"));
+        assert!(out.contains("ytdlp_extractions_total{platform=\"x\",outcome=\"timeout\"} 2"));
+        assert!(out.contains("ytdlp_schema_violations_total{platform=\"tiktok\",field=\"formats[].url\"} 1"));
+    }
+}
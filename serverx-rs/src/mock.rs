@@ -0,0 +1,195 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::error::ExtractError;
+use crate::fixtures::Fixture;
+use crate::{classify_format, FormatKind};
+
+/// Posts MOCK_EXTRACTOR answers for: one of each kind /download handles.
+const FIXTURES: [&str; 7] = [
+    // Video with progressive, HLS and audio formats
+    include_str!("../fixtures/x_1790000000000000001.json"),
+    // Gallery (two photos, as playlist entries)
+    include_str!("../fixtures/x_1790000000000000002.json"),
+    // Video with HLS formats only
+    include_str!("../fixtures/x_1790000000000000004.json"),
+    // Playlist of two videos
+    include_str!("../fixtures/x_1790000000000000003.json"),
+    // Audio only (a Space)
+    include_str!("../fixtures/x_1SampleSpace01.json"),
+    // TikTok photo post with sound (slideshow)
+    include_str!("../fixtures/tiktok_7300000000000000001.json"),
+    // TikTok video (H.264, H.265 and the watermarked download)
+    include_str!("../fixtures/tiktok_7300000000000000002.json"),
+];
+
+/// Stands in for yt-dlp (MOCK_EXTRACTOR): the synthetic posts in `fixtures/`,
+/// matched by their id in the requested URL, with every media URL pointed at
+/// this server's GET /mock/media, so /download, /estimate, /stream and
+/// /slideshow run end to end without Python, cookies or network access.
+/// The placeholder video doesn't decode, so the FFmpeg endpoints that read
+/// video (/probe, /preview, chapter clips, re-encodes) fail on it.
+pub struct MockExtractor {
+    posts: Vec<Fixture>,
+}
+
+impl MockExtractor {
+    /// `media_base` is where /mock/media is reachable from this process,
+    /// e.g. `http://127.0.0.1:8025/mock/media`.
+    pub fn new(media_base: &str) -> Self {
+        let posts = FIXTURES
+            .iter()
+            .map(|raw| {
+                let mut post: Fixture = serde_json::from_str(raw).expect("Invalid mock fixture");
+                point_media_at(&mut post.info, media_base);
+                post
+            })
+            .collect();
+        Self { posts }
+    }
+
+    /// Post URLs with a canned extraction.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        self.posts.iter().map(|p| p.url.as_str())
+    }
+
+    pub fn extract(&self, url: &str) -> Result<String, ExtractError> {
+        let path = url.split(['?', '#']).next().unwrap_or("");
+        self.posts
+            .iter()
+            .find(|p| p.info["id"].as_str().is_some_and(|id| path.split('/').any(|seg| seg == id)))
+            .map(|p| p.info.to_string())
+            .ok_or_else(|| ExtractError::NotFound(format!("No mock fixture for {url}")))
+    }
+}
+
+/// Replace format and thumbnail URLs, in entries too, with /mock/media files
+/// of the same kind (and size, for images).
+fn point_media_at(item: &mut serde_json::Value, media_base: &str) {
+    if let Some(formats) = item["formats"].as_array_mut() {
+        for fmt in formats {
+            let file = match classify_format(fmt) {
+                Some(FormatKind::Image) => format!(
+                    "image_{}x{}.jpg",
+                    fmt["width"].as_u64().unwrap_or(320),
+                    fmt["height"].as_u64().unwrap_or(320)
+                ),
+                Some(FormatKind::Audio) => "audio.mp3".into(),
+                _ => "video.mp4".into(),
+            };
+            fmt["url"] = format!("{media_base}/{file}").into();
+        }
+    }
+    if item["thumbnail"].is_string() {
+        item["thumbnail"] = format!("{media_base}/thumbnail.jpg").into();
+    }
+    if let Some(thumbnails) = item["thumbnails"].as_array_mut() {
+        for thumb in thumbnails {
+            thumb["url"] = format!("{media_base}/thumbnail.jpg").into();
+        }
+    }
+    if let Some(entries) = item["entries"].as_array_mut() {
+        for entry in entries {
+            point_media_at(entry, media_base);
+        }
+    }
+}
+
+/// Largest side of a placeholder image.
+const MAX_IMAGE_SIDE: u32 = 4096;
+
+/// GET /mock/media/{file} — placeholder media for MOCK_EXTRACTOR formats:
+/// `image_{w}x{h}.jpg` (any other `.jpg` is 320x320) is a grey JPEG,
+/// `audio.mp3` is a second of silence and `video.mp4` only carries the
+/// signature /stream sniffs and doesn't play.
+pub async fn media(Path(file): Path<String>) -> Response {
+    let (content_type, body) = match file.rsplit_once('.') {
+        Some((name, "jpg")) => {
+            let (w, h) = name
+                .strip_prefix("image_")
+                .and_then(|size| size.split_once('x'))
+                .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                .unwrap_or((320, 320));
+            ("image/jpeg", placeholder_jpeg(w.clamp(1, MAX_IMAGE_SIDE), h.clamp(1, MAX_IMAGE_SIDE)))
+        }
+        Some(("video", "mp4")) => {
+            let mut body = b"\0\0\0\x18ftypisom\0\0\x02\0isommp41".to_vec();
+            body.extend_from_slice(&padding_box(b"free"));
+            ("video/mp4", body)
+        }
+        Some(("audio", "mp3")) => ("audio/mpeg", silent_mp3()),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    ([("Content-Type", content_type)], body).into_response()
+}
+
+fn placeholder_jpeg(width: u32, height: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([200, 200, 200])))
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut out, 50))
+        .expect("Encoding a placeholder JPEG can't fail");
+    out
+}
+
+/// Frames in the placeholder MP3, 1152 samples each at 44.1 kHz.
+const MP3_FRAMES: usize = 40;
+
+/// An empty ID3v2.4 tag, then MP3_FRAMES MPEG-1 Layer III frames (128
+/// kbit/s, 44.1 kHz, mono) whose side info and main data are all zero:
+/// every granule has no Huffman bits, so decoders play silence.
+fn silent_mp3() -> Vec<u8> {
+    // 144 * 128000 / 44100 bytes, without the padding slot
+    const FRAME_LEN: usize = 417;
+    let mut body = b"ID3\x04\0\0\0\0\0\0".to_vec();
+    for _ in 0..MP3_FRAMES {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0xC4];
+        frame.resize(FRAME_LEN, 0);
+        body.extend_from_slice(&frame);
+    }
+    body
+}
+
+/// A 16 KiB MP4 box of type `kind` filled with zeros.
+fn padding_box(kind: &[u8; 4]) -> Vec<u8> {
+    let len: u32 = 16 * 1024;
+    let mut b = len.to_be_bytes().to_vec();
+    b.extend_from_slice(kind);
+    b.resize(len as usize, 0);
+    b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_extract_and_media() {
+        let mock = MockExtractor::new("http://127.0.0.1:8025/mock/media");
+        assert_eq!(mock.urls().count(), FIXTURES.len());
+
+        let info: serde_json::Value =
+            serde_json::from_str(&mock.extract("https://twitter.com/someone/status/1790000000000000002?s=20").unwrap()).unwrap();
+        assert_eq!(info["_type"], "playlist");
+        assert_eq!(info["entries"][0]["formats"][0]["url"], "http://127.0.0.1:8025/mock/media/image_2048x1536.jpg");
+        let info: serde_json::Value = serde_json::from_str(&mock.extract("https://x.com/i/spaces/1SampleSpace01").unwrap()).unwrap();
+        assert_eq!(info["formats"][0]["url"], "http://127.0.0.1:8025/mock/media/audio.mp3");
+        assert!(matches!(mock.extract("https://x.com/a/status/17900000000000000011"), Err(ExtractError::NotFound(_))));
+
+        for (file, expected) in [("image_30x20.jpg", "image/jpeg"), ("video.mp4", "video/mp4"), ("audio.mp3", "audio/mpeg")] {
+            let resp = media(Path(file.to_string())).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(crate::sniff::sniff(&body, expected).map(|(mime, _)| mime), Some(expected), "{file}");
+        }
+        assert_eq!(media(Path("other.gif".to_string())).await.status(), StatusCode::NOT_FOUND);
+
+        // Every frame of the MP3 starts with the same header, back to back
+        let mp3 = silent_mp3();
+        let frames = &mp3[10..];
+        assert_eq!(frames.len(), MP3_FRAMES * 417);
+        assert!(frames.chunks(417).all(|f| f[..4] == [0xFF, 0xFB, 0x90, 0xC4]));
+    }
+}
@@ -0,0 +1,101 @@
+use image::codecs::jpeg::{JpegDecoder, JpegEncoder};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder};
+use std::io::Cursor;
+
+/// Re-encode quality for JPEGs that have to be rotated.
+const JPEG_QUALITY: u8 = 90;
+
+/// Apply a JPEG's EXIF orientation to the pixels and drop the EXIF block, so
+/// clients that ignore the tag don't show the photo sideways. Upright JPEGs
+/// only lose their EXIF segment (no re-encode); other formats are returned
+/// untouched. CPU-bound — call from spawn_blocking.
+pub fn auto_orient(bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return Ok(bytes);
+    }
+    let mut decoder = JpegDecoder::new(Cursor::new(&bytes)).map_err(|e| e.to_string())?;
+    let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+    if orientation == Orientation::NoTransforms {
+        return strip_exif(&bytes);
+    }
+
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    image.apply_orientation(orientation);
+    let mut out = Vec::with_capacity(bytes.len());
+    image
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))
+        .map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+/// Copy a JPEG without its APP1 "Exif" segments. Everything from the start
+/// of scan on is copied as-is.
+fn strip_exif(jpeg: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(jpeg.len());
+    out.extend_from_slice(&jpeg[..2]);
+    let mut pos = 2;
+    while pos + 4 <= jpeg.len() {
+        if jpeg[pos] != 0xFF {
+            return Err(format!("Malformed JPEG marker at byte {}", pos));
+        }
+        let marker = jpeg[pos + 1];
+        // Start of scan: entropy-coded data follows, no more metadata
+        if marker == 0xDA {
+            break;
+        }
+        let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > jpeg.len() {
+            return Err(format!("Truncated JPEG segment at byte {}", pos));
+        }
+        let is_exif = marker == 0xE1 && jpeg[pos + 4..end].starts_with(b"Exif\0\0");
+        if !is_exif {
+            out.extend_from_slice(&jpeg[pos..end]);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&jpeg[pos..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbImage};
+
+    /// 2x1 JPEG with an APP1 Exif segment carrying `orientation`.
+    fn jpeg_with_orientation(orientation: u8) -> Vec<u8> {
+        let mut plain = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 1, image::Rgb([200, 10, 10])))
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut plain, 95))
+            .unwrap();
+        // Big-endian TIFF with one IFD entry: 0x0112 Orientation, SHORT, 1
+        let mut tiff = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        tiff.extend_from_slice(&[0, orientation, 0, 0, 0, 0, 0, 0]);
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(&tiff);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((app1.len() + 2) as u16).to_be_bytes());
+        jpeg.extend_from_slice(&app1);
+        jpeg.extend_from_slice(&plain[2..]);
+        jpeg
+    }
+
+    #[test]
+    fn test_auto_orient_rotates_and_strips_exif() {
+        let rotated = auto_orient(jpeg_with_orientation(6)).unwrap();
+        let image = image::load_from_memory(&rotated).unwrap();
+        assert_eq!(image.dimensions(), (1, 2));
+        assert!(!rotated.windows(6).any(|w| w == b"Exif\0\0"));
+
+        let upright = jpeg_with_orientation(1);
+        let stripped = auto_orient(upright.clone()).unwrap();
+        assert!(!stripped.windows(6).any(|w| w == b"Exif\0\0"));
+        assert_eq!(stripped.len(), upright.len() - 4 - 6 - 26);
+        assert_eq!(image::load_from_memory(&stripped).unwrap().dimensions(), (2, 1));
+
+        let png = b"\x89PNG\r\n\x1a\n".to_vec();
+        assert_eq!(auto_orient(png.clone()).unwrap(), png);
+    }
+}
@@ -0,0 +1,146 @@
+use serde::Deserialize;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use tracing::info;
+
+use crate::slideshow::run_ffmpeg;
+
+/// Length of a preview, seconds.
+pub const PREVIEW_SECONDS: f64 = 3.0;
+
+/// Previews fit in this many pixels on their longer side.
+const MAX_SIDE: u32 = 480;
+
+/// Previews up to this size are cached for the session; bigger ones (long
+/// WebPs of busy scenes) are streamed from their work folder once.
+pub const MAX_CACHED_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Bounds of the preview cache, across sessions; the oldest go first.
+pub const CACHE_ENTRIES: usize = 512;
+pub const CACHE_BYTES: u64 = 128 * 1024 * 1024;
+
+/// Where a preview starts: the requested second, rounded to a whole one so
+/// nearby hovers share a cached preview, and early enough for a full
+/// preview when the duration is known.
+pub fn start_secs(requested: Option<f64>, duration: Option<f64>) -> u64 {
+    let start = requested.filter(|s| s.is_finite()).unwrap_or(0.0).max(0.0).round();
+    let last = duration.filter(|d| d.is_finite()).map_or(f64::MAX, |d| (d - PREVIEW_SECONDS).floor().max(0.0));
+    start.min(last) as u64
+}
+
+/// What GET /preview produces.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewOutput {
+    /// Muted H.264 MP4, for `<video muted autoplay loop>`
+    #[default]
+    Mp4,
+    /// Looping animated WebP, for `<img>`
+    Webp,
+}
+
+impl PreviewOutput {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Mp4 => "video/mp4",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    /// ffprobe's codec_name of the video stream
+    pub fn codec(self) -> &'static str {
+        match self {
+            Self::Mp4 => "h264",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Webp => "webp",
+        }
+    }
+}
+
+/// ffmpeg arguments turning PREVIEW_SECONDS of `input` (requested with
+/// `headers`) from `start` into a small muted preview at `output_path`.
+fn preview_args(input: &str, headers: &[(String, String)], start: f64, output: PreviewOutput, output_path: &str) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    if !headers.is_empty() {
+        let joined: String = headers.iter().map(|(k, v)| format!("{k}: {v}\r\n")).collect();
+        args.extend(["-headers".to_string(), joined]);
+    }
+    args.extend(["-ss", &format!("{start:.3}"), "-t", &format!("{PREVIEW_SECONDS:.3}"), "-i", input, "-an"].map(String::from));
+    let scale = format!(
+        "scale=w='min({MAX_SIDE},iw)':h='min({MAX_SIDE},ih)':force_original_aspect_ratio=decrease:force_divisible_by=2"
+    );
+    match output {
+        PreviewOutput::Mp4 => args.extend(
+            ["-vf", &scale, "-c:v", "libx264", "-preset", "veryfast", "-crf", "28", "-pix_fmt", "yuv420p", "-movflags", "+faststart"]
+                .map(String::from),
+        ),
+        PreviewOutput::Webp => args.extend(
+            ["-vf", &format!("fps=12,{scale}"), "-c:v", "libwebp", "-quality", "60", "-loop", "0", "-f", "webp"].map(String::from),
+        ),
+    }
+    args.push(output_path.to_string());
+    args
+}
+
+/// Run ffmpeg to make a preview of `input` at `output_path`, killing it
+/// once `stop` is set, and return its size. Blocking — call from
+/// spawn_blocking.
+pub fn generate(
+    input: &str,
+    headers: &[(String, String)],
+    start: f64,
+    output: PreviewOutput,
+    output_path: &str,
+    stop: &AtomicBool,
+) -> Result<u64, String> {
+    info!("Generating {:?} preview from {:.1}s", output, start);
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(preview_args(input, headers, start, output, output_path));
+    run_ffmpeg(cmd, output_path, stop)?;
+    let size = std::fs::metadata(output_path).map_err(|e| e.to_string())?.len();
+    if size == 0 {
+        // -ss past the end leaves an empty file
+        return Err("No video to preview".into());
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_args() {
+        let headers = vec![("Referer".to_string(), "https://x.com/".to_string())];
+        let args = preview_args("https://v/a.mp4", &headers, 1.5, PreviewOutput::Mp4, "p.mp4");
+        assert_eq!(args[1..3], ["-headers", "Referer: https://x.com/\r\n"]);
+        assert!(args.windows(2).any(|w| w == ["-ss", "1.500"]));
+        assert!(args.windows(2).any(|w| w == ["-t", "3.000"]));
+        assert!(args.contains(&"-an".to_string()));
+        assert!(args.windows(2).any(|w| w == ["-c:v", "libx264"]));
+        assert_eq!(args.last().unwrap(), "p.mp4");
+
+        let webp = preview_args("https://v/a.mp4", &[], 0.0, PreviewOutput::Webp, "p.webp");
+        assert!(webp.windows(2).any(|w| w == ["-c:v", "libwebp"]));
+        assert!(webp.windows(2).any(|w| w == ["-loop", "0"]));
+        assert!(webp.iter().any(|a| a.starts_with("fps=12,scale=")));
+        assert_eq!((PreviewOutput::Webp.content_type(), PreviewOutput::default().extension()), ("image/webp", "mp4"));
+    }
+
+    #[test]
+    fn test_start_secs() {
+        assert_eq!(start_secs(None, None), 0);
+        assert_eq!(start_secs(Some(f64::NAN), Some(10.0)), 0);
+        assert_eq!(start_secs(Some(-4.0), None), 0);
+        assert_eq!(start_secs(Some(1.4), None), 1);
+        assert_eq!(start_secs(Some(1.6), Some(30.0)), 2);
+        assert_eq!(start_secs(Some(1e9), Some(30.5)), 27);
+        assert_eq!(start_secs(Some(5.0), Some(2.0)), 0);
+    }
+}
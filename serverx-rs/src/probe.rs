@@ -0,0 +1,226 @@
+use serde::Serialize;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use tracing::info;
+
+use crate::slideshow::run_ffmpeg;
+
+/// What ffprobe found in a format's first bytes (or HLS segment).
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ProbeResult {
+    /// ffprobe's format_name, e.g. "mov,mp4,m4a,3gp,3g2,mj2" or "hls"
+    pub container: Option<String>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    /// Coded size, as stored
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    /// Degrees players should rotate the picture by (display matrix or the
+    /// legacy `rotate` tag), 0 when there's none
+    pub rotation: i64,
+    /// Size as displayed, after rotation
+    pub display_width: Option<u64>,
+    pub display_height: Option<u64>,
+    pub duration_seconds: Option<f64>,
+    pub bit_rate: Option<u64>,
+}
+
+/// ffprobe arguments reading at most `max_bytes` of `input` (requested with
+/// `headers`) and writing its findings as JSON to `output_path`.
+fn probe_args(input: &str, headers: &[(String, String)], max_bytes: u64, output_path: &str) -> Vec<String> {
+    let mut args = vec!["-v".to_string(), "error".to_string()];
+    if !headers.is_empty() {
+        let joined: String = headers.iter().map(|(k, v)| format!("{k}: {v}\r\n")).collect();
+        args.extend(["-headers".to_string(), joined]);
+    }
+    // Network reads give up after 15s (microseconds)
+    args.extend(["-rw_timeout", "15000000", "-probesize", &max_bytes.max(32).to_string()].map(String::from));
+    args.extend(["-print_format", "json", "-show_format", "-show_streams", "-o", output_path, input].map(String::from));
+    args
+}
+
+/// Run ffprobe on `input`, killing it once `stop` is set. Blocking — call
+/// from spawn_blocking.
+pub fn probe(
+    input: &str,
+    headers: &[(String, String)],
+    max_bytes: u64,
+    output_path: &str,
+    stop: &AtomicBool,
+) -> Result<ProbeResult, String> {
+    info!("Probing format (up to {} bytes)", max_bytes);
+    let mut cmd = Command::new("ffprobe");
+    cmd.args(probe_args(input, headers, max_bytes, output_path));
+    run_ffmpeg(cmd, output_path, stop)?;
+    let json = std::fs::read_to_string(output_path).map_err(|e| format!("ffprobe output missing: {e}"))?;
+    parse_probe(&json)
+}
+
+/// What a generated file must contain to be served.
+#[derive(Debug, Clone)]
+pub struct ExpectedOutput {
+    /// Video streams: 0 or 1
+    pub video_streams: usize,
+    /// Codec of the video stream, None for any (stream copies)
+    pub video_codec: Option<&'static str>,
+    /// Audio streams: 0 or 1, None when it depends on the source
+    pub audio_streams: Option<usize>,
+    /// Shortest and longest acceptable duration, None when it isn't known
+    /// ahead (animated WebP has none)
+    pub duration_secs: Option<(f64, f64)>,
+}
+
+/// Allowed drift between an expected and a probed duration.
+pub const DURATION_TOLERANCE_SECS: f64 = 1.0;
+
+impl ExpectedOutput {
+    /// `secs` give or take DURATION_TOLERANCE_SECS.
+    pub fn around(secs: f64) -> Option<(f64, f64)> {
+        Some((secs - DURATION_TOLERANCE_SECS, secs + DURATION_TOLERANCE_SECS))
+    }
+}
+
+/// Run ffprobe on a generated file at `path` and check its duration,
+/// stream counts and codec, so a partly failed FFmpeg run isn't served as a
+/// broken file. Blocking — call from spawn_blocking.
+pub fn verify_output(path: &str, expected: &ExpectedOutput, stop: &AtomicBool) -> Result<(), String> {
+    let size = std::fs::metadata(path).map_err(|e| format!("Output file missing: {e}"))?.len();
+    if size == 0 {
+        return Err("Output file is empty".into());
+    }
+    let json_path = format!("{path}.probe.json");
+    let mut cmd = Command::new("ffprobe");
+    cmd.args(["-v", "error", "-print_format", "json", "-show_format", "-show_streams", "-o", &json_path, path]);
+    run_ffmpeg(cmd, &json_path, stop)?;
+    let json = std::fs::read_to_string(&json_path).map_err(|e| format!("ffprobe output missing: {e}"))?;
+    check_output(&json, expected)
+}
+
+fn check_output(json: &str, expected: &ExpectedOutput) -> Result<(), String> {
+    let v: serde_json::Value = serde_json::from_str(json).map_err(|e| format!("Invalid ffprobe output: {e}"))?;
+    let streams = v["streams"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    let of_type = |t: &str| streams.iter().filter(|s| s["codec_type"] == t).collect::<Vec<_>>();
+    let (video, audio) = (of_type("video"), of_type("audio"));
+    if video.len() != expected.video_streams || expected.audio_streams.is_some_and(|n| audio.len() != n) {
+        return Err(format!("Found {} video and {} audio streams", video.len(), audio.len()));
+    }
+    if let (Some(stream), Some(codec)) = (video.first(), expected.video_codec) {
+        let found = stream["codec_name"].as_str().unwrap_or("unknown");
+        if found != codec {
+            return Err(format!("Video codec is {found}, expected {codec}"));
+        }
+    }
+    if let Some((min, max)) = expected.duration_secs {
+        let duration = v["format"]["duration"]
+            .as_str()
+            .and_then(|d| d.parse::<f64>().ok())
+            .ok_or("Output has no duration")?;
+        if duration < min || duration > max {
+            return Err(format!("Duration is {duration:.2}s, expected {min:.2}s to {max:.2}s"));
+        }
+    }
+    Ok(())
+}
+
+/// ffprobe's `-show_format -show_streams` JSON, first video and audio
+/// stream.
+fn parse_probe(json: &str) -> Result<ProbeResult, String> {
+    let v: serde_json::Value = serde_json::from_str(json).map_err(|e| format!("Invalid ffprobe output: {e}"))?;
+    let streams = v["streams"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    let of_type = |t: &str| streams.iter().find(|s| s["codec_type"] == t);
+    let video = of_type("video");
+    let audio = of_type("audio");
+    if video.is_none() && audio.is_none() {
+        return Err("No audio or video stream found".into());
+    }
+    // ffprobe prints numbers as strings in `format` and tags
+    let number = |x: &serde_json::Value| x.as_f64().or_else(|| x.as_str()?.parse().ok());
+
+    let mut result = ProbeResult {
+        container: v["format"]["format_name"].as_str().map(String::from),
+        video_codec: video.and_then(|s| s["codec_name"].as_str()).map(String::from),
+        audio_codec: audio.and_then(|s| s["codec_name"].as_str()).map(String::from),
+        width: video.and_then(|s| s["width"].as_u64()),
+        height: video.and_then(|s| s["height"].as_u64()),
+        duration_seconds: number(&v["format"]["duration"])
+            .or_else(|| video.or(audio).and_then(|s| number(&s["duration"]))),
+        bit_rate: number(&v["format"]["bit_rate"]).map(|b| b as u64),
+        ..Default::default()
+    };
+    if let Some(video) = video {
+        // Display matrix rotation is counter-clockwise; the rotate tag clockwise
+        let rotation = video["side_data_list"]
+            .as_array()
+            .and_then(|list| list.iter().find_map(|d| number(&d["rotation"])))
+            .map(|r| -r)
+            .or_else(|| number(&video["tags"]["rotate"]))
+            .unwrap_or(0.0);
+        result.rotation = (rotation.round() as i64).rem_euclid(360);
+    }
+    let sideways = result.rotation % 180 == 90;
+    (result.display_width, result.display_height) =
+        if sideways { (result.height, result.width) } else { (result.width, result.height) };
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_args_and_parse() {
+        let args = probe_args("https://v/a.mp4", &[("Cookie".to_string(), "a=b".to_string())], 1_000_000, "p.json");
+        assert_eq!(args[2..4], ["-headers", "Cookie: a=b\r\n"]);
+        assert!(args.windows(2).any(|w| w == ["-probesize", "1000000"]));
+        assert!(args.windows(2).any(|w| w == ["-o", "p.json"]));
+        assert_eq!(args.last().unwrap(), "https://v/a.mp4");
+
+        // TikTok HEVC stored landscape with a display matrix turning it upright
+        let probed = parse_probe(
+            r#"{"streams": [
+                {"codec_type": "video", "codec_name": "hevc", "width": 1920, "height": 1080,
+                 "side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}]},
+                {"codec_type": "audio", "codec_name": "aac"}
+            ], "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "15.023000", "bit_rate": "2400000"}}"#,
+        )
+        .unwrap();
+        assert_eq!((probed.video_codec.as_deref(), probed.audio_codec.as_deref()), (Some("hevc"), Some("aac")));
+        assert_eq!((probed.rotation, probed.display_width, probed.display_height), (90, Some(1080), Some(1920)));
+        assert_eq!((probed.duration_seconds, probed.bit_rate), (Some(15.023), Some(2_400_000)));
+
+        let tagged = parse_probe(
+            r#"{"streams": [{"codec_type": "video", "codec_name": "h264", "width": 720, "height": 1280,
+                "duration": "9.5", "tags": {"rotate": "180"}}], "format": {}}"#,
+        )
+        .unwrap();
+        assert_eq!((tagged.rotation, tagged.display_width, tagged.duration_seconds), (180, Some(720), Some(9.5)));
+        assert!(parse_probe(r#"{"streams": [], "format": {}}"#).is_err());
+        assert!(parse_probe("not json").is_err());
+    }
+
+    #[test]
+    fn test_check_output() {
+        let expected = ExpectedOutput {
+            video_streams: 1,
+            video_codec: Some("h264"),
+            audio_streams: Some(1),
+            duration_secs: ExpectedOutput::around(8.0),
+        };
+        let output = |video: &str, duration: &str| {
+            format!(
+                r#"{{"streams": [{{"codec_type": "video", "codec_name": "{video}"}}, {{"codec_type": "audio", "codec_name": "aac"}}],
+                    "format": {{"duration": "{duration}"}}}}"#
+            )
+        };
+        assert!(check_output(&output("h264", "8.021000"), &expected).is_ok());
+        assert_eq!(check_output(&output("hevc", "8.0"), &expected).unwrap_err(), "Video codec is hevc, expected h264");
+        assert!(check_output(&output("h264", "3.2"), &expected).unwrap_err().starts_with("Duration is 3.20s"));
+
+        let silent = r#"{"streams": [{"codec_type": "video", "codec_name": "h264"}], "format": {"duration": "8.0"}}"#;
+        assert_eq!(check_output(silent, &expected).unwrap_err(), "Found 1 video and 0 audio streams");
+        let any_audio = ExpectedOutput { audio_streams: None, video_codec: None, ..expected.clone() };
+        assert!(check_output(silent, &any_audio).is_ok());
+        let untimed = ExpectedOutput { duration_secs: None, ..any_audio };
+        assert!(check_output(r#"{"streams": [{"codec_type": "video"}], "format": {}}"#, &untimed).is_ok());
+    }
+}
@@ -0,0 +1,387 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Bounded work queue: `workers` jobs run at once, at most `max_queue` more
+/// may wait for a slot, anything beyond that is rejected. Waiting requests
+/// get a worker in priority order; every `aging` they wait counts as one
+/// extra priority level so low tiers can't starve. Used for yt-dlp
+/// extractions and, in serverrs, separately for slideshow FFmpeg runs.
+pub struct ExtractionQueue {
+    shared: Arc<Shared>,
+    workers: usize,
+    max_queue: usize,
+    queued: Arc<AtomicUsize>,
+}
+
+struct Shared {
+    aging: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    free: usize,
+    next_seq: u64,
+    waiters: Vec<Waiter>,
+}
+
+struct Waiter {
+    priority: u8,
+    seq: u64,
+    since: Instant,
+    tx: oneshot::Sender<WorkerPermit>,
+}
+
+impl Waiter {
+    /// Priority plus one level per `aging` waited; FIFO within a level.
+    fn rank(&self, now: Instant, aging: Duration) -> (u64, std::cmp::Reverse<u64>) {
+        let aged = now.duration_since(self.since).as_secs_f64() / aging.as_secs_f64().max(0.001);
+        (self.priority as u64 + aged as u64, std::cmp::Reverse(self.seq))
+    }
+}
+
+/// A running extraction's worker slot; handed to the best waiter when dropped.
+pub struct WorkerPermit {
+    shared: Option<Arc<Shared>>,
+}
+
+impl Drop for WorkerPermit {
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.take() {
+            Shared::release(shared);
+        }
+    }
+}
+
+impl Shared {
+    fn release(shared: Arc<Self>) {
+        loop {
+            let waiter = {
+                let mut state = shared.state.lock().unwrap();
+                let now = Instant::now();
+                let best = (0..state.waiters.len())
+                    .max_by_key(|&i| state.waiters[i].rank(now, shared.aging));
+                match best {
+                    Some(i) => state.waiters.swap_remove(i),
+                    None => {
+                        state.free += 1;
+                        return;
+                    }
+                }
+            };
+            let permit = WorkerPermit {
+                shared: Some(shared.clone()),
+            };
+            match waiter.tx.send(permit) {
+                Ok(()) => return,
+                // The waiter gave up (timeout / client gone); try the next one
+                Err(mut permit) => {
+                    permit.shared = None;
+                }
+            }
+        }
+    }
+}
+
+/// Returned by `ExtractionQueue::enter` when all workers are busy and the queue is full.
+pub struct QueueFull {
+    pub queued: usize,
+}
+
+/// A place in the queue; `wait()` resolves to a worker permit.
+pub enum Ticket {
+    Ready(WorkerPermit),
+    Queued {
+        rx: oneshot::Receiver<WorkerPermit>,
+        seq: u64,
+        _slot: QueueSlot,
+    },
+}
+
+/// Counts as one queued request until dropped (also on timeout/cancellation).
+pub struct QueueSlot {
+    queued: Arc<AtomicUsize>,
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Serialize)]
+pub struct QueueStatus {
+    pub workers: usize,
+    pub in_flight: usize,
+    pub queued: usize,
+    pub max_queue: usize,
+}
+
+impl ExtractionQueue {
+    pub fn new(workers: usize, max_queue: usize, aging: Duration) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                aging,
+                state: Mutex::new(State {
+                    free: workers,
+                    next_seq: 0,
+                    waiters: Vec::new(),
+                }),
+            }),
+            workers,
+            max_queue,
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Take a worker slot immediately, or a queue slot (at `priority`, higher
+    /// runs first) if one is free.
+    pub fn enter(&self, priority: u8) -> Result<Ticket, QueueFull> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.free > 0 {
+            state.free -= 1;
+            return Ok(Ticket::Ready(WorkerPermit {
+                shared: Some(self.shared.clone()),
+            }));
+        }
+
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |q| {
+                (q < self.max_queue).then_some(q + 1)
+            })
+            .map_err(|queued| QueueFull { queued })?;
+
+        // Forget waiters that already gave up
+        state.waiters.retain(|w| !w.tx.is_closed());
+        let (tx, rx) = oneshot::channel();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.waiters.push(Waiter {
+            priority,
+            seq,
+            since: Instant::now(),
+            tx,
+        });
+        Ok(Ticket::Queued {
+            rx,
+            seq,
+            _slot: QueueSlot {
+                queued: self.queued.clone(),
+            },
+        })
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        let free = self.shared.state.lock().unwrap().free;
+        QueueStatus {
+            workers: self.workers,
+            in_flight: self.workers - free,
+            queued: self.queued.load(Ordering::SeqCst),
+            max_queue: self.max_queue,
+        }
+    }
+
+    /// 1-based place of a queued ticket among the current waiters (by rank,
+    /// so it can move as others age); 0 once it has a worker.
+    pub fn position(&self, ticket: &Ticket) -> usize {
+        match ticket {
+            Ticket::Queued { seq, .. } => self.position_of(*seq),
+            Ticket::Ready(_) => 0,
+        }
+    }
+
+    fn position_of(&self, seq: u64) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        let now = Instant::now();
+        let Some(own) = state.waiters.iter().find(|w| w.seq == seq) else {
+            return 0;
+        };
+        let own_rank = own.rank(now, self.shared.aging);
+        1 + state
+            .waiters
+            .iter()
+            .filter(|w| !w.tx.is_closed() && w.rank(now, self.shared.aging) > own_rank)
+            .count()
+    }
+
+    /// Estimated seconds until a newly rejected request would get a worker,
+    /// given the mean extraction time.
+    pub fn retry_after_secs(&self, mean_extraction_secs: f64) -> u64 {
+        self.wait_secs(self.queued.load(Ordering::SeqCst) + 1, mean_extraction_secs)
+    }
+
+    /// Estimated seconds until the request at `position` gets a worker.
+    pub fn wait_secs(&self, position: usize, mean_secs: f64) -> u64 {
+        let rounds = position as f64 / self.workers.max(1) as f64;
+        (rounds * mean_secs).ceil().max(1.0) as u64
+    }
+}
+
+/// Requests registered under a name their client chose (X-Queue-Ticket),
+/// so it can poll where the request stands while the request itself has
+/// nothing to show yet.
+#[derive(Default)]
+pub struct NamedTickets {
+    names: Mutex<HashMap<String, Stage>>,
+}
+
+#[derive(Clone, Copy)]
+enum Stage {
+    Preparing,
+    Queued(u64),
+    Running,
+}
+
+/// Where a named request stands (GET /slideshow/queue/{ticket}).
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TicketState {
+    /// Not in the queue yet: extracting or downloading assets
+    Preparing,
+    Queued { position: usize },
+    Running,
+}
+
+/// Keeps a name registered until dropped.
+pub struct NamedTicket {
+    tickets: Arc<NamedTickets>,
+    name: String,
+}
+
+impl NamedTickets {
+    /// Register `name` for a new request; None when another request has it.
+    pub fn register(self: &Arc<Self>, name: &str) -> Option<NamedTicket> {
+        let mut names = self.names.lock().unwrap();
+        if names.contains_key(name) {
+            return None;
+        }
+        names.insert(name.to_string(), Stage::Preparing);
+        Some(NamedTicket {
+            tickets: self.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    /// Where the request named `name` stands in `queue`, if it's registered.
+    pub fn state(&self, name: &str, queue: &ExtractionQueue) -> Option<TicketState> {
+        let stage = *self.names.lock().unwrap().get(name)?;
+        Some(match stage {
+            Stage::Preparing => TicketState::Preparing,
+            Stage::Queued(seq) => match queue.position_of(seq) {
+                // Handed a worker, not marked running yet
+                0 => TicketState::Running,
+                position => TicketState::Queued { position },
+            },
+            Stage::Running => TicketState::Running,
+        })
+    }
+}
+
+impl NamedTicket {
+    /// The request entered the queue with `ticket`.
+    pub fn queued(&self, ticket: &Ticket) {
+        let stage = match ticket {
+            Ticket::Queued { seq, .. } => Stage::Queued(*seq),
+            Ticket::Ready(_) => Stage::Running,
+        };
+        self.set(stage);
+    }
+
+    pub fn running(&self) {
+        self.set(Stage::Running);
+    }
+
+    fn set(&self, stage: Stage) {
+        if let Some(s) = self.tickets.names.lock().unwrap().get_mut(&self.name) {
+            *s = stage;
+        }
+    }
+}
+
+impl Drop for NamedTicket {
+    fn drop(&mut self) {
+        self.tickets.names.lock().unwrap().remove(&self.name);
+    }
+}
+
+impl Ticket {
+    pub async fn wait(self) -> WorkerPermit {
+        match self {
+            Ticket::Ready(permit) => permit,
+            Ticket::Queued { rx, .. } => rx.await.expect("queue dropped"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rejects_when_workers_and_queue_are_full() {
+        let queue = ExtractionQueue::new(1, 1, Duration::from_secs(10));
+
+        let running = queue.enter(0).ok().unwrap().wait().await;
+        let waiting = queue.enter(0).ok().unwrap();
+        assert_eq!(queue.status().in_flight, 1);
+        assert_eq!(queue.status().queued, 1);
+
+        let full = queue.enter(0).err().unwrap();
+        assert_eq!(full.queued, 1);
+        assert_eq!(queue.retry_after_secs(4.0), 8);
+
+        drop(running);
+        let _permit = waiting.wait().await;
+        assert_eq!(queue.status().queued, 0);
+        assert!(queue.enter(0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_higher_priority_runs_first_and_low_priority_ages() {
+        let queue = ExtractionQueue::new(1, 10, Duration::from_millis(50));
+        let running = queue.enter(0).ok().unwrap().wait().await;
+
+        let low = queue.enter(0).ok().unwrap();
+        let high = queue.enter(2).ok().unwrap();
+        assert_eq!(queue.position(&high), 1);
+        assert_eq!(queue.position(&low), 2);
+        drop(running);
+        let Ticket::Queued { rx: mut low_rx, .. } = low else { panic!("expected queued") };
+        assert!(low_rx.try_recv().is_err());
+        let permit = high.wait().await;
+
+        // After waiting 3 aging periods the free-tier job outranks a new high one
+        tokio::time::sleep(Duration::from_millis(160)).await;
+        let _newer_high = queue.enter(2).ok().unwrap();
+        drop(permit);
+        assert!(low_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_named_tickets() {
+        let queue = ExtractionQueue::new(1, 10, Duration::from_secs(10));
+        let tickets = Arc::new(NamedTickets::default());
+        let running = tickets.register("a").unwrap();
+        assert!(tickets.register("a").is_none());
+        assert_eq!(tickets.state("a", &queue), Some(TicketState::Preparing));
+        let first = queue.enter(0).ok().unwrap();
+        running.queued(&first);
+        assert_eq!(tickets.state("a", &queue), Some(TicketState::Running));
+
+        let waiting = tickets.register("b").unwrap();
+        let second = queue.enter(0).ok().unwrap();
+        waiting.queued(&second);
+        assert_eq!(tickets.state("b", &queue), Some(TicketState::Queued { position: 1 }));
+        assert_eq!(queue.wait_secs(1, 20.0), 20);
+
+        drop((first, running));
+        assert_eq!(tickets.state("a", &queue), None);
+        let _permit = second.wait().await;
+        assert_eq!(tickets.state("b", &queue), Some(TicketState::Running));
+        drop(waiting);
+        assert!(tickets.register("b").is_some());
+    }
+}
@@ -0,0 +1,141 @@
+use redis::aio::MultiplexedConnection;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+
+use crate::env_parse;
+
+/// Lazily connected Redis handle. The server starts (and keeps serving) while
+/// Redis is down: `get()` reconnects on demand with exponential backoff, and
+/// callers fall back to degraded mode while it returns `None`.
+pub struct RedisConn {
+    client: redis::Client,
+    url: String,
+    conn: RwLock<Option<MultiplexedConnection>>,
+    backoff: Mutex<Backoff>,
+    connect_timeout: Duration,
+}
+
+impl RedisConn {
+    /// Only an invalid REDIS_URL is an error; nothing is connected yet.
+    pub fn from_env() -> Result<Self, redis::RedisError> {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let client = redis::Client::open(url.clone())?;
+        Ok(Self {
+            client,
+            url,
+            conn: RwLock::new(None),
+            backoff: Mutex::new(Backoff::new(
+                Duration::from_millis(env_parse("REDIS_RETRY_MIN_MS", 500)),
+                Duration::from_secs(env_parse("REDIS_RETRY_MAX_SECS", 30)),
+            )),
+            connect_timeout: Duration::from_secs(env_parse("REDIS_CONNECT_TIMEOUT_SECS", 3)),
+        })
+    }
+
+    /// The live connection, connecting first if there is none and the backoff
+    /// allows another attempt. `None` means Redis is unavailable right now.
+    pub async fn get(&self) -> Option<MultiplexedConnection> {
+        if let Some(conn) = self.conn.read().await.as_ref() {
+            return Some(conn.clone());
+        }
+
+        // Another request is already connecting; don't stack attempts
+        let mut backoff = self.backoff.try_lock().ok()?;
+        if let Some(conn) = self.conn.read().await.as_ref() {
+            return Some(conn.clone());
+        }
+        if !backoff.ready(Instant::now()) {
+            return None;
+        }
+
+        let attempt = tokio::time::timeout(self.connect_timeout, self.client.get_multiplexed_async_connection()).await;
+        match attempt {
+            Ok(Ok(conn)) => {
+                info!("✅ Connected to Redis at {}", self.url);
+                backoff.succeeded();
+                *self.conn.write().await = Some(conn.clone());
+                Some(conn)
+            }
+            Ok(Err(e)) => {
+                let retry_in = backoff.failed(Instant::now());
+                warn!("Redis at {} unavailable ({}), retrying in {:?}", self.url, e, retry_in);
+                None
+            }
+            Err(_) => {
+                let retry_in = backoff.failed(Instant::now());
+                warn!("Redis connect to {} timed out, retrying in {:?}", self.url, retry_in);
+                None
+            }
+        }
+    }
+
+    /// Drop the connection after a connection-level error so the next `get()`
+    /// reconnects; command errors (wrong type, …) keep it.
+    pub async fn report(&self, err: &redis::RedisError) {
+        let fatal = err.is_io_error() || err.is_connection_dropped() || err.is_connection_refusal() || err.is_timeout();
+        if fatal && self.conn.write().await.take().is_some() {
+            warn!("Lost Redis connection: {}", err);
+        }
+    }
+}
+
+/// Exponential reconnect backoff: `min`, 2×, 4×, … capped at `max`.
+struct Backoff {
+    min: Duration,
+    max: Duration,
+    delay: Duration,
+    next_attempt: Option<Instant>,
+}
+
+impl Backoff {
+    fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            delay: min,
+            next_attempt: None,
+        }
+    }
+
+    fn ready(&self, now: Instant) -> bool {
+        self.next_attempt.is_none_or(|at| now >= at)
+    }
+
+    /// Schedule the next attempt; returns how long until then.
+    fn failed(&mut self, now: Instant) -> Duration {
+        let wait = self.delay;
+        self.next_attempt = Some(now + wait);
+        self.delay = (self.delay * 2).min(self.max);
+        wait
+    }
+
+    fn succeeded(&mut self) {
+        self.delay = self.min;
+        self.next_attempt = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let now = Instant::now();
+        assert!(backoff.ready(now));
+
+        assert_eq!(backoff.failed(now), Duration::from_secs(1));
+        assert!(!backoff.ready(now));
+        assert!(backoff.ready(now + Duration::from_secs(1)));
+        assert_eq!(backoff.failed(now), Duration::from_secs(2));
+        assert_eq!(backoff.failed(now), Duration::from_secs(4));
+        assert_eq!(backoff.failed(now), Duration::from_secs(5));
+        assert_eq!(backoff.failed(now), Duration::from_secs(5));
+
+        backoff.succeeded();
+        assert!(backoff.ready(now));
+        assert_eq!(backoff.failed(now), Duration::from_secs(1));
+    }
+}
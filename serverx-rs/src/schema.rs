@@ -0,0 +1,114 @@
+use serde_json::Value;
+
+/// JSON type a field of yt-dlp's info dict is expected to have.
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Number,
+}
+
+impl Kind {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Self::String => value.is_string(),
+            Self::Number => value.is_number(),
+        }
+    }
+}
+
+/// Fields a post of `platform` always has (playlist entries aside, which
+/// only carry their media). Unknown platforms only need an id.
+fn required_fields(platform: &str) -> &'static [(&'static str, Kind)] {
+    match platform {
+        "tiktok" | "x" => &[("id", Kind::String), ("extractor", Kind::String), ("uploader_id", Kind::String), ("timestamp", Kind::Number)],
+        _ => &[("id", Kind::String)],
+    }
+}
+
+/// Fields every format has; parse_formats can't classify or stream one
+/// without them.
+const FORMAT_FIELDS: [(&str, Kind); 2] = [("format_id", Kind::String), ("url", Kind::String)];
+
+/// Check the info dict yt-dlp returned for `platform` against the shape the
+/// response builders rely on. Returns the fields that are missing or of the
+/// wrong type, as paths with list indexes dropped (`entries[].formats[].url`),
+/// so a changed extractor is reported as such instead of as a response full
+/// of nulls.
+pub fn violations(info: &Value, platform: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    if !info.is_object() {
+        return vec!["(root)".to_string()];
+    }
+    check_fields(info, required_fields(platform), "", &mut found);
+    if info["_type"] == "playlist" {
+        match info["entries"].as_array() {
+            Some(entries) => {
+                for entry in entries {
+                    check_media(entry, "entries[].", &mut found);
+                }
+            }
+            None => found.push("entries".to_string()),
+        }
+    } else {
+        check_media(info, "", &mut found);
+    }
+    let mut seen = std::collections::HashSet::new();
+    found.retain(|path| seen.insert(path.clone()));
+    found
+}
+
+fn check_fields(value: &Value, fields: &[(&str, Kind)], prefix: &str, found: &mut Vec<String>) {
+    for (name, kind) in fields {
+        if !kind.matches(&value[name]) {
+            found.push(format!("{prefix}{name}"));
+        }
+    }
+}
+
+/// A post or playlist entry: an id and at least one well-formed format.
+fn check_media(media: &Value, prefix: &str, found: &mut Vec<String>) {
+    if !media.is_object() {
+        found.push(prefix.trim_end_matches('.').to_string());
+        return;
+    }
+    check_fields(media, &[("id", Kind::String)], prefix, found);
+    match media["formats"].as_array() {
+        Some(formats) if !formats.is_empty() => {
+            for format in formats {
+                check_fields(format, &FORMAT_FIELDS, &format!("{prefix}formats[]."), found);
+            }
+        }
+        _ => found.push(format!("{prefix}formats")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_violations() {
+        let format = json!({"format_id": "http-832", "url": "https://video.twimg.com/a.mp4"});
+        let post = json!({
+            "id": "1790000000000000001", "extractor": "twitter", "uploader_id": "example",
+            "timestamp": 1714000000, "formats": [format],
+        });
+        assert!(violations(&post, "x").is_empty());
+
+        // uploader_id renamed, timestamp turned into a string
+        let mut drifted = post.clone();
+        drifted["uploader_id"].take();
+        drifted["timestamp"] = json!("1714000000");
+        assert_eq!(violations(&drifted, "x"), ["uploader_id", "timestamp"]);
+        assert!(violations(&drifted, "unknown").is_empty());
+
+        // Every broken format is reported once
+        let gallery = json!({
+            "_type": "playlist", "id": "1", "extractor": "twitter", "uploader_id": "example", "timestamp": 1,
+            "entries": [{"id": "a", "formats": [{"format_id": "orig"}, {"format_id": "small"}]}, {"id": "b", "formats": []}],
+        });
+        assert_eq!(violations(&gallery, "x"), ["entries[].formats[].url", "entries[].formats"]);
+        assert_eq!(violations(&json!(null), "tiktok"), ["(root)"]);
+    }
+}
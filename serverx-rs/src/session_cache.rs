@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Results derived from a session's formats (waveforms, previews), by
+/// session and key, kept as long as sessions live. A bounded cache also
+/// drops its oldest entries past a count or a total size.
+pub struct SessionCache<T> {
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: u64,
+    size: fn(&T) -> u64,
+    entries: Mutex<HashMap<String, (Instant, Arc<T>)>>,
+}
+
+impl<T> SessionCache<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self::bounded(ttl, usize::MAX, u64::MAX, |_| 0)
+    }
+
+    /// At most `max_entries`, together at most `max_bytes` as measured by `size`.
+    pub fn bounded(ttl: Duration, max_entries: usize, max_bytes: u64, size: fn(&T) -> u64) -> Self {
+        Self { ttl, max_entries, max_bytes, size, entries: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn get(&self, session_id: &str, key: &str) -> Option<Arc<T>> {
+        let entries = self.entries.lock().unwrap();
+        let (at, value) = entries.get(&cache_key(session_id, key))?;
+        (at.elapsed() < self.ttl).then(|| value.clone())
+    }
+
+    /// Store `value`, dropping expired entries and then the oldest ones
+    /// while over the limits.
+    pub fn insert(&self, session_id: &str, key: &str, value: Arc<T>) {
+        if (self.size)(&value) > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+        entries.insert(cache_key(session_id, key), (Instant::now(), value));
+        let mut total: u64 = entries.values().map(|(_, v)| (self.size)(v)).sum();
+        while entries.len() > self.max_entries || total > self.max_bytes {
+            let Some(oldest) = entries.iter().min_by_key(|(_, (at, _))| *at).map(|(k, _)| k.clone()) else {
+                break;
+            };
+            if let Some((_, v)) = entries.remove(&oldest) {
+                total -= (self.size)(&v);
+            }
+        }
+    }
+}
+
+fn cache_key(session_id: &str, key: &str) -> String {
+    format!("{session_id}\n{key}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_cache() {
+        let cache = SessionCache::new(Duration::from_secs(60));
+        cache.insert("s1", "audio", Arc::new(vec![0.5f32]));
+        assert_eq!(cache.get("s1", "audio").as_deref(), Some(&vec![0.5]));
+        assert!(cache.get("s1", "best").is_none());
+        assert!(cache.get("s2", "audio").is_none());
+        let expired = SessionCache::new(Duration::ZERO);
+        expired.insert("s1", "audio", Arc::new(1));
+        assert!(expired.get("s1", "audio").is_none());
+
+        // Oldest out first, by count and by total size
+        let bounded = SessionCache::bounded(Duration::from_secs(60), 2, 10, |v: &Vec<u8>| v.len() as u64);
+        bounded.insert("s1", "a", Arc::new(vec![0; 4]));
+        std::thread::sleep(Duration::from_millis(2));
+        bounded.insert("s1", "b", Arc::new(vec![0; 4]));
+        std::thread::sleep(Duration::from_millis(2));
+        bounded.insert("s1", "c", Arc::new(vec![0; 4]));
+        assert!(bounded.get("s1", "a").is_none());
+        assert!(bounded.get("s1", "b").is_some() && bounded.get("s1", "c").is_some());
+        std::thread::sleep(Duration::from_millis(2));
+        bounded.insert("s2", "a", Arc::new(vec![0; 5]));
+        assert!(bounded.get("s1", "b").is_none());
+        assert!(bounded.get("s1", "c").is_some() && bounded.get("s2", "a").is_some());
+        bounded.insert("s3", "a", Arc::new(vec![0; 11]));
+        assert!(bounded.get("s3", "a").is_none());
+    }
+}
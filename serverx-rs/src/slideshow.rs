@@ -0,0 +1,247 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+
+/// How long each photo is shown; the sound is looped or cut to fit.
+pub const SECS_PER_IMAGE: f64 = 4.0;
+
+/// Work folders left behind (e.g. by a crash) are removed after this long.
+const WORK_DIR_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// How often a running FFmpeg checks whether its request is gone.
+const FFMPEG_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Index of a TikTok photo format (`image-3` -> 3).
+pub fn image_index(format_id: &str) -> Option<u32> {
+    format_id.strip_prefix("image-")?.parse().ok()
+}
+
+/// Per-request folder under TEMP_DIR, removed with everything in it on drop.
+pub struct WorkDir(PathBuf);
+
+impl WorkDir {
+    pub fn create(temp_dir: &Path, name: &str) -> std::io::Result<Self> {
+        let path = temp_dir.join(name);
+        std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+
+    pub fn file(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            warn!("Failed to remove {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// Sets the flag when dropped — with the request future when the client
+/// disconnects — so the FFmpeg run started for it is killed. Dropped
+/// before `finish`, it logs that the client went away.
+pub struct StopOnDrop {
+    stop: Arc<AtomicBool>,
+    finished: bool,
+}
+
+impl StopOnDrop {
+    pub fn new(stop: Arc<AtomicBool>) -> Self {
+        Self { stop, finished: false }
+    }
+
+    /// The run is over (or given up on): stop it quietly.
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        if !self.finished {
+            warn!("🔌 Client went away, killing FFmpeg");
+        }
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// ffmpeg arguments turning the photos (scaled and padded to 1080x1920 and
+/// shown SECS_PER_IMAGE each) and an optional looped sound into an MP4.
+fn ffmpeg_args(image_paths: &[String], audio_path: Option<&str>, output_path: &str) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    for img in image_paths {
+        args.extend(["-loop", "1", "-t", &format!("{SECS_PER_IMAGE:.3}"), "-i", img].map(String::from));
+    }
+    if let Some(audio) = audio_path {
+        args.extend(["-stream_loop", "-1", "-i", audio].map(String::from));
+    }
+
+    let n = image_paths.len();
+    let mut filters: Vec<String> = (0..n)
+        .map(|i| {
+            format!(
+                "[{i}:v]scale=w=1080:h=1920:force_original_aspect_ratio=decrease,\
+                 pad=1080:1920:(ow-iw)/2:(oh-ih)/2:color=black,setsar=1[v{i}]"
+            )
+        })
+        .collect();
+    let inputs: String = (0..n).map(|i| format!("[v{i}]")).collect();
+    filters.push(format!("{inputs}concat=n={n}:v=1:a=0[vout]"));
+    if audio_path.is_some() {
+        filters.push(format!("[{n}:a]atrim=0:{:.3}[aout]", n as f64 * SECS_PER_IMAGE));
+    }
+
+    args.extend(["-filter_complex".to_string(), filters.join(";"), "-map".into(), "[vout]".into()]);
+    if audio_path.is_some() {
+        args.extend(["-map", "[aout]", "-c:a", "aac"].map(String::from));
+    }
+    args.extend(
+        ["-fps_mode", "cfr", "-pix_fmt", "yuv420p", "-c:v", "libx264", "-preset", "medium", "-crf", "23"]
+            .map(String::from),
+    );
+    args.push(output_path.to_string());
+    args
+}
+
+/// Run ffmpeg to build the slideshow at `output_path`, killing it once
+/// `stop` is set. Blocking — call from spawn_blocking.
+pub fn create_slideshow(
+    image_paths: &[String],
+    audio_path: Option<&str>,
+    output_path: &str,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    if image_paths.is_empty() {
+        return Err("No images to build a slideshow from".into());
+    }
+    info!("Creating slideshow with {} images", image_paths.len());
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(ffmpeg_args(image_paths, audio_path, output_path));
+    run_ffmpeg(cmd, output_path, stop)
+}
+
+/// Run `cmd` (ffmpeg or ffprobe) until it has written `output_path`,
+/// killing it once `stop` is set. Blocking.
+pub fn run_ffmpeg(cmd: Command, output_path: &str, stop: &AtomicBool) -> Result<(), String> {
+    let tool = if cmd.get_program() == "ffprobe" { "ffprobe" } else { "FFmpeg" };
+    let Some((status, stderr)) = run_until_stopped(cmd, stop)? else {
+        return Err(format!("{tool} cancelled"));
+    };
+    if !status.success() {
+        error!("{} error: {}", tool, stderr);
+        return Err(format!("{tool} failed with code {:?}", status.code()));
+    }
+    if !Path::new(output_path).exists() {
+        return Err("Output file was not created".into());
+    }
+    Ok(())
+}
+
+/// Run `cmd` to completion and return its exit status and stderr, or kill
+/// it and return None once `stop` is set. Blocking.
+pub fn run_until_stopped(mut cmd: Command, stop: &AtomicBool) -> Result<Option<(std::process::ExitStatus, String)>, String> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run FFmpeg: {e}"))?;
+    // Drained on its own thread so a chatty FFmpeg can't block on a full pipe
+    let mut pipe = child.stderr.take().expect("stderr is piped");
+    let stderr = std::thread::spawn(move || {
+        let mut out = String::new();
+        let _ = pipe.read_to_string(&mut out);
+        out
+    });
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| format!("Failed to wait for FFmpeg: {e}"))? {
+            return Ok(Some((status, stderr.join().unwrap_or_default())));
+        }
+        if stop.load(Ordering::Relaxed) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(FFMPEG_POLL_INTERVAL);
+    }
+}
+
+/// Remove work folders older than WORK_DIR_MAX_AGE. Returns how many were
+/// removed. Blocking.
+fn cleanup_old_work_dirs(temp_dir: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(temp_dir) else {
+        return 0;
+    };
+    let now = SystemTime::now();
+    entries
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter(|e| {
+            e.metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|t| now.duration_since(t).unwrap_or_default() > WORK_DIR_MAX_AGE)
+        })
+        .filter(|e| std::fs::remove_dir_all(e.path()).is_ok())
+        .count()
+}
+
+/// Sweep leftover work folders every 15 minutes. Call once at startup.
+pub fn spawn_cleanup_task(temp_dir: PathBuf) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(15 * 60));
+        loop {
+            interval.tick().await;
+            let dir = temp_dir.clone();
+            let removed = tokio::task::spawn_blocking(move || cleanup_old_work_dirs(&dir))
+                .await
+                .unwrap_or(0);
+            if removed > 0 {
+                info!("🧹 Removed {} stale slideshow folder(s)", removed);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_index_and_ffmpeg_args() {
+        assert_eq!(image_index("image-12"), Some(12));
+        assert_eq!(image_index("audio"), None);
+
+        let images = vec!["a.jpg".to_string(), "b.jpg".to_string()];
+        let args = ffmpeg_args(&images, Some("s.m4a"), "out.mp4");
+        let filter = &args[args.iter().position(|a| a == "-filter_complex").unwrap() + 1];
+        assert!(filter.contains("[v0][v1]concat=n=2:v=1:a=0[vout]"));
+        assert!(filter.ends_with("[2:a]atrim=0:8.000[aout]"));
+        assert!(args.windows(2).any(|w| w == ["-map", "[aout]"]));
+        assert_eq!(args.last().unwrap(), "out.mp4");
+
+        let silent = ffmpeg_args(&images, None, "out.mp4");
+        assert!(!silent.iter().any(|a| a.contains("[aout]") || a == "-stream_loop"));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (status, _) = run_until_stopped(Command::new("true"), &stop).unwrap().unwrap();
+        assert!(status.success());
+        StopOnDrop::new(stop.clone()).finish();
+        let mut sleep = Command::new("sleep");
+        sleep.arg("30");
+        assert!(run_until_stopped(sleep, &stop).unwrap().is_none());
+
+        let dir = std::env::temp_dir().join(format!("serverx_slideshow_test_{}", std::process::id()));
+        let work = WorkDir::create(&dir, "job").unwrap();
+        std::fs::write(work.file("image_0.jpg"), b"jpeg").unwrap();
+        drop(work);
+        assert!(!dir.join("job").exists());
+        assert_eq!(cleanup_old_work_dirs(&dir), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+
+/// Bytes of the upstream body collected before sniffing; enough for every
+/// image/video/audio signature infer checks (ftyp brands, EBML doctype).
+pub const SNIFF_LEN: usize = 64;
+
+/// Content type and file extension of a media file from its first bytes.
+/// `expected` is the format's guessed content type: an MP4 container for
+/// an audio-only format stays audio, since magic bytes can't tell them
+/// apart. None when the bytes aren't a known image, video or audio format.
+pub fn sniff(prefix: &[u8], expected: &str) -> Option<(&'static str, &'static str)> {
+    let kind = infer::get(prefix)?;
+    if !matches!(
+        kind.matcher_type(),
+        infer::MatcherType::Image | infer::MatcherType::Video | infer::MatcherType::Audio
+    ) {
+        return None;
+    }
+    match kind.mime_type() {
+        "video/mp4" | "video/x-m4v" | "audio/m4a" if expected.starts_with("audio/") => Some(("audio/mp4", "m4a")),
+        "audio/m4a" => Some(("audio/mp4", "m4a")),
+        "video/x-m4v" => Some(("video/mp4", "mp4")),
+        mime => Some((mime, kind.extension())),
+    }
+}
+
+/// File extension for a content type that couldn't be sniffed.
+pub fn extension_for(content_type: &str) -> &'static str {
+    match content_type.split(';').next().unwrap_or("").trim() {
+        "image/png" => "png",
+        "image/webp" => "webp",
+        "image/gif" => "gif",
+        "image/heic" | "image/heif" => "heic",
+        "video/webm" => "webm",
+        "video/quicktime" => "mov",
+        "audio/mpeg" => "mp3",
+        "audio/webm" => "weba",
+        ct if ct.starts_with("audio/") => "m4a",
+        ct if ct.starts_with("image/") => "jpg",
+        _ => "mp4",
+    }
+}
+
+/// Read chunks from `body` until at least SNIFF_LEN bytes (or the end). A
+/// first chunk that long, the usual case, is returned as is rather than
+/// copied.
+pub async fn read_prefix<S, E>(body: &mut S) -> Result<Bytes, E>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    let first = match body.next().await {
+        Some(chunk) => chunk?,
+        None => return Ok(Bytes::new()),
+    };
+    if first.len() >= SNIFF_LEN {
+        return Ok(first);
+    }
+    let mut prefix = first.to_vec();
+    while prefix.len() < SNIFF_LEN {
+        match body.next().await {
+            Some(chunk) => prefix.extend_from_slice(&chunk?),
+            None => break,
+        }
+    }
+    Ok(Bytes::from(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut b = vec![0, 0, 0, 0x18];
+        b.extend_from_slice(b"ftyp");
+        b.extend_from_slice(brand);
+        b.extend_from_slice(&[0, 0, 0, 0]);
+        b.extend_from_slice(brand);
+        b.extend_from_slice(b"mp41");
+        b
+    }
+
+    #[tokio::test]
+    async fn test_sniff_and_read_prefix() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "image/jpeg"), Some(("image/png", "png")));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 ", "image/jpeg"), Some(("image/webp", "webp")));
+        assert_eq!(sniff(&ftyp(b"isom"), "video/mp4"), Some(("video/mp4", "mp4")));
+        assert_eq!(sniff(&ftyp(b"isom"), "audio/mp4"), Some(("audio/mp4", "m4a")));
+        assert_eq!(sniff(&ftyp(b"qt  "), "video/mp4"), Some(("video/quicktime", "mov")));
+        assert_eq!(sniff(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84webm", "video/mp4"), Some(("video/webm", "webm")));
+        assert_eq!(sniff(b"<html>", "video/mp4"), None);
+        assert_eq!(extension_for("image/jpeg"), "jpg");
+        assert_eq!(extension_for("audio/mp4"), "m4a");
+        assert_eq!(extension_for("video/webm; codecs=vp9"), "webm");
+
+        let chunks: Vec<Result<Bytes, ()>> = vec![
+            Ok(Bytes::from_static(&[1; 40])),
+            Ok(Bytes::from_static(&[2; 40])),
+            Ok(Bytes::from_static(&[3; 40])),
+        ];
+        let mut body = futures_util::stream::iter(chunks);
+        assert_eq!(read_prefix(&mut body).await.unwrap().len(), 80);
+        assert_eq!(body.count().await, 1);
+        let chunk = Bytes::from(vec![4; 100]);
+        let mut body = futures_util::stream::iter([Ok::<_, ()>(chunk.clone())]);
+        assert_eq!(read_prefix(&mut body).await.unwrap().as_ptr(), chunk.as_ptr());
+        assert!(read_prefix(&mut body).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::cdn::num_var;
+
+/// sd_notify(3) messages to systemd over NOTIFY_SOCKET, for Type=notify
+/// units: READY=1 once serving, WATCHDOG=1 keep-alives and STATUS= lines
+/// for `systemctl status`.
+pub struct Notifier {
+    socket: UnixDatagram,
+}
+
+impl Notifier {
+    /// None unless systemd started the process with a notify socket.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("NOTIFY_SOCKET").ok().filter(|p| !p.is_empty())?;
+        match Self::connect(&path) {
+            Ok(notifier) => Some(notifier),
+            Err(e) => {
+                warn!("Ignoring NOTIFY_SOCKET {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// `path` is a socket file, or an abstract socket name after `@`.
+    fn connect(path: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                socket.connect_addr(&std::os::unix::net::SocketAddr::from_abstract_name(name)?)?
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets are Linux-only")),
+            None => socket.connect(path)?,
+        }
+        Ok(Self { socket })
+    }
+
+    /// Send `state`, newline-separated assignments like "READY=1".
+    pub fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send(state.as_bytes()) {
+            warn!("Failed to notify systemd ({}): {}", state.lines().next().unwrap_or(""), e);
+        }
+    }
+}
+
+/// How often to feed systemd's watchdog: half of WATCHDOG_USEC (set by
+/// WatchdogSec=). None without a watchdog, or when WATCHDOG_PID names
+/// another process.
+pub fn watchdog_interval(var: &impl Fn(&str) -> Option<String>) -> Option<Duration> {
+    let usec = num_var(var, "WATCHDOG_USEC").filter(|u| *u > 0)?;
+    if num_var(var, "WATCHDOG_PID").is_some_and(|pid| pid != std::process::id() as u64) {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Verdict of one self-check round: the STATUS= line when the watchdog may
+/// be fed, else what failed. `python` is the yt_dlp import check of the
+/// embedded interpreter (None without one), `redis` whether Redis answered
+/// PING (None in standalone builds). An unreachable Redis only fails the
+/// check with `require_redis`: the server keeps serving in degraded mode,
+/// and a restart wouldn't bring Redis back.
+pub fn self_check(python: Option<Result<String, String>>, redis: Option<bool>, require_redis: bool) -> Result<String, String> {
+    let mut status = Vec::new();
+    match python {
+        Some(Ok(version)) => status.push(format!("yt-dlp {version}")),
+        Some(Err(e)) => return Err(e),
+        None => {}
+    }
+    match redis {
+        Some(true) => status.push("Redis connected".to_string()),
+        Some(false) if require_redis => return Err("Redis is not answering PING".into()),
+        Some(false) => status.push("Redis unreachable, degraded".to_string()),
+        None => {}
+    }
+    if status.is_empty() {
+        return Ok("Serving".into());
+    }
+    Ok(format!("Serving, {}", status.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_notify_and_self_check() {
+        let path = std::env::temp_dir().join(format!("serverx-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::connect(path.to_str().unwrap()).unwrap();
+        notifier.notify("READY=1\nSTATUS=Serving");
+        let mut buf = [0; 64];
+        let n = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=Serving");
+        std::fs::remove_file(&path).unwrap();
+        assert!(Notifier::connect(path.to_str().unwrap()).is_err());
+
+        let pid = std::process::id().to_string();
+        let vars = HashMap::from([("WATCHDOG_USEC", "30000000"), ("WATCHDOG_PID", pid.as_str())]);
+        assert_eq!(watchdog_interval(&|k: &str| vars.get(k).map(|v| v.to_string())), Some(Duration::from_secs(15)));
+        let other = |k: &str| match k {
+            "WATCHDOG_USEC" => Some("30000000".to_string()),
+            "WATCHDOG_PID" => Some("1".to_string()),
+            _ => None,
+        };
+        assert_eq!(watchdog_interval(&other), None);
+        assert_eq!(watchdog_interval(&|_: &str| None), None);
+
+        assert_eq!(self_check(Some(Ok("2024.12.23".into())), Some(true), false).unwrap(), "Serving, yt-dlp 2024.12.23, Redis connected");
+        assert_eq!(self_check(None, Some(false), false).unwrap(), "Serving, Redis unreachable, degraded");
+        assert!(self_check(None, Some(false), true).is_err());
+        assert_eq!(self_check(Some(Err("stuck".into())), Some(true), false), Err("stuck".to_string()));
+        assert_eq!(self_check(None, None, true).unwrap(), "Serving");
+    }
+}
@@ -0,0 +1,189 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use tracing::{info, warn};
+
+/// Named response templates loaded from RESPONSE_TEMPLATES_PATH:
+///
+/// ```json
+/// {
+///   "default": {"ok": "$.success", "id": "$.data.video_id", "links": "$.video_formats[*].url"},
+///   "legacy": {"status": "ok", "video": {"title": "$.data.title", "hd": "$.video_formats[0].url"}}
+/// }
+/// ```
+///
+/// Strings starting with `$` are paths into the DownloadResponse JSON (`.key`,
+/// `[N]`, `[*]` to map over an array); everything else is copied as-is. A
+/// string starting with `$$` is a literal `$`.
+#[derive(Debug, Default)]
+pub struct ResponseTemplates {
+    templates: HashMap<String, Value>,
+}
+
+impl ResponseTemplates {
+    pub fn from_env() -> Self {
+        let path = env::var("RESPONSE_TEMPLATES_PATH").unwrap_or_default();
+        if path.is_empty() {
+            return Self::default();
+        }
+        match std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Self::parse(&text))
+        {
+            Ok(t) => {
+                info!("✅ Loaded {} response template(s) from {}", t.templates.len(), path);
+                t
+            }
+            Err(e) => {
+                warn!("Ignoring RESPONSE_TEMPLATES_PATH {}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let templates: HashMap<String, Value> =
+            serde_json::from_str(text).map_err(|e| format!("invalid JSON: {e}"))?;
+        for (name, template) in &templates {
+            validate(template).map_err(|e| format!("template {name}: {e}"))?;
+        }
+        Ok(Self { templates })
+    }
+
+    /// The template a request asked for, or `default` when it didn't name
+    /// one. `Ok(None)` means the response is returned unchanged.
+    pub fn select(&self, name: Option<&str>) -> Result<Option<&Value>, String> {
+        match name {
+            Some("raw") => Ok(None),
+            Some(name) => self
+                .templates
+                .get(name)
+                .map(Some)
+                .ok_or_else(|| format!("Unknown response template: {name}")),
+            None => Ok(self.templates.get("default")),
+        }
+    }
+}
+
+/// Build the template's shape from `input`.
+pub fn apply(template: &Value, input: &Value) -> Value {
+    match template {
+        Value::String(s) if s.starts_with("$$") => Value::String(s[1..].to_string()),
+        Value::String(s) if s.starts_with('$') => match parse_path(s) {
+            Ok(path) => lookup(input, &path),
+            Err(_) => Value::Null,
+        },
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), apply(v, input)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(|v| apply(v, input)).collect()),
+        other => other.clone(),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    Each,
+}
+
+fn validate(template: &Value) -> Result<(), String> {
+    match template {
+        Value::String(s) if s.starts_with('$') && !s.starts_with("$$") => parse_path(s).map(|_| ()),
+        Value::Object(map) => map.values().try_for_each(validate),
+        Value::Array(items) => items.iter().try_for_each(validate),
+        _ => Ok(()),
+    }
+}
+
+fn parse_path(path: &str) -> Result<Vec<Segment>, String> {
+    let mut rest = path.strip_prefix('$').ok_or_else(|| format!("{path}: must start with $"))?;
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(format!("{path}: empty key"));
+            }
+            segments.push(Segment::Key(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(|| format!("{path}: missing ]"))?;
+            let inner = &after[..end];
+            segments.push(if inner == "*" {
+                Segment::Each
+            } else {
+                Segment::Index(inner.parse().map_err(|_| format!("{path}: bad index {inner}"))?)
+            });
+            rest = &after[end + 1..];
+        } else {
+            return Err(format!("{path}: expected . or ["));
+        }
+    }
+    Ok(segments)
+}
+
+fn lookup(value: &Value, path: &[Segment]) -> Value {
+    let Some((first, rest)) = path.split_first() else {
+        return value.clone();
+    };
+    match first {
+        Segment::Key(k) => value.get(k).map_or(Value::Null, |v| lookup(v, rest)),
+        Segment::Index(i) => value.get(*i).map_or(Value::Null, |v| lookup(v, rest)),
+        Segment::Each => match value.as_array() {
+            Some(items) => Value::Array(items.iter().map(|v| lookup(v, rest)).collect()),
+            None => Value::Null,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_template() {
+        let templates = ResponseTemplates::parse(
+            r#"{"legacy": {
+                "status": "ok",
+                "price": "$$5",
+                "id": "$.data.video_id",
+                "hd": "$.video_formats[0].url",
+                "qualities": "$.video_formats[*].quality",
+                "missing": "$.data.nope.deeper",
+                "pair": ["$.success", 1]
+            }}"#,
+        )
+        .unwrap();
+        let input = serde_json::json!({
+            "success": true,
+            "data": {"video_id": "42"},
+            "video_formats": [
+                {"quality": "720p", "url": "https://a/720"},
+                {"quality": "360p", "url": "https://a/360"}
+            ]
+        });
+
+        let out = apply(templates.select(Some("legacy")).unwrap().unwrap(), &input);
+        assert_eq!(
+            out,
+            serde_json::json!({
+                "status": "ok",
+                "price": "$5",
+                "id": "42",
+                "hd": "https://a/720",
+                "qualities": ["720p", "360p"],
+                "missing": null,
+                "pair": [true, 1]
+            })
+        );
+
+        assert!(templates.select(None).unwrap().is_none());
+        assert!(templates.select(Some("raw")).unwrap().is_none());
+        assert!(templates.select(Some("other")).is_err());
+        assert!(ResponseTemplates::parse(r#"{"bad": {"x": "$.a[oops]"}}"#).is_err());
+    }
+}
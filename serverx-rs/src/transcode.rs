@@ -0,0 +1,230 @@
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use tracing::{info, warn};
+
+use crate::slideshow::{run_ffmpeg, WorkDir};
+
+/// Bounds of the re-encode cache, across sessions; the oldest go first.
+pub const CACHE_ENTRIES: usize = 32;
+pub const CACHE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// A re-encoded video kept in its work folder, which is removed once the
+/// cache and every response streaming it have let go.
+pub struct Reencoded {
+    work_dir: WorkDir,
+    name: String,
+    pub len: u64,
+}
+
+impl Reencoded {
+    /// The file `name` of `work_dir`, `len` bytes long.
+    pub fn new(work_dir: WorkDir, name: &str, len: u64) -> Self {
+        Self { work_dir, name: name.to_string(), len }
+    }
+
+    pub fn path(&self) -> String {
+        self.work_dir.file(&self.name)
+    }
+}
+
+/// H.264 encoder FFmpeg re-encodes video with (TRANSCODE_HWACCEL), for
+/// /stream?compat=h264, ?fix_rotation=true and ?burn_subs=.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum H264Encoder {
+    /// libx264 on the CPU
+    #[default]
+    Software,
+    /// NVIDIA: CUDA decoding, h264_nvenc
+    Nvenc,
+    /// Intel/AMD on Linux: h264_vaapi on a DRM render node
+    /// (TRANSCODE_VAAPI_DEVICE)
+    Vaapi { device: String },
+    /// Intel Quick Sync: h264_qsv
+    Qsv,
+    /// macOS: h264_videotoolbox
+    VideoToolbox,
+}
+
+impl H264Encoder {
+    pub fn from_env() -> Self {
+        Self::with_vars(&|key| std::env::var(key).ok())
+    }
+
+    fn with_vars(var: &impl Fn(&str) -> Option<String>) -> Self {
+        let raw = var("TRANSCODE_HWACCEL").unwrap_or_default();
+        match raw.trim().to_lowercase().as_str() {
+            "" | "none" | "software" => Self::Software,
+            "nvenc" | "cuda" => Self::Nvenc,
+            "vaapi" => Self::Vaapi {
+                device: var("TRANSCODE_VAAPI_DEVICE")
+                    .filter(|d| !d.trim().is_empty())
+                    .unwrap_or_else(|| "/dev/dri/renderD128".to_string()),
+            },
+            "qsv" => Self::Qsv,
+            "videotoolbox" => Self::VideoToolbox,
+            other => {
+                warn!("Ignoring invalid TRANSCODE_HWACCEL={} (none, nvenc, vaapi, qsv or videotoolbox)", other);
+                Self::Software
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Software => "libx264",
+            Self::Nvenc => "h264_nvenc",
+            Self::Vaapi { .. } => "h264_vaapi",
+            Self::Qsv => "h264_qsv",
+            Self::VideoToolbox => "h264_videotoolbox",
+        }
+    }
+
+    /// Arguments before `-i`.
+    fn input_args(&self) -> Vec<String> {
+        match self {
+            Self::Nvenc => vec!["-hwaccel".into(), "cuda".into()],
+            Self::Vaapi { device } => vec!["-vaapi_device".into(), device.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Filter handing frames to the encoder, last in -vf. Frames stay in
+    /// system memory up to there, so autorotate's transpose and burned-in
+    /// subtitles work with every encoder.
+    fn upload_filter(&self) -> Option<&'static str> {
+        match self {
+            Self::Vaapi { .. } => Some("format=nv12,hwupload"),
+            Self::Qsv => Some("format=nv12"),
+            _ => None,
+        }
+    }
+
+    /// Video encoding arguments.
+    fn output_args(&self) -> Vec<String> {
+        let args: &[&str] = match self {
+            Self::Software => &["-c:v", "libx264", "-preset", "veryfast", "-crf", "20", "-pix_fmt", "yuv420p"],
+            Self::Nvenc => &["-c:v", "h264_nvenc", "-preset", "p4", "-cq", "21", "-pix_fmt", "yuv420p"],
+            Self::Vaapi { .. } => &["-c:v", "h264_vaapi", "-qp", "21"],
+            Self::Qsv => &["-c:v", "h264_qsv", "-global_quality", "23"],
+            Self::VideoToolbox => &["-c:v", "h264_videotoolbox", "-b:v", "6M", "-pix_fmt", "yuv420p"],
+        };
+        args.iter().map(|a| a.to_string()).collect()
+    }
+}
+
+/// ffmpeg arguments re-encoding the video of `input` (requested with
+/// `headers`) to H.264 into `output_path`. FFmpeg's autorotate turns the
+/// frames by the display matrix / rotate tag while decoding, so the output
+/// is upright and carries no rotation left for players to ignore. Audio is
+/// copied. `subtitles` names a subtitle file in FFmpeg's working directory
+/// to burn in.
+fn h264_args(
+    input: &str,
+    headers: &[(String, String)],
+    encoder: &H264Encoder,
+    subtitles: Option<&str>,
+    output_path: &str,
+) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    if !headers.is_empty() {
+        let joined: String = headers.iter().map(|(k, v)| format!("{k}: {v}\r\n")).collect();
+        args.extend(["-headers".to_string(), joined]);
+    }
+    args.extend(encoder.input_args());
+    args.extend(["-autorotate", "1", "-i", input, "-map", "0:v:0", "-map", "0:a?"].map(String::from));
+    let filters: Vec<String> =
+        subtitles.map(|file| format!("subtitles={file}")).into_iter().chain(encoder.upload_filter().map(String::from)).collect();
+    if !filters.is_empty() {
+        args.extend(["-vf".to_string(), filters.join(",")]);
+    }
+    args.extend(encoder.output_args());
+    // Older FFmpeg copies the legacy rotate tag over even after turning the frames
+    args.extend(["-c:a", "copy", "-metadata:s:v:0", "rotate=0", "-movflags", "+faststart"].map(String::from));
+    args.push(output_path.to_string());
+    args
+}
+
+/// Run ffmpeg to re-encode `input` to upright H.264 at `output_path`, with
+/// the subtitle file at `subtitles` burned in, killing it once `stop` is
+/// set. A failing hardware encoder is retried in software. Blocking — call
+/// from spawn_blocking.
+pub fn to_h264(
+    input: &str,
+    headers: &[(String, String)],
+    encoder: &H264Encoder,
+    subtitles: Option<&Path>,
+    output_path: &str,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    match encode(input, headers, encoder, subtitles, output_path, stop) {
+        Err(e) if *encoder != H264Encoder::Software && !e.contains("cancelled") => {
+            warn!("{} failed ({}), retrying with libx264", encoder.name(), e);
+            encode(input, headers, &H264Encoder::Software, subtitles, output_path, stop)
+        }
+        result => result,
+    }
+}
+
+fn encode(
+    input: &str,
+    headers: &[(String, String)],
+    encoder: &H264Encoder,
+    subtitles: Option<&Path>,
+    output_path: &str,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    info!("Re-encoding video with {}", encoder.name());
+    let mut cmd = Command::new("ffmpeg");
+    // The subtitles filter gets a bare file name, which needs no filtergraph escaping
+    let subtitle_file = subtitles.and_then(|path| path.file_name()).and_then(|name| name.to_str());
+    let mut output = output_path.to_string();
+    if let Some(dir) = subtitles.and_then(Path::parent) {
+        cmd.current_dir(dir);
+        // TEMP_DIR may be relative to where the server started
+        output = std::path::absolute(output_path).map_err(|e| e.to_string())?.to_string_lossy().into_owned();
+    }
+    cmd.args(h264_args(input, headers, encoder, subtitle_file, &output));
+    run_ffmpeg(cmd, output_path, stop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_encoders_and_h264_args() {
+        let encoder = |vars: &[(&str, &str)]| {
+            let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+            H264Encoder::with_vars(&|k: &str| vars.get(k).map(|v| v.to_string()))
+        };
+        assert_eq!(encoder(&[]), H264Encoder::Software);
+        assert_eq!(encoder(&[("TRANSCODE_HWACCEL", "NVENC")]), H264Encoder::Nvenc);
+        assert_eq!(encoder(&[("TRANSCODE_HWACCEL", "vaapi")]), H264Encoder::Vaapi { device: "/dev/dri/renderD128".into() });
+        let vaapi = encoder(&[("TRANSCODE_HWACCEL", "vaapi"), ("TRANSCODE_VAAPI_DEVICE", "/dev/dri/renderD129")]);
+        assert_eq!(vaapi, H264Encoder::Vaapi { device: "/dev/dri/renderD129".into() });
+        assert_eq!(encoder(&[("TRANSCODE_HWACCEL", "gpu")]), H264Encoder::Software);
+
+        let headers = [("User-Agent".to_string(), "UA".to_string())];
+        let args = h264_args("https://v/a.mp4", &headers, &H264Encoder::Software, None, "out.mp4");
+        assert_eq!(args[1..3], ["-headers", "User-Agent: UA\r\n"]);
+        // autorotate is an input option: it has to come before -i
+        let autorotate = args.iter().position(|a| a == "-autorotate").unwrap();
+        assert!(autorotate < args.iter().position(|a| a == "-i").unwrap());
+        assert!(args.windows(2).any(|w| w == ["-c:v", "libx264"]));
+        assert!(args.windows(2).any(|w| w == ["-c:a", "copy"]));
+        assert!(args.windows(2).any(|w| w == ["-metadata:s:v:0", "rotate=0"]));
+        assert_eq!(args.last().unwrap(), "out.mp4");
+        assert!(!args.contains(&"-vf".to_string()));
+
+        // Subtitles are burned in before the frames go to the hardware
+        let args = h264_args("https://v/a.mp4", &[], &vaapi, Some("subs.vtt"), "out.mp4");
+        assert_eq!(args[1..3], ["-vaapi_device", "/dev/dri/renderD129"]);
+        assert!(args.windows(2).any(|w| w == ["-vf", "subtitles=subs.vtt,format=nv12,hwupload"]));
+        assert!(args.windows(2).any(|w| w == ["-c:v", "h264_vaapi"]));
+        let nvenc = h264_args("https://v/a.mp4", &[], &H264Encoder::Nvenc, Some("subs.srt"), "o.mp4");
+        assert!(nvenc.windows(2).any(|w| w == ["-hwaccel", "cuda"]));
+        assert!(nvenc.windows(2).any(|w| w == ["-vf", "subtitles=subs.srt"]));
+    }
+}
@@ -0,0 +1,129 @@
+use std::io::{BufReader, Read};
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use tracing::info;
+
+use crate::slideshow::run_ffmpeg;
+
+/// Audio is decoded to mono at this rate; plenty for peaks and keeps the
+/// decoded file of an hour-long Space under 30 MB.
+const SAMPLE_RATE: u32 = 4000;
+
+/// Audio past this is left out of the waveform; 4 hours decode to about
+/// 115 MB.
+pub const MAX_SECONDS: u32 = 4 * 3600;
+
+/// Peaks computed (and cached) per format; requests for fewer get the max
+/// of neighbouring ones.
+pub const MAX_PEAKS: usize = 2000;
+
+/// Peaks of one format, loudest at 1.0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Waveform {
+    pub duration_seconds: f64,
+    pub peaks: Vec<f32>,
+}
+
+impl Waveform {
+    /// `count` peaks (1..=MAX_PEAKS), each the loudest of the ones it covers.
+    pub fn resample(&self, count: usize) -> Vec<f32> {
+        let len = self.peaks.len();
+        let count = count.clamp(1, len.max(1));
+        if len == 0 {
+            return Vec::new();
+        }
+        (0..count)
+            .map(|i| self.peaks[i * len / count..(i + 1) * len / count].iter().copied().fold(0.0, f32::max))
+            .collect()
+    }
+}
+
+/// ffmpeg arguments decoding the first MAX_SECONDS of the audio of `input`
+/// (requested with `headers`) to raw mono 16-bit samples at `output_path`.
+fn decode_args(input: &str, headers: &[(String, String)], output_path: &str) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    if !headers.is_empty() {
+        let joined: String = headers.iter().map(|(k, v)| format!("{k}: {v}\r\n")).collect();
+        args.extend(["-headers".to_string(), joined]);
+    }
+    // Network reads give up after 15s (microseconds)
+    args.extend(["-rw_timeout", "15000000", "-i", input, "-vn", "-t", &MAX_SECONDS.to_string()].map(String::from));
+    args.extend(["-ac", "1", "-ar", &SAMPLE_RATE.to_string(), "-f", "s16le"].map(String::from));
+    args.push(output_path.to_string());
+    args
+}
+
+/// Decode `input` with ffmpeg and reduce it to MAX_PEAKS peaks, killing
+/// ffmpeg once `stop` is set. Blocking — call from spawn_blocking.
+pub fn generate(input: &str, headers: &[(String, String)], work_path: &str, stop: &AtomicBool) -> Result<Waveform, String> {
+    info!("Generating waveform");
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(decode_args(input, headers, work_path));
+    run_ffmpeg(cmd, work_path, stop)?;
+    let file = std::fs::File::open(work_path).map_err(|e| format!("Decoded audio missing: {e}"))?;
+    let samples = file.metadata().map_err(|e| e.to_string())?.len() / 2;
+    if samples == 0 {
+        return Err("No audio to draw".into());
+    }
+    peaks(BufReader::new(file), samples, MAX_PEAKS).map_err(|e| format!("Failed to read decoded audio: {e}"))
+}
+
+/// Peaks of `samples` s16le samples read from `raw`: `count` buckets (fewer
+/// for short audio), normalized so the loudest is 1.0 and rounded to 3
+/// decimals.
+fn peaks(mut raw: impl Read, samples: u64, count: usize) -> std::io::Result<Waveform> {
+    let count = (count as u64).min(samples).max(1);
+    let mut maxima = vec![0u16; count as usize];
+    let mut buf = [0u8; 8192];
+    let (mut index, mut filled) = (0u64, 0);
+    loop {
+        let n = raw.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+        // A read can end mid-sample; its first byte waits for the next one
+        let whole = filled - filled % 2;
+        for pair in buf[..whole].chunks_exact(2) {
+            let amplitude = i16::from_le_bytes([pair[0], pair[1]]).unsigned_abs();
+            let bucket = ((index * count / samples) as usize).min(maxima.len() - 1);
+            maxima[bucket] = maxima[bucket].max(amplitude);
+            index += 1;
+        }
+        buf.copy_within(whole..filled, 0);
+        filled -= whole;
+    }
+    let loudest = maxima.iter().copied().max().unwrap_or(0).max(1) as f32;
+    Ok(Waveform {
+        duration_seconds: samples as f64 / SAMPLE_RATE as f64,
+        peaks: maxima.iter().map(|m| (*m as f32 / loudest * 1000.0).round() / 1000.0).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peaks() {
+        let args = decode_args("https://v/a.m4a", &[("User-Agent".to_string(), "UA".to_string())], "w.raw");
+        assert_eq!(args[1..3], ["-headers", "User-Agent: UA\r\n"]);
+        assert!(args.windows(2).any(|w| w == ["-ar", "4000"]));
+        assert!(args.windows(2).any(|w| w == ["-f", "s16le"]));
+        assert!(args.windows(2).any(|w| w == ["-t", "14400"]));
+
+        // 8 samples in 4 buckets: the loudest bucket (-20000) becomes 1.0
+        let samples: [i16; 8] = [100, -200, 10000, 0, -20000, 5000, 0, 1];
+        let raw: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let waveform = peaks(raw.as_slice(), 8, 4).unwrap();
+        assert_eq!(waveform.peaks, [0.01, 0.5, 1.0, 0.0]);
+        assert_eq!(waveform.duration_seconds, 8.0 / 4000.0);
+        assert_eq!(waveform.resample(2), [0.5, 1.0]);
+        assert_eq!(waveform.resample(100).len(), 4);
+        // Fewer samples than buckets: one bucket per sample
+        assert_eq!(peaks(raw.as_slice(), 8, 100).unwrap().peaks.len(), 8);
+        // Reads ending mid-sample
+        let trickle = std::io::Read::chain(&raw[..3], &raw[3..]);
+        assert_eq!(peaks(trickle, 8, 4).unwrap(), waveform);
+    }
+}
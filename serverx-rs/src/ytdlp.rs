@@ -0,0 +1,196 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use tracing::info;
+
+use crate::error::ExtractError;
+
+/// `format` is a yt-dlp format selector ("bv*[height<=1080]+ba/b"); its pick
+/// is reported as the info's `format_id`/`requested_formats`. yt-dlp only
+/// connects to addresses of `source_address`'s family ("0.0.0.0" for IPv4).
+pub fn extract_with_ytdlp(
+    url: &str,
+    cookiefile: Option<&str>,
+    format: Option<&str>,
+    source_address: Option<&str>,
+) -> Result<String, ExtractError> {
+    Python::with_gil(|py| {
+        let yt_dlp = py
+            .import("yt_dlp")
+            .map_err(|e| ExtractError::Internal(format!("Failed to import yt_dlp: {e}")))?;
+
+        let opts = PyDict::new(py);
+        opts.set_item("quiet", true).unwrap();
+        opts.set_item("no_warnings", true).unwrap();
+        opts.set_item("extract_flat", false).unwrap();
+        opts.set_item("socket_timeout", 30).unwrap();
+        if let Some(path) = cookiefile {
+            opts.set_item("cookiefile", path).unwrap();
+        }
+        if let Some(selector) = format {
+            opts.set_item("format", selector).unwrap();
+        }
+        if let Some(addr) = source_address {
+            opts.set_item("source_address", addr).unwrap();
+        }
+
+        let ydl_class = yt_dlp
+            .getattr("YoutubeDL")
+            .map_err(|e| ExtractError::Internal(format!("Failed to get YoutubeDL: {e}")))?;
+        let ydl = ydl_class
+            .call1((opts,))
+            .map_err(|e| ExtractError::Internal(format!("Failed to create YoutubeDL: {e}")))?;
+
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("download", false).unwrap();
+        let info = ydl
+            .call_method("extract_info", (url,), Some(&kwargs))
+            .map_err(|e| ExtractError::classify(e.to_string()))?;
+
+        // Cookie is stripped from each format's http_headers; keep what the
+        // cookiejar holds for its URL as '_cookies' before closing ydl
+        if let Ok(cookiejar) = ydl.getattr("cookiejar") {
+            inject_format_cookies(&info, &cookiejar);
+            if let Ok(entries) = info.get_item("entries").and_then(|e| e.try_iter()) {
+                for entry in entries.flatten() {
+                    inject_format_cookies(&entry, &cookiejar);
+                }
+            }
+        }
+        let _ = ydl.call_method0("close");
+
+        let json_mod = py
+            .import("json")
+            .map_err(|e| ExtractError::Internal(format!("Failed to import json: {e}")))?;
+        let json_str = json_mod
+            .call_method1("dumps", (info,))
+            .map_err(|e| ExtractError::Internal(format!("Failed to serialize: {e}")))?
+            .extract::<String>()
+            .map_err(|e| ExtractError::Internal(format!("Failed to extract string: {e}")))?;
+
+        Ok(json_str)
+    })
+}
+
+/// Set `_cookies` on every format of `info` with a non-empty Cookie header
+/// for its URL.
+fn inject_format_cookies(info: &Bound<'_, PyAny>, cookiejar: &Bound<'_, PyAny>) {
+    let Ok(formats) = info.get_item("formats").and_then(|f| f.try_iter()) else {
+        return;
+    };
+    for fmt in formats.flatten() {
+        let Ok(fmt_url) = fmt.get_item("url") else {
+            continue;
+        };
+        let cookie_header = cookiejar
+            .call_method1("get_cookie_header", (fmt_url,))
+            .and_then(|h| h.extract::<String>())
+            .ok();
+        if let Some(cookies) = cookie_header.filter(|c| !c.is_empty()) {
+            let _ = fmt.set_item("_cookies", cookies);
+        }
+    }
+}
+
+/// How long the check's interpreter may take to import yt_dlp.
+const CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Import yt_dlp in a fresh process of the embedded interpreter's python,
+/// with its sys.path, and return its version string. Importing again in the
+/// embedded interpreter would be answered from sys.modules, so a broken or
+/// removed install would never show up. Used by the /health check; blocking.
+pub fn check_ytdlp() -> Result<String, String> {
+    let (executable, path) = Python::with_gil(|py| -> PyResult<(Option<std::path::PathBuf>, Vec<String>)> {
+        Ok((interpreter_executable(py)?, py.import("sys")?.getattr("path")?.extract()?))
+    })
+    .map_err(|e| format!("Failed to read the interpreter's sys.path: {e}"))?;
+    let Some(executable) = executable else {
+        // Only the shared library is installed; the embedded import is all there is
+        return import_ytdlp();
+    };
+    let pythonpath = std::env::join_paths(path.iter().filter(|p| !p.is_empty()))
+        .map_err(|e| format!("Invalid sys.path: {e}"))?;
+
+    let child = std::process::Command::new(&executable)
+        .args(["-c", "import yt_dlp, yt_dlp.version; print(yt_dlp.version.__version__)"])
+        .env("PYTHONPATH", pythonpath)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {e}", executable.display()))?;
+    // wait_with_output drains both pipes while waiting, so a chatty import
+    // can't fill one and hang
+    let pid = child.id();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(child.wait_with_output());
+    });
+    let output = match rx.recv_timeout(CHECK_TIMEOUT) {
+        Ok(output) => output.map_err(|e| format!("Failed to read {} output: {e}", executable.display()))?,
+        Err(_) => {
+            // The waiting thread reaps it
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            return Err(format!("Importing yt_dlp took over {}s", CHECK_TIMEOUT.as_secs()));
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("no output");
+        return Err(format!("Failed to import yt_dlp: {reason}"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The python binary of the interpreter PyO3 embeds: sys.executable when it
+/// names one (a venv's python, say), else python<major>.<minor> under
+/// sys.prefix or sys.base_prefix. Embedded, sys.executable is often empty or
+/// this binary, and whatever `python3` is on PATH may be another version.
+fn interpreter_executable(py: Python<'_>) -> PyResult<Option<std::path::PathBuf>> {
+    let sys = py.import("sys")?;
+    let executable: String = sys.getattr("executable")?.extract()?;
+    let is_self = std::env::current_exe().is_ok_and(|exe| exe == std::path::Path::new(&executable));
+    if !executable.is_empty() && !is_self {
+        return Ok(Some(executable.into()));
+    }
+    let version = py.version_info();
+    let name = format!("python{}.{}", version.major, version.minor);
+    for prefix in ["prefix", "base_prefix"] {
+        let prefix: String = sys.getattr(prefix)?.extract()?;
+        let candidate = std::path::Path::new(&prefix).join("bin").join(&name);
+        if candidate.is_file() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Import yt_dlp in the embedded interpreter and return its version string.
+fn import_ytdlp() -> Result<String, String> {
+    Python::with_gil(|py| {
+        py.import("yt_dlp").map_err(|e| format!("Failed to import yt_dlp: {e}"))?;
+        py.import("yt_dlp.version")
+            .and_then(|m| m.getattr("__version__"))
+            .and_then(|v| v.extract::<String>())
+            .map_err(|e| format!("Failed to read yt_dlp version: {e}"))
+    })
+}
+
+/// Import yt_dlp (and optionally build the extractor list) before serving traffic,
+/// so the first request doesn't pay the multi-second import latency.
+pub fn warm_up_ytdlp(preload_extractors: bool) -> Result<String, String> {
+    let started = std::time::Instant::now();
+    let version = import_ytdlp()?;
+
+    if preload_extractors {
+        let count = Python::with_gil(|py| {
+            py.import("yt_dlp.extractor")
+                .and_then(|m| m.call_method0("gen_extractor_classes"))
+                .and_then(|classes| classes.len())
+                .map_err(|e| format!("Failed to build extractor list: {e}"))
+        })?;
+        info!("Preloaded {} yt-dlp extractors", count);
+    }
+
+    info!("🔥 yt_dlp {} warmed up in {}ms", version, started.elapsed().as_millis());
+    Ok(version)
+}